@@ -1,76 +1,75 @@
 // Benchmark our hashing function.
+//
+// This runs on stable Rust, without the `test` crate, so the timing is
+// done by hand.  Run with `cargo bench`.
 
-#![feature(test)]
-
-extern crate openssl;
-extern crate rsure;
-extern crate tempdir;
-extern crate test;
-// extern crate sha1;
-
-use rsure::{Progress, SureHash};
-use std::fs::File;
-use std::io::Write;
+use openssl::hash::{Hasher, MessageDigest};
+use rsure::StoreTags;
+use std::{
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
 use tempdir::TempDir;
-use test::Bencher;
 
-// To compute hashing speed, use 1 over the benchmark time in seconds, and
-// then multiply the result by the number of iterations in the 'for i'
-// loop.  For example, if the benchmark runs in 29,924,583 ns/iter, and the
-// count is 16, that would be about 534 MiB/sec hash performance.
+// To compute hashing speed, divide the number of MiB hashed per iteration
+// (16 in `tree_mb_bench`) by the time per iteration.
 //
 // The loop count should be large enough to overflow the CPU's largest
 // cache, with the value 16 (16MiB) overflowing the 8MiB cache on the Core
 // i7-950 I wrote this on.
-#[bench]
-fn tree_mb_bench(b: &mut Bencher) {
+const ITERATIONS: u32 = 10;
+
+fn main() {
+    report("tree_mb_bench", tree_mb_bench());
+    report("openssl_bench", openssl_bench());
+}
+
+fn report(name: &str, total: Duration) {
+    println!("{:20} {:>12?}/iter", name, total / ITERATIONS);
+}
+
+fn tree_mb_bench() -> Duration {
     let tmp = TempDir::new("rsure-bench").unwrap();
+    let data = tmp.path().join("data");
+    std::fs::create_dir(&data).unwrap();
     for i in 0..16 {
         let name = format!("large-{}", i);
-        let mut fd = File::create(tmp.path().join(&name)).unwrap();
+        let mut fd = File::create(data.join(&name)).unwrap();
         let buf = vec![0; 1024];
         for _ in 0..1024 {
             fd.write_all(&buf).unwrap();
         }
     }
 
-    b.iter(|| {
-        let mut tree = rsure::scan_fs(tmp.path()).unwrap();
-        let estimate = tree.hash_estimate();
-        let mut progress = Progress::new(estimate.files, estimate.bytes);
-        tree.hash_update(tmp.path(), &mut progress);
-        // progress.flush();
-    })
-}
+    let mut total = Duration::default();
+    for i in 0..ITERATIONS {
+        let store = rsure::parse_store(
+            tmp.path()
+                .join(format!("bench{}.dat.gz", i))
+                .to_str()
+                .unwrap(),
+        )
+        .unwrap();
+        let mut tags = StoreTags::new();
+        tags.insert("name".into(), "bench".into());
 
-#[bench]
-fn openssl_bench(b: &mut Bencher) {
-    use openssl::hash::{Hasher, MessageDigest};
+        let start = Instant::now();
+        rsure::update(&data, &*store, false, &tags, &[rsure::HashAlgorithm::Sha1]).unwrap();
+        total += start.elapsed();
+    }
+    total
+}
 
+fn openssl_bench() -> Duration {
     // Make buffer big enough to not fit in cache.
     let buf = vec![0; 1024 * 1024 * 16];
 
-    b.iter(|| {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
         let mut h = Hasher::new(MessageDigest::sha1()).unwrap();
         h.write_all(&buf).unwrap();
         h.finish().unwrap();
-    })
-}
-
-/* Bring in the SHA1 crate.  Currently, it seems to be about 4.2 times slower than the openssl one.
- */
-/*
-#[bench]
-fn sha1_bench(b: &mut Bencher) {
-    use sha1::Sha1;
-
-    // Make buffer big enough to not fit in cache.
-    let buf = vec![0; 1024 * 1024 * 16];
-
-    b.iter(|| {
-        let mut h = Sha1::new();
-        h.update(&buf);
-        let _ = h.digest();
-    })
+    }
+    start.elapsed()
 }
-*/
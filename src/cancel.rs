@@ -0,0 +1,121 @@
+//! Cancelling, and pausing, long-running operations.
+//!
+//! A `CancellationToken` is shared between the code running a scan, update
+//! or comparison, and whatever decides to stop it, such as another thread
+//! of an embedding program.  Once `cancel` is called, the operation stops
+//! at the next node or file, and returns `Error::Cancelled`.  Nothing is
+//! committed to the store by a cancelled update.
+//!
+//! The token can also `pause` the operation, such as to give the disks back
+//! to other work for a while.  Each thread of the operation waits at the
+//! next node or file until the token is resumed, or cancelled, and then
+//! carries on where it was.
+
+use crate::{Error, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+/// A shared flag asking an operation to stop, or to wait.  Clones share
+/// the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Ask the operations using this token to stop.  This also ends any
+    /// pause.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let _paused = self.0.paused.lock().unwrap();
+        self.0.cond.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Make the operations using this token wait, at their next check,
+    /// until `resume` is called.
+    pub fn pause(&self) {
+        *self.0.paused.lock().unwrap() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.0.paused.lock().unwrap() = false;
+        self.0.cond.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.paused.lock().unwrap()
+    }
+
+    /// Wait while paused, and then give an error, if cancelled, to return
+    /// from the operation.
+    pub fn check(&self) -> Result<()> {
+        let mut paused = self.0.paused.lock().unwrap();
+        while *paused && !self.is_cancelled() {
+            paused = self.0.cond.wait(paused).unwrap();
+        }
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wrap a node iterator, such as the trees given to `compare_trees`, so
+    /// that it waits while paused, and once cancelled, gives
+    /// `Error::Cancelled`, and then ends.
+    pub fn guard<I>(&self, iter: I) -> Guarded<I> {
+        Guarded {
+            iter,
+            token: self.clone(),
+            done: false,
+        }
+    }
+}
+
+/// An iterator that stops when its token is cancelled.
+pub struct Guarded<I> {
+    iter: I,
+    token: CancellationToken,
+    done: bool,
+}
+
+impl<I, T> Iterator for Guarded<I>
+where
+    I: Iterator<Item = Result<T>>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.done {
+            return None;
+        }
+        if let Err(e) = self.token.check() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        self.iter.next()
+    }
+}
+
+/// Check an optional token.
+pub(crate) fn check(token: &Option<CancellationToken>) -> Result<()> {
+    match token {
+        Some(token) => token.check(),
+        None => Ok(()),
+    }
+}
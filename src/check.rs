@@ -0,0 +1,92 @@
+//! Checking a tree against a version of a store.
+//!
+//! This is what `rsure check` does: scan and hash the tree into a
+//! temporary store, then compare that with the version.  [`check`] returns
+//! the changes found, rather than writing a report, so that other programs
+//! can use them as they like.
+
+use crate::{
+    compare_trees_with_renames, compare_trees_with_rules, ignore::IgnoreRules, scratch::ScratchDir,
+    store::WeaveStore, update_with, Change, ChangeSummary, Error, Failures, HashAlgorithm, Result,
+    Store, StoreTags, StoreVersion, Tombstones, UpdateHooks, Version,
+};
+use std::path::Path;
+use weave::Compression;
+
+/// How to check a tree.  The defaults check it as `rsure check` does.
+#[derive(Default)]
+pub struct CheckOptions {
+    /// How to scan and hash the tree, such as paths to leave out.
+    pub hooks: UpdateHooks,
+    /// The attributes to ignore when comparing.  By default, these are the
+    /// rules kept with the store.
+    pub rules: Option<IgnoreRules>,
+    /// Report files moved with the same content as renamed, rather than as
+    /// removed and added.
+    pub renames: bool,
+}
+
+/// What a check found.
+#[derive(Clone, Debug)]
+pub struct CheckReport {
+    /// The version the tree was compared with.
+    pub version: StoreVersion,
+    /// The changes, in the order of the tree.
+    pub changes: Vec<Change>,
+    /// The number of each kind of change.
+    pub summary: ChangeSummary,
+    /// The paths that couldn't be read while scanning the tree.
+    pub failures: Failures,
+}
+
+/// Compare the tree at `dir` with a version of the store.  The tree is
+/// hashed with the algorithms the version was.  The store is only read.
+pub fn check<P: AsRef<Path>>(
+    dir: P,
+    store: &dyn Store,
+    version: Version,
+    options: CheckOptions,
+) -> Result<CheckReport> {
+    let dir = dir.as_ref();
+    let version = store
+        .get_version(&version)?
+        .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+    let algorithms = HashAlgorithm::from_tags(&version.tags)?;
+    let rules = match options.rules {
+        Some(rules) => rules,
+        None => IgnoreRules::load(store)?,
+    };
+
+    // Scan the tree to a temp store.
+    let tdir = ScratchDir::new("rsure")?;
+    let tstore = WeaveStore::new(tdir.path(), "check", Compression::Gzip);
+    let mut tags = StoreTags::new();
+    tags.insert("name".to_string(), "check".to_string());
+    update_with(dir, &tstore, false, &tags, &algorithms, options.hooks)?;
+
+    let old_tree = store.load_iter(version.version.clone())?;
+    let new_tree = tstore.load_iter(Version::Latest)?;
+    let (excluded, failures) = match tstore.get_version(&Version::Latest)? {
+        Some(v) => (
+            Tombstones::from_tags(&v.tags)?,
+            Failures::from_tags(&v.tags)?,
+        ),
+        None => (Tombstones::new(), Failures::new()),
+    };
+    let compare = if options.renames {
+        compare_trees_with_renames
+    } else {
+        compare_trees_with_rules
+    };
+    let mut changes = vec![];
+    let summary = compare(old_tree, new_tree, dir, &rules, &excluded, |change| {
+        changes.push(change)
+    })?;
+
+    Ok(CheckReport {
+        version,
+        changes,
+        summary,
+        failures,
+    })
+}
@@ -0,0 +1,57 @@
+//! Sources of time.
+//!
+//! Snapshots are stamped with the time they were made.  Rather than
+//! calling `Utc::now()` directly, this time comes from a `Clock`, so that
+//! tests can run with a frozen time and get reproducible output, and
+//! importers can record historical times.  The clock for a store is set
+//! with `Store::set_clock`, for example, to commit a version with its
+//! original capture time:
+//!
+//! ```rust
+//! # fn main() -> rsure::Result<()> {
+//! # let tmp = tempdir::TempDir::new("rsure")?;
+//! # let path = tmp.path().join("2sure.dat.gz");
+//! let mut store = rsure::parse_store(path.to_str().unwrap())?;
+//! let time = rsure::clock::parse_time("2019-03-01T09:30:00Z")?;
+//! store.set_clock(Box::new(rsure::FixedClock(time)));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use std::env;
+
+pub use weave::{Clock, FixedClock, SystemClock};
+
+/// The environment variable that enables deterministic mode.  If its
+/// value is an RFC 3339 timestamp, that is used as the fixed time,
+/// otherwise the time is frozen at the Unix epoch.
+const DETERMINISTIC_VAR: &str = "RSURE_DETERMINISTIC";
+
+/// Is deterministic mode enabled?  In this mode, which is intended for
+/// tests and CI, volatile attributes are normalized when snapshots are
+/// written, and the clock is frozen, so that scanning an identical tree
+/// twice gives identical output.
+pub fn is_deterministic() -> bool {
+    env::var_os(DETERMINISTIC_VAR).is_some()
+}
+
+/// Parse an RFC 3339 timestamp, such as one given on the command line.
+pub fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| Error::InvalidTimestamp(text.to_string()))
+}
+
+/// Return the clock to use: a frozen one in deterministic mode, otherwise
+/// the system clock.
+pub fn default_clock() -> Box<dyn Clock> {
+    match env::var(DETERMINISTIC_VAR) {
+        Ok(text) => {
+            let time = parse_time(&text).unwrap_or_else(|_| Utc.timestamp(0, 0));
+            Box::new(FixedClock(time))
+        }
+        Err(_) => Box::new(SystemClock),
+    }
+}
@@ -0,0 +1,491 @@
+//! Running scans periodically.
+//!
+//! The daemon reads a configuration file describing a set of profiles, each
+//! a directory to scan into a store, and how often to do so.  Each profile
+//! runs on its own schedule, but all of them share a single pool limiting
+//! how many files are hashed at once.  The daemon keeps a status file
+//! recording when each profile last ran, when it will next run, and the
+//! phase and progress of any scan in progress.  This is rewritten
+//! periodically, even when idle, so a monitor can notice a stuck daemon
+//! from a stale `updated` time.  The same status can also be read from a
+//! Unix socket.
+//!
+//! The configuration is JSON, for example:
+//!
+//! ```json
+//! {
+//!     "hash_threads": 4,
+//!     "profiles": [
+//!         { "name": "home", "dir": "/home", "store": "/var/lib/rsure/home.weave.gz",
+//!           "interval": 3600, "hash": "sha1,sha256", "exclude": [".cache/"] }
+//!     ]
+//! }
+//! ```
+//!
+//! `interval` is in seconds.  `hash` is optional, and defaults to the
+//! algorithms of the latest version in the store.  `exclude` is an optional
+//! list of patterns, as for `--exclude`, and `exclude_profiles` a list of
+//! built-in profiles, as for `--profile`.  `cross_filesystems`,
+//! `follow_symlinks`, `hash_link_targets`, `selinux` and `flags` are as
+//! for `--cross-filesystems`, `--follow-symlinks`, `--hash-link-targets`,
+//! `--selinux` and `--flags`.
+//! The status is written to
+//! `status` if given, otherwise to the configuration file's path with
+//! `.status` appended, and refreshed every `status_interval` seconds
+//! (default 10).  If `socket` is given, each connection to that Unix socket
+//! is sent the current status, and closed.  `memory_limit`, such as
+//! `"256M"`, bounds the memory each scan uses for its buffers.
+//!
+//! Under systemd, the daemon reports when it is ready, pings the watchdog if
+//! one is configured, and reports when it is stopping.  A shutdown, either
+//! from `Daemon::stop` or a signal (see `service::handle_termination`),
+//! lets any scans in progress finish, but starts no new ones.
+
+use crate::{
+    clock,
+    monitor::{Activity, ActivityState, Phase},
+    parse_store, service, update_with, Error, Exclude, HashAlgorithm, HashPool, MemoryLimit,
+    ProgressSink, Result, ScanOptions, StoreTags, UpdateHooks, Version,
+};
+use chrono::{DateTime, Duration, Local, Utc};
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
+};
+
+/// The daemon's configuration file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DaemonConfig {
+    /// Where to write the status file.
+    pub status: Option<PathBuf>,
+    /// Seconds between rewrites of the status file.
+    pub status_interval: Option<u64>,
+    /// A Unix socket to serve the status on.
+    pub socket: Option<PathBuf>,
+    /// How many files may be hashed at once, across all profiles.
+    /// Defaults to the number of CPUs.
+    pub hash_threads: Option<usize>,
+    /// A limit on the memory used by each scan, such as "256M".
+    pub memory_limit: Option<String>,
+    pub profiles: Vec<Profile>,
+}
+
+/// A single tree to be scanned periodically.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// The directory to scan.
+    pub dir: PathBuf,
+    /// The store to update, as given to `parse_store`.
+    pub store: String,
+    /// Seconds between the start of each scan.
+    pub interval: u64,
+    /// Comma separated hash algorithms.
+    pub hash: Option<String>,
+    /// Glob patterns of paths to leave out of the scan.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Built-in exclude profiles, such as "linux-host".
+    #[serde(default)]
+    pub exclude_profiles: Vec<String>,
+    /// Descend into other filesystems below `dir`.
+    #[serde(default)]
+    pub cross_filesystems: bool,
+    /// Record what symlinks point to, rather than the links.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Record where symlinks point, and hash the files they point to.
+    #[serde(default)]
+    pub hash_link_targets: bool,
+    /// Record the SELinux security context of each node.
+    #[serde(default)]
+    pub selinux: bool,
+    /// Record the flags, such as immutable, of each file and directory.
+    #[serde(default)]
+    pub flags: bool,
+}
+
+impl Profile {
+    /// The parsed exclude patterns, including those of the built-in
+    /// profiles.
+    pub fn exclude(&self) -> Result<Exclude> {
+        let mut exclude = Exclude::new();
+        for name in &self.exclude_profiles {
+            exclude.add_profile(name)?;
+        }
+        for glob in &self.exclude {
+            exclude.add(glob)?;
+        }
+        Ok(exclude)
+    }
+}
+
+/// The status of every profile, as written to the status file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DaemonStatus {
+    /// The process id of the daemon.
+    #[serde(default)]
+    pub pid: Option<u32>,
+    /// When this status was written.
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
+    pub profiles: BTreeMap<String, ProfileStatus>,
+}
+
+/// The status of a single profile.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProfileStatus {
+    /// When the last run started, if there has been one.
+    pub last_run: Option<DateTime<Utc>>,
+    /// The error from the last run, if it failed.
+    pub last_error: Option<String>,
+    /// When the next run will start.
+    pub next_run: DateTime<Utc>,
+    /// The progress of the scan, if one is running.
+    #[serde(default)]
+    pub activity: Option<ActivityState>,
+}
+
+impl DaemonConfig {
+    /// Read a configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DaemonConfig> {
+        let config: DaemonConfig = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        config.memory_limit()?;
+
+        let mut names = BTreeMap::new();
+        for p in &config.profiles {
+            if names.insert(&p.name, ()).is_some() {
+                return Err(Error::DaemonConfig(format!(
+                    "duplicate profile {:?}",
+                    p.name
+                )));
+            }
+            if p.interval == 0 {
+                return Err(Error::DaemonConfig(format!(
+                    "profile {:?} has a zero interval",
+                    p.name
+                )));
+            }
+            if let Some(hash) = &p.hash {
+                HashAlgorithm::parse_list(hash)?;
+            }
+            p.exclude()?;
+        }
+
+        Ok(config)
+    }
+
+    /// The parsed memory limit, if one is given.
+    pub fn memory_limit(&self) -> Result<Option<MemoryLimit>> {
+        self.memory_limit.as_deref().map(str::parse).transpose()
+    }
+}
+
+/// Read a status file, as written by a running daemon.
+pub fn load_status<P: AsRef<Path>>(path: P) -> Result<DaemonStatus> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+}
+
+pub struct Daemon {
+    config: DaemonConfig,
+    status_path: PathBuf,
+    status: Mutex<DaemonStatus>,
+    activities: BTreeMap<String, Arc<Activity>>,
+    pool: Arc<HashPool>,
+    // Held while writing the status file, as several threads write it.
+    save_lock: Mutex<()>,
+    stop: AtomicBool,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl Daemon {
+    /// Load the daemon's configuration file.  If there is a status file
+    /// from an earlier run, profiles continue on their old schedule,
+    /// otherwise they are all due to run immediately.
+    pub fn new<P: AsRef<Path>>(config_path: P) -> Result<Daemon> {
+        let config_path = config_path.as_ref();
+        let config = DaemonConfig::load(config_path)?;
+        let status_path = Daemon::status_path(&config, config_path);
+
+        let old = load_status(&status_path).unwrap_or_default();
+        let now = clock::default_clock().now();
+        let mut status = DaemonStatus::default();
+        for p in &config.profiles {
+            let entry = match old.profiles.get(&p.name) {
+                Some(entry) => entry.clone(),
+                None => ProfileStatus {
+                    last_run: None,
+                    last_error: None,
+                    next_run: now,
+                    activity: None,
+                },
+            };
+            status.profiles.insert(p.name.clone(), entry);
+        }
+
+        let activities = config
+            .profiles
+            .iter()
+            .map(|p| (p.name.clone(), Arc::new(Activity::new())))
+            .collect();
+        let threads = config.hash_threads.unwrap_or_else(num_cpus::get);
+        Ok(Daemon {
+            config,
+            status_path,
+            status: Mutex::new(status),
+            activities,
+            pool: Arc::new(HashPool::new(threads)),
+            save_lock: Mutex::new(()),
+            stop: AtomicBool::new(false),
+            progress: None,
+        })
+    }
+
+    /// Report the progress of the scans to the given sink, instead of the
+    /// terminal.
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> Daemon {
+        self.progress = Some(sink);
+        self
+    }
+
+    /// Determine where the status file for the given configuration lives.
+    pub fn status_path(config: &DaemonConfig, config_path: &Path) -> PathBuf {
+        match &config.status {
+            Some(path) => path.clone(),
+            None => {
+                let mut name = OsString::from(config_path.as_os_str());
+                name.push(".status");
+                PathBuf::from(name)
+            }
+        }
+    }
+
+    /// The profiles from the configuration.
+    pub fn profiles(&self) -> &[Profile] {
+        &self.config.profiles
+    }
+
+    /// The current status, including the progress of running scans.
+    pub fn status(&self) -> DaemonStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        status.pid = Some(process::id());
+        status.updated = Some(clock::default_clock().now());
+        for (name, entry) in &mut status.profiles {
+            let activity = self.activities[name].snapshot();
+            entry.activity = if activity.phase == Phase::Idle {
+                None
+            } else {
+                Some(activity)
+            };
+        }
+        status
+    }
+
+    /// Ask a running daemon to shut down.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    fn stopping(&self) -> bool {
+        self.stop.load(Ordering::SeqCst) || service::shutdown_requested()
+    }
+
+    /// Sleep for the given time, waking early if asked to shut down.
+    /// Returns false if the daemon is shutting down.
+    fn wait(&self, duration: time::Duration) -> bool {
+        let tick = time::Duration::from_millis(250);
+        let end = time::Instant::now() + duration;
+        loop {
+            if self.stopping() {
+                return false;
+            }
+            let now = time::Instant::now();
+            if now >= end {
+                return true;
+            }
+            thread::sleep(tick.min(end - now));
+        }
+    }
+
+    /// Run every profile on its schedule, until asked to shut down.
+    pub fn run(&self) -> Result<()> {
+        self.save_status()?;
+        #[cfg(unix)]
+        let listener = match &self.config.socket {
+            Some(path) => {
+                // Remove a socket left behind by an earlier daemon.
+                let _ = fs::remove_file(path);
+                Some(std::os::unix::net::UnixListener::bind(path)?)
+            }
+            None => None,
+        };
+        let interval = time::Duration::from_secs(self.config.status_interval.unwrap_or(10));
+
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                while self.wait(interval) {
+                    if let Err(e) = self.save_status() {
+                        error!("Unable to write status: {}", e);
+                    }
+                }
+                let _ = service::notify("STOPPING=1");
+                info!("Shutting down, waiting for running scans");
+
+                // Wake the status socket, so it notices the shutdown.
+                #[cfg(unix)]
+                if let Some(path) = &self.config.socket {
+                    let _ = std::os::unix::net::UnixStream::connect(path);
+                }
+            });
+
+            if let Some(watchdog) = service::watchdog_interval() {
+                s.spawn(move |_| loop {
+                    if let Err(e) = service::notify("WATCHDOG=1") {
+                        error!("Unable to notify watchdog: {}", e);
+                    }
+                    if !self.wait(watchdog) {
+                        break;
+                    }
+                });
+            }
+
+            #[cfg(unix)]
+            if let Some(listener) = &listener {
+                s.spawn(move |_| {
+                    for stream in listener.incoming() {
+                        if self.stopping() {
+                            break;
+                        }
+                        let result = stream.map_err(Error::from).and_then(|mut stream| {
+                            serde_json::to_writer_pretty(&mut stream, &self.status())?;
+                            writeln!(stream)?;
+                            Ok(())
+                        });
+                        if let Err(e) = result {
+                            error!("Status socket: {}", e);
+                        }
+                    }
+                });
+            }
+
+            for profile in &self.config.profiles {
+                s.spawn(move |_| loop {
+                    let next = self.status.lock().unwrap().profiles[&profile.name].next_run;
+                    let wait = (next - clock::default_clock().now())
+                        .to_std()
+                        .unwrap_or_default();
+                    if !self.wait(wait) {
+                        break;
+                    }
+                    if let Err(e) = self.run_profile(profile) {
+                        error!("Profile {:?}: {}", profile.name, e);
+                    }
+                });
+            }
+
+            if let Err(e) = service::notify("READY=1") {
+                error!("Unable to notify service manager: {}", e);
+            }
+        })
+        .map_err(|e| Error::DaemonConfig(format!("profile thread panicked: {:?}", e)))?;
+
+        #[cfg(unix)]
+        if let Some(path) = &self.config.socket {
+            let _ = fs::remove_file(path);
+        }
+        self.save_status()
+    }
+
+    /// Scan a single profile now, recording the result, and scheduling its
+    /// next run.  The error from the scan is returned, as well as recorded
+    /// in the status.
+    pub fn run_profile(&self, profile: &Profile) -> Result<()> {
+        let start = clock::default_clock().now();
+        info!("Profile {:?}: scanning {:?}", profile.name, profile.dir);
+        let result = self.scan(profile, start);
+        self.activities[&profile.name].set_phase(Phase::Idle);
+
+        {
+            let mut status = self.status.lock().unwrap();
+            let entry = status.profiles.get_mut(&profile.name).unwrap();
+            entry.last_run = Some(start);
+            entry.last_error = result.as_ref().err().map(|e| e.to_string());
+            entry.next_run = start + Duration::seconds(profile.interval as i64);
+        }
+        self.save_status()?;
+        result
+    }
+
+    fn scan(&self, profile: &Profile, start: DateTime<Utc>) -> Result<()> {
+        let store = parse_store(&profile.store)?;
+
+        // A store with no versions yet gets a fresh scan.
+        let latest = store.get_version(&Version::Latest)?;
+        let algorithms = match (&profile.hash, &latest) {
+            (Some(hash), _) => HashAlgorithm::parse_list(hash)?,
+            (None, Some(v)) => HashAlgorithm::from_tags(&v.tags)?,
+            (None, None) => vec![HashAlgorithm::default()],
+        };
+
+        let mut tags = StoreTags::new();
+        tags.insert("name".to_string(), start.with_timezone(&Local).to_rfc3339());
+        tags.insert(
+            "dir".to_string(),
+            profile.dir.canonicalize()?.to_string_lossy().into_owned(),
+        );
+        tags.insert("profile".to_string(), profile.name.clone());
+
+        let hooks = UpdateHooks {
+            pool: Some(self.pool.clone()),
+            activity: Some(self.activities[&profile.name].clone()),
+            memory_limit: self.config.memory_limit()?,
+            scan: ScanOptions {
+                cross_filesystems: profile.cross_filesystems,
+                follow_symlinks: profile.follow_symlinks,
+                hash_link_targets: profile.hash_link_targets,
+                selinux: profile.selinux,
+                flags: profile.flags,
+                exclude: profile.exclude()?,
+                ..ScanOptions::default()
+            },
+            progress: self.progress.clone(),
+            ..UpdateHooks::default()
+        };
+        update_with(
+            &profile.dir,
+            &*store,
+            latest.is_some(),
+            &tags,
+            &algorithms,
+            hooks,
+        )
+    }
+
+    /// Write the status file.  It is written to a temp file and renamed,
+    /// so readers never see a partial status.
+    fn save_status(&self) -> Result<()> {
+        let _lock = self.save_lock.lock().unwrap();
+        let status = self.status();
+        let mut tmp_name = OsString::from(self.status_path.as_os_str());
+        tmp_name.push(".tmp");
+        let tmp_name = PathBuf::from(tmp_name);
+
+        let mut fd = File::create(&tmp_name)?;
+        serde_json::to_writer_pretty(&mut fd, &status)?;
+        writeln!(fd)?;
+        drop(fd);
+        fs::rename(&tmp_name, &self.status_path)?;
+        Ok(())
+    }
+}
@@ -17,14 +17,34 @@ pub enum Error {
     #[error("Int parse error: {0:?}")]
     IntParse(#[from] std::num::ParseIntError),
 
+    #[error("{0} filesystems failed to scan")]
+    SystemScan(usize),
     #[error("Root must be a directory")]
     RootMustBeDir,
+    #[error("Refusing to scan {0:?}, a {1} pseudo filesystem")]
+    PseudoFilesystem(std::path::PathBuf, String),
+    #[error("Can't name {0:?} as one of several directories")]
+    RootName(std::path::PathBuf),
+    #[error("Several directories are named {0:?}")]
+    DuplicateRoot(String),
+    #[error("{0} takes a single directory")]
+    SeveralRoots(String),
+    #[error("No version {0} in the store")]
+    UnknownVersion(String),
+    #[error("The destination store already has versions")]
+    StoreNotEmpty,
+    #[error("Cancelled")]
+    Cancelled,
     #[error("Unknown directory specified")]
     UnknownDirectory,
     #[error("File not in directory")]
     FileNotInDirectory,
     #[error("Path missing final file component")]
     PathMissingFinalFile,
+    #[error("No {0:?} in the version shown")]
+    PathNotInVersion(std::path::PathBuf),
+    #[error("No directory {0:?} in either version")]
+    DirNotInVersions(std::path::PathBuf),
 
     // Errors from comparison.
     #[error("empty left iterator")]
@@ -46,9 +66,40 @@ pub enum Error {
     SureFileEof,
     #[error("Truncated surefile")]
     TruncatedSurefile,
+    #[error("Unexpected node in surefile")]
+    UnexpectedNode,
+    #[error("Invalid path {0:?} in path list, expect one relative to the top of the tree")]
+    InvalidPathList(String),
+    #[error("Version {0:?} wasn't hashed with {1}")]
+    NotHashed(String, String),
+    #[error("Path {0:?} has a newline, which can only be listed ending with a NUL")]
+    NewlineInPath(String),
+    #[error("Invalid fleet file {0}")]
+    Fleet(String),
+    #[error("Invalid age {0:?}, expecting a number and one of s, m, h, d or w")]
+    InvalidAge(String),
+    #[error("Invalid pin file {0}")]
+    InvalidPin(String),
+    #[error("Invalid ignore rule: {0}")]
+    InvalidIgnore(String),
+    #[error("The pinned version {0} ({1}) is no longer in the store")]
+    StalePin(String, String),
+    #[error("Invalid checksum manifest, {0}")]
+    Manifest(String),
+    #[error("Invalid mtree specification, {0}")]
+    Mtree(String),
+    #[error("Invalid escaped name in surefile: {0:?}")]
+    InvalidEscape(String),
     #[error("Invalid surefile line start: {0:?}")]
     InvalidSurefileChar(char),
+    #[error("Temp file {0:?} is corrupt, its checksum doesn't match")]
+    TempChecksum(std::path::PathBuf),
+    #[error("The store's weave has {0} problems")]
+    StoreProblems(usize),
+    #[error("This store can't {0}")]
+    Unsupported(String),
 
+    #[cfg(feature = "sqlite")]
     #[error("Sql error: {0:?}")]
     Sql(#[from] rusqlite::Error),
     // For one case that needs to be written to be able to move the error.
@@ -56,6 +107,42 @@ pub enum Error {
     WrappedSql(String),
     #[error("Hash error: {0:?}")]
     Hash(String),
+    #[error("Unknown hash algorithm: {0:?}")]
+    UnknownHash(String),
+    #[error("Unknown hash reuse {0:?}, expect \"metadata\" or \"never\"")]
+    UnknownHashReuse(String),
+    #[error("Unknown report format {0:?}")]
+    UnknownFormat(String),
+    #[error("Invalid digest {0:?}, expect a hash in hex")]
+    InvalidDigest(String),
+    #[error("Invalid timestamp {0:?}, expect RFC 3339")]
+    InvalidTimestamp(String),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid size {0:?}, expect a number with an optional K, M or G suffix")]
+    InvalidSize(String),
+    #[error("Invalid rate {0:?}, expect megabytes per second, or a size with a K, M or G suffix")]
+    InvalidRate(String),
+    #[error("Invalid exclude pattern {0:?}")]
+    InvalidPattern(String),
+    #[error("Unknown exclude profile {0:?}")]
+    UnknownProfile(String),
+    #[error("Object store error: {0}")]
+    ObjectStore(String),
+    #[error("SSH error: {0}")]
+    Ssh(String),
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+    #[error("Signature error: {0}")]
+    Signature(String),
+    #[error("Version {0:?} is not signed")]
+    Unsigned(String),
+    #[error("The signature of version {0:?} doesn't match, it may have been altered")]
+    BadSignature(String),
+    #[error("Invalid artifact kind {0:?}")]
+    InvalidArtifact(String),
+    #[error("Daemon configuration error: {0}")]
+    DaemonConfig(String),
     #[error("mpsc error: {0:?}")]
     Mpsc(#[from] std::sync::mpsc::RecvError),
 }
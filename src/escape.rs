@@ -117,6 +117,6 @@ fn test_unescape() {
 #[test]
 fn test_escape() {
     let buf: Vec<u8> = (0u32..256).map(|i| i as u8).collect();
-    let text = (&buf[..]).escaped();
+    let text = buf[..].escaped();
     assert_eq!(text.unescape().unwrap(), buf);
 }
@@ -0,0 +1,258 @@
+//! Leaving paths out of a scan.
+//!
+//! Exclude patterns are globs, matched against each path relative to the
+//! root of the scan.  `*` and `?` match within a single path component,
+//! `**` matches across components, and `[...]` matches a class of
+//! characters (`[!...]` for its complement).  A pattern without a `/`
+//! matches a name at any depth, such as `*.tmp` or `.cache`, whereas one
+//! with a `/` is relative to the root, such as `var/tmp` or `/spool`.  A
+//! trailing `/` matches only directories.  Excluding a directory leaves out
+//! everything beneath it.
+//!
+//! If the root of a scan has a `.rsureignore` file, its lines are added as
+//! patterns, ignoring blank lines, and those starting with `#`.
+//!
+//! There are also built-in profiles, sets of patterns in the same format
+//! for common sources of noise, such as `linux-host`, which leaves out
+//! `/proc`, `/sys`, `/run`, `/tmp`, lock files and editor temp files from a
+//! scan of a whole host.
+//!
+//! A scan remembers what it left out, as [`Tombstones`], which are kept in
+//! the snapshot's tags, so that a path that is newly excluded can be told
+//! apart from one deleted from disk.
+
+use crate::{platform::path_bytes, Error, Result, StoreTags};
+use regex::bytes::Regex;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// The file at the root of a scan that lists patterns to exclude.
+pub const IGNORE_FILE: &str = ".rsureignore";
+
+/// The built-in profiles, by name.
+const PROFILES: &[(&str, &str)] = &[
+    ("editors", include_str!("profiles/editors.ignore")),
+    ("linux-host", include_str!("profiles/linux-host.ignore")),
+];
+
+/// A set of patterns naming paths to leave out of a scan.
+#[derive(Clone, Debug, Default)]
+pub struct Exclude {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    // The glob, as given, which identifies the rule.
+    text: String,
+    regex: Regex,
+    dir_only: bool,
+}
+
+impl Exclude {
+    pub fn new() -> Exclude {
+        Exclude::default()
+    }
+
+    /// Add a single glob pattern.
+    pub fn add(&mut self, glob: &str) -> Result<()> {
+        let text = glob.to_string();
+        let (glob, dir_only) = match glob.strip_suffix('/') {
+            Some(glob) => (glob, true),
+            None => (glob, false),
+        };
+        if glob.is_empty() {
+            return Err(Error::InvalidPattern(glob.to_string()));
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.trim_start_matches('/');
+
+        let mut re = String::from("(?s-u)^");
+        if !anchored {
+            re.push_str("(?:.*/)?");
+        }
+        translate(glob, &mut re)?;
+        re.push('$');
+
+        let regex = Regex::new(&re).map_err(|_| Error::InvalidPattern(glob.to_string()))?;
+        self.patterns.push(Pattern {
+            text,
+            regex,
+            dir_only,
+        });
+        Ok(())
+    }
+
+    /// Add the patterns from an ignore file, one per line.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.add_lines(&fs::read_to_string(path)?)
+    }
+
+    /// Add the patterns of a built-in profile.
+    pub fn add_profile(&mut self, name: &str) -> Result<()> {
+        match PROFILES.iter().find(|(n, _)| *n == name) {
+            Some((_, text)) => self.add_lines(text),
+            None => Err(Error::UnknownProfile(name.to_string())),
+        }
+    }
+
+    /// The names of the built-in profiles.
+    pub fn profile_names() -> impl Iterator<Item = &'static str> {
+        PROFILES.iter().map(|(name, _)| *name)
+    }
+
+    fn add_lines(&mut self, text: &str) -> Result<()> {
+        for line in text.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.add(line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the patterns from the root's ignore file, if it has one.
+    pub fn add_root<P: AsRef<Path>>(&mut self, root: P) -> Result<()> {
+        let path = root.as_ref().join(IGNORE_FILE);
+        if path.is_file() {
+            self.add_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Add all of the patterns from another set.
+    pub fn extend(&mut self, other: &Exclude) {
+        self.patterns.extend(other.patterns.iter().cloned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Should the given path, relative to the root, be left out?
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.matching(path, is_dir).is_some()
+    }
+
+    /// The first pattern, as it was given, that leaves out the given path,
+    /// relative to the root.
+    pub fn matching(&self, path: &Path, is_dir: bool) -> Option<&str> {
+        let path = path_bytes(path);
+        self.patterns
+            .iter()
+            .find(|p| (is_dir || !p.dir_only) && p.regex.is_match(&path))
+            .map(|p| p.text.as_str())
+    }
+}
+
+/// The tag, on each version in a store, holding the paths its scan left
+/// out.  Versions without it either excluded nothing, or predate it.
+pub const EXCLUDED_TAG: &str = "excluded";
+
+/// The paths a scan left out, relative to the root and escaped, each with
+/// the pattern that excluded it.  A directory is recorded once, not for
+/// each of its contents.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Tombstones {
+    paths: BTreeMap<String, String>,
+}
+
+impl Tombstones {
+    pub fn new() -> Tombstones {
+        Tombstones::default()
+    }
+
+    /// Record that a path was left out by the given pattern.
+    pub fn insert(&mut self, path: String, rule: &str) {
+        self.paths.insert(path, rule.to_string());
+    }
+
+    /// The pattern that left out a path, if it was left out.
+    pub fn rule(&self, path: &str) -> Option<&str> {
+        self.paths.get(path).map(|r| r.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// The paths, and the pattern that left out each, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.paths.iter().map(|(p, r)| (p.as_str(), r.as_str()))
+    }
+
+    /// Read the tombstones recorded in a version's tags.
+    pub fn from_tags(tags: &StoreTags) -> Result<Tombstones> {
+        match tags.get(EXCLUDED_TAG) {
+            None => Ok(Tombstones::new()),
+            Some(text) => Ok(Tombstones {
+                paths: serde_json::from_str(text)?,
+            }),
+        }
+    }
+
+    /// Record these in a version's tags, unless there are none.
+    pub fn add_to_tags(&self, tags: &mut StoreTags) {
+        if !self.is_empty() {
+            let text = serde_json::to_string(&self.paths).expect("string map serializes");
+            tags.insert(EXCLUDED_TAG.to_string(), text);
+        }
+    }
+}
+
+/// Translate a glob into the equivalent regex, appending it to `out`.
+fn translate(glob: &str, out: &mut String) -> Result<()> {
+    let bad = || Error::InvalidPattern(glob.to_string());
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // "**/" matches any number of leading directories,
+                    // including none.
+                    chars.next();
+                    out.push_str("(?:.*/)?");
+                } else {
+                    out.push_str(".*");
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => {
+                out.push('[');
+                if let Some('!') | Some('^') = chars.peek() {
+                    chars.next();
+                    out.push('^');
+                }
+                let mut empty = true;
+                loop {
+                    match chars.next() {
+                        None => return Err(bad()),
+                        Some(']') if !empty => break,
+                        Some('\\') => {
+                            let ch = chars.next().ok_or_else(bad)?;
+                            out.push_str(&regex::escape(&ch.to_string()));
+                        }
+                        Some(ch @ ('[' | ']' | '&' | '~')) => {
+                            out.push('\\');
+                            out.push(ch);
+                        }
+                        Some(ch) => out.push(ch),
+                    }
+                    empty = false;
+                }
+                out.push(']');
+            }
+            '\\' => {
+                let ch = chars.next().ok_or_else(bad)?;
+                out.push_str(&regex::escape(&ch.to_string()));
+            }
+            ch => out.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! Exporting a version of a store for other tools.
+//!
+//! The `mtree` format is the specification read by BSD mtree(8) (`mtree -f`) and libarchive, so
+//! that a snapshot can be checked, or a tree built from it, on systems without rsure.
+//!
+//! The `sha256sum` and `sha1sum` formats are the manifests written by the coreutils tools of the
+//! same name, a line of `<hash>  <path>` for each file, so that the files can be checked with
+//! `sha256sum -c` from the top of the tree.  Files without a hash, such as those that couldn't be
+//! read when the version was made, are left out.
+//!
+//! `export_changed` lists just the paths added or modified between two versions, such as for
+//! `rsync --files-from`, or a backup or restore of only what changed.
+
+use crate::{
+    mtree::{self, unescape},
+    node::compare_trees,
+    store::{Store, StoreVersion, Version},
+    ChangeAction, Error, HashAlgorithm, Result, SureNode,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The formats a version can be exported in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// A BSD mtree(8) specification.
+    #[default]
+    Mtree,
+    /// A manifest for `sha256sum -c`.
+    Sha256sum,
+    /// A manifest for `sha1sum -c`.
+    Sha1sum,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<ExportFormat> {
+        match text {
+            "mtree" => Ok(ExportFormat::Mtree),
+            "sha256sum" => Ok(ExportFormat::Sha256sum),
+            "sha1sum" => Ok(ExportFormat::Sha1sum),
+            _ => Err(Error::UnknownFormat(text.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportFormat::Mtree => write!(f, "mtree"),
+            ExportFormat::Sha256sum => write!(f, "sha256sum"),
+            ExportFormat::Sha1sum => write!(f, "sha1sum"),
+        }
+    }
+}
+
+/// Write a version of the store to `out` in the given format.
+pub fn export<W: Write>(
+    store: &dyn Store,
+    version: &Version,
+    format: ExportFormat,
+    mut out: W,
+) -> Result<()> {
+    let info = store
+        .get_version(version)?
+        .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+    let nodes = store.load_iter(info.version.clone())?;
+    match format {
+        ExportFormat::Mtree => mtree::write(&info, nodes, &mut out)?,
+        ExportFormat::Sha256sum => write_sums(&info, HashAlgorithm::Sha256, nodes, &mut out)?,
+        ExportFormat::Sha1sum => write_sums(&info, HashAlgorithm::Sha1, nodes, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_sums<I, W>(
+    info: &StoreVersion,
+    algorithm: HashAlgorithm,
+    nodes: I,
+    out: &mut W,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    W: Write,
+{
+    if !HashAlgorithm::from_tags(&info.tags)?.contains(&algorithm) {
+        return Err(Error::NotHashed(info.name.clone(), algorithm.to_string()));
+    }
+
+    // The path of the directory, below the root.
+    let mut dirs: Vec<Vec<u8>> = vec![];
+    let mut depth = 0;
+    for node in nodes {
+        match node? {
+            SureNode::Enter { name, .. } => {
+                if depth > 0 {
+                    dirs.push(unescape(&name)?);
+                }
+                depth += 1;
+            }
+            SureNode::Leave => {
+                depth -= 1;
+                dirs.pop();
+            }
+            SureNode::Sep => (),
+            SureNode::File { name, atts } => {
+                let hash = match atts.get(algorithm.name()) {
+                    Some(hash) if atts.get("kind").map(|k| k.as_str()) == Some("file") => hash,
+                    _ => continue,
+                };
+                let mut path = dirs.join(&b'/');
+                if !path.is_empty() {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(&unescape(&name)?);
+                write_sum(hash, &path, b'\n', out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a line of a sums manifest.  As the tools do, a name with a backslash or newline is
+/// escaped, and the line marked with a leading backslash.
+fn write_sum<W: Write>(hash: &str, path: &[u8], end: u8, out: &mut W) -> Result<()> {
+    let mut path = path.to_vec();
+    if path.contains(&b'\\') || path.contains(&b'\n') {
+        let mut escaped = Vec::with_capacity(path.len() + 2);
+        for &ch in &path {
+            match ch {
+                b'\\' => escaped.extend_from_slice(b"\\\\"),
+                b'\n' => escaped.extend_from_slice(b"\\n"),
+                ch => escaped.push(ch),
+            }
+        }
+        out.write_all(b"\\")?;
+        path = escaped;
+    }
+    out.write_all(hash.as_bytes())?;
+    out.write_all(b"  ")?;
+    out.write_all(&path)?;
+    out.write_all(&[end])?;
+    Ok(())
+}
+
+/// How `export_changed` lists the paths.
+#[derive(Clone, Debug, Default)]
+pub struct ChangedOptions {
+    /// Write each file's hash with this algorithm before its path, as a `sha256sum` manifest
+    /// does, so what is copied can be checked.  Only regular files are listed then, as nothing
+    /// else has a hash.
+    pub hashes: Option<HashAlgorithm>,
+    /// End each line with a NUL rather than a newline, as `rsync --from0` reads, so that any
+    /// name can be listed.
+    pub null: bool,
+}
+
+/// Write the paths added or modified from version `from` to version `to`, relative to the top of
+/// the tree, one per line, in tree order.  Everything in an added directory is listed, as well
+/// as the directory itself.  Returns the number of paths written.
+pub fn export_changed<W: Write>(
+    store: &dyn Store,
+    from: &Version,
+    to: &Version,
+    options: &ChangedOptions,
+    mut out: W,
+) -> Result<usize> {
+    let get = |version: &Version| {
+        store
+            .get_version(version)?
+            .ok_or_else(|| Error::UnknownVersion(version.to_string()))
+    };
+    let from = get(from)?;
+    let to = get(to)?;
+    if let Some(algorithm) = options.hashes {
+        if !HashAlgorithm::from_tags(&to.tags)?.contains(&algorithm) {
+            return Err(Error::NotHashed(to.name.clone(), algorithm.to_string()));
+        }
+    }
+
+    // What changed, by the path compare gives, made of the escaped names.
+    let mut changed = HashMap::new();
+    compare_trees(
+        store.load_iter(from.version.clone())?,
+        store.load_iter(to.version.clone())?,
+        Path::new(""),
+        &[],
+        |change| {
+            if let ChangeAction::Added | ChangeAction::Modified = change.action {
+                changed.insert(change.path, change.action);
+            }
+        },
+    )?;
+
+    let end = if options.null { 0 } else { b'\n' };
+    let mut count = 0;
+    // The escaped and real names of the directories, below the root, and how many of them are
+    // within an added one, whose contents are all new.
+    let mut names = PathBuf::new();
+    let mut dirs: Vec<Vec<u8>> = vec![];
+    let mut depth = 0;
+    let mut added: Option<usize> = None;
+    for node in store.load_iter(to.version)? {
+        let node = node?;
+        let name = match &node {
+            SureNode::Enter { name, .. } => {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+                name
+            }
+            SureNode::File { name, .. } => name,
+            SureNode::Leave => {
+                if added == Some(depth) {
+                    added = None;
+                }
+                if depth > 1 {
+                    names.pop();
+                    dirs.pop();
+                }
+                depth -= 1;
+                continue;
+            }
+            SureNode::Sep => continue,
+        };
+
+        names.push(name);
+        let action = changed.get(&names).copied();
+        let real = unescape(name)?;
+        let mut path = dirs.join(&b'/');
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(&real);
+        if node.is_file() {
+            names.pop();
+        } else {
+            dirs.push(real);
+            if added.is_none() && action == Some(ChangeAction::Added) {
+                added = Some(depth);
+            }
+        }
+        if action.is_none() && added.is_none() {
+            continue;
+        }
+
+        match options.hashes {
+            Some(algorithm) => {
+                let hash = match &node {
+                    SureNode::File { atts, .. }
+                        if atts.get("kind").map(|k| k.as_str()) == Some("file") =>
+                    {
+                        atts.get(algorithm.name())
+                    }
+                    _ => None,
+                };
+                match hash {
+                    Some(hash) => write_sum(hash, &path, end, &mut out)?,
+                    None => continue,
+                }
+            }
+            None => {
+                if !options.null && path.contains(&b'\n') {
+                    return Err(Error::NewlineInPath(
+                        String::from_utf8_lossy(&path).into_owned(),
+                    ));
+                }
+                out.write_all(&path)?;
+                out.write_all(&[end])?;
+            }
+        }
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
@@ -0,0 +1,138 @@
+//! The paths an update couldn't read.
+//!
+//! A directory that can't be listed is recorded as empty, a file that
+//! can't be stat'ed is left out, and one that can't be read is left without
+//! a hash.  These are logged as they happen, but are also collected, and
+//! recorded with the new version, so that it is known how much of the tree
+//! the version covers.  Only the first [`MAX_FAILURES`] are kept, along
+//! with a count of the rest, so that a tree that is mostly unreadable
+//! doesn't make for an enormous tag.
+
+use crate::{Result, StoreTags};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The tag recording the paths that couldn't be read.
+pub const FAILURES_TAG: &str = "failures";
+
+/// The tag counting the paths that couldn't be read beyond those recorded.
+pub const FAILURES_OMITTED_TAG: &str = "failures-omitted";
+
+/// How many paths are recorded, with why each couldn't be read.
+pub const MAX_FAILURES: usize = 1000;
+
+/// The paths that couldn't be read, each with why.  Clones share the same
+/// paths, so that the scan, and each hashing thread, can add to them.
+#[derive(Clone, Debug, Default)]
+pub struct Failures {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    paths: BTreeMap<String, String>,
+    // How many more there were, once `paths` was full.
+    omitted: usize,
+}
+
+impl Failures {
+    pub fn new() -> Failures {
+        Failures::default()
+    }
+
+    /// Record that `path` couldn't be read.  Past [`MAX_FAILURES`] paths,
+    /// it is only counted.
+    pub fn add(&self, path: &Path, error: String) {
+        let mut inner = self.inner.lock().unwrap();
+        let path = path.to_string_lossy().into_owned();
+        if inner.paths.len() < MAX_FAILURES || inner.paths.contains_key(&path) {
+            inner.paths.insert(path, error);
+        } else {
+            inner.omitted += 1;
+        }
+    }
+
+    /// How many paths couldn't be read, including those not recorded.
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.paths.len() + inner.omitted
+    }
+
+    /// How many paths couldn't be read beyond those recorded.
+    pub fn omitted(&self) -> usize {
+        self.inner.lock().unwrap().omitted
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The recorded paths, in order, with why each couldn't be read.
+    pub fn list(&self) -> Vec<(String, String)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .paths
+            .iter()
+            .map(|(p, e)| (p.clone(), e.clone()))
+            .collect()
+    }
+
+    /// The failures recorded in a version's tags.
+    pub fn from_tags(tags: &StoreTags) -> Result<Failures> {
+        let paths = match tags.get(FAILURES_TAG) {
+            None => BTreeMap::new(),
+            Some(text) => serde_json::from_str(text)?,
+        };
+        let omitted = match tags.get(FAILURES_OMITTED_TAG) {
+            None => 0,
+            Some(text) => text.parse()?,
+        };
+        Ok(Failures {
+            inner: Arc::new(Mutex::new(Inner { paths, omitted })),
+        })
+    }
+
+    /// Record these in a version's tags, unless there are none.
+    pub fn add_to_tags(&self, tags: &mut StoreTags) {
+        let inner = self.inner.lock().unwrap();
+        if !inner.paths.is_empty() {
+            let text = serde_json::to_string(&inner.paths).expect("string map serializes");
+            tags.insert(FAILURES_TAG.to_string(), text);
+        }
+        if inner.omitted > 0 {
+            tags.insert(FAILURES_OMITTED_TAG.to_string(), inner.omitted.to_string());
+        }
+    }
+}
+
+// Paths made to fail, for testing.  The flag saves taking the lock when there
+// are none, as there never are outside of tests.
+static INJECTING: AtomicBool = AtomicBool::new(false);
+static INJECTED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Make reading `path` fail, as if it weren't permitted, for the rest of
+/// the process.  This is for testing how unreadable paths are handled,
+/// which can't be done with file modes when running as root.
+#[doc(hidden)]
+pub fn inject_failure(path: &Path) {
+    INJECTED.lock().unwrap().insert(path.to_owned());
+    INJECTING.store(true, Ordering::Release);
+}
+
+/// Fail, if `path` has been made to with [`inject_failure`].
+pub(crate) fn injected(path: &Path) -> io::Result<()> {
+    if INJECTING.load(Ordering::Acquire) && INJECTED.lock().unwrap().contains(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Injected failure",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,254 @@
+//! Gathering the results of runs on many hosts.
+//!
+//! Each run of rsure can write a [`RunSummary`], a small JSON record of
+//! what ran, on which host and store, when, and what it found.  These are
+//! collected from the hosts, by whatever means, and ingested into a
+//! [`Fleet`], a file of summaries kept on one machine, one JSON summary per
+//! line.  The fleet's report then names the hosts needing attention: those
+//! whose last check found changes or failed, those whose last run changed
+//! more than a threshold, and those that haven't run recently.
+//!
+//! A host may keep several stores, such as one per filesystem, and each is
+//! reported on its own.
+
+use crate::{clock, platform, ChangeSummary, Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The result of a single run of rsure.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunSummary {
+    pub host: String,
+    /// The store, as given to rsure on the host.
+    pub store: String,
+    /// The command run, such as "check" or "update".
+    pub command: String,
+    /// When the run finished.
+    pub time: DateTime<Utc>,
+    /// The changes found: by a check, from the version compared against to
+    /// the tree, and by a scan or update, from the version before.
+    #[serde(default)]
+    pub changes: Option<ChangeSummary>,
+    /// Why the run failed, if it did.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl RunSummary {
+    /// A summary of a run on this host, finishing now.
+    pub fn new(store: &str, command: &str) -> RunSummary {
+        RunSummary {
+            host: platform::hostname(),
+            store: store.to_string(),
+            command: command.to_string(),
+            time: clock::default_clock().now(),
+            changes: None,
+            error: None,
+        }
+    }
+
+    /// Write the summary as a line of JSON.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        serde_json::to_writer(&mut out, self)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// Did the run fail, or, for a check, find anything changed?
+    pub fn failed(&self) -> bool {
+        self.error.is_some() || (self.command == "check" && total(self.changes) > 0)
+    }
+}
+
+fn total(changes: Option<ChangeSummary>) -> usize {
+    changes.map_or(0, |c| c.count())
+}
+
+/// What the fleet's report looks for.
+#[derive(Clone, Debug, Default)]
+pub struct Thresholds {
+    /// Report a store whose last run found more changes than this.
+    pub max_changes: Option<usize>,
+    /// Report a store that hasn't run for longer than this.
+    pub stale: Option<Duration>,
+}
+
+/// Something wrong with a store of the fleet.
+#[derive(Clone, Debug)]
+pub enum Problem {
+    /// The last check found changes, or failed.
+    Failed(RunSummary),
+    /// The last run found more changes than allowed.
+    Drifted(RunSummary),
+    /// Nothing has run since this time.
+    Stale(DateTime<Utc>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Failed(run) => match (&run.error, run.changes) {
+                (Some(error), _) => write!(f, "{} failed: {}", run.command, error),
+                (None, Some(changes)) => write!(f, "{} found changes {}", run.command, changes),
+                (None, None) => write!(f, "{} failed", run.command),
+            },
+            Problem::Drifted(run) => write!(
+                f,
+                "{} found {} changes {}",
+                run.command,
+                total(run.changes),
+                run.changes.unwrap_or_default()
+            ),
+            Problem::Stale(time) => write!(f, "no run since {}", time.to_rfc3339()),
+        }
+    }
+}
+
+/// The state of a single store on a host.
+#[derive(Clone, Debug)]
+pub struct StoreStatus {
+    pub host: String,
+    pub store: String,
+    /// The most recent run.
+    pub last: RunSummary,
+    /// The most recent check, if there has been one.
+    pub last_check: Option<RunSummary>,
+    /// What is wrong, if anything.
+    pub problems: Vec<Problem>,
+}
+
+/// The summaries gathered from a fleet of hosts, kept in a file.
+pub struct Fleet {
+    path: PathBuf,
+}
+
+impl Fleet {
+    /// The fleet kept in the given file, which is made by the first ingest.
+    pub fn open<P: AsRef<Path>>(path: P) -> Fleet {
+        Fleet {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Add the summaries read from `input`, which holds any number of
+    /// them, skipping any already in the fleet.  Returns the number added.
+    pub fn ingest<R: Read>(&self, input: R) -> Result<usize> {
+        let mut seen: HashSet<_> = self.summaries()?.iter().map(key).collect();
+        let mut out = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut added = 0;
+        for run in serde_json::Deserializer::from_reader(input).into_iter::<RunSummary>() {
+            let run = run?;
+            if seen.insert(key(&run)) {
+                run.write(&mut out)?;
+                added += 1;
+            }
+        }
+        out.flush()?;
+        Ok(added)
+    }
+
+    /// Every summary in the fleet, in the order ingested.
+    pub fn summaries(&self) -> Result<Vec<RunSummary>> {
+        let input = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut result = vec![];
+        for (number, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            result.push(serde_json::from_str(&line).map_err(|e| {
+                Error::Fleet(format!("{}:{}: {}", self.path.display(), number + 1, e))
+            })?);
+        }
+        Ok(result)
+    }
+
+    /// The status of each store in the fleet, by host and store, as of
+    /// `now`.
+    pub fn report(&self, thresholds: &Thresholds, now: DateTime<Utc>) -> Result<Vec<StoreStatus>> {
+        let mut stores: BTreeMap<(String, String), Vec<RunSummary>> = BTreeMap::new();
+        for run in self.summaries()? {
+            stores
+                .entry((run.host.clone(), run.store.clone()))
+                .or_default()
+                .push(run);
+        }
+
+        let mut result = vec![];
+        for ((host, store), mut runs) in stores {
+            runs.sort_by_key(|r| r.time);
+            let last = runs.last().unwrap().clone();
+            let last_check = runs.iter().rev().find(|r| r.command == "check").cloned();
+
+            let mut problems = vec![];
+            if let Some(check) = &last_check {
+                if check.failed() {
+                    problems.push(Problem::Failed(check.clone()));
+                }
+            }
+            if last.error.is_some() && last.command != "check" {
+                problems.push(Problem::Failed(last.clone()));
+            }
+            if let Some(max) = thresholds.max_changes {
+                if total(last.changes) > max {
+                    problems.push(Problem::Drifted(last.clone()));
+                }
+            }
+            if let Some(stale) = thresholds.stale {
+                if now - last.time > stale {
+                    problems.push(Problem::Stale(last.time));
+                }
+            }
+
+            result.push(StoreStatus {
+                host,
+                store,
+                last,
+                last_check,
+                problems,
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// What makes a summary distinct, so ingesting one twice adds it once.
+fn key(run: &RunSummary) -> (String, String, String, DateTime<Utc>) {
+    (
+        run.host.clone(),
+        run.store.clone(),
+        run.command.clone(),
+        run.time,
+    )
+}
+
+/// Parse an age, such as "36h" or "7d", a number followed by "s", "m",
+/// "h", "d" or "w".
+pub fn parse_age(text: &str) -> Result<Duration> {
+    let bad = || Error::InvalidAge(text.to_string());
+    let split = text.find(|c: char| !c.is_ascii_digit()).ok_or_else(bad)?;
+    let (number, unit) = text.split_at(split);
+    let number: i64 = number.parse().map_err(|_| bad())?;
+    match unit {
+        "s" => Ok(Duration::seconds(number)),
+        "m" => Ok(Duration::minutes(number)),
+        "h" => Ok(Duration::hours(number)),
+        "d" => Ok(Duration::days(number)),
+        "w" => Ok(Duration::weeks(number)),
+        _ => Err(bad()),
+    }
+}
@@ -1,28 +1,299 @@
 //! Computing hashes for files.
 
-use crate::Result;
-use openssl::hash::{DigestBytes, Hasher, MessageDigest};
-use std::io::{Read, Write};
+use crate::{throttle::Throttle, Error, Result, StoreTags};
+use memmap2::{Advice, Mmap};
+use openssl::hash::{Hasher, MessageDigest};
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Write},
+    str::FromStr,
+};
 #[derive(Debug)]
 pub struct Estimate {
     pub files: u64,
     pub bytes: u64,
 }
 
+/// The digest used to hash file contents.  The hash is stored in an
+/// attribute with the same name as the algorithm, so a surefile records
+/// which algorithm made each hash.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+/// The tag, on each version in a store, naming the hash algorithms used
+/// for that snapshot, separated by commas.  Versions without it predate
+/// configurable hashes, and use SHA-1.
+pub const HASH_TAG: &str = "hash";
+
+impl HashAlgorithm {
+    /// The name of the algorithm, which is also the name of the attribute
+    /// the hash is stored in.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// The length of this algorithm's digest, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+        }
+    }
+
+    /// Determine the hash algorithms recorded in a version's tags.
+    pub fn from_tags(tags: &StoreTags) -> Result<Vec<HashAlgorithm>> {
+        match tags.get(HASH_TAG) {
+            None => Ok(vec![HashAlgorithm::Sha1]),
+            Some(names) => HashAlgorithm::parse_list(names),
+        }
+    }
+
+    /// Parse a comma separated list of algorithm names.
+    pub fn parse_list(text: &str) -> Result<Vec<HashAlgorithm>> {
+        text.split(',').map(|name| name.parse()).collect()
+    }
+
+    /// Format a list of algorithms, the inverse of `parse_list`.
+    pub fn format_list(algorithms: &[HashAlgorithm]) -> String {
+        let names: Vec<_> = algorithms.iter().map(|a| a.name()).collect();
+        names.join(",")
+    }
+
+    /// The OpenSSL digest for this algorithm, or None if it isn't
+    /// computed with OpenSSL.
+    fn digest(self) -> Option<MessageDigest> {
+        match self {
+            HashAlgorithm::Sha1 => Some(MessageDigest::sha1()),
+            HashAlgorithm::Sha256 => Some(MessageDigest::sha256()),
+            HashAlgorithm::Blake3 => None,
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<HashAlgorithm> {
+        match text {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(Error::UnknownHash(text.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// When an update carries a file's hash over from the latest version,
+/// rather than hashing the file again.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HashReuse {
+    /// When its inode, ctime, mtime and size are all unchanged.
+    #[default]
+    Metadata,
+    /// Never, every file is hashed again.  This is the safe choice on
+    /// network filesystems, whose clients may cache stale attributes, or
+    /// make up inode numbers and times, so that a changed file can look
+    /// unchanged.
+    Never,
+}
+
+impl FromStr for HashReuse {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<HashReuse> {
+        match text {
+            "metadata" => Ok(HashReuse::Metadata),
+            "never" => Ok(HashReuse::Never),
+            _ => Err(Error::UnknownHashReuse(text.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for HashReuse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashReuse::Metadata => f.write_str("metadata"),
+            HashReuse::Never => f.write_str("never"),
+        }
+    }
+}
+
+/// The read buffer size to use when hashing with the given algorithms.
+pub(crate) fn buffer_size(algorithms: &[HashAlgorithm]) -> usize {
+    // BLAKE3 wants large blocks to be able to use multiple threads.
+    if algorithms.contains(&HashAlgorithm::Blake3) {
+        4 * BLAKE3_PARALLEL
+    } else {
+        8192
+    }
+}
+
+/// Hash the contents of a file with each of the given algorithms, reading
+/// the file only once, `buffer` bytes at a time.  The digests are returned
+/// concatenated, in the same order as the algorithms, each
+/// `HashAlgorithm::size` bytes long.
 // TODO: Reuse buffer and hasher for a given thread.
-pub(crate) fn hash_file<R: Read>(rd: &mut R) -> Result<DigestBytes> {
-    let mut h = Hasher::new(MessageDigest::sha1())?;
-    let mut buf = vec![0u8; 8192];
+pub(crate) fn hash_file<R: Read>(
+    rd: &mut R,
+    algorithms: &[HashAlgorithm],
+    buffer: usize,
+) -> Result<Vec<u8>> {
+    let mut hashers = FileHasher::all(algorithms)?;
+
+    let mut buf = vec![0u8; buffer];
 
     loop {
-        let count = rd.read(&mut buf)?;
+        let count = read_full(rd, &mut buf)?;
         if count == 0 {
             break;
         }
 
-        h.write_all(&buf[0..count])?;
+        for h in &mut hashers {
+            h.update(&buf[0..count])?;
+        }
+    }
+
+    FileHasher::finish_all(hashers)
+}
+
+/// Hash an open file, as `hash_file`, but if it is at least `mmap` bytes
+/// long, map it into memory and hash it from there, which saves copying
+/// each block of a large file, and is faster on fast disks.  A file that
+/// can't be mapped is read instead.
+///
+/// If the file is truncated while it is mapped, reading the pages that are
+/// gone kills the process with SIGBUS, which is why this isn't the default.
+///
+/// With a throttle, the file is read no faster than it allows.
+pub(crate) fn hash_open_file(
+    fd: &mut File,
+    algorithms: &[HashAlgorithm],
+    buffer: usize,
+    mmap: Option<u64>,
+    throttle: Option<&Throttle>,
+) -> Result<Vec<u8>> {
+    let read = |fd: &mut File| match throttle {
+        Some(throttle) => hash_file(&mut throttle.reader(fd), algorithms, buffer),
+        None => hash_file(fd, algorithms, buffer),
+    };
+    let mapped = match mmap {
+        // An empty file can't be mapped.
+        Some(threshold) => {
+            let len = fd.metadata()?.len();
+            len > 0 && len >= threshold
+        }
+        None => false,
+    };
+    if !mapped {
+        return read(fd);
+    }
+
+    // Safety: the map is only read, and only while the file is open.  What
+    // is hashed, if the file changes meanwhile, is whatever a read at that
+    // moment would have seen, but for truncation, above.
+    let map = match unsafe { Mmap::map(&*fd) } {
+        Ok(map) => map,
+        Err(_) => return read(fd),
+    };
+    let _ = map.advise(Advice::Sequential);
+
+    let mut hashers = FileHasher::all(algorithms)?;
+    for block in map.chunks(buffer) {
+        for h in &mut hashers {
+            h.update(block)?;
+        }
+        if let Some(throttle) = throttle {
+            throttle.consume(block.len() as u64);
+        }
+    }
+    FileHasher::finish_all(hashers)
+}
+
+/// A hash of a single file in progress.
+enum FileHasher {
+    OpenSsl(Hasher),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl FileHasher {
+    /// A hasher for each of the algorithms.
+    fn all(algorithms: &[HashAlgorithm]) -> Result<Vec<FileHasher>> {
+        algorithms.iter().map(|&a| FileHasher::new(a)).collect()
+    }
+
+    /// Finish each of the hashes, returning their digests concatenated.
+    fn finish_all(hashers: Vec<FileHasher>) -> Result<Vec<u8>> {
+        let mut result = vec![];
+        for h in hashers {
+            h.finish(&mut result)?;
+        }
+        Ok(result)
+    }
+
+    fn new(algorithm: HashAlgorithm) -> Result<FileHasher> {
+        Ok(match algorithm.digest() {
+            Some(digest) => FileHasher::OpenSsl(Hasher::new(digest)?),
+            None => FileHasher::Blake3(Box::new(blake3::Hasher::new())),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            FileHasher::OpenSsl(h) => h.write_all(data)?,
+            // Full blocks are hashed across multiple threads, so that a
+            // single huge file isn't limited to one core.
+            FileHasher::Blake3(h) if data.len() >= BLAKE3_PARALLEL => {
+                h.update_rayon(data);
+            }
+            FileHasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the hash, appending the digest to `result`.
+    fn finish(self, result: &mut Vec<u8>) -> Result<()> {
+        match self {
+            FileHasher::OpenSsl(mut h) => result.extend_from_slice(&h.finish()?),
+            FileHasher::Blake3(h) => result.extend_from_slice(h.finalize().as_bytes()),
+        }
+        Ok(())
+    }
+}
+
+/// Reads of at least this size are hashed across multiple threads.
+/// BLAKE3 only benefits from this with fairly large inputs.
+const BLAKE3_PARALLEL: usize = 1024 * 1024;
+
+/// Read until the buffer is full, or the end of the file, returning the
+/// number of bytes read.
+fn read_full<R: Read>(rd: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        let count = rd.read(&mut buf[total..])?;
+        if count == 0 {
+            break;
+        }
+        total += count;
     }
-    Ok(h.finish()?)
+    Ok(total)
 }
 
 pub(crate) use self::atime_impl::noatime_open;
@@ -41,6 +312,7 @@ mod atime_impl {
     const O_NOATIME: i32 = 0o1000000;
 
     pub fn noatime_open(name: &Path) -> io::Result<File> {
+        crate::failures::injected(name)?;
         // Try opening it first with noatime, and if that fails, try the open
         // again without the option.
         match OpenOptions::new()
@@ -62,6 +334,7 @@ mod atime_impl {
     use std::path::Path;
 
     pub fn noatime_open(name: &Path) -> io::Result<File> {
+        crate::failures::injected(name)?;
         OpenOptions::new().read(true).open(name)
     }
 }
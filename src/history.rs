@@ -0,0 +1,181 @@
+//! The history of a single path across the versions of a store.
+//!
+//! Paths here are relative to the root of the scans, with each component
+//! escaped as it is in the surefile, and separated by `/`, such as
+//! `usr/bin/ls`.
+
+use crate::{
+    escape::Escape,
+    hashes::{buffer_size, hash_file, noatime_open},
+    platform::path_bytes,
+    surefs::encode_atts,
+    AttMap, Error, HashAlgorithm, Result, Store, StoreVersion, SureNode,
+};
+use data_encoding::HEXLOWER;
+use std::{fs, path::Path};
+
+/// Attributes that are expected to differ between a file and a copy of it
+/// that is otherwise the same, or that depend on other files.
+const VOLATILE: &[&str] = &["btime", "ctime", "ino", "link", "rollup"];
+
+/// The attributes recorded for a path in each version of the store, newest
+/// first.  A version without the path has `None`.
+pub fn path_history(store: &dyn Store, path: &str) -> Result<Vec<(StoreVersion, Option<AttMap>)>> {
+    let mut result = vec![];
+    for version in store.get_versions()? {
+        let atts = find_path(store.load_iter(version.version.clone())?, path)?;
+        result.push((version, atts));
+    }
+    Ok(result)
+}
+
+/// Find the attributes of the node at `path` in a tree.
+fn find_path<I>(nodes: I, path: &str) -> Result<Option<AttMap>>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    let mut found = None;
+    for_each_path(nodes, |name, node| {
+        if name == path {
+            found = node.atts().cloned();
+            false
+        } else {
+            true
+        }
+    })?;
+    Ok(found)
+}
+
+/// Call `visit` with the path and node of each directory and file of a
+/// tree, other than the root, until it returns false.
+pub(crate) fn for_each_path<I, F>(nodes: I, mut visit: F) -> Result<()>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    F: FnMut(&str, &SureNode) -> bool,
+{
+    let mut dirs: Vec<String> = vec![];
+    for node in nodes {
+        let node = node?;
+        let name = match &node {
+            SureNode::Enter { name, .. } => {
+                dirs.push(name.clone());
+                if dirs.len() == 1 {
+                    continue;
+                }
+                dirs[1..].join("/")
+            }
+            SureNode::File { name, .. } => {
+                let mut full = dirs[1..].join("/");
+                if !full.is_empty() {
+                    full.push('/');
+                }
+                full.push_str(name);
+                full
+            }
+            SureNode::Leave => {
+                dirs.pop();
+                continue;
+            }
+            SureNode::Sep => continue,
+        };
+        if !visit(&name, &node) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// How a live file compares with one version of the store that has it.
+#[derive(Clone, Debug)]
+pub struct VersionMatch {
+    pub version: StoreVersion,
+    /// The stored hashes are the same as the file's content.  False if the
+    /// version has no hash for it, such as for something other than a
+    /// regular file.
+    pub content: bool,
+    /// The names of the other attributes that differ, in order, ignoring
+    /// the change time and inode, which differ for any copy of a file.
+    pub attrs_changed: Vec<String>,
+}
+
+impl VersionMatch {
+    /// Is the file the same as it was in this version?
+    pub fn is_match(&self) -> bool {
+        self.content && self.attrs_changed.is_empty()
+    }
+}
+
+/// Compare a live file, at `file`, with each version of the store scanned
+/// from `root` that has it, newest first.  The file is hashed once, with
+/// every algorithm used by any of the versions.  A path that no version
+/// has gives an empty result.
+pub fn verify_file(store: &dyn Store, root: &Path, file: &Path) -> Result<Vec<VersionMatch>> {
+    let rel = relative_path(root, file)?;
+    let history = path_history(store, &rel)?;
+    if history.iter().all(|(_, atts)| atts.is_none()) {
+        return Ok(vec![]);
+    }
+
+    let mut algorithms: Vec<HashAlgorithm> = vec![];
+    for (version, _) in &history {
+        for algorithm in HashAlgorithm::from_tags(&version.tags)? {
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+    }
+
+    let meta = fs::symlink_metadata(file)?;
+    let mut live = encode_atts(file, &meta);
+    if meta.is_file() && !algorithms.is_empty() {
+        let mut fd = noatime_open(file)?;
+        let digests = hash_file(&mut fd, &algorithms, buffer_size(&algorithms))?;
+        let mut digests = &digests[..];
+        for algorithm in &algorithms {
+            let (digest, rest) = digests.split_at(algorithm.size());
+            live.insert(algorithm.name().to_string(), HEXLOWER.encode(digest));
+            digests = rest;
+        }
+    }
+
+    let hash_names: Vec<&str> = algorithms.iter().map(|a| a.name()).collect();
+    Ok(history
+        .into_iter()
+        .filter_map(|(version, atts)| atts.map(|atts| (version, atts)))
+        .map(|(version, atts)| {
+            let hashes: Vec<_> = atts
+                .keys()
+                .filter(|k| hash_names.contains(&k.as_str()))
+                .collect();
+            let content = !hashes.is_empty() && hashes.iter().all(|k| live.get(*k) == atts.get(*k));
+            let attrs_changed = atts
+                .iter()
+                .filter(|(k, _)| {
+                    !hash_names.contains(&k.as_str()) && !VOLATILE.contains(&k.as_str())
+                })
+                .filter(|(k, v)| live.get(*k) != Some(v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            VersionMatch {
+                version,
+                content,
+                attrs_changed,
+            }
+        })
+        .collect())
+}
+
+/// The path of `file` within `root`, as it is named in the surefile.  The
+/// file itself, if it is a symlink, is not followed.
+fn relative_path(root: &Path, file: &Path) -> Result<String> {
+    let name = file.file_name().ok_or(Error::PathMissingFinalFile)?;
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => Path::new(".").canonicalize()?,
+    };
+    let full = parent.join(name);
+    let rel = full
+        .strip_prefix(root.canonicalize()?)
+        .map_err(|_| Error::FileNotInDirectory)?;
+    Ok(path_bytes(rel).escaped())
+}
@@ -0,0 +1,223 @@
+//! Attributes to ignore when comparing, kept with the store.
+//!
+//! Some changes are known noise, such as the mtimes of log files, or the
+//! permissions of a directory a package manager keeps resetting.  Rather
+//! than giving `-i` to every check, these can be kept alongside the store,
+//! in a file of rules, and are then left out of each check and signoff.
+//! Each rule names an attribute, and optionally the part of the tree it
+//! applies to:
+//!
+//! ```text
+//! # Attributes ignored when comparing, and where.
+//! atime
+//! mtime var/log
+//! ```
+//!
+//! A path is relative to the top of the tree, and the rule applies to it,
+//! and everything in it.  A rule without one applies everywhere.  Blank
+//! lines, and those starting with `#`, are ignored.
+
+use crate::{Error, Result, Store};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// The extension of the rules file, alongside the store.
+pub const IGNORE_EXT: &str = "ignore";
+
+/// An attribute ignored when comparing, everywhere, or just under a path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IgnoreRule {
+    pub att: String,
+    /// The part of the tree the rule applies to, relative to its top.
+    pub under: Option<PathBuf>,
+}
+
+impl IgnoreRule {
+    /// A rule, checking that the attribute is a single word, and the path
+    /// is relative, and can be written on a line.
+    pub fn new(att: &str, under: Option<&Path>) -> Result<IgnoreRule> {
+        let bad = |why: &str| Error::InvalidIgnore(format!("{}: {}", att, why));
+        if att.is_empty() || att.contains(char::is_whitespace) || att.starts_with('#') {
+            return Err(bad("not an attribute name"));
+        }
+        let under = match under {
+            None => None,
+            Some(path) => {
+                let mut clean = PathBuf::new();
+                for part in path.components() {
+                    match part {
+                        Component::CurDir => (),
+                        Component::Normal(name) => clean.push(name),
+                        _ => return Err(bad("path must be relative to the top of the tree")),
+                    }
+                }
+                match clean.to_str() {
+                    Some(text) if text.contains('\n') => return Err(bad("path has a newline")),
+                    Some(_) => (),
+                    None => return Err(bad("path isn't UTF-8")),
+                }
+                // "." is the whole tree.
+                if clean.as_os_str().is_empty() {
+                    None
+                } else {
+                    Some(clean)
+                }
+            }
+        };
+        Ok(IgnoreRule {
+            att: att.to_string(),
+            under,
+        })
+    }
+
+    /// Does the rule apply to the given path, relative to the top of the
+    /// tree?
+    pub fn applies(&self, path: &Path) -> bool {
+        self.under
+            .as_ref()
+            .is_none_or(|under| path.starts_with(under))
+    }
+}
+
+impl fmt::Display for IgnoreRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.under {
+            Some(under) => write!(f, "{} {}", self.att, under.display()),
+            None => write!(f, "{}", self.att),
+        }
+    }
+}
+
+/// A set of rules, such as those kept with a store.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    pub fn new() -> IgnoreRules {
+        IgnoreRules::default()
+    }
+
+    /// Rules ignoring each of the attributes everywhere, as given to `-i`.
+    pub fn everywhere(atts: &[&str]) -> IgnoreRules {
+        IgnoreRules {
+            rules: atts
+                .iter()
+                .map(|att| IgnoreRule {
+                    att: att.to_string(),
+                    under: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// The rules kept with the store, if there are any.
+    pub fn load(store: &dyn Store) -> Result<IgnoreRules> {
+        let path = match store.sidecar(IGNORE_EXT) {
+            Ok(path) => path,
+            // A store that can't keep rules has none.
+            Err(Error::Unsupported(_)) => return Ok(IgnoreRules::new()),
+            Err(e) => return Err(e),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(IgnoreRules::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut rules = IgnoreRules::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = match line.split_once(' ') {
+                Some((att, under)) => IgnoreRule::new(att, Some(Path::new(under.trim_start()))),
+                None => IgnoreRule::new(line, None),
+            }
+            .map_err(|e| Error::InvalidIgnore(format!("{}: {}", path.display(), e)))?;
+            rules.add(rule);
+        }
+        Ok(rules)
+    }
+
+    /// Keep the rules with the store, replacing those kept before.
+    pub fn save(&self, store: &dyn Store) -> Result<()> {
+        let path = store.sidecar(IGNORE_EXT)?;
+        let temp = store.sidecar(&format!("{}.tmp", IGNORE_EXT))?;
+        {
+            let mut out = fs::File::create(&temp)?;
+            writeln!(out, "# Attributes ignored when comparing, and where.")?;
+            for rule in &self.rules {
+                writeln!(out, "{}", rule)?;
+            }
+            out.flush()?;
+        }
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Add a rule, returning false if it was already there.
+    pub fn add(&mut self, rule: IgnoreRule) -> bool {
+        if self.rules.contains(&rule) {
+            false
+        } else {
+            self.rules.push(rule);
+            true
+        }
+    }
+
+    /// Remove a rule, returning false if it wasn't there.
+    pub fn remove(&mut self, rule: &IgnoreRule) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|r| r != rule);
+        self.rules.len() != before
+    }
+
+    /// Add each of the other rules.
+    pub fn extend(&mut self, other: &IgnoreRules) {
+        for rule in &other.rules {
+            self.add(rule.clone());
+        }
+    }
+
+    pub fn rules(&self) -> &[IgnoreRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The rules for comparing just the directory at `path`, relative to
+    /// it, rather than to the top of the tree.
+    pub fn within(&self, path: &Path) -> IgnoreRules {
+        let mut rules = IgnoreRules::new();
+        for rule in &self.rules {
+            let under = match &rule.under {
+                Some(under) if !path.starts_with(under) => match under.strip_prefix(path) {
+                    Ok(rest) => Some(rest.to_path_buf()),
+                    Err(_) => continue,
+                },
+                _ => None,
+            };
+            rules.add(IgnoreRule {
+                att: rule.att.clone(),
+                under,
+            });
+        }
+        rules
+    }
+
+    /// The attributes ignored at the given path, relative to the top of
+    /// the tree.
+    pub fn ignored<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a str> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.applies(path))
+            .map(|rule| rule.att.as_str())
+    }
+}
@@ -0,0 +1,176 @@
+//! Bringing plain surefiles into a weave store.
+//!
+//! Before the weave store, rsure kept a single "asure-2.0" surefile, such as
+//! `2sure.dat.gz`, with the previous one as the backup, `2sure.bak.gz`.  These
+//! are read a node at a time, and added as new versions of a store, with the
+//! time the surefile was written as the time of the version.  A directory of
+//! surefiles kept over time, such as `host-2019-03-01.dat.gz`, can be added
+//! the same way, oldest first.
+//!
+//! A BSD mtree(8) specification can also be added as a version, for moving
+//! from mtree, or tools such as AIDE that can write one, to rsure.
+
+use crate::{
+    mtree, node, Error, FixedClock, HashAlgorithm, NodeWriter, Result, Store, StoreTags, HASH_TAG,
+};
+use chrono::{DateTime, Local, Utc};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The formats, other than rsure's own, that a version can be imported from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ImportFormat {
+    /// A BSD mtree(8) specification.
+    #[default]
+    Mtree,
+}
+
+impl FromStr for ImportFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<ImportFormat> {
+        match text {
+            "mtree" => Ok(ImportFormat::Mtree),
+            _ => Err(Error::UnknownFormat(text.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportFormat::Mtree => write!(f, "mtree"),
+        }
+    }
+}
+
+/// Add a file in the given format to the store as a new version.  The
+/// version is named for the file, without its extension, if the tags don't
+/// give a name, and records the hashes found in the file.
+pub fn import_file(
+    store: &dyn Store,
+    path: &Path,
+    format: ImportFormat,
+    tags: &StoreTags,
+) -> Result<()> {
+    let input = BufReader::new(File::open(path)?);
+    let (nodes, algorithms) = match format {
+        ImportFormat::Mtree => mtree::read(input)?,
+    };
+
+    let mut tags = tags.clone();
+    if let Some(stem) = path.file_stem() {
+        tags.entry("name".to_string())
+            .or_insert_with(|| stem.to_string_lossy().into_owned());
+    }
+    if !algorithms.is_empty() {
+        tags.insert(
+            HASH_TAG.to_string(),
+            HashAlgorithm::format_list(&algorithms),
+        );
+    }
+
+    let mut out = store.make_new(&tags)?;
+    {
+        let mut writer = NodeWriter::new(&mut out)?;
+        for node in &nodes {
+            writer.write_node(node)?;
+        }
+    }
+    out.commit()
+}
+
+/// Add a plain surefile to the store as a new version.  The version is
+/// given the time the file was last modified, which is also its name, if
+/// the tags don't give one.  This sets the store's clock to that time.
+pub fn import_surefile(store: &mut dyn Store, path: &Path, tags: &StoreTags) -> Result<()> {
+    let time: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+    let mut tags = tags.clone();
+    tags.entry("name".to_string())
+        .or_insert_with(|| time.with_timezone(&Local).to_rfc3339());
+    store.set_clock(Box::new(FixedClock(time)));
+
+    let mut out = store.make_new(&tags)?;
+    {
+        let mut writer = NodeWriter::new(&mut out)?;
+        for node in node::load(path)? {
+            writer.write_node(&node?)?;
+        }
+    }
+    out.commit()
+}
+
+/// Migrate a plain surefile, and its backup, if there is one, to a new
+/// store.  The backup becomes the first version, and the surefile the
+/// second, so that "signoff" compares them as before.  Returns the number
+/// of versions added.
+pub fn migrate(src: &Path, dest: &mut dyn Store) -> Result<usize> {
+    let versions = match dest.get_versions() {
+        Ok(versions) => versions,
+        // A store that isn't there yet is empty.
+        Err(Error::Io(e)) | Err(Error::Weave(weave::Error::Io(e)))
+            if e.kind() == io::ErrorKind::NotFound =>
+        {
+            vec![]
+        }
+        Err(e) => return Err(e),
+    };
+    if !versions.is_empty() {
+        return Err(Error::StoreNotEmpty);
+    }
+
+    let mut files = vec![];
+    if let Some(backup) = backup_name(src) {
+        if backup.is_file() {
+            files.push(backup);
+        }
+    }
+    files.push(src.to_path_buf());
+
+    for file in &files {
+        import_surefile(dest, file, &StoreTags::new())?;
+    }
+    Ok(files.len())
+}
+
+/// Add each plain surefile in a directory, named `*.dat.gz`, to the store,
+/// in the order they were written, as given by their modification times.
+/// Each version is named for its file, without the suffix.  Returns the
+/// number of versions added.
+pub fn import_dir(src: &Path, dest: &mut dyn Store) -> Result<usize> {
+    let mut files = vec![];
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(base) = name.strip_suffix(".dat.gz") {
+            if entry.file_type()?.is_file() {
+                let mtime = entry.metadata()?.modified()?;
+                files.push((mtime, base.to_string(), path.clone()));
+            }
+        }
+    }
+    files.sort();
+
+    for (_, name, path) in &files {
+        let mut tags = StoreTags::new();
+        tags.insert("name".to_string(), name.clone());
+        import_surefile(dest, path, &tags)?;
+    }
+    Ok(files.len())
+}
+
+/// The backup of a plain surefile, `name.bak.gz` for `name.dat.gz`.
+fn backup_name(src: &Path) -> Option<PathBuf> {
+    let name = src.file_name()?.to_str()?;
+    let base = name.strip_suffix(".dat.gz")?;
+    Some(src.with_file_name(format!("{}.bak.gz", base)))
+}
@@ -0,0 +1,281 @@
+//! An index of every content hash in a store.
+//!
+//! Finding whether a file's content ever appeared in a store otherwise
+//! means reading every version.  The index is a bloom filter of all of the
+//! hashes in all of the versions, kept alongside the store, and rebuilt by
+//! `build_index`.  A hash that isn't in the filter was never seen, which is
+//! the usual answer, and quick.  A hash that might be in it is then looked
+//! for in each version, to say where it was seen.
+//!
+//! The index records which versions it was built from, and is ignored once
+//! the store has changed, until it is rebuilt.  An update adds the hashes
+//! of the version it writes to an index that is current, as it writes
+//! them, so the index stays current without the version being read back.
+//! The filter is sized when it is built, and fills as hashes are added, so
+//! rebuilding it now and then keeps it quick.
+
+use crate::{
+    history::for_each_path, node::NodeObserver, Error, HashAlgorithm, Result, Store, StoreVersion,
+    SureNode, Version,
+};
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use log::warn;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+};
+
+/// The extension of the index file, alongside the store.
+pub const INDEX_EXT: &str = "bloom";
+
+/// The first word of the index file.
+const MAGIC: &str = "rsure-bloom";
+
+/// The false positive rate the filter is sized for.
+const FALSE_POSITIVES: f64 = 0.01;
+
+/// A place a hash was found.
+#[derive(Clone, Debug)]
+pub struct Sighting {
+    pub version: StoreVersion,
+    /// The path of the file, relative to the root of the scan, and escaped.
+    pub path: String,
+    /// The name of the hash algorithm that matched.
+    pub algorithm: String,
+}
+
+/// Build the index of the store's hashes, replacing any previous one.
+/// Returns the number of distinct hashes indexed.
+pub fn build_index(store: &dyn Store) -> Result<usize> {
+    let versions = store.get_versions()?;
+    let mut digests = HashSet::new();
+    for version in &versions {
+        let names = hash_names(version)?;
+        let nodes = store.load_iter(version.version.clone())?;
+        for node in nodes {
+            if let SureNode::File { atts, .. } = node? {
+                for name in &names {
+                    if let Some(hex) = atts.get(name) {
+                        digests.insert(decode(hex)?);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut filter = Bloom::new(digests.len());
+    for digest in &digests {
+        filter.insert(digest);
+    }
+    save_index(store, &filter, &versions)?;
+    Ok(digests.len())
+}
+
+/// Adds the hashes of a new version to the store's index, as the version is
+/// written.
+pub(crate) struct IndexUpdater {
+    filter: Bloom,
+    names: Vec<String>,
+}
+
+impl IndexUpdater {
+    /// An updater for the store's index, if it has one that is current, for
+    /// a version hashed with `algorithms`.
+    pub(crate) fn open(
+        store: &dyn Store,
+        algorithms: &[HashAlgorithm],
+    ) -> Result<Option<IndexUpdater>> {
+        let versions = store.get_versions()?;
+        Ok(load_index(store, &versions)?.map(|filter| IndexUpdater {
+            filter,
+            names: algorithms.iter().map(|a| a.name().to_string()).collect(),
+        }))
+    }
+
+    /// Write the index back, once the new version is committed, as current
+    /// for the store's versions.
+    pub(crate) fn save(self, store: &dyn Store) -> Result<()> {
+        save_index(store, &self.filter, &store.get_versions()?)
+    }
+}
+
+impl NodeObserver for IndexUpdater {
+    fn observe(&mut self, node: &SureNode) -> Result<()> {
+        if let SureNode::File { atts, .. } = node {
+            for name in &self.names {
+                if let Some(hex) = atts.get(name) {
+                    self.filter.insert(&decode(hex)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the index, as built from the given versions.
+fn save_index(store: &dyn Store, filter: &Bloom, versions: &[StoreVersion]) -> Result<()> {
+    let path = store.sidecar(INDEX_EXT)?;
+    let temp = store.sidecar(&format!("{}.tmp", INDEX_EXT))?;
+    {
+        let mut wr = BufWriter::new(File::create(&temp)?);
+        writeln!(
+            wr,
+            "{} 1 {} {} {}",
+            MAGIC,
+            filter.hashes,
+            filter.bits.len(),
+            stamp(versions)
+        )?;
+        for word in &filter.bits {
+            wr.write_all(&word.to_le_bytes())?;
+        }
+        wr.flush()?;
+    }
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+/// Find where a hash, given in hex, appears in the store, newest version
+/// first.  Uses the index, when it is current, to answer quickly for
+/// hashes that were never seen.
+pub fn seen_hash(store: &dyn Store, digest: &str) -> Result<Vec<Sighting>> {
+    let bytes = decode(digest)?;
+    let versions = store.get_versions()?;
+    match load_index(store, &versions)? {
+        Some(filter) if !filter.contains(&bytes) => return Ok(vec![]),
+        Some(_) => (),
+        None => warn!("No current index of the store, reading every version"),
+    }
+
+    let hex = HEXLOWER.encode(&bytes);
+    let mut result = vec![];
+    for version in versions {
+        let names = hash_names(&version)?;
+        let nodes = store.load_iter(version.version.clone())?;
+        for_each_path(nodes, |path, node| {
+            if let SureNode::File { atts, .. } = node {
+                for name in &names {
+                    if atts.get(name) == Some(&hex) {
+                        result.push(Sighting {
+                            version: version.clone(),
+                            path: path.to_string(),
+                            algorithm: name.clone(),
+                        });
+                    }
+                }
+            }
+            true
+        })?;
+    }
+    Ok(result)
+}
+
+/// The names of the hash attributes of a version.
+fn hash_names(version: &StoreVersion) -> Result<Vec<String>> {
+    Ok(HashAlgorithm::from_tags(&version.tags)?
+        .iter()
+        .map(|a| a.name().to_string())
+        .collect())
+}
+
+fn decode(hex: &str) -> Result<Vec<u8>> {
+    match HEXLOWER_PERMISSIVE.decode(hex.as_bytes()) {
+        Ok(bytes) if bytes.len() >= 16 => Ok(bytes),
+        _ => Err(Error::InvalidDigest(hex.to_string())),
+    }
+}
+
+/// Identifies the versions of a store, so that an index built from other
+/// versions isn't used.
+fn stamp(versions: &[StoreVersion]) -> String {
+    match versions.first() {
+        Some(latest) => {
+            let number = match &latest.version {
+                Version::Tagged(number) => number.as_str(),
+                _ => "-",
+            };
+            format!("{} {} {}", versions.len(), number, latest.time.to_rfc3339())
+        }
+        None => "0".to_string(),
+    }
+}
+
+/// Read the index, if there is one that is current for these versions.
+fn load_index(store: &dyn Store, versions: &[StoreVersion]) -> Result<Option<Bloom>> {
+    // A store that can't keep an index has none.
+    let file = match store
+        .sidecar(INDEX_EXT)
+        .and_then(|path| Ok(File::open(path)?))
+    {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    let mut rd = BufReader::new(file);
+    let mut line = String::new();
+    rd.read_line(&mut line)?;
+    let fields: Vec<&str> = line.trim_end().splitn(5, ' ').collect();
+    let (hashes, words) = match fields.as_slice() {
+        [MAGIC, "1", hashes, words, rest] if *rest == stamp(versions) => {
+            (hashes.parse()?, words.parse::<usize>()?)
+        }
+        _ => return Ok(None),
+    };
+
+    let mut data = vec![0u8; words * 8];
+    rd.read_exact(&mut data)?;
+    let bits = data
+        .chunks(8)
+        .map(|c| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(c);
+            u64::from_le_bytes(word)
+        })
+        .collect();
+    Ok(Some(Bloom { bits, hashes }))
+}
+
+/// A bloom filter of digests.  The digests are already uniformly
+/// distributed, so their bytes are used directly as the two hashes that
+/// the bit positions are derived from.
+struct Bloom {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl Bloom {
+    /// A filter sized for `count` digests.
+    fn new(count: usize) -> Bloom {
+        let ln2 = std::f64::consts::LN_2;
+        let count = count.max(1) as f64;
+        let bits = (-count * FALSE_POSITIVES.ln() / (ln2 * ln2)).ceil() as usize;
+        let hashes = ((bits as f64 / count) * ln2).round().max(1.0) as u32;
+        Bloom {
+            bits: vec![0; bits.div_ceil(64).max(1)],
+            hashes,
+        }
+    }
+
+    fn positions<'a>(&'a self, digest: &[u8]) -> impl Iterator<Item = usize> + 'a {
+        let word = |i: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&digest[i..i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let (h1, h2) = (word(0), word(8) | 1);
+        let size = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+
+    fn insert(&mut self, digest: &[u8]) {
+        let positions: Vec<_> = self.positions(digest).collect();
+        for pos in positions {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+    }
+
+    fn contains(&self, digest: &[u8]) -> bool {
+        self.positions(digest)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+}
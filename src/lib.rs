@@ -2,54 +2,145 @@
 //! still true.
 //!
 //! The easiest way to use Rsure is to build the `rsure` executable contained in this crate.  This
-//! program allows you to use most of the functionality of the crate.
+//! program allows you to use most of the functionality of the crate.  It is built with the `cli`
+//! feature, on by default; a program using just the library can turn it off, and with it the
+//! command line parser, the logger behind `log_init`, and `tempdir`.
 //!
 //! However, it is also possible to use the crate programmatically.  At the top level of the crate
-//! as some utility functions for the most common operations.
+//! are some utility functions for the most common operations.
 //!
 //! For example, to scan a directory or do an update use `update`.
 //!
 //! This example makes use of several of the building blocks necessary to use the store.  First is
 //! the store itself.  `parse_store` is able to decode options that are passed to the command line.
-//! it is also possible to build a `store::Plain` store directly.
 //!
 //! Next are the tags for the snapshot.  Generally, this should hold some kind of information about
 //! the snapshot itself.  For the `Plain` store, it can be just an empty map.  Other store types
 //! may require certain tags to be present.
+//!
+//! # The public API
+//!
+//! What is exported at the top of the crate is its stable API, changed only with a new minor
+//! version before 1.0:
+//!
+//! - Updating: [`update`], [`UpdateOptions`], [`update_with`] and [`UpdateHooks`], with
+//!   [`ScanOptions`], [`Exclude`], [`PathList`], [`HashAlgorithm`], [`HashReuse`] and
+//!   [`CancellationToken`].
+//! - Stores: [`parse_store`], the [`Store`] trait, [`StoreTags`], [`StoreVersion`] and
+//!   [`Version`], and the buckets a store can be kept in.
+//! - Comparing: [`compare_trees`], [`Change`] and [`ChangeSummary`], and checking a tree against
+//!   the store with [`check`], [`CheckOptions`] and [`CheckReport`].
+//! - Progress: the [`ProgressSink`] trait and [`ProgressEvent`], and the sinks given here.
+//! - Errors: [`Error`] and [`Result`].
+//!
+//! The modules, such as [`node`], [`export`] and [`report`], give the building blocks of these,
+//! and of the `rsure` commands.  They are public for programs needing more than the above, but
+//! follow the needs of the executable, and may change more freely.
 
 #![warn(bare_trait_objects)]
 
-use std::{fs::File, path::Path};
+use crate::{
+    index::IndexUpdater,
+    monitor::{Activity, Phase},
+    node::{Rollups, TreeDigest},
+    roots::Roots,
+    stats::CountingWriter,
+};
+use log::warn;
+use std::{
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 pub use crate::{
+    cancel::CancellationToken,
+    check::{check, CheckOptions, CheckReport},
+    clock::{Clock, FixedClock, SystemClock},
     errors::{Error, Result},
-    hashes::Estimate,
+    exclude::{Exclude, Tombstones, EXCLUDED_TAG},
+    failures::{Failures, FAILURES_OMITTED_TAG, FAILURES_TAG, MAX_FAILURES},
+    fs::ScanOptions,
+    hashes::{Estimate, HashAlgorithm, HashReuse, HASH_TAG},
+    memory::MemoryLimit,
     node::{
-        compare_trees, fs, load_from, HashCombiner, HashUpdater, NodeWriter, ReadIterator, Source,
-        SureNode,
+        compare_dir, compare_trees, compare_trees_with, compare_trees_with_renames,
+        compare_trees_with_rules, fs, load_from, rollup, tree_digest, Change, ChangeAction,
+        ChangeSummary, HashCombiner, HashMerger, HashPool, HashUpdater, MergeIter, NodeObserver,
+        NodeTee, NodeWriter, PathList, ReadIterator, Source, SureNode, CHANGES_TAG, DIGEST_TAG,
+        ROLLUP_ATT,
+    },
+    progress::{
+        humanize, JsonProgress, NullProgress, Progress, ProgressEvent, ProgressSink, Spinner,
+        TerminalProgress,
+    },
+    show::{show_nodes, show_tree, ShowOptions},
+    store::{
+        parse_store, version_name, Bucket, ObjectStore, SignedStore, SigningKeys, SshBucket, Store,
+        StoreLock, StoreTags, StoreVersion, TempLoader, Version, WeaveProblem, WeaveStats,
+        ARTIFACT_EXT, SIGNATURE_ARTIFACT,
     },
-    progress::{log_init, Progress},
-    show::show_tree,
-    store::{parse_store, Store, StoreTags, StoreVersion, TempLoader, Version},
     suretree::AttMap,
+    throttle::{ReadRate, Throttle},
 };
 
+#[doc(hidden)]
+pub use crate::failures::inject_failure;
+
+#[cfg(feature = "cli")]
+pub use crate::progress::log_init;
+#[cfg(feature = "encryption")]
+pub use crate::store::AgeCipher;
+#[cfg(feature = "s3")]
+pub use crate::store::{sign_v4, S3Bucket};
+
+pub mod cancel;
+mod check;
+pub mod clock;
+pub mod daemon;
 mod errors;
 mod escape;
+pub mod exclude;
+pub mod export;
+mod failures;
+pub mod fleet;
 mod hashes;
+pub mod history;
+pub mod ignore;
+pub mod import;
+pub mod index;
+pub mod manifest;
+mod memory;
+pub mod monitor;
+mod mtree;
 pub mod node;
+pub mod pin;
+mod platform;
 mod progress;
+pub mod report;
+pub mod roots;
+mod scratch;
+pub mod service;
 mod show;
+pub mod stats;
 mod store;
 mod surefs;
 mod suretree;
+#[cfg(unix)]
+pub mod system;
+mod throttle;
+pub mod watch;
 
 // Some common operations, abstracted here.
 
 /// Perform an update scan, using the given store.
 ///
 /// If 'update' is true, use the hashes from a previous run, otherwise perform a fresh scan.
-/// Depending on the [`Store`] type, the tags may be kept, or ignored.
+/// Depending on the [`Store`] type, the tags may be kept, or ignored.  Files are hashed with each
+/// of `algorithms` (reading each file once), which are recorded in the "hash" tag of the new
+/// version.  Directories and files that can't be read are logged, and recorded in the
+/// "failures" tag, as [`Failures`].
 ///
 /// [`Store`]: trait.Store.html
 ///
@@ -59,10 +150,12 @@ mod suretree;
 /// # use std::error::Error;
 /// #
 /// # fn try_main() -> Result<(), Box<Error>> {
+/// # let tmp = tempdir::TempDir::new("rsure")?;
+/// # std::env::set_current_dir(tmp.path())?;
 /// let mut tags = rsure::StoreTags::new();
 /// tags.insert("name".into(), "sample".into());
 /// let store = rsure::parse_store("2sure.dat.gz")?;
-/// rsure::update(".", &*store, false, &tags)?;
+/// rsure::update(".", &*store, false, &tags, &[rsure::HashAlgorithm::Sha1])?;
 /// #     Ok(())
 /// # }
 /// #
@@ -70,21 +163,323 @@ mod suretree;
 /// #     try_main().unwrap();
 /// # }
 /// ```
+///
+/// Other settings are given with [`UpdateOptions`].
 pub fn update<P: AsRef<Path>>(
     dir: P,
     store: &dyn Store,
     is_update: bool,
     tags: &StoreTags,
+    algorithms: &[HashAlgorithm],
+) -> Result<()> {
+    UpdateOptions::new()
+        .with_update(is_update)
+        .with_tags(tags)
+        .with_algorithms(algorithms)
+        .run(dir, store)
+}
+
+/// The settings of an update, built up from those of a fresh scan, with no tags, hashing with the
+/// default algorithm, and then run with [`UpdateOptions::run`].
+///
+/// ```rust
+/// # use std::error::Error;
+/// #
+/// # fn try_main() -> Result<(), Box<Error>> {
+/// # let tmp = tempdir::TempDir::new("rsure")?;
+/// # std::env::set_current_dir(tmp.path())?;
+/// let store = rsure::parse_store("2sure.dat.gz")?;
+/// rsure::UpdateOptions::new()
+///     .with_tag("name", "sample")
+///     .with_jobs(2)
+///     .with_cross_filesystems()
+///     .run(".", &*store)?;
+/// #     Ok(())
+/// # }
+/// #
+/// # fn main() {
+/// #     try_main().unwrap();
+/// # }
+/// ```
+#[derive(Default)]
+pub struct UpdateOptions {
+    is_update: bool,
+    tags: StoreTags,
+    algorithms: Vec<HashAlgorithm>,
+    hooks: UpdateHooks,
+}
+
+impl UpdateOptions {
+    pub fn new() -> UpdateOptions {
+        UpdateOptions::default()
+    }
+
+    /// Start from these hooks, for the settings without a method of their own.  The methods
+    /// below then change the settings they cover.
+    pub fn from_hooks(hooks: UpdateHooks) -> UpdateOptions {
+        UpdateOptions {
+            hooks,
+            ..UpdateOptions::default()
+        }
+    }
+
+    /// Use the hashes from the latest version, if `is_update`, rather than hashing every file.
+    pub fn with_update(mut self, is_update: bool) -> UpdateOptions {
+        self.is_update = is_update;
+        self
+    }
+
+    /// Tag the new version with these tags, as well as those already given.
+    pub fn with_tags(mut self, tags: &StoreTags) -> UpdateOptions {
+        self.tags
+            .extend(tags.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+
+    /// Tag the new version with a tag, such as its "name".
+    pub fn with_tag(mut self, key: &str, value: &str) -> UpdateOptions {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Hash files with each of these algorithms, rather than the default.
+    pub fn with_algorithms(mut self, algorithms: &[HashAlgorithm]) -> UpdateOptions {
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Hash at most this many files at once, rather than one per CPU.
+    pub fn with_jobs(mut self, jobs: usize) -> UpdateOptions {
+        self.hooks.jobs = Some(jobs);
+        self
+    }
+
+    /// Leave these paths out of the scan, in addition to those in the root's `.rsureignore`.
+    pub fn with_exclude(mut self, exclude: Exclude) -> UpdateOptions {
+        self.hooks.scan.exclude = exclude;
+        self
+    }
+
+    /// Report the progress of the update here, instead of the terminal.
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> UpdateOptions {
+        self.hooks.progress = Some(sink);
+        self
+    }
+
+    /// Descend into directories on other filesystems, rather than recording them as empty.
+    pub fn with_cross_filesystems(mut self) -> UpdateOptions {
+        self.hooks.scan.cross_filesystems = true;
+        self
+    }
+
+    /// Run the update of the store from the tree at `dir`.
+    pub fn run<P: AsRef<Path>>(self, dir: P, store: &dyn Store) -> Result<()> {
+        update_with(
+            dir.as_ref(),
+            store,
+            self.is_update,
+            &self.tags,
+            &self.algorithms,
+            self.hooks,
+        )
+    }
+}
+
+/// Optional settings for an update.  The defaults give the same update as `update`.
+#[derive(Default)]
+pub struct UpdateHooks {
+    /// Limits file hashing, shared with other updates running at the same time.
+    pub pool: Option<Arc<HashPool>>,
+    /// Records the progress of the update.
+    pub activity: Option<Arc<Activity>>,
+    /// Bounds the memory used by the update's buffers.  This is slower, as fewer files may be
+    /// hashed at once, with smaller reads.
+    pub memory_limit: Option<MemoryLimit>,
+    /// How to scan the filesystem, such as paths to leave out.  Its `failures` aren't used, as
+    /// the paths the update can't read are recorded in the new version's [`FAILURES_TAG`].
+    pub scan: ScanOptions,
+    /// Hash files as they are found, while the scan continues, rather than once it is done.  The
+    /// progress totals then grow during the update, rather than being known at the start.
+    pub pipelined: bool,
+    /// Stop the update once this is cancelled, returning `Error::Cancelled`, without adding a
+    /// version to the store.  This also stops the scan, if its options don't have a token of
+    /// their own.
+    pub cancel: Option<CancellationToken>,
+    /// Given each node of the new version as it is written, and finished once the version is
+    /// committed, so that whatever is gathered from the nodes needn't be read back from the store.
+    pub observers: Vec<Box<dyn NodeObserver>>,
+    /// Where to report the progress of the scan, hashing and writing, instead of the terminal.
+    /// This is also given to the scan, if its options don't have a sink of their own.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// For an update, look at just these paths, rather than scanning the tree, carrying every
+    /// other node forward from the latest version.  The listed files are all hashed again.
+    pub paths: Option<PathList>,
+    /// When an update may carry a file's hash over from the latest version.  By default, this is
+    /// whenever its metadata is unchanged, except when the tree is on a network, or FUSE,
+    /// filesystem, where that can't be trusted, and every file is hashed again.
+    pub hash_reuse: Option<HashReuse>,
+    /// Hash files of at least this many bytes by mapping them into memory, rather than reading
+    /// them, which is faster on fast disks.  A file truncated while it is being hashed this way
+    /// kills the process with SIGBUS.
+    pub mmap: Option<u64>,
+    /// Read small files in batches with io_uring, which is faster when there are many of them.
+    /// This is experimental, and needs the "uring" feature, on Linux.
+    pub uring: bool,
+    /// Hash at most this many files at once, rather than one per CPU.
+    pub jobs: Option<usize>,
+    /// Limits how fast files are read to hash them, shared with other updates using the same
+    /// throttle.
+    pub throttle: Option<Arc<Throttle>>,
+    /// Hash at the lowest CPU and I/O priority, so that other work on the machine comes first.
+    pub background: bool,
+    /// Scan these directories, each named at the top of the tree, rather than the one given to
+    /// the update.  This can't be used with `paths`.
+    pub roots: Option<Arc<Roots>>,
+}
+
+/// Perform an update, as `update`, with the given hooks.
+pub fn update_with(
+    dir: &Path,
+    store: &dyn Store,
+    is_update: bool,
+    tags: &StoreTags,
+    algorithms: &[HashAlgorithm],
+    mut hooks: UpdateHooks,
 ) -> Result<()> {
-    let dir = dir.as_ref();
+    let mut observers = std::mem::take(&mut hooks.observers);
+    let phase = |phase| {
+        if let Some(activity) = &hooks.activity {
+            activity.set_phase(phase);
+        }
+    };
+    phase(Phase::Scanning);
+
+    let default_algorithms = [HashAlgorithm::default()];
+    let algorithms = if algorithms.is_empty() {
+        &default_algorithms[..]
+    } else {
+        algorithms
+    };
+
+    let stats = stats::global();
+    let count_scanned = |node: &Result<SureNode>| {
+        if let Ok(n) = node {
+            stats.add_scanned(n.atts().map_or(0, |a| a.len()));
+        }
+    };
+
+    let network = match &hooks.roots {
+        Some(roots) => roots
+            .iter()
+            .find_map(|(_, root)| surefs::network_fs(root).map(|fstype| (root, fstype))),
+        None => surefs::network_fs(dir).map(|fstype| (dir, fstype)),
+    };
+    let reuse = match (hooks.hash_reuse, network) {
+        (reuse, Some((dir, fstype))) => {
+            warn!(
+                "{:?} is on a {} filesystem, where inode numbers and ctimes may not be stable, \
+                 and files may be opened without noatime",
+                dir, fstype
+            );
+            reuse.unwrap_or_else(|| {
+                if is_update {
+                    warn!("Hashing every file again, rather than trusting unchanged metadata");
+                }
+                HashReuse::Never
+            })
+        }
+        (reuse, None) => reuse.unwrap_or_default(),
+    };
+
+    let mut scan_options = hooks.scan.clone();
+    if scan_options.cancel.is_none() {
+        scan_options.cancel = hooks.cancel.clone();
+    }
+    if scan_options.progress.is_none() {
+        scan_options.progress = hooks.progress.clone();
+    }
+    // What couldn't be read, by the scan or the hashing, recorded with the new version.
+    let failures = Failures::new();
+    scan_options.failures = failures.clone();
 
     let mut estimate = Estimate { files: 0, bytes: 0 };
-    let tmp = if is_update {
+    let mut hashes = None;
+    // What the scan left out, recorded with the new version.
+    let mut excluded = None;
+    if hooks.roots.is_some() && hooks.paths.is_some() {
+        return Err(Error::SeveralRoots("Updating listed paths".to_string()));
+    }
+
+    // Held until the new version is written, so that the latest version the update reuses hashes
+    // from, and compares with, is the one it is based on.
+    let lock = store.lock()?;
+    let tmp = if let (true, Some(paths)) = (is_update, &hooks.paths) {
+        // Just the listed paths, with the rest of the latest version, and what it left out.
+        let start = Instant::now();
+        let latest = store
+            .get_version(&Version::Latest)?
+            .ok_or_else(|| Error::UnknownVersion(Version::Latest.to_string()))?;
+        excluded = Some(Arc::new(Mutex::new(Tombstones::from_tags(&latest.tags)?)));
+        let mut tmp = store.make_temp()?;
+        let mut counter = CountingWriter::new(&mut tmp);
+        let mut writer = NodeWriter::new(&mut counter)?;
+        estimate = paths.apply(
+            dir,
+            store.load_iter(latest.version)?,
+            &scan_options,
+            algorithms,
+            &mut writer,
+        )?;
+        writer.into_inner()?;
+        stats.add_stage("paths", start, Some(counter.count()));
+        tmp
+    } else if hooks.pipelined {
+        // Scan, combining with the latest version for an update, and hash the files as they are
+        // found.  The hashes are kept by id, so they still merge in order.
+        let start = Instant::now();
+        let mut tmp = store.make_temp()?;
+        if let Some(activity) = &hooks.activity {
+            activity.set_totals(0, 0);
+        }
+        let hu = hash_updater((), store, algorithms, &failures, &hooks);
+        let (merger, written) = hu.compute_with(dir, |found| {
+            let mut counter = CountingWriter::new(&mut tmp);
+            let (scan, tombstones) = scan_tree(dir, &hooks, &scan_options)?;
+            excluded = Some(tombstones);
+            let src = scan.inspect(count_scanned);
+            let nodes: Box<dyn Iterator<Item = Result<SureNode>>> = if is_update {
+                let latest = store.load_iter(Version::Latest)?;
+                Box::new(
+                    HashCombiner::new(latest, src)?
+                        .with_algorithms(algorithms)
+                        .with_reuse(reuse),
+                )
+            } else {
+                Box::new(src)
+            };
+            node::save_to(
+                &mut counter,
+                nodes.inspect(|node| {
+                    if let Ok(n) = node {
+                        found(n);
+                    }
+                }),
+            )?;
+            Ok(counter.count())
+        })?;
+        stats.add_stage("pipeline", start, Some(written));
+        hashes = Some(merger);
+        tmp
+    } else if is_update {
         // In update mode, first tmp file is just the scan.
         let scan_temp = {
+            let start = Instant::now();
             let mut tmp = store.make_temp()?;
-            let src = fs::scan_fs(dir)?;
-            node::save_to(&mut tmp, src)?;
+            let mut counter = CountingWriter::new(&mut tmp);
+            let (scan, tombstones) = scan_tree(dir, &hooks, &scan_options)?;
+            excluded = Some(tombstones);
+            node::save_to(&mut counter, scan.inspect(count_scanned))?;
+            stats.add_stage("scan", start, Some(counter.count()));
             tmp
         }
         .into_loader()?;
@@ -92,47 +487,130 @@ pub fn update<P: AsRef<Path>>(
         let latest = store.load_iter(Version::Latest)?;
 
         let tmp = {
+            let start = Instant::now();
             let mut tmp = store.make_temp()?;
+            let mut counter = CountingWriter::new(&mut tmp);
             let loader = Loader(&*scan_temp);
-            let combiner = HashCombiner::new(latest, loader.iter()?)?.inspect(|node| {
-                if let Ok(n @ SureNode::File { .. }) = node {
-                    if n.needs_hash() {
-                        estimate.files += 1;
-                        estimate.bytes += n.size();
+            let combiner = HashCombiner::new(latest, loader.iter()?)?
+                .with_algorithms(algorithms)
+                .with_reuse(reuse)
+                .inspect(|node| {
+                    if let Ok(n @ SureNode::File { .. }) = node {
+                        if n.needs_hash(algorithms) {
+                            estimate.files += 1;
+                            estimate.bytes += n.data_size();
+                        }
                     }
-                }
-            });
-            node::save_to(&mut tmp, combiner)?;
+                });
+            node::save_to(&mut counter, combiner)?;
+            stats.add_stage("combine", start, Some(counter.count()));
             tmp
         };
 
         tmp
     } else {
+        let start = Instant::now();
         let mut tmp = store.make_temp()?;
-        let src = fs::scan_fs(dir)?.inspect(|node| {
+        let mut counter = CountingWriter::new(&mut tmp);
+        let (scan, tombstones) = scan_tree(dir, &hooks, &scan_options)?;
+        excluded = Some(tombstones);
+        let src = scan.inspect(count_scanned).inspect(|node| {
             if let Ok(n @ SureNode::File { .. }) = node {
-                if n.needs_hash() {
+                if n.needs_hash(algorithms) {
                     estimate.files += 1;
-                    estimate.bytes += n.size();
+                    estimate.bytes += n.data_size();
                 }
             }
         });
-        node::save_to(&mut tmp, src)?;
+        node::save_to(&mut counter, src)?;
+        stats.add_stage("scan", start, Some(counter.count()));
         tmp
     }
     .into_loader()?;
+    cancel::check(&hooks.cancel)?;
 
     // TODO: If this is an update, pull in hashes from the old version.
 
     // Update any missing hashes.
     let loader = Loader(&*tmp);
-    let hu = HashUpdater::new(loader, store);
-    // TODO: This will panic on non-unicode directories.
-    let hm = hu.compute_parallel(dir.to_str().unwrap(), &estimate)?;
-    let mut tmp2 = store.make_new(tags)?;
-    hm.merge(&mut NodeWriter::new(&mut tmp2)?)?;
-
+    let hm = match hashes {
+        Some(hashes) => hashes.with_source(loader),
+        None => {
+            let hu = hash_updater(loader, store, algorithms, &failures, &hooks);
+            if let Some(activity) = &hooks.activity {
+                activity.set_totals(estimate.files, estimate.bytes);
+            }
+            phase(Phase::Hashing);
+            let start = Instant::now();
+            let hm = hu.compute_parallel(dir, &estimate)?;
+            stats.add_stage("hash", start, None);
+            hm
+        }
+    };
+    let mut tags = tags.clone();
+    tags.insert(HASH_TAG.to_string(), HashAlgorithm::format_list(algorithms));
+    let excluded = match excluded {
+        Some(excluded) => excluded.lock().unwrap().clone(),
+        None => Tombstones::new(),
+    };
+    excluded.add_to_tags(&mut tags);
+    failures.add_to_tags(&mut tags);
+    phase(Phase::Writing);
+    let start = Instant::now();
+    let spinner = match &hooks.progress {
+        Some(sink) => Spinner::with_sink("write", sink.clone()),
+        None => Spinner::new("write"),
+    };
+    // Merge into another temp first, as the rollup of each directory is only known once all of
+    // it has been merged.  For an update, the changes from the latest version are counted as it
+    // is, and the digest of the whole tree taken, both to be recorded in the tags of the new one.
+    let mut merged = store.make_temp()?;
+    let latest = if is_update {
+        Some(store.load_iter(Version::Latest)?)
+    } else {
+        None
+    };
+    let (rollups, summary, digest) = merge_to(hm, algorithms, &mut merged, latest, &excluded)?;
+    let merged = merged.into_loader()?;
+    if let Some(summary) = summary {
+        summary.add_to_tags(&mut tags);
+    }
+    tags.insert(DIGEST_TAG.to_string(), digest);
+    // A current index of the store's hashes is kept current with those written.
+    let mut index = match IndexUpdater::open(store, algorithms) {
+        Ok(index) => index,
+        Err(e) => {
+            warn!("Not updating the index of the store: {}", e);
+            None
+        }
+    };
+    let mut tmp2 = store.make_new_locked(&tags, lock)?;
+    let nodes = rollups.apply(Loader(&*merged).iter()?);
+    let written = match &hooks.cancel {
+        Some(cancel) => {
+            tmp2.set_cancel(cancel.clone());
+            write_to(
+                cancel.guard(nodes),
+                &mut tmp2,
+                index.as_mut(),
+                &mut observers,
+            )?
+        }
+        None => write_to(nodes, &mut tmp2, index.as_mut(), &mut observers)?,
+    };
+    cancel::check(&hooks.cancel)?;
     tmp2.commit()?;
+    if let Some(index) = index {
+        if let Err(e) = index.save(store) {
+            warn!("Unable to update the index of the store: {}", e);
+        }
+    }
+    for observer in &mut observers {
+        observer.finish()?;
+    }
+    drop(spinner);
+    stats.add_stage("write", start, Some(written));
+    phase(Phase::Idle);
     /*
         let dir = dir.as_ref();
 
@@ -153,11 +631,147 @@ pub fn update<P: AsRef<Path>>(
     Ok(())
 }
 
+/// The nodes of a scan of `dir`, or of the hooks' roots, and what it leaves out.
+type Scan = (
+    Box<dyn Iterator<Item = Result<SureNode>>>,
+    Arc<Mutex<Tombstones>>,
+);
+
+/// Scan `dir`, or each of the hooks' roots.
+fn scan_tree(dir: &Path, hooks: &UpdateHooks, options: &ScanOptions) -> Result<Scan> {
+    match &hooks.roots {
+        Some(roots) => {
+            let scan = roots.scan(options)?;
+            let tombstones = scan.tombstones();
+            Ok((Box::new(scan), tombstones))
+        }
+        None => {
+            let scan = fs::scan_fs_with(dir, options)?;
+            let tombstones = scan.tombstones();
+            Ok((Box::new(scan), tombstones))
+        }
+    }
+}
+
+/// The rollups of the directories of a merged tree, the changes from the latest version, and the
+/// digest of the whole tree.
+type Merged = (Rollups, Option<ChangeSummary>, String);
+
+/// Write the nodes, with their hashes merged in, returning the rollups of their directories, the
+/// digest of the tree, and, given the `latest` version, the changes from it.
+fn merge_to<S: Source, W: Write>(
+    hm: HashMerger<S>,
+    algorithms: &[HashAlgorithm],
+    out: W,
+    latest: Option<Box<dyn Iterator<Item = Result<SureNode>>>>,
+    excluded: &Tombstones,
+) -> Result<Merged> {
+    let mut rollups = Rollups::new(algorithms);
+    let mut digest = TreeDigest::new()?;
+    let mut writer = NodeWriter::new(out)?;
+    let summary = {
+        let mut nodes = hm.iter()?.map(|node| {
+            let node = node?;
+            rollups.add(&node);
+            digest.observe(&node)?;
+            writer.write_node(&node)?;
+            Ok(node)
+        });
+        let summary = match latest {
+            Some(latest) => Some(ChangeSummary::between(latest, nodes.by_ref(), excluded)?),
+            None => None,
+        };
+        for node in nodes {
+            node?;
+        }
+        summary
+    };
+    writer.into_inner()?;
+    Ok((rollups, summary, digest.finish()?))
+}
+
+/// Write the nodes of the new version, adding their hashes to the index, if given, returning the
+/// number of bytes written.
+fn write_to<I, W>(
+    nodes: I,
+    out: W,
+    index: Option<&mut IndexUpdater>,
+    observers: &mut [Box<dyn NodeObserver>],
+) -> Result<u64>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    W: Write,
+{
+    let mut counter = CountingWriter::new(out);
+    let mut writer = NodeWriter::new(&mut counter)?;
+    if clock::is_deterministic() {
+        writer = writer.normalized();
+    }
+    let mut tee = NodeTee::new(writer);
+    if let Some(index) = index {
+        tee = tee.with_observer(index);
+    }
+    for observer in observers {
+        tee = tee.with_observer(&mut **observer);
+    }
+    for node in nodes {
+        tee.write_node(&node?)?;
+    }
+    tee.into_inner()?;
+    Ok(counter.count())
+}
+
+/// A hash updater for `source`, set up from the hooks.
+fn hash_updater<'a, S>(
+    source: S,
+    store: &'a dyn Store,
+    algorithms: &[HashAlgorithm],
+    failures: &Failures,
+    hooks: &UpdateHooks,
+) -> HashUpdater<'a, S> {
+    let mut hu = HashUpdater::new(source, store)
+        .with_algorithms(algorithms)
+        .with_failures(failures.clone());
+    if let Some(pool) = hooks.pool.clone() {
+        hu = hu.with_pool(pool);
+    }
+    if let Some(limit) = hooks.memory_limit {
+        hu = hu.with_memory_limit(limit);
+    }
+    if let Some(activity) = hooks.activity.clone() {
+        hu = hu.with_activity(activity);
+    }
+    if let Some(cancel) = hooks.cancel.clone() {
+        hu = hu.with_cancel(cancel);
+    }
+    if let Some(sink) = hooks.progress.clone() {
+        hu = hu.with_progress(sink);
+    }
+    if let Some(size) = hooks.mmap {
+        hu = hu.with_mmap(size);
+    }
+    if hooks.uring {
+        hu = hu.with_uring();
+    }
+    if let Some(jobs) = hooks.jobs {
+        hu = hu.with_jobs(jobs);
+    }
+    if let Some(throttle) = hooks.throttle.clone() {
+        hu = hu.with_throttle(throttle);
+    }
+    if hooks.background {
+        hu = hu.with_background();
+    }
+    if let Some(roots) = hooks.roots.clone() {
+        hu = hu.with_roots(roots);
+    }
+    hu
+}
+
 struct Loader<'a>(&'a dyn TempLoader);
 
 impl<'a> Source for Loader<'a> {
     fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<SureNode>> + Send>> {
-        let rd = File::open(self.0.path_ref())?;
-        Ok(Box::new(load_from(rd)?))
+        Ok(Box::new(load_from(self.0.new_loader()?)?))
     }
 }
@@ -2,30 +2,242 @@
 
 #![warn(bare_trait_objects)]
 
-use chrono::Local;
-use std::{collections::BTreeMap, path::Path};
+use chrono::{DateTime, Local, Utc};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::Duration,
+};
 use structopt::StructOpt;
 use tempdir::TempDir;
 
-use rsure::{log_init, parse_store, show_tree, Store, StoreTags, StoreVersion, Version};
+use rsure::{
+    clock,
+    daemon::{self, Daemon, DaemonConfig},
+    export::{self, ExportFormat},
+    fleet::{self, Fleet, RunSummary, Thresholds},
+    history::VersionMatch,
+    humanize,
+    ignore::{IgnoreRule, IgnoreRules},
+    import::ImportFormat,
+    log_init,
+    manifest::Manifest,
+    parse_store,
+    pin::{self, Pin},
+    report::{self, ChangeSink, Format},
+    roots::Roots,
+    show_nodes, stats,
+    watch::Watch,
+    ChangeSummary, CheckOptions, Error, Exclude, Failures, FixedClock, HashAlgorithm, HashReuse,
+    JsonProgress, MemoryLimit, NullProgress, PathList, ProgressSink, ReadRate, ScanOptions,
+    ShowOptions, SignedStore, SigningKeys, Store, StoreTags, StoreVersion, SureNode, Throttle,
+    Tombstones, UpdateHooks, Version, DIGEST_TAG,
+};
 
 // For now, just use the crate's error type.
 pub use rsure::Result;
 
+/// The exit status of check and signoff when they find changes.
+const EXIT_CHANGES: i32 = 1;
+
+/// The exit status of check and signoff when they fail.
+const EXIT_ERROR: i32 = 2;
+
 #[derive(StructOpt)]
 #[structopt(name = "rsure", about = "File integrity")]
 struct Opt {
     #[structopt(short = "f", long = "file", default_value = "2sure.dat.gz")]
-    /// Base of file name, default 2sure, will get .dat.gz appended
+    /// Store file name, default 2sure.dat.gz; use a .zstd suffix for zstd compression, or
+    /// s3://bucket/prefix for a store in S3, or ssh://[user@]host[:port]/path/ for a store on
+    /// another host
     file: String,
-    #[structopt(short = "d", long = "dir", default_value = ".")]
-    /// Directory to scan, defaults to "."
-    dir: String,
+    #[structopt(short = "d", long = "dir", number_of_values = 1, parse(from_os_str))]
+    /// Directory to scan, defaults to "."; give several to scan, update and
+    /// check them as one tree, each named at its top after the last part
+    /// of its path
+    dirs: Vec<PathBuf>,
+    #[structopt(skip)]
+    /// The directory given by -d, or when there are several, none, as the
+    /// top of their tree isn't a real directory.
+    dir: PathBuf,
+    #[structopt(skip)]
+    /// The directories given by -d, when there are several.
+    roots: Option<Arc<Roots>>,
     #[structopt(long = "tag")]
     /// key=value to associate with scan
     tag: Vec<String>,
     #[structopt(short = "v", long = "version")]
-    version: Option<String>,
+    /// The revision for check to compare against, as shown by "list", or
+    /// "prior", or "name:" and the revision's name tag; defaults to the
+    /// latest, or the one the tree is pinned to
+    version: Option<Version>,
+    #[structopt(long = "pin", conflicts_with = "version", parse(try_from_str = pin::parse_version))]
+    /// The revision the tree should match, such as "v42", for check to
+    /// compare against, and pin to pin the tree to.  Without this, or -v,
+    /// check compares against the revision in the tree's .rsure-pin, if it
+    /// has one
+    pin: Option<Version>,
+    #[structopt(long = "hash", use_delimiter = true)]
+    /// Hash algorithms to use (sha1, sha256 or blake3), several can be
+    /// given separated by commas.  Update defaults to the ones used by the
+    /// latest version
+    hash: Vec<HashAlgorithm>,
+    #[structopt(long = "timestamp", parse(try_from_str = clock::parse_time))]
+    /// RFC 3339 time to record for a new version, instead of the current
+    /// time, such as when replaying old snapshots
+    timestamp: Option<DateTime<Utc>>,
+    #[structopt(long = "memory-limit")]
+    /// Keep the memory used by a scan under this size, such as 256M;
+    /// fewer files are hashed at once, with smaller reads, to stay under it
+    memory_limit: Option<MemoryLimit>,
+    #[structopt(long = "exclude")]
+    /// Leave paths matching this glob out of the scan, such as "*.tmp" or
+    /// "var/cache/"; can be given several times.  Patterns are also read
+    /// from a .rsureignore file in the scanned directory
+    exclude: Vec<String>,
+    #[structopt(long = "profile")]
+    /// Leave out the paths in a built-in profile: "linux-host" for
+    /// volatile system paths (/proc, /sys, /run, /tmp, lock and editor
+    /// files) or "editors" for editor temp files
+    profile: Vec<String>,
+    #[structopt(long = "cross-filesystems")]
+    /// Descend into directories on other filesystems, such as bind mounts,
+    /// rather than recording them as empty
+    cross_filesystems: bool,
+    #[structopt(long = "follow-symlinks")]
+    /// Record what symlinks point to, descending into linked directories,
+    /// rather than the links themselves
+    follow_symlinks: bool,
+    #[structopt(long = "hash-link-targets")]
+    /// Record where each symlink finally points, and hash the content of
+    /// the regular files they point to, as well as the link text
+    hash_link_targets: bool,
+    #[structopt(long = "selinux")]
+    /// Record the SELinux security context of each file and directory, as
+    /// "secontext", so that relabelling shows up when comparing
+    selinux: bool,
+    #[structopt(long = "flags")]
+    /// Record the flags of each file and directory, such as immutable and
+    /// append only, as "flags", so that they changing shows up when
+    /// comparing
+    flags: bool,
+    #[structopt(long = "blocked")]
+    /// Write the store as independently compressed blocks, so recent
+    /// versions can be read without decompressing the whole history.
+    /// Stores already written this way stay blocked
+    blocked: bool,
+    #[structopt(long = "decode-threads", default_value = "0")]
+    /// Decompress the store on this many other threads when reading it,
+    /// which speeds up large checks and updates; blocked stores are
+    /// decompressed in parallel
+    decode_threads: usize,
+    #[structopt(long = "delta-window")]
+    /// Compare each new version with the last as it is written, within a
+    /// window of this many lines, rather than holding the last version in
+    /// memory, and the new one in a temp file.  For very large stores on
+    /// hosts short of memory or space
+    delta_window: Option<usize>,
+    #[structopt(long = "lock-wait")]
+    /// Wait up to this many seconds for another update of the store to
+    /// finish, rather than failing as soon as the store is found locked
+    lock_wait: Option<u64>,
+    #[structopt(long = "backups", default_value = "1")]
+    /// Keep this many backups of the store when updating it, the older
+    /// ones as 2sure.bak.1.gz, 2sure.bak.2.gz and so on
+    backups: usize,
+    #[structopt(long = "temp-dir", parse(from_os_str))]
+    /// Write the temp files of an update in this directory, such as a
+    /// tmpfs or a faster disk, rather than beside the store
+    temp_dir: Option<PathBuf>,
+    #[structopt(long = "identity", parse(from_os_str))]
+    /// Encrypt the store with the identities in this age identity file,
+    /// such as one written by age-keygen.  The same file is needed to read
+    /// the store.  Needs the "encryption" feature
+    identity: Option<PathBuf>,
+    #[structopt(long = "signing-key", parse(from_os_str))]
+    /// Sign new versions with this ed25519 private key, in PEM form, and
+    /// check the signature of each version read.  With a public key,
+    /// versions are only checked
+    signing_key: Option<PathBuf>,
+    #[structopt(long = "pipelined")]
+    /// Hash files while the scan is still going, rather than after it,
+    /// which is faster when there is a lot to hash
+    pipelined: bool,
+    #[structopt(long = "hash-reuse")]
+    /// When an update reuses a file's hash from the last version: "metadata",
+    /// when its inode, ctime, mtime and size are unchanged, or "never".  The
+    /// default is "metadata", but "never" on network and FUSE filesystems
+    hash_reuse: Option<HashReuse>,
+    #[structopt(long = "mmap")]
+    /// Hash files of at least this size, such as "64M", by mapping them
+    /// into memory rather than reading them, which is faster on fast
+    /// disks.  A file truncated while it is hashed kills rsure
+    mmap: Option<MemoryLimit>,
+    #[structopt(short = "j", long = "jobs")]
+    /// Hash this many files at once, rather than one per CPU, such as
+    /// fewer on a shared machine, or more on storage that handles many
+    /// reads at once
+    jobs: Option<usize>,
+    #[structopt(long = "io-uring")]
+    /// Read small files to hash in batches with io_uring, which is faster
+    /// when there are many.  Experimental, and needs the "uring" feature,
+    /// on Linux
+    io_uring: bool,
+    #[structopt(long = "throttle")]
+    /// Read files to hash no faster than this many megabytes a second, or
+    /// a rate such as "512K/s", so that a scheduled scan leaves the disks
+    /// to the rest of the machine
+    throttle: Option<ReadRate>,
+    #[structopt(long = "idle")]
+    /// Hash at the lowest CPU priority, and on Linux, the idle I/O class,
+    /// as with "nice" and "ionice -c 3", so other work comes first
+    idle: bool,
+    #[structopt(long = "pause-signals")]
+    /// Pause a scan on SIGUSR1, and resume it on SIGUSR2, such as to give
+    /// the disks back to other work for a while
+    pause_signals: bool,
+    #[structopt(short = "q", long = "quiet")]
+    /// Show no progress meter, such as when run from cron, where its
+    /// terminal control sequences would end up in the mail
+    quiet: bool,
+    #[structopt(long = "progress-json", parse(from_os_str))]
+    /// Write the progress as lines of JSON to this file, such as a named
+    /// pipe, or "-" for stderr, for another program to show, rather than
+    /// showing a progress meter
+    progress_json: Option<PathBuf>,
+    #[structopt(skip)]
+    /// Where the progress goes, from --quiet and --progress-json, opened
+    /// once for every update run.
+    progress: Option<Arc<dyn ProgressSink>>,
+    #[structopt(long = "timings")]
+    /// Print the time taken and bytes written by each stage, and other
+    /// counters, to stderr when finished
+    timings: bool,
+    #[structopt(long = "format", default_value = "text")]
+    /// How check, signoff and diff report the changes: "text", one line
+    /// per change, "junit", JUnit XML for CI systems, "markdown", for
+    /// merge requests or chat, or "html", a page with a table that can be
+    /// sorted and filtered
+    format: Format,
+    #[structopt(long = "renames")]
+    /// Have check, signoff and diff report a file gone from one place, and
+    /// found at another with the same size and hash, as renamed, rather
+    /// than removed and added.  The changes are then shown once the whole
+    /// tree has been compared
+    renames: bool,
+    #[structopt(long = "output", parse(from_os_str))]
+    /// Write the report of check, signoff or diff to this file, rather
+    /// than to stdout
+    output: Option<PathBuf>,
+    #[structopt(long = "summary", parse(from_os_str))]
+    /// Write a summary of a scan, update, check or signoff to this file,
+    /// as JSON, for "fleet ingest" on another host: when it ran, on which
+    /// host and store, and the changes found or the error
+    summary: Option<PathBuf>,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -37,95 +249,1300 @@ enum Command {
     Scan,
     #[structopt(name = "update")]
     /// Update the scan using the dat/weave file
-    Update,
+    Update {
+        #[structopt(long = "paths-from", parse(from_os_str))]
+        /// Look at just the paths listed in this file, or "-" for stdin,
+        /// one per line or each ending with a NUL, relative to the
+        /// directory, carrying the rest forward from the latest revision.
+        /// The listed files are hashed again
+        paths_from: Option<PathBuf>,
+    },
+    #[structopt(name = "watch")]
+    /// Watch the directory with inotify, and commit what is touched in it
+    /// as new revisions, rehashing just the touched files.  Linux only
+    Watch {
+        #[structopt(long = "interval", default_value = "60")]
+        /// Commit what was touched at most this often, in seconds
+        interval: u64,
+    },
     #[structopt(name = "check")]
-    /// Compare the directory with the dat/weave file
+    /// Compare the directory with the dat/weave file.  Exits with 0 when
+    /// nothing changed, 1 when something did, and 2 on an error
     Check {
-        #[structopt(short = "i", long = "ignore")]
-        /// Tag to ignore when comparing.
+        #[structopt(short = "i", long = "ignore", use_delimiter = true)]
+        /// Attributes to ignore when comparing, such as "uid,gid,perm"
         ignore: Vec<String>,
+        #[structopt(long = "manifest", parse(from_os_str))]
+        /// Compare the files of the tree, or of the revision given by -v,
+        /// with this manifest from sha256sum, sha1sum or b3sum (with
+        /// --hash blake3), rather than with a revision in the store
+        manifest: Option<PathBuf>,
     },
     #[structopt(name = "signoff")]
-    /// Compare dat with bak file, or last two versions in weave file
+    /// Compare dat with bak file, or last two versions in weave file.
+    /// Exits with 0 when nothing changed, 1 when something did, and 2 on
+    /// an error
     Signoff {
-        #[structopt(short = "i", long = "ignore")]
-        /// Tag to ignore when comparing.
+        #[structopt(short = "i", long = "ignore", use_delimiter = true)]
+        /// Attributes to ignore when comparing, such as "uid,gid,perm"
         ignore: Vec<String>,
+        #[structopt(long = "pin")]
+        /// Once the changes are reported, pin the tree to the latest
+        /// revision, so later checks compare against it
+        pin: bool,
+    },
+    #[structopt(name = "pin")]
+    /// Pin the tree to a revision, given by --pin or -v, or the latest, by
+    /// writing a .rsure-pin file at its root, for check to compare against
+    Pin {
+        #[structopt(long = "show")]
+        /// Show the revision the tree is pinned to, rather than pinning it
+        show: bool,
+    },
+    #[structopt(name = "diff")]
+    /// Compare any two revisions in the store
+    Diff {
+        #[structopt(long = "old")]
+        /// The earlier revision, as shown by "list", or "latest", "prior"
+        /// or "name:" and its name tag
+        old: String,
+        #[structopt(long = "new")]
+        /// The later revision, as shown by "list", or "latest", "prior" or
+        /// "name:" and its name tag
+        new: String,
+        #[structopt(short = "i", long = "ignore", use_delimiter = true)]
+        /// Attributes to ignore when comparing, such as "uid,gid,perm"
+        ignore: Vec<String>,
+        #[structopt(long = "path", parse(from_os_str))]
+        /// Compare just this directory, relative to the top, reading no
+        /// more of either revision than it takes to get past it
+        path: Option<PathBuf>,
     },
     #[structopt(name = "show")]
-    /// Pretty print the dat file
-    Show,
+    /// Show a revision as an indented tree, with some of the attributes of
+    /// each file and directory
+    Show {
+        #[structopt(long = "rev", default_value = "latest")]
+        /// The revision, as shown by "list", or "latest", "prior" or
+        /// "name:" and its name tag
+        rev: String,
+        #[structopt(long = "path", parse(from_os_str))]
+        /// Show just this directory, or file, relative to the top
+        path: Option<PathBuf>,
+        #[structopt(long = "depth")]
+        /// Show nothing more than this many levels below the top, or the
+        /// path
+        depth: Option<usize>,
+        #[structopt(long = "atts", use_delimiter = true)]
+        /// The attributes to show, separated by commas, or "all".  The
+        /// default is perm,uid,gid,size,mtime
+        atts: Vec<String>,
+        #[structopt(long = "no-pager")]
+        /// Write to stdout, rather than through $PAGER (less by default)
+        /// when stdout is a terminal
+        no_pager: bool,
+    },
     #[structopt(name = "list")]
     /// List revisions in a given sure store
+    List {
+        #[structopt(long = "verbose")]
+        /// Also show how many paths each revision added (+), removed (-)
+        /// and modified (~), and the start of the digest of its tree
+        verbose: bool,
+    },
+    #[structopt(name = "stats")]
+    /// Show the size of the store, compressed and not, and how many lines
+    /// each revision has, to see how the store grows
+    Stats,
+    #[structopt(name = "store-check")]
+    /// Check the structure of the store's weave, such as that every
+    /// insert and delete in it is ended, to find damage before it breaks
+    /// reading a revision
+    StoreCheck,
+    #[structopt(name = "verify-file")]
+    /// Compare a file with every revision in the store that has it, to see
+    /// if it has ever been this way before
+    VerifyFile {
+        #[structopt(parse(from_os_str))]
+        /// The file, within the directory given by -d
+        path: PathBuf,
+    },
+    #[structopt(name = "index")]
+    /// Rebuild the index of every hash in the store, which speeds up
+    /// seen-hash
+    Index,
+    #[structopt(name = "seen-hash")]
+    /// List where a file with the given content hash appears in any
+    /// revision of the store
+    SeenHash {
+        /// The hash, in hex, with any of the algorithms the store uses
+        digest: String,
+    },
+    #[structopt(name = "delete")]
+    /// Remove a revision from the store, such as a scan of the wrong
+    /// directory
+    Delete {
+        /// The revision to remove, as shown by "list"
+        version: String,
+    },
+    #[structopt(name = "verify-signature")]
+    /// Check the signature of every revision in the store, with the key
+    /// given by --signing-key
+    VerifySignature {
+        #[structopt(long = "sign-unsigned")]
+        /// Sign the revisions that have no signature, such as those written
+        /// before the store was signed, trusting they haven't been altered
+        sign_unsigned: bool,
+    },
+    #[structopt(name = "export")]
+    /// Write a revision in a format other tools read, to the file given by
+    /// --output, or stdout
+    Export {
+        #[structopt(long = "format", default_value = "mtree")]
+        /// The format: "mtree", a BSD mtree(8) specification, as read by
+        /// "mtree -f" and libarchive, or "sha256sum" or "sha1sum", a
+        /// manifest to check with "sha256sum -c" from the top of the tree
+        format: ExportFormat,
+        #[structopt(short = "v", long = "version")]
+        /// The revision to export, as for -v; defaults to the latest
+        version: Option<Version>,
+    },
+    #[structopt(name = "export-changed")]
+    /// List the paths added or modified between two revisions, one per
+    /// line, for tools that take a list of files, such as "rsync
+    /// --files-from"
+    ExportChanged {
+        #[structopt(long = "from")]
+        /// The earlier revision, as shown by "list", or "latest", "prior"
+        /// or "name:" and its name tag
+        from: String,
+        #[structopt(long = "to")]
+        /// The later revision, as for --from
+        to: String,
+        #[structopt(long = "out", parse(from_os_str))]
+        /// Write the list to this file, rather than to the file given by
+        /// --output, or stdout
+        out: Option<PathBuf>,
+        #[structopt(long = "hashes")]
+        /// Write each file's hash before its path, as sha256sum does, with
+        /// the first algorithm given by --hash, or the revision's own.
+        /// Only regular files are listed
+        hashes: bool,
+        #[structopt(short = "0", long = "null")]
+        /// End each path with a NUL, rather than a newline, as read by
+        /// "rsync --from0"
+        null: bool,
+    },
+    #[structopt(name = "import")]
+    /// Add a file written by another tool to the store as a new revision,
+    /// such as an mtree specification from "mtree -c"
+    Import {
+        #[structopt(long = "format", default_value = "mtree")]
+        /// The format: "mtree", a BSD mtree(8) specification
+        format: ImportFormat,
+        #[structopt(parse(from_os_str))]
+        /// The file to import
+        file: PathBuf,
+    },
+    #[structopt(name = "migrate")]
+    /// Copy a plain surefile, and its backup, into a new store, such as
+    /// one given by -f in another directory
+    Migrate {
+        #[structopt(long = "src", parse(from_os_str))]
+        /// The plain surefile, such as 2sure.dat.gz
+        src: PathBuf,
+        #[structopt(long = "dest")]
+        /// The new store, a directory or file name, as for -f
+        dest: String,
+    },
+    #[structopt(name = "import-dir")]
+    /// Add every plain surefile (*.dat.gz) in a directory to a store, as
+    /// versions named for the files, oldest first
+    ImportDir {
+        #[structopt(long = "src", parse(from_os_str))]
+        /// The directory of surefiles
+        src: PathBuf,
+        #[structopt(long = "dest")]
+        /// The store, a directory or file name, as for -f
+        dest: String,
+    },
+    #[structopt(name = "system-scan")]
+    /// Scan or update every real mounted filesystem, each into its own
+    /// store in the directory given by -f
+    SystemScan,
+    #[structopt(name = "daemon")]
+    /// Periodically scan several trees
+    Daemon {
+        #[structopt(subcommand)]
+        command: DaemonCommand,
+    },
+    #[structopt(name = "ignore")]
+    /// Keep attributes to ignore with the store, everywhere, or under a
+    /// path, for check and signoff to leave out
+    Ignore {
+        #[structopt(subcommand)]
+        command: IgnoreCommand,
+    },
+    #[structopt(name = "fleet")]
+    /// Gather the summaries written by --summary on many hosts, and report
+    /// those needing attention
+    Fleet {
+        #[structopt(long = "db", default_value = "fleet.jsonl", parse(from_os_str))]
+        /// The file the summaries are kept in
+        db: PathBuf,
+        #[structopt(subcommand)]
+        command: FleetCommand,
+    },
+}
+
+impl Command {
+    /// The name a summary of the command is recorded with, for those that
+    /// write one.
+    fn summary_name(&self) -> Option<&'static str> {
+        match self {
+            Command::Scan => Some("scan"),
+            Command::Update { .. } => Some("update"),
+            Command::Check { .. } => Some("check"),
+            Command::Signoff { .. } => Some("signoff"),
+            _ => None,
+        }
+    }
+
+    /// The name of the command, for those that need a single directory,
+    /// rather than the several -d can give.
+    fn single_dir_name(&self) -> Option<&'static str> {
+        match self {
+            Command::Update {
+                paths_from: Some(_),
+            } => Some("update --paths-from"),
+            Command::Watch { .. } => Some("watch"),
+            Command::Check {
+                manifest: Some(_), ..
+            } => Some("check --manifest"),
+            Command::Signoff { pin: true, .. } => Some("signoff --pin"),
+            Command::Pin { .. } => Some("pin"),
+            Command::VerifyFile { .. } => Some("verify-file"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum DaemonCommand {
+    #[structopt(name = "run")]
+    /// Run the profiles in the config file on their schedules
+    Run {
+        #[structopt(short = "c", long = "config")]
+        /// The daemon configuration file
+        config: PathBuf,
+    },
+    #[structopt(name = "status")]
+    /// Show the last and next run of each profile
+    Status {
+        #[structopt(short = "c", long = "config")]
+        /// The daemon configuration file
+        config: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+enum IgnoreCommand {
+    #[structopt(name = "add")]
+    /// Ignore an attribute, such as "mtime"
+    Add {
+        att: String,
+        #[structopt(long = "under", parse(from_os_str))]
+        /// Just in this directory, or file, relative to the top of the
+        /// tree, or within -d
+        under: Option<PathBuf>,
+    },
+    #[structopt(name = "remove")]
+    /// Stop ignoring an attribute, given as it was added
+    Remove {
+        att: String,
+        #[structopt(long = "under", parse(from_os_str))]
+        /// The path the rule was added with
+        under: Option<PathBuf>,
+    },
+    #[structopt(name = "list")]
+    /// List the attributes ignored, and where
     List,
 }
 
+#[derive(StructOpt)]
+enum FleetCommand {
+    #[structopt(name = "ingest")]
+    /// Add the summaries in these files, skipping those already added
+    Ingest {
+        #[structopt(parse(from_os_str))]
+        /// Files of summaries, or "-" for stdin
+        files: Vec<PathBuf>,
+    },
+    #[structopt(name = "report")]
+    /// Report the hosts whose last check found changes or failed, whose
+    /// last run changed too much, or that haven't run recently
+    Report {
+        #[structopt(long = "max-changes")]
+        /// Report a store whose last run found more changes than this
+        max_changes: Option<usize>,
+        #[structopt(long = "stale", default_value = "2d", parse(try_from_str = fleet::parse_age))]
+        /// Report a store that hasn't run for this long, such as "36h" or
+        /// "7d"
+        stale: chrono::Duration,
+        #[structopt(long = "all")]
+        /// Show every store, not just those needing attention
+        all: bool,
+    },
+}
+
 #[allow(dead_code)]
 fn main() -> Result<()> {
     log_init();
 
     let opt = Opt::from_args();
+    let summary = match (&opt.summary, opt.command.summary_name()) {
+        (Some(path), Some(name)) => Some((path.clone(), RunSummary::new(&opt.file, name))),
+        _ => None,
+    };
+    let compares = matches!(opt.command, Command::Check { .. } | Command::Signoff { .. });
 
-    let store = parse_store(&opt.file)?;
-
-    let mut tags = decode_tags(Some(opt.tag.iter().map(|x| x.as_str())));
+    let result = run(opt);
+    if let Some((path, mut summary)) = summary {
+        summary.time = clock::default_clock().now();
+        match &result {
+            Ok(changes) => summary.changes = *changes,
+            Err(e) => summary.error = Some(e.to_string()),
+        }
+        summary.write(File::create(path)?)?;
+    }
 
-    add_name_tag(&mut tags, &opt.dir);
+    // Check and signoff exit as diff(1) does, so that scripts can tell
+    // changes from failures.
+    match result {
+        Ok(Some(changes)) if compares && changes.count() > 0 => process::exit(EXIT_CHANGES),
+        Err(e) if compares => {
+            eprintln!("Error: {:?}", e);
+            process::exit(EXIT_ERROR);
+        }
+        result => result.map(|_| ()),
+    }
+}
 
-    // Note that only the "check" command uses the version tag.
-    let latest = match opt.version {
-        None => Version::Latest,
-        Some(ref x) => Version::Tagged(x.to_string()),
+/// Run the command, returning the changes it found, for the commands that
+/// look for them.
+fn run(mut opt: Opt) -> Result<Option<ChangeSummary>> {
+    opt.progress = progress_sink(&opt)?;
+    match opt.dirs.as_slice() {
+        [] => opt.dir = PathBuf::from("."),
+        [dir] => opt.dir = dir.clone(),
+        dirs => {
+            if let Some(name) = opt.command.single_dir_name() {
+                return Err(Error::SeveralRoots(name.to_string()));
+            }
+            opt.roots = Some(Arc::new(Roots::new(dirs)?));
+        }
+    }
+    let mut store = parse_store(&opt.file)?;
+    if let Some(time) = opt.timestamp {
+        store.set_clock(Box::new(FixedClock(time)));
+    }
+    if opt.blocked {
+        store.set_blocked();
+    }
+    store.set_decode_threads(opt.decode_threads);
+    if let Some(lines) = opt.delta_window {
+        store.set_delta_window(lines);
+    }
+    if let Some(secs) = opt.lock_wait {
+        store.set_lock_wait(Duration::from_secs(secs))?;
+    }
+    store.set_backups(opt.backups);
+    if let Some(dir) = &opt.temp_dir {
+        store.set_temp_dir(dir)?;
+    }
+    set_identity(&mut *store, &opt)?;
+    let store: Box<dyn Store> = match signing_keys(&opt)? {
+        Some(keys) => {
+            let store = SignedStore::new(store, keys);
+            if let Command::VerifySignature { sign_unsigned } = opt.command {
+                return verify_signatures(&store, sign_unsigned).map(|()| None);
+            }
+            Box::new(store)
+        }
+        None => store,
     };
 
+    let mut tags = decode_tags(Some(opt.tag.iter().map(|x| x.as_str())));
+
+    add_name_tag(&mut tags, &opt, &*store, opt.timestamp)?;
+
+    let mut changes = None;
     match &opt.command {
         Command::Scan => {
-            rsure::update(&opt.dir, &*store, false, &tags)?;
+            update(&opt, &*store, false, &tags, &opt.hash)?;
+            changes = latest_changes(&*store)?;
+        }
+        Command::Update { paths_from } => {
+            let algorithms = if opt.hash.is_empty() {
+                stored_algorithms(&*store, &Version::Latest)?
+            } else {
+                opt.hash.clone()
+            };
+            let mut hooks = update_hooks(&opt)?;
+            hooks.paths = match paths_from {
+                Some(file) if file.as_os_str() == "-" => Some(PathList::read(io::stdin().lock())?),
+                Some(file) => Some(PathList::load(file)?),
+                None => None,
+            };
+            rsure::update_with(
+                Path::new(&opt.dir),
+                &*store,
+                true,
+                &tags,
+                &algorithms,
+                hooks,
+            )?;
+            report_failures(&opt, &*store)?;
+            changes = latest_changes(&*store)?;
         }
-        Command::Update => {
-            rsure::update(&opt.dir, &*store, true, &tags)?;
+        Command::Watch { interval } => {
+            rsure::service::handle_termination();
+            let algorithms = if opt.hash.is_empty() {
+                stored_algorithms(&*store, &Version::Latest)?
+            } else {
+                opt.hash.clone()
+            };
+            Watch::new(Path::new(&opt.dir), &*store, &algorithms)
+                .with_tags(&tags)
+                .with_interval(Duration::from_secs(*interval))
+                .run(|| update_hooks(&opt))?;
         }
-        Command::Check { ignore } => {
-            let ignore: Vec<_> = ignore.iter().map(|x| x.as_str()).collect();
-            run_check(&*store, &opt, latest, &ignore)?;
+        Command::Check {
+            manifest: Some(manifest),
+            ..
+        } => {
+            changes = Some(run_manifest_check(&*store, &opt, manifest)?);
         }
-        Command::Signoff { ignore } => {
-            let ignore: Vec<_> = ignore.iter().map(|x| x.as_str()).collect();
+        Command::Check {
+            ignore,
+            manifest: None,
+        } => {
+            let rules = stored_rules(&*store, ignore)?;
+            let baseline = check_baseline(&*store, &opt)?;
+            changes = Some(run_check(&*store, &opt, baseline, rules)?);
+        }
+        Command::Signoff { ignore, pin } => {
+            let rules = stored_rules(&*store, ignore)?;
             let old_tree = store.load_iter(Version::Prior)?;
             let new_tree = store.load_iter(Version::Latest)?;
-            println!("signoff {}", opt.file);
-            rsure::compare_trees(old_tree, new_tree, &Path::new(&opt.dir), &ignore)?;
+            let excluded = stored_tombstones(&*store, &Version::Latest)?;
+            let title = format!("signoff {}", opt.file);
+            status(&opt, &title);
+            changes = Some(report(&opt, &title, old_tree, new_tree, &rules, &excluded)?);
+            if *pin {
+                pin_tree(&*store, &opt, &Version::Latest)?;
+            }
+        }
+        Command::Pin { show: true } => match Pin::load(&opt.dir)? {
+            Some(pin) => {
+                let time = pin.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+                println!("{:>4} | {} | {}", pin.version, time, pin.name);
+            }
+            None => println!("Not pinned"),
+        },
+        Command::Pin { show: false } => {
+            let version = opt.pin.as_ref().or(opt.version.as_ref());
+            pin_tree(&*store, &opt, version.unwrap_or(&Version::Latest))?;
         }
-        Command::Show => {
-            println!("show {}", opt.file);
-            show_tree(&*store)?;
+        Command::Diff {
+            old,
+            new,
+            ignore,
+            path: Some(path),
+        } => {
+            let rules = ignored(ignore)?;
+            let old = stored_version(&*store, old)?;
+            let new = stored_version(&*store, new)?;
+            let title = format!("diff {} {}", opt.file, path.display());
+            status(&opt, &title);
+            let mut sink = report_sink(&opt, &title)?;
+            let changes = store.diff_dir(old, new, path, &rules, &mut |mut change| {
+                change.path = Path::new(&opt.dir).join(&change.path);
+                sink.change(change)
+            })?;
+            sink.finish()?;
+            status(&opt, &changes.describe());
         }
-        Command::List => {
+        Command::Diff {
+            old,
+            new,
+            ignore,
+            path: None,
+        } => {
+            let rules = ignored(ignore)?;
+            let old = stored_version(&*store, old)?;
+            let new = stored_version(&*store, new)?;
+            let excluded = stored_tombstones(&*store, &new)?;
+            let old_tree = store.load_iter(old)?;
+            let new_tree = store.load_iter(new)?;
+            let title = format!("diff {}", opt.file);
+            status(&opt, &title);
+            report(&opt, &title, old_tree, new_tree, &rules, &excluded)?;
+        }
+        Command::Show {
+            rev,
+            path,
+            depth,
+            atts,
+            no_pager,
+        } => {
+            let version = stored_version(&*store, rev)?;
+            let options = ShowOptions {
+                path: path.clone(),
+                depth: *depth,
+                atts: atts.clone(),
+            };
+            paged(*no_pager, |out| {
+                writeln!(out, "show {}", opt.file)?;
+                show_nodes(store.load_iter(version)?, &options, out)
+            })?;
+        }
+        Command::List { verbose } => {
             let version = store.get_versions()?;
-            dump_versions(&version);
+            dump_versions(&version, *verbose)?;
+        }
+        Command::Stats => dump_stats(&*store, &opt.file)?,
+        Command::StoreCheck => {
+            let problems = store.fsck()?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(Error::StoreProblems(problems.len()));
+            }
+            println!("No problems found in {}", opt.file);
+        }
+        Command::VerifyFile { path } => {
+            let matches = rsure::history::verify_file(&*store, &opt.dir, path)?;
+            dump_matches(&matches);
+        }
+        Command::Index => {
+            let count = rsure::index::build_index(&*store)?;
+            println!("Indexed {} hashes", count);
+        }
+        Command::SeenHash { digest } => {
+            let sightings = rsure::index::seen_hash(&*store, digest)?;
+            if sightings.is_empty() {
+                println!("Never seen");
+            }
+            for s in &sightings {
+                println!(
+                    "{:>4} | {} | {} {}",
+                    s.version.version.numeric().unwrap_or(0),
+                    s.version
+                        .time
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M:%S"),
+                    s.algorithm,
+                    s.path
+                );
+            }
+        }
+        Command::VerifySignature { .. } => {
+            return Err(Error::Signature(
+                "verify-signature needs a key, given with --signing-key".to_string(),
+            ));
+        }
+        Command::Export { format, version } => {
+            let version = version
+                .clone()
+                .or_else(|| opt.version.clone())
+                .unwrap_or(Version::Latest);
+            let out: Box<dyn Write> = match &opt.output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout()),
+            };
+            export::export(&*store, &version, *format, out)?;
+        }
+        Command::ExportChanged {
+            from,
+            to,
+            out,
+            hashes,
+            null,
+        } => {
+            let from = stored_version(&*store, from)?;
+            let to = stored_version(&*store, to)?;
+            let hashes = if *hashes {
+                match opt.hash.first() {
+                    Some(algorithm) => Some(*algorithm),
+                    None => stored_algorithms(&*store, &to)?.first().copied(),
+                }
+            } else {
+                None
+            };
+            let options = export::ChangedOptions {
+                hashes,
+                null: *null,
+            };
+            let out: Box<dyn Write> = match out.as_ref().or(opt.output.as_ref()) {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout()),
+            };
+            export::export_changed(&*store, &from, &to, &options, out)?;
+        }
+        Command::Import { format, file } => {
+            rsure::import::import_file(&*store, file, *format, &tags)?;
+        }
+        Command::Delete { version } => {
+            store.delete_version(version.parse()?)?;
+        }
+        Command::Migrate { src, dest } => {
+            let mut dest = parse_store(dest)?;
+            if opt.blocked {
+                dest.set_blocked();
+            }
+            let count = rsure::import::migrate(src, &mut *dest)?;
+            println!("Migrated {} versions", count);
+        }
+        Command::ImportDir { src, dest } => {
+            let mut dest = parse_store(dest)?;
+            if opt.blocked {
+                dest.set_blocked();
+            }
+            let count = rsure::import::import_dir(src, &mut *dest)?;
+            println!("Imported {} versions", count);
+        }
+        Command::SystemScan => {
+            system_scan(&opt, &tags)?;
+        }
+        Command::Daemon {
+            command: DaemonCommand::Run { config },
+        } => {
+            rsure::service::handle_termination();
+            let mut daemon = Daemon::new(config)?;
+            if let Some(sink) = opt.progress.clone() {
+                daemon = daemon.with_progress(sink);
+            }
+            daemon.run()?;
+        }
+        Command::Daemon {
+            command: DaemonCommand::Status { config },
+        } => {
+            let path = Daemon::status_path(&DaemonConfig::load(config)?, config);
+            dump_daemon_status(&daemon::load_status(path)?);
+        }
+        Command::Fleet { db, command } => run_fleet(&Fleet::open(db), command)?,
+        Command::Ignore { command } => run_ignore(&*store, &opt, command)?,
+    }
+
+    if opt.timings {
+        eprintln!("{}", rsure::stats::global().snapshot());
+    }
+
+    Ok(changes)
+}
+
+fn run_fleet(fleet: &Fleet, command: &FleetCommand) -> Result<()> {
+    match command {
+        FleetCommand::Ingest { files } => {
+            let mut added = 0;
+            for file in files {
+                added += if file == Path::new("-") {
+                    fleet.ingest(io::stdin().lock())?
+                } else {
+                    fleet.ingest(BufReader::new(File::open(file)?))?
+                };
+            }
+            println!("Added {} summaries", added);
+        }
+        FleetCommand::Report {
+            max_changes,
+            stale,
+            all,
+        } => {
+            let thresholds = Thresholds {
+                max_changes: *max_changes,
+                stale: Some(*stale),
+            };
+            let now = clock::default_clock().now();
+            for status in fleet.report(&thresholds, now)? {
+                if status.problems.is_empty() && !all {
+                    continue;
+                }
+                let time = status.last.time.with_timezone(&Local);
+                let state = if status.problems.is_empty() {
+                    "ok"
+                } else {
+                    "ATTENTION"
+                };
+                println!(
+                    "{:<9} {} {} (last {} {})",
+                    state,
+                    status.host,
+                    status.store,
+                    status.last.command,
+                    time.format("%Y-%m-%d %H:%M:%S")
+                );
+                for problem in &status.problems {
+                    println!("          {}", problem);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The version check compares against: the one given, or the one the tree is
+/// pinned to, or the latest.
+fn check_baseline(store: &dyn Store, opt: &Opt) -> Result<Version> {
+    if let Some(version) = opt.version.as_ref().or(opt.pin.as_ref()) {
+        return Ok(version.clone());
+    }
+    // A tree of several directories has nowhere to keep a pin.
+    let pin = match opt.roots {
+        Some(_) => None,
+        None => Pin::load(&opt.dir)?,
+    };
+    match pin {
+        Some(pin) => {
+            let version = pin.resolve(store)?;
+            status(opt, &format!("Pinned to {} ({})", version, pin.name));
+            Ok(version)
+        }
+        None => Ok(Version::Latest),
+    }
+}
+
+/// Pin the tree to a version of the store.
+fn pin_tree(store: &dyn Store, opt: &Opt, version: &Version) -> Result<()> {
+    let info = store
+        .get_version(version)?
+        .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+    let pin = Pin::of(&info);
+    pin.save(&opt.dir, store)?;
+    status(
+        opt,
+        &format!("Pinned {:?} to {} ({})", opt.dir, pin.version, pin.name),
+    );
+    Ok(())
+}
+
+/// Check the tree against a version of the store, with `rsure::check`, and report what it found.
+fn run_check(
+    store: &dyn Store,
+    opt: &Opt,
+    latest: Version,
+    rules: IgnoreRules,
+) -> Result<ChangeSummary> {
+    status(opt, "Scanning");
+    let options = CheckOptions {
+        hooks: update_hooks(opt)?,
+        rules: Some(rules),
+        renames: opt.renames,
+    };
+    let found = rsure::check(&opt.dir, store, latest, options)?;
+    report_count(opt, &found.failures);
+
+    let title = format!("Check {}", opt.file);
+    status(opt, &title);
+    let mut sink = report_sink(opt, &title)?;
+    for change in found.changes {
+        sink.change(change);
+    }
+    sink.finish()?;
+    status(opt, &found.summary.describe());
+    Ok(found.summary)
+}
+
+/// Compare the files of the tree, or a version of the store, with a
+/// checksum manifest.
+fn run_manifest_check(store: &dyn Store, opt: &Opt, path: &Path) -> Result<ChangeSummary> {
+    let manifest = Manifest::load(path, opt.hash.first().copied())?;
+    let algorithm = manifest.algorithm();
+    let title = format!("Check {}", path.display());
+
+    let (tree, _tdir) = match &opt.version {
+        Some(version) => {
+            let info = store
+                .get_version(version)?
+                .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+            if !HashAlgorithm::from_tags(&info.tags)?.contains(&algorithm) {
+                return Err(Error::NotHashed(info.name, algorithm.to_string()));
+            }
+            (store.load_iter(info.version)?, None)
+        }
+        None => {
+            let tdir = TempDir::new("rsure")?;
+            let tpath = tdir.path().join("check.dat.gz");
+            let tstore = parse_store(tpath.to_str().unwrap())?;
+            let mut tags = BTreeMap::new();
+            add_name_tag(&mut tags, opt, &*tstore, None)?;
+            status(opt, "Scanning");
+            update(opt, &*tstore, false, &tags, &[algorithm])?;
+            (tstore.load_iter(Version::Latest)?, Some(tdir))
+        }
+    };
+
+    status(opt, &title);
+    let mut sink = report_sink(opt, &title)?;
+    let changes = manifest.check(tree, Path::new(&opt.dir), |change| sink.change(change))?;
+    sink.finish()?;
+    status(opt, &changes.describe());
+    Ok(changes)
+}
+
+/// Compare two trees, writing the changes in the chosen format to the
+/// output file, or stdout.
+fn report<IA, IB>(
+    opt: &Opt,
+    title: &str,
+    old_tree: IA,
+    new_tree: IB,
+    rules: &IgnoreRules,
+    excluded: &Tombstones,
+) -> Result<ChangeSummary>
+where
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+{
+    let mut sink = report_sink(opt, title)?;
+    let compare = if opt.renames {
+        rsure::compare_trees_with_renames
+    } else {
+        rsure::compare_trees_with_rules
+    };
+    let changes = compare(
+        old_tree,
+        new_tree,
+        Path::new(&opt.dir),
+        rules,
+        excluded,
+        |change| sink.change(change),
+    )?;
+    sink.finish()?;
+    status(opt, &changes.describe());
+    Ok(changes)
+}
+
+/// The ignore rules kept with the store, along with the attributes given to `-i`.
+fn stored_rules(store: &dyn Store, ignore: &[String]) -> Result<IgnoreRules> {
+    let mut rules = IgnoreRules::load(store)?;
+    rules.extend(&ignored(ignore)?);
+    Ok(rules)
+}
+
+/// The attributes given to `-i`, ignored everywhere.
+fn ignored(ignore: &[String]) -> Result<IgnoreRules> {
+    let mut rules = IgnoreRules::new();
+    for att in ignore {
+        rules.add(IgnoreRule::new(att.trim(), None)?);
+    }
+    Ok(rules)
+}
+
+/// A rule for the ignore command, whose path may be given within the tree, such as "/var/log"
+/// for a tree at "/".
+fn ignore_rule(opt: &Opt, att: &str, under: Option<&Path>) -> Result<IgnoreRule> {
+    let under = match under {
+        Some(_) if opt.roots.is_some() => {
+            return Err(Error::SeveralRoots(
+                "ignore with an absolute path".to_string(),
+            ))
+        }
+        Some(path) if path.is_absolute() => {
+            let top = fs::canonicalize(&opt.dir)?;
+            let rel = path
+                .strip_prefix(&opt.dir)
+                .or_else(|_| path.strip_prefix(&top))
+                .map_err(|_| Error::InvalidIgnore(format!("{:?} isn't in {:?}", path, opt.dir)))?;
+            Some(rel.to_path_buf())
+        }
+        under => under.map(Path::to_path_buf),
+    };
+    IgnoreRule::new(att, under.as_deref())
+}
+
+fn run_ignore(store: &dyn Store, opt: &Opt, command: &IgnoreCommand) -> Result<()> {
+    let mut rules = IgnoreRules::load(store)?;
+    match command {
+        IgnoreCommand::Add { att, under } => {
+            let rule = ignore_rule(opt, att, under.as_deref())?;
+            if rules.add(rule.clone()) {
+                rules.save(store)?;
+                println!("Ignoring {}", rule);
+            } else {
+                println!("Already ignoring {}", rule);
+            }
+        }
+        IgnoreCommand::Remove { att, under } => {
+            let rule = ignore_rule(opt, att, under.as_deref())?;
+            if rules.remove(&rule) {
+                rules.save(store)?;
+                println!("No longer ignoring {}", rule);
+            } else {
+                return Err(Error::InvalidIgnore(format!("{}: no such rule", rule)));
+            }
+        }
+        IgnoreCommand::List => {
+            for rule in rules.rules() {
+                match &rule.under {
+                    Some(under) => println!("{:<12} {}", rule.att, under.display()),
+                    None => println!("{:<12} (everywhere)", rule.att),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The changes recorded in the latest version, from the one before.
+fn latest_changes(store: &dyn Store) -> Result<Option<ChangeSummary>> {
+    match store.get_version(&Version::Latest)? {
+        Some(v) => ChangeSummary::from_tags(&v.tags),
+        None => Ok(None),
+    }
+}
+
+/// Where the changes go, in the chosen format, to the output file or stdout.
+fn report_sink(opt: &Opt, title: &str) -> Result<Box<dyn ChangeSink>> {
+    let out: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    Ok(report::sink(opt.format, title, out))
+}
+
+/// Write the output of `write` through the pager, when stdout is a terminal, and the pager isn't
+/// turned off.
+fn paged<F>(no_pager: bool, write: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let stdout = io::stdout();
+    if no_pager || unsafe { libc::isatty(1) } == 0 {
+        return write(&mut stdout.lock());
+    }
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    let mut child = match process::Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .env(
+            "LESS",
+            std::env::var("LESS").unwrap_or_else(|_| "FRX".to_string()),
+        )
+        .stdin(process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return write(&mut stdout.lock()),
+    };
+    let mut pipe = BufWriter::new(child.stdin.take().unwrap());
+    let result = write(&mut pipe).and_then(|()| Ok(pipe.flush()?));
+    // Closing the pipe lets the pager see the end.
+    drop(pipe);
+    child.wait()?;
+    match result {
+        // The pager was quit before the end.
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Print a progress line.  Formats other than text go to stdout on their
+/// own, so the lines go to stderr instead.
+fn status(opt: &Opt, line: &str) {
+    match (opt.format, &opt.output) {
+        (Format::Text, None) => println!("{}", line),
+        _ => eprintln!("{}", line),
+    }
+}
+
+/// Scan or update `opt.dir`, with the scan options and memory limit given.
+fn update(
+    opt: &Opt,
+    store: &dyn Store,
+    is_update: bool,
+    tags: &StoreTags,
+    algorithms: &[HashAlgorithm],
+) -> Result<()> {
+    rsure::update_with(
+        Path::new(&opt.dir),
+        store,
+        is_update,
+        tags,
+        algorithms,
+        update_hooks(opt)?,
+    )?;
+    report_failures(opt, store)
+}
+
+/// Say how many paths the latest version couldn't read, if any.
+fn report_failures(opt: &Opt, store: &dyn Store) -> Result<()> {
+    if let Some(v) = store.get_version(&Version::Latest)? {
+        report_count(opt, &Failures::from_tags(&v.tags)?);
+    }
+    Ok(())
+}
+
+/// Say how many paths couldn't be read, if any.
+fn report_count(opt: &Opt, failures: &Failures) {
+    match failures.len() {
+        0 => (),
+        1 => status(opt, "1 path couldn't be read"),
+        n => status(opt, &format!("{} paths couldn't be read", n)),
+    }
+}
+
+/// Where the progress goes, if not to the terminal.
+fn progress_sink(opt: &Opt) -> Result<Option<Arc<dyn ProgressSink>>> {
+    match &opt.progress_json {
+        Some(path) if path.as_os_str() == "-" => {
+            Ok(Some(Arc::new(JsonProgress::new(io::stderr()))))
         }
+        Some(path) => Ok(Some(Arc::new(JsonProgress::new(File::create(path)?)))),
+        None if opt.quiet => Ok(Some(Arc::new(NullProgress))),
+        None => Ok(None),
+    }
+}
+
+/// The update settings from the command line.
+fn update_hooks(opt: &Opt) -> Result<UpdateHooks> {
+    let mut exclude = Exclude::new();
+    for name in &opt.profile {
+        exclude.add_profile(name)?;
+    }
+    for glob in &opt.exclude {
+        exclude.add(glob)?;
+    }
+    let cancel = if opt.pause_signals {
+        rsure::service::handle_pause()?;
+        Some(rsure::service::pause_token())
+    } else {
+        None
+    };
+    Ok(UpdateHooks {
+        memory_limit: opt.memory_limit,
+        pipelined: opt.pipelined,
+        scan: ScanOptions {
+            cross_filesystems: opt.cross_filesystems,
+            follow_symlinks: opt.follow_symlinks,
+            hash_link_targets: opt.hash_link_targets,
+            selinux: opt.selinux,
+            flags: opt.flags,
+            exclude,
+            ..ScanOptions::default()
+        },
+        cancel,
+        progress: opt.progress.clone(),
+        hash_reuse: opt.hash_reuse,
+        mmap: opt.mmap.map(MemoryLimit::bytes),
+        uring: opt.io_uring,
+        jobs: opt.jobs,
+        throttle: opt.throttle.map(|rate| Arc::new(Throttle::new(rate))),
+        background: opt.idle,
+        roots: opt.roots.clone(),
+        ..UpdateHooks::default()
+    })
+}
+
+/// Scan every real mounted filesystem, each into its own store in the directory given by `-f`,
+/// and print a summary of each.
+#[cfg(unix)]
+fn system_scan(opt: &Opt, tags: &StoreTags) -> Result<()> {
+    use rsure::system;
+
+    let dir = Path::new(&opt.file);
+    if !dir.is_dir() {
+        return Err(Error::RootMustBeDir);
+    }
+
+    println!("mount                | fstype   |    nodes |   hashed |        bytes | result");
+    println!("---------------------+----------+----------+----------+--------------+-------");
+    let mut failed = 0;
+    for mount in system::mounts()? {
+        let before = stats::global().snapshot();
+        let result = scan_mount(
+            opt,
+            tags,
+            &mount,
+            &dir.join(format!("{}.weave.gz", mount.label())),
+        );
+        let counts = stats::global().snapshot().since(&before);
+        let result = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => {
+                failed += 1;
+                e.to_string()
+            }
+        };
+        println!(
+            "{:20} | {:8} | {:>8} | {:>8} | {:>12} | {}",
+            mount.mountpoint.display(),
+            mount.fstype,
+            counts.nodes_scanned,
+            counts.files_hashed,
+            counts.bytes_hashed,
+            result
+        );
+    }
+
+    if failed > 0 {
+        return Err(Error::SystemScan(failed));
+    }
+    Ok(())
+}
+
+/// Scan or update a single mount into the given store.
+#[cfg(unix)]
+fn scan_mount(
+    opt: &Opt,
+    tags: &StoreTags,
+    mount: &rsure::system::Mount,
+    path: &Path,
+) -> Result<()> {
+    let mut store = parse_store(&path.to_string_lossy())?;
+    if let Some(time) = opt.timestamp {
+        store.set_clock(Box::new(FixedClock(time)));
+    }
+    if opt.blocked {
+        store.set_blocked();
+    }
+    store.set_decode_threads(opt.decode_threads);
+    if let Some(lines) = opt.delta_window {
+        store.set_delta_window(lines);
     }
+    if let Some(secs) = opt.lock_wait {
+        store.set_lock_wait(Duration::from_secs(secs))?;
+    }
+    store.set_backups(opt.backups);
+    if let Some(dir) = &opt.temp_dir {
+        store.set_temp_dir(dir)?;
+    }
+    set_identity(&mut *store, opt)?;
+    let store: Box<dyn Store> = match signing_keys(opt)? {
+        Some(keys) => Box::new(SignedStore::new(store, keys)),
+        None => store,
+    };
+    // A store with no versions yet gets a fresh scan.
+    let is_update = store.get_version(&Version::Latest)?.is_some();
+    let algorithms = if !opt.hash.is_empty() {
+        opt.hash.clone()
+    } else if is_update {
+        stored_algorithms(&*store, &Version::Latest)?
+    } else {
+        vec![HashAlgorithm::default()]
+    };
+
+    let mut tags = tags.clone();
+    tags.insert(
+        "dir".to_string(),
+        mount.mountpoint.to_string_lossy().into_owned(),
+    );
+    tags.insert("fstype".to_string(), mount.fstype.clone());
+    tags.insert("source".to_string(), mount.source.clone());
+
+    // Each mount has its own store, so stay on it.
+    let mut hooks = update_hooks(opt)?;
+    hooks.scan.cross_filesystems = false;
+    rsure::update_with(
+        &mount.mountpoint,
+        &*store,
+        is_update,
+        &tags,
+        &algorithms,
+        hooks,
+    )
+}
+
+#[cfg(not(unix))]
+fn system_scan(_opt: &Opt, _tags: &StoreTags) -> Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "system-scan reads the mount table, on Unix",
+    )
+    .into())
+}
 
+/// Encrypt the store with the identity file given, if any.
+#[cfg(feature = "encryption")]
+fn set_identity(store: &mut dyn Store, opt: &Opt) -> Result<()> {
+    if let Some(path) = &opt.identity {
+        store.set_cipher(std::sync::Arc::new(rsure::AgeCipher::from_identity_file(
+            path,
+        )?))?;
+    }
     Ok(())
 }
 
-fn run_check(store: &dyn Store, opt: &Opt, latest: Version, ignore: &[&str]) -> Result<()> {
-    // Perform a full scan to a temp store.
-    let tdir = TempDir::new("rsure")?;
-    let tpath = tdir.path().join("check.dat.gz");
-    let tstore = parse_store(tpath.to_str().unwrap())?;
-    let mut tags = BTreeMap::new();
-    add_name_tag(&mut tags, &opt.dir);
-    println!("Scanning");
-    rsure::update(&opt.dir, &*tstore, false, &tags)?;
-
-    let old_tree = store.load_iter(latest)?;
-    let new_tree = tstore.load_iter(Version::Latest)?;
-    println!("Check {}", opt.file);
-    rsure::compare_trees(old_tree, new_tree, &Path::new(&opt.dir), ignore)?;
+#[cfg(not(feature = "encryption"))]
+fn set_identity(_store: &mut dyn Store, opt: &Opt) -> Result<()> {
+    match opt.identity {
+        Some(_) => Err(Error::Encryption(
+            "rsure was built without the \"encryption\" feature".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The keys to sign and check the store with, if any were given.
+fn signing_keys(opt: &Opt) -> Result<Option<Arc<SigningKeys>>> {
+    match &opt.signing_key {
+        Some(path) => Ok(Some(Arc::new(SigningKeys::from_file(path)?))),
+        None => Ok(None),
+    }
+}
+
+/// Check the signature of each version in the store, reporting those that fail.
+fn verify_signatures(store: &SignedStore, sign_unsigned: bool) -> Result<()> {
+    let mut failed = 0;
+    for v in store.get_versions()? {
+        match store.verify_version(&v.version) {
+            Ok(()) => println!("{:>4} good   {}", v.version, v.name),
+            Err(Error::Unsigned(_)) if sign_unsigned => {
+                store.sign_version(&v.version)?;
+                println!("{:>4} signed {}", v.version, v.name);
+            }
+            Err(e) => {
+                println!("{:>4} FAILED {}: {}", v.version, v.name, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Error::Signature(format!(
+            "{} versions failed verification",
+            failed
+        )));
+    }
     Ok(())
 }
 
+/// Determine which hash algorithms the given version was captured with.
+fn stored_algorithms(store: &dyn Store, version: &Version) -> Result<Vec<HashAlgorithm>> {
+    match store.get_version(version)? {
+        Some(v) => HashAlgorithm::from_tags(&v.tags),
+        None => Ok(vec![HashAlgorithm::default()]),
+    }
+}
+
+/// A version given on the command line, which must be in the store.
+fn stored_version(store: &dyn Store, text: &str) -> Result<Version> {
+    let version: Version = text.parse()?;
+    match store.get_version(&version)? {
+        Some(_) => Ok(version),
+        None => Err(Error::UnknownVersion(text.to_string())),
+    }
+}
+
+/// The paths the scan of a version left out.
+fn stored_tombstones(store: &dyn Store, version: &Version) -> Result<Tombstones> {
+    match store.get_version(version)? {
+        Some(v) => Tombstones::from_tags(&v.tags),
+        None => Ok(Tombstones::new()),
+    }
+}
+
 /// Decode the command-line tags.  Tags should be of the form key=value, and multiple can be
 /// specified, terminated by the command.  It is also possible to specify --tag multiple times.
 fn decode_tags<'a, I>(tags: Option<I>) -> StoreTags
@@ -134,7 +1551,7 @@ where
 {
     match tags {
         None => BTreeMap::new(),
-        Some(tags) => tags.map(|x| decode_tag(x)).collect(),
+        Some(tags) => tags.map(decode_tag).collect(),
     }
 }
 
@@ -146,39 +1563,173 @@ fn decode_tag(tag: &str) -> (String, String) {
     (fields[0].to_string(), fields[1].to_string())
 }
 
-/// If the caller doesn't specify a 'name=' tag, generate one based on the current timestamp.
-/// Also will add a 'dir' attribute for where the tree was captured.
-fn add_name_tag<P: AsRef<Path>>(tags: &mut StoreTags, dir: P) {
+/// If the caller doesn't specify a 'name=' tag, generate one based on the given timestamp, or the
+/// current time.  Also will add a 'dir' attribute for where the tree was captured, listing each
+/// directory when there are several.
+fn add_name_tag(
+    tags: &mut StoreTags,
+    opt: &Opt,
+    store: &dyn Store,
+    time: Option<DateTime<Utc>>,
+) -> Result<()> {
     if !tags.contains_key("name") {
-        tags.insert("name".to_string(), Local::now().to_rfc3339());
+        let now = time.unwrap_or_else(|| clock::default_clock().now());
+        tags.insert("name".to_string(), rsure::version_name(store, now)?);
     }
 
     if !tags.contains_key("dir") {
-        tags.insert(
-            "dir".to_string(),
-            dir.as_ref()
-                .canonicalize()
+        let canonical = |dir: &Path| {
+            dir.canonicalize()
                 .unwrap_or_else(|_| Path::new("invalid").to_owned())
                 .to_string_lossy()
-                .into_owned(),
-        );
+                .into_owned()
+        };
+        let dir = match &opt.roots {
+            Some(roots) => roots
+                .iter()
+                .map(|(_, dir)| canonical(dir))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => canonical(&opt.dir),
+        };
+        tags.insert("dir".to_string(), dir);
     }
+    Ok(())
 }
 
-fn dump_versions(versions: &[StoreVersion]) {
-    println!("vers | Time captured       | name");
-    println!("-----+---------------------+------------------");
+fn dump_versions(versions: &[StoreVersion], verbose: bool) -> Result<()> {
+    if verbose {
+        println!("vers | Time captured       | changes              | digest       | name");
+        println!(
+            "-----+---------------------+----------------------+--------------+------------------"
+        );
+    } else {
+        println!("vers | Time captured       | name");
+        println!("-----+---------------------+------------------");
+    }
     for v in versions {
         let vers = match v.version {
             Version::Latest => "tip",
             Version::Prior => "prev",
-            Version::Tagged(ref v) => v,
+            Version::Tagged(ref v) | Version::Named(ref v) => v,
+        };
+        let time = v.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        if verbose {
+            let changes = match ChangeSummary::from_tags(&v.tags)? {
+                Some(summary) => summary.to_string(),
+                None => String::new(),
+            };
+            // Enough of the digest to tell versions apart at a glance.
+            let digest = v.tags.get(DIGEST_TAG).map_or("", |d| &d[..d.len().min(12)]);
+            println!(
+                "{:>4} | {} | {:20} | {:12} | {}",
+                vers, time, changes, digest, v.name
+            );
+        } else {
+            println!("{:>4} | {} | {}", vers, time, v.name);
+        }
+    }
+    Ok(())
+}
+
+/// Show the sizes of the store, and of each of its revisions.
+fn dump_stats(store: &dyn Store, file: &str) -> Result<()> {
+    let stats = store.weave_stats()?;
+    let mut nodes = 0;
+    for node in store.load_iter(Version::Latest)? {
+        let node = node?;
+        if node.is_enter() || node.is_file() {
+            nodes += 1;
+        }
+    }
+    let ratio = stats.plain as f64 / stats.stored.max(1) as f64;
+
+    println!("Store:         {}", file);
+    println!("Stored size:   {}", humanize(stats.stored).trim());
+    println!(
+        "Uncompressed:  {} ({:.1} times as large)",
+        humanize(stats.plain).trim(),
+        ratio
+    );
+    println!("Weave lines:   {}", stats.lines);
+    println!("Latest nodes:  {}", nodes);
+    println!();
+    println!("vers | Time captured       |    lines | name");
+    println!("-----+---------------------+----------+------------------");
+    for v in store.get_versions()? {
+        let lines = v
+            .version
+            .numeric()
+            .and_then(|n| stats.delta_lines.get(&n))
+            .copied()
+            .unwrap_or(0);
+        let time = v.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+        let vers = v.version.to_string();
+        println!("{:>4} | {} | {:>8} | {}", vers, time, lines, v.name);
+    }
+    Ok(())
+}
+
+fn dump_matches(matches: &[VersionMatch]) {
+    if matches.is_empty() {
+        println!("No revision has this file");
+        return;
+    }
+    println!("vers | Time captured       | content | attributes");
+    println!("-----+---------------------+---------+------------------");
+    for m in matches {
+        let vers = match m.version.version {
+            Version::Latest => "tip",
+            Version::Prior => "prev",
+            Version::Tagged(ref v) | Version::Named(ref v) => v,
+        };
+        let content = if m.content { "same" } else { "differs" };
+        let atts = if m.attrs_changed.is_empty() {
+            "same".to_string()
+        } else {
+            m.attrs_changed.join(",")
         };
         println!(
-            "{:>4} | {} | {}",
+            "{:>4} | {} | {:7} | {}",
             vers,
-            v.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
-            v.name
+            m.version
+                .time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            content,
+            atts
+        );
+    }
+}
+
+fn dump_daemon_status(status: &daemon::DaemonStatus) {
+    if let (Some(pid), Some(updated)) = (status.pid, status.updated) {
+        println!(
+            "daemon pid {}, status at {}",
+            pid,
+            updated.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    println!("profile          | Last run            | Next run            | result");
+    println!("-----------------+---------------------+---------------------+------------------");
+    for (name, p) in &status.profiles {
+        let last = match p.last_run {
+            Some(t) => t
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            None => "never".to_string(),
+        };
+        let result = match &p.activity {
+            Some(a) => format!("{} {}/{} files", a.phase.name(), a.files, a.total_files),
+            None => p.last_error.clone().unwrap_or_else(|| "ok".to_string()),
+        };
+        println!(
+            "{:16} | {:19} | {} | {}",
+            name,
+            last,
+            p.next_run.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            result
         );
     }
 }
@@ -0,0 +1,283 @@
+//! Checking a tree against a checksum manifest.
+//!
+//! A manifest is the output of `sha256sum`, `sha1sum` or `b3sum`, a line of
+//! `<hash>  <path>` for each file, or of their `--tag` option, lines of
+//! `SHA256 (<path>) = <hash>`.  Paths are relative to the top of the tree,
+//! and a line starting with a backslash has its path escaped, as the tools
+//! write names with a backslash or newline.
+//!
+//! Only regular files are compared, as a manifest has nothing to say about
+//! directories or links.  A file in the manifest and not the tree is
+//! reported as removed, one in the tree and not the manifest as added, and
+//! one whose content differs as modified, in its hash attribute.
+
+use crate::{
+    node::into_tracker, platform::os_from_bytes, Change, ChangeAction, ChangeSummary, Error,
+    HashAlgorithm, Result, SureNode,
+};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// The hashes of a manifest's files, by their path within the tree.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    algorithm: HashAlgorithm,
+    sums: BTreeMap<PathBuf, String>,
+}
+
+impl Manifest {
+    /// Read the manifest in the named file.  See `read`.
+    pub fn load<P: AsRef<Path>>(path: P, algorithm: Option<HashAlgorithm>) -> Result<Manifest> {
+        Manifest::read(BufReader::new(File::open(path)?), algorithm)
+    }
+
+    /// Read a manifest.  Hashes are taken to be of `algorithm`.  Without
+    /// one, it is named by the tagged lines, or, for the untagged, is sha1
+    /// for 40 digits, and sha256 for 64, so `b3sum` manifests need it to be
+    /// given.
+    pub fn read<R: BufRead>(input: R, algorithm: Option<HashAlgorithm>) -> Result<Manifest> {
+        let mut algorithm = algorithm;
+        let mut sums = BTreeMap::new();
+        for (number, line) in input.split(b'\n').enumerate() {
+            let mut line = line?;
+            let bad = |why: &str| Error::Manifest(format!("line {}: {}", number + 1, why));
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let escaped = line[0] == b'\\';
+            let line = if escaped { &line[1..] } else { &line[..] };
+            let Line { tag, hash, path } =
+                split_line(line).ok_or_else(|| bad("not a checksum line"))?;
+            let path = if escaped {
+                unescape(path).ok_or_else(|| bad("invalid escape"))?
+            } else {
+                path.to_vec()
+            };
+            let hash = String::from_utf8_lossy(hash).to_ascii_lowercase();
+
+            let named = match tag {
+                Some(tag) => Some(
+                    String::from_utf8_lossy(tag)
+                        .to_ascii_lowercase()
+                        .parse::<HashAlgorithm>()?,
+                ),
+                None => match hash.len() {
+                    40 => Some(HashAlgorithm::Sha1),
+                    64 => Some(HashAlgorithm::Sha256),
+                    _ => None,
+                },
+            };
+            let found = match (algorithm, named) {
+                (Some(a), Some(n)) if tag.is_some() && a != n => {
+                    return Err(bad(&format!("a {} hash, in a {} manifest", n, a)))
+                }
+                (Some(a), _) => a,
+                (None, Some(n)) => n,
+                (None, None) => return Err(bad("unknown hash")),
+            };
+            algorithm = Some(found);
+            match HEXLOWER_PERMISSIVE.decode(hash.as_bytes()) {
+                Ok(bytes) if bytes.len() == found.size() => (),
+                _ => return Err(bad(&format!("invalid {} hash", found))),
+            }
+
+            let path = normalize(&path).ok_or_else(|| bad("path outside of the tree"))?;
+            sums.insert(path, hash);
+        }
+
+        Ok(Manifest {
+            algorithm: algorithm.unwrap_or_default(),
+            sums,
+        })
+    }
+
+    /// The algorithm of the manifest's hashes.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// The number of files in the manifest.
+    pub fn len(&self) -> usize {
+        self.sums.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sums.is_empty()
+    }
+
+    /// Compare the regular files of a tree, which must have hashes of the
+    /// manifest's algorithm, with the manifest, giving each difference to
+    /// `visit`, in path order.  `dir` is prefixed to the paths, and the
+    /// number of each kind of change returned, as for `compare_trees`.
+    pub fn check<I, F>(&self, nodes: I, dir: &Path, mut visit: F) -> Result<ChangeSummary>
+    where
+        I: Iterator<Item = Result<SureNode>>,
+        F: FnMut(Change),
+    {
+        let name = self.algorithm.name();
+        let mut changes = vec![];
+        let mut seen = vec![];
+        let mut unchanged = 0;
+        // The manifest doesn't know the size of the files it lists, so only
+        // those in the tree count.
+        let mut bytes = 0;
+        for node in into_tracker(nodes, Path::new("")) {
+            let node = node?;
+            let (atts, path) = match (&node.node, node.path) {
+                (SureNode::File { atts, .. }, Some(path)) => (atts, path),
+                _ => continue,
+            };
+            let kind = atts.get("kind").map(|k| k.as_str()).unwrap_or("file");
+            let (action, changed) = match self.sums.get(&path) {
+                None if kind != "file" => continue,
+                None => (ChangeAction::Added, vec![]),
+                Some(_) if kind != "file" => (ChangeAction::Modified, vec!["kind".to_string()]),
+                Some(hash) if atts.get(name) == Some(hash) => {
+                    seen.push(path);
+                    unchanged += 1;
+                    continue;
+                }
+                Some(_) => (ChangeAction::Modified, vec![name.to_string()]),
+            };
+            if action != ChangeAction::Added {
+                seen.push(path.clone());
+            }
+            bytes += atts
+                .get("size")
+                .and_then(|size| size.parse::<u64>().ok())
+                .unwrap_or(0);
+            changes.push(change(dir, path, kind, action, changed));
+        }
+
+        seen.sort();
+        for path in self.sums.keys() {
+            if seen.binary_search(path).is_err() {
+                changes.push(change(
+                    dir,
+                    path.clone(),
+                    "file",
+                    ChangeAction::Removed,
+                    vec![],
+                ));
+            }
+        }
+
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut summary = ChangeSummary {
+            unchanged,
+            bytes,
+            ..Default::default()
+        };
+        for change in changes {
+            summary.add(&change);
+            visit(change);
+        }
+        Ok(summary)
+    }
+}
+
+fn change(
+    dir: &Path,
+    path: PathBuf,
+    kind: &str,
+    action: ChangeAction,
+    attrs_changed: Vec<String>,
+) -> Change {
+    Change {
+        path: dir.join(path),
+        kind: kind.to_string(),
+        action,
+        attrs_changed,
+        rule: None,
+        from: None,
+    }
+}
+
+/// The parts of a line of a manifest.
+struct Line<'a> {
+    /// The name of the algorithm, in the tagged form.
+    tag: Option<&'a [u8]>,
+    hash: &'a [u8],
+    path: &'a [u8],
+}
+
+fn split_line(line: &[u8]) -> Option<Line<'_>> {
+    // "SHA256 (path) = hash", where the path may itself hold ") = ".
+    if let Some(open) = find(line, b" (") {
+        let tag = &line[..open];
+        if !tag.is_empty() && tag.iter().all(|c| c.is_ascii_alphanumeric()) {
+            let close = rfind(line, b") = ")?;
+            if close < open + 2 {
+                return None;
+            }
+            return Some(Line {
+                tag: Some(tag),
+                hash: &line[close + 4..],
+                path: &line[open + 2..close],
+            });
+        }
+    }
+
+    // "hash  path", or "hash *path" for one read in binary mode.
+    let space = line.iter().position(|&c| c == b' ')?;
+    match line.get(space + 1) {
+        Some(b' ') | Some(b'*') if space + 2 < line.len() => Some(Line {
+            tag: None,
+            hash: &line[..space],
+            path: &line[space + 2..],
+        }),
+        _ => None,
+    }
+}
+
+fn find(text: &[u8], pat: &[u8]) -> Option<usize> {
+    text.windows(pat.len()).position(|w| w == pat)
+}
+
+fn rfind(text: &[u8], pat: &[u8]) -> Option<usize> {
+    text.windows(pat.len()).rposition(|w| w == pat)
+}
+
+/// Undo the tools' escaping of a backslash and newline.
+fn unescape(path: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(path.len());
+    let mut bytes = path.iter();
+    while let Some(&ch) = bytes.next() {
+        if ch != b'\\' {
+            result.push(ch);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'\\') => result.push(b'\\'),
+            Some(b'n') => result.push(b'\n'),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+/// The path, relative to the top of the tree, without any "." components.
+fn normalize(path: &[u8]) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for part in path.split(|&c| c == b'/') {
+        match part {
+            b"" | b"." => (),
+            b".." => return None,
+            part => result.push(os_from_bytes(part.to_vec())),
+        }
+    }
+    if result.as_os_str().is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
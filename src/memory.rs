@@ -0,0 +1,161 @@
+//! Keeping an update's memory use under a limit.
+//!
+//! Left alone, an update sizes its buffers for speed: a hashing thread per
+//! CPU, each with a large read buffer when BLAKE3 is in use, sqlite's
+//! default page cache, and all of the hash results collected in a single
+//! transaction.  On a small VM or NAS box this can be more than the machine
+//! can spare.  A `MemoryLimit` trades some of that speed for a bounded
+//! footprint.  Part of the limit goes to the sqlite cache, and the rest to
+//! the hashing threads.  Read buffers shrink first, then fewer files are
+//! hashed at once, and the hash results are committed in batches sized to
+//! fit the cache, so sqlite spills them to disk early rather than holding
+//! them in memory.  Without sqlite, the same batch sizes the runs the
+//! hash results are sorted in.
+
+use crate::{hashes, node::uring, Error, HashAlgorithm, Result};
+use log::info;
+use std::{fmt, str::FromStr};
+
+/// An upper bound, in bytes, on the memory used by the buffers of an
+/// update.  It parses from a number with an optional `K`, `M` or `G`
+/// suffix, such as "256M".
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryLimit(u64);
+
+impl MemoryLimit {
+    pub fn new(bytes: u64) -> MemoryLimit {
+        MemoryLimit(bytes)
+    }
+
+    pub fn bytes(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for MemoryLimit {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<MemoryLimit> {
+        let bad = || Error::InvalidSize(text.to_string());
+        let trimmed = text.trim();
+        let (digits, scale) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some('K') => (&trimmed[..trimmed.len() - 1], 1 << 10),
+            Some('M') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+            Some('G') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+            _ => (trimmed, 1),
+        };
+        let count: u64 = digits.parse().map_err(|_| bad())?;
+        count.checked_mul(scale).map(MemoryLimit).ok_or_else(bad)
+    }
+}
+
+impl fmt::Display for MemoryLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            n if n != 0 && n % (1 << 30) == 0 => write!(f, "{}G", n >> 30),
+            n if n != 0 && n % (1 << 20) == 0 => write!(f, "{}M", n >> 20),
+            n if n != 0 && n % (1 << 10) == 0 => write!(f, "{}K", n >> 10),
+            n => write!(f, "{}", n),
+        }
+    }
+}
+
+/// The smallest read buffer to shrink to.
+const MIN_BUFFER: usize = 64 * 1024;
+
+/// Memory used by each hashing thread beyond its read buffer: the hasher
+/// state, the path, and its place in the channels.
+const WORKER_OVERHEAD: u64 = 64 * 1024;
+
+/// sqlite's default page cache, which a limit never grows.
+const DEFAULT_CACHE: u64 = 2 * 1024 * 1024;
+const MIN_CACHE: u64 = 128 * 1024;
+
+/// The approximate cache space taken by each hash result.
+const ROW_COST: u64 = 128;
+
+/// How the buffers of an update are sized.
+#[derive(Debug)]
+pub(crate) struct MemoryPlan {
+    /// How many files to hash at once.
+    pub workers: usize,
+    /// The read buffer for each file being hashed.
+    pub buffer: usize,
+    /// The capacity of the work and result channels.
+    pub queue: usize,
+    /// How many small files each thread may read at once with io_uring,
+    /// each into a buffer of up to `buffer` bytes.
+    pub reads: usize,
+    /// The sqlite page cache, in KiB, if it should be changed.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub cache_kib: Option<u64>,
+    /// Commit the hash results after this many, rather than all at once.
+    pub batch: Option<usize>,
+}
+
+impl MemoryPlan {
+    /// Size the buffers for hashing with the given algorithms, staying
+    /// within the limit if there is one, with at most `jobs` files hashed at
+    /// once, or one per CPU.
+    pub fn new(
+        limit: Option<MemoryLimit>,
+        algorithms: &[HashAlgorithm],
+        jobs: Option<usize>,
+    ) -> MemoryPlan {
+        let cpus = jobs.unwrap_or_else(num_cpus::get).max(1);
+        let buffer = hashes::buffer_size(algorithms);
+        let limit = match limit {
+            None => {
+                return MemoryPlan {
+                    workers: cpus,
+                    buffer,
+                    queue: cpus,
+                    reads: uring::BATCH,
+                    cache_kib: None,
+                    batch: None,
+                }
+            }
+            Some(limit) => limit.bytes(),
+        };
+
+        let cache = (limit / 4).clamp(MIN_CACHE, DEFAULT_CACHE);
+        let hashing = limit.saturating_sub(cache);
+
+        // Shrink the buffers before giving up threads.
+        let share = (hashing / cpus as u64).saturating_sub(WORKER_OVERHEAD);
+        let buffer = (share as usize).clamp(MIN_BUFFER.min(buffer), buffer);
+        let workers = (hashing / (buffer as u64 + WORKER_OVERHEAD)).clamp(1, cpus as u64) as usize;
+
+        // Whatever is left of each thread's share goes to batched reads.
+        let share = (hashing / workers as u64).saturating_sub(WORKER_OVERHEAD);
+        let reads = (share / buffer as u64).clamp(1, uring::BATCH as u64) as usize;
+
+        let plan = MemoryPlan {
+            workers,
+            buffer,
+            queue: workers,
+            reads,
+            cache_kib: Some(cache / 1024),
+            batch: Some((cache / ROW_COST) as usize),
+        };
+        info!(
+            "Memory limit {}: hashing {} files at once, with {}K buffers",
+            MemoryLimit(limit),
+            plan.workers,
+            plan.buffer / 1024
+        );
+        plan
+    }
+}
+
+#[test]
+fn test_parse_limits() {
+    assert_eq!("4096".parse::<MemoryLimit>().unwrap().bytes(), 4096);
+    assert_eq!("64k".parse::<MemoryLimit>().unwrap().bytes(), 64 << 10);
+    assert_eq!("256M".parse::<MemoryLimit>().unwrap().bytes(), 256 << 20);
+    assert_eq!("2G".parse::<MemoryLimit>().unwrap().bytes(), 2 << 30);
+    assert_eq!(MemoryLimit::new(256 << 20).to_string(), "256M");
+    assert!("".parse::<MemoryLimit>().is_err());
+    assert!("12X".parse::<MemoryLimit>().is_err());
+    assert!("99999999999G".parse::<MemoryLimit>().is_err());
+}
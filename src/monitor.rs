@@ -0,0 +1,115 @@
+//! Reporting what a long-running update is doing.
+//!
+//! An `Activity` is shared between an update and whoever is watching it.
+//! The update records which phase it is in, and how far hashing has
+//! progressed, and the watcher takes snapshots of this, for example to
+//! write to a status file that a monitoring agent can read.
+
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// The phases of an update.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Not running an update.
+    Idle,
+    /// Walking the filesystem.
+    Scanning,
+    /// Computing hashes of files.
+    Hashing,
+    /// Writing the new version to the store.
+    Writing,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Idle => "idle",
+            Phase::Scanning => "scanning",
+            Phase::Hashing => "hashing",
+            Phase::Writing => "writing",
+        }
+    }
+}
+
+/// A snapshot of an update's progress.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ActivityState {
+    pub phase: Phase,
+    /// Files and bytes hashed so far.
+    pub files: u64,
+    pub bytes: u64,
+    /// The estimated files and bytes needing hashes.
+    pub total_files: u64,
+    pub total_bytes: u64,
+}
+
+/// The shared progress of an update.
+pub struct Activity {
+    state: Mutex<ActivityState>,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity {
+            state: Mutex::new(ActivityState {
+                phase: Phase::Idle,
+                files: 0,
+                bytes: 0,
+                total_files: 0,
+                total_bytes: 0,
+            }),
+        }
+    }
+
+    /// Move to a new phase.  Entering scanning starts a new update, and
+    /// clears the counts.
+    pub fn set_phase(&self, phase: Phase) {
+        let mut state = self.state.lock().unwrap();
+        if phase == Phase::Scanning {
+            *state = ActivityState {
+                phase,
+                files: 0,
+                bytes: 0,
+                total_files: 0,
+                total_bytes: 0,
+            };
+        } else {
+            state.phase = phase;
+        }
+    }
+
+    /// Set the estimate of how much needs to be hashed.
+    pub fn set_totals(&self, files: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total_files = files;
+        state.total_bytes = bytes;
+    }
+
+    /// Add to the totals, when what needs to be hashed is found as the
+    /// hashing goes.
+    pub fn add_totals(&self, files: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total_files += files;
+        state.total_bytes += bytes;
+    }
+
+    /// Record some files having been hashed.
+    pub fn add(&self, files: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.files += files;
+        state.bytes += bytes;
+    }
+
+    /// Retrieve the current state.
+    pub fn snapshot(&self) -> ActivityState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Activity {
+        Activity::new()
+    }
+}
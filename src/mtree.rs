@@ -0,0 +1,398 @@
+//! BSD mtree(8) specifications.
+//!
+//! These are written in the hierarchical form `mtree -c` produces, each directory entered by its
+//! line, and left with "..".  Either form is read: the hierarchical one, or the one with a full
+//! path on each line that libarchive writes, along with `/set` and `/unset` lines, and lines
+//! continued with a backslash.
+//!
+//! Only what both have a place for is carried over: the type, mode, owner, size, time, link
+//! target, device, and SHA-1 and SHA-256 hashes.  rsure's BLAKE3 hashes aren't written, as mtree
+//! has no keyword for them, and mtree's other hashes, and user and group names, aren't read.
+
+use crate::{
+    escape::{Escape, Unescape},
+    store::StoreVersion,
+    suretree::AttMap,
+    Error, HashAlgorithm, Result, SureNode,
+};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{BufRead, Write},
+};
+
+/// Write the nodes of a version as an mtree specification.
+pub(crate) fn write<I, W>(info: &StoreVersion, nodes: I, out: &mut W) -> Result<()>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    W: Write,
+{
+    writeln!(out, "#mtree")?;
+    if let Some(dir) = info.tags.get("dir") {
+        writeln!(out, "#\t   tree: {}", dir)?;
+    }
+    writeln!(out, "#\t   date: {}", info.time.to_rfc3339())?;
+    writeln!(
+        out,
+        "#\tversion: {} ({})",
+        info.version.numeric().unwrap_or(0),
+        info.name
+    )?;
+
+    // The names of the directories entered, in mtree's encoding.
+    let mut path: Vec<String> = vec![];
+    for node in nodes {
+        match node? {
+            SureNode::Enter { name, atts } => {
+                let name = if path.is_empty() {
+                    ".".to_string()
+                } else {
+                    vis(&unescape(&name)?)
+                };
+                path.push(name);
+                writeln!(out)?;
+                writeln!(out, "# {}", path.join("/"))?;
+                let name = path.last().unwrap();
+                writeln!(out, "{}{}", name, keywords(&atts)?)?;
+            }
+            SureNode::File { name, atts } => {
+                writeln!(out, "    {}{}", vis(&unescape(&name)?), keywords(&atts)?)?;
+            }
+            SureNode::Sep => (),
+            SureNode::Leave => {
+                path.pop();
+                // The root isn't left, as it wasn't entered by name.
+                if !path.is_empty() {
+                    writeln!(out, "# {}", path.join("/"))?;
+                    writeln!(out, "..")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The mtree names of rsure's kinds of node.
+const TYPES: &[(&str, &str)] = &[
+    ("dir", "dir"),
+    ("file", "file"),
+    ("lnk", "link"),
+    ("fifo", "fifo"),
+    ("sock", "socket"),
+    ("chr", "char"),
+    ("blk", "block"),
+];
+
+/// The mtree keywords for the attributes of a node, each preceded by a space.
+fn keywords(atts: &AttMap) -> Result<String> {
+    let mut text = String::new();
+    let mut add = |key: &str, value: &str| {
+        text.push(' ');
+        text.push_str(key);
+        text.push('=');
+        text.push_str(value);
+    };
+
+    let kind = atts.get("kind").map(|k| k.as_str()).unwrap_or("file");
+    let kind = TYPES
+        .iter()
+        .find(|(ours, _)| *ours == kind)
+        .map_or("file", |(_, theirs)| theirs);
+    add("type", kind);
+    if let Some(perm) = atts.get("perm").and_then(|p| p.parse::<u32>().ok()) {
+        add("mode", &format!("{:04o}", perm & 0o7777));
+    }
+    for key in &["uid", "gid", "nlink", "size"] {
+        if let Some(value) = atts.get(*key) {
+            add(key, value);
+        }
+    }
+    if let Some(mtime) = atts.get("mtime") {
+        add("time", &format!("{}.000000000", mtime));
+    }
+    if let Some(targ) = atts.get("targ") {
+        add("link", &vis(&unescape(targ)?));
+    }
+    if let (Some(major), Some(minor)) = (atts.get("devmaj"), atts.get("devmin")) {
+        add("device", &format!("native,{},{}", major, minor));
+    }
+    if let Some(sha1) = atts.get("sha1") {
+        add("sha1digest", sha1);
+    }
+    if let Some(sha256) = atts.get("sha256") {
+        add("sha256digest", sha256);
+    }
+    Ok(text)
+}
+
+/// A directory of a specification being read.
+#[derive(Default)]
+struct Dir {
+    atts: Option<AttMap>,
+    dirs: BTreeMap<Vec<u8>, Dir>,
+    files: BTreeMap<Vec<u8>, AttMap>,
+}
+
+impl Dir {
+    /// Add a node at the given path, creating the directories above it that weren't given.
+    fn insert(&mut self, path: &[Vec<u8>], atts: AttMap) {
+        match path.split_first() {
+            None => self.atts = Some(atts),
+            Some((name, [])) if atts.get("kind").map(|k| k.as_str()) == Some("dir") => {
+                self.files.remove(name);
+                self.dirs.entry(name.clone()).or_default().atts = Some(atts);
+            }
+            Some((name, [])) => {
+                self.dirs.remove(name);
+                self.files.insert(name.clone(), atts);
+            }
+            Some((name, rest)) => self
+                .dirs
+                .entry(name.clone())
+                .or_default()
+                .insert(rest, atts),
+        }
+    }
+
+    /// The nodes of the directory, in the order rsure writes them.
+    fn nodes(self, name: String, nodes: &mut Vec<SureNode>) {
+        let atts = self.atts.unwrap_or_else(|| {
+            let mut atts = AttMap::new();
+            atts.insert("kind".to_string(), "dir".to_string());
+            atts
+        });
+        nodes.push(SureNode::Enter { name, atts });
+        for (name, dir) in self.dirs {
+            dir.nodes(name.escaped(), nodes);
+        }
+        nodes.push(SureNode::Sep);
+        for (name, atts) in self.files {
+            nodes.push(SureNode::File {
+                name: name.escaped(),
+                atts,
+            });
+        }
+        nodes.push(SureNode::Leave);
+    }
+}
+
+/// Read an mtree specification, returning its nodes, in the order rsure writes them, and the
+/// hash algorithms found in it.
+pub(crate) fn read<R: BufRead>(input: R) -> Result<(Vec<SureNode>, Vec<HashAlgorithm>)> {
+    let mut root = Dir::default();
+    // The defaults given by "/set".
+    let mut defaults = BTreeMap::new();
+    // The directory the hierarchical form is in.
+    let mut cwd: Vec<Vec<u8>> = vec![];
+    let mut algorithms = vec![];
+
+    let mut lines = input.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let bad = |why: &str| Error::Mtree(format!("line {}: {}", number + 1, why));
+        let mut line = line?;
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some((_, next)) => line.push_str(&next?),
+                None => return Err(bad("continued past the end")),
+            }
+        }
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            None => continue,
+            Some(word) if word.starts_with('#') => continue,
+            Some(word) => word,
+        };
+
+        match name {
+            "/set" => {
+                for word in words {
+                    if let Some((key, value)) = word.split_once('=') {
+                        defaults.insert(key.to_string(), value.to_string());
+                    }
+                }
+                continue;
+            }
+            "/unset" => {
+                for word in words {
+                    if word == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(word);
+                    }
+                }
+                continue;
+            }
+            ".." => {
+                cwd.pop();
+                continue;
+            }
+            name if name.starts_with('/') => return Err(bad("unknown command")),
+            _ => (),
+        }
+
+        let mut keys = defaults.clone();
+        for word in words {
+            match word.split_once('=') {
+                Some((key, value)) => keys.insert(key.to_string(), value.to_string()),
+                // Flags, such as "optional" or "nochange".
+                None => keys.insert(word.to_string(), String::new()),
+            };
+        }
+        let atts = to_atts(&keys, &mut algorithms).map_err(|why| bad(&why))?;
+
+        let name = unvis(name).ok_or_else(|| bad("invalid name"))?;
+        let full = name.contains(&b'/');
+        let mut path = if full { vec![] } else { cwd.clone() };
+        for part in name.split(|&b| b == b'/') {
+            match part {
+                b"" | b"." => (),
+                b".." => return Err(bad("\"..\" in a path")),
+                part => path.push(part.to_vec()),
+            }
+        }
+        let is_dir = atts["kind"] == "dir";
+        root.insert(&path, atts);
+        if is_dir && !full {
+            cwd = path;
+        }
+    }
+
+    let mut nodes = vec![];
+    root.nodes("__root__".to_string(), &mut nodes);
+    algorithms.sort();
+    Ok((nodes, algorithms))
+}
+
+/// The rsure attributes for the keywords of an entry.
+fn to_atts(
+    keys: &BTreeMap<String, String>,
+    algorithms: &mut Vec<HashAlgorithm>,
+) -> std::result::Result<AttMap, String> {
+    let mut atts = AttMap::new();
+    let kind = keys.get("type").map(|t| t.as_str()).unwrap_or("file");
+    let kind = TYPES
+        .iter()
+        .find(|(_, theirs)| *theirs == kind)
+        .map(|(ours, _)| *ours)
+        .ok_or_else(|| format!("unknown type {:?}", kind))?;
+    atts.insert("kind".to_string(), kind.to_string());
+
+    let number = |key: &str, value: &str| {
+        value
+            .parse::<u64>()
+            .map(|n| n.to_string())
+            .map_err(|_| format!("invalid {} {:?}", key, value))
+    };
+    for (key, value) in keys {
+        match key.as_str() {
+            "mode" => {
+                let mode = u32::from_str_radix(value, 8)
+                    .map_err(|_| format!("invalid mode {:?}, expect octal", value))?;
+                atts.insert("perm".to_string(), (mode & 0o7777).to_string());
+            }
+            "uid" | "gid" | "nlink" => {
+                atts.insert(key.clone(), number(key, value)?);
+            }
+            // Only files have a size in rsure.
+            "size" if kind == "file" => {
+                atts.insert(key.clone(), number(key, value)?);
+            }
+            "time" => {
+                let secs = value.split('.').next().unwrap_or("");
+                atts.insert("mtime".to_string(), number(key, secs)?);
+            }
+            "link" => {
+                let target = unvis(value).ok_or_else(|| format!("invalid link {:?}", value))?;
+                atts.insert("targ".to_string(), target.escaped());
+            }
+            "device" => {
+                let parts: Vec<_> = value.split(',').collect();
+                if parts.len() < 3 {
+                    return Err(format!(
+                        "invalid device {:?}, expect format,major,minor",
+                        value
+                    ));
+                }
+                atts.insert("devmaj".to_string(), number(key, parts[1])?);
+                atts.insert("devmin".to_string(), number(key, parts[2])?);
+            }
+            "sha1" | "sha1digest" => add_hash(&mut atts, algorithms, HashAlgorithm::Sha1, value)?,
+            "sha256" | "sha256digest" => {
+                add_hash(&mut atts, algorithms, HashAlgorithm::Sha256, value)?
+            }
+            _ => (),
+        }
+    }
+    Ok(atts)
+}
+
+fn add_hash(
+    atts: &mut AttMap,
+    algorithms: &mut Vec<HashAlgorithm>,
+    algorithm: HashAlgorithm,
+    value: &str,
+) -> std::result::Result<(), String> {
+    let value = value.to_ascii_lowercase();
+    if value.len() != algorithm.size() * 2 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid {} {:?}", algorithm, value));
+    }
+    atts.insert(algorithm.name().to_string(), value);
+    if !algorithms.contains(&algorithm) {
+        algorithms.push(algorithm);
+    }
+    Ok(())
+}
+
+pub(crate) fn unescape(text: &str) -> Result<Vec<u8>> {
+    text.unescape()
+        .map_err(|_| Error::InvalidEscape(text.to_string()))
+}
+
+/// Encode a name as mtree does, with vis(3): anything other than printable ASCII, and the
+/// characters mtree gives a meaning to, as a backslash and three octal digits.
+fn vis(name: &[u8]) -> String {
+    let mut text = String::with_capacity(name.len());
+    for &ch in name {
+        if (b'!'..=b'~').contains(&ch) && !b"\\#*?[".contains(&ch) {
+            text.push(ch as char);
+        } else {
+            text.push_str(&format!("\\{:03o}", ch));
+        }
+    }
+    text
+}
+
+/// Decode a name encoded with vis(3), as octal escapes, or the C style escapes of a single
+/// character.
+fn unvis(text: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(ch) = bytes.next() {
+        if ch != b'\\' {
+            result.push(ch);
+            continue;
+        }
+        let ch = match bytes.next()? {
+            d @ b'0'..=b'7' => {
+                let mut value = u32::from(d - b'0');
+                for _ in 0..2 {
+                    match bytes.next()? {
+                        d @ b'0'..=b'7' => value = value * 8 + u32::from(d - b'0'),
+                        _ => return None,
+                    }
+                }
+                u8::try_from(value).ok()?
+            }
+            b's' => b' ',
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b'\\' => b'\\',
+            b'#' => b'#',
+            _ => return None,
+        };
+        result.push(ch);
+    }
+    Some(result)
+}
@@ -4,7 +4,7 @@
 //! representations as iterators across SureNodes instead of keeping an
 //! entire tree in memory, we can process larger filesystem trees, using
 //! temporary space on the hard disk instead of using memory.
-use crate::{suretree::AttMap, Error, Result};
+use crate::{suretree::AttMap, Error, HashAlgorithm, Result};
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use std::{
     fs::File,
@@ -14,13 +14,28 @@ use std::{
 use weave::NamingConvention;
 
 mod compare;
+mod digest;
 pub mod fs;
 mod fullpath;
+mod hashbuf;
 mod hashes;
-
-pub use compare::compare_trees;
+mod listed;
+mod rollup;
+mod subtree;
+pub(crate) mod uring;
+
+pub use compare::{
+    compare_trees, compare_trees_with, compare_trees_with_renames, compare_trees_with_rules,
+    Change, ChangeAction, ChangeSummary, CHANGES_TAG,
+};
+pub(crate) use digest::TreeDigest;
+pub use digest::{tree_digest, DIGEST_TAG};
 pub use fullpath::into_tracker;
-pub use hashes::{HashCombiner, HashUpdater, Source};
+pub use hashes::{HashCombiner, HashMerger, HashPool, HashUpdater, MergeIter, Source};
+pub use listed::PathList;
+pub(crate) use rollup::Rollups;
+pub use rollup::{rollup, ROLLUP_ATT};
+pub use subtree::{compare_dir, subtree, Subtree};
 
 #[derive(Clone, Debug)]
 pub enum SureNode {
@@ -54,22 +69,49 @@ impl SureNode {
         matches!(self, SureNode::Sep)
     }
 
-    pub fn needs_hash(&self) -> bool {
+    /// Is this node missing a hash from any of the given algorithms?  This
+    /// includes symlinks to regular files, when their targets are hashed.
+    pub fn needs_hash(&self, algorithms: &[HashAlgorithm]) -> bool {
         match self {
-            SureNode::File { atts, .. } => atts["kind"] == "file" && !atts.contains_key("sha1"),
+            SureNode::File { atts, .. } => {
+                let hashed = match atts["kind"].as_str() {
+                    "file" => true,
+                    "lnk" => atts.contains_key("tsize"),
+                    _ => false,
+                };
+                hashed && algorithms.iter().any(|a| !atts.contains_key(a.name()))
+            }
             _ => false,
         }
     }
 
+    /// The size of what is hashed for this node: a regular file, or the
+    /// target of a symlink.
     pub fn size(&self) -> u64 {
         match self {
-            SureNode::File { atts, .. } => {
-                atts.get("size").map(|x| x.parse().unwrap()).unwrap_or(0)
-            }
+            SureNode::File { atts, .. } => atts
+                .get("size")
+                .or_else(|| atts.get("tsize"))
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(0),
             _ => 0,
         }
     }
 
+    /// The bytes of data read to hash this node: its size, or for a sparse
+    /// file, just what is allocated to it, as its holes read as zeros
+    /// without touching the disk.  This is what the progress of hashing is
+    /// estimated with.
+    pub fn data_size(&self) -> u64 {
+        let size = self.size();
+        match self.atts().and_then(|atts| atts.get("blocks")) {
+            Some(blocks) => blocks
+                .parse::<u64>()
+                .map_or(size, |blocks| size.min(blocks.saturating_mul(512))),
+            None => size,
+        }
+    }
+
     /// Get the name of this node.  Panics if the node type does not have
     /// an associated name.
     pub fn name(&self) -> &str {
@@ -168,6 +210,7 @@ where
 /// For pushed based writing, we can also write using a NodeWriter.
 pub struct NodeWriter<W: Write> {
     writer: BufWriter<W>,
+    normalize: bool,
 }
 
 impl<W: Write> NodeWriter<W> {
@@ -176,13 +219,39 @@ impl<W: Write> NodeWriter<W> {
         writeln!(&mut wr, "asure-2.0")?;
         writeln!(&mut wr, "-----")?;
 
-        Ok(NodeWriter { writer: wr })
+        Ok(NodeWriter {
+            writer: wr,
+            normalize: false,
+        })
+    }
+
+    /// Normalize volatile attributes (see `normalize_volatile`) of every
+    /// node written.
+    pub fn normalized(mut self) -> NodeWriter<W> {
+        self.normalize = true;
+        self
     }
 
     pub fn write_node(&mut self, node: &SureNode) -> Result<()> {
+        if self.normalize && node.atts().is_some() {
+            let mut node = node.clone();
+            normalize_volatile(&mut node);
+            return self.write_raw(&node);
+        }
+        self.write_raw(node)
+    }
+
+    /// Flush what has been written, and return the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        self.writer
+            .into_inner()
+            .map_err(|e| Error::Io(e.into_error()))
+    }
+
+    fn write_raw(&mut self, node: &SureNode) -> Result<()> {
         match node {
-            SureNode::Enter { name, atts } => header(&mut self.writer, 'd', &name, &atts)?,
-            SureNode::File { name, atts } => header(&mut self.writer, 'f', &name, &atts)?,
+            SureNode::Enter { name, atts } => header(&mut self.writer, 'd', name, atts)?,
+            SureNode::File { name, atts } => header(&mut self.writer, 'f', name, atts)?,
             SureNode::Sep => writeln!(&mut self.writer, "-")?,
             SureNode::Leave => writeln!(&mut self.writer, "u")?,
         }
@@ -190,6 +259,89 @@ impl<W: Write> NodeWriter<W> {
     }
 }
 
+/// Something that watches the nodes as they are written, such as to gather
+/// statistics, or an index, in the same pass as the write.
+pub trait NodeObserver: Send {
+    /// Called with each node, in order, before it is written.  An error
+    /// stops the write.
+    fn observe(&mut self, node: &SureNode) -> Result<()>;
+
+    /// Called once every node has been written, and, for an update, the
+    /// new version committed to the store.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> NodeObserver for F
+where
+    F: FnMut(&SureNode) -> Result<()> + Send,
+{
+    fn observe(&mut self, node: &SureNode) -> Result<()> {
+        self(node)
+    }
+}
+
+/// A NodeWriter that also gives each node written to a set of observers.
+pub struct NodeTee<'a, W: Write> {
+    writer: NodeWriter<W>,
+    observers: Vec<&'a mut dyn NodeObserver>,
+}
+
+impl<'a, W: Write> NodeTee<'a, W> {
+    pub fn new(writer: NodeWriter<W>) -> NodeTee<'a, W> {
+        NodeTee {
+            writer,
+            observers: vec![],
+        }
+    }
+
+    pub fn with_observer(mut self, observer: &'a mut dyn NodeObserver) -> NodeTee<'a, W> {
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn write_node(&mut self, node: &SureNode) -> Result<()> {
+        for observer in &mut self.observers {
+            observer.observe(node)?;
+        }
+        self.writer.write_node(node)
+    }
+
+    /// Flush what has been written, and return the underlying writer.  The
+    /// observers aren't finished, as the caller may have more to do before
+    /// the nodes are really kept.
+    pub fn into_inner(self) -> Result<W> {
+        self.writer.into_inner()
+    }
+}
+
+/// The attribute of a directory listing the attributes its scan looked
+/// for that only some nodes have, such as "cap", so that a node without
+/// one is known to have none, rather than to have been scanned by a
+/// version of rsure that didn't record it.
+pub(crate) const RECORDED_ATT: &str = "recorded";
+
+/// The attributes that differ between two scans of an identical tree
+/// (and between a tree and a restore of it).  The blocks allocated to a
+/// file change as some filesystems, such as ZFS and btrfs, settle their
+/// writes, or compress them.
+pub(crate) const VOLATILE_ATTS: &[&str] = &["blocks", "btime", "ctime", "ino"];
+
+/// Zero out the volatile attributes of a node, so that scans of identical
+/// trees produce identical surefiles.  This is only done as the snapshot
+/// is written, as the hash updates rely on the real values to decide
+/// whether an old hash can be reused.
+pub fn normalize_volatile(node: &mut SureNode) {
+    if let Some(atts) = node.atts_mut() {
+        for att in VOLATILE_ATTS {
+            if let Some(value) = atts.get_mut(*att) {
+                *value = "0".to_string();
+            }
+        }
+    }
+}
+
 fn header<W: Write>(out: &mut W, kind: char, name: &str, atts: &AttMap) -> Result<()> {
     write!(out, "{}{} [", kind, name)?;
 
@@ -277,6 +429,14 @@ impl<R: Read> Iterator for ReadIterator<R> {
                 self.depth -= 1;
                 if self.depth == 0 {
                     self.done = true;
+                    // Read to the end, so that a reader checking the whole
+                    // stream sees all of it.  Anything after the tree is
+                    // ignored, as it always has been.
+                    for line in &mut self.lines {
+                        if let Err(e) = line {
+                            return Some(Err(Error::SureFileError(e)));
+                        }
+                    }
                 }
                 Some(Ok(SureNode::Leave))
             }
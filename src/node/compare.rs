@@ -1,16 +1,208 @@
 //! Compare two iterator-based trees.
+//!
+//! The differences are reported as `Change` values, passed to a callback as
+//! they are found.  Their `Display` gives the traditional textual report.
 
-use crate::{node::SureNode, Error, Result};
+use crate::{
+    exclude::Tombstones,
+    ignore::IgnoreRules,
+    node::{SureNode, RECORDED_ATT, ROLLUP_ATT},
+    progress::humanize,
+    Error, HashAlgorithm, Result, StoreTags,
+};
 use log::error;
-use std::{collections::HashSet, path::Path};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, mem,
+    path::{Path, PathBuf},
+};
+
+/// How a node differs between the old and new trees.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ChangeAction {
+    /// Only present in the new tree.
+    Added,
+    /// Only present in the old tree.
+    Removed,
+    /// Only present in the old tree, as the new scan left it out.
+    Excluded,
+    /// Present in both, with some attributes differing.
+    Modified,
+    /// Gone from one place in the old tree, and at another in the new one,
+    /// with the same size and hash.
+    Renamed,
+}
+
+/// A single difference between two trees.  An added or removed directory is
+/// reported once, not for each of its contents.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Change {
+    /// The node's path, within the directory given to `compare_trees`.
+    pub path: PathBuf,
+    /// The node's "kind" attribute, such as "file", "dir" or "lnk".
+    pub kind: String,
+    pub action: ChangeAction,
+    /// The names of the attributes that differ, in order.  Empty unless
+    /// the node was modified.
+    pub attrs_changed: Vec<String>,
+    /// The exclude pattern that left out an excluded node.
+    pub rule: Option<String>,
+    /// Where a renamed file was in the old tree.
+    pub from: Option<PathBuf>,
+}
+
+impl Change {
+    /// A change to the node at `path`, of the given kind, with no
+    /// attributes, rule or former path.
+    pub fn new<P: Into<PathBuf>>(path: P, kind: &str, action: ChangeAction) -> Change {
+        Change {
+            path: path.into(),
+            kind: kind.to_string(),
+            action,
+            attrs_changed: vec![],
+            rule: None,
+            from: None,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            ChangeAction::Added => write!(f, "+ {:22} {:?}", self.kind, self.path),
+            ChangeAction::Removed => write!(f, "- {:22} {:?}", self.kind, self.path),
+            ChangeAction::Excluded => write!(
+                f,
+                "x {:22} {:?} ({})",
+                self.kind,
+                self.path,
+                self.rule.as_deref().unwrap_or("")
+            ),
+            ChangeAction::Modified => write!(
+                f,
+                "  [{:<20}] {:?}",
+                self.attrs_changed.join(","),
+                self.path
+            ),
+            ChangeAction::Renamed => write!(
+                f,
+                "> {:22} {:?} (from {:?})",
+                self.kind,
+                self.path,
+                self.from.as_deref().unwrap_or_else(|| Path::new(""))
+            ),
+        }
+    }
+}
+
+/// The tag recording, on a version, how it changed from the one before.
+pub const CHANGES_TAG: &str = "changes";
+
+/// The number of each kind of change between two trees.  As with `Change`,
+/// an added or removed directory counts once.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChangeSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    #[serde(default)]
+    pub excluded: usize,
+    #[serde(default)]
+    pub renamed: usize,
+    /// The nodes in both trees that are the same.
+    #[serde(default)]
+    pub unchanged: usize,
+    /// The size of the files added, removed, or whose contents changed,
+    /// including those in directories added or removed.
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+impl ChangeSummary {
+    /// The number of changes, other than those excluded, which aren't
+    /// differences in the tree.
+    pub fn count(&self) -> usize {
+        self.added + self.removed + self.modified + self.renamed
+    }
+
+    pub fn add(&mut self, change: &Change) {
+        match change.action {
+            ChangeAction::Added => self.added += 1,
+            ChangeAction::Removed => self.removed += 1,
+            ChangeAction::Modified => self.modified += 1,
+            ChangeAction::Excluded => self.excluded += 1,
+            ChangeAction::Renamed => self.renamed += 1,
+        }
+    }
+
+    /// A line describing the changes, for the end of a report, such as "2
+    /// added, 1 removed, 1 modified, 12 unchanged, 4.000KiB affected".
+    pub fn describe(&self) -> String {
+        let mut parts = vec![
+            format!("{} added", self.added),
+            format!("{} removed", self.removed),
+            format!("{} modified", self.modified),
+        ];
+        if self.renamed > 0 {
+            parts.push(format!("{} renamed", self.renamed));
+        }
+        if self.excluded > 0 {
+            parts.push(format!("{} excluded", self.excluded));
+        }
+        parts.push(format!("{} unchanged", self.unchanged));
+        parts.push(format!("{} affected", humanize(self.bytes).trim()));
+        parts.join(", ")
+    }
+
+    /// Count the changes from an old tree to a new one.
+    pub fn between<IA, IB>(left: IA, right: IB, excluded: &Tombstones) -> Result<ChangeSummary>
+    where
+        IA: Iterator<Item = Result<SureNode>>,
+        IB: Iterator<Item = Result<SureNode>>,
+    {
+        compare_trees_with(left, right, ".", &[], excluded, |_| ())
+    }
+
+    /// The summary recorded in a version's tags, if it has one.  Versions
+    /// written before summaries were recorded, and first versions, don't.
+    pub fn from_tags(tags: &StoreTags) -> Result<Option<ChangeSummary>> {
+        match tags.get(CHANGES_TAG) {
+            None => Ok(None),
+            Some(text) => Ok(Some(serde_json::from_str(text)?)),
+        }
+    }
+
+    pub fn add_to_tags(&self, tags: &mut StoreTags) {
+        let text = serde_json::to_string(self).expect("summary serializes");
+        tags.insert(CHANGES_TAG.to_string(), text);
+    }
+}
+
+impl fmt::Display for ChangeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "+{} -{} ~{}", self.added, self.removed, self.modified)?;
+        if self.renamed > 0 {
+            write!(f, " >{}", self.renamed)?;
+        }
+        if self.excluded > 0 {
+            write!(f, " x{}", self.excluded)?;
+        }
+        Ok(())
+    }
+}
 
 /// This is the mutable state that is threaded through the recursive
 /// traversal of the two trees.
-struct State<IA, IB> {
+struct State<'a, IA, IB, F> {
     left: SureNode,
     right: SureNode,
     left_iter: IA,
     right_iter: IB,
+    on_change: F,
+    summary: ChangeSummary,
 
     // Track warning messages about added and deleted attributes.
     adds: HashSet<String>,
@@ -18,23 +210,151 @@ struct State<IA, IB> {
 
     // Attributes to be ignored
     ignore: HashSet<String>,
+    // And those ignored in just parts of the tree.
+    rules: &'a IgnoreRules,
+
+    // What the new tree left out, by path relative to `root`.
+    excluded: &'a Tombstones,
+    root: PathBuf,
+
+    // The changes held back to find renames, when looking for them.
+    renames: Option<Renames>,
+
+    // For each directory being walked, the attributes only some nodes have
+    // that both trees looked for in it.
+    recorded: Vec<Vec<String>>,
 }
 
-pub fn compare_trees<P: AsRef<Path>, IA, IB>(
-    mut left: IA,
-    mut right: IB,
+/// The changes of a comparison looking for renames, held until it is done,
+/// with the size and hashes of the files added and removed.
+#[derive(Default)]
+struct Renames {
+    /// The changes, with the bytes each affects.
+    changes: Vec<(Change, u64)>,
+    /// The changes removing files, by each of their keys, in order.
+    removed: HashMap<RenameKey, Vec<usize>>,
+    /// The changes adding files, with their keys.
+    added: Vec<(usize, Vec<RenameKey>)>,
+}
+
+/// A file's size, and the name and value of one of its hashes.
+type RenameKey = (String, &'static str, String);
+
+/// The hashes of a file's contents, which a rename can be found by.
+const HASHES: [HashAlgorithm; 3] = [
+    HashAlgorithm::Sha1,
+    HashAlgorithm::Sha256,
+    HashAlgorithm::Blake3,
+];
+
+/// Compare an old tree with a new one, calling `on_change` with each
+/// difference, in tree order.  `dir` is prefixed to the paths of the
+/// changes.  Attributes named in `ignore`, as well as "ctime", "ino" and
+/// the "rollup" of directories, are not compared.  An attribute only some
+/// nodes have, such as the "link" of a hardlinked file, coming or going is
+/// a change to it.  Returns the number of each kind of change.
+pub fn compare_trees<P: AsRef<Path>, IA, IB, F>(
+    left: IA,
+    right: IB,
+    dir: P,
+    ignore: &[&str],
+    on_change: F,
+) -> Result<ChangeSummary>
+where
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
+{
+    compare_trees_with(left, right, dir, ignore, &Tombstones::new(), on_change)
+}
+
+/// Compare trees, as `compare_trees`, where `excluded` is what the scan of
+/// the new tree left out.  Something only in the old tree that the new scan
+/// excluded is reported as `Excluded`, rather than `Removed`, so that
+/// changing the exclude patterns isn't mistaken for deleting files.
+pub fn compare_trees_with<P: AsRef<Path>, IA, IB, F>(
+    left: IA,
+    right: IB,
     dir: P,
     ignore: &[&str],
-) -> Result<()>
+    excluded: &Tombstones,
+    on_change: F,
+) -> Result<ChangeSummary>
+where
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
+{
+    let rules = IgnoreRules::everywhere(ignore);
+    compare_trees_with_rules(left, right, dir, &rules, excluded, on_change)
+}
+
+/// Compare trees, as `compare_trees_with`, ignoring the attributes the
+/// rules give for each path, such as those kept with the store.
+pub fn compare_trees_with_rules<P: AsRef<Path>, IA, IB, F>(
+    left: IA,
+    right: IB,
+    dir: P,
+    rules: &IgnoreRules,
+    excluded: &Tombstones,
+    on_change: F,
+) -> Result<ChangeSummary>
+where
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
+{
+    compare(left, right, dir.as_ref(), rules, excluded, false, on_change)
+}
+
+/// Compare trees, as `compare_trees_with_rules`, reporting a file gone from
+/// one place, and found at another with the same size and hash, as
+/// `Renamed`, rather than removed and added.  Empty files, which all have
+/// the same hash, aren't paired up, nor are the files of directories added
+/// or removed, which are reported as a whole.  As a file can't be known to
+/// be removed until the rest of the tree has been searched for it, the
+/// changes are all given, in order, once the comparison is done.
+pub fn compare_trees_with_renames<P: AsRef<Path>, IA, IB, F>(
+    left: IA,
+    right: IB,
+    dir: P,
+    rules: &IgnoreRules,
+    excluded: &Tombstones,
+    on_change: F,
+) -> Result<ChangeSummary>
 where
     IA: Iterator<Item = Result<SureNode>>,
     IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
 {
-    let mut ignore: HashSet<String> = ignore.iter().map(|x| (*x).to_owned()).collect();
+    compare(left, right, dir.as_ref(), rules, excluded, true, on_change)
+}
+
+fn compare<IA, IB, F>(
+    mut left: IA,
+    mut right: IB,
+    dir: &Path,
+    rules: &IgnoreRules,
+    excluded: &Tombstones,
+    renames: bool,
+    on_change: F,
+) -> Result<ChangeSummary>
+where
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
+{
+    let mut ignore = HashSet::new();
     // The ctime and ino will be different if a backup is restored, and we'd still like to get
     // meaningful results.  Add these to the list of ignored attributes.
     ignore.insert("ctime".to_owned());
     ignore.insert("ino".to_owned());
+    // Nor do the blocks allocated to a file stay put on all filesystems; its "sparse" is compared.
+    ignore.insert("blocks".to_owned());
+    // A change to a directory's rollup is a change to something in it, which is reported itself.
+    ignore.insert(ROLLUP_ATT.to_owned());
+    // What was looked for changes with the version of rsure, not the tree.
+    ignore.insert(RECORDED_ATT.to_owned());
 
     let ln = match left.next() {
         None => return Err(Error::EmptyLeftIterator),
@@ -51,19 +371,119 @@ where
         right: rn,
         left_iter: left,
         right_iter: right,
+        on_change,
+        summary: ChangeSummary::default(),
         adds: HashSet::new(),
         missings: HashSet::new(),
         ignore,
+        rules,
+        excluded,
+        root: dir.to_path_buf(),
+        renames: if renames {
+            Some(Renames::default())
+        } else {
+            None
+        },
+        recorded: vec![],
     };
 
-    state.walk_root(dir.as_ref())
+    state.walk_root(dir)?;
+    state.finish_renames();
+    Ok(state.summary)
 }
 
-impl<IA, IB> State<IA, IB>
+impl<'a, IA, IB, F> State<'a, IA, IB, F>
 where
     IA: Iterator<Item = Result<SureNode>>,
     IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
 {
+    /// Report a change, affecting `bytes` of file contents.
+    fn report(&mut self, change: Change, bytes: u64) {
+        match &mut self.renames {
+            Some(renames) => renames.changes.push((change, bytes)),
+            None => {
+                self.summary.add(&change);
+                self.summary.bytes += bytes;
+                (self.on_change)(change);
+            }
+        }
+    }
+
+    /// Report a file added or removed, which, when looking for renames,
+    /// may turn out to have been moved.
+    fn report_moved(&mut self, change: Change, bytes: u64, keys: Vec<RenameKey>) {
+        if let Some(renames) = &mut self.renames {
+            let index = renames.changes.len();
+            match change.action {
+                ChangeAction::Added => renames.added.push((index, keys)),
+                _ => {
+                    for key in keys {
+                        renames.removed.entry(key).or_default().push(index);
+                    }
+                }
+            }
+        }
+        self.report(change, bytes);
+    }
+
+    /// Pair up the files removed and added with the same size and hash, in
+    /// order, and give the changes held back.
+    fn finish_renames(&mut self) {
+        let mut renames = match self.renames.take() {
+            Some(renames) => renames,
+            None => return,
+        };
+        let mut from = vec![None; renames.changes.len()];
+        let mut moved = vec![false; renames.changes.len()];
+        for (index, keys) in &renames.added {
+            let removed = keys.iter().find_map(|key| {
+                renames
+                    .removed
+                    .get(key)
+                    .and_then(|removed| removed.iter().copied().find(|&removed| !moved[removed]))
+            });
+            if let Some(removed) = removed {
+                moved[removed] = true;
+                from[*index] = Some(renames.changes[removed].0.path.clone());
+            }
+        }
+        let changes = mem::take(&mut renames.changes);
+        for (index, (mut change, mut bytes)) in changes.into_iter().enumerate() {
+            if moved[index] {
+                continue;
+            }
+            if let Some(from) = from[index].take() {
+                // The contents are the same, just somewhere else.
+                change.action = ChangeAction::Renamed;
+                change.from = Some(from);
+                bytes = 0;
+            }
+            self.report(change, bytes);
+        }
+    }
+
+    /// What a file can be found by, when looking for renames.
+    fn rename_keys(&self, node: &SureNode) -> Vec<RenameKey> {
+        let atts = match node {
+            SureNode::File { atts, .. } if self.renames.is_some() => atts,
+            _ => return vec![],
+        };
+        let size = match atts.get("size") {
+            Some(size) if size != "0" && atts.get("kind").map(|k| k.as_str()) == Some("file") => {
+                size
+            }
+            _ => return vec![],
+        };
+        HASHES
+            .iter()
+            .filter_map(|alg| {
+                atts.get(alg.name())
+                    .map(|hash| (size.clone(), alg.name(), hash.clone()))
+            })
+            .collect()
+    }
+
     /// Advance the left iterator.  If it sees the end, it will drop in a
     /// "Leave" node, which shouldn't be visited as long as the tree is
     /// well-formed.
@@ -162,6 +582,7 @@ where
         loop {
             match (self.left.is_leave(), self.right.is_leave()) {
                 (true, true) => {
+                    self.recorded.pop();
                     self.next_left()?;
                     self.next_right()?;
                     return Ok(());
@@ -194,7 +615,7 @@ where
     }
 
     /// Old directory on the left tree.  Walk through nodes recursively to
-    /// discard entire tree.
+    /// discard entire tree, counting the size of its files.
     fn walk_leftdir(&mut self) -> Result<()> {
         loop {
             if self.left.is_enter() {
@@ -204,13 +625,14 @@ where
                 self.next_left()?;
                 return Ok(());
             } else {
+                self.summary.bytes += size(&self.left);
                 self.next_left()?;
             }
         }
     }
 
     /// New directory on the right tree.  Walk through nodes recursively to
-    /// discard entire tree.
+    /// discard entire tree, counting the size of its files.
     fn walk_rightdir(&mut self) -> Result<()> {
         loop {
             if self.right.is_enter() {
@@ -220,29 +642,69 @@ where
                 self.next_right()?;
                 return Ok(());
             } else {
+                self.summary.bytes += size(&self.right);
                 self.next_right()?;
             }
         }
     }
 
-    /// Print a message about something added (the name will be the thing
-    /// on the right.
-    fn show_add(&self, dir: &Path) {
-        println!(
-            "+ {:22} {:?}",
+    /// Report something added (the name will be the thing on the right).
+    fn show_add(&mut self, dir: &Path) {
+        let keys = self.rename_keys(&self.right);
+        let bytes = size(&self.right);
+        let change = Change::new(
+            dir.join(self.right.name()),
             self.right.kind(),
-            dir.join(self.right.name())
+            ChangeAction::Added,
         );
+        self.report_moved(change, bytes, keys);
     }
 
-    /// Print a message about something removed (the name will be the thing
-    /// on the left.
-    fn show_delete(&self, dir: &Path) {
-        println!("- {:22} {:?}", self.left.kind(), dir.join(self.left.name()));
+    /// Report something removed (the name will be the thing on the left),
+    /// or excluded, if the new scan left it out.
+    fn show_delete(&mut self, dir: &Path) {
+        let path = dir.join(self.left.name());
+        let rule = path
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(|rel| rel.to_str())
+            .and_then(|rel| self.excluded.rule(rel))
+            .map(|rule| rule.to_string());
+        let (action, bytes, keys) = match rule {
+            Some(_) => (ChangeAction::Excluded, 0, vec![]),
+            None => (
+                ChangeAction::Removed,
+                size(&self.left),
+                self.rename_keys(&self.left),
+            ),
+        };
+        let change = Change {
+            rule,
+            ..Change::new(path, self.left.kind(), action)
+        };
+        self.report_moved(change, bytes, keys);
     }
 
-    /// Compare the two "Enter" nodes we are visiting.
+    /// Compare the two "Enter" nodes we are visiting, noting what both
+    /// trees looked for in the directory until it is left.
     fn compare_enter(&mut self, dir: &Path) -> Result<()> {
+        let recorded = |node: &SureNode| -> Vec<String> {
+            node.atts()
+                .and_then(|atts| atts.get(RECORDED_ATT))
+                .map(|atts| {
+                    atts.split(',')
+                        .filter(|att| !att.is_empty())
+                        .map(|att| att.to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let right = recorded(&self.right);
+        let both = recorded(&self.left)
+            .into_iter()
+            .filter(|att| right.contains(att))
+            .collect();
+        self.recorded.push(both);
         self.compare_atts('d', dir)
     }
 
@@ -261,9 +723,52 @@ where
             old.remove(att);
             new.remove(att);
         }
+        let rel = dir.strip_prefix(&self.root).unwrap_or(dir);
+        for att in self.rules.ignored(rel) {
+            old.remove(att);
+            new.remove(att);
+        }
+
+        // Some attributes are only on some nodes, so their coming or going
+        // is a change, not a change of format, as long as both trees record
+        // the attribute paired with them:
+        //
+        // - Only the later files of a hardlink group have a "link", so a
+        //   hardlink made or broken changes it, and "nlink".
+        // - Only mountpoints have a "mount", so a directory becoming, or no
+        //   longer being, one changes it, and "fstype" if it is a different
+        //   kind of filesystem.
+        // - Only files with capabilities have a "cap", only nodes with flags
+        //   such as immutable set have "flags", and only sparse files have
+        //   "sparse", so gaining or losing them changes it, when both trees
+        //   looked for it in the directory, as listed by its "recorded".
+        // - A broken link has no target to record, so a link becoming
+        //   broken, or no longer broken, changes its "tpath", and its
+        //   "tsize" and hashes if it is to a file, when both trees record
+        //   link targets ("tdigest").
+        let target = ["tpath", "tsize"]
+            .iter()
+            .copied()
+            .chain(HASHES.iter().map(|alg| alg.name()))
+            .map(|att| (att, "tdigest"));
+        let optional: Vec<&str> = [("link", "nlink"), ("mount", "fstype")]
+            .iter()
+            .copied()
+            .chain(target)
+            .filter(|(_, with)| old.contains_key(*with) && new.contains_key(*with))
+            .map(|(att, _)| att)
+            .chain(
+                self.recorded
+                    .last()
+                    .into_iter()
+                    .flatten()
+                    .map(|att| att.as_str()),
+            )
+            .collect();
 
         for (k, v) in &new {
             match old.get(k) {
+                None if optional.contains(&k.as_str()) => diffs.push(k.clone()),
                 None => {
                     // This attribute is in the new tree, but not the old
                     // one, warn, but only once.
@@ -282,24 +787,187 @@ where
         }
 
         for k in old.keys() {
-            if !self.missings.contains(k) {
+            if optional.contains(&k.as_str()) {
+                diffs.push(k.clone());
+            } else if !self.missings.contains(k) {
                 error!("Missing attribute: {}", k);
                 self.missings.insert(k.clone());
             }
         }
 
-        if !diffs.is_empty() {
-            let mut buf = String::new();
+        if diffs.is_empty() {
+            self.summary.unchanged += 1;
+        } else {
             diffs.sort();
-            for d in &diffs {
-                if !buf.is_empty() {
-                    buf.push(',');
-                }
-                buf.push_str(&d);
-            }
-            println!("  [{:<20}] {:?}", buf, dir);
+            // Only a change of contents affects the file's bytes, not, say,
+            // a change of its permissions.
+            let contents = diffs
+                .iter()
+                .any(|att| att == "size" || HASHES.iter().any(|alg| alg.name() == att));
+            let bytes = if contents { size(&self.right) } else { 0 };
+            self.report(
+                Change {
+                    attrs_changed: diffs,
+                    ..Change::new(dir, self.right.kind(), ChangeAction::Modified)
+                },
+                bytes,
+            );
         }
 
         Ok(())
     }
 }
+
+/// The size of a file node, or 0 for anything without one.
+fn size(node: &SureNode) -> u64 {
+    node.atts()
+        .and_then(|atts| atts.get("size"))
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_rename_pairing() {
+    use crate::suretree::AttMap;
+
+    let dir = |name: &str| SureNode::Enter {
+        name: name.to_string(),
+        atts: [("kind", "dir")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    };
+    let file = |name: &str, content: &str| {
+        let atts: AttMap = [
+            ("kind", "file"),
+            ("size", &content.len().to_string()),
+            ("sha1", content),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        SureNode::File {
+            name: name.to_string(),
+            atts,
+        }
+    };
+    // The top, holding "a" and "b", with the files given in each.
+    let tree = |a: Vec<SureNode>, b: Vec<SureNode>| {
+        let mut nodes = vec![dir("__root__"), dir("a"), SureNode::Sep];
+        nodes.extend(a);
+        nodes.extend(vec![SureNode::Leave, dir("b"), SureNode::Sep]);
+        nodes.extend(b);
+        nodes.extend(vec![SureNode::Leave, SureNode::Sep, SureNode::Leave]);
+        nodes.into_iter().map(Ok)
+    };
+    let old = tree(
+        vec![
+            file("empty", ""),
+            file("gone", "gone"),
+            file("moved", "moved"),
+            file("same1", "same"),
+            file("same2", "same"),
+        ],
+        vec![],
+    );
+    let new = tree(
+        vec![],
+        vec![
+            file("empty", ""),
+            file("moved", "moved"),
+            file("new", "new"),
+            file("same", "same"),
+        ],
+    );
+
+    let mut changes = vec![];
+    let summary = compare_trees_with_renames(
+        old,
+        new,
+        "",
+        &IgnoreRules::default(),
+        &Tombstones::new(),
+        |change| changes.push(change),
+    )
+    .unwrap();
+    let found: Vec<_> = changes
+        .iter()
+        .map(|c| (c.path.to_str().unwrap(), c.action, c.from.clone()))
+        .collect();
+    let from = |path: &str| Some(PathBuf::from(path));
+    assert_eq!(
+        found,
+        [
+            // Empty files are never paired.
+            ("a/empty", ChangeAction::Removed, None),
+            ("a/gone", ChangeAction::Removed, None),
+            // Of two files with the same content, the first one gone is.
+            ("a/same2", ChangeAction::Removed, None),
+            ("b/empty", ChangeAction::Added, None),
+            ("b/moved", ChangeAction::Renamed, from("a/moved")),
+            ("b/new", ChangeAction::Added, None),
+            ("b/same", ChangeAction::Renamed, from("a/same1")),
+        ]
+    );
+    assert_eq!(summary.renamed, 2);
+    // Only the files not moved affect any bytes.
+    assert_eq!(summary.bytes, 11);
+}
+
+#[test]
+fn test_recorded_pairing() {
+    let atts = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    // A root holding a file, with or without capabilities, scanned by a
+    // version recording the given attributes, if any.
+    let tree = |recorded: Option<&str>, cap: bool| {
+        let mut root = vec![("kind", "dir")];
+        if let Some(recorded) = recorded {
+            root.push((RECORDED_ATT, recorded));
+        }
+        let mut file = vec![("kind", "file"), ("size", "1")];
+        if cap {
+            file.push(("cap", "01"));
+        }
+        vec![
+            SureNode::Enter {
+                name: "__root__".to_string(),
+                atts: atts(&root),
+            },
+            SureNode::Sep,
+            SureNode::File {
+                name: "ping".to_string(),
+                atts: atts(&file),
+            },
+            SureNode::Leave,
+        ]
+        .into_iter()
+        .map(Ok)
+    };
+    let changed = |old, new| {
+        let mut changes = vec![];
+        compare_trees(old, new, "", &[], |change| changes.push(change)).unwrap();
+        changes
+            .into_iter()
+            .map(|c| c.attrs_changed.join(","))
+            .collect::<Vec<_>>()
+    };
+
+    // Both trees looked for capabilities, so gaining or losing them is a
+    // change.
+    assert_eq!(
+        changed(tree(Some("cap"), false), tree(Some("cap"), true)),
+        ["cap"]
+    );
+    assert_eq!(
+        changed(tree(Some("cap"), true), tree(Some("cap"), false)),
+        ["cap"]
+    );
+    // An older scan, that didn't look for them, isn't known to have none.
+    assert!(changed(tree(None, false), tree(Some("cap"), true)).is_empty());
+    assert!(changed(tree(Some(""), false), tree(Some("cap"), true)).is_empty());
+}
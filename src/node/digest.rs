@@ -0,0 +1,115 @@
+//! Digests of whole trees.
+//!
+//! Each version written by an update records, in its tags, a digest of all
+//! of its nodes: the SHA-256 of its surefile, as [`NodeWriter`] writes it,
+//! but without the attributes that differ between copies of the same tree,
+//! its files' "ino", "ctime", "btime" and "blocks", nor the "rollup" of
+//! each directory, which only repeats what is below it.  Two versions with the
+//! same digest hold the same names, kinds, owners, modes, times, sizes and
+//! hashes throughout, so two stores, the stores of two machines, or a tree
+//! and a restore of it, can be compared by one value before reaching for a
+//! full diff.  Unlike a directory's rollup, this covers owners, modes and
+//! modification times.
+
+use crate::{
+    node::{NodeObserver, NodeWriter, SureNode, ROLLUP_ATT, VOLATILE_ATTS},
+    Result,
+};
+use data_encoding::HEXLOWER;
+use openssl::hash::{Hasher, MessageDigest};
+
+/// The tag recording the digest of a version's tree.
+pub const DIGEST_TAG: &str = "digest";
+
+/// The digest of a tree, as recorded in the [`DIGEST_TAG`] of the version
+/// it was written as.  Computing this over the nodes read back from a
+/// version checks them against its tag.
+pub fn tree_digest<I>(nodes: I) -> Result<String>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    let mut digest = TreeDigest::new()?;
+    for node in nodes {
+        digest.observe(&node?)?;
+    }
+    digest.finish()
+}
+
+/// A tree's digest, taken as its nodes go by, such as while they are
+/// merged into a new version.
+pub(crate) struct TreeDigest {
+    writer: NodeWriter<Hasher>,
+}
+
+impl TreeDigest {
+    pub(crate) fn new() -> Result<TreeDigest> {
+        Ok(TreeDigest {
+            writer: NodeWriter::new(Hasher::new(MessageDigest::sha256())?)?,
+        })
+    }
+
+    /// The digest of the nodes observed.
+    pub(crate) fn finish(self) -> Result<String> {
+        let digest = self.writer.into_inner()?.finish()?;
+        Ok(HEXLOWER.encode(&digest))
+    }
+}
+
+impl NodeObserver for TreeDigest {
+    fn observe(&mut self, node: &SureNode) -> Result<()> {
+        let mut node = node.clone();
+        if let Some(atts) = node.atts_mut() {
+            for att in VOLATILE_ATTS.iter().chain(&[ROLLUP_ATT]) {
+                atts.remove(*att);
+            }
+        }
+        self.writer.write_node(&node)
+    }
+}
+
+#[test]
+fn test_tree_digest() {
+    use crate::suretree::AttMap;
+
+    let tree = |uid: &str, ino: &str, rollup: &str| {
+        let atts = |pairs: &[(&str, &str)]| -> AttMap {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        vec![
+            SureNode::Enter {
+                name: "__root__".to_string(),
+                atts: atts(&[("kind", "dir"), ("uid", uid), (ROLLUP_ATT, rollup)]),
+            },
+            SureNode::Sep,
+            SureNode::File {
+                name: "a".to_string(),
+                atts: atts(&[("kind", "file"), ("uid", uid), ("ino", ino)]),
+            },
+            SureNode::Leave,
+        ]
+    };
+    let digest = |nodes: Vec<SureNode>| tree_digest(nodes.into_iter().map(Ok)).unwrap();
+
+    // The SHA-256 of the surefile, without the volatile attributes and
+    // rollups.
+    let mut writer = NodeWriter::new(vec![]).unwrap();
+    for mut node in tree("0", "", "") {
+        if let Some(atts) = node.atts_mut() {
+            atts.remove("ino");
+            atts.remove(ROLLUP_ATT);
+        }
+        writer.write_node(&node).unwrap();
+    }
+    let surefile = writer.into_inner().unwrap();
+    let expect = HEXLOWER.encode(&openssl::sha::sha256(&surefile));
+    assert_eq!(digest(tree("0", "12", "aaaa")), expect);
+
+    // A copy, with other inodes, is the same tree, one with another owner
+    // isn't.
+    assert_eq!(digest(tree("0", "34", "aaaa")), expect);
+    assert_eq!(digest(tree("0", "12", "bbbb")), expect);
+    assert_ne!(digest(tree("1000", "12", "aaaa")), expect);
+}
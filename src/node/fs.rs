@@ -1,14 +1,24 @@
 /// Sure tree scanning from the filesystem.
 use crate::{
-    escape::Escape, node::SureNode, progress::ScanProgress, surefs::encode_atts, suretree::AttMap,
-    Error, Result,
+    escape::Escape,
+    exclude::{Exclude, Tombstones},
+    failures::{self, Failures},
+    node::SureNode,
+    pin::PIN_FILE,
+    platform::{device, file_id, nlink, os_bytes, path_bytes, stat_order},
+    progress::{ProgressSink, ScanProgress},
+    surefs::{
+        encode_atts, flags_atts, fs_type, link_target_atts, pseudo_fs, recorded_atts, selinux_atts,
+    },
+    suretree::AttMap,
+    CancellationToken, Error, Result,
 };
-use log::error;
+use log::{error, warn};
 use std::{
-    collections::VecDeque,
-    fs::{self, symlink_metadata, Metadata},
-    os::unix::prelude::*,
+    collections::{HashMap, VecDeque},
+    fs::{self, metadata, symlink_metadata, Metadata},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
 pub fn walk<P: AsRef<Path>>(root: P) -> Result<()> {
@@ -20,21 +30,88 @@ pub fn walk<P: AsRef<Path>>(root: P) -> Result<()> {
     Ok(())
 }
 
+/// Settings for a filesystem scan.
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    /// Descend into directories on other filesystems.  Normally these are
+    /// recorded as empty directories, so that a scan stays on the root's
+    /// filesystem.
+    pub cross_filesystems: bool,
+    /// Paths to leave out, in addition to those in the root's
+    /// `.rsureignore`.
+    pub exclude: Exclude,
+    /// Record what symlinks point to, instead of the links themselves, and
+    /// descend into linked directories.  A link back to a directory being
+    /// scanned is still recorded as a link, with a warning, rather than
+    /// looping.  Broken links are also kept as links.
+    pub follow_symlinks: bool,
+    /// Record the path each symlink finally resolves to, and when that is a
+    /// regular file, hash its content, so that changes to where links point
+    /// are caught, as well as changes to the link text.  The hashes of link
+    /// targets are not carried over by updates, but computed every time.
+    /// Each link also gets "tdigest", a hash of its text, and a link
+    /// becoming broken shows up as a change when comparing.
+    pub hash_link_targets: bool,
+    /// Record the SELinux security context of each node, as "secontext",
+    /// so that labels changing show up when comparing.  Nodes without one
+    /// have no "secontext".
+    pub selinux: bool,
+    /// Record the flags set on each file and directory, such as immutable
+    /// and append only, as "flags", so that they changing shows up when
+    /// comparing.  This takes opening each of them, on Linux.  Nodes with
+    /// none set have no "flags".
+    pub flags: bool,
+    /// Stop the scan once this is cancelled, giving `Error::Cancelled`, and
+    /// wait while it is paused.
+    pub cancel: Option<CancellationToken>,
+    /// Where to report the scan's progress, instead of the terminal.
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    /// Where to record the directories that can't be read, and the files
+    /// that can't be stat'ed.  These are also logged.  An update records
+    /// its own, with the new version.
+    pub failures: Failures,
+}
+
 /// A filesystem scanner walks a filesystem, iterating over a tree as it is
-/// encountered.
+/// encountered.  Paths matching the patterns in the root's `.rsureignore`
+/// are left out, as is its `.rsure-pin`, and other filesystems are not
+/// descended into.  Pseudo filesystems, such as /proc and /sys, are never
+/// scanned: they are an error as the root, and skipped with a warning
+/// below it.
 pub fn scan_fs<P: AsRef<Path>>(root: P) -> Result<ScanIterator> {
+    scan_fs_with(root, &ScanOptions::default())
+}
+
+/// Scan a filesystem, as `scan_fs`, with the given options.
+pub fn scan_fs_with<P: AsRef<Path>>(root: P, options: &ScanOptions) -> Result<ScanIterator> {
     let root = root.as_ref().to_path_buf();
-    let meta = symlink_metadata(&root)?;
+    let meta = if options.follow_symlinks {
+        metadata(&root)?
+    } else {
+        symlink_metadata(&root)?
+    };
 
     if !meta.is_dir() {
         return Err(Error::RootMustBeDir);
     }
+    if let Some(fstype) = pseudo_fs(&root) {
+        return Err(Error::PseudoFilesystem(root, fstype.to_string()));
+    }
 
-    let atts = encode_atts(&root, &meta);
-    let root_dev = meta.dev();
+    let mut exclude = options.exclude.clone();
+    exclude.add_root(&root)?;
+
+    let mut atts = encode_atts(&root, &meta);
+    if options.selinux {
+        selinux_atts(&root, &mut atts);
+    }
+    if options.flags {
+        flags_atts(&root, &meta, &mut atts);
+    }
+    let root_dev = device(&meta);
     let mut todo = VecDeque::new();
     todo.push_back(AugNode::SubDir {
-        path: root,
+        path: root.clone(),
         name: "__root__".to_string(),
         meta,
         atts,
@@ -42,8 +119,25 @@ pub fn scan_fs<P: AsRef<Path>>(root: P) -> Result<ScanIterator> {
 
     let si = ScanIterator {
         todo,
+        root,
         root_dev,
-        progress: ScanProgress::new(),
+        cross_filesystems: options.cross_filesystems,
+        follow_symlinks: options.follow_symlinks,
+        hash_link_targets: options.hash_link_targets,
+        selinux: options.selinux,
+        flags: options.flags,
+        ancestors: vec![],
+        pseudo: HashMap::new(),
+        fstypes: HashMap::new(),
+        links: HashMap::new(),
+        exclude,
+        tombstones: Arc::new(Mutex::new(Tombstones::new())),
+        failures: options.failures.clone(),
+        progress: match &options.progress {
+            Some(sink) => ScanProgress::new().with_sink(sink.clone()),
+            None => ScanProgress::new(),
+        },
+        cancel: options.cancel.clone(),
     };
 
     Ok(si)
@@ -51,26 +145,69 @@ pub fn scan_fs<P: AsRef<Path>>(root: P) -> Result<ScanIterator> {
 
 pub struct ScanIterator {
     todo: VecDeque<AugNode>,
+    root: PathBuf,
     root_dev: u64,
+    cross_filesystems: bool,
+    follow_symlinks: bool,
+    hash_link_targets: bool,
+    selinux: bool,
+    flags: bool,
+    // The device and inode of each directory being scanned, from the root
+    // down, to catch symlinks that loop back.
+    ancestors: Vec<(u64, u64)>,
+    // Whether each device crossed into is a pseudo filesystem.
+    pseudo: HashMap<u64, bool>,
+    // The type of each device's filesystem, if it can be found.
+    fstypes: HashMap<u64, Option<String>>,
+    // The first file seen of each hardlink group, by device and inode.
+    links: HashMap<(u64, u64), String>,
+    exclude: Exclude,
+    tombstones: Arc<Mutex<Tombstones>>,
+    failures: Failures,
     progress: ScanProgress,
+    cancel: Option<CancellationToken>,
 }
 
 impl Iterator for ScanIterator {
     type Item = Result<SureNode>;
 
     fn next(&mut self) -> Option<Result<SureNode>> {
+        if let Some(cancel) = &self.cancel {
+            if !self.todo.is_empty() {
+                if let Err(e) = cancel.check() {
+                    self.todo.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
         match self.todo.pop_front() {
             None => None,
             Some(AugNode::Normal(e)) => Some(Ok(e)),
+            Some(AugNode::Leave) => {
+                self.ancestors.pop();
+                Some(Ok(SureNode::Leave))
+            }
             Some(AugNode::SubDir {
                 path,
                 name,
-                atts,
+                mut atts,
                 meta,
             }) => {
+                let dev = device(&meta);
+                let mount = match self.ancestors.last() {
+                    Some(&(parent, _)) => dev != parent,
+                    None => is_mount(&path, &meta),
+                };
+                self.fs_atts(&path, dev, mount, &mut atts);
+                recorded_atts(self.flags, &mut atts);
+
                 // Push the contents of this directory.  Unless we have
-                // crossed a mountpoint.
-                if !meta.is_dir() || meta.dev() == self.root_dev {
+                // crossed a mountpoint, and weren't asked to, or it is a
+                // pseudo filesystem.
+                let descend =
+                    dev == self.root_dev || (self.cross_filesystems && !self.is_pseudo(&path, dev));
+                if !meta.is_dir() || descend {
+                    self.ancestors.extend(file_id(&meta));
                     match self.push_dir(&path) {
                         Ok(()) => (),
                         Err(e) => return Some(Err(e)),
@@ -86,16 +223,52 @@ impl Iterator for ScanIterator {
 }
 
 impl ScanIterator {
+    /// The paths this scan has left out, so far.  These can still be read
+    /// once the iterator has been consumed.
+    pub fn tombstones(&self) -> Arc<Mutex<Tombstones>> {
+        self.tombstones.clone()
+    }
+
+    /// Is the directory at `path`, on device `dev`, on a pseudo
+    /// filesystem?  Warns the first time one is found.
+    fn is_pseudo(&mut self, path: &Path, dev: u64) -> bool {
+        *self
+            .pseudo
+            .entry(dev)
+            .or_insert_with(|| match pseudo_fs(path) {
+                Some(fstype) => {
+                    warn!("Skipping {} filesystem at {:?}", fstype, path);
+                    true
+                }
+                None => false,
+            })
+    }
+
+    /// Record the type of filesystem a directory is on, and whether it is
+    /// a mountpoint, so that a subtree moving to another filesystem shows
+    /// up when comparing.
+    fn fs_atts(&mut self, path: &Path, dev: u64, mount: bool, atts: &mut AttMap) {
+        let fstype = self.fstypes.entry(dev).or_insert_with(|| fs_type(path));
+        if let Some(fstype) = fstype {
+            atts.insert("fstype".to_string(), fstype.clone());
+            if mount {
+                atts.insert("mount".to_string(), "1".to_string());
+            }
+        }
+    }
+
     fn push_dir(&mut self, path: &Path) -> Result<()> {
         let mut entries = vec![];
 
-        match fs::read_dir(path) {
+        match failures::injected(path).and_then(|()| fs::read_dir(path)) {
             Ok(dir) => {
                 for entry in dir {
                     let entry = match entry {
                         Ok(ent) => ent,
                         Err(err) => {
                             error!("Unable to read from dir: {:?} ({})", path, err);
+                            self.failures
+                                .add(path, format!("Unable to read from dir ({})", err));
                             break;
                         }
                     };
@@ -105,19 +278,53 @@ impl ScanIterator {
             Err(e) => {
                 // Warn about the issue, but otherwise continue, with just an empty directory.
                 error!("Unable to read dir: {:?} ({})", path, e);
+                self.failures
+                    .add(path, format!("Unable to read dir ({})", e));
             }
         };
 
+        // The pin changes each time the tree is signed off, so isn't part of it.
+        if path == self.root {
+            entries.retain(|e| e.file_name() != PIN_FILE);
+        }
+
+        if !self.exclude.is_empty() {
+            let root = &self.root;
+            let exclude = &self.exclude;
+            let mut tombstones = self.tombstones.lock().unwrap();
+            entries.retain(|e| {
+                let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                let path = e.path();
+                let rel = path.strip_prefix(root).unwrap_or(&path);
+                match exclude.matching(rel, is_dir) {
+                    Some(rule) => {
+                        tombstones.insert(path_bytes(rel).escaped(), rule);
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+
         // Sort by inode first.  This helps performance on some filesystems
         // (such as ext4).
-        entries.sort_by_key(|a| a.ino());
+        entries.sort_by_key(stat_order);
 
         let mut files: Vec<_> = entries
             .iter()
-            .filter_map(|e| match e.metadata() {
+            .filter_map(|e| match self.entry_metadata(e) {
                 Ok(m) => {
                     let path = e.path();
-                    let atts = encode_atts(&path, &m);
+                    let mut atts = encode_atts(&path, &m);
+                    if self.hash_link_targets && m.file_type().is_symlink() {
+                        link_target_atts(&path, &mut atts);
+                    }
+                    if self.selinux {
+                        selinux_atts(&path, &mut atts);
+                    }
+                    if self.flags {
+                        flags_atts(&path, &m, &mut atts);
+                    }
 
                     Some(OneFile {
                         path,
@@ -127,6 +334,8 @@ impl ScanIterator {
                 }
                 Err(err) => {
                     error!("Unable to stat file: {:?} ({})", e.path(), err);
+                    self.failures
+                        .add(&e.path(), format!("Unable to stat file ({})", err));
                     None
                 }
             })
@@ -135,20 +344,41 @@ impl ScanIterator {
         // Sort them back by name.
         files.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
 
+        // Later files of a hardlink group name the first one seen, so that
+        // links being made or broken show up when comparing.
+        for f in &mut files {
+            if !f.meta.is_file() || nlink(&f.meta) < 2 {
+                continue;
+            }
+            if let Some(key) = file_id(&f.meta) {
+                let rel = f.path.strip_prefix(&self.root).unwrap_or(&f.path);
+                let rel = path_bytes(rel).escaped();
+                match self.links.get(&key) {
+                    Some(first) => {
+                        f.atts.insert("link".to_string(), first.clone());
+                    }
+                    None => {
+                        self.links.insert(key, rel);
+                    }
+                }
+            }
+        }
+
         let (dirs, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|n| n.meta.is_dir());
 
         self.progress.update(
+            path,
             dirs.len() as u64,
             files.len() as u64,
             files.iter().map(|x| x.meta.len()).sum(),
         );
 
-        self.todo.push_front(AugNode::Normal(SureNode::Leave));
+        self.todo.push_front(AugNode::Leave);
 
         // The files in reverse order.
         for f in files.into_iter().rev() {
             self.todo.push_front(AugNode::Normal(SureNode::File {
-                name: f.path.file_name().unwrap().as_bytes().escaped(),
+                name: os_bytes(f.path.file_name().unwrap()).escaped(),
                 atts: f.atts,
             }));
         }
@@ -157,7 +387,7 @@ impl ScanIterator {
 
         // The dirs in reverse order.
         for d in dirs.into_iter().rev() {
-            let name = d.path.file_name().unwrap().as_bytes().escaped();
+            let name = os_bytes(d.path.file_name().unwrap()).escaped();
             self.todo.push_front(AugNode::SubDir {
                 path: d.path,
                 name,
@@ -169,6 +399,35 @@ impl ScanIterator {
         Ok(())
     }
 
+    /// The metadata for a directory entry.  When following symlinks, this is
+    /// of what a link points to, unless it is broken, or points to a
+    /// directory being scanned.
+    fn entry_metadata(&self, entry: &fs::DirEntry) -> std::io::Result<Metadata> {
+        let meta = entry.metadata()?;
+        if !self.follow_symlinks || !meta.file_type().is_symlink() {
+            return Ok(meta);
+        }
+        match metadata(entry.path()) {
+            Ok(target) => {
+                let looped = match file_id(&target) {
+                    Some(id) => self.ancestors.contains(&id),
+                    // Without a way to tell, it might loop.
+                    None => true,
+                };
+                if target.is_dir() && looped {
+                    warn!("Not following looping symlink: {:?}", entry.path());
+                    Ok(meta)
+                } else {
+                    Ok(target)
+                }
+            }
+            Err(err) => {
+                warn!("Unable to follow symlink: {:?} ({})", entry.path(), err);
+                Ok(meta)
+            }
+        }
+    }
+
     /// Pushes the Sep and Leave needed to make an empty directory work.
     /// Used when skipping directories that cross mountpoints.
     fn push_empty_dir(&mut self) {
@@ -177,6 +436,18 @@ impl ScanIterator {
     }
 }
 
+/// Is the directory at `path` a mountpoint?  The root of the whole
+/// filesystem is its own parent, and counts as one.
+fn is_mount(path: &Path, meta: &Metadata) -> bool {
+    match metadata(path.join("..")) {
+        Ok(parent) => {
+            device(&parent) != device(meta)
+                || (file_id(&parent).is_some() && file_id(&parent) == file_id(meta))
+        }
+        Err(_) => false,
+    }
+}
+
 struct OneFile {
     path: PathBuf,
     meta: Metadata,
@@ -187,6 +458,8 @@ struct OneFile {
 /// containing enough information to add subdirectories.
 enum AugNode {
     Normal(SureNode),
+    // The end of a directory that was descended into.
+    Leave,
     SubDir {
         path: PathBuf,
         name: String,
@@ -9,19 +9,14 @@
 //! more complicated that avoids computing (and allocating) the result
 //! paths for each node encountered.
 
-use crate::{escape::Unescape, node::SureNode, Result};
-use std::{
-    ffi::OsString,
-    os::unix::ffi::OsStringExt,
-    path::{Path, PathBuf},
-};
-
-pub fn into_tracker<I>(iter: I, root: &str) -> impl Iterator<Item = Result<PathedNode>>
+use crate::{escape::Unescape, node::SureNode, platform::os_from_bytes, Result};
+use std::path::{Path, PathBuf};
+
+pub fn into_tracker<I>(iter: I, root: &Path) -> impl Iterator<Item = Result<PathedNode>>
 where
     I: Iterator<Item = Result<SureNode>>,
 {
-    let root: OsString = OsStringExt::from_vec(root.unescape().unwrap());
-    let mut cur = Path::new(&root).to_path_buf();
+    let mut cur = root.to_path_buf();
     let mut at_root = true;
     iter.map(move |node| {
         let node = node?;
@@ -34,13 +29,13 @@ where
                     }
                     at_root = false;
                 } else {
-                    let name: OsString = OsStringExt::from_vec(name.unescape().unwrap());
+                    let name = os_from_bytes(name.unescape().unwrap());
                     cur.push(&name);
                 }
                 Some(cur.clone())
             }
             SureNode::File { name, .. } => {
-                let name: OsString = OsStringExt::from_vec(name.unescape().unwrap());
+                let name = os_from_bytes(name.unescape().unwrap());
                 cur.push(&name);
                 Some(cur.clone())
             }
@@ -0,0 +1,379 @@
+//! Holding the hash results of an update until they are merged.
+//!
+//! The files are hashed out of order, and the results are needed again in order, by the number of
+//! the file, once all of them are done.  There can be too many to keep in memory, so they are kept
+//! in a temp file, either a sqlite database, or, without the "sqlite" feature, or if sqlite can't
+//! be used, as runs sorted in memory and appended to a single file, which are merged as they are
+//! read back.
+
+use crate::{
+    memory::MemoryPlan,
+    store::{Store, TempCleaner},
+    Result,
+};
+#[cfg(feature = "sqlite")]
+use log::warn;
+#[cfg(feature = "sqlite")]
+use rusqlite::{types::ToSql, Connection};
+#[cfg(feature = "sqlite")]
+use std::collections::VecDeque;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// The hash of a single file, numbered by the order of the files needing hashes.
+#[derive(Debug)]
+pub(crate) struct HashInfo {
+    pub id: i64,
+    pub hash: Vec<u8>,
+}
+
+/// How many results are sorted at once when there is no memory limit to size the runs by.
+const DEFAULT_RUN: usize = 64 * 1024;
+
+/// The most runs merged at once, each with its own file handle and buffer.  More runs than this
+/// are first merged, this many at a time, into longer runs.
+const FAN_IN: usize = 64;
+
+pub(crate) enum HashBuffer {
+    #[cfg(feature = "sqlite")]
+    Sql(Connection),
+    Runs(SortedRuns),
+}
+
+impl HashBuffer {
+    /// Set up a buffer in a temp file of the store, returning the cleaner that removes the file,
+    /// which has to be kept until the buffer is no longer used.
+    pub fn new(store: &dyn Store, plan: &MemoryPlan) -> Result<(HashBuffer, Box<dyn TempCleaner>)> {
+        // The buffer only holds hashes, by node number, so it isn't encrypted, even in an
+        // encrypted store.
+        let tmp = store.make_temp()?.into_loader()?;
+        let path = tmp.path_ref().to_owned();
+        let cleaner = tmp.into_cleaner()?;
+
+        #[cfg(feature = "sqlite")]
+        match open_sql(&path, plan) {
+            Ok(conn) => return Ok((HashBuffer::Sql(conn), cleaner)),
+            Err(e) => warn!(
+                "Unable to use sqlite for the hashes, sorting them instead ({})",
+                e
+            ),
+        }
+
+        let runs = SortedRuns::new(path, plan.batch.unwrap_or(DEFAULT_RUN))?;
+        Ok((HashBuffer::Runs(runs), cleaner))
+    }
+
+    /// Add the hash results to the buffer.  If a batch size is given, sqlite commits the results
+    /// after that many, so that the page cache is written out as it fills, rather than at the end.
+    pub fn store<I>(&mut self, results: I, batch: Option<usize>) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<HashInfo>>,
+    {
+        match self {
+            #[cfg(feature = "sqlite")]
+            HashBuffer::Sql(conn) => store_sql(conn, results, batch),
+            HashBuffer::Runs(runs) => {
+                let _ = batch;
+                runs.store(results)
+            }
+        }
+    }
+
+    /// The results, in order of their ids.
+    pub fn into_sorted(self) -> Result<Box<dyn Iterator<Item = Result<HashInfo>>>> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            HashBuffer::Sql(conn) => Ok(Box::new(SqlRows {
+                conn,
+                last: -1,
+                page: VecDeque::new(),
+                done: false,
+            })),
+            HashBuffer::Runs(runs) => Ok(Box::new(runs.merge()?)),
+        }
+    }
+}
+
+/// How many rows are read from sqlite at a time.
+#[cfg(feature = "sqlite")]
+const PAGE: i64 = 1024;
+
+/// Reads the results back from sqlite a page at a time, each starting after the last id of the
+/// one before, so that no statement outlives a call, and the connection can be owned here.
+#[cfg(feature = "sqlite")]
+struct SqlRows {
+    conn: Connection,
+    last: i64,
+    page: VecDeque<HashInfo>,
+    done: bool,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqlRows {
+    fn fill(&mut self) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id, hash FROM hashes WHERE id > ?1 ORDER BY id LIMIT ?2")?;
+        let rows = stmt.query_map([self.last, PAGE], |row| {
+            Ok(HashInfo {
+                id: row.get(0)?,
+                hash: row.get(1)?,
+            })
+        })?;
+        for row in rows {
+            self.page.push_back(row?);
+        }
+        match self.page.back() {
+            Some(info) => self.last = info.id,
+            None => self.done = true,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Iterator for SqlRows {
+    type Item = Result<HashInfo>;
+
+    fn next(&mut self) -> Option<Result<HashInfo>> {
+        if self.page.is_empty() && !self.done {
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sql(path: &std::path::Path, plan: &MemoryPlan) -> Result<Connection> {
+    // Sqlite wants a new database to be empty.
+    File::create(path)?;
+    let conn = Connection::open(path)?;
+    if let Some(kib) = plan.cache_kib {
+        // A negative size is in KiB, rather than pages.
+        conn.execute_batch(&format!("PRAGMA cache_size = -{}", kib))?;
+    }
+    conn.execute(
+        "CREATE TABLE hashes (
+            id INTEGER PRIMARY KEY,
+            hash BLOB)",
+        [],
+    )?;
+    Ok(conn)
+}
+
+#[cfg(feature = "sqlite")]
+fn store_sql<I>(conn: &mut Connection, results: I, batch: Option<usize>) -> Result<()>
+where
+    I: IntoIterator<Item = Result<HashInfo>>,
+{
+    let mut trans = conn.transaction()?;
+    let mut pending = 0;
+    for info in results {
+        let info = info?;
+        trans.execute(
+            "INSERT INTO hashes (id, hash) VALUES (?1, ?2)",
+            [&info.id as &dyn ToSql, &info.hash as &dyn ToSql],
+        )?;
+        pending += 1;
+        if Some(pending) == batch {
+            trans.commit()?;
+            trans = conn.transaction()?;
+            pending = 0;
+        }
+    }
+    trans.commit()?;
+    Ok(())
+}
+
+/// Results sorted a run at a time, each run appended to the file as a sequence of records: the
+/// id, and the length of the hash, as little endian, then the hash.
+pub(crate) struct SortedRuns {
+    path: PathBuf,
+    file: BufWriter<File>,
+    run_size: usize,
+    /// The offset and length of each run in the file.
+    runs: Vec<(u64, u64)>,
+    end: u64,
+}
+
+impl SortedRuns {
+    fn new(path: PathBuf, run_size: usize) -> Result<SortedRuns> {
+        let file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&path)?;
+        Ok(SortedRuns {
+            path,
+            file: BufWriter::new(file),
+            run_size: run_size.max(1),
+            runs: vec![],
+            end: 0,
+        })
+    }
+
+    fn store<I>(&mut self, results: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Result<HashInfo>>,
+    {
+        let mut run = Vec::with_capacity(self.run_size.min(DEFAULT_RUN));
+        for info in results {
+            run.push(info?);
+            if run.len() == self.run_size {
+                self.write_run(&mut run)?;
+            }
+        }
+        self.write_run(&mut run)?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_run(&mut self, run: &mut Vec<HashInfo>) -> Result<()> {
+        if run.is_empty() {
+            return Ok(());
+        }
+        run.sort_unstable_by_key(|info| info.id);
+        let start = self.end;
+        for info in run.drain(..) {
+            self.write_record(&info)?;
+        }
+        self.runs.push((start, self.end - start));
+        Ok(())
+    }
+
+    fn write_record(&mut self, info: &HashInfo) -> Result<()> {
+        self.file.write_all(&info.id.to_le_bytes())?;
+        self.file
+            .write_all(&(info.hash.len() as u32).to_le_bytes())?;
+        self.file.write_all(&info.hash)?;
+        self.end += 12 + info.hash.len() as u64;
+        Ok(())
+    }
+
+    /// Merge all of the runs.  If there are more than `FAN_IN`, they are merged in passes, each
+    /// merging groups of them into longer runs appended to the file, until few enough are left.
+    fn merge(mut self) -> Result<RunMerge> {
+        while self.runs.len() > FAN_IN {
+            let runs = std::mem::take(&mut self.runs);
+            for group in runs.chunks(FAN_IN) {
+                let start = self.end;
+                for info in merge_runs(&self.path, group)? {
+                    self.write_record(&info?)?;
+                }
+                self.runs.push((start, self.end - start));
+            }
+            self.file.flush()?;
+        }
+        merge_runs(&self.path, &self.runs)
+    }
+}
+
+/// Merge the given runs of the file, each its offset and length.
+fn merge_runs(path: &Path, runs: &[(u64, u64)]) -> Result<RunMerge> {
+    let mut readers = vec![];
+    let mut heads = BinaryHeap::new();
+    for (index, &(offset, len)) in runs.iter().enumerate() {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file).take(len);
+        if let Some(info) = read_record(&mut reader)? {
+            heads.push(Reverse(Head(info, index)));
+        }
+        readers.push(reader);
+    }
+    Ok(RunMerge { readers, heads })
+}
+
+fn read_record<R: Read>(reader: &mut R) -> Result<Option<HashInfo>> {
+    let mut header = [0u8; 12];
+    match reader.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let id = i64::from_le_bytes(header[..8].try_into().unwrap());
+    let len = u32::from_le_bytes(header[8..].try_into().unwrap());
+    let mut hash = vec![0; len as usize];
+    reader.read_exact(&mut hash)?;
+    Ok(Some(HashInfo { id, hash }))
+}
+
+/// The next result of a run, ordered by its id.
+struct Head(HashInfo, usize);
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Head) -> std::cmp::Ordering {
+        self.0.id.cmp(&other.0.id)
+    }
+}
+
+/// Merges the runs, giving the results of all of them in order.
+struct RunMerge {
+    readers: Vec<io::Take<BufReader<File>>>,
+    heads: BinaryHeap<Reverse<Head>>,
+}
+
+impl Iterator for RunMerge {
+    type Item = Result<HashInfo>;
+
+    fn next(&mut self) -> Option<Result<HashInfo>> {
+        let Reverse(Head(info, index)) = self.heads.pop()?;
+        match read_record(&mut self.readers[index]) {
+            Ok(Some(next)) => self.heads.push(Reverse(Head(next, index))),
+            Ok(None) => (),
+            Err(e) => {
+                self.heads.clear();
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(info))
+    }
+}
+
+#[test]
+fn test_merge_passes() {
+    let tmp = tempdir::TempDir::new("rsure").unwrap();
+    // One result to a run, enough to take two passes before the last merge.
+    let count = FAN_IN * FAN_IN + 100;
+    let mut runs = SortedRuns::new(tmp.path().join("runs"), 1).unwrap();
+    // 7919 is prime, so this visits every id, out of order.
+    let ids = (0..count).map(|i| (i * 7919 % count) as i64);
+    runs.store(ids.map(|id| {
+        Ok(HashInfo {
+            id,
+            hash: id.to_le_bytes().to_vec(),
+        })
+    }))
+    .unwrap();
+    assert_eq!(runs.runs.len(), count);
+
+    let merged: Vec<_> = runs.merge().unwrap().map(|info| info.unwrap()).collect();
+    assert_eq!(merged.len(), count);
+    for (expect, info) in merged.iter().enumerate() {
+        assert_eq!(info.id, expect as i64);
+        assert_eq!(info.hash, info.id.to_le_bytes());
+    }
+}
@@ -1,22 +1,39 @@
 //! Hash updates for node-based sure file.
 
 use crate::{
-    hashes::{hash_file, noatime_open, Estimate},
-    node::{into_tracker, NodeWriter, SureNode},
-    progress::Progress,
+    cancel::{self, CancellationToken},
+    failures::Failures,
+    hashes::{hash_open_file, noatime_open, Estimate, HashAlgorithm, HashReuse},
+    memory::{MemoryLimit, MemoryPlan},
+    monitor::Activity,
+    node::{
+        fullpath::PathedNode,
+        hashbuf::{HashBuffer, HashInfo},
+        into_tracker,
+        uring::{self, UringReader},
+        NodeWriter, SureNode,
+    },
+    progress::{self, Progress, ProgressSink},
+    roots::Roots,
+    stats,
     store::{Store, TempCleaner},
+    throttle::{self, Throttle},
     Error, Result,
 };
-use crossbeam::channel::{bounded, Sender};
+use crossbeam::{
+    channel::{bounded, Receiver, Sender},
+    thread::Scope,
+};
 use data_encoding::HEXLOWER;
-use log::{debug, error};
-use rusqlite::{types::ToSql, Connection};
+use log::{debug, error, warn};
 use std::{
     cmp::Ordering,
+    fmt,
     io::Write,
+    iter::{self, Peekable},
     mem,
-    path::PathBuf,
-    sync::{mpsc::sync_channel, Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{mpsc::sync_channel, Arc, Condvar, Mutex, Once},
     thread,
 };
 
@@ -30,60 +47,343 @@ pub trait Source {
 pub struct HashUpdater<'n, S> {
     source: S,
     store: &'n dyn Store,
+    algorithms: Vec<HashAlgorithm>,
+    pool: Option<Arc<HashPool>>,
+    activity: Option<Arc<Activity>>,
+    memory: Option<MemoryLimit>,
+    cancel: Option<CancellationToken>,
+    progress: Arc<dyn ProgressSink>,
+    mmap: Option<u64>,
+    uring: bool,
+    jobs: Option<usize>,
+    throttle: Option<Arc<Throttle>>,
+    background: bool,
+    roots: Option<Arc<Roots>>,
+    failures: Failures,
+}
+
+/// The nodes, with the paths of their files, under `base`, or the roots.
+fn tracker<I>(
+    nodes: I,
+    base: &Path,
+    roots: Option<Arc<Roots>>,
+) -> impl Iterator<Item = Result<PathedNode>>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    let base = if roots.is_some() { Path::new("") } else { base };
+    into_tracker(nodes, base).map(move |node| {
+        let mut node = node?;
+        if let (Some(roots), Some(path)) = (&roots, &node.path) {
+            node.path = Some(roots.resolve(path));
+        }
+        Ok(node)
+    })
+}
+
+/// A limit on how many files are hashed at once, which can be shared
+/// between several updates running at the same time, so that together
+/// they don't overwhelm the machine.
+pub struct HashPool {
+    available: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl HashPool {
+    /// Construct a pool that allows `size` files to be hashed at once.
+    pub fn new(size: usize) -> HashPool {
+        HashPool {
+            available: Mutex::new(size.max(1)),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Wait until a file can be hashed.  The slot is given back when the
+    /// permit is dropped.
+    fn acquire(&self) -> PoolPermit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.cond.wait(available).unwrap();
+        }
+        *available -= 1;
+        PoolPermit(self)
+    }
+}
+
+struct PoolPermit<'a>(&'a HashPool);
+
+impl<'a> Drop for PoolPermit<'a> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.cond.notify_one();
+    }
 }
 
 pub struct HashMerger<S> {
     source: S,
-    conn: Connection,
-    // Own the temp, so it won't be deleted until the connection is also
+    algorithms: Vec<HashAlgorithm>,
+    hashes: HashBuffer,
+    // Own the temp, so it won't be deleted until the buffer is also
     // closed.
     _temp: Box<dyn TempCleaner>,
+    cancel: Option<CancellationToken>,
 }
 
-impl<'a, S: Source> HashUpdater<'a, S> {
-    pub fn new(source: S, store: &dyn Store) -> HashUpdater<S> {
-        HashUpdater { source, store }
+impl<'a, S> HashUpdater<'a, S> {
+    pub fn new(source: S, store: &dyn Store) -> HashUpdater<'_, S> {
+        HashUpdater {
+            source,
+            store,
+            algorithms: vec![HashAlgorithm::default()],
+            pool: None,
+            activity: None,
+            memory: None,
+            cancel: None,
+            progress: progress::default_sink(),
+            mmap: None,
+            uring: false,
+            jobs: None,
+            throttle: None,
+            background: false,
+            roots: None,
+            failures: Failures::new(),
+        }
+    }
+
+    /// Share the given pool, limiting how many files are hashed at once
+    /// across every updater using it.
+    pub fn with_pool(mut self, pool: Arc<HashPool>) -> HashUpdater<'a, S> {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Record the hashing progress in the given activity, as well as on
+    /// the progress meter.
+    pub fn with_activity(mut self, activity: Arc<Activity>) -> HashUpdater<'a, S> {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// Compute hashes with each of the given algorithms, instead of just
+    /// the default SHA-1.  Each file is still only read once.
+    pub fn with_algorithms(mut self, algorithms: &[HashAlgorithm]) -> HashUpdater<'a, S> {
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Stop hashing, and merging, once the token is cancelled, returning
+    /// `Error::Cancelled`, and wait while it is paused.
+    pub fn with_cancel(mut self, token: CancellationToken) -> HashUpdater<'a, S> {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Report the hashing progress to the given sink, instead of the
+    /// terminal.
+    pub fn with_progress(mut self, sink: Arc<dyn ProgressSink>) -> HashUpdater<'a, S> {
+        self.progress = sink;
+        self
+    }
+
+    /// Hash files of at least `size` bytes by mapping them into memory,
+    /// rather than reading them.  See `hash_open_file` for the catch.
+    pub fn with_mmap(mut self, size: u64) -> HashUpdater<'a, S> {
+        self.mmap = Some(size);
+        self
+    }
+
+    /// Hash at most this many files at once, on as many threads, rather
+    /// than one per CPU, such as to leave room for others on a shared
+    /// machine, or to keep more reads queued on storage that can take them.
+    pub fn with_jobs(mut self, jobs: usize) -> HashUpdater<'a, S> {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Read small files in batches with io_uring, on the hashing threads of
+    /// `compute_parallel` and `compute_with`.  This is experimental, and
+    /// needs the "uring" feature, on Linux; otherwise, files are read as
+    /// usual.  A batch counts as a single file in the updater's pool.
+    pub fn with_uring(mut self) -> HashUpdater<'a, S> {
+        self.uring = true;
+        self
+    }
+
+    /// Read files no faster than the throttle allows, shared with every
+    /// updater using it.
+    pub fn with_throttle(mut self, throttle: Arc<Throttle>) -> HashUpdater<'a, S> {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Hash at the lowest CPU and I/O priority, so that the hashing threads
+    /// only get what the rest of the machine leaves over.
+    pub fn with_background(mut self) -> HashUpdater<'a, S> {
+        self.background = true;
+        self
+    }
+
+    /// Find the files in the given directories, by the first part of their
+    /// paths, as a tree from `Roots::scan`, rather than under the `base`
+    /// given to compute the hashes.
+    pub fn with_roots(mut self, roots: Arc<Roots>) -> HashUpdater<'a, S> {
+        self.roots = Some(roots);
+        self
+    }
+
+    /// Record the files that can't be read here, as well as logging them.
+    pub fn with_failures(mut self, failures: Failures) -> HashUpdater<'a, S> {
+        self.failures = failures;
+        self
     }
 
+    /// Size the hashing buffers to stay within the given memory limit,
+    /// rather than for speed.
+    pub fn with_memory_limit(mut self, limit: MemoryLimit) -> HashUpdater<'a, S> {
+        self.memory = Some(limit);
+        self
+    }
+
+    /// First pass, pipelined with finding the nodes.  `produce` is run on
+    /// the calling thread, and is given a function to call with each node,
+    /// in order, as it is found.  The files among them are hashed while it
+    /// continues, and as they aren't known in advance, the totals of the
+    /// progress meter grow as they are found.  The updater's own source
+    /// isn't used, so it can be `()`, with the source given to the
+    /// returned HashMerger once the nodes are saved.
+    pub fn compute_with<F, T>(self, base: &Path, produce: F) -> Result<(HashMerger<S>, T)>
+    where
+        F: FnOnce(&mut dyn FnMut(&SureNode)) -> Result<T>,
+    {
+        let meter = Mutex::new(self.meter(0, 0));
+        let plan = MemoryPlan::new(self.memory, &self.algorithms, self.jobs);
+        let (mut hashes, temp) = HashBuffer::new(self.store, &plan)?;
+        let hashers = self.hashers(&plan, &meter);
+        let hashes_ref = &mut hashes;
+        let batch = plan.batch;
+
+        let produced = crossbeam::scope(|s| {
+            let (node_send, node_recv) = bounded(plan.queue);
+            let nodes = tracker(node_recv.into_iter().map(Ok), base, self.roots.clone());
+            let results = hashers.spawn(s, nodes, true);
+
+            // The results are stored on their own thread, as this one is
+            // busy producing the nodes.
+            let storing = s.spawn(move |_| hashes_ref.store(results.into_iter().map(Ok), batch));
+
+            // If the hashing has failed, there is nobody to send to, but
+            // the error comes from the storing thread.
+            let produced = produce(&mut |node| {
+                let _ = node_send.send(node.clone());
+            });
+            drop(node_send);
+            storing
+                .join()
+                .map_err(|e| Error::Hash(format!("{:?}", e)))??;
+            produced
+        })
+        .map_err(|e| Error::Hash(format!("{:?}", e)))??;
+        cancel::check(&self.cancel)?;
+
+        meter.lock().unwrap().flush();
+        Ok((
+            HashMerger {
+                source: self.source,
+                algorithms: self.algorithms,
+                hashes,
+                _temp: temp,
+                cancel: self.cancel,
+            },
+            produced,
+        ))
+    }
+
+    /// A progress meter for the given estimate, reporting to the updater's
+    /// sink.
+    fn meter(&self, files: u64, bytes: u64) -> Progress {
+        Progress::new(files, bytes).with_sink(self.progress.clone())
+    }
+
+    /// What the hashing threads need from the updater.
+    fn hashers<'s>(&'s self, plan: &'s MemoryPlan, meter: &'s Mutex<Progress>) -> Hashers<'s> {
+        Hashers {
+            algorithms: &self.algorithms,
+            pool: self.pool.as_deref(),
+            activity: self.activity.as_deref(),
+            cancel: self.cancel.as_ref(),
+            plan,
+            meter,
+            mmap: self.mmap,
+            uring: self.uring,
+            throttle: self.throttle.as_deref(),
+            background: self.background,
+            failures: &self.failures,
+        }
+    }
+}
+
+impl<'a, S: Source> HashUpdater<'a, S> {
     /// First pass.  Go through the source nodes, and for any that need a
     /// hash, compute the hash, and collect the results into a temporary
     /// file.  Consumes the updater, returning the HashMerger which is used
     /// to merge the hash results into a datastream.
-    pub fn compute(mut self, base: &str, estimate: &Estimate) -> Result<HashMerger<S>> {
-        let meter = Arc::new(Mutex::new(Progress::new(estimate.files, estimate.bytes)));
-        let (mut conn, temp) = self.setup_db()?;
+    pub fn compute(self, base: &Path, estimate: &Estimate) -> Result<HashMerger<S>> {
+        let meter = Arc::new(Mutex::new(self.meter(estimate.files, estimate.bytes)));
+        let plan = MemoryPlan::new(self.memory, &self.algorithms, self.jobs);
+        let (mut hashes, temp) = HashBuffer::new(self.store, &plan)?;
 
-        let (tx, rx) = sync_channel(num_cpus::get());
+        let (tx, rx) = sync_channel(plan.queue);
 
-        let iter = into_tracker(self.source.iter()?, base);
+        let iter = tracker(self.source.iter()?, base, self.roots.clone());
         let mut count = 0;
         let meter2 = meter.clone();
+        let algorithms = self.algorithms.clone();
+        let pool = self.pool.clone();
+        let activity = self.activity.clone();
+        let cancel = self.cancel.clone();
+        let buffer = plan.buffer;
+        let mmap = self.mmap;
+        let throttle = self.throttle.clone();
+        let background = self.background;
+        let failures = self.failures.clone();
         thread::spawn(move || {
+            if background {
+                throttle::background();
+            }
             for entry in iter {
+                if cancel.as_ref().is_some_and(|c| c.check().is_err()) {
+                    break;
+                }
                 let entry = entry.unwrap();
-                if entry.node.needs_hash() {
+                if entry.node.needs_hash(&algorithms) {
                     let path = entry.path.unwrap();
+                    let _permit = pool.as_ref().map(|p| p.acquire());
                     match noatime_open(&path) {
-                        Ok(mut fd) => match hash_file(&mut fd) {
-                            Ok(ref h) => {
-                                tx.send(Some(HashInfo {
-                                    id: count,
-                                    hash: h.as_ref().to_owned(),
-                                }))
-                                .unwrap();
-                            }
-                            Err(e) => {
-                                error!("Unable to hash file: '{:?}' ({})", path, e);
+                        Ok(mut fd) => match hash_open_file(
+                            &mut fd,
+                            &algorithms,
+                            buffer,
+                            mmap,
+                            throttle.as_deref(),
+                        ) {
+                            Ok(hash) => {
+                                tx.send(Some(HashInfo { id: count, hash })).unwrap();
                             }
+                            Err(e) => hash_failed(&failures, &path, e),
                         },
-                        Err(e) => {
-                            error!("Unable to open '{:?}' for hashing ({})", path, e);
-                        }
+                        Err(e) => open_failed(&failures, &path, e),
                     }
                     // println!("{} {:?}", count, entry.path);
                     count += 1;
 
-                    meter2.lock().unwrap().update(1, entry.node.size());
+                    meter2
+                        .lock()
+                        .unwrap()
+                        .update_path(1, entry.node.data_size(), &path);
+                    stats::global().add_hashed(entry.node.data_size());
+                    if let Some(activity) = &activity {
+                        activity.add(1, entry.node.data_size());
+                    }
                 }
             }
             tx.send(None).unwrap();
@@ -91,20 +391,17 @@ impl<'a, S: Source> HashUpdater<'a, S> {
 
         // The above will send Option<HashInfo> over the tx/rx channel.
         // Capture these and add them all to the database.
-        let trans = conn.transaction()?;
-        while let Some(info) = rx.recv()? {
-            trans.execute(
-                "INSERT INTO hashes (id, hash) VALUES (?1, ?2)",
-                &[&info.id as &dyn ToSql, &info.hash as &dyn ToSql],
-            )?;
-        }
-        trans.commit()?;
+        let results = iter::from_fn(|| rx.recv().map_err(Error::from).transpose());
+        hashes.store(results, plan.batch)?;
+        cancel::check(&self.cancel)?;
 
         meter.lock().unwrap().flush();
         Ok(HashMerger {
             source: self.source,
-            conn,
+            algorithms: self.algorithms,
+            hashes,
             _temp: temp,
+            cancel: self.cancel,
         })
     }
 
@@ -113,196 +410,365 @@ impl<'a, S: Source> HashUpdater<'a, S> {
     /// result into a temporary file.  Consumes the updater, returning the
     /// HashMerger which is used to merge the hash results into a
     /// datastream.
-    pub fn compute_parallel(mut self, base: &str, estimate: &Estimate) -> Result<HashMerger<S>> {
-        let meter = Arc::new(Mutex::new(Progress::new(estimate.files, estimate.bytes)));
-        let iter = into_tracker(self.source.iter()?, base);
-        let (mut conn, temp) = self.setup_db()?;
-        let trans = conn.transaction()?;
-
-        let meter2 = meter.clone();
-        crossbeam::scope(move |s| {
-            let ncpu = num_cpus::get();
+    pub fn compute_parallel(self, base: &Path, estimate: &Estimate) -> Result<HashMerger<S>> {
+        let meter = Mutex::new(self.meter(estimate.files, estimate.bytes));
+        let iter = tracker(self.source.iter()?, base, self.roots.clone());
+        let plan = MemoryPlan::new(self.memory, &self.algorithms, self.jobs);
+        let (mut hashes, temp) = HashBuffer::new(self.store, &plan)?;
+        let hashers = self.hashers(&plan, &meter);
+        let hashes_ref = &mut hashes;
 
-            // The work channel.  Single sender, multiple receivers (one
-            // for each CPU).
-            let (work_send, work_recv) = bounded(ncpu);
-
-            // The result channel.  Multiple senders, single receiver.
-            let (result_send, result_recv) = bounded(ncpu);
-
-            // This thread reads the nodes, and submits work requests for
-            // them.  This will close the channel when it finishes, as the
-            // work_send is moved in.
-            s.spawn(move |_| {
-                let mut count = 0;
-                for entry in iter {
-                    let entry = entry.unwrap(); // TODO: Handle error.
-                    if entry.node.needs_hash() {
-                        let path = entry.path.unwrap();
-                        work_send
-                            .send(HashWork {
-                                id: count,
-                                path,
-                                size: entry.node.size(),
-                            })
-                            .unwrap();
-                        count += 1;
-                    }
-                }
-            });
-
-            // Fire off a thread for each worker.
-            for _ in 0..ncpu {
-                let work_recv = work_recv.clone();
-                let result_send = result_send.clone();
-                let meter2 = meter2.clone();
-                s.spawn(move |_| {
-                    for work in work_recv {
-                        hash_one_file(&work, &result_send, &meter2);
-                    }
-                });
-            }
-            drop(result_send);
+        crossbeam::scope(|s| {
+            let results = hashers.spawn(s, iter, false);
 
             // And, in the main thread, take all of the results, and add
             // them to the sql database.
-            for info in result_recv {
-                trans
-                    .execute(
-                        "INSERT INTO hashes (id, hash) VALUES (?1, ?2)",
-                        &[&info.id as &dyn ToSql, &info.hash as &dyn ToSql],
-                    )
-                    .unwrap();
-            }
-            trans.commit()?;
-            ok_result()
+            hashes_ref.store(results.into_iter().map(Ok), plan.batch)
         })
         .map_err(|e| Error::Hash(format!("{:?}", e)))??;
+        cancel::check(&self.cancel)?;
 
         meter.lock().unwrap().flush();
         Ok(HashMerger {
             source: self.source,
-            conn,
+            algorithms: self.algorithms,
+            hashes,
             _temp: temp,
+            cancel: self.cancel,
         })
     }
+}
 
-    /// Set up the sqlite database to hold the hash updates.
-    fn setup_db(&mut self) -> Result<(Connection, Box<dyn TempCleaner>)> {
-        // Create the temp file.  Discard the file so that it will be
-        // closed.
-        let tmp = self.store.make_temp()?.into_loader()?;
-        let conn = Connection::open(tmp.path_ref())?;
-        conn.execute(
-            "CREATE TABLE hashes (
-                id INTEGER PRIMARY KEY,
-                hash BLOB)",
-            [],
-        )?;
+/// The parts of a HashUpdater shared by its hashing threads.
+#[derive(Clone, Copy)]
+struct Hashers<'a> {
+    algorithms: &'a [HashAlgorithm],
+    pool: Option<&'a HashPool>,
+    activity: Option<&'a Activity>,
+    cancel: Option<&'a CancellationToken>,
+    plan: &'a MemoryPlan,
+    meter: &'a Mutex<Progress>,
+    mmap: Option<u64>,
+    uring: bool,
+    throttle: Option<&'a Throttle>,
+    background: bool,
+    failures: &'a Failures,
+}
 
-        Ok((conn, tmp.into_cleaner()?))
+impl<'a> Hashers<'a> {
+    /// Start hashing the files among the given nodes, on threads of the
+    /// scope, returning the channel the results come back on.  If
+    /// `growing`, the files aren't known in advance, and are added to the
+    /// totals as they are found.
+    fn spawn<I>(self, s: &Scope<'a>, iter: I, growing: bool) -> Receiver<HashInfo>
+    where
+        I: Iterator<Item = Result<PathedNode>> + Send + 'a,
+    {
+        let hashers = self;
+        let Hashers {
+            algorithms,
+            pool,
+            activity,
+            cancel,
+            plan,
+            meter,
+            uring,
+            background,
+            ..
+        } = self;
+        // Waits while paused.
+        let cancelled = move || cancel.is_some_and(|c| c.check().is_err());
+
+        // The work channel.  Single sender, multiple receivers (one
+        // for each worker).
+        let (work_send, work_recv) = bounded(plan.queue);
+
+        // The result channel.  Multiple senders, single receiver.
+        let (result_send, result_recv) = bounded(plan.queue);
+
+        // This thread reads the nodes, and submits work requests for
+        // them.  This will close the channel when it finishes, as the
+        // work_send is moved in.
+        s.spawn(move |_| {
+            let mut count = 0;
+            for entry in iter {
+                if cancelled() {
+                    break;
+                }
+                let entry = entry.unwrap(); // TODO: Handle error.
+                if entry.node.needs_hash(algorithms) {
+                    let path = entry.path.unwrap();
+                    let size = entry.node.size();
+                    let data = entry.node.data_size();
+                    if growing {
+                        meter.lock().unwrap().add_totals(1, data);
+                        if let Some(activity) = activity {
+                            activity.add_totals(1, data);
+                        }
+                    }
+                    work_send
+                        .send(HashWork {
+                            id: count,
+                            path,
+                            size,
+                            data,
+                        })
+                        .unwrap();
+                    count += 1;
+                }
+            }
+        });
+
+        let hashed = move |work: &HashWork| {
+            stats::global().add_hashed(work.data);
+            if let Some(activity) = activity {
+                activity.add(1, work.data);
+            }
+        };
+
+        // Fire off a thread for each worker, normally one per CPU.
+        for _ in 0..plan.workers {
+            let work_recv = work_recv.clone();
+            let result_send = result_send.clone();
+            s.spawn(move |_| {
+                if background {
+                    throttle::background();
+                }
+                let mut reader = if uring {
+                    UringReader::new(plan.reads)
+                        .map_err(|e| {
+                            static WARNED: Once = Once::new();
+                            WARNED.call_once(|| {
+                                warn!("Unable to use io_uring, reading as usual ({})", e)
+                            });
+                        })
+                        .ok()
+                } else {
+                    None
+                };
+                let small = |work: &HashWork| work.size < uring::SMALL;
+                for work in work_recv.iter() {
+                    // Drain the queued work, without hashing it.
+                    if cancelled() {
+                        continue;
+                    }
+                    let _permit = pool.map(|p| p.acquire());
+                    let batched = match reader.as_mut() {
+                        Some(reader) if small(&work) => reader,
+                        _ => {
+                            hash_one_file(&hashers, &work, &result_send);
+                            hashed(&work);
+                            continue;
+                        }
+                    };
+
+                    // Gather the small files already queued, up to a large one.
+                    let mut batch = vec![work];
+                    let mut large = None;
+                    while batch.len() < batched.depth() && large.is_none() {
+                        match work_recv.try_recv() {
+                            Ok(work) if small(&work) => batch.push(work),
+                            Ok(work) => large = Some(work),
+                            Err(_) => break,
+                        }
+                    }
+                    if let Err(e) = hash_batch(&hashers, batched, &batch, &result_send) {
+                        // Give up on the reader, and read the batch as usual.
+                        warn!("Unable to use io_uring, reading as usual ({})", e);
+                        reader = None;
+                        batch
+                            .iter()
+                            .for_each(|work| hash_one_file(&hashers, work, &result_send));
+                    }
+                    batch.iter().for_each(hashed);
+                    if let Some(work) = large {
+                        hash_one_file(&hashers, &work, &result_send);
+                        hashed(&work);
+                    }
+                }
+            });
+        }
+
+        result_recv
     }
 }
 
-fn hash_one_file(work: &HashWork, sender: &Sender<HashInfo>, meter: &Arc<Mutex<Progress>>) {
+fn hash_one_file(hashers: &Hashers, work: &HashWork, sender: &Sender<HashInfo>) {
     match noatime_open(&work.path) {
-        Ok(mut fd) => match hash_file(&mut fd) {
-            Ok(ref h) => {
-                sender
-                    .send(HashInfo {
-                        id: work.id,
-                        hash: h.as_ref().to_owned(),
-                    })
-                    .unwrap();
-            }
-            Err(e) => {
-                error!("Unable to hash file: '{:?}' ({})", work.path, e);
+        Ok(mut fd) => match hash_open_file(
+            &mut fd,
+            hashers.algorithms,
+            hashers.plan.buffer,
+            hashers.mmap,
+            hashers.throttle,
+        ) {
+            Ok(hash) => {
+                sender.send(HashInfo { id: work.id, hash }).unwrap();
             }
+            Err(e) => hash_failed(hashers.failures, &work.path, e),
         },
-        Err(e) => {
-            error!("Unable to open '{:?}' for hashing ({})", work.path, e);
-        }
+        Err(e) => open_failed(hashers.failures, &work.path, e),
     }
-    meter.lock().unwrap().update(1, work.size);
+    hashers
+        .meter
+        .lock()
+        .unwrap()
+        .update_path(1, work.data, &work.path);
 }
 
-// To make it easier to return a typed result.
-fn ok_result() -> Result<()> {
+/// Log, and record, a file that couldn't be read to hash it.
+fn hash_failed(failures: &Failures, path: &Path, e: impl fmt::Display) {
+    error!("Unable to hash file: '{:?}' ({})", path, e);
+    failures.add(path, format!("Unable to hash file ({})", e));
+}
+
+/// Log, and record, a file that couldn't be opened to hash it.
+fn open_failed(failures: &Failures, path: &Path, e: impl fmt::Display) {
+    error!("Unable to open '{:?}' for hashing ({})", path, e);
+    failures.add(path, format!("Unable to open for hashing ({})", e));
+}
+
+/// Hash a batch of small files, reading them all at once.  An error is from
+/// the reader, before any of the files were hashed.
+fn hash_batch(
+    hashers: &Hashers,
+    reader: &mut UringReader,
+    batch: &[HashWork],
+    sender: &Sender<HashInfo>,
+) -> Result<()> {
+    let paths: Vec<&Path> = batch.iter().map(|work| work.path.as_path()).collect();
+    for (work, hash) in
+        batch
+            .iter()
+            .zip(reader.hash_files(&paths, hashers.algorithms, hashers.plan.buffer)?)
+    {
+        match hash {
+            Ok(hash) => {
+                sender.send(HashInfo { id: work.id, hash }).unwrap();
+            }
+            Err(e) => hash_failed(hashers.failures, &work.path, e),
+        }
+        hashers
+            .meter
+            .lock()
+            .unwrap()
+            .update_path(1, work.data, &work.path);
+    }
+    // The files were all read at once, so they are paid for together.
+    if let Some(throttle) = hashers.throttle {
+        throttle.consume(batch.iter().map(|work| work.data).sum());
+    }
     Ok(())
 }
 
+impl<S> HashMerger<S> {
+    /// Give the merger the source to merge the hashes into, such as once
+    /// the nodes given to `HashUpdater::compute_with` have been saved.
+    pub fn with_source<T>(self, source: T) -> HashMerger<T> {
+        HashMerger {
+            source,
+            algorithms: self.algorithms,
+            hashes: self.hashes,
+            _temp: self._temp,
+            cancel: self.cancel,
+        }
+    }
+}
+
 impl<S: Source> HashMerger<S> {
-    /// Second pass.  Merge the updated hashes back into the data.  Note
-    /// that this is 'push' based instead of 'pull' because there is a
-    /// chain of lifetime dependencies from Connection->Statement->Rows and
-    /// if we tried to return something holding the Rows iterator, the user
-    /// would have to manage these lifetimes.
+    /// Second pass.  Merge the updated hashes back into the data, giving
+    /// the nodes of the source with their new hashes, which can be
+    /// filtered or examined before they are written.
+    pub fn iter(self) -> Result<MergeIter> {
+        Ok(MergeIter {
+            nodes: self.source.iter()?,
+            hashes: self.hashes.into_sorted()?.peekable(),
+            algorithms: self.algorithms,
+            count: 0,
+            cancel: self.cancel,
+            _temp: self._temp,
+        })
+    }
+
+    /// Second pass, writing the merged nodes to the writer.
     pub fn merge<W: Write>(self, writer: &mut NodeWriter<W>) -> Result<()> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, hash FROM hashes ORDER BY id")?;
-        let mut hash_iter = stmt
-            .query_map([], |row| {
-                Ok(HashInfo {
-                    id: row.get(0)?,
-                    hash: row.get(1)?,
-                })
-            })?
-            .peekable();
+        for node in self.iter()? {
+            writer.write_node(&node?)?;
+        }
+        Ok(())
+    }
+}
 
-        let mut count = 0;
-        for entry in self.source.iter()? {
-            let mut entry = entry?;
-            if entry.needs_hash() {
-                let hnode = loop {
-                    match hash_iter.peek() {
-                        Some(Ok(hnode)) => {
-                            match count.cmp(&hnode.id) {
-                                Ordering::Equal => {
-                                    let node = hash_iter.next().unwrap()?;
-                                    break Some(node);
-                                }
-                                Ordering::Less => {
-                                    // Node not present in hash, means we
-                                    // weren't able to compute a hash of the
-                                    // file.
-                                    break None;
-                                }
-                                _ => panic!("Out of sequence hash"),
-                            }
-                        }
-                        Some(Err(e)) => {
-                            return Err(Error::WrappedSql(format!("{:?}", e)));
-                        }
-                        None => break None,
-                    }
-                };
+/// The nodes of a source, with the hashes computed by a HashUpdater merged
+/// into them.
+pub struct MergeIter {
+    nodes: Box<dyn Iterator<Item = Result<SureNode>> + Send>,
+    hashes: Peekable<Box<dyn Iterator<Item = Result<HashInfo>>>>,
+    algorithms: Vec<HashAlgorithm>,
+    /// The number of the next node needing a hash.
+    count: i64,
+    cancel: Option<CancellationToken>,
+    _temp: Box<dyn TempCleaner>,
+}
 
-                if let Some(HashInfo { hash, .. }) = &hnode {
-                    let hex = HEXLOWER.encode(hash);
-                    entry.atts_mut().unwrap().insert("sha1".to_string(), hex);
+impl MergeIter {
+    fn merge_one(&mut self, mut entry: SureNode) -> Result<SureNode> {
+        if !entry.needs_hash(&self.algorithms) {
+            return Ok(entry);
+        }
+        let hnode = match self.hashes.peek() {
+            Some(Ok(hnode)) => {
+                match self.count.cmp(&hnode.id) {
+                    Ordering::Equal => Some(self.hashes.next().unwrap()?),
+                    Ordering::Less => {
+                        // Node not present in hash, means we weren't able
+                        // to compute a hash of the file.
+                        None
+                    }
+                    _ => panic!("Out of sequence hash"),
                 }
+            }
+            Some(Err(_)) => return Err(self.hashes.next().unwrap().unwrap_err()),
+            None => None,
+        };
 
-                count += 1;
+        if let Some(HashInfo { hash, .. }) = &hnode {
+            // The digests are stored concatenated, in the order of the
+            // algorithms.
+            let atts = entry.atts_mut().unwrap();
+            let mut rest = &hash[..];
+            for algorithm in &self.algorithms {
+                let (digest, tail) = rest.split_at(algorithm.size());
+                atts.insert(algorithm.name().to_string(), HEXLOWER.encode(digest));
+                rest = tail;
             }
-            writer.write_node(&entry)?;
-            // println!("{:?}", entry);
         }
 
-        Ok(())
+        self.count += 1;
+        Ok(entry)
     }
 }
 
-#[derive(Debug)]
-struct HashInfo {
-    id: i64,
-    hash: Vec<u8>,
+impl Iterator for MergeIter {
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        if let Err(e) = cancel::check(&self.cancel) {
+            return Some(Err(e));
+        }
+        match self.nodes.next()? {
+            Ok(entry) => Some(self.merge_one(entry)),
+            Err(e) => Some(Err(e)),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct HashWork {
     id: i64,
     size: u64,
+    // The bytes read, less than the size for a sparse file.
+    data: u64,
     path: PathBuf,
 }
 
@@ -325,6 +791,11 @@ pub struct HashCombiner<Iold: Iterator, Inew: Iterator> {
 
     state: Vec<CombineState>,
     seen_root: bool,
+
+    /// The hashes to carry over from the old tree.
+    algorithms: Vec<HashAlgorithm>,
+    /// When to carry them over.
+    reuse: HashReuse,
 }
 
 #[derive(Debug)]
@@ -368,9 +839,25 @@ where
             right_iter,
             state: vec![],
             seen_root: false,
+            algorithms: vec![HashAlgorithm::default()],
+            reuse: HashReuse::default(),
         })
     }
 
+    /// Carry over hashes of each of the given algorithms, instead of just
+    /// the default SHA-1.
+    pub fn with_algorithms(mut self, algorithms: &[HashAlgorithm]) -> HashCombiner<Iold, Inew> {
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Carry over hashes only as the given policy allows, instead of
+    /// whenever the file's metadata is unchanged.
+    pub fn with_reuse(mut self, reuse: HashReuse) -> HashCombiner<Iold, Inew> {
+        self.reuse = reuse;
+        self
+    }
+
     /// Advance the left iterator, replacing 'left' with the new value, and
     /// returning that old value.  Returns the error from the iterator if
     /// that happened.  If we see the end of the iterator, places 'Leave'
@@ -424,7 +911,7 @@ macro_rules! vro {
 // The iterator for the hash combiner.  This iterator lazily traverses two
 // iterators that are assumed to be and old and new traversal of the same
 // filesystem.  The output will be the same nodes as the new, but possibly
-// with 'sha1' values carried over from the old tree when there is a
+// with hash values carried over from the old tree when there is a
 // sufficient match.
 impl<Iold, Inew> Iterator for HashCombiner<Iold, Inew>
 where
@@ -497,7 +984,7 @@ where
             (false, false) => {
                 // We are still visiting directories.  Assume it is well
                 // formed, and we are only going to see Enter nodes.
-                match self.left.name().cmp(&self.right.name()) {
+                match self.left.name().cmp(self.right.name()) {
                     Ordering::Equal => {
                         // This is the same directory, descend it.
                         self.state.push(CombineState::SameDirs);
@@ -562,11 +1049,13 @@ where
                 self.state.push(CombineState::SameFiles);
 
                 // Two names within a directory.
-                match self.left.name().cmp(&self.right.name()) {
+                match self.left.name().cmp(self.right.name()) {
                     Ordering::Equal => {
                         let left = self.next_left()?;
                         let mut right = self.next_right()?;
-                        maybe_copy_sha(&left, &mut right);
+                        if self.reuse == HashReuse::Metadata {
+                            maybe_copy_sha(&left, &mut right, &self.algorithms);
+                        }
                         vro!(right)
                     }
                     Ordering::Less => {
@@ -623,30 +1112,33 @@ where
     }
 }
 
-fn maybe_copy_sha(left: &SureNode, right: &mut SureNode) {
+fn maybe_copy_sha(left: &SureNode, right: &mut SureNode, algorithms: &[HashAlgorithm]) {
     let latts = left.atts().unwrap();
     let ratts = right.atts_mut().unwrap();
 
-    // If we already have a sha1, don't do anything.
-    if ratts.contains_key("sha1") {
-        return;
-    }
-
     // Only compare regular files.
     if latts["kind"] != "file" || ratts["kind"] != "file" {
         return;
     }
 
-    // Make sure inode and ctime are identical.
-    if latts.get("ino") != ratts.get("ino") || latts.get("ctime") != ratts.get("ctime") {
+    // Make sure inode and ctime are identical.  Where those aren't
+    // recorded, such as on Windows, the size and mtime have to do.
+    if ["ino", "ctime", "mtime", "size"]
+        .iter()
+        .any(|&key| latts.get(key) != ratts.get(key))
+    {
         return;
     }
 
-    // And only update if there is a sha1 to get.
-    match latts.get("sha1") {
-        None => (),
-        Some(v) => {
-            ratts.insert("sha1".to_string(), v.to_string());
+    // And only update the hashes there are to get, and that we don't
+    // already have.
+    for algorithm in algorithms {
+        let key = algorithm.name();
+        if ratts.contains_key(key) {
+            continue;
+        }
+        if let Some(v) = latts.get(key) {
+            ratts.insert(key.to_string(), v.to_string());
         }
     }
 }
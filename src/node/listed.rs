@@ -0,0 +1,409 @@
+//! Updating just the paths in a list.
+//!
+//! When something else already knows what changed in a tree, such as a
+//! deployment tool, or `rsure export-changed`, an update can look at just
+//! those paths, carrying every other node forward from the latest version,
+//! hash and all, rather than scanning the whole tree.  Each listed path
+//! that is still there is stat'ed again, and a listed file is hashed again;
+//! one that is gone is removed.  A listed path in a directory that wasn't in
+//! the latest version adds that directory as well, but nothing in it that
+//! isn't listed.
+
+use crate::{
+    escape::{Escape, Unescape},
+    exclude::Exclude,
+    node::{NodeWriter, SureNode, RECORDED_ATT},
+    platform::{os_bytes, os_from_bytes},
+    surefs::{encode_atts, flags_atts, link_target_atts, recorded, selinux_atts},
+    suretree::AttMap,
+    Error, Estimate, HashAlgorithm, Result, ScanOptions,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, symlink_metadata, Metadata},
+    io::{BufRead, BufReader, Write},
+    iter::Peekable,
+    path::{Component, Path, PathBuf},
+};
+
+/// The paths to look at, relative to the top of the tree.
+#[derive(Clone, Debug, Default)]
+pub struct PathList {
+    root: Listed,
+    len: usize,
+}
+
+/// A directory of the list, or a path in it.
+#[derive(Clone, Debug, Default)]
+struct Listed {
+    /// Was this path itself listed, or only things in it?
+    listed: bool,
+    /// By name, in the order the scanner writes them.
+    children: BTreeMap<Vec<u8>, Listed>,
+}
+
+impl PathList {
+    pub fn new() -> PathList {
+        PathList::default()
+    }
+
+    /// Read a list of paths from a file, as `read`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<PathList> {
+        PathList::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Read a list of paths, one per line, or each ending with a NUL, if
+    /// there are any, as `rsure export-changed -0` and `find -print0`
+    /// write them.  Blank lines are skipped.
+    pub fn read<R: BufRead>(mut input: R) -> Result<PathList> {
+        let mut text = vec![];
+        input.read_to_end(&mut text)?;
+        let end = if text.contains(&0) { 0 } else { b'\n' };
+        let mut list = PathList::new();
+        for path in text.split(|&ch| ch == end) {
+            let path = if end == b'\n' {
+                path.strip_suffix(b"\r").unwrap_or(path)
+            } else {
+                path
+            };
+            if !path.is_empty() {
+                list.insert(os_from_bytes(path.to_vec()))?;
+            }
+        }
+        Ok(list)
+    }
+
+    /// Add a path, relative to the top of the tree, such as "etc/passwd",
+    /// or "." for the top itself.
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut node = &mut self.root;
+        for part in path.components() {
+            match part {
+                Component::CurDir => (),
+                Component::Normal(name) => {
+                    node = node
+                        .children
+                        .entry(os_bytes(name).into_owned())
+                        .or_default();
+                }
+                _ => return Err(Error::InvalidPathList(path.display().to_string())),
+            }
+        }
+        if !node.listed {
+            node.listed = true;
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// The number of paths listed.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Write the nodes of the tree at `dir` to `out`: the `latest` nodes,
+    /// with those of the listed paths looked at again.  The listed files
+    /// have no hash, to be hashed by a `HashUpdater`, and the returned
+    /// estimate is of those needing one with the given algorithms.  The
+    /// exclude patterns, `hash_link_targets`, `selinux` and `flags`, of the
+    /// options are used for the listed paths.
+    pub fn apply<I, W>(
+        &self,
+        dir: &Path,
+        latest: I,
+        options: &ScanOptions,
+        algorithms: &[HashAlgorithm],
+        out: &mut NodeWriter<W>,
+    ) -> Result<Estimate>
+    where
+        I: Iterator<Item = Result<SureNode>>,
+        W: Write,
+    {
+        let mut exclude = options.exclude.clone();
+        exclude.add_root(dir)?;
+        let mut walk = Walk {
+            old: latest.peekable(),
+            root: dir,
+            exclude,
+            hash_link_targets: options.hash_link_targets,
+            selinux: options.selinux,
+            flags: options.flags,
+            algorithms,
+            estimate: Estimate { files: 0, bytes: 0 },
+            out,
+        };
+
+        let atts = match walk.next()? {
+            SureNode::Enter { atts, .. } => atts,
+            _ => return Err(Error::UnexpectedNode),
+        };
+        let atts = if self.root.listed {
+            let meta = symlink_metadata(dir)?;
+            dir_atts(dir, &meta, &atts, options.selinux, options.flags)
+        } else {
+            atts
+        };
+        walk.write(SureNode::Enter {
+            name: "__root__".to_string(),
+            atts: atts.clone(),
+        })?;
+        walk.dir(dir, &self.root, &atts, true)?;
+        Ok(walk.estimate)
+    }
+}
+
+/// A listed path, as it is now.
+struct Entry<'l> {
+    name: &'l [u8],
+    listed: &'l Listed,
+    path: PathBuf,
+    /// None if it is gone, or excluded.
+    meta: Option<Metadata>,
+}
+
+impl<'l> Entry<'l> {
+    fn is_dir(&self) -> bool {
+        self.meta.as_ref().is_some_and(|m| m.is_dir())
+    }
+}
+
+struct Walk<'a, I: Iterator, W: Write> {
+    old: Peekable<I>,
+    root: &'a Path,
+    exclude: Exclude,
+    hash_link_targets: bool,
+    selinux: bool,
+    flags: bool,
+    algorithms: &'a [HashAlgorithm],
+    estimate: Estimate,
+    out: &'a mut NodeWriter<W>,
+}
+
+impl<'a, I, W> Walk<'a, I, W>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    W: Write,
+{
+    fn next(&mut self) -> Result<SureNode> {
+        self.old.next().unwrap_or(Err(Error::TruncatedSurefile))
+    }
+
+    /// The next old node, which stays the next.
+    fn peek(&mut self) -> Result<&SureNode> {
+        if let Some(Err(_)) = self.old.peek() {
+            return Err(self.next().unwrap_err());
+        }
+        match self.old.peek() {
+            Some(Ok(node)) => Ok(node),
+            _ => Err(Error::TruncatedSurefile),
+        }
+    }
+
+    /// The name of the next old node, if it is a directory, or a file.
+    fn peek_name(&mut self, dir: bool) -> Result<Option<Vec<u8>>> {
+        match self.peek()? {
+            SureNode::Enter { name, .. } if dir => Ok(Some(unescape(name)?)),
+            SureNode::File { name, .. } if !dir => Ok(Some(unescape(name)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn write(&mut self, node: SureNode) -> Result<()> {
+        if node.needs_hash(self.algorithms) {
+            self.estimate.files += 1;
+            self.estimate.bytes += node.data_size();
+        }
+        self.out.write_node(&node)
+    }
+
+    /// Copy, or skip, the rest of an old directory, up to and including its
+    /// Leave.
+    fn rest_of_dir(&mut self, copy: bool) -> Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            let node = self.next()?;
+            match node {
+                SureNode::Enter { .. } => depth += 1,
+                SureNode::Leave => depth -= 1,
+                _ => (),
+            }
+            if copy {
+                self.write(node)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The contents of the directory at `path`, once its Enter has been
+    /// written, along with its Leave.  Its old contents, if it `has_old`
+    /// ones, are the next old nodes.
+    fn dir(&mut self, path: &Path, listed: &Listed, atts: &AttMap, has_old: bool) -> Result<()> {
+        let mut entries = vec![];
+        for (name, child) in &listed.children {
+            let child_path = path.join(os_from_bytes(name.clone()));
+            let meta = symlink_metadata(&child_path).ok().filter(|meta| {
+                let rel = child_path.strip_prefix(self.root).unwrap_or(&child_path);
+                self.exclude.matching(rel, meta.is_dir()).is_none()
+            });
+            entries.push(Entry {
+                name,
+                listed: child,
+                path: child_path,
+                meta,
+            });
+        }
+
+        // The subdirectories.
+        let mut dirs = entries.iter().filter(|e| e.is_dir()).peekable();
+        if has_old {
+            while let Some(name) = self.peek_name(true)? {
+                while let Some(entry) = dirs.next_if(|e| e.name < &name[..]) {
+                    self.new_dir(entry, atts)?;
+                }
+                let entry = match entries.iter().find(|e| e.name == &name[..]) {
+                    Some(entry) => entry,
+                    None => {
+                        let node = self.next()?;
+                        self.write(node)?;
+                        self.rest_of_dir(true)?;
+                        continue;
+                    }
+                };
+                let (name, old_atts) = match self.next()? {
+                    SureNode::Enter { name, atts } => (name, atts),
+                    _ => unreachable!(),
+                };
+                match &entry.meta {
+                    Some(meta) if meta.is_dir() => {
+                        dirs.next();
+                        let atts = if entry.listed.listed {
+                            dir_atts(&entry.path, meta, &old_atts, self.selinux, self.flags)
+                        } else {
+                            old_atts
+                        };
+                        self.write(SureNode::Enter {
+                            name,
+                            atts: atts.clone(),
+                        })?;
+                        self.dir(&entry.path, entry.listed, &atts, true)?;
+                    }
+                    // Gone, or no longer a directory, and in with the files.
+                    _ => self.rest_of_dir(false)?,
+                }
+            }
+        }
+        for entry in dirs {
+            self.new_dir(entry, atts)?;
+        }
+        if has_old {
+            match self.next()? {
+                SureNode::Sep => (),
+                _ => return Err(Error::UnexpectedNode),
+            }
+        }
+        self.write(SureNode::Sep)?;
+
+        // The files.
+        let mut files = entries
+            .iter()
+            .filter(|e| e.listed.listed && e.meta.is_some() && !e.is_dir())
+            .peekable();
+        if has_old {
+            while let Some(name) = self.peek_name(false)? {
+                while let Some(entry) = files.next_if(|e| e.name < &name[..]) {
+                    self.new_file(entry)?;
+                }
+                let node = self.next()?;
+                match entries.iter().find(|e| e.name == &name[..]) {
+                    Some(entry) if entry.listed.listed || entry.is_dir() => {
+                        // Written as a new file, unless it is gone, or now a directory.
+                        if files.next_if(|e| e.name == &name[..]).is_some() {
+                            self.new_file(entry)?;
+                        }
+                    }
+                    _ => self.write(node)?,
+                }
+            }
+            match self.next()? {
+                SureNode::Leave => (),
+                _ => return Err(Error::UnexpectedNode),
+            }
+        }
+        for entry in files {
+            self.new_file(entry)?;
+        }
+        self.write(SureNode::Leave)
+    }
+
+    /// A directory that wasn't in the latest version, with just what is
+    /// listed in it.
+    fn new_dir(&mut self, entry: &Entry, parent: &AttMap) -> Result<()> {
+        // It is taken to be on the same filesystem as its parent.
+        let mut inherited = parent.clone();
+        inherited.remove("mount");
+        let atts = dir_atts(
+            &entry.path,
+            entry.meta.as_ref().unwrap(),
+            &inherited,
+            self.selinux,
+            self.flags,
+        );
+        self.write(SureNode::Enter {
+            name: entry.name.escaped(),
+            atts: atts.clone(),
+        })?;
+        self.dir(&entry.path, entry.listed, &atts, false)
+    }
+
+    fn new_file(&mut self, entry: &Entry) -> Result<()> {
+        let mut atts = encode_atts(&entry.path, entry.meta.as_ref().unwrap());
+        if self.hash_link_targets && entry.meta.as_ref().unwrap().file_type().is_symlink() {
+            link_target_atts(&entry.path, &mut atts);
+        }
+        if self.selinux {
+            selinux_atts(&entry.path, &mut atts);
+        }
+        if self.flags {
+            flags_atts(&entry.path, entry.meta.as_ref().unwrap(), &mut atts);
+        }
+        self.write(SureNode::File {
+            name: entry.name.escaped(),
+            atts,
+        })
+    }
+}
+
+fn unescape(name: &str) -> Result<Vec<u8>> {
+    name.unescape()
+        .map_err(|_| Error::InvalidEscape(name.to_string()))
+}
+
+/// The attributes of a directory, stat'ed again, keeping those from its
+/// filesystem that the scanner adds.  As its files aren't all scanned
+/// again, only what both the old and new scans of them looked for is
+/// recorded.
+fn dir_atts(path: &Path, meta: &Metadata, old: &AttMap, selinux: bool, flags: bool) -> AttMap {
+    let mut atts = encode_atts(path, meta);
+    if selinux {
+        selinux_atts(path, &mut atts);
+    }
+    if flags {
+        flags_atts(path, meta, &mut atts);
+    }
+    for key in &["fstype", "mount"] {
+        if let Some(value) = old.get(*key) {
+            atts.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(before) = old.get(RECORDED_ATT) {
+        let now = recorded(flags);
+        let both: Vec<_> = before.split(',').filter(|att| now.contains(att)).collect();
+        atts.insert(RECORDED_ATT.to_string(), both.join(","));
+    }
+    atts
+}
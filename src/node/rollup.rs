@@ -0,0 +1,209 @@
+//! Rollup hashes of directories.
+//!
+//! Each directory written by an update gets a "rollup", a hash of the names
+//! and contents of what is in it: the hashes of its files, the text of its
+//! symlinks, and the rollups of its subdirectories.  Two directories with
+//! the same rollup hold the same files, so whether anything under, say,
+//! /etc changed between two versions is a matter of comparing one value
+//! from each, found with [`rollup`], rather than walking every file.  The
+//! other attributes, such as owners and times, are not part of it.
+//!
+//! A directory's rollup is only known once all of it has gone by, but it is
+//! written with the directory, before what is in it.  So the rollups are
+//! computed as the nodes are merged with their hashes, into a temp file,
+//! and added as that is written to the store.
+
+use crate::{
+    node::{subtree, SureNode},
+    HashAlgorithm, Result,
+};
+use data_encoding::HEXLOWER;
+use openssl::sha::Sha256;
+use std::{fmt::Write, path::Path, vec};
+
+/// The attribute holding a directory's rollup.
+pub const ROLLUP_ATT: &str = "rollup";
+
+/// The rollup of the directory at `path`, relative to the top of the tree,
+/// reading no more of the tree than it takes to get to it.  Returns None if
+/// the tree has no such directory, or it has no rollup, as in versions
+/// written before rollups were.
+pub fn rollup<I>(nodes: I, path: &Path) -> Result<Option<String>>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    match subtree(nodes, path)? {
+        Some(mut dir) => match dir.next().transpose()? {
+            Some(SureNode::Enter { atts, .. }) => Ok(atts.get(ROLLUP_ATT).cloned()),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The rollups of the directories of a tree, computed as its nodes go by.
+pub(crate) struct Rollups {
+    algorithms: Vec<HashAlgorithm>,
+    /// The rollup of each directory, in the order they are entered.
+    done: Vec<[u8; 32]>,
+    /// The directories being gone through, with where their rollups go.
+    open: Vec<(usize, String, Sha256)>,
+}
+
+impl Rollups {
+    /// Rollups made from the file hashes of the given algorithms.
+    pub(crate) fn new(algorithms: &[HashAlgorithm]) -> Rollups {
+        Rollups {
+            algorithms: algorithms.to_vec(),
+            done: vec![],
+            open: vec![],
+        }
+    }
+
+    /// Take in the next node of the tree.
+    pub(crate) fn add(&mut self, node: &SureNode) {
+        match node {
+            SureNode::Enter { name, .. } => {
+                self.open
+                    .push((self.done.len(), name.clone(), Sha256::new()));
+                self.done.push([0; 32]);
+            }
+            SureNode::Leave => {
+                if let Some((index, name, hasher)) = self.open.pop() {
+                    let digest = hasher.finish();
+                    self.done[index] = digest;
+                    if let Some((_, _, parent)) = self.open.last_mut() {
+                        let line = format!("dir {} {}\n", name, HEXLOWER.encode(&digest));
+                        parent.update(line.as_bytes());
+                    }
+                }
+            }
+            SureNode::File { name, atts } => {
+                if let Some((_, _, parent)) = self.open.last_mut() {
+                    let kind = atts.get("kind").map_or("file", |kind| kind.as_str());
+                    let mut line = format!("{} {}", kind, name);
+                    for alg in &self.algorithms {
+                        if let Some(hash) = atts.get(alg.name()) {
+                            write!(line, " {}={}", alg.name(), hash).unwrap();
+                        }
+                    }
+                    if let Some(targ) = atts.get("targ") {
+                        write!(line, " targ={}", targ).unwrap();
+                    }
+                    line.push('\n');
+                    parent.update(line.as_bytes());
+                }
+            }
+            SureNode::Sep => (),
+        }
+    }
+
+    /// The nodes of the same tree again, with the rollup of each directory
+    /// added to it.
+    pub(crate) fn apply<I>(&self, nodes: I) -> WithRollups<I>
+    where
+        I: Iterator<Item = Result<SureNode>>,
+    {
+        WithRollups {
+            nodes,
+            rollups: self.done.clone().into_iter(),
+        }
+    }
+}
+
+/// The nodes of a tree, with the rollups of its directories, from
+/// `Rollups::apply`.
+pub(crate) struct WithRollups<I> {
+    nodes: I,
+    rollups: vec::IntoIter<[u8; 32]>,
+}
+
+impl<I> Iterator for WithRollups<I>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        match self.nodes.next()? {
+            Ok(SureNode::Enter { name, mut atts }) => {
+                if let Some(digest) = self.rollups.next() {
+                    atts.insert(ROLLUP_ATT.to_string(), HEXLOWER.encode(&digest));
+                }
+                Some(Ok(SureNode::Enter { name, atts }))
+            }
+            node => Some(node),
+        }
+    }
+}
+
+#[test]
+fn test_rollups() {
+    use crate::suretree::AttMap;
+
+    let atts = |pairs: &[(&str, &str)]| -> AttMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    let dir = |name: &str, uid: &str| SureNode::Enter {
+        name: name.to_string(),
+        atts: atts(&[("kind", "dir"), ("uid", uid)]),
+    };
+    let file = |name: &str, sha1: &str, uid: &str| SureNode::File {
+        name: name.to_string(),
+        atts: atts(&[("kind", "file"), ("sha1", sha1), ("uid", uid)]),
+    };
+    // The top holding "etc", with "hosts" and "ssh/sshd_config" in it, and
+    // "var", with "log".
+    let tree = |config: &str, uid: &str| {
+        let nodes = vec![
+            dir("__root__", uid),
+            dir("etc", uid),
+            dir("ssh", uid),
+            SureNode::Sep,
+            file("sshd_config", config, uid),
+            SureNode::Leave,
+            SureNode::Sep,
+            file("hosts", "1111", uid),
+            SureNode::Leave,
+            dir("var", uid),
+            SureNode::Sep,
+            file("log", "2222", uid),
+            SureNode::Leave,
+            SureNode::Sep,
+            SureNode::Leave,
+        ];
+        let mut rollups = Rollups::new(&[HashAlgorithm::Sha1]);
+        for node in &nodes {
+            rollups.add(node);
+        }
+        let nodes: Vec<_> = rollups
+            .apply(nodes.into_iter().map(Ok))
+            .map(|node| node.unwrap())
+            .collect();
+        move |path: &str| {
+            rollup(nodes.clone().into_iter().map(Ok), Path::new(path))
+                .unwrap()
+                .unwrap()
+        }
+    };
+
+    let first = tree("aaaa", "0");
+    assert_eq!(first("etc").len(), 64);
+    assert_ne!(first("etc"), first("etc/ssh"));
+
+    // Only the hashes of files count, not their other attributes.
+    let owned = tree("aaaa", "1000");
+    for path in &["", "etc", "etc/ssh", "var"] {
+        assert_eq!(owned(path), first(path));
+    }
+
+    // Only the directories above a change roll up differently.
+    let changed = tree("bbbb", "0");
+    for path in &["", "etc", "etc/ssh"] {
+        assert_ne!(changed(path), first(path));
+    }
+    assert_eq!(changed("var"), first("var"));
+}
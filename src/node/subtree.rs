@@ -0,0 +1,186 @@
+//! One directory of a tree.
+//!
+//! Looking into a single directory of a version shouldn't take reading all
+//! of it.  The nodes of a tree are in order, so the directory is found by
+//! skipping over the directories before it, and nothing after it is read.
+//! The same goes when it isn't there, which is known as soon as a name that
+//! sorts after it turns up.  As a store's versions are read as they are
+//! pulled, the rest of the version is never even decompressed.
+
+use crate::{
+    escape::Escape,
+    exclude::Tombstones,
+    ignore::IgnoreRules,
+    node::{compare_trees_with_rules, Change, ChangeAction, ChangeSummary, SureNode},
+    platform::os_bytes,
+    suretree::AttMap,
+    Error, Result,
+};
+use std::{
+    cmp::Ordering,
+    path::{Component, Path, PathBuf},
+};
+
+/// The directory at `path`, relative to the top of the tree, and everything
+/// in it, as a tree of its own, with the directory at its top.  Returns None
+/// if the tree has no such directory.
+pub fn subtree<I>(mut nodes: I, path: &Path) -> Result<Option<Subtree<I>>>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    let mut atts = match nodes.next().transpose()? {
+        Some(SureNode::Enter { atts, .. }) => atts,
+        _ => return Ok(None),
+    };
+    for part in parts(path)? {
+        atts = loop {
+            match nodes.next().transpose()? {
+                Some(SureNode::Enter { name, atts }) => match name.cmp(&part) {
+                    Ordering::Less => skip(&mut nodes)?,
+                    Ordering::Equal => break atts,
+                    Ordering::Greater => return Ok(None),
+                },
+                // The directories come before the files, so it isn't here.
+                _ => return Ok(None),
+            }
+        };
+    }
+    Ok(Some(Subtree {
+        nodes,
+        top: Some(atts),
+        depth: 0,
+        done: false,
+    }))
+}
+
+/// A directory of a tree, from `subtree`.
+pub struct Subtree<I> {
+    nodes: I,
+    /// The attributes of the directory, until it has been given.
+    top: Option<AttMap>,
+    /// How many directories deep within it the nodes are.
+    depth: usize,
+    done: bool,
+}
+
+impl<I> Iterator for Subtree<I>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        if let Some(atts) = self.top.take() {
+            return Some(Ok(SureNode::Enter {
+                name: "__root__".to_string(),
+                atts,
+            }));
+        }
+        if self.done {
+            return None;
+        }
+        let node = self.nodes.next()?;
+        match &node {
+            Ok(SureNode::Enter { .. }) => self.depth += 1,
+            Ok(SureNode::Leave) if self.depth == 0 => self.done = true,
+            Ok(SureNode::Leave) => self.depth -= 1,
+            Ok(_) => (),
+            Err(_) => self.done = true,
+        }
+        Some(node)
+    }
+}
+
+/// Compare the directory at `path`, relative to the top of the trees,
+/// between an old tree and a new one, as `compare_trees_with_rules`, reading
+/// no more of either than it takes to get past the directory.  The changes
+/// are given with `dir`, then `path`, before their paths, and the rules are
+/// relative to the top of the trees.  A directory in just one of the trees
+/// is reported as added or removed, as it would be comparing the whole
+/// trees.
+pub fn compare_dir<P, IA, IB, F>(
+    left: IA,
+    right: IB,
+    dir: P,
+    path: &Path,
+    rules: &IgnoreRules,
+    mut on_change: F,
+) -> Result<ChangeSummary>
+where
+    P: AsRef<Path>,
+    IA: Iterator<Item = Result<SureNode>>,
+    IB: Iterator<Item = Result<SureNode>>,
+    F: FnMut(Change),
+{
+    let mut top = dir.as_ref().to_path_buf();
+    let mut within = PathBuf::new();
+    for part in parts(path)? {
+        top.push(&part);
+        within.push(&part);
+    }
+
+    let (left, right) = (subtree(left, path)?, subtree(right, path)?);
+    let (dir_atts, action) = match (left, right) {
+        (Some(left), Some(right)) => {
+            return compare_trees_with_rules(
+                left,
+                right,
+                &top,
+                &rules.within(&within),
+                &Tombstones::new(),
+                on_change,
+            );
+        }
+        (Some(mut left), None) => (left.top.take(), ChangeAction::Removed),
+        (None, Some(mut right)) => (right.top.take(), ChangeAction::Added),
+        (None, None) => return Err(Error::DirNotInVersions(path.to_path_buf())),
+    };
+
+    let change = Change {
+        path: top,
+        kind: dir_atts
+            .as_ref()
+            .and_then(|atts| atts.get("kind"))
+            .map_or("dir", |kind| kind.as_str())
+            .to_string(),
+        action,
+        attrs_changed: vec![],
+        rule: None,
+        from: None,
+    };
+    let mut summary = ChangeSummary::default();
+    summary.add(&change);
+    on_change(change);
+    Ok(summary)
+}
+
+/// Skip the rest of a directory, whose Enter has just been read.
+fn skip<I>(nodes: &mut I) -> Result<()>
+where
+    I: Iterator<Item = Result<SureNode>>,
+{
+    let mut depth = 0;
+    while let Some(node) = nodes.next().transpose()? {
+        match node {
+            SureNode::Enter { .. } => depth += 1,
+            SureNode::Leave if depth == 0 => break,
+            SureNode::Leave => depth -= 1,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// The escaped names of each part of a path relative to the top of the
+/// tree.
+fn parts(path: &Path) -> Result<Vec<String>> {
+    let mut result = vec![];
+    for part in path.components() {
+        match part {
+            Component::CurDir => (),
+            Component::Normal(name) => result.push(os_bytes(name).escaped()),
+            _ => return Err(Error::DirNotInVersions(path.to_path_buf())),
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,198 @@
+//! Reading small files to hash with io_uring.
+//!
+//! Hashing many small files spends most of its time waiting on each open
+//! and read in turn.  With the experimental "uring" feature, on Linux, each
+//! hashing thread can instead gather a batch of small files, and submit a
+//! read of each at once, overlapping them, and hashing each as it is read.
+//! A file that turns out to be larger than was read is finished with
+//! ordinary reads.  Without the feature, or where io_uring isn't available,
+//! such as on older kernels, or under a seccomp policy forbidding it, the
+//! reader can't be made, and files are read as usual.  A reader that fails
+//! while reading is given up on, and its files read as usual too.
+
+pub(crate) use self::uring_impl::UringReader;
+
+/// The most files read at once by a thread, fewer if the memory plan can't
+/// afford a buffer for each.
+pub(crate) const BATCH: usize = 32;
+
+/// Files smaller than this are read in batches, each in a single read.
+pub(crate) const SMALL: u64 = 64 * 1024;
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring_impl {
+    use crate::{
+        hashes::{hash_file, noatime_open},
+        HashAlgorithm, Result,
+    };
+    use io_uring::{opcode, types, IoUring};
+    use std::{
+        fs::File,
+        io::{self, Read, Seek, SeekFrom},
+        os::unix::io::AsRawFd,
+        path::Path,
+    };
+
+    pub(crate) struct UringReader {
+        ring: IoUring,
+        depth: usize,
+    }
+
+    impl UringReader {
+        /// Make a reader of up to `depth` files at once.
+        pub(crate) fn new(depth: usize) -> io::Result<UringReader> {
+            let depth = depth.max(1);
+            Ok(UringReader {
+                ring: IoUring::new(depth as u32)?,
+                depth,
+            })
+        }
+
+        /// The most files to give `hash_files` at once.
+        pub(crate) fn depth(&self) -> usize {
+            self.depth
+        }
+
+        /// Hash each of the files, of at most `depth`, giving the result of
+        /// each in the same order.  An error means the reader itself failed,
+        /// and shouldn't be used again; none of the files were hashed.
+        pub(crate) fn hash_files(
+            &mut self,
+            paths: &[&Path],
+            algorithms: &[HashAlgorithm],
+            buffer: usize,
+        ) -> Result<Vec<Result<Vec<u8>>>> {
+            if paths.len() > self.depth {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} files for an io_uring of {}", paths.len(), self.depth),
+                )
+                .into());
+            }
+            let mut files: Vec<Option<(File, Vec<u8>)>> = Vec::with_capacity(paths.len());
+            let mut results: Vec<Option<Result<Vec<u8>>>> = Vec::with_capacity(paths.len());
+            for path in paths {
+                let opened = noatime_open(path).and_then(|file| {
+                    // One more than the size, so a file read in full is
+                    // seen to have ended.
+                    let size = file.metadata()?.len() as usize;
+                    Ok((file, vec![0u8; size.saturating_add(1).min(buffer)]))
+                });
+                match opened {
+                    Ok(opened) => {
+                        files.push(Some(opened));
+                        results.push(None);
+                    }
+                    Err(e) => {
+                        files.push(None);
+                        results.push(Some(Err(e.into())));
+                    }
+                }
+            }
+
+            // The buffers stay put until every read has completed.
+            let mut pending = 0;
+            let mut full = false;
+            for (index, (file, buf)) in files
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, f)| f.as_mut().map(|f| (i, f)))
+            {
+                let read = opcode::Read::new(
+                    types::Fd(file.as_raw_fd()),
+                    buf.as_mut_ptr(),
+                    buf.len() as u32,
+                )
+                .offset(0)
+                .build()
+                .user_data(index as u64);
+                // Safety: the buffer outlives the read, as all of the reads
+                // are waited for below, or the buffers leaked.
+                if unsafe { self.ring.submission().push(&read) }.is_err() {
+                    full = true;
+                    break;
+                }
+                pending += 1;
+            }
+            if full {
+                // The ring holds `depth` entries, so this shouldn't happen,
+                // but if it does, the reads queued so far still refer to
+                // the buffers.
+                std::mem::forget(files);
+                return Err(io::Error::other("io_uring submission queue is full").into());
+            }
+
+            while pending > 0 {
+                if let Err(e) = self.ring.submit_and_wait(pending) {
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    // The reads may still be in flight, and their buffers
+                    // can't be freed, so give up on them.
+                    std::mem::forget(files);
+                    return Err(e.into());
+                }
+                for done in self.ring.completion() {
+                    let index = done.user_data() as usize;
+                    let (file, buf) = files[index].as_mut().unwrap();
+                    results[index] = Some(finish(file, buf, done.result(), algorithms, buffer));
+                    pending -= 1;
+                }
+            }
+
+            Ok(results.into_iter().map(|r| r.unwrap()).collect())
+        }
+    }
+
+    /// Hash a file, given the result of reading it into `buf`.  A read that
+    /// fills the buffer didn't reach the end, and the rest is read as usual.
+    fn finish(
+        file: &mut File,
+        buf: &[u8],
+        result: i32,
+        algorithms: &[HashAlgorithm],
+        buffer: usize,
+    ) -> Result<Vec<u8>> {
+        if result < 0 {
+            return Err(io::Error::from_raw_os_error(-result).into());
+        }
+        let count = result as usize;
+        if count < buf.len() {
+            hash_file(&mut &buf[..count], algorithms, buffer)
+        } else {
+            file.seek(SeekFrom::Start(count as u64))?;
+            hash_file(&mut buf.chain(file), algorithms, buffer)
+        }
+    }
+}
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+mod uring_impl {
+    use crate::{HashAlgorithm, Result};
+    use std::{io, path::Path};
+
+    /// There is never a reader to be had.
+    pub(crate) enum UringReader {}
+
+    impl UringReader {
+        pub(crate) fn new(_depth: usize) -> io::Result<UringReader> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "built without the \"uring\" feature",
+            ))
+        }
+
+        pub(crate) fn depth(&self) -> usize {
+            match *self {}
+        }
+
+        pub(crate) fn hash_files(
+            &mut self,
+            _paths: &[&Path],
+            _algorithms: &[HashAlgorithm],
+            _buffer: usize,
+        ) -> Result<Vec<Result<Vec<u8>>>> {
+            match *self {}
+        }
+    }
+}
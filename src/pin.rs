@@ -0,0 +1,164 @@
+//! Pinning a tree to the version of the store it was verified against.
+//!
+//! A `.rsure-pin` file at the root of a tree names the version that the
+//! tree should match, such as the one a deployment was signed off against,
+//! so that checks compare with that version rather than the latest.  Fleet
+//! tooling can read the pin, or ship one with a deployment, to say which
+//! baseline each host should match.
+//!
+//! The file is a few lines of text:
+//!
+//! ```text
+//! version 42
+//! name release-1.4
+//! time 2024-03-01T12:00:00Z
+//! ```
+//!
+//! The name and time identify the version, so that a pin isn't taken to
+//! mean another version given the same number, such as in a store that was
+//! rebuilt.  Blank lines, and those starting with `#`, are ignored.  The pin
+//! file itself is never scanned, as it changes each time the tree is signed
+//! off.
+//!
+//! Since it isn't scanned, a change to the pin doesn't show up as a change
+//! to the tree.  Anyone who can write the tree can point a check at an
+//! older version, one the tree's unwanted changes already match, and the
+//! check only says which version it compared with.  Where that matters, the
+//! version should be given to the check, rather than taken from the tree.
+
+use crate::{Error, Result, Store, StoreVersion, Version};
+use chrono::{DateTime, Utc};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process,
+};
+
+/// The file at the root of a tree that pins it to a version.
+pub const PIN_FILE: &str = ".rsure-pin";
+
+/// The extension of the file a pin is written to, alongside the store,
+/// before it is moved into the tree.
+const PIN_TEMP_EXT: &str = "pin.tmp";
+
+/// A version of a store that a tree is pinned to.
+#[derive(Clone, Debug)]
+pub struct Pin {
+    pub version: Version,
+    pub name: String,
+    pub time: DateTime<Utc>,
+}
+
+impl Pin {
+    /// A pin of a version read from a store.
+    pub fn of(info: &StoreVersion) -> Pin {
+        Pin {
+            version: info.version.clone(),
+            name: info.name.clone(),
+            time: info.time,
+        }
+    }
+
+    /// Read the pin of the tree at `dir`, if it has one.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Option<Pin>> {
+        let path = dir.as_ref().join(PIN_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let bad = |why: &str| Error::InvalidPin(format!("{}: {}", path.display(), why));
+        let (mut version, mut name, mut time) = (None, None, None);
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let value = value.trim_start();
+            match key {
+                "version" => version = Some(parse_version(value)?),
+                "name" => name = Some(value.to_string()),
+                "time" => {
+                    time = Some(
+                        DateTime::parse_from_rfc3339(value)
+                            .map_err(|_| bad("invalid time"))?
+                            .with_timezone(&Utc),
+                    )
+                }
+                _ => return Err(bad(&format!("unknown key {:?}", key))),
+            }
+        }
+
+        match (version, name, time) {
+            (Some(version), Some(name), Some(time)) => Ok(Some(Pin {
+                version,
+                name,
+                time,
+            })),
+            _ => Err(bad("needs a version, name and time")),
+        }
+    }
+
+    /// Write the pin to the tree at `dir`, replacing any earlier one.  It is
+    /// written first alongside `store`, rather than in the tree, so that a
+    /// scan of the tree never comes across a partly written pin.
+    pub fn save<P: AsRef<Path>>(&self, dir: P, store: &dyn Store) -> Result<()> {
+        let path = dir.as_ref().join(PIN_FILE);
+        let temp = match store.sidecar(PIN_TEMP_EXT) {
+            Ok(temp) => temp,
+            Err(Error::Unsupported(_)) => {
+                env::temp_dir().join(format!("rsure-pin.{}", process::id()))
+            }
+            Err(e) => return Err(e),
+        };
+        {
+            let mut out = fs::File::create(&temp)?;
+            writeln!(
+                out,
+                "# The version of the store this tree was verified against."
+            )?;
+            writeln!(out, "version {}", self.version)?;
+            writeln!(out, "name {}", self.name)?;
+            writeln!(out, "time {}", self.time.to_rfc3339())?;
+            out.flush()?;
+        }
+        match fs::rename(&temp, &path) {
+            Ok(()) => (),
+            // On another filesystem than the tree, so can only be copied in.
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let copied = fs::copy(&temp, &path);
+                fs::remove_file(&temp)?;
+                copied?;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The version in the store that the pin names, which must still have
+    /// the same name and time.
+    pub fn resolve(&self, store: &dyn Store) -> Result<Version> {
+        match store.get_version(&self.version)? {
+            Some(info) if info.name == self.name && info.time == self.time => Ok(info.version),
+            _ => Err(Error::StalePin(self.version.to_string(), self.name.clone())),
+        }
+    }
+}
+
+/// A version to pin to, as given on the command line.  As well as the
+/// forms `Version` takes, a number may be written with a leading "v", such
+/// as "v42".
+pub fn parse_version(text: &str) -> Result<Version> {
+    match text.strip_prefix('v') {
+        Some(number) if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => {
+            Ok(Version::Tagged(number.to_string()))
+        }
+        _ => text.parse(),
+    }
+}
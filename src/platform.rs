@@ -0,0 +1,129 @@
+//! The platform specific parts of scanning.
+//!
+//! Names are stored escaped from their bytes.  On Unix, these are the raw
+//! bytes of the name.  On Windows, they are the UTF-8 of the name, with any
+//! unpaired surrogates replaced, and paths use `/` between components, so
+//! that exclude patterns are the same on both.
+//!
+//! Unix identifies a file by its device and inode, which is how hardlinks,
+//! mount points, and symlinks that loop back are found.  The stable Windows
+//! metadata has neither, so on Windows, every file is taken to be on the
+//! root's volume, with a single link, and symlinks to directories are not
+//! followed.  The daemon, service notification and whole system scans are
+//! still Unix only.
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        borrow::Cow,
+        ffi::{OsStr, OsString},
+        fs::{DirEntry, Metadata},
+        os::unix::prelude::*,
+        path::Path,
+    };
+
+    /// The bytes of a name, to be escaped.
+    pub(crate) fn os_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+        Cow::Borrowed(name.as_bytes())
+    }
+
+    /// The bytes of a relative path, to be escaped or matched.
+    pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+        os_bytes(path.as_os_str())
+    }
+
+    /// A name from its unescaped bytes.
+    pub(crate) fn os_from_bytes(bytes: Vec<u8>) -> OsString {
+        OsString::from_vec(bytes)
+    }
+
+    /// The device a file is on.
+    pub(crate) fn device(meta: &Metadata) -> u64 {
+        meta.dev()
+    }
+
+    /// The device and inode of a file, identifying it across links.
+    pub(crate) fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+        Some((meta.dev(), meta.ino()))
+    }
+
+    /// The number of hardlinks to a file.
+    pub(crate) fn nlink(meta: &Metadata) -> u64 {
+        meta.nlink()
+    }
+
+    /// The order to stat the entries of a directory in.  Going by inode
+    /// helps performance on some filesystems (such as ext4).
+    pub(crate) fn stat_order(entry: &DirEntry) -> u64 {
+        entry.ino()
+    }
+
+    /// The name of this host.
+    pub(crate) fn hostname() -> String {
+        let mut buf = [0u8; 256];
+        let len = unsafe {
+            if libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) != 0 {
+                return "localhost".to_string();
+            }
+            buf.iter().position(|&b| b == 0).unwrap_or(buf.len())
+        };
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::{
+        borrow::Cow,
+        ffi::{OsStr, OsString},
+        fs::{DirEntry, Metadata},
+        path::Path,
+    };
+
+    pub(crate) fn os_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+        match name.to_string_lossy() {
+            Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+            Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+        }
+    }
+
+    pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+        let bytes = os_bytes(path.as_os_str());
+        if bytes.contains(&b'\\') {
+            Cow::Owned(
+                bytes
+                    .iter()
+                    .map(|&b| if b == b'\\' { b'/' } else { b })
+                    .collect(),
+            )
+        } else {
+            bytes
+        }
+    }
+
+    pub(crate) fn os_from_bytes(bytes: Vec<u8>) -> OsString {
+        OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    pub(crate) fn device(_meta: &Metadata) -> u64 {
+        0
+    }
+
+    pub(crate) fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+        None
+    }
+
+    pub(crate) fn nlink(_meta: &Metadata) -> u64 {
+        1
+    }
+
+    pub(crate) fn stat_order(_entry: &DirEntry) -> u64 {
+        0
+    }
+
+    pub(crate) fn hostname() -> String {
+        std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+    }
+}
+
+pub(crate) use self::imp::*;
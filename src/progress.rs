@@ -3,16 +3,286 @@
 //! Records updates of number of files visited, and number of bytes
 //! processed.  When given an estimate, printes a simple periodic report of
 //! how far along we think we are.
-
+//!
+//! An update reports in phases: `ScanProgress` counts what the scan finds,
+//! which is the estimate of the work for `Progress` to report on, with an
+//! ETA, during hashing.  Writing the new version has no such measure, so a
+//! `Spinner` just shows that it is still going.
+//!
+//! The meters don't print anything themselves, but give each
+//! `ProgressEvent` to a `ProgressSink`.  By default this is the
+//! `TerminalProgress`, which prints to stdout, but library users can give
+//! their own, to show the progress in their own way, or `NullProgress`, to
+//! show nothing at all.  `JsonProgress` writes the events as lines of JSON,
+//! for other programs to show.
+
+#[cfg(feature = "cli")]
 use env_logger::Builder;
 use lazy_static::lazy_static;
+#[cfg(feature = "cli")]
 use log::Log;
+use serde::Serializer;
+use serde_derive::Serialize;
 use std::{
-    io::{stdout, Write},
-    sync::Mutex,
+    fmt,
+    io::{self, stdout, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration as StdDuration, Instant},
 };
 use time::{Duration, OffsetDateTime};
 
+/// Something a progress meter reports.  As JSON, each is an object with
+/// an "event" of "scan", "hash" or "stage", and the fields, with `elapsed`
+/// in seconds.  A path that isn't UTF-8 is given with its invalid bytes
+/// replaced, as it is only to be shown.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ProgressEvent {
+    /// What the scan has found so far.
+    Scan {
+        dirs: u64,
+        files: u64,
+        bytes: u64,
+        /// The directory last read.
+        #[serde(serialize_with = "lossy_path")]
+        path: Option<PathBuf>,
+        /// The scan has finished, and these are the final counts.
+        done: bool,
+    },
+    /// How far hashing has got, out of the estimated totals.
+    Hash {
+        files: u64,
+        total_files: u64,
+        bytes: u64,
+        total_bytes: u64,
+        /// The file last hashed.
+        #[serde(serialize_with = "lossy_path")]
+        path: Option<PathBuf>,
+        /// How long hashing has been going.
+        #[serde(serialize_with = "seconds")]
+        elapsed: StdDuration,
+        done: bool,
+    },
+    /// Work with no measure of how far along it is, such as writing out a
+    /// new version, which is reported periodically while it continues.
+    Stage {
+        label: &'static str,
+        #[serde(serialize_with = "seconds")]
+        elapsed: StdDuration,
+        done: bool,
+    },
+}
+
+impl ProgressEvent {
+    /// Is this the last event of its meter?
+    pub fn is_done(&self) -> bool {
+        match *self {
+            ProgressEvent::Scan { done, .. }
+            | ProgressEvent::Hash { done, .. }
+            | ProgressEvent::Stage { done, .. } => done,
+        }
+    }
+}
+
+fn seconds<S: Serializer>(time: &StdDuration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(time.as_secs_f64())
+}
+
+fn lossy_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_str(&path.to_string_lossy()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Where the progress meters send their reports.  A sink is given every
+/// update, and when the meter is finished, an event with `done` set, so it
+/// should limit how often it shows anything itself.  It is called from
+/// whichever thread is doing the work, so may be called from several at
+/// once.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, event: &ProgressEvent);
+}
+
+impl<F> ProgressSink for F
+where
+    F: Fn(&ProgressEvent) + Send + Sync,
+{
+    fn report(&self, event: &ProgressEvent) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn ProgressSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressSink")
+    }
+}
+
+/// A sink that ignores all progress, such as for tests, or when running
+/// unattended.
+pub struct NullProgress;
+
+impl ProgressSink for NullProgress {
+    fn report(&self, _event: &ProgressEvent) {}
+}
+
+/// The default sink, which prints the progress to stdout, a few times a
+/// second when logging, and otherwise every few seconds, coordinated with
+/// the logger set up by `log_init`.
+#[derive(Default)]
+pub struct TerminalProgress {
+    // How far a spinner has turned.
+    turn: AtomicUsize,
+}
+
+impl ProgressSink for TerminalProgress {
+    fn report(&self, event: &ProgressEvent) {
+        const TURNS: [char; 4] = ['|', '/', '-', '\\'];
+
+        let mut st = STATE.lock().unwrap();
+        let done = event.is_done();
+        if !done && !st.need_update() {
+            return;
+        }
+        let message = match *event {
+            ProgressEvent::Stage {
+                label,
+                elapsed,
+                done: false,
+            } => {
+                let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+                format!(
+                    "{}: {} {}\n",
+                    label,
+                    TURNS[turn % TURNS.len()],
+                    duration(elapsed)
+                )
+            }
+            _ => message(event),
+        };
+        st.update(message);
+
+        // Clear the finished message so that it stays shown.
+        if done {
+            st.message.clear();
+        }
+    }
+}
+
+/// A sink that writes each event as a line of JSON, such as to stderr or
+/// a named pipe, for another program to show the progress.  Other than the
+/// last of each meter, events are written at most once per interval, of a
+/// quarter of a second unless given.
+pub struct JsonProgress<W> {
+    out: Mutex<JsonOut<W>>,
+    interval: StdDuration,
+}
+
+struct JsonOut<W> {
+    writer: W,
+    next: Instant,
+}
+
+impl<W: Write + Send> JsonProgress<W> {
+    pub fn new(writer: W) -> JsonProgress<W> {
+        JsonProgress {
+            out: Mutex::new(JsonOut {
+                writer,
+                next: Instant::now(),
+            }),
+            interval: StdDuration::from_millis(250),
+        }
+    }
+
+    /// Write the events at most this often.
+    pub fn with_interval(mut self, interval: StdDuration) -> JsonProgress<W> {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonProgress<W> {
+    fn report(&self, event: &ProgressEvent) {
+        let mut out = self.out.lock().unwrap();
+        let now = Instant::now();
+        if !event.is_done() && now < out.next {
+            return;
+        }
+        out.next = now + self.interval;
+
+        // Whoever is reading may have gone away, which shouldn't stop the
+        // work being reported on.
+        let _ = out.write(event);
+    }
+}
+
+impl<W: Write> JsonOut<W> {
+    /// Write an event as a line of JSON.
+    fn write(&mut self, event: &ProgressEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.writer.flush()
+    }
+}
+
+/// The sink to use when none is given.
+pub(crate) fn default_sink() -> Arc<dyn ProgressSink> {
+    Arc::new(TerminalProgress::default())
+}
+
+/// The line the terminal shows for an event.
+fn message(event: &ProgressEvent) -> String {
+    match *event {
+        ProgressEvent::Scan {
+            dirs, files, bytes, ..
+        } => format!(
+            "scan: {} dirs {} files, {} bytes\n",
+            dirs,
+            files,
+            humanize(bytes)
+        ),
+        ProgressEvent::Hash {
+            files,
+            total_files,
+            bytes,
+            total_bytes,
+            elapsed,
+            ..
+        } => format!(
+            "hash: {:7}/{:7} ({:5.1}%) files, {}/{} ({:5.1}%) bytes{}\n",
+            files,
+            total_files,
+            (files as f64 * 100.0) / total_files as f64,
+            humanize(bytes),
+            humanize(total_bytes),
+            (bytes as f64 * 100.0) / total_bytes as f64,
+            eta(bytes, total_bytes, elapsed)
+        ),
+        ProgressEvent::Stage { label, elapsed, .. } => {
+            format!("{}: done in {}\n", label, duration(elapsed))
+        }
+    }
+}
+
+/// The estimated time left, from the rate the bytes have been hashed at so
+/// far, if there is enough to go on.
+fn eta(bytes: u64, total_bytes: u64, elapsed: StdDuration) -> String {
+    if bytes == 0 || bytes >= total_bytes {
+        return String::new();
+    }
+    let rate = bytes as f64 / elapsed.as_secs_f64();
+    let left = (total_bytes - bytes) as f64 / rate;
+    format!(", ETA {}", duration(StdDuration::from_secs_f64(left)))
+}
+
 // The Rust logging system (log crate) only allows a single logger to be
 // logged once.  If we want to capture this, it has to be done before any
 // logger is initialized.  Globally, within a mutex, we keep this simple
@@ -31,13 +301,16 @@ struct State {
 
 // The SafeLogger wraps another logger, coordinating the logging with the
 // state to properly interleave logs and messages.
+#[cfg(feature = "cli")]
 struct SafeLogger {
     inner: Box<dyn Log>,
 }
 
 /// Initialize the standard logger, based on `env_logger::init()`, but
 /// coordinated with any progress meters.  Like `init`, this will panic if
-/// the logging system has already been initialized.
+/// the logging system has already been initialized.  This is part of the
+/// "cli" feature, as it brings in `env_logger`.
+#[cfg(feature = "cli")]
 pub fn log_init() {
     let mut st = STATE.lock().unwrap();
     let inner = Builder::from_default_env().build();
@@ -107,6 +380,7 @@ impl State {
     }
 }
 
+#[cfg(feature = "cli")]
 impl Log for SafeLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
         self.inner.enabled(metadata)
@@ -139,6 +413,10 @@ pub struct Progress {
 
     cur_bytes: u64,
     total_bytes: u64,
+
+    start: Instant,
+    path: Option<PathBuf>,
+    sink: Arc<dyn ProgressSink>,
 }
 
 impl Progress {
@@ -151,40 +429,58 @@ impl Progress {
 
             cur_bytes: 0,
             total_bytes: bytes,
+
+            start: Instant::now(),
+            path: None,
+            sink: default_sink(),
         }
     }
 
+    /// Report to the given sink, instead of the terminal.
+    pub fn with_sink(mut self, sink: Arc<dyn ProgressSink>) -> Progress {
+        self.sink = sink;
+        self
+    }
+
+    /// Add to the totals, for when the work isn't known in advance, but
+    /// found as it goes.
+    pub fn add_totals(&mut self, files: u64, bytes: u64) {
+        self.total_files += files;
+        self.total_bytes += bytes;
+    }
+
     /// Update the progress meter.
     pub fn update(&mut self, files: u64, bytes: u64) {
         self.cur_files += files;
         self.cur_bytes += bytes;
+        self.sink.report(&self.event(false));
+    }
 
-        let mut st = STATE.lock().unwrap();
-        if st.need_update() {
-            st.update(self.message());
-        }
+    /// Update the progress meter, with the path of the file just hashed.
+    pub fn update_path(&mut self, files: u64, bytes: u64, path: &Path) {
+        self.path = Some(path.to_path_buf());
+        self.update(files, bytes);
     }
 
     /// Flush the output, regardless of if any update is needed.
     pub fn flush(&mut self) {
-        let mut st = STATE.lock().unwrap();
-        st.update(self.message());
-
-        // Clear the current message so that we don't clear out the shown
-        // message.
-        st.message.clear();
+        self.sink.report(&self.event(true));
     }
 
     pub fn message(&self) -> String {
-        format!(
-            "{:7}/{:7} ({:5.1}%) files, {}/{} ({:5.1}%) bytes\n",
-            self.cur_files,
-            self.total_files,
-            (self.cur_files as f64 * 100.0) / self.total_files as f64,
-            humanize(self.cur_bytes),
-            humanize(self.total_bytes),
-            (self.cur_bytes as f64 * 100.0) / self.total_bytes as f64
-        )
+        message(&self.event(false))
+    }
+
+    fn event(&self, done: bool) -> ProgressEvent {
+        ProgressEvent::Hash {
+            files: self.cur_files,
+            total_files: self.total_files,
+            bytes: self.cur_bytes,
+            total_bytes: self.total_bytes,
+            path: self.path.clone(),
+            elapsed: self.start.elapsed(),
+            done,
+        }
     }
 }
 
@@ -193,6 +489,8 @@ pub struct ScanProgress {
     dirs: u64,
     files: u64,
     bytes: u64,
+    path: Option<PathBuf>,
+    sink: Arc<dyn ProgressSink>,
 }
 
 impl ScanProgress {
@@ -202,37 +500,111 @@ impl ScanProgress {
             dirs: 0,
             files: 0,
             bytes: 0,
+            path: None,
+            sink: default_sink(),
         }
     }
 
-    /// Update the meter.
-    pub fn update(&mut self, dirs: u64, files: u64, bytes: u64) {
+    /// Report to the given sink, instead of the terminal.
+    pub fn with_sink(mut self, sink: Arc<dyn ProgressSink>) -> ScanProgress {
+        self.sink = sink;
+        self
+    }
+
+    /// Update the meter, with what was found in the directory at `path`.
+    pub fn update(&mut self, path: &Path, dirs: u64, files: u64, bytes: u64) {
+        self.path = Some(path.to_path_buf());
         self.dirs += dirs;
         self.files += files;
         self.bytes += bytes;
+        self.sink.report(&self.event(false));
+    }
 
-        let mut st = STATE.lock().unwrap();
-        if st.need_update() {
-            st.update(self.message());
+    fn event(&self, done: bool) -> ProgressEvent {
+        ProgressEvent::Scan {
+            dirs: self.dirs,
+            files: self.files,
+            bytes: self.bytes,
+            path: self.path.clone(),
+            done,
         }
     }
+}
 
-    fn message(&self) -> String {
-        format!(
-            "scan: {} dirs {} files, {} bytes\n",
-            self.dirs,
-            self.files,
-            humanize(self.bytes)
-        )
+impl Drop for ScanProgress {
+    fn drop(&mut self) {
+        self.sink.report(&self.event(true));
     }
 }
 
-impl Drop for ScanProgress {
+/// A spinner, for work with no measure of how far along it is, such as
+/// writing out a new version.  It turns on its own thread, until dropped.
+pub struct Spinner {
+    label: &'static str,
+    start: Instant,
+    sink: Arc<dyn ProgressSink>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start a spinner, showing the given label.
+    pub fn new(label: &'static str) -> Spinner {
+        Spinner::with_sink(label, default_sink())
+    }
+
+    /// Start a spinner, reporting to the given sink.
+    pub fn with_sink(label: &'static str, sink: Arc<dyn ProgressSink>) -> Spinner {
+        let start = Instant::now();
+        let (stop, stopped) = channel::<()>();
+        let thread_sink = sink.clone();
+        let thread = thread::spawn(move || {
+            // Nothing is sent, the sender is just dropped.
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(StdDuration::from_millis(100))
+            {
+                thread_sink.report(&ProgressEvent::Stage {
+                    label,
+                    elapsed: start.elapsed(),
+                    done: false,
+                });
+            }
+        });
+
+        Spinner {
+            label,
+            start,
+            sink,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Spinner {
     fn drop(&mut self) {
-        let mut st = STATE.lock().unwrap();
-        st.update(self.message());
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
 
-        st.message.clear();
+        self.sink.report(&ProgressEvent::Stage {
+            label: self.label,
+            elapsed: self.start.elapsed(),
+            done: true,
+        });
+    }
+}
+
+/// Print a duration, to the second, such as "1h02m03s".
+fn duration(time: StdDuration) -> String {
+    let secs = time.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
     }
 }
 
@@ -250,6 +622,11 @@ pub fn humanize(value: u64) -> String {
         "B  ", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB",
     ];
 
+    // A number of bytes is whole, so has no fraction.
+    if unit == 0 {
+        return format!("{:6}{}", value as u64, UNITS[unit]);
+    }
+
     let precision = if value < 10.0 {
         3
     } else if value < 100.0 {
@@ -0,0 +1,437 @@
+//! Reporting the differences found by comparing trees.
+//!
+//! The changes from `compare_trees` are passed, one at a time, to a
+//! [`ChangeSink`], which writes them out in one of the [`Format`]s.  The
+//! text format writes each change as it is found.  Others, that need to
+//! group the changes, write everything once the comparison is finished.
+
+use crate::{Change, ChangeAction, Error, Result};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The ways a report can be written.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// One line per change, as `Change` displays.
+    #[default]
+    Text,
+    /// JUnit XML, for CI systems, with each change a failing testcase,
+    /// grouped into a testsuite per directory.
+    Junit,
+    /// GitHub or GitLab flavored Markdown, for merge requests or chat, with
+    /// a summary table, and a collapsible section per directory.
+    Markdown,
+    /// A standalone HTML page, with a single table of the changes that can
+    /// be sorted and filtered, for comparisons too large to read otherwise.
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Format> {
+        match text {
+            "text" => Ok(Format::Text),
+            "junit" => Ok(Format::Junit),
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(Error::UnknownFormat(text.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Junit => "junit",
+            Format::Markdown => "markdown",
+            Format::Html => "html",
+        })
+    }
+}
+
+/// Something that the changes of a comparison are given to.
+pub trait ChangeSink {
+    /// A single difference, in tree order.
+    fn change(&mut self, change: Change);
+
+    /// The comparison is done.  Writes anything still to be written, and
+    /// reports any error from writing earlier changes.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// A sink writing the given format to `out`.  The `title` names the
+/// comparison, such as "check 2sure.dat.gz", in formats that have a place
+/// for it.
+pub fn sink<'a>(format: Format, title: &str, out: Box<dyn Write + 'a>) -> Box<dyn ChangeSink + 'a> {
+    match format {
+        Format::Text => Box::new(TextSink { out, error: None }),
+        format => Box::new(GroupSink {
+            out,
+            format,
+            title: title.to_string(),
+            dirs: BTreeMap::new(),
+        }),
+    }
+}
+
+struct TextSink<'a> {
+    out: Box<dyn Write + 'a>,
+    // The first error writing, reported when finished.
+    error: Option<io::Error>,
+}
+
+impl<'a> ChangeSink for TextSink<'a> {
+    fn change(&mut self, change: Change) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", change) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(self.out.flush()?),
+        }
+    }
+}
+
+/// A sink for the formats that group the changes by directory, so write
+/// nothing until the comparison is finished.
+struct GroupSink<'a> {
+    out: Box<dyn Write + 'a>,
+    format: Format,
+    title: String,
+    // The changes, by the directory they are in.
+    dirs: BTreeMap<PathBuf, Vec<Change>>,
+}
+
+impl<'a> ChangeSink for GroupSink<'a> {
+    fn change(&mut self, change: Change) {
+        let dir = change.path.parent().unwrap_or_else(|| Path::new(""));
+        self.dirs.entry(dir.to_path_buf()).or_default().push(change);
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        match self.format {
+            Format::Markdown => write_markdown(&mut self.out, &self.title, &self.dirs)?,
+            Format::Html => write_html(&mut self.out, &self.title, &self.dirs)?,
+            _ => write_junit(&mut self.out, &self.title, &self.dirs)?,
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// The number of changes of each action, leaving out those with none.
+fn counts(dirs: &BTreeMap<PathBuf, Vec<Change>>) -> Vec<(ChangeAction, usize)> {
+    let actions = [
+        ChangeAction::Added,
+        ChangeAction::Removed,
+        ChangeAction::Modified,
+        ChangeAction::Renamed,
+        ChangeAction::Excluded,
+    ];
+    actions
+        .iter()
+        .map(|&action| {
+            let count = dirs
+                .values()
+                .flatten()
+                .filter(|c| c.action == action)
+                .count();
+            (action, count)
+        })
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+/// The attributes that changed, the rule that excluded a node, or where a
+/// renamed file was.
+fn details(change: &Change) -> String {
+    match change.action {
+        ChangeAction::Modified => change.attrs_changed.join(", "),
+        ChangeAction::Excluded => change.rule.clone().unwrap_or_default(),
+        ChangeAction::Renamed => match &change.from {
+            Some(from) => format!("from {}", from.display()),
+            None => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// The word for what happened to a node.
+fn action_name(action: ChangeAction) -> &'static str {
+    match action {
+        ChangeAction::Added => "added",
+        ChangeAction::Removed => "removed",
+        ChangeAction::Excluded => "excluded",
+        ChangeAction::Modified => "modified",
+        ChangeAction::Renamed => "renamed",
+    }
+}
+
+fn write_junit(
+    out: &mut dyn Write,
+    title: &str,
+    dirs: &BTreeMap<PathBuf, Vec<Change>>,
+) -> io::Result<()> {
+    let total: usize = dirs.values().map(|c| c.len()).sum();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites name="{}" tests="{}" failures="{}">"#,
+        xml_escape(title),
+        total.max(1),
+        total
+    )?;
+    if dirs.is_empty() {
+        // A passing test, so that a clean comparison still shows up.
+        writeln!(
+            out,
+            r#"  <testsuite name="{0}" tests="1" failures="0">"#,
+            xml_escape(title)
+        )?;
+        writeln!(
+            out,
+            r#"    <testcase classname="{0}" name="unchanged"/>"#,
+            xml_escape(title)
+        )?;
+        writeln!(out, "  </testsuite>")?;
+    }
+    for (dir, changes) in dirs {
+        let dir = xml_escape(&dir.to_string_lossy());
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}">"#,
+            dir,
+            changes.len(),
+            changes.len()
+        )?;
+        for change in changes {
+            let kind = action_name(change.action);
+            let message = match change.action {
+                ChangeAction::Modified => change.attrs_changed.join(","),
+                _ => format!("{} {}", kind, change.kind),
+            };
+            writeln!(
+                out,
+                r#"    <testcase classname="{}" name="{}">"#,
+                dir,
+                xml_escape(&change.path.to_string_lossy())
+            )?;
+            writeln!(
+                out,
+                r#"      <failure type="{}" message="{}">{}</failure>"#,
+                kind,
+                xml_escape(&message),
+                xml_escape(&change.to_string())
+            )?;
+            writeln!(out, "    </testcase>")?;
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")
+}
+
+fn write_markdown(
+    out: &mut dyn Write,
+    title: &str,
+    dirs: &BTreeMap<PathBuf, Vec<Change>>,
+) -> io::Result<()> {
+    writeln!(out, "### {}", md_escape(title))?;
+    writeln!(out)?;
+    if dirs.is_empty() {
+        return writeln!(out, "No changes.");
+    }
+
+    writeln!(out, "| Change | Count |")?;
+    writeln!(out, "|--------|------:|")?;
+    for (action, count) in counts(dirs) {
+        writeln!(out, "| {} | {} |", action_name(action), count)?;
+    }
+    let total: usize = dirs.values().map(|c| c.len()).sum();
+    writeln!(out, "| **total** | **{}** |", total)?;
+
+    for (dir, changes) in dirs {
+        writeln!(out)?;
+        writeln!(out, "<details>")?;
+        writeln!(
+            out,
+            "<summary><code>{}</code> ({} {})</summary>",
+            xml_escape(&dir.to_string_lossy()),
+            changes.len(),
+            if changes.len() == 1 {
+                "change"
+            } else {
+                "changes"
+            }
+        )?;
+        writeln!(out)?;
+        writeln!(out, "| Path | Kind | Change | Details |")?;
+        writeln!(out, "|------|------|--------|---------|")?;
+        for change in changes {
+            writeln!(
+                out,
+                "| <code>{}</code> | {} | {} | {} |",
+                md_escape(&change.path.to_string_lossy()),
+                md_escape(&change.kind),
+                action_name(change.action),
+                md_escape(&details(change))
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(out, "</details>")?;
+    }
+    Ok(())
+}
+
+/// The style and script of the HTML report.  Clicking a column heading
+/// sorts by it, and the rows can be filtered by any text in them.
+const HTML_HEAD: &str = r#"<style>
+body { font-family: sans-serif; margin: 1em 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+#changes th { cursor: pointer; background: #eee; }
+#changes td:first-child { font-family: monospace; }
+tr.added td:nth-child(3) { color: #080; }
+tr.removed td:nth-child(3) { color: #c00; }
+tr.modified td:nth-child(3) { color: #a60; }
+tr.renamed td:nth-child(3) { color: #06c; }
+tr.excluded td:nth-child(3) { color: #888; }
+</style>
+<script>
+document.addEventListener("DOMContentLoaded", () => {
+  const table = document.getElementById("changes");
+  if (!table) return;
+  const body = table.tBodies[0];
+  const rows = Array.from(body.rows);
+  const shown = document.getElementById("shown");
+  document.getElementById("filter").addEventListener("input", (e) => {
+    const text = e.target.value.toLowerCase();
+    let count = 0;
+    for (const row of rows) {
+      row.hidden = !row.textContent.toLowerCase().includes(text);
+      if (!row.hidden) count++;
+    }
+    shown.textContent = count;
+  });
+  table.tHead.addEventListener("click", (e) => {
+    const th = e.target.closest("th");
+    if (!th) return;
+    const col = th.cellIndex;
+    const dir = th.dataset.order === "asc" ? -1 : 1;
+    for (const other of th.parentNode.cells) delete other.dataset.order;
+    th.dataset.order = dir > 0 ? "asc" : "desc";
+    rows.sort((a, b) =>
+      dir * a.cells[col].textContent.localeCompare(b.cells[col].textContent));
+    body.append(...rows);
+  });
+});
+</script>
+"#;
+
+fn write_html(
+    out: &mut dyn Write,
+    title: &str,
+    dirs: &BTreeMap<PathBuf, Vec<Change>>,
+) -> io::Result<()> {
+    let title = xml_escape(title);
+    let total: usize = dirs.values().map(|c| c.len()).sum();
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, r#"<meta charset="utf-8">"#)?;
+    writeln!(out, "<title>{}</title>", title)?;
+    out.write_all(HTML_HEAD.as_bytes())?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>{}</h1>", title)?;
+    if dirs.is_empty() {
+        writeln!(out, "<p>No changes.</p>")?;
+    } else {
+        writeln!(out, "<table>")?;
+        writeln!(out, "<tr><th>Change</th><th>Count</th></tr>")?;
+        for (action, count) in counts(dirs) {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                action_name(action),
+                count
+            )?;
+        }
+        writeln!(out, "<tr><th>total</th><th>{}</th></tr>", total)?;
+        writeln!(out, "</table>")?;
+        writeln!(
+            out,
+            r#"<p><input id="filter" type="search" placeholder="Filter"> <span id="shown">{}</span> shown</p>"#,
+            total
+        )?;
+        writeln!(out, r#"<table id="changes">"#)?;
+        writeln!(
+            out,
+            "<thead><tr><th>Path</th><th>Kind</th><th>Change</th><th>Details</th></tr></thead>"
+        )?;
+        writeln!(out, "<tbody>")?;
+        for change in dirs.values().flatten() {
+            let action = action_name(change.action);
+            writeln!(
+                out,
+                r#"<tr class="{}"><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+                action,
+                xml_escape(&change.path.to_string_lossy()),
+                xml_escape(&change.kind),
+                action,
+                xml_escape(&details(change))
+            )?;
+        }
+        writeln!(out, "</tbody>")?;
+        writeln!(out, "</table>")?;
+    }
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")
+}
+
+/// Escape text to go in Markdown, including a table cell, so that names
+/// are shown as they are, rather than as markup.
+fn md_escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '\\' | '`' | '*' | '_' | '[' | ']' | '~' | '|' | '#' => {
+                result.push('\\');
+                result.push(ch);
+            }
+            '\n' | '\r' => result.push(' '),
+            ch => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Escape text for use in XML or HTML content or attribute values.
+fn xml_escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            ch => result.push(ch),
+        }
+    }
+    result
+}
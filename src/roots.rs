@@ -0,0 +1,177 @@
+//! Several directories in one tree.
+//!
+//! A store can cover more than one directory, such as `/etc`, `/usr` and
+//! `/boot` together, by scanning each as a directory at the top of a tree,
+//! named after the last part of its path.  The top itself isn't a real
+//! directory, so it has no attributes but its kind, and the paths in the
+//! tree, such as `etc/passwd`, are found again by looking up the directory
+//! named by their first part.
+//!
+//! Each directory is scanned as it would be on its own: its `.rsureignore`,
+//! and the patterns in the scan options, are relative to it, and hardlinks
+//! are only found within it.
+
+use crate::{
+    escape::Escape,
+    exclude::Tombstones,
+    fs::{scan_fs_with, ScanIterator, ScanOptions},
+    node::SureNode,
+    platform::os_bytes,
+    suretree::AttMap,
+    Error, Result,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fs, iter,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The directories of a tree, each named at its top.
+#[derive(Clone, Debug)]
+pub struct Roots {
+    /// In the order of their names, which is the order of the tree.
+    roots: Vec<(OsString, PathBuf)>,
+}
+
+impl Roots {
+    /// The given directories, named after the last part of each path.  Two
+    /// with the same name are an error, as is a path with no name, such as
+    /// "/".
+    pub fn new<P: AsRef<Path>>(dirs: &[P]) -> Result<Roots> {
+        let mut roots = vec![];
+        for dir in dirs {
+            let dir = dir.as_ref();
+            let name = match dir.file_name() {
+                Some(name) => name.to_os_string(),
+                None => fs::canonicalize(dir)?
+                    .file_name()
+                    .ok_or_else(|| Error::RootName(dir.to_path_buf()))?
+                    .to_os_string(),
+            };
+            roots.push((name, dir.to_path_buf()));
+        }
+        roots.sort_by(|a, b| a.0.cmp(&b.0));
+        for pair in roots.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(Error::DuplicateRoot(
+                    pair[0].0.to_string_lossy().into_owned(),
+                ));
+            }
+        }
+        Ok(Roots { roots })
+    }
+
+    /// The name and path of each directory, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&OsStr, &Path)> {
+        self.roots.iter().map(|(n, p)| (n.as_os_str(), p.as_path()))
+    }
+
+    /// Where a path of the tree, relative to its top, is found.  A path
+    /// not in any of the directories is given back as it is.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        let mut parts = path.components();
+        if let Some(Component::Normal(name)) = parts.next() {
+            if let Some((_, dir)) = self.roots.iter().find(|(n, _)| n == name) {
+                return dir.join(parts.as_path());
+            }
+        }
+        path.to_path_buf()
+    }
+
+    /// Scan each of the directories, as `scan_fs_with`, into one tree.
+    pub fn scan(&self, options: &ScanOptions) -> Result<RootsScan> {
+        let tombstones = Arc::new(Mutex::new(Tombstones::new()));
+        let mut scans = vec![];
+        for (name, dir) in &self.roots {
+            scans.push(RootScan {
+                name: os_bytes(name).escaped(),
+                scan: scan_fs_with(dir, options)?,
+                top: true,
+                tombstones: tombstones.clone(),
+            });
+        }
+
+        let mut atts = AttMap::new();
+        atts.insert("kind".to_string(), "dir".to_string());
+        let top = SureNode::Enter {
+            name: "__root__".to_string(),
+            atts,
+        };
+        let nodes = iter::once(Ok(top))
+            .chain(scans.into_iter().flatten())
+            .chain(vec![Ok(SureNode::Sep), Ok(SureNode::Leave)]);
+        Ok(RootsScan {
+            nodes: Box::new(nodes),
+            tombstones,
+        })
+    }
+}
+
+/// A scan of several directories, from `Roots::scan`.
+pub struct RootsScan {
+    nodes: Box<dyn Iterator<Item = Result<SureNode>>>,
+    tombstones: Arc<Mutex<Tombstones>>,
+}
+
+impl RootsScan {
+    /// The paths this scan has left out, relative to the top, as
+    /// `ScanIterator::tombstones`.  Those of each directory are added once
+    /// its scan is done.
+    pub fn tombstones(&self) -> Arc<Mutex<Tombstones>> {
+        self.tombstones.clone()
+    }
+}
+
+impl Iterator for RootsScan {
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        self.nodes.next()
+    }
+}
+
+/// The scan of one of the directories, with its top renamed, and the paths
+/// it gives made relative to the top of the whole tree.
+struct RootScan {
+    name: String,
+    scan: ScanIterator,
+    /// Whether the top of the directory is still to come.
+    top: bool,
+    tombstones: Arc<Mutex<Tombstones>>,
+}
+
+impl Iterator for RootScan {
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        let node = match self.scan.next() {
+            Some(node) => node,
+            None => {
+                let done = std::mem::take(&mut *self.scan.tombstones().lock().unwrap());
+                let mut tombstones = self.tombstones.lock().unwrap();
+                for (path, rule) in done.iter() {
+                    tombstones.insert(format!("{}/{}", self.name, path), rule);
+                }
+                return None;
+            }
+        };
+        match node {
+            Ok(SureNode::Enter { atts, .. }) if self.top => {
+                self.top = false;
+                Some(Ok(SureNode::Enter {
+                    name: self.name.clone(),
+                    atts,
+                }))
+            }
+            // Hardlinks name the first file of their group by its path.
+            Ok(SureNode::File { name, mut atts }) => {
+                if let Some(first) = atts.get_mut("link") {
+                    *first = format!("{}/{}", self.name, first);
+                }
+                Some(Ok(SureNode::File { name, atts }))
+            }
+            node => Some(node),
+        }
+    }
+}
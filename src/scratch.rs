@@ -0,0 +1,49 @@
+//! Scratch directories, removed when dropped.
+//!
+//! The library needs a private directory now and then, such as to scan a
+//! tree into a store of its own.  This is all it needs of the `tempdir`
+//! crate, which is left to the executable and the tests.
+
+use crate::Result;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Keeps scratch directories of the same process apart.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A new directory under the system temp directory, removed, with its
+/// contents, when this is dropped.
+pub(crate) struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Make a directory whose name starts with `prefix`.
+    pub fn new(prefix: &str) -> Result<ScratchDir> {
+        let base = env::temp_dir();
+        loop {
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = base.join(format!("{}.{}.{}", prefix, process::id(), count));
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(ScratchDir { path }),
+                // Left behind by an earlier process of the same id.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
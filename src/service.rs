@@ -0,0 +1,200 @@
+//! Running under a service manager, such as systemd.
+//!
+//! Long-running modes report their state with `notify`, which implements
+//! the `sd_notify` protocol: a datagram sent to the socket named by
+//! `NOTIFY_SOCKET`.  When not started by systemd, this variable isn't set,
+//! and notifications are silently skipped.  If the unit has a watchdog
+//! configured, `watchdog_interval` gives how often the service must ping
+//! it with "WATCHDOG=1".
+//!
+//! `handle_termination` arranges for SIGTERM and SIGINT to request a
+//! graceful shutdown, which long-running modes check for with
+//! `shutdown_requested`, instead of being killed in the middle of writing.
+//!
+//! `pause_token` gives a token for the updates of the process, which, once
+//! `handle_pause` is called, SIGUSR1 pauses and SIGUSR2 resumes, so that an
+//! operator can have a long scan give the disks back to other work for a
+//! while, without losing its progress.  Nothing is done with the signals
+//! unless asked for, as a program embedding the library may have its own
+//! use for them.
+
+use crate::CancellationToken;
+use lazy_static::lazy_static;
+use log::info;
+use std::{
+    env, io,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs::File,
+    io::Read,
+    os::unix::io::FromRawFd,
+    sync::{atomic::AtomicI32, Once},
+    thread,
+};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// The pipe SIGUSR1 and SIGUSR2 are passed on through, once handled.
+#[cfg(unix)]
+static PAUSE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+lazy_static! {
+    static ref PAUSE_TOKEN: CancellationToken = CancellationToken::new();
+}
+
+/// Send a notification to the service manager, such as "READY=1".
+/// Returns false if there is no service manager to notify.
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<bool> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let sock = UnixDatagram::unbound()?;
+    match path.as_bytes() {
+        // A leading '@' names a socket in the abstract namespace.
+        #[cfg(target_os = "linux")]
+        [b'@', name @ ..] => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            sock.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// How often the watchdog must be pinged, if the service manager has a
+/// watchdog enabled for this process.  This is half of the configured
+/// timeout, as recommended by systemd.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Has a shutdown been requested by a signal?
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Make SIGTERM and SIGINT request a graceful shutdown, rather than
+/// terminating the process.
+#[cfg(unix)]
+pub fn handle_termination() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    // These can only fail for an invalid signal.
+    let _ = set_handler(libc::SIGTERM, on_signal);
+    let _ = set_handler(libc::SIGINT, on_signal);
+}
+
+#[cfg(not(unix))]
+pub fn handle_termination() {}
+
+/// The token for the updates of this process, which SIGUSR1 pauses, and
+/// SIGUSR2 resumes, once `handle_pause` has been called.
+pub fn pause_token() -> CancellationToken {
+    PAUSE_TOKEN.clone()
+}
+
+/// Make SIGUSR1 pause, and SIGUSR2 resume, the `pause_token`.  The signals
+/// are only handled from the first call.
+#[cfg(unix)]
+pub fn handle_pause() -> io::Result<()> {
+    static START: Once = Once::new();
+    let mut result = Ok(());
+    START.call_once(|| result = start_pause());
+    result
+}
+
+#[cfg(not(unix))]
+pub fn handle_pause() -> io::Result<()> {
+    Ok(())
+}
+
+/// Pass the signals on to a thread, over a pipe, as a signal handler can't
+/// take the token's lock.  The thread waits on the pipe between signals.
+#[cfg(unix)]
+fn start_pause() -> io::Result<()> {
+    extern "C" fn on_signal(signal: libc::c_int) {
+        let byte = (signal == libc::SIGUSR1) as u8;
+        // Only `write` is safe here.  A signal arriving while the pipe is
+        // full is dropped, which the ones already queued make up for.
+        unsafe {
+            libc::write(
+                PAUSE_PIPE.load(Ordering::SeqCst),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for &fd in &fds {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    let mut signals = unsafe { File::from_raw_fd(fds[0]) };
+    PAUSE_PIPE.store(fds[1], Ordering::SeqCst);
+    set_handler(libc::SIGUSR1, on_signal)?;
+    set_handler(libc::SIGUSR2, on_signal)?;
+
+    thread::spawn(move || {
+        let mut byte = [0];
+        while signals.read_exact(&mut byte).is_ok() {
+            let pause = byte[0] == 1;
+            if pause != PAUSE_TOKEN.is_paused() {
+                if pause {
+                    info!("Pausing");
+                    PAUSE_TOKEN.pause();
+                } else {
+                    info!("Resuming");
+                    PAUSE_TOKEN.resume();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Install a handler for the signal with `sigaction`, restarting the system
+/// calls it interrupts.
+#[cfg(unix)]
+fn set_handler(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,183 @@
-// Show module.
+//! Showing a version of the store, as an indented tree.
 
-use crate::{Result, Store, Version};
+use crate::{
+    escape::Unescape,
+    platform::{os_bytes, os_from_bytes},
+    suretree::AttMap,
+    Error, Result, Store, SureNode, Version,
+};
+use chrono::{TimeZone, Utc};
+use std::{
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
 
+/// The attributes shown when none are asked for.
+pub const DEFAULT_ATTS: &[&str] = &["perm", "uid", "gid", "size", "mtime"];
+
+/// What of a version to show.
+#[derive(Clone, Debug, Default)]
+pub struct ShowOptions {
+    /// Show just this directory, or file, relative to the top of the tree.
+    pub path: Option<PathBuf>,
+    /// Show nothing more than this many levels below the top, or `path`.
+    pub depth: Option<usize>,
+    /// The attributes to show, in this order, rather than `DEFAULT_ATTS`,
+    /// or every attribute of each node, if this holds just "all".
+    pub atts: Vec<String>,
+}
+
+/// Show the latest version of the store on stdout.
 pub fn show_tree(store: &dyn Store) -> Result<()> {
-    for node in store.load_iter(Version::Latest)? {
+    let stdout = io::stdout();
+    show_nodes(
+        store.load_iter(Version::Latest)?,
+        &ShowOptions::default(),
+        stdout.lock(),
+    )
+}
+
+/// Write the nodes, as an indented tree, one node per line, with the
+/// directories ending with a "/", and the chosen attributes after each.
+pub fn show_nodes<I, W>(nodes: I, options: &ShowOptions, mut out: W) -> Result<()>
+where
+    I: Iterator<Item = Result<SureNode>>,
+    W: Write,
+{
+    let top: Vec<Vec<u8>> = match &options.path {
+        Some(path) => parts(path)?,
+        None => vec![],
+    };
+    let keys: Vec<&str> = if options.atts.is_empty() {
+        DEFAULT_ATTS.to_vec()
+    } else {
+        options.atts.iter().map(|a| a.as_str()).collect()
+    };
+
+    // The path of the current directory, below the root.
+    let mut dirs: Vec<Vec<u8>> = vec![];
+    let mut at_root = true;
+    let mut found = false;
+    for node in nodes {
         let node = node?;
-        println!("{:?}", node);
+        let (name, atts_of, is_dir) = match &node {
+            SureNode::Enter { atts, .. } if at_root => {
+                at_root = false;
+                if top.is_empty() {
+                    found = true;
+                    write_node(&mut out, 0, ".", true, atts, &keys)?;
+                }
+                continue;
+            }
+            SureNode::Enter { name, atts } => (name, atts, true),
+            SureNode::File { name, atts } => (name, atts, false),
+            SureNode::Leave => {
+                dirs.pop();
+                continue;
+            }
+            SureNode::Sep => continue,
+        };
+        let name = name
+            .unescape()
+            .map_err(|_| Error::InvalidEscape(name.to_string()))?;
+        dirs.push(name);
+
+        if dirs.len() >= top.len() && dirs[..top.len()] == top[..] {
+            let depth = dirs.len() - top.len();
+            if options.depth.is_none_or(|max| depth <= max) {
+                found = true;
+                // The top is shown as it was asked for, and the rest by name.
+                let shown = match &options.path {
+                    Some(path) if depth == 0 => path.clone(),
+                    _ => PathBuf::from(os_from_bytes(dirs[dirs.len() - 1].clone())),
+                };
+                write_node(
+                    &mut out,
+                    depth,
+                    &shown.display().to_string(),
+                    is_dir,
+                    atts_of,
+                    &keys,
+                )?;
+            }
+        }
+
+        if !is_dir {
+            dirs.pop();
+        }
     }
+
+    if found {
+        Ok(())
+    } else {
+        Err(Error::PathNotInVersion(
+            options.path.clone().unwrap_or_default(),
+        ))
+    }
+}
+
+/// The names of each part of a path relative to the top of the tree.
+fn parts(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut result = vec![];
+    for part in path.components() {
+        match part {
+            Component::CurDir => (),
+            Component::Normal(name) => result.push(os_bytes(name).into_owned()),
+            _ => return Err(Error::PathNotInVersion(path.to_path_buf())),
+        }
+    }
+    Ok(result)
+}
+
+fn write_node<W: Write>(
+    out: &mut W,
+    depth: usize,
+    name: &str,
+    is_dir: bool,
+    atts: &AttMap,
+    shown: &[&str],
+) -> Result<()> {
+    write!(out, "{:width$}{}", "", name, width = depth * 2)?;
+    if is_dir && name != "." {
+        write!(out, "/")?;
+    }
+    if let Some(targ) = atts.get("targ") {
+        let targ = targ
+            .unescape()
+            .map_err(|_| Error::InvalidEscape(targ.to_string()))?;
+        write!(out, " -> {}", Path::new(&os_from_bytes(targ)).display())?;
+    }
+    if shown == ["all"] {
+        for (key, value) in atts {
+            write!(out, "  {}={}", key, readable(key, value))?;
+        }
+    } else {
+        for key in shown {
+            if let Some(value) = atts.get(*key) {
+                write!(out, "  {}={}", key, readable(key, value))?;
+            }
+        }
+    }
+    writeln!(out)?;
     Ok(())
 }
+
+/// An attribute, as it is easier to read: permissions in octal, and times
+/// as dates.
+fn readable(key: &str, value: &str) -> String {
+    match key {
+        "perm" => match value.parse::<u32>() {
+            Ok(perm) => format!("{:04o}", perm),
+            Err(_) => value.to_string(),
+        },
+        "mtime" | "ctime" | "btime" => match value
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| Utc.timestamp_opt(t, 0).single())
+        {
+            Some(time) => time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            None => value.to_string(),
+        },
+        _ => value.to_string(),
+    }
+}
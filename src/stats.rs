@@ -0,0 +1,209 @@
+//! Counters for performance work.
+//!
+//! Updates count the nodes they scan and the attributes those nodes hold,
+//! the files and bytes they hash, and the time taken and bytes written by
+//! each stage.  The counters are process wide, and accumulate across every
+//! update the process runs, with each stage's runs added together under its
+//! name, so take a `snapshot` before and after to measure a single one.  The written bytes are counted before any
+//! compression done by the store.
+
+use lazy_static::lazy_static;
+use serde_derive::Serialize;
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref STATS: Stats = Stats::new();
+}
+
+/// The process wide counters.
+pub fn global() -> &'static Stats {
+    &STATS
+}
+
+pub struct Stats {
+    nodes_scanned: AtomicU64,
+    attrs: AtomicU64,
+    files_hashed: AtomicU64,
+    bytes_hashed: AtomicU64,
+    stages: Mutex<Vec<Stage>>,
+}
+
+/// The counters at a point in time.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StatsSnapshot {
+    /// Nodes read from the filesystem.
+    pub nodes_scanned: u64,
+    /// Attributes held by the scanned nodes.
+    pub attrs: u64,
+    pub files_hashed: u64,
+    pub bytes_hashed: u64,
+    /// Each stage that has run, in the order each first ran.
+    pub stages: Vec<Stage>,
+}
+
+/// A stage of an update, totalled over each time it ran.
+#[derive(Clone, Debug, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    /// The number of times the stage ran.
+    pub runs: u64,
+    pub elapsed: Duration,
+    /// Bytes written to the stage's output, if it writes a node stream.
+    pub bytes_written: Option<u64>,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            nodes_scanned: AtomicU64::new(0),
+            attrs: AtomicU64::new(0),
+            files_hashed: AtomicU64::new(0),
+            bytes_hashed: AtomicU64::new(0),
+            stages: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            nodes_scanned: self.nodes_scanned.load(Ordering::Relaxed),
+            attrs: self.attrs.load(Ordering::Relaxed),
+            files_hashed: self.files_hashed.load(Ordering::Relaxed),
+            bytes_hashed: self.bytes_hashed.load(Ordering::Relaxed),
+            stages: self.stages.lock().unwrap().clone(),
+        }
+    }
+
+    pub(crate) fn add_scanned(&self, attrs: usize) {
+        self.nodes_scanned.fetch_add(1, Ordering::Relaxed);
+        self.attrs.fetch_add(attrs as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_hashed(&self, bytes: u64) {
+        self.files_hashed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record a stage that started at `start`, adding it to the earlier runs
+    /// of the stage.
+    pub(crate) fn add_stage(&self, name: &'static str, start: Instant, bytes_written: Option<u64>) {
+        let elapsed = start.elapsed();
+        let mut stages = self.stages.lock().unwrap();
+        match stages.iter_mut().find(|s| s.name == name) {
+            Some(stage) => {
+                stage.runs += 1;
+                stage.elapsed += elapsed;
+                stage.bytes_written = add_written(stage.bytes_written, bytes_written);
+            }
+            None => stages.push(Stage {
+                name,
+                runs: 1,
+                elapsed,
+                bytes_written,
+            }),
+        }
+    }
+}
+
+fn add_written(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+impl StatsSnapshot {
+    /// The counts since an earlier snapshot.
+    pub fn since(&self, earlier: &StatsSnapshot) -> StatsSnapshot {
+        StatsSnapshot {
+            nodes_scanned: self.nodes_scanned - earlier.nodes_scanned,
+            attrs: self.attrs - earlier.attrs,
+            files_hashed: self.files_hashed - earlier.files_hashed,
+            bytes_hashed: self.bytes_hashed - earlier.bytes_hashed,
+            stages: self
+                .stages
+                .iter()
+                .filter_map(
+                    |stage| match earlier.stages.iter().find(|s| s.name == stage.name) {
+                        Some(before) if before.runs == stage.runs => None,
+                        Some(before) => Some(Stage {
+                            name: stage.name,
+                            runs: stage.runs - before.runs,
+                            elapsed: stage.elapsed - before.elapsed,
+                            bytes_written: stage
+                                .bytes_written
+                                .map(|bytes| bytes - before.bytes_written.unwrap_or(0)),
+                        }),
+                        None => Some(stage.clone()),
+                    },
+                )
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "stage    |   runs |       time |      written")?;
+        writeln!(f, "---------+--------+------------+-------------")?;
+        for stage in &self.stages {
+            let written = match stage.bytes_written {
+                Some(bytes) => bytes.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:8} | {:>6} | {:>9.3}s | {:>12}",
+                stage.name,
+                stage.runs,
+                stage.elapsed.as_secs_f64(),
+                written
+            )?;
+        }
+        writeln!(
+            f,
+            "{} nodes scanned, holding {} attributes",
+            self.nodes_scanned, self.attrs
+        )?;
+        write!(
+            f,
+            "{} files hashed, {} bytes",
+            self.files_hashed, self.bytes_hashed
+        )
+    }
+}
+
+/// A writer that counts the bytes written through it.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> CountingWriter<W> {
+        CountingWriter { inner, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,22 +1,54 @@
 // Surefile store
 
-use crate::{Error, Result, SureNode};
-use chrono::{DateTime, Utc};
+use crate::{
+    cancel::CancellationToken,
+    clock,
+    ignore::IgnoreRules,
+    node::{compare_dir, Change, ChangeSummary},
+    Clock, Error, Result, SureNode,
+};
+use chrono::{DateTime, Local, Utc};
 use log::info;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
     io::{BufRead, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
+mod artifact;
+#[cfg(feature = "encryption")]
+mod crypt;
+mod object;
+#[cfg(feature = "s3")]
+mod s3;
+mod signed;
+mod ssh;
 mod weave;
 
-pub use self::weave::WeaveStore;
+pub use self::artifact::ARTIFACT_EXT;
+#[cfg(feature = "encryption")]
+pub use self::crypt::AgeCipher;
+pub use self::object::{Bucket, ObjectStore};
+#[cfg(feature = "s3")]
+pub use self::s3::{sign_v4, S3Bucket};
+pub use self::signed::{SignedStore, SigningKeys, SIGNATURE_ARTIFACT};
+pub use self::ssh::SshBucket;
 use self::weave::Compression;
+pub use self::weave::WeaveStore;
+use ::weave::{Cipher, NamingConvention, SimpleNaming, WeaveLock};
+pub use ::weave::{Problem as WeaveProblem, WeaveStats};
 
 /// Tags are just key/value pairs.  Both key and value should be printable strings.
 pub type StoreTags = BTreeMap<String, String>;
 
+/// A store locked against other writers, from [`Store::lock`], until this is dropped.
+#[derive(Debug, Default)]
+pub struct StoreLock(Option<WeaveLock>);
+
 /// Something that can store and retrieve SureTrees.
 pub trait Store {
     /// Retrieve the available versions, in the store.  These should be listed, newest first.
@@ -26,10 +58,176 @@ pub trait Store {
     fn load_iter(&self, version: Version) -> Result<Box<dyn Iterator<Item = Result<SureNode>>>>;
 
     /// Create a temporary storage location.
-    fn make_temp(&self) -> Result<Box<dyn TempFile + '_>>;
+    fn make_temp(&self) -> Result<Box<dyn TempFile<'_> + '_>>;
 
     /// Create a writer for a new version.
-    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter + '_>>;
+    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter<'_> + '_>>;
+
+    /// Lock the store against other writers, until the lock is dropped, or given to
+    /// `make_new_locked`.  An update takes the lock before reading the latest version, so that
+    /// another can't add one in between.  By default, there is nothing to lock.
+    fn lock(&self) -> Result<StoreLock> {
+        Ok(StoreLock::default())
+    }
+
+    /// Create a writer for a new version, with the lock taken by `lock`, rather than taking it
+    /// again.
+    fn make_new_locked(
+        &self,
+        tags: &StoreTags,
+        _lock: StoreLock,
+    ) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        self.make_new(tags)
+    }
+
+    /// The time a version written now would be stamped with, from the store's clock, such as to
+    /// name it with.
+    fn now(&self) -> DateTime<Utc> {
+        clock::default_clock().now()
+    }
+
+    // The settings below only tune how a store is read and written, so each does nothing by
+    // default, for stores that have no use for it.  Those that protect the store, or where it
+    // writes, give `Error::Unsupported` instead, so that they aren't quietly ignored.
+
+    /// Set the clock that new versions take their timestamps from.
+    fn set_clock(&mut self, _clock: Box<dyn Clock>) {}
+
+    /// Write new versions as independently compressed blocks, so that a single version can be
+    /// read without decompressing the whole history.  A store that is already blocked stays
+    /// blocked.
+    fn set_blocked(&mut self) {}
+
+    /// Decompress the store on up to this many other threads when reading it, rather than on the
+    /// reading thread.
+    fn set_decode_threads(&mut self, _threads: usize) {}
+
+    /// Compare new versions with the one before as they are written, within a window of this many
+    /// lines, rather than holding the previous version in memory, and the new one in a temp file.
+    /// Changes larger than the window take more space in the store.
+    fn set_delta_window(&mut self, _lines: usize) {}
+
+    /// Encrypt the store, and the temp files written while updating it, with this cipher.  A
+    /// store that was written without it can't be read with it, nor the other way around.  The
+    /// stores in this crate all encrypt, but one that can't, by default, refuses the cipher,
+    /// rather than writing in the clear.
+    fn set_cipher(&mut self, _cipher: Arc<dyn Cipher>) -> Result<()> {
+        Err(Error::Unsupported("encrypt".to_string()))
+    }
+
+    /// Wait up to this long for another writer to finish with the store, rather than failing as
+    /// soon as it is found locked.  Only one writer adds a version to a store at a time.
+    fn set_lock_wait(&mut self, _wait: Duration) -> Result<()> {
+        Err(Error::Unsupported("wait for its lock".to_string()))
+    }
+
+    /// Keep this many backups of the store, the previous one being `2sure.bak.gz`, and older ones
+    /// `2sure.bak.1.gz` and so on, rather than just the one.
+    fn set_backups(&mut self, _count: usize) {}
+
+    /// Write the temp files of updates in this directory, such as one on a faster disk, rather
+    /// than beside the store.  The new store is copied into place if the directory is on another
+    /// filesystem, rather than just renamed.
+    fn set_temp_dir(&mut self, _dir: &Path) -> Result<()> {
+        Err(Error::Unsupported(
+            "write its temp files elsewhere".to_string(),
+        ))
+    }
+
+    /// Remove a version from the store, such as a snapshot taken of the wrong tree.  The other
+    /// versions are unchanged.  The only version in a store can't be removed.  By default, the
+    /// store can't remove versions at all.
+    fn delete_version(&self, _version: Version) -> Result<()> {
+        Err(Error::Unsupported("delete versions".to_string()))
+    }
+
+    /// The path of a file kept alongside the store, named with the given extension, such as an
+    /// index of the store.  A store that can't keep files alongside it, such as one whose only
+    /// local copy is a cache, gives an error, so that what would be kept there, such as artifacts
+    /// and ignore rules, isn't quietly lost.
+    fn sidecar(&self, _ext: &str) -> Result<PathBuf> {
+        Err(Error::Unsupported("keep files alongside it".to_string()))
+    }
+
+    /// The size of the store's weave, as stored and decompressed, and the number of lines of each
+    /// version, by its number, to see how the store grows.  This reads through the whole weave.
+    fn weave_stats(&self) -> Result<WeaveStats> {
+        Err(Error::Unsupported(
+            "report the size of its weave".to_string(),
+        ))
+    }
+
+    /// Check the structure of the store's weave, such as that each insert and delete is ended,
+    /// and names a version the store has, returning the problems found.  Damage that would
+    /// otherwise only show up when a version is read, or not at all, is found this way.
+    fn fsck(&self) -> Result<Vec<WeaveProblem>> {
+        Err(Error::Unsupported(
+            "check the structure of its weave".to_string(),
+        ))
+    }
+
+    /// Attach an artifact to a version, such as the report of the scan that made it, replacing
+    /// any artifact of the same kind.  The kind names the artifact, with letters, digits, '-',
+    /// '_' and '.'.  Artifacts are meant to be small, and are kept alongside the store.
+    fn put_artifact(&self, version: &Version, kind: &str, data: &[u8]) -> Result<()> {
+        artifact::put(self, version, kind, data)
+    }
+
+    /// Read back an artifact of a version, if it has one of this kind.
+    fn get_artifact(&self, version: &Version, kind: &str) -> Result<Option<Vec<u8>>> {
+        artifact::get(self, version, kind)
+    }
+
+    /// The kinds of the artifacts attached to a version, in order.
+    fn artifacts(&self, version: &Version) -> Result<Vec<String>> {
+        artifact::list(self, version)
+    }
+
+    /// Remove the artifacts of versions no longer in the store, returning the number of versions
+    /// whose artifacts were removed.  Deleting a version removes its own, so this is for those
+    /// left behind some other way, such as by an older rsure.
+    fn prune_artifacts(&self) -> Result<usize> {
+        artifact::prune(self)
+    }
+
+    /// Compare one directory, and everything in it, between two versions, calling `on_change`
+    /// with each difference, with its path relative to the top of the tree.  Only as much of each
+    /// version is read as it takes to get past the directory, which is much quicker than
+    /// comparing whole versions, for a directory near the start of a large tree.
+    fn diff_dir(
+        &self,
+        from: Version,
+        to: Version,
+        path: &Path,
+        rules: &IgnoreRules,
+        on_change: &mut dyn FnMut(Change),
+    ) -> Result<ChangeSummary> {
+        compare_dir(
+            self.load_iter(from)?,
+            self.load_iter(to)?,
+            "",
+            path,
+            rules,
+            on_change,
+        )
+    }
+
+    /// Look up the information about a single version, if it is present.
+    fn get_version(&self, version: &Version) -> Result<Option<StoreVersion>> {
+        let versions = self.get_versions()?;
+        Ok(match version {
+            Version::Latest => versions.into_iter().next(),
+            Version::Prior => versions.into_iter().nth(1),
+            Version::Tagged(_) => {
+                let num = version.numeric();
+                versions
+                    .into_iter()
+                    .find(|v| num.is_some() && v.version.numeric() == num)
+            }
+            // The latest, if several have the name.
+            Version::Named(name) => versions.into_iter().find(|v| &v.name == name),
+        })
+    }
 }
 
 /// A TempFile is a temporary storage location that can be written to, and
@@ -53,8 +251,10 @@ pub trait TempFile<'a>: Write {
 
 /// A temp file that can spawn multiple loaders.
 pub trait TempLoader {
-    /// Open the temp file, and return a reader on it.
-    fn new_loader(&self) -> Result<Box<dyn BufRead>>;
+    /// Open the temp file, and return a reader on it.  The reader checks
+    /// the data against the checksum taken as it was written, giving an
+    /// error at the end of the file if they differ.
+    fn new_loader(&self) -> Result<Box<dyn BufRead + Send>>;
 
     /// Return the name of the temp file.
     fn path_ref(&self) -> &Path;
@@ -69,6 +269,11 @@ pub trait TempLoader {
 pub trait StoreWriter<'a>: Write {
     /// All data has been written, commit this as a new version.
     fn commit(self: Box<Self>) -> Result<()>;
+
+    /// Stop committing, with `Error::Cancelled`, once this is cancelled, for a store whose commit
+    /// has much work of its own to do, such as merging the version into a weave.  By default,
+    /// the token is ignored.
+    fn set_cancel(&mut self, _cancel: CancellationToken) {}
 }
 
 pub trait TempCleaner {}
@@ -79,20 +284,51 @@ pub enum Version {
     Latest,
     Prior,
     Tagged(String),
+    /// The version with this "name" tag.
+    Named(String),
 }
 
 impl Version {
     /// Retrieve this version as a number, or none if that makes no sense
-    /// (either it is `Latest`, `Prior`, or the textual version is not an
-    /// integer).
+    /// (either it is `Latest`, `Prior`, `Named`, or the textual version is
+    /// not an integer).
     pub fn numeric(&self) -> Option<usize> {
         match self {
-            Version::Latest | Version::Prior => None,
+            Version::Latest | Version::Prior | Version::Named(_) => None,
             Version::Tagged(text) => text.parse().ok(),
         }
     }
 }
 
+/// Parses a version as given on the command line: "latest", "prior",
+/// "name:" followed by the name tag, or otherwise the version number.
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Version> {
+        Ok(match text {
+            "latest" => Version::Latest,
+            "prior" => Version::Prior,
+            text => match text.strip_prefix("name:") {
+                Some("") => return Err(Error::UnknownVersion(text.to_string())),
+                Some(name) => Version::Named(name.to_string()),
+                None => Version::Tagged(text.to_string()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Latest => write!(f, "latest"),
+            Version::Prior => write!(f, "prior"),
+            Version::Tagged(text) => write!(f, "{}", text),
+            Version::Named(name) => write!(f, "name:{}", name),
+        }
+    }
+}
+
 /// Information about a given version in the store.
 #[derive(Clone, Debug)]
 pub struct StoreVersion {
@@ -102,21 +338,64 @@ pub struct StoreVersion {
     pub time: DateTime<Utc>,
     /// The identifier for this version.
     pub version: Version,
+    /// The tags given when this version was created.
+    pub tags: StoreTags,
+}
+
+/// The name for a new version made at `time`, when none is given: the local time.  Versions can
+/// be made within the same second, so if the store already has a version of that name, a suffix
+/// is added, as in "2024-03-01T12:00:00+00:00-2".
+pub fn version_name(store: &dyn Store, time: DateTime<Utc>) -> Result<String> {
+    let base = time.with_timezone(&Local).to_rfc3339();
+    let names: BTreeSet<String> = store.get_versions()?.into_iter().map(|v| v.name).collect();
+    let mut name = base.clone();
+    let mut count = 1;
+    while names.contains(&name) {
+        count += 1;
+        name = format!("{}-{}", base, count);
+    }
+    Ok(name)
 }
 
 /// Parse a command line specified path to determine the parameters and type of store desired.  The
 /// path can be the path to a directory.  In this case, look at possible filenames to determine the
 /// other parameters.  The path can also give a filename of one of the surefiles, and we will
 /// derive the name information from that.
+///
+/// A store in an S3 bucket is given as `s3://bucket/prefix`, with the credentials taken from the
+/// environment, as described for `S3Bucket::from_env`.  This needs the "s3" feature.  A store on
+/// another host is given as `ssh://[user@]host[:port]/path/2sure.dat.gz`, and read and written
+/// with the `ssh` command, or the command in `RSURE_SSH`.
 pub fn parse_store(text: &str) -> Result<Box<dyn Store>> {
+    if let Some(path) = text.strip_prefix("s3://") {
+        return parse_object_store(path);
+    }
+    if let Some(path) = text.strip_prefix("ssh://") {
+        let (bucket, path) = SshBucket::parse(path)?;
+        let bucket = match env::var("RSURE_SSH") {
+            Ok(command) => bucket.with_command(&command),
+            Err(_) => bucket,
+        };
+        return Ok(Box::new(ObjectStore::with_key(Box::new(bucket), &path)?));
+    }
+
     // First determine if this path is a directory.
     let p = Path::new(text);
     info!("Parsing: {:?}", p);
 
-    // If we're given an existing directory, construct a store directly from it.
-    // TODO: Look in the directory to see what might be there.
+    // If we're given an existing directory, construct a store directly from it, using the
+    // compression of a store already there, or gzip for a new store.
     if p.is_dir() {
-        return Ok(Box::new(WeaveStore::new(p.to_path_buf(), "2sure", Compression::Gzip)));
+        let compression = [Compression::Gzip, Compression::Zstd, Compression::Plain]
+            .iter()
+            .copied()
+            .find(|&c| {
+                SimpleNaming::new(p, "2sure", "dat", c)
+                    .main_file()
+                    .is_file()
+            })
+            .unwrap_or(Compression::Gzip);
+        return Ok(Box::new(WeaveStore::new(p, "2sure", compression)));
     }
 
     // Otherwise, try to get the parent.  If it seems to be empty, use the current directory as the
@@ -145,17 +424,25 @@ pub fn parse_store(text: &str) -> Result<Box<dyn Store>> {
         None => panic!("Path came from string, yet is no longer UTF-8"),
     };
 
-    let (base, compression) = if let Some(core_name) = base.strip_suffix(".gz") {
+    let (base, ext, compression) = split_name(base);
+    Ok(Box::new(WeaveStore::with_ext(dir, base, ext, compression)))
+}
+
+/// Split the file name of a store into its base, the extension of its main file, and its
+/// compression.  `2sure.dat.gz`, and its backup, `2sure.bak.gz`, are both the store with the base
+/// "2sure", and a main file of `2sure.dat.gz`.
+pub(crate) fn split_name(name: &str) -> (&str, &'static str, Compression) {
+    let (base, compression) = if let Some(core_name) = name.strip_suffix(".gz") {
         (core_name, Compression::Gzip)
-    } else if let Some(core_name) = base.strip_suffix(".zstd") {
+    } else if let Some(core_name) = name.strip_suffix(".zstd") {
         (core_name, Compression::Zstd)
     } else {
-        (base, Compression::Plain)
+        (name, Compression::Plain)
     };
 
     // Check for weave format.
     if let Some(base) = base.strip_suffix(".weave") {
-        return Ok(Box::new(WeaveStore::new(dir, base, compression)));
+        return (base, "weave", compression);
     }
 
     // Strip off known suffixes.
@@ -164,6 +451,67 @@ pub fn parse_store(text: &str) -> Result<Box<dyn Store>> {
     } else {
         base
     };
+    (base, "dat", compression)
+}
+
+#[cfg(feature = "s3")]
+fn parse_object_store(path: &str) -> Result<Box<dyn Store>> {
+    let (bucket, prefix) = match path.find('/') {
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => (path, ""),
+    };
+    if bucket.is_empty() {
+        return Err(Error::ObjectStore("No bucket given".to_string()));
+    }
+    let bucket = S3Bucket::from_env(bucket)?;
+    Ok(Box::new(ObjectStore::new(Box::new(bucket), prefix)?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn parse_object_store(_path: &str) -> Result<Box<dyn Store>> {
+    Err(Error::ObjectStore(
+        "rsure was built without the \"s3\" feature".to_string(),
+    ))
+}
+
+#[test]
+fn test_store_defaults() {
+    // A store only has to give its versions, read them, and write new ones.
+    struct Empty;
+
+    impl Store for Empty {
+        fn get_versions(&self) -> Result<Vec<StoreVersion>> {
+            Ok(vec![])
+        }
+
+        fn load_iter(
+            &self,
+            version: Version,
+        ) -> Result<Box<dyn Iterator<Item = Result<SureNode>>>> {
+            Err(Error::UnknownVersion(version.to_string()))
+        }
+
+        fn make_temp(&self) -> Result<Box<dyn TempFile<'_> + '_>> {
+            Err(Error::Unsupported("write".to_string()))
+        }
+
+        fn make_new(&self, _tags: &StoreTags) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+            Err(Error::Unsupported("write".to_string()))
+        }
+    }
 
-    Ok(Box::new(WeaveStore::new(dir, base, compression)))
+    let mut store = Empty;
+    store.set_blocked();
+    store.set_backups(3);
+    assert!(matches!(
+        store.set_lock_wait(Duration::from_secs(1)),
+        Err(Error::Unsupported(_))
+    ));
+    assert!(store.get_version(&Version::Latest).unwrap().is_none());
+    assert!(matches!(
+        store.delete_version(Version::Latest),
+        Err(Error::Unsupported(_))
+    ));
+    assert!(matches!(store.fsck(), Err(Error::Unsupported(_))));
+    assert!(matches!(store.sidecar("aux"), Err(Error::Unsupported(_))));
 }
@@ -0,0 +1,141 @@
+//! Artifacts attached to the versions of a store.
+//!
+//! A version can carry small auxiliary files, such as the report of the scan that made it, or a
+//! signature of it.  Each is named by its kind, and kept in a directory alongside the store,
+//! `2sure.aux/<version number>/<kind>`, so that the artifacts of a version can be listed, and are
+//! removed along with it.
+
+use crate::{
+    store::{Store, Version},
+    Error, Result,
+};
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, FileType},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The extension of the directory of artifacts, alongside the store.
+pub const ARTIFACT_EXT: &str = "aux";
+
+/// The directory holding the artifacts of a version.
+fn version_dir<S: Store + ?Sized>(store: &S, version: &Version) -> Result<PathBuf> {
+    let number = store
+        .get_version(version)?
+        .and_then(|v| v.version.numeric())
+        .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+    Ok(store.sidecar(ARTIFACT_EXT)?.join(number.to_string()))
+}
+
+/// Kinds name files, so are kept to letters, digits, '-', '_' and '.', and can't start with a '.'.
+fn check_kind(kind: &str) -> Result<()> {
+    let valid = !kind.is_empty()
+        && !kind.starts_with('.')
+        && kind
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidArtifact(kind.to_string()))
+    }
+}
+
+pub(crate) fn put<S: Store + ?Sized>(
+    store: &S,
+    version: &Version,
+    kind: &str,
+    data: &[u8],
+) -> Result<()> {
+    check_kind(kind)?;
+    let dir = version_dir(store, version)?;
+    fs::create_dir_all(&dir)?;
+
+    // Written to the side, and renamed, so a reader never sees part of one.
+    let temp = dir.join(format!(".{}.tmp", kind));
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temp, dir.join(kind))?;
+    Ok(())
+}
+
+pub(crate) fn get<S: Store + ?Sized>(
+    store: &S,
+    version: &Version,
+    kind: &str,
+) -> Result<Option<Vec<u8>>> {
+    check_kind(kind)?;
+    match fs::read(version_dir(store, version)?.join(kind)) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) fn list<S: Store + ?Sized>(store: &S, version: &Version) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(version_dir(store, version)?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut kinds = vec![];
+    for entry in entries {
+        if let Some(name) = entry?.file_name().to_str() {
+            if check_kind(name).is_ok() {
+                kinds.push(name.to_string());
+            }
+        }
+    }
+    kinds.sort();
+    Ok(kinds)
+}
+
+pub(crate) fn prune<S: Store + ?Sized>(store: &S) -> Result<usize> {
+    let top = store.sidecar(ARTIFACT_EXT)?;
+    let entries = match fs::read_dir(&top) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let present: BTreeSet<String> = store
+        .get_versions()?
+        .iter()
+        .filter_map(|v| v.version.numeric())
+        .map(|n| n.to_string())
+        .collect();
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !present.contains(&*name.to_string_lossy()) {
+            remove_entry(&entry.path(), entry.file_type()?)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove the artifacts of a version, before the version itself is deleted, so that a failure
+/// leaves the version in place, rather than artifacts with no version.
+pub(crate) fn remove<S: Store + ?Sized>(store: &S, version: &Version) -> Result<()> {
+    let dir = version_dir(store, version)?;
+    match fs::symlink_metadata(&dir) {
+        Ok(meta) => remove_entry(&dir, meta.file_type()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove something from the artifacts directory.  Only directories are made there, but anything
+/// else found there is removed too, and a link isn't followed.
+fn remove_entry(path: &Path, file_type: FileType) -> Result<()> {
+    if file_type.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
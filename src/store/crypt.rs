@@ -0,0 +1,98 @@
+//! Stores encrypted with age.
+//!
+//! The store, its backup, and the temp files written while updating it, are encrypted to the
+//! X25519 identities of an age identity file, such as one written by `age-keygen`.  The same
+//! file is needed to read the store back, so it should be kept apart from the store.
+
+use crate::{Error, Result};
+use age::{x25519, Decryptor, Encryptor};
+use std::{
+    fmt, fs,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+use weave::{Cipher, EncryptWrite};
+
+/// Encrypts a store to the identities of an age identity file.
+pub struct AgeCipher {
+    identities: Vec<x25519::Identity>,
+    recipients: Vec<x25519::Recipient>,
+}
+
+impl AgeCipher {
+    /// Read the identities of an age identity file.
+    pub fn from_identity_file<P: AsRef<Path>>(path: P) -> Result<AgeCipher> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Encryption(format!("Unable to read {:?}: {}", path, e)))?;
+        AgeCipher::parse(&text)
+    }
+
+    /// Parse the text of an identity file.  Blank lines, and comments starting with '#', are
+    /// skipped.  New files are encrypted to every identity, and can be read with any of them.
+    pub fn parse(text: &str) -> Result<AgeCipher> {
+        let identities = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.parse::<x25519::Identity>()
+                    .map_err(|e| Error::Encryption(format!("Invalid identity: {}", e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if identities.is_empty() {
+            return Err(Error::Encryption("No identities given".to_string()));
+        }
+        let recipients = identities.iter().map(|id| id.to_public()).collect();
+        Ok(AgeCipher {
+            identities,
+            recipients,
+        })
+    }
+}
+
+impl fmt::Debug for AgeCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgeCipher")
+            .field("recipients", &self.recipients)
+            .finish()
+    }
+}
+
+impl Cipher for AgeCipher {
+    fn encrypt(&self, dest: File) -> weave::Result<Box<dyn EncryptWrite>> {
+        let recipients = self.recipients.iter().map(|r| r as &dyn age::Recipient);
+        let writer = Encryptor::with_recipients(recipients)
+            .map_err(io::Error::other)?
+            .wrap_output(dest)?;
+        Ok(Box::new(AgeWriter(writer)))
+    }
+
+    fn decrypt(&self, src: File) -> weave::Result<Box<dyn Read + Send>> {
+        let identities = self.identities.iter().map(|id| id as &dyn age::Identity);
+        let reader = Decryptor::new_buffered(BufReader::new(src))
+            .and_then(|d| d.decrypt(identities))
+            .map_err(io::Error::other)?;
+        Ok(Box::new(reader))
+    }
+}
+
+struct AgeWriter(age::stream::StreamWriter<File>);
+
+impl Write for AgeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl EncryptWrite for AgeWriter {
+    fn finish(self: Box<Self>) -> weave::Result<()> {
+        self.0.finish()?;
+        Ok(())
+    }
+}
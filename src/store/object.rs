@@ -0,0 +1,249 @@
+//! Stores kept remotely, such as in an S3-compatible object store, or on
+//! another host, over SSH.
+//!
+//! The store is an ordinary weave, kept as a single object in a bucket, so
+//! that the integrity database of a host doesn't live on the host itself.
+//! The weave is fetched into a local cache the first time the store is
+//! read, and each new version, or deleted one, writes the whole weave back
+//! as a new object.  Only one host should write to a given key at a time.
+//!
+//! Nothing else is kept in the bucket, so these stores can't keep files
+//! alongside the weave, such as artifacts, signatures and ignore rules.
+//! Trying to gives an error, rather than writing them to the cache, to be
+//! lost with it.
+
+use crate::{
+    cancel::CancellationToken,
+    scratch::ScratchDir,
+    store::{
+        split_name, weave::WeaveStore, Store, StoreLock, StoreTags, StoreVersion, StoreWriter,
+        TempFile, Version,
+    },
+    Clock, Result, SureNode,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use weave::{Cipher, NamingConvention, Problem, SimpleNaming, WeaveStats};
+
+/// Somewhere to keep whole objects by key, such as an S3 bucket.
+pub trait Bucket {
+    /// Retrieve an object, or `None` if there is no object with this key.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Write an object, replacing any object with this key.
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// Copy an object to `dest`, returning false if there is no object with
+    /// this key.  By default, the object is retrieved whole with `get`; a
+    /// bucket that can stream it gives its own.
+    fn get_to(&self, key: &str, dest: &mut dyn Write) -> Result<bool> {
+        match self.get(key)? {
+            Some(data) => {
+                dest.write_all(&data)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Write an object from `src`, replacing any object with this key.  By
+    /// default, `src` is read whole, and given to `put`.
+    fn put_from(&self, key: &str, src: &mut dyn Read) -> Result<()> {
+        let mut data = vec![];
+        src.read_to_end(&mut data)?;
+        self.put(key, &data)
+    }
+}
+
+/// A weave store kept as an object in a bucket.
+pub struct ObjectStore {
+    bucket: Box<dyn Bucket>,
+    key: String,
+    local: WeaveStore,
+    main_file: PathBuf,
+    fetched: Cell<bool>,
+    // Held for the life of the store, removing the cache on drop.
+    _cache: ScratchDir,
+}
+
+impl ObjectStore {
+    /// A store kept under this prefix of the bucket, as the object
+    /// `prefix/2sure.dat.gz`.
+    pub fn new(bucket: Box<dyn Bucket>, prefix: &str) -> Result<ObjectStore> {
+        let prefix = prefix.trim_matches('/');
+        if prefix.is_empty() {
+            ObjectStore::with_key(bucket, "2sure.dat.gz")
+        } else {
+            ObjectStore::with_key(bucket, &format!("{}/2sure.dat.gz", prefix))
+        }
+    }
+
+    /// A store kept as the object with this key.  The last part of the key
+    /// is named as for `parse_store`, such as `2sure.weave.gz`, which gives
+    /// its compression.
+    pub fn with_key(bucket: Box<dyn Bucket>, key: &str) -> Result<ObjectStore> {
+        let cache = ScratchDir::new("rsure-object")?;
+        let name = key.rsplit('/').next().unwrap_or(key);
+        let (base, ext, compression) = split_name(name);
+        let main_file = SimpleNaming::new(cache.path(), base, ext, compression).main_file();
+
+        Ok(ObjectStore {
+            bucket,
+            key: key.to_string(),
+            local: WeaveStore::with_ext(cache.path(), base, ext, compression),
+            main_file,
+            fetched: Cell::new(false),
+            _cache: cache,
+        })
+    }
+
+    /// The key of the object holding the weave.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Bring the weave into the local cache, once.  A missing object is a
+    /// store with no versions yet.
+    fn fetch(&self) -> Result<()> {
+        if !self.fetched.get() {
+            let mut file = File::create(&self.main_file)?;
+            let found = self.bucket.get_to(&self.key, &mut file)?;
+            drop(file);
+            if !found {
+                fs::remove_file(&self.main_file)?;
+            }
+            self.fetched.set(true);
+        }
+        Ok(())
+    }
+
+    /// Write the cached weave back to the bucket.
+    fn upload(&self) -> Result<()> {
+        let mut file = File::open(&self.main_file)?;
+        self.bucket.put_from(&self.key, &mut file)
+    }
+}
+
+impl Store for ObjectStore {
+    fn get_versions(&self) -> Result<Vec<StoreVersion>> {
+        self.fetch()?;
+        self.local.get_versions()
+    }
+
+    fn load_iter(&self, version: Version) -> Result<Box<dyn Iterator<Item = Result<SureNode>>>> {
+        self.fetch()?;
+        self.local.load_iter(version)
+    }
+
+    fn make_temp(&self) -> Result<Box<dyn TempFile<'_> + '_>> {
+        self.local.make_temp()
+    }
+
+    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        self.make_new_locked(tags, StoreLock::default())
+    }
+
+    fn lock(&self) -> Result<StoreLock> {
+        self.local.lock()
+    }
+
+    fn make_new_locked(
+        &self,
+        tags: &StoreTags,
+        lock: StoreLock,
+    ) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        self.fetch()?;
+        Ok(Box::new(ObjectWriter {
+            store: self,
+            inner: self.local.make_new_locked(tags, lock)?,
+        }))
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.local.set_clock(clock);
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.local.now()
+    }
+
+    fn set_blocked(&mut self) {
+        self.local.set_blocked();
+    }
+
+    fn set_decode_threads(&mut self, threads: usize) {
+        self.local.set_decode_threads(threads);
+    }
+
+    fn set_delta_window(&mut self, lines: usize) {
+        self.local.set_delta_window(lines);
+    }
+
+    fn set_cipher(&mut self, cipher: Arc<dyn Cipher>) -> Result<()> {
+        self.local.set_cipher(cipher)
+    }
+
+    fn set_lock_wait(&mut self, wait: Duration) -> Result<()> {
+        self.local.set_lock_wait(wait)
+    }
+
+    fn set_backups(&mut self, count: usize) {
+        self.local.set_backups(count);
+    }
+
+    fn set_temp_dir(&mut self, dir: &Path) -> Result<()> {
+        self.local.set_temp_dir(dir)
+    }
+
+    fn delete_version(&self, version: Version) -> Result<()> {
+        self.fetch()?;
+        self.local.delete_version(version)?;
+        self.upload()
+    }
+
+    /// The sizes of the cached copy of the weave, which are those of the object.
+    fn weave_stats(&self) -> Result<WeaveStats> {
+        self.fetch()?;
+        self.local.weave_stats()
+    }
+
+    fn fsck(&self) -> Result<Vec<Problem>> {
+        self.fetch()?;
+        self.local.fsck()
+    }
+}
+
+/// Writes the new version to the cached weave, and uploads it once
+/// committed.
+struct ObjectWriter<'a> {
+    store: &'a ObjectStore,
+    inner: Box<dyn StoreWriter<'a> + 'a>,
+}
+
+impl<'a> StoreWriter<'a> for ObjectWriter<'a> {
+    fn commit(self: Box<Self>) -> Result<()> {
+        self.inner.commit()?;
+        self.store.upload()
+    }
+
+    fn set_cancel(&mut self, cancel: CancellationToken) {
+        self.inner.set_cancel(cancel);
+    }
+}
+
+impl<'a> Write for ObjectWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,232 @@
+//! Buckets in an S3-compatible object store.
+
+use crate::{store::object::Bucket, Error, Result};
+use chrono::Utc;
+use data_encoding::HEXLOWER;
+use openssl::{hash::MessageDigest, pkey::PKey, sha::sha256, sign::Signer};
+use std::{collections::BTreeMap, env, io::Read, sync::Arc};
+
+/// A bucket of an S3-compatible service, such as AWS, or MinIO.  Objects
+/// are addressed path-style, as `endpoint/bucket/key`, which all of them
+/// support.
+pub struct S3Bucket {
+    agent: ureq::Agent,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Bucket {
+    /// A bucket at the given endpoint, such as `https://s3.us-east-1.amazonaws.com`.
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Result<S3Bucket> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split("://")
+            .nth(1)
+            .filter(|h| !h.is_empty())
+            .ok_or_else(|| Error::ObjectStore(format!("Invalid endpoint {:?}", endpoint)))?;
+        let tls = native_tls::TlsConnector::new().map_err(|e| Error::ObjectStore(e.to_string()))?;
+        Ok(S3Bucket {
+            agent: ureq::AgentBuilder::new()
+                .tls_connector(Arc::new(tls))
+                .build(),
+            endpoint: endpoint.to_string(),
+            host: host.to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            session_token: None,
+        })
+    }
+
+    /// Sign requests with temporary credentials, which also need this
+    /// token.
+    pub fn with_session_token(mut self, token: &str) -> S3Bucket {
+        self.session_token = Some(token.to_string());
+        self
+    }
+
+    /// A bucket using the credentials in the usual AWS environment
+    /// variables, `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and
+    /// `AWS_SESSION_TOKEN`, if set.  The region is from `AWS_REGION`, or
+    /// `AWS_DEFAULT_REGION`, or else "us-east-1", and another service can be
+    /// given by `AWS_ENDPOINT_URL`.
+    pub fn from_env(bucket: &str) -> Result<S3Bucket> {
+        let var = |name: &str| {
+            env::var(name).map_err(|_| Error::ObjectStore(format!("{} is not set", name)))
+        };
+        let region = var("AWS_REGION")
+            .or_else(|_| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let endpoint = var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+        let result = S3Bucket::new(
+            &endpoint,
+            bucket,
+            &region,
+            &var("AWS_ACCESS_KEY_ID")?,
+            &var("AWS_SECRET_ACCESS_KEY")?,
+        )?;
+        Ok(match var("AWS_SESSION_TOKEN") {
+            Ok(token) => result.with_session_token(&token),
+            Err(_) => result,
+        })
+    }
+
+    /// Make a signed request for an object.
+    fn request(&self, method: &str, key: &str, body: &[u8]) -> Result<Option<ureq::Response>> {
+        let path = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(key));
+        let mut headers = BTreeMap::new();
+        headers.insert("host".to_string(), self.host.clone());
+        headers.insert(
+            "x-amz-content-sha256".to_string(),
+            HEXLOWER.encode(&sha256(body)),
+        );
+        headers.insert(
+            "x-amz-date".to_string(),
+            Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+        );
+        if let Some(token) = &self.session_token {
+            headers.insert("x-amz-security-token".to_string(), token.clone());
+        }
+        let auth = sign_v4(
+            method,
+            &path,
+            &headers,
+            &self.region,
+            &self.access_key,
+            &self.secret_key,
+        )?;
+
+        let mut req = self
+            .agent
+            .request(method, &format!("{}{}", self.endpoint, path))
+            .set("authorization", &auth);
+        for (name, value) in &headers {
+            if name != "host" {
+                req = req.set(name, value);
+            }
+        }
+        match req.send_bytes(body) {
+            Ok(resp) => Ok(Some(resp)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(ureq::Error::Status(code, resp)) => Err(Error::ObjectStore(format!(
+                "{} {}: {} {}",
+                method,
+                key,
+                code,
+                resp.into_string().unwrap_or_default()
+            ))),
+            Err(e) => Err(Error::ObjectStore(e.to_string())),
+        }
+    }
+}
+
+impl Bucket for S3Bucket {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", key, b"")? {
+            Some(resp) => {
+                let mut data = vec![];
+                resp.into_reader().read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        match self.request("PUT", key, data)? {
+            Some(_) => Ok(()),
+            None => Err(Error::ObjectStore(format!(
+                "No bucket {:?} at {}",
+                self.bucket, self.endpoint
+            ))),
+        }
+    }
+}
+
+/// Sign a request with AWS Signature Version 4, giving the value of its
+/// "authorization" header.  The path must already be URI encoded, and the
+/// headers, with lower case names, must include "host", "x-amz-date", and
+/// "x-amz-content-sha256", the hash of the body.  All of the headers given
+/// are signed.
+pub fn sign_v4(
+    method: &str,
+    path: &str,
+    headers: &BTreeMap<String, String>,
+    region: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> Result<String> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|v| v.as_str())
+            .ok_or_else(|| Error::ObjectStore(format!("Request has no {:?} header", name)))
+    };
+    let stamp = header("x-amz-date")?;
+    let date = &stamp[..8.min(stamp.len())];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+
+    let mut canonical = format!("{}\n{}\n\n", method, path);
+    for (name, value) in headers {
+        canonical.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    let signed: Vec<&str> = headers.keys().map(|k| k.as_str()).collect();
+    let signed = signed.join(";");
+    canonical.push_str(&format!(
+        "\n{}\n{}",
+        signed,
+        header("x-amz-content-sha256")?
+    ));
+
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        stamp,
+        scope,
+        HEXLOWER.encode(&sha256(canonical.as_bytes()))
+    );
+    let mut key = format!("AWS4{}", secret_key).into_bytes();
+    for part in &[date, region, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes())?;
+    }
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key,
+        scope,
+        signed,
+        HEXLOWER.encode(&hmac(&key, to_sign.as_bytes())?)
+    ))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Encode an object key for a request path, as S3 expects, leaving the
+/// slashes between its parts.
+fn uri_encode(text: &str) -> String {
+    let mut result = String::new();
+    for &ch in text.as_bytes() {
+        if ch.is_ascii_alphanumeric() || b"-_.~/".contains(&ch) {
+            result.push(ch as char);
+        } else {
+            result.push_str(&format!("%{:02X}", ch));
+        }
+    }
+    result
+}
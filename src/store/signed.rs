@@ -0,0 +1,379 @@
+//! Stores whose versions are signed.
+//!
+//! Someone able to modify the store could otherwise rewrite the hashes in it, so that a change to
+//! the tree goes unnoticed.  A `SignedStore` signs each new version with an ed25519 key as it is
+//! committed, keeping the signature as an artifact of the version, and checks the signature of
+//! each version as it is read, failing the read if the version is unsigned, or has been changed.
+//!
+//! The signature covers the name, time and tags of the version, and a SHA-256 of its surefile.
+//! Only the public key is needed to check signatures, so a machine that only reads the store need
+//! not hold the private key.
+
+use crate::{
+    cancel::CancellationToken,
+    node::NodeWriter,
+    store::{
+        Store, StoreLock, StoreTags, StoreVersion, StoreWriter, TempFile, Version, ARTIFACT_EXT,
+    },
+    Clock, Error, Result, SureNode,
+};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use openssl::{
+    hash::{Hasher, MessageDigest},
+    pkey::{Id, PKey, Private, Public},
+    sign::{Signer, Verifier},
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use weave::{Cipher, Problem, WeaveStats};
+
+/// The kind of the artifact holding the signature of a version.
+pub const SIGNATURE_ARTIFACT: &str = "signature.ed25519";
+
+/// The keys to sign and check versions with.  Without the private key, versions can be checked,
+/// but not written.
+pub struct SigningKeys {
+    private: Option<PKey<Private>>,
+    public: PKey<Public>,
+}
+
+impl SigningKeys {
+    /// Keys from an ed25519 private key in PEM form, such as one written by `openssl genpkey
+    /// -algorithm ed25519`.
+    pub fn from_private_pem(pem: &[u8]) -> Result<SigningKeys> {
+        let private = PKey::private_key_from_pem(pem)
+            .map_err(|e| Error::Signature(format!("Invalid private key: {}", e)))?;
+        check_ed25519(private.id())?;
+        let public = PKey::public_key_from_raw_bytes(&private.raw_public_key()?, Id::ED25519)?;
+        Ok(SigningKeys {
+            private: Some(private),
+            public,
+        })
+    }
+
+    /// Keys that only check versions, from an ed25519 public key in PEM form, such as one written
+    /// by `openssl pkey -pubout`.
+    pub fn from_public_pem(pem: &[u8]) -> Result<SigningKeys> {
+        let public = PKey::public_key_from_pem(pem)
+            .map_err(|e| Error::Signature(format!("Invalid public key: {}", e)))?;
+        check_ed25519(public.id())?;
+        Ok(SigningKeys {
+            private: None,
+            public,
+        })
+    }
+
+    /// Read keys from a PEM file holding either a private or a public key.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SigningKeys> {
+        let path = path.as_ref();
+        let pem = fs::read(path)
+            .map_err(|e| Error::Signature(format!("Unable to read {:?}: {}", path, e)))?;
+        if pem
+            .windows(b"PUBLIC KEY-----".len())
+            .any(|w| w == b"PUBLIC KEY-----")
+        {
+            SigningKeys::from_public_pem(&pem)
+        } else {
+            SigningKeys::from_private_pem(&pem)
+        }
+    }
+
+    /// Whether these keys are able to sign new versions.
+    pub fn can_sign(&self) -> bool {
+        self.private.is_some()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let private = self.private.as_ref().ok_or_else(|| {
+            Error::Signature("Only a public key was given, so can't sign".to_string())
+        })?;
+        Ok(Signer::new_without_digest(private)?.sign_oneshot_to_vec(message)?)
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<bool> {
+        Ok(Verifier::new_without_digest(&self.public)?.verify_oneshot(signature, message)?)
+    }
+}
+
+fn check_ed25519(id: Id) -> Result<()> {
+    if id == Id::ED25519 {
+        Ok(())
+    } else {
+        Err(Error::Signature(
+            "The key is not an ed25519 key".to_string(),
+        ))
+    }
+}
+
+/// The message signed for a version with the given digest of its surefile.
+fn message(version: &StoreVersion, digest: &[u8]) -> Vec<u8> {
+    let mut text = format!(
+        "rsure-snapshot-v1\nname {}\ntime {}\n",
+        version.name,
+        version.time.to_rfc3339()
+    );
+    for (key, value) in &version.tags {
+        text.push_str(&format!("tag {}={}\n", key, value));
+    }
+    text.push_str(&format!("content {}\n", HEXLOWER.encode(digest)));
+    text.into_bytes()
+}
+
+/// A store that signs new versions, and checks the signatures of the versions it reads.
+pub struct SignedStore {
+    inner: Box<dyn Store>,
+    keys: Arc<SigningKeys>,
+}
+
+impl SignedStore {
+    pub fn new(inner: Box<dyn Store>, keys: Arc<SigningKeys>) -> SignedStore {
+        SignedStore { inner, keys }
+    }
+
+    /// Sign a version as it is now, without checking it.  This is for versions written before the
+    /// store was signed, and trusts that they haven't been changed since.
+    pub fn sign_version(&self, version: &Version) -> Result<()> {
+        let mut writer = NodeWriter::new(Hasher::new(MessageDigest::sha256())?)?;
+        for node in self.inner.load_iter(version.clone())? {
+            writer.write_node(&node?)?;
+        }
+        let digest = writer.into_inner()?.finish()?;
+        self.put_signature(version, &digest)
+    }
+
+    /// Check the signature of a version, by reading all of it.
+    pub fn verify_version(&self, version: &Version) -> Result<()> {
+        for node in self.load_iter(version.clone())? {
+            node?;
+        }
+        Ok(())
+    }
+
+    fn put_signature(&self, version: &Version, digest: &[u8]) -> Result<()> {
+        let info = self
+            .inner
+            .get_version(version)?
+            .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+        let signature = self.keys.sign(&message(&info, digest))?;
+        let text = format!("{}\n", HEXLOWER.encode(&signature));
+        self.inner
+            .put_artifact(&info.version, SIGNATURE_ARTIFACT, text.as_bytes())
+    }
+}
+
+impl Store for SignedStore {
+    fn get_versions(&self) -> Result<Vec<StoreVersion>> {
+        self.inner.get_versions()
+    }
+
+    fn load_iter(&self, version: Version) -> Result<Box<dyn Iterator<Item = Result<SureNode>>>> {
+        let info = self
+            .inner
+            .get_version(&version)?
+            .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+        let signature = self
+            .inner
+            .get_artifact(&info.version, SIGNATURE_ARTIFACT)?
+            .ok_or_else(|| Error::Unsigned(info.name.clone()))?;
+        let signature = HEXLOWER
+            .decode(String::from_utf8_lossy(&signature).trim().as_bytes())
+            .map_err(|_| Error::BadSignature(info.name.clone()))?;
+        let nodes = self.inner.load_iter(info.version.clone())?;
+        Ok(Box::new(VerifyIter {
+            nodes,
+            writer: Some(NodeWriter::new(Hasher::new(MessageDigest::sha256())?)?),
+            keys: self.keys.clone(),
+            info,
+            signature,
+        }))
+    }
+
+    fn make_temp(&self) -> Result<Box<dyn TempFile<'_> + '_>> {
+        self.inner.make_temp()
+    }
+
+    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        self.make_new_locked(tags, StoreLock::default())
+    }
+
+    fn lock(&self) -> Result<StoreLock> {
+        self.inner.lock()
+    }
+
+    fn make_new_locked(
+        &self,
+        tags: &StoreTags,
+        lock: StoreLock,
+    ) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        if !self.keys.can_sign() {
+            return Err(Error::Signature(
+                "A private key is needed to write to a signed store".to_string(),
+            ));
+        }
+        // Fail before the version is written if its signature can't be kept.
+        self.inner.sidecar(ARTIFACT_EXT)?;
+        Ok(Box::new(SignedWriter {
+            inner: self.inner.make_new_locked(tags, lock)?,
+            hasher: Hasher::new(MessageDigest::sha256())?,
+            store: self,
+        }))
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.inner.set_clock(clock)
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.inner.now()
+    }
+
+    fn set_blocked(&mut self) {
+        self.inner.set_blocked()
+    }
+
+    fn set_decode_threads(&mut self, threads: usize) {
+        self.inner.set_decode_threads(threads)
+    }
+
+    fn set_delta_window(&mut self, lines: usize) {
+        self.inner.set_delta_window(lines)
+    }
+
+    fn set_cipher(&mut self, cipher: Arc<dyn Cipher>) -> Result<()> {
+        self.inner.set_cipher(cipher)
+    }
+
+    fn set_lock_wait(&mut self, wait: Duration) -> Result<()> {
+        self.inner.set_lock_wait(wait)
+    }
+
+    fn set_backups(&mut self, count: usize) {
+        self.inner.set_backups(count)
+    }
+
+    fn set_temp_dir(&mut self, dir: &Path) -> Result<()> {
+        self.inner.set_temp_dir(dir)
+    }
+
+    fn delete_version(&self, version: Version) -> Result<()> {
+        self.inner.delete_version(version)
+    }
+
+    fn sidecar(&self, ext: &str) -> Result<PathBuf> {
+        self.inner.sidecar(ext)
+    }
+
+    fn weave_stats(&self) -> Result<WeaveStats> {
+        self.inner.weave_stats()
+    }
+
+    fn fsck(&self) -> Result<Vec<Problem>> {
+        self.inner.fsck()
+    }
+
+    fn put_artifact(&self, version: &Version, kind: &str, data: &[u8]) -> Result<()> {
+        self.inner.put_artifact(version, kind, data)
+    }
+
+    fn get_artifact(&self, version: &Version, kind: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.get_artifact(version, kind)
+    }
+
+    fn artifacts(&self, version: &Version) -> Result<Vec<String>> {
+        self.inner.artifacts(version)
+    }
+
+    fn prune_artifacts(&self) -> Result<usize> {
+        self.inner.prune_artifacts()
+    }
+
+    fn get_version(&self, version: &Version) -> Result<Option<StoreVersion>> {
+        self.inner.get_version(version)
+    }
+}
+
+/// Hashes the surefile as it is written, and signs the new version once it is committed.
+struct SignedWriter<'a> {
+    inner: Box<dyn StoreWriter<'a> + 'a>,
+    hasher: Hasher,
+    store: &'a SignedStore,
+}
+
+impl<'a> Write for SignedWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.hasher.write_all(&buf[..count])?;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a> StoreWriter<'a> for SignedWriter<'a> {
+    fn commit(mut self: Box<Self>) -> Result<()> {
+        let digest = self.hasher.finish()?;
+        self.inner.commit()?;
+        self.store.put_signature(&Version::Latest, &digest)
+    }
+
+    fn set_cancel(&mut self, cancel: CancellationToken) {
+        self.inner.set_cancel(cancel);
+    }
+}
+
+/// Hashes the nodes as they are read, written back out as a surefile, and checks the signature
+/// once the last has been read.
+struct VerifyIter {
+    nodes: Box<dyn Iterator<Item = Result<SureNode>>>,
+    writer: Option<NodeWriter<Hasher>>,
+    keys: Arc<SigningKeys>,
+    info: StoreVersion,
+    signature: Vec<u8>,
+}
+
+impl VerifyIter {
+    fn check(&self, writer: NodeWriter<Hasher>) -> Result<()> {
+        let digest = writer.into_inner()?.finish()?;
+        if self
+            .keys
+            .verify(&message(&self.info, &digest), &self.signature)?
+        {
+            Ok(())
+        } else {
+            Err(Error::BadSignature(self.info.name.clone()))
+        }
+    }
+}
+
+impl Iterator for VerifyIter {
+    type Item = Result<SureNode>;
+
+    fn next(&mut self) -> Option<Result<SureNode>> {
+        let writer = self.writer.as_mut()?;
+        match self.nodes.next() {
+            Some(Ok(node)) => {
+                if let Err(e) = writer.write_node(&node) {
+                    self.writer = None;
+                    return Some(Err(e));
+                }
+                Some(Ok(node))
+            }
+            Some(Err(e)) => {
+                self.writer = None;
+                Some(Err(e))
+            }
+            None => {
+                let writer = self.writer.take()?;
+                self.check(writer).err().map(Err)
+            }
+        }
+    }
+}
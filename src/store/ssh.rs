@@ -0,0 +1,209 @@
+//! Stores on another host, reached over SSH.
+//!
+//! The weave is read and written by running `cat` on the other host, with
+//! the system's `ssh` command, so that its configuration, keys and agent
+//! are used as for any other login.  Nothing more is needed on the other
+//! host than a shell.
+
+use crate::{store::object::Bucket, Error, Result};
+use std::{
+    io::{self, Read, Write},
+    process::{Child, Command, Stdio},
+};
+
+/// The exit status of the remote command when the store doesn't exist.
+const MISSING: i32 = 66;
+
+/// Files on another host, reached over SSH, each named by its path there.
+pub struct SshBucket {
+    command: String,
+    host: String,
+    port: Option<u16>,
+}
+
+impl SshBucket {
+    /// Files on the given host, which may be given as `user@host`.
+    pub fn new(host: &str) -> Result<SshBucket> {
+        if host.is_empty() || host.starts_with('-') {
+            return Err(Error::Ssh(format!("Invalid host {:?}", host)));
+        }
+        Ok(SshBucket {
+            command: "ssh".to_string(),
+            host: host.to_string(),
+            port: None,
+        })
+    }
+
+    /// Run this command, instead of `ssh`.  It is split into words, so it
+    /// can give options, such as "ssh -i key".
+    pub fn with_command(mut self, command: &str) -> SshBucket {
+        self.command = command.to_string();
+        self
+    }
+
+    /// Connect to this port, instead of the one ssh is configured with.
+    pub fn with_port(mut self, port: u16) -> SshBucket {
+        self.port = Some(port);
+        self
+    }
+
+    /// The host given to ssh, which may be `user@host`.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port, if one was given.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Parse the part of an `ssh://` url after the scheme,
+    /// `[user@]host[:port]/path`, giving the bucket, and the path of the
+    /// file on the host.  An IPv6 address is given in brackets, as
+    /// `[::1]:2222/path`.  A path starting `/~/` is in the home directory,
+    /// and one ending in `/` is the file `2sure.dat.gz` there.  The bucket
+    /// runs `ssh`, unless given another command with `with_command`.
+    pub fn parse(text: &str) -> Result<(SshBucket, String)> {
+        let (host, path) = match text.find('/') {
+            Some(pos) if pos + 1 < text.len() => (&text[..pos], &text[pos..]),
+            _ => return Err(Error::Ssh(format!("No path given in ssh://{}", text))),
+        };
+        let (host, port) = split_port(host)?;
+        let bucket = SshBucket::new(&host)?;
+        let bucket = match port {
+            Some(port) => bucket.with_port(port),
+            None => bucket,
+        };
+        let path = match path.strip_prefix("/~/") {
+            Some(path) => path,
+            None => path,
+        };
+        let path = if path.is_empty() || path.ends_with('/') {
+            format!("{}2sure.dat.gz", path)
+        } else {
+            path.to_string()
+        };
+        Ok((bucket, path))
+    }
+
+    /// Start a shell command on the host.
+    fn spawn(&self, script: &str, stdin: Stdio, stdout: Stdio) -> Result<Child> {
+        let mut words = self.command.split_whitespace();
+        let mut cmd = Command::new(words.next().unwrap_or("ssh"));
+        cmd.args(words);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        cmd.arg(&self.host)
+            .arg(script)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Stdio::inherit());
+        cmd.spawn()
+            .map_err(|e| Error::Ssh(format!("Unable to run {:?}: {}", self.command, e)))
+    }
+
+    fn failed(&self, status: std::process::ExitStatus) -> Error {
+        Error::Ssh(format!(
+            "{} on {} failed: {}",
+            self.command, self.host, status
+        ))
+    }
+}
+
+impl Bucket for SshBucket {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut data = vec![];
+        Ok(if self.get_to(key, &mut data)? {
+            Some(data)
+        } else {
+            None
+        })
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.put_from(key, &mut &data[..])
+    }
+
+    fn get_to(&self, key: &str, dest: &mut dyn Write) -> Result<bool> {
+        let path = quote(key);
+        let script = format!(
+            "if test -e {0}; then exec cat {0}; else exit {1}; fi",
+            path, MISSING
+        );
+        let mut child = self.spawn(&script, Stdio::null(), Stdio::piped())?;
+        let copied = io::copy(child.stdout.as_mut().expect("piped stdout"), dest);
+        let status = child.wait()?;
+        copied?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(MISSING) => Ok(false),
+            _ => Err(self.failed(status)),
+        }
+    }
+
+    fn put_from(&self, key: &str, src: &mut dyn Read) -> Result<()> {
+        // Written to the side, and renamed, so the store is never left
+        // partly written.
+        let script = format!(
+            "cat > {0} && mv {0} {1}",
+            quote(&format!("{}.tmp", key)),
+            quote(key)
+        );
+        let mut child = self.spawn(&script, Stdio::piped(), Stdio::null())?;
+        let copied = {
+            let mut stdin = child.stdin.take().expect("piped stdin");
+            io::copy(src, &mut stdin)
+        };
+        let status = child.wait()?;
+        copied?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(self.failed(status))
+        }
+    }
+}
+
+/// Split the port from the host part of a url, which may be an IPv6
+/// address in brackets, with or without a user, such as `me@[::1]:2222`.
+/// The brackets are removed, as ssh wants the bare address.
+fn split_port(text: &str) -> Result<(String, Option<u16>)> {
+    let parse_port = |port: &str| {
+        port.parse()
+            .map_err(|_| Error::Ssh(format!("Invalid port {:?}", port)))
+    };
+    let (user, host) = match text.rsplit_once('@') {
+        Some((user, host)) => (format!("{}@", user), host),
+        None => (String::new(), text),
+    };
+    if let Some(rest) = host.strip_prefix('[') {
+        let (addr, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| Error::Ssh(format!("Unclosed '[' in {:?}", text)))?;
+        let port = match rest {
+            "" => None,
+            _ => match rest.strip_prefix(':') {
+                Some(port) => Some(parse_port(port)?),
+                None => return Err(Error::Ssh(format!("Invalid host {:?}", text))),
+            },
+        };
+        return Ok((format!("{}{}", user, addr), port));
+    }
+    match host.split(':').count() {
+        1 => Ok((text.to_string(), None)),
+        2 => {
+            let (host, port) = text.rsplit_once(':').unwrap();
+            Ok((host.to_string(), Some(parse_port(port)?)))
+        }
+        _ => Err(Error::Ssh(format!(
+            "An IPv6 address needs brackets, as [{}]",
+            host
+        ))),
+    }
+}
+
+/// Quote a word for the remote shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
@@ -1,38 +1,60 @@
 //! SCCS-style delta weave stores.
 
 use crate::{
-    node,
+    cancel::CancellationToken,
+    clock, node,
     store::{
-        Store, StoreTags, StoreVersion, StoreWriter, TempCleaner, TempFile, TempLoader, Version,
+        artifact, Store, StoreLock, StoreTags, StoreVersion, StoreWriter, TempCleaner, TempFile,
+        TempLoader, Version,
     },
-    Error, Result, SureNode,
+    Clock, Error, Result, SureNode,
 };
+use chrono::{DateTime, Utc};
 use std::{
-    env,
-    fs::{self, File},
-    io::{self, BufRead, BufReader, BufWriter, Write},
+    env, fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
+pub use weave::Compression;
 use weave::{
-    self, DeltaWriter, NamingConvention, NewWeave, PullParser, SimpleNaming,
+    self, Cipher, DeltaWriter, EncryptWrite, NamingConvention, NewWeave, PullParser, SimpleNaming,
 };
-pub use weave::Compression;
 
 pub struct WeaveStore {
     naming: SimpleNaming,
+    clock: Box<dyn Clock>,
 }
 
 impl WeaveStore {
     pub fn new<P: AsRef<Path>>(path: P, base: &str, compression: Compression) -> WeaveStore {
+        WeaveStore::with_ext(path, base, "dat", compression)
+    }
+
+    /// Construct a store whose main file uses an extension other than
+    /// "dat".  Stores written before 0.8 were named "2sure.weave.gz".
+    pub fn with_ext<P: AsRef<Path>>(
+        path: P,
+        base: &str,
+        ext: &str,
+        compression: Compression,
+    ) -> WeaveStore {
         WeaveStore {
-            naming: SimpleNaming::new(path, base, "dat", compression),
+            naming: SimpleNaming::new(path, base, ext, compression),
+            clock: clock::default_clock(),
         }
     }
 }
 
 impl Store for WeaveStore {
     fn get_versions(&self) -> Result<Vec<StoreVersion>> {
-        let header = PullParser::new(&self.naming, 1)?.into_header();
+        let header = match PullParser::new(&self.naming, 1) {
+            Ok(parser) => parser.into_header(),
+            // A store that hasn't been written yet has no versions.
+            Err(weave::Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
         let mut versions: Vec<_> = header
             .deltas
             .iter()
@@ -40,6 +62,7 @@ impl Store for WeaveStore {
                 name: v.name.clone(),
                 time: v.time,
                 version: Version::Tagged(v.number.to_string()),
+                tags: v.tags.clone(),
             })
             .collect();
         versions.reverse();
@@ -47,57 +70,168 @@ impl Store for WeaveStore {
     }
 
     fn load_iter(&self, version: Version) -> Result<Box<dyn Iterator<Item = Result<SureNode>>>> {
-        let last = weave::get_last_delta(&self.naming)?;
         let last = match version {
-            Version::Latest => last,
-            Version::Prior => last - 1,
-            Version::Tagged(vers) => vers.parse()?,
+            Version::Tagged(_) | Version::Named(_) => self
+                .delta_number(&version)?
+                .ok_or_else(|| Error::UnknownVersion(version.to_string()))?,
+            // With deleted versions, the numbers can have gaps.  Delta 0 is an empty tree.
+            version => self.delta_number(&version)?.unwrap_or(0),
         };
 
         Ok(Box::new(WeaveIter::new(&self.naming, last)?))
     }
 
-    fn make_temp(&self) -> Result<Box<dyn TempFile + '_>> {
+    fn make_temp(&self) -> Result<Box<dyn TempFile<'_> + '_>> {
         // TODO: Fixup naming to allow uncompressed writes.
         let (path, file) = self.naming.temp_file()?;
         let cpath = path.clone();
         Ok(Box::new(WeaveTemp {
             parent: self,
             path,
-            file: BufWriter::new(file),
+            file: BufWriter::new(weave::encrypt_to(&self.naming, file)?),
+            sum: 0,
             cleaner: FileClean(cpath),
         }))
     }
 
-    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter + '_>> {
+    fn make_new(&self, tags: &StoreTags) -> Result<Box<dyn StoreWriter<'_> + '_>> {
         let itags = tags.iter().map(|(k, v)| (k.as_ref(), v.as_ref()));
-        match weave::get_last_delta(&self.naming) {
-            Ok(base) => {
-                let wv = DeltaWriter::new(&self.naming, itags, base)?;
-                Ok(Box::new(NewWeaveDelta { weave: wv }))
-            }
-            Err(_) => {
-                // Create a new weave file.
-                let wv = NewWeave::new(&self.naming, itags)?;
-                Ok(Box::new(NewWeaveWriter { weave: wv }))
-            }
+        if self.naming.main_file().exists() {
+            // Based on the last version once the store is locked, in case
+            // another writer was adding one.
+            let wv = DeltaWriter::on_latest(&self.naming, itags, &*self.clock)?;
+            Ok(Box::new(NewWeaveDelta { weave: wv }))
+        } else {
+            // Create a new weave file.
+            let wv = NewWeave::with_clock(&self.naming, itags, &*self.clock)?;
+            Ok(Box::new(NewWeaveWriter { weave: wv }))
         }
     }
+
+    fn lock(&self) -> Result<StoreLock> {
+        Ok(StoreLock(Some(weave::lock(&self.naming)?)))
+    }
+
+    fn make_new_locked(
+        &self,
+        tags: &StoreTags,
+        lock: StoreLock,
+    ) -> Result<Box<dyn StoreWriter<'_> + '_>> {
+        let lock = match lock.0 {
+            Some(lock) => lock,
+            None => return self.make_new(tags),
+        };
+        let itags = tags.iter().map(|(k, v)| (k.as_ref(), v.as_ref()));
+        if self.naming.main_file().exists() {
+            let wv = DeltaWriter::on_latest_locked(&self.naming, itags, &*self.clock, lock)?;
+            Ok(Box::new(NewWeaveDelta { weave: wv }))
+        } else {
+            let wv = NewWeave::with_lock(&self.naming, itags, &*self.clock, lock)?;
+            Ok(Box::new(NewWeaveWriter { weave: wv }))
+        }
+    }
+
+    fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    fn set_blocked(&mut self) {
+        self.naming = self
+            .naming
+            .clone()
+            .with_block_size(weave::DEFAULT_BLOCK_SIZE);
+    }
+
+    fn set_decode_threads(&mut self, threads: usize) {
+        self.naming = self.naming.clone().with_decode_threads(threads);
+    }
+
+    fn set_delta_window(&mut self, lines: usize) {
+        self.naming = self.naming.clone().with_delta_window(lines);
+    }
+
+    fn set_cipher(&mut self, cipher: Arc<dyn Cipher>) -> Result<()> {
+        self.naming = self.naming.clone().with_cipher(cipher);
+        Ok(())
+    }
+
+    fn set_lock_wait(&mut self, wait: Duration) -> Result<()> {
+        self.naming = self.naming.clone().with_lock_wait(wait);
+        Ok(())
+    }
+
+    fn set_backups(&mut self, count: usize) {
+        self.naming = self.naming.clone().with_backups(count);
+    }
+
+    fn set_temp_dir(&mut self, dir: &Path) -> Result<()> {
+        self.naming = self.naming.clone().with_temp_dir(dir);
+        Ok(())
+    }
+
+    fn sidecar(&self, ext: &str) -> Result<PathBuf> {
+        Ok(self.naming.make_name(ext, Compression::Plain))
+    }
+
+    fn weave_stats(&self) -> Result<weave::WeaveStats> {
+        Ok(weave::stats(&self.naming)?)
+    }
+
+    fn fsck(&self) -> Result<Vec<weave::Problem>> {
+        Ok(weave::fsck(&self.naming)?)
+    }
+
+    fn delete_version(&self, version: Version) -> Result<()> {
+        let number = self
+            .delta_number(&version)?
+            .ok_or_else(|| Error::UnknownVersion(version.to_string()))?;
+        // The artifacts go first, so that a failure removing them leaves the version to retry
+        // the deletion with.
+        artifact::remove(self, &Version::Tagged(number.to_string()))?;
+        weave::delete_delta(&self.naming, number)?;
+        Ok(())
+    }
+}
+
+impl WeaveStore {
+    /// The delta number of a version, if it is present in the weave.
+    fn delta_number(&self, version: &Version) -> Result<Option<usize>> {
+        let header = weave::read_header(&self.naming)?;
+        let mut numbers: Vec<usize> = header.deltas.iter().map(|d| d.number).collect();
+        numbers.sort_unstable();
+        Ok(match version {
+            Version::Latest => numbers.last().copied(),
+            Version::Prior => numbers.iter().rev().nth(1).copied(),
+            Version::Tagged(_) => version.numeric().filter(|n| numbers.contains(n)),
+            Version::Named(name) => header.find_delta(name),
+        })
+    }
 }
 
 struct WeaveTemp<'a> {
     parent: &'a WeaveStore,
     path: PathBuf,
-    file: BufWriter<File>,
+    file: BufWriter<Box<dyn EncryptWrite>>,
+    // The CRC32C of what has been written, to catch the temp file being
+    // corrupted before it is read back.
+    sum: u32,
     cleaner: FileClean,
 }
 
 impl<'a> TempFile<'a> for WeaveTemp<'a> {
     fn into_loader(self: Box<Self>) -> Result<Box<dyn TempLoader + 'a>> {
-        drop(self.file);
+        self.file
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .finish()?;
         Ok(Box::new(WeaveTempLoader {
             _parent: self.parent,
             path: self.path,
+            sum: self.sum,
             cleaner: self.cleaner,
         }))
     }
@@ -109,7 +243,9 @@ impl<'a> TempFile<'a> for WeaveTemp<'a> {
 
 impl<'a> Write for WeaveTemp<'a> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.file.write(buf)
+        let count = self.file.write(buf)?;
+        self.sum = crc32c::crc32c_append(self.sum, &buf[..count]);
+        Ok(count)
     }
     fn flush(&mut self) -> io::Result<()> {
         self.file.flush()
@@ -119,13 +255,18 @@ impl<'a> Write for WeaveTemp<'a> {
 pub struct WeaveTempLoader<'a> {
     _parent: &'a WeaveStore,
     path: PathBuf,
+    sum: u32,
     cleaner: FileClean,
 }
 
 impl<'a> TempLoader for WeaveTempLoader<'a> {
-    fn new_loader(&self) -> Result<Box<dyn BufRead>> {
-        let read = BufReader::new(File::open(&self.path)?);
-        Ok(Box::new(read))
+    fn new_loader(&self) -> Result<Box<dyn BufRead + Send>> {
+        Ok(Box::new(CheckedReader {
+            file: BufReader::new(weave::decrypt_from(&self._parent.naming, &self.path)?),
+            path: self.path.clone(),
+            sum: 0,
+            expect: self.sum,
+        }))
     }
 
     fn path_ref(&self) -> &Path {
@@ -137,6 +278,44 @@ impl<'a> TempLoader for WeaveTempLoader<'a> {
     }
 }
 
+/// Reads a temp file, checking its checksum when the end is reached.
+struct CheckedReader {
+    file: BufReader<Box<dyn Read + Send>>,
+    path: PathBuf,
+    sum: u32,
+    expect: u32,
+}
+
+impl Read for CheckedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = {
+            let data = self.fill_buf()?;
+            let count = data.len().min(buf.len());
+            buf[..count].copy_from_slice(&data[..count]);
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for CheckedReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.file.fill_buf()?.is_empty() && self.sum != self.expect {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Error::TempChecksum(self.path.clone()),
+            ));
+        }
+        self.file.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.sum = crc32c::crc32c_append(self.sum, &self.file.buffer()[..amt]);
+        self.file.consume(amt);
+    }
+}
+
 pub struct NewWeaveWriter<'a> {
     weave: NewWeave<'a>,
 }
@@ -164,8 +343,15 @@ pub struct NewWeaveDelta<'a> {
 
 impl<'a> StoreWriter<'a> for NewWeaveDelta<'a> {
     fn commit(self: Box<Self>) -> Result<()> {
-        self.weave.close()?;
-        Ok(())
+        match self.weave.close() {
+            Err(weave::Error::Cancelled) => Err(Error::Cancelled),
+            result => Ok(result?),
+        }
+    }
+
+    fn set_cancel(&mut self, cancel: CancellationToken) {
+        self.weave
+            .set_cancel(Arc::new(move || cancel.check().is_err()));
     }
 }
 
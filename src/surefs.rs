@@ -1,17 +1,22 @@
 // Filesystem scanning.
 
-use crate::{escape::*, suretree::AttMap};
+use crate::{escape::*, node::RECORDED_ATT, platform::os_bytes, suretree::AttMap};
+use data_encoding::HEXLOWER;
 use log::error;
+use openssl::sha::sha256;
 
+#[cfg(unix)]
+use std::os::unix::prelude::*;
 use std::{
     fs::{self, Metadata},
-    os::unix::prelude::*,
     path::Path,
+    time::UNIX_EPOCH,
 };
 
 // Encode the attributes for the given node.  Note that this returns, even
 // when there is an error (resolving a symlink).  It logs an error, and
 // returns a placeholder.
+#[cfg(unix)]
 pub(crate) fn encode_atts(name: &Path, meta: &Metadata) -> AttMap {
     // let fname = name.file_name().unwrap().as_bytes().escaped();
     let mode = meta.mode() as libc::mode_t & libc::S_IFMT;
@@ -34,9 +39,22 @@ pub(crate) fn encode_atts(name: &Path, meta: &Metadata) -> AttMap {
         libc::S_IFREG => {
             base.insert("kind".to_string(), "file".to_string());
             base.insert("ino".to_string(), meta.ino().to_string());
+            base.insert("nlink".to_string(), meta.nlink().to_string());
             base.insert("size".to_string(), meta.size().to_string());
+            // In 512 byte units, so a sparse file has fewer than its size.
+            // The count itself isn't compared, as it moves about on some
+            // filesystems, but only files with fewer are marked "sparse".
+            base.insert("blocks".to_string(), meta.blocks().to_string());
+            if meta.blocks().saturating_mul(512) < meta.size() {
+                base.insert("sparse".to_string(), "1".to_string());
+            }
             time_info(&mut base, meta);
-            // Note that the 'sha1' attribute is computed later.
+            // Only files given capabilities have them, so most have no
+            // "cap" attribute.
+            if let Some(cap) = xattr(name, "security.capability") {
+                base.insert("cap".to_string(), HEXLOWER.encode(&cap));
+            }
+            // Note that the hash attribute is computed later.
         }
         libc::S_IFLNK => {
             base.insert("kind".to_string(), "lnk".to_string());
@@ -72,6 +90,113 @@ pub(crate) fn encode_atts(name: &Path, meta: &Metadata) -> AttMap {
     base
 }
 
+/// Record where the symlink at `name` finally resolves to, as "tpath", and
+/// if that is a regular file, its size, as "tsize", so that its content is
+/// hashed like a file's.  A broken link gets neither, but every link gets
+/// "tdigest", the sha256 of its text, so that however long or odd the text
+/// is, it compares as a short hash.
+pub(crate) fn link_target_atts(name: &Path, base: &mut AttMap) {
+    if let Some(Ok(text)) = base.get("targ").map(|targ| targ.unescape()) {
+        base.insert("tdigest".to_string(), HEXLOWER.encode(&sha256(&text)));
+    }
+    let target = match fs::canonicalize(name) {
+        Ok(target) => target,
+        Err(err) => {
+            error!("Unable to resolve link: {:?} ({})", name, err);
+            return;
+        }
+    };
+    base.insert("tpath".to_string(), os_bytes(target.as_os_str()).escaped());
+    if let Ok(meta) = fs::metadata(&target) {
+        if meta.is_file() {
+            base.insert("tsize".to_string(), meta.len().to_string());
+        }
+    }
+}
+
+/// The attributes only some nodes have that a scan looks for, with or
+/// without file flags.
+pub(crate) fn recorded(flags: bool) -> Vec<&'static str> {
+    let mut recorded = vec![];
+    if cfg!(target_os = "linux") {
+        recorded.push("cap");
+    }
+    if cfg!(unix) {
+        recorded.push("sparse");
+    }
+    if flags
+        && cfg!(any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "freebsd"
+        ))
+    {
+        recorded.push("flags");
+    }
+    recorded
+}
+
+/// Record, on a directory, which of the attributes only some nodes have
+/// are looked for on it and its files, as "recorded".
+pub(crate) fn recorded_atts(flags: bool, base: &mut AttMap) {
+    base.insert(RECORDED_ATT.to_string(), recorded(flags).join(","));
+}
+
+/// Record the SELinux security context of the node at `name`, as
+/// "secontext", if it has one.
+#[cfg(unix)]
+pub(crate) fn selinux_atts(name: &Path, base: &mut AttMap) {
+    if let Some(mut context) = xattr(name, "security.selinux") {
+        if context.last() == Some(&0) {
+            context.pop();
+        }
+        base.insert("secontext".to_string(), context.escaped());
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn selinux_atts(_name: &Path, _base: &mut AttMap) {}
+
+// On Windows, there is no owner or mode, but there are the attribute bits,
+// such as read-only and hidden.  Nor is there a change time, so only the
+// modification and creation times are recorded.
+#[cfg(windows)]
+pub(crate) fn encode_atts(name: &Path, meta: &Metadata) -> AttMap {
+    use std::os::windows::fs::MetadataExt;
+
+    let mut base = AttMap::new();
+    base.insert("attrs".to_string(), format!("{:x}", meta.file_attributes()));
+
+    let kind = meta.file_type();
+    if kind.is_symlink() {
+        base.insert("kind".to_string(), "lnk".to_string());
+        let link = match fs::read_link(name) {
+            Ok(l) => l,
+            Err(err) => {
+                error!("Unable to read link: {:?} ({})", name, err);
+                From::from("???")
+            }
+        };
+        base.insert("targ".to_string(), os_bytes(link.as_os_str()).escaped());
+    } else if kind.is_dir() {
+        base.insert("kind".to_string(), "dir".to_string());
+    } else {
+        base.insert("kind".to_string(), "file".to_string());
+        base.insert("size".to_string(), meta.len().to_string());
+        if let Some(mtime) = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        {
+            base.insert("mtime".to_string(), mtime.as_secs().to_string());
+        }
+        add_btime(&mut base, meta);
+    }
+
+    base
+}
+
+#[cfg(unix)]
 fn add_dev(base: &mut AttMap, meta: &Metadata) {
     let rdev = meta.rdev();
     // This is defined in a macro, and hasn't made it into libc.  Given how
@@ -81,8 +206,261 @@ fn add_dev(base: &mut AttMap, meta: &Metadata) {
     base.insert("devmin".to_string(), (rdev & 0xff).to_string());
 }
 
+/// The value of the extended attribute `key` of the file at `name`, not
+/// following a symlink, or None if it has none, or its filesystem has no
+/// extended attributes.
+#[cfg(target_os = "linux")]
+fn xattr(name: &Path, key: &str) -> Option<Vec<u8>> {
+    use std::{ffi::CString, io, ptr};
+
+    let path = CString::new(name.as_os_str().as_bytes()).ok()?;
+    let key = CString::new(key).ok()?;
+    loop {
+        // Ask the size first, as most files don't have the attribute.
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), key.as_ptr(), ptr::null_mut(), 0) };
+        if size >= 0 {
+            let mut buf = vec![0u8; size as usize];
+            let len = unsafe {
+                libc::lgetxattr(
+                    path.as_ptr(),
+                    key.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if len >= 0 {
+                buf.truncate(len as usize);
+                return Some(buf);
+            }
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            // It grew since its size was asked.
+            Some(libc::ERANGE) => continue,
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => return None,
+            _ => {
+                error!("Unable to read {:?} of {:?} ({})", key, name, err);
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn xattr(_name: &Path, _key: &str) -> Option<Vec<u8>> {
+    None
+}
+
+/// The file flags worth recording, such as immutable and append only, by
+/// the names lsattr gives them on Linux, and chflags on the BSDs.
+#[cfg(target_os = "linux")]
+const FLAGS: &[(u32, &str)] = &[
+    (0x0000_0008, "sync"),
+    (0x0000_0010, "immutable"),
+    (0x0000_0020, "append"),
+    (0x0000_0040, "nodump"),
+    (0x0000_0080, "noatime"),
+    (0x0001_0000, "dirsync"),
+    (0x0080_0000, "nocow"),
+];
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+const FLAGS: &[(u32, &str)] = &[
+    (0x0000_0001, "nodump"),
+    (0x0000_0002, "uchg"),
+    (0x0000_0004, "uappnd"),
+    (0x0002_0000, "schg"),
+    (0x0004_0000, "sappnd"),
+];
+
+/// Record the flags set on a node, as "flags", a list such as
+/// "immutable,append".  Nodes with none set have no "flags".
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+pub(crate) fn flags_atts(name: &Path, meta: &Metadata, base: &mut AttMap) {
+    if let Some(flags) = file_flags(name, meta) {
+        let names: Vec<_> = FLAGS
+            .iter()
+            .filter(|(bit, _)| flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if !names.is_empty() {
+            base.insert("flags".to_string(), names.join(","));
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+pub(crate) fn flags_atts(_name: &Path, _meta: &Metadata, _base: &mut AttMap) {}
+
+/// The flags of a file or directory, from FS_IOC_GETFLAGS.  Other kinds of
+/// nodes are left alone, as it takes opening them.
+#[cfg(target_os = "linux")]
+fn file_flags(name: &Path, meta: &Metadata) -> Option<u32> {
+    use std::ffi::CString;
+
+    if !meta.is_file() && !meta.is_dir() {
+        return None;
+    }
+    let path = CString::new(name.as_os_str().as_bytes()).ok()?;
+    // Without blocking on a fifo, or taking a terminal, should the node
+    // have been replaced by one since it was stat'ed.
+    let flags = libc::O_RDONLY | libc::O_NONBLOCK | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+    if fd < 0 {
+        return None;
+    }
+    let mut value: libc::c_int = 0;
+    let result = unsafe { libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut value) };
+    unsafe { libc::close(fd) };
+    if result == 0 {
+        Some(value as u32)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn file_flags(_name: &Path, meta: &Metadata) -> Option<u32> {
+    use std::os::macos::fs::MetadataExt;
+    Some(meta.st_flags())
+}
+
+#[cfg(target_os = "freebsd")]
+fn file_flags(_name: &Path, meta: &Metadata) -> Option<u32> {
+    use std::os::freebsd::fs::MetadataExt;
+    Some(meta.st_flags())
+}
+
+#[cfg(unix)]
 fn time_info(base: &mut AttMap, meta: &Metadata) {
     // TODO: Handle the nsec part of the time.
     base.insert("mtime".to_string(), meta.mtime().to_string());
     base.insert("ctime".to_string(), meta.ctime().to_string());
+    add_btime(base, meta);
+}
+
+/// Record when the file was created, as "btime", where the filesystem
+/// keeps it.  On Linux, this comes from statx.  A file replaced by another
+/// with the same size and mtime still has a new btime.
+fn add_btime(base: &mut AttMap, meta: &Metadata) {
+    if let Some(btime) = meta
+        .created()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    {
+        base.insert("btime".to_string(), btime.as_secs().to_string());
+    }
+}
+
+/// The `f_type` values of pseudo filesystems, from linux/magic.h.
+#[cfg(target_os = "linux")]
+const PSEUDO: &[(u32, &str)] = &[
+    (0x9fa0, "proc"),
+    (0x6265_6572, "sysfs"),
+    (0x1cd1, "devpts"),
+    (0x0027_e0eb, "cgroup"),
+    (0x6367_7270, "cgroup2"),
+    (0x6462_6720, "debugfs"),
+    (0x7472_6163, "tracefs"),
+    (0x7363_6673, "securityfs"),
+    (0xcafe_4a11, "bpf"),
+    (0x6165_676c, "pstore"),
+    (0x6265_6570, "configfs"),
+    (0x6573_5543, "fusectl"),
+    (0x1980_0202, "mqueue"),
+    (0x4249_4e4d, "binfmt_misc"),
+    (0xde5e_81e4, "efivarfs"),
+    (0xf97c_ff8c, "selinuxfs"),
+    (0x6e73_6673, "nsfs"),
+    (0x0187, "autofs"),
+];
+
+/// The `f_type` values of filesystems that hold files, also from
+/// linux/magic.h.
+#[cfg(target_os = "linux")]
+const STORED: &[(u32, &str)] = &[
+    // Also ext2 and ext3, which share the magic number.
+    (0xef53, "ext4"),
+    (0x5846_5342, "xfs"),
+    (0x9123_683e, "btrfs"),
+    (0x2fc1_2fc1, "zfs"),
+    (0xf2f5_2010, "f2fs"),
+    (0x3153_464a, "jfs"),
+    (0x5265_4973, "reiserfs"),
+    (0x0102_1994, "tmpfs"),
+    (0x8584_58f6, "ramfs"),
+    (0x794c_7630, "overlay"),
+    (0x6573_5546, "fuse"),
+    (0x6969, "nfs"),
+    (0xff53_4d42, "cifs"),
+    (0xfe53_4d42, "smb2"),
+    (0x00c3_6400, "ceph"),
+    (0x0102_1997, "9p"),
+    (0x6b41_4653, "afs"),
+    (0x4d44, "vfat"),
+    (0x2011_bab0, "exfat"),
+    (0x5346_544e, "ntfs"),
+    (0x9660, "iso9660"),
+    (0x7371_7368, "squashfs"),
+    (0x7275, "romfs"),
+];
+
+/// The `f_type` of the filesystem the given path is on.
+#[cfg(target_os = "linux")]
+fn statfs_type(path: &Path) -> Option<u32> {
+    use std::{ffi::CString, mem::MaybeUninit};
+
+    let name = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(name.as_ptr(), buf.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    Some(unsafe { buf.assume_init() }.f_type as u32)
+}
+
+/// If the given path is on a pseudo filesystem, one presenting kernel state
+/// rather than stored files, return the name of its type.  Reading these
+/// can hang, or give attributes that are meaningless in a snapshot.
+#[cfg(target_os = "linux")]
+pub(crate) fn pseudo_fs(path: &Path) -> Option<&'static str> {
+    let fstype = statfs_type(path)?;
+    PSEUDO
+        .iter()
+        .find(|(magic, _)| *magic == fstype)
+        .map(|(_, name)| *name)
+}
+
+/// The filesystems whose files are somewhere else, over the network, or in
+/// userspace, where the inode numbers, times and atime handling can't be
+/// relied on as they are for local ones.
+const NETWORK: &[&str] = &["nfs", "cifs", "smb2", "ceph", "9p", "afs", "fuse"];
+
+/// If the given path is on a network, or FUSE, filesystem, return the name
+/// of its type.
+pub(crate) fn network_fs(path: &Path) -> Option<String> {
+    fs_type(path).filter(|fstype| NETWORK.contains(&fstype.as_str()))
+}
+
+/// The type of filesystem the given path is on, such as "ext4", or its
+/// `f_type` in hex, if it isn't one known here.
+#[cfg(target_os = "linux")]
+pub(crate) fn fs_type(path: &Path) -> Option<String> {
+    let fstype = statfs_type(path)?;
+    let name = PSEUDO
+        .iter()
+        .chain(STORED)
+        .find(|(magic, _)| *magic == fstype)
+        .map(|(_, name)| name.to_string());
+    Some(name.unwrap_or_else(|| format!("{:#x}", fstype)))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn fs_type(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pseudo_fs(_path: &Path) -> Option<&'static str> {
+    None
 }
@@ -0,0 +1,141 @@
+//! Scanning a whole host.
+//!
+//! A system scan covers every real filesystem that is mounted, each in its
+//! own store, so that a change of one mount's layout doesn't disturb the
+//! history of the others.  Virtual filesystems, such as proc, tmpfs and
+//! overlays, are left out, as are further mounts of a device that has
+//! already been seen, such as bind mounts.
+
+use crate::Result;
+use data_encoding::HEXLOWER;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::MetadataExt,
+    },
+    path::PathBuf,
+};
+
+/// A mounted filesystem.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mount {
+    /// The device, or other source, that is mounted.
+    pub source: String,
+    pub mountpoint: PathBuf,
+    pub fstype: String,
+}
+
+/// Filesystem types that don't hold stored files.
+const VIRTUAL: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fuse.gvfsd-fuse",
+    "fuse.portal",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "overlay",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "squashfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+impl Mount {
+    /// Does this filesystem hold stored files, that are worth scanning?
+    pub fn is_real(&self) -> bool {
+        !VIRTUAL.contains(&self.fstype.as_str())
+    }
+
+    /// A name for this mount, usable as a file name: "root" for "/", and
+    /// otherwise the path with the slashes replaced by dashes, followed by
+    /// part of a hash of the whole path, such as "var-lib-018076f2" for
+    /// "/var/lib".  The hash keeps apart mounts such as "/var/lib" and
+    /// "/var-lib", whose names would otherwise be the same.
+    pub fn label(&self) -> String {
+        let text = self.mountpoint.to_string_lossy();
+        let path = text.trim_matches('/');
+        if path.is_empty() {
+            "root".to_string()
+        } else {
+            let hash = openssl::sha::sha256(self.mountpoint.as_os_str().as_bytes());
+            format!("{}-{}", path.replace('/', "-"), HEXLOWER.encode(&hash[..4]))
+        }
+    }
+}
+
+/// The real filesystems mounted on this host, in the order they were
+/// mounted, so each mount comes after the one it is mounted on.
+pub fn mounts() -> Result<Vec<Mount>> {
+    let text = fs::read_to_string("/proc/self/mounts")?;
+    let mut devices = HashSet::new();
+    Ok(parse_mounts(&text)
+        .into_iter()
+        .filter(|m| m.is_real())
+        // Only the first mount of each device.
+        .filter(|m| match fs::metadata(&m.mountpoint) {
+            Ok(meta) => devices.insert(meta.dev()),
+            Err(_) => false,
+        })
+        .collect())
+}
+
+/// Parse the contents of `/proc/self/mounts`, or `/etc/mtab`.
+pub fn parse_mounts(text: &str) -> Vec<Mount> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let source = unescape(fields.next()?);
+            let mountpoint = unescape(fields.next()?);
+            let fstype = fields.next()?.to_string();
+            Some(Mount {
+                source: String::from_utf8_lossy(&source).into_owned(),
+                mountpoint: PathBuf::from(OsString::from_vec(mountpoint)),
+                fstype,
+            })
+        })
+        .collect()
+}
+
+/// Undo the octal escapes used for spaces and other special characters in
+/// the mount table, such as "\040".
+fn unescape(field: &str) -> Vec<u8> {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let text = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(text, 8).ok()
+        });
+        match (bytes[i], octal) {
+            (b'\\', Some(ch)) => {
+                result.push(ch);
+                i += 4;
+            }
+            (ch, _) => {
+                result.push(ch);
+                i += 1;
+            }
+        }
+    }
+    result
+}
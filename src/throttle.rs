@@ -0,0 +1,158 @@
+//! Keeping the hashing of an update from starving other work.
+//!
+//! A scheduled scan of a busy server reads everything on its disks, as fast
+//! as they go, competing with whatever the server is there to do.  A
+//! `Throttle` caps the rate the hashing threads read files at, shared
+//! between them, so that together they stay under it, and `background`
+//! puts a hashing thread at the lowest CPU and I/O priority, so it only
+//! gets the time, and the disk, left over by everything else.
+
+use crate::{memory::MemoryLimit, Error, Result};
+use log::warn;
+use std::{
+    fmt,
+    io::{self, Read},
+    str::FromStr,
+    sync::{Mutex, Once},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A rate to read at, in bytes per second.  It parses from a number of
+/// megabytes per second, such as "50", or a size with a `K`, `M` or `G`
+/// suffix, optionally followed by "/s", such as "512K/s".
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReadRate(u64);
+
+impl ReadRate {
+    pub fn new(bytes_per_second: u64) -> ReadRate {
+        ReadRate(bytes_per_second)
+    }
+
+    pub fn bytes_per_second(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for ReadRate {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<ReadRate> {
+        let bad = || Error::InvalidRate(text.to_string());
+        let trimmed = text.trim();
+        let size = trimmed
+            .strip_suffix("/s")
+            .or_else(|| trimmed.strip_suffix("/S"))
+            .unwrap_or(trimmed);
+        let bytes = if !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit()) {
+            size.parse::<u64>()
+                .ok()
+                .and_then(|mb| mb.checked_mul(1 << 20))
+                .ok_or_else(bad)?
+        } else {
+            size.parse::<MemoryLimit>().map_err(|_| bad())?.bytes()
+        };
+        if bytes == 0 {
+            return Err(bad());
+        }
+        Ok(ReadRate(bytes))
+    }
+}
+
+impl fmt::Display for ReadRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/s", MemoryLimit::new(self.0))
+    }
+}
+
+/// A limit on how fast files are read, which can be shared between the
+/// hashing threads of several updates.
+pub struct Throttle {
+    rate: u64,
+    /// When the reads so far have been paid for.
+    paid: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(rate: ReadRate) -> Throttle {
+        Throttle {
+            rate: rate.bytes_per_second().max(1),
+            paid: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Having read `bytes`, wait long enough to stay under the rate.
+    pub fn consume(&self, bytes: u64) {
+        let now = Instant::now();
+        let until = {
+            let mut paid = self.paid.lock().unwrap();
+            let cost = Duration::from_secs_f64(bytes as f64 / self.rate as f64);
+            *paid = (*paid).max(now) + cost;
+            *paid
+        };
+        if until > now {
+            thread::sleep(until - now);
+        }
+    }
+
+    /// A reader that waits, after each read, to stay under the rate.
+    pub(crate) fn reader<R: Read>(&self, inner: R) -> Throttled<'_, R> {
+        Throttled {
+            throttle: self,
+            inner,
+        }
+    }
+}
+
+/// A reader limited by a `Throttle`.
+pub(crate) struct Throttled<'a, R> {
+    throttle: &'a Throttle,
+    inner: R,
+}
+
+impl<'a, R: Read> Read for Throttled<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.throttle.consume(count as u64);
+        Ok(count)
+    }
+}
+
+/// Put the calling thread at the lowest CPU priority, and, on Linux, in the
+/// idle I/O class, as `nice -n 19 ionice -c 3` would, so that it only runs,
+/// and reads, when nothing else wants to.  Threads it starts inherit this.
+/// A failure is warned about once, and the thread goes on as it was.
+pub(crate) fn background() {
+    if let Err(e) = lower_priority() {
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| warn!("Unable to lower the priority of hashing ({})", e));
+    }
+}
+
+#[cfg(unix)]
+fn lower_priority() -> io::Result<()> {
+    // On Linux, these apply to the calling thread, rather than the whole
+    // process.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        let prio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lower_priority() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
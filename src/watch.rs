@@ -0,0 +1,474 @@
+//! Keeping a store up to date by watching the tree.
+//!
+//! Rather than scanning the whole tree on a schedule, `Watch` asks the
+//! kernel, with inotify, to say what is touched in it, and every so often
+//! commits a new version with just those paths looked at again, as
+//! `update --paths-from` does, so only the touched files are hashed.
+//! Everything else is carried forward from the version before.
+//!
+//! Every directory of the tree is watched, up to the limit in
+//! `/proc/sys/fs/inotify/max_user_watches`.  When the watches are in place,
+//! the tree is updated in full, so that whatever changed while it wasn't
+//! being watched is picked up.  A new directory is watched as soon as it is
+//! seen, and everything in it listed.  If the kernel's queue of events
+//! overflows, or a directory is moved, which leaves the watches of
+//! everything in it with the wrong paths, the watches are set up again, and
+//! the next version is a full update.  The store's own files, if they are
+//! in the tree, aren't watched, or every commit would cause the next.
+//!
+//! Inotify is only on Linux, and elsewhere `Watch::run` gives an error.
+//! fanotify isn't used, as watching a whole mount needs root.
+
+use crate::{
+    node::PathList,
+    platform::{device, os_bytes},
+    service, update_with, version_name, CancellationToken, Error, Exclude, HashAlgorithm, Result,
+    Store, StoreTags, UpdateHooks,
+};
+use log::{info, warn};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use self::inotify_impl::Inotify;
+
+/// The longest to wait for events before checking for a shutdown.
+const POLL: Duration = Duration::from_secs(1);
+
+/// Something that happened in the tree.
+#[derive(Debug)]
+pub(crate) enum Event {
+    /// A path, relative to the top of the tree, that was touched.
+    Touched { path: PathBuf, new_dir: bool },
+    /// The events are no longer to be trusted, such as after the kernel's
+    /// queue overflowed.
+    Lost,
+}
+
+/// Watches a tree, committing the touched paths to its store as new
+/// versions.
+pub struct Watch<'a> {
+    dir: PathBuf,
+    store: &'a dyn Store,
+    algorithms: Vec<HashAlgorithm>,
+    tags: StoreTags,
+    interval: Duration,
+    cancel: Option<CancellationToken>,
+}
+
+impl<'a> Watch<'a> {
+    /// Watch the tree at `dir`, hashing with the given algorithms.
+    pub fn new(dir: &Path, store: &'a dyn Store, algorithms: &[HashAlgorithm]) -> Watch<'a> {
+        Watch {
+            dir: dir.to_path_buf(),
+            store,
+            algorithms: algorithms.to_vec(),
+            tags: StoreTags::new(),
+            interval: Duration::from_secs(60),
+            cancel: None,
+        }
+    }
+
+    /// Tags for each new version.  Each is named with the time it is
+    /// committed, rather than any name given here.
+    pub fn with_tags(mut self, tags: &StoreTags) -> Watch<'a> {
+        self.tags = tags.clone();
+        self
+    }
+
+    /// Commit the touched paths at most this often, rather than every
+    /// minute.  The first touch after a commit starts the wait for the next.
+    pub fn with_interval(mut self, interval: Duration) -> Watch<'a> {
+        self.interval = interval;
+        self
+    }
+
+    /// Stop watching once the token is cancelled, as well as on a shutdown
+    /// from `service::handle_termination`.  Anything touched is committed
+    /// first.
+    pub fn with_cancel(mut self, token: CancellationToken) -> Watch<'a> {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn stopping(&self) -> bool {
+        service::shutdown_requested() || self.cancel.as_ref().is_some_and(|c| c.is_cancelled())
+    }
+
+    /// Watch the tree until stopped.  `hooks` gives the settings for each
+    /// update, whose scan options give the paths to leave out, and whether
+    /// to cross filesystems.  They can't give several roots.
+    pub fn run<F>(&self, mut hooks: F) -> Result<()>
+    where
+        F: FnMut() -> Result<UpdateHooks>,
+    {
+        let first = hooks()?;
+        if first.roots.is_some() {
+            return Err(Error::SeveralRoots("Watching".to_string()));
+        }
+        let tree = Tree::new(&self.dir, self.store, &first)?;
+        let mut inotify = tree.watch_all()?;
+
+        // Catch up with whatever happened while nobody was watching.  A
+        // store with no versions yet is a new one.
+        let is_update = !self.store.get_versions()?.is_empty();
+        self.commit(is_update, None, first)?;
+        let _ = service::notify("READY=1");
+
+        let mut touched = PathList::new();
+        let mut full = false;
+        let mut due: Option<Instant> = None;
+        loop {
+            let stopping = self.stopping();
+            if stopping || due.is_some_and(|due| Instant::now() >= due) {
+                if full {
+                    self.commit(true, None, hooks()?)?;
+                } else if !touched.is_empty() {
+                    let paths = std::mem::take(&mut touched);
+                    self.commit(true, Some(paths), hooks()?)?;
+                }
+                full = false;
+                due = None;
+            }
+            if stopping {
+                return Ok(());
+            }
+
+            let wait = match due {
+                Some(due) => due.saturating_duration_since(Instant::now()).min(POLL),
+                None => POLL,
+            };
+            for event in inotify.wait(wait)? {
+                match event {
+                    Event::Touched { path, .. } if tree.is_store(&path) => continue,
+                    Event::Touched { path, new_dir } => {
+                        if new_dir {
+                            for path in tree.watch_new(&mut inotify, &path)? {
+                                touched.insert(path)?;
+                            }
+                        }
+                        touched.insert(path)?;
+                    }
+                    Event::Lost => {
+                        warn!(
+                            "Watching {:?} lost track of changes, updating in full",
+                            self.dir
+                        );
+                        inotify = tree.watch_all()?;
+                        full = true;
+                    }
+                }
+                if due.is_none() {
+                    due = Some(Instant::now() + self.interval);
+                }
+            }
+        }
+    }
+
+    /// Commit a new version, of just the given paths, or a full update.
+    fn commit(
+        &self,
+        is_update: bool,
+        paths: Option<PathList>,
+        mut hooks: UpdateHooks,
+    ) -> Result<()> {
+        let mut tags = self.tags.clone();
+        let name = version_name(self.store, self.store.now())?;
+        tags.insert("name".to_string(), name);
+        match &paths {
+            Some(paths) => info!("Committing {} touched paths in {:?}", paths.len(), self.dir),
+            None => info!("Updating {:?} in full", self.dir),
+        }
+        hooks.paths = paths;
+        update_with(
+            &self.dir,
+            self.store,
+            is_update,
+            &tags,
+            &self.algorithms,
+            hooks,
+        )
+    }
+}
+
+/// The tree being watched, and what of it to leave out.
+struct Tree {
+    root: PathBuf,
+    exclude: Exclude,
+    /// The device of the root, unless crossing filesystems.
+    device: Option<u64>,
+    /// The directory of the store, relative to the root, if it is in the
+    /// tree, and the start of the names of its files.
+    store: Option<(PathBuf, OsString)>,
+}
+
+impl Tree {
+    fn new(dir: &Path, store: &dyn Store, hooks: &UpdateHooks) -> Result<Tree> {
+        let root = fs::canonicalize(dir)?;
+        let mut exclude = hooks.scan.exclude.clone();
+        exclude.add_root(&root)?;
+        let device = if hooks.scan.cross_filesystems {
+            None
+        } else {
+            Some(device(&fs::metadata(&root)?))
+        };
+
+        // Every file of the store is named with its base and an extension.  A store that keeps
+        // no files alongside it has none to leave out.
+        let prefix = store.sidecar("").ok();
+        let store = match prefix.as_ref().map(|p| (p.parent(), p.file_name())) {
+            Some((Some(parent), Some(base))) => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                fs::canonicalize(parent).ok().and_then(|parent| {
+                    parent
+                        .strip_prefix(&root)
+                        .ok()
+                        .map(|rel| (rel.to_path_buf(), base.to_os_string()))
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Tree {
+            root,
+            exclude,
+            device,
+            store,
+        })
+    }
+
+    /// Is the path one of the store's files?
+    fn is_store(&self, path: &Path) -> bool {
+        let (dir, base) = match &self.store {
+            Some(store) => store,
+            None => return false,
+        };
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return false,
+        };
+        path.parent() == Some(dir.as_path()) && os_bytes(name).starts_with(&os_bytes(base))
+    }
+
+    /// Watch every directory of the tree, afresh.
+    fn watch_all(&self) -> Result<Inotify> {
+        let mut inotify = Inotify::new()?;
+        self.watch_dir(&mut inotify, Path::new(""), &mut |_| ())?;
+        Ok(inotify)
+    }
+
+    /// Watch a new directory, and everything in it, giving the paths of
+    /// everything in it, as they may have been made before the watch was.
+    fn watch_new(&self, inotify: &mut Inotify, rel: &Path) -> Result<Vec<PathBuf>> {
+        let mut found = vec![];
+        self.watch_dir(inotify, rel, &mut |path| found.push(path))?;
+        Ok(found)
+    }
+
+    fn watch_dir(
+        &self,
+        inotify: &mut Inotify,
+        rel: &Path,
+        found: &mut dyn FnMut(PathBuf),
+    ) -> Result<()> {
+        let path = self.root.join(rel);
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => meta,
+            // Gone already, or not a directory.
+            _ => return Ok(()),
+        };
+        if self.device.is_some_and(|dev| dev != device(&meta)) {
+            return Ok(());
+        }
+        if !rel.as_os_str().is_empty() && self.exclude.matching(rel, true).is_some() {
+            return Ok(());
+        }
+        if let Err(e) = inotify.add(&path, rel) {
+            warn!("Unable to watch {:?} ({})", path, e);
+            return Ok(());
+        }
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            found(child.clone());
+            if entry.file_type()?.is_dir() {
+                self.watch_dir(inotify, &child, found)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify_impl {
+    use super::Event;
+    use std::{
+        collections::HashMap,
+        ffi::{CString, OsStr},
+        io,
+        mem::size_of,
+        os::unix::{ffi::OsStrExt, io::RawFd},
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    const MASK: u32 = libc::IN_ATTRIB
+        | libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_MOVE_SELF
+        | libc::IN_DONT_FOLLOW
+        | libc::IN_ONLYDIR;
+
+    pub(crate) struct Inotify {
+        fd: RawFd,
+        /// The directory of each watch, relative to the top of the tree.
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Inotify {
+        pub(crate) fn new() -> io::Result<Inotify> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                fd,
+                dirs: HashMap::new(),
+            })
+        }
+
+        /// Watch the directory at `path`, whose events are given relative
+        /// to `rel`.
+        pub(crate) fn add(&mut self, path: &Path, rel: &Path) -> io::Result<()> {
+            let name = CString::new(path.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, name.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, rel.to_path_buf());
+            Ok(())
+        }
+
+        /// Wait up to `timeout` for events, and give those that have come.
+        pub(crate) fn wait(&mut self, timeout: Duration) -> io::Result<Vec<Event>> {
+            let mut poll = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+            if unsafe { libc::poll(&mut poll, 1, millis) } < 0 {
+                let e = io::Error::last_os_error();
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(vec![]),
+                    _ => Err(e),
+                };
+            }
+
+            let mut events = vec![];
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let count = unsafe {
+                    libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+                };
+                if count < 0 {
+                    let e = io::Error::last_os_error();
+                    return match e.kind() {
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(events),
+                        _ => Err(e),
+                    };
+                }
+                let mut pos = 0;
+                while pos + size_of::<libc::inotify_event>() <= count as usize {
+                    // Safety: the kernel writes whole events, and the read
+                    // is unaligned.
+                    let event: libc::inotify_event =
+                        unsafe { std::ptr::read_unaligned(buf[pos..].as_ptr() as *const _) };
+                    let start = pos + size_of::<libc::inotify_event>();
+                    pos = start + event.len as usize;
+                    let name = &buf[start..pos];
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                    self.event(&event, OsStr::from_bytes(name), &mut events);
+                }
+            }
+        }
+
+        fn event(&mut self, event: &libc::inotify_event, name: &OsStr, events: &mut Vec<Event>) {
+            let mask = event.mask;
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(Event::Lost);
+                return;
+            }
+            if mask & libc::IN_IGNORED != 0 {
+                self.dirs.remove(&event.wd);
+                return;
+            }
+            let dir = match self.dirs.get(&event.wd) {
+                Some(dir) => dir,
+                None => return,
+            };
+            let is_dir = mask & libc::IN_ISDIR != 0;
+            // A directory moved within the tree leaves the watches below
+            // it with the wrong paths.
+            if mask & libc::IN_MOVE_SELF != 0 || (is_dir && mask & libc::IN_MOVED_FROM != 0) {
+                events.push(Event::Lost);
+                return;
+            }
+            if name.is_empty() {
+                return;
+            }
+            events.push(Event::Touched {
+                path: dir.join(name),
+                new_dir: is_dir && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0,
+            });
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod inotify_impl {
+    use super::Event;
+    use std::{io, path::Path, time::Duration};
+
+    pub(crate) struct Inotify;
+
+    impl Inotify {
+        pub(crate) fn new() -> io::Result<Inotify> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "watching needs inotify, on Linux",
+            ))
+        }
+
+        pub(crate) fn add(&mut self, _path: &Path, _rel: &Path) -> io::Result<()> {
+            unreachable!("no watches without inotify")
+        }
+
+        pub(crate) fn wait(&mut self, _timeout: Duration) -> io::Result<Vec<Event>> {
+            unreachable!("no watches without inotify")
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Artifacts attached to the versions of a store.
+
+use rsure::{parse_store, Error, StoreTags, Version, ARTIFACT_EXT};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn artifacts() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+
+    // Nothing to attach them to yet.
+    assert!(store
+        .put_artifact(&Version::Latest, "report.json", b"{}")
+        .is_err());
+
+    let mut tags = StoreTags::new();
+    for name in &["first", "second", "third"] {
+        tags.insert("name".into(), name.to_string());
+        rsure::update(&tree, &*store, name != &"first", &tags, &[]).unwrap();
+    }
+
+    let first = Version::Named("first".into());
+    store.put_artifact(&first, "report.json", b"{}").unwrap();
+    store.put_artifact(&first, "signature", b"one").unwrap();
+    store.put_artifact(&first, "signature", b"two").unwrap();
+    store
+        .put_artifact(&Version::Latest, "report.json", b"[]")
+        .unwrap();
+
+    assert_eq!(
+        store.artifacts(&first).unwrap(),
+        ["report.json", "signature"]
+    );
+    assert_eq!(
+        store.get_artifact(&first, "signature").unwrap().unwrap(),
+        b"two"
+    );
+    assert_eq!(
+        store
+            .get_artifact(&Version::Tagged("3".into()), "report.json")
+            .unwrap()
+            .unwrap(),
+        b"[]"
+    );
+    assert!(store
+        .get_artifact(&Version::Prior, "report.json")
+        .unwrap()
+        .is_none());
+    assert!(store.artifacts(&Version::Prior).unwrap().is_empty());
+
+    for kind in &["", ".hidden", "a/b", "../up", "sp ace"] {
+        assert!(matches!(
+            store.put_artifact(&first, kind, b""),
+            Err(Error::InvalidArtifact(_))
+        ));
+    }
+    assert!(matches!(
+        store.put_artifact(&Version::Named("nope".into()), "signature", b""),
+        Err(Error::UnknownVersion(_))
+    ));
+
+    // Deleting a version removes its artifacts, and a later version starts with none.
+    store.delete_version(Version::Latest).unwrap();
+    let dir = tmp.path().join(format!("2sure.{}", ARTIFACT_EXT));
+    assert!(dir.join("1").is_dir());
+    assert!(!dir.join("3").exists());
+    tags.insert("name".into(), "fourth".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert!(store.artifacts(&Version::Latest).unwrap().is_empty());
+
+    // Left over from elsewhere, including a stray file.
+    fs::create_dir_all(dir.join("17")).unwrap();
+    fs::write(dir.join("18"), b"stray").unwrap();
+    assert_eq!(store.prune_artifacts().unwrap(), 2);
+    assert!(!dir.join("18").exists());
+    assert_eq!(store.prune_artifacts().unwrap(), 0);
+    assert_eq!(store.artifacts(&first).unwrap().len(), 2);
+}
@@ -0,0 +1,35 @@
+// Keeping several backups of a store.
+
+use rsure::{parse_store, StoreTags};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+use weave::{Compression, SimpleNaming};
+
+/// The names of the versions in a backup of the store in `dir`.
+fn names(dir: &Path, ext: &str) -> Vec<String> {
+    let backup = SimpleNaming::new(dir, "2sure", ext, Compression::Gzip);
+    let header = weave::read_header(&backup).unwrap();
+    header.deltas.into_iter().map(|d| d.name).collect()
+}
+
+#[test]
+fn store_backups() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+
+    let path = tmp.path().join("2sure.dat.gz");
+    let mut store = parse_store(path.to_str().unwrap()).unwrap();
+    store.set_backups(2);
+    let mut tags = StoreTags::new();
+    for name in &["a", "b", "c", "d"] {
+        fs::write(tree.join(name), name).unwrap();
+        tags.insert("name".into(), name.to_string());
+        rsure::update(&tree, &*store, *name != "a", &tags, &[]).unwrap();
+    }
+
+    assert_eq!(names(tmp.path(), "dat"), ["a", "b", "c", "d"]);
+    assert_eq!(names(tmp.path(), "bak"), ["a", "b", "c"]);
+    assert_eq!(names(tmp.path(), "bak.1"), ["a", "b"]);
+    assert!(!tmp.path().join("2sure.bak.2.gz").exists());
+}
@@ -0,0 +1,61 @@
+// Recording when files were created.
+
+use rsure::{check, parse_store, CheckOptions, StoreTags, Version};
+use std::{
+    fs::{self, File},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+use tempdir::TempDir;
+
+#[test]
+fn replaced_file() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    let path = tree.join("file");
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::write(&path, "file\n").unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    // Not every filesystem keeps creation times.
+    if fs::metadata(&path).unwrap().created().is_err() {
+        return;
+    }
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let node = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|node| node.unwrap())
+        .find(|node| node.is_file())
+        .unwrap();
+    assert!(node.atts().unwrap().contains_key("btime"));
+
+    // Replaced by a file just like it, other than when it was made.
+    thread::sleep(Duration::from_millis(1100));
+    let new = tree.join("file.new");
+    fs::write(&new, "file\n").unwrap();
+    File::options()
+        .write(true)
+        .open(&new)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    fs::rename(&new, &path).unwrap();
+
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    let changes: Vec<_> = report
+        .changes
+        .iter()
+        .map(|c| (c.path.clone(), c.attrs_changed.clone()))
+        .collect();
+    assert_eq!(changes, [(path, vec!["btime".to_string()])]);
+}
@@ -0,0 +1,151 @@
+// Cancelling scans, updates and comparisons.
+
+use rsure::{
+    compare_trees, fs::scan_fs_with, parse_store, CancellationToken, Error, ScanOptions, Store,
+    StoreTags, SureNode, UpdateHooks, Version,
+};
+use std::{fs, thread, time::Duration};
+use tempdir::TempDir;
+
+fn tree(tmp: &TempDir) -> std::path::PathBuf {
+    let tree = tmp.path().join("tree");
+    for dir in &["a", "b", "c"] {
+        fs::create_dir_all(tree.join(dir)).unwrap();
+        for file in &["1", "2", "3"] {
+            fs::write(tree.join(dir).join(file), "data\n").unwrap();
+        }
+    }
+    tree
+}
+
+#[test]
+fn cancel_scan() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tree(&tmp);
+    let token = CancellationToken::new();
+    let options = ScanOptions {
+        cancel: Some(token.clone()),
+        ..ScanOptions::default()
+    };
+
+    let mut scan = scan_fs_with(&tree, &options).unwrap();
+    for _ in 0..3 {
+        assert!(scan.next().unwrap().is_ok());
+    }
+    token.cancel();
+    assert!(matches!(scan.next(), Some(Err(Error::Cancelled))));
+    assert!(scan.next().is_none());
+}
+
+#[test]
+fn cancel_update() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tree(&tmp);
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+
+    let token = CancellationToken::new();
+    token.cancel();
+    for &pipelined in &[false, true] {
+        let hooks = UpdateHooks {
+            cancel: Some(token.clone()),
+            pipelined,
+            ..UpdateHooks::default()
+        };
+        let result = rsure::update_with(&tree, &*store, false, &tags, &[], hooks);
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+    // Nothing was added to the store, which was never made.
+    assert!(!tmp.path().join("2sure.dat.gz").exists());
+
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    assert_eq!(store.get_versions().unwrap().len(), 1);
+
+    // Cancelled while the new version is being written.
+    let token = CancellationToken::new();
+    let stop = token.clone();
+    let hooks = UpdateHooks {
+        cancel: Some(token),
+        observers: vec![Box::new(move |_: &SureNode| {
+            stop.cancel();
+            Ok(())
+        })],
+        ..UpdateHooks::default()
+    };
+    tags.insert("name".into(), "second".into());
+    let result = rsure::update_with(&tree, &*store, true, &tags, &[], hooks);
+    assert!(matches!(result, Err(Error::Cancelled)), "{:?}", result);
+    assert_eq!(store.get_versions().unwrap().len(), 1);
+}
+
+#[test]
+fn cancel_compare() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tree(&tmp);
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let load = |store: &dyn Store| store.load_iter(Version::Latest).unwrap();
+
+    let token = CancellationToken::new();
+    let mut count = 0;
+    compare_trees(
+        token.guard(load(&*store)),
+        token.guard(load(&*store)),
+        &tree,
+        &[],
+        |_| count += 1,
+    )
+    .unwrap();
+    assert_eq!(count, 0);
+
+    token.cancel();
+    let result = compare_trees(
+        token.guard(load(&*store)),
+        token.guard(load(&*store)),
+        &tree,
+        &[],
+        |_| (),
+    );
+    assert!(matches!(result, Err(Error::Cancelled)));
+}
+
+#[test]
+fn pause_scan() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tree(&tmp);
+    let token = CancellationToken::new();
+    let options = ScanOptions {
+        cancel: Some(token.clone()),
+        ..ScanOptions::default()
+    };
+    let count = |options: ScanOptions, tree: std::path::PathBuf| {
+        thread::spawn(move || {
+            scan_fs_with(&tree, &options)
+                .unwrap()
+                .collect::<rsure::Result<Vec<_>>>()
+                .map(|nodes| nodes.len())
+        })
+    };
+
+    let full = count(options.clone(), tree.clone())
+        .join()
+        .unwrap()
+        .unwrap();
+    token.pause();
+    assert!(token.is_paused());
+    let scan = count(options.clone(), tree.clone());
+    thread::sleep(Duration::from_millis(200));
+    assert!(!scan.is_finished());
+    token.resume();
+    assert_eq!(scan.join().unwrap().unwrap(), full);
+
+    // Cancelling also ends a pause.
+    token.pause();
+    let scan = count(options, tree);
+    thread::sleep(Duration::from_millis(100));
+    token.cancel();
+    assert!(matches!(scan.join().unwrap(), Err(Error::Cancelled)));
+}
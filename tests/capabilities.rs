@@ -0,0 +1,64 @@
+// Recording the capabilities given to files.
+
+#![cfg(target_os = "linux")]
+
+use rsure::{check, parse_store, CheckOptions, StoreTags, Version};
+use std::{ffi::CString, fs, os::unix::ffi::OsStrExt, path::Path};
+use tempdir::TempDir;
+
+/// Give the file cap_net_raw, permitted and effective, returning false if
+/// that isn't allowed here.
+fn set_net_raw(path: &Path) -> bool {
+    // A revision 2 vfs_cap_data.
+    let mut data = vec![];
+    for word in &[0x0200_0001u32, 1 << 13, 0, 0, 0] {
+        data.extend_from_slice(&word.to_le_bytes());
+    }
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let key = CString::new("security.capability").unwrap();
+    unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            key.as_ptr(),
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+            0,
+        ) == 0
+    }
+}
+
+#[test]
+fn capabilities() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("ping"), "ping\n").unwrap();
+    fs::write(tree.join("plain"), "plain\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    // Setting capabilities takes CAP_SETFCAP, and a filesystem with
+    // extended attributes.
+    if !set_net_raw(&tree.join("ping")) {
+        return;
+    }
+
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert_eq!(report.changes.len(), 1);
+    assert_eq!(report.changes[0].path, tree.join("ping"));
+    assert_eq!(report.changes[0].attrs_changed, ["cap"]);
+
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert!(report.changes.is_empty());
+
+    // Writing to the file drops them.
+    fs::write(tree.join("ping"), "ping\n").unwrap();
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert_eq!(report.changes.len(), 1);
+    assert!(report.changes[0].attrs_changed.contains(&"cap".to_string()));
+}
@@ -0,0 +1,85 @@
+// Checking a tree with the library, rather than the check command.
+
+use rsure::{
+    check, ignore::IgnoreRules, parse_store, ChangeAction, CheckOptions, Error, HashAlgorithm,
+    StoreTags, Version,
+};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn check_report() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    fs::write(tree.join("c"), "c\n").unwrap();
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha256]).unwrap();
+
+    fs::write(tree.join("a"), "changed\n").unwrap();
+    fs::rename(tree.join("b"), tree.join("moved")).unwrap();
+    fs::remove_file(tree.join("c")).unwrap();
+
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert_eq!(report.version.name, "first");
+    let changes: Vec<_> = report
+        .changes
+        .iter()
+        .map(|c| (c.action, c.path.clone()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (ChangeAction::Modified, tree.join("a")),
+            (ChangeAction::Removed, tree.join("b")),
+            (ChangeAction::Removed, tree.join("c")),
+            (ChangeAction::Added, tree.join("moved")),
+        ]
+    );
+    assert_eq!(report.summary.modified, 1);
+    assert_eq!(report.summary.removed, 2);
+    assert_eq!(report.summary.added, 1);
+    assert!(report.changes[0]
+        .attrs_changed
+        .contains(&"sha256".to_string()));
+
+    // With renames, and the changed contents of "a" ignored.
+    let options = CheckOptions {
+        rules: Some(IgnoreRules::everywhere(&[
+            "sha256", "size", "mtime", "ctime",
+        ])),
+        renames: true,
+        ..CheckOptions::default()
+    };
+    let report = check(&tree, &*store, Version::Latest, options).unwrap();
+    let changes: Vec<_> = report
+        .changes
+        .iter()
+        .map(|c| (c.action, c.from.clone(), c.path.clone()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (ChangeAction::Removed, None, tree.join("c")),
+            (
+                ChangeAction::Renamed,
+                Some(tree.join("b")),
+                tree.join("moved")
+            ),
+        ]
+    );
+
+    match check(
+        &tree,
+        &*store,
+        Version::Named("other".into()),
+        CheckOptions::default(),
+    ) {
+        Err(Error::UnknownVersion(name)) => assert_eq!(name, "name:other"),
+        other => panic!("Unexpected result: {:?}", other.map(|r| r.changes)),
+    }
+}
@@ -0,0 +1,181 @@
+// The multi-profile daemon.
+//
+// Runs each profile once, rather than waiting on the schedule, and checks
+// that the status file records the runs.
+
+use rsure::{daemon::Daemon, parse_store, NullProgress};
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tempdir::TempDir;
+
+// Running daemons notify whatever `NOTIFY_SOCKET` names, so only run one at
+// a time.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+fn make_tree(root: &Path, text: &str) {
+    fs::create_dir_all(root).unwrap();
+    fs::write(root.join("file"), text).unwrap();
+}
+
+#[test]
+fn run_profiles() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let top = tmp.path();
+    make_tree(&top.join("one"), "one\n");
+    make_tree(&top.join("two"), "two\n");
+
+    let config = top.join("daemon.json");
+    fs::write(
+        &config,
+        format!(
+            r#"{{
+                "hash_threads": 1,
+                "profiles": [
+                    {{ "name": "one", "dir": "{0}/one", "store": "{0}/one.weave.gz",
+                       "interval": 60 }},
+                    {{ "name": "two", "dir": "{0}/two", "store": "{0}/two.weave.gz",
+                       "interval": 3600, "hash": "sha256" }},
+                    {{ "name": "bad", "dir": "{0}/missing", "store": "{0}/bad.weave.gz",
+                       "interval": 60 }}
+                ]
+            }}"#,
+            top.display()
+        ),
+    )
+    .unwrap();
+
+    let daemon = Daemon::new(&config)
+        .unwrap()
+        .with_progress(Arc::new(NullProgress));
+    for p in daemon.profiles() {
+        let _ = daemon.run_profile(p);
+    }
+    // Second run of the first profile is an update.
+    daemon.run_profile(&daemon.profiles()[0]).unwrap();
+
+    let status = rsure::daemon::load_status(top.join("daemon.json.status")).unwrap();
+    let one = &status.profiles["one"];
+    assert!(one.last_error.is_none());
+    assert_eq!(
+        one.next_run - one.last_run.unwrap(),
+        chrono::Duration::seconds(60)
+    );
+    let two = &status.profiles["two"];
+    assert_eq!(
+        two.next_run - two.last_run.unwrap(),
+        chrono::Duration::seconds(3600)
+    );
+    assert!(status.profiles["bad"].last_error.is_some());
+
+    let one = parse_store(top.join("one.weave.gz").to_str().unwrap()).unwrap();
+    let versions = one.get_versions().unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].tags["profile"], "one");
+    let two = parse_store(top.join("two.weave.gz").to_str().unwrap()).unwrap();
+    assert_eq!(two.get_versions().unwrap()[0].tags["hash"], "sha256");
+}
+
+#[cfg(unix)]
+#[test]
+fn status_socket() {
+    use std::{io::Read, os::unix::net::UnixStream, sync::Arc, thread, time::Duration};
+
+    let tmp = TempDir::new("rsure").unwrap();
+    let top = tmp.path();
+    make_tree(&top.join("one"), "one\n");
+
+    let config = top.join("daemon.json");
+    let socket = top.join("status.sock");
+    fs::write(
+        &config,
+        format!(
+            r#"{{
+                "status_interval": 1,
+                "socket": "{0}/status.sock",
+                "profiles": [
+                    {{ "name": "one", "dir": "{0}/one", "store": "{0}/one.weave.gz",
+                       "interval": 3600 }}
+                ]
+            }}"#,
+            top.display()
+        ),
+    )
+    .unwrap();
+
+    let _lock = RUNNING.lock().unwrap();
+    let daemon = Arc::new(Daemon::new(&config).unwrap());
+    let runner = {
+        let daemon = daemon.clone();
+        thread::spawn(move || daemon.run())
+    };
+
+    let mut text = String::new();
+    for _ in 0..100 {
+        if let Ok(mut stream) = UnixStream::connect(&socket) {
+            stream.read_to_string(&mut text).unwrap();
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let status: rsure::daemon::DaemonStatus = serde_json::from_str(&text).unwrap();
+    assert_eq!(status.pid, Some(std::process::id()));
+    assert!(status.updated.is_some());
+    assert!(status.profiles.contains_key("one"));
+
+    daemon.stop();
+    runner.join().unwrap().unwrap();
+    assert!(!socket.exists());
+}
+
+// The daemon tells the service manager it is ready, and when it stops.
+#[cfg(unix)]
+#[test]
+fn service_notify() {
+    use std::{os::unix::net::UnixDatagram, sync::Arc, thread, time::Duration};
+
+    let tmp = TempDir::new("rsure").unwrap();
+    let top = tmp.path();
+    make_tree(&top.join("one"), "one\n");
+
+    let notify = UnixDatagram::bind(top.join("notify.sock")).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let config = top.join("daemon.json");
+    fs::write(
+        &config,
+        format!(
+            r#"{{
+                "profiles": [
+                    {{ "name": "one", "dir": "{0}/one", "store": "{0}/one.weave.gz",
+                       "interval": 3600 }}
+                ]
+            }}"#,
+            top.display()
+        ),
+    )
+    .unwrap();
+
+    let _lock = RUNNING.lock().unwrap();
+    std::env::set_var("NOTIFY_SOCKET", top.join("notify.sock"));
+    let daemon = Arc::new(Daemon::new(&config).unwrap());
+    let runner = {
+        let daemon = daemon.clone();
+        thread::spawn(move || daemon.run())
+    };
+
+    let mut buf = [0u8; 64];
+    let mut receive = || {
+        let len = notify.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    };
+    assert_eq!(receive(), "READY=1");
+    daemon.stop();
+    assert_eq!(receive(), "STOPPING=1");
+    runner.join().unwrap().unwrap();
+    std::env::remove_var("NOTIFY_SOCKET");
+}
@@ -0,0 +1,84 @@
+// Removing a version from a store.
+
+use rsure::{parse_store, Error, Store, StoreTags, SureNode, Version};
+use std::fs;
+use tempdir::TempDir;
+
+/// The names and attributes of a version's nodes.
+fn nodes(store: &dyn Store, version: Version) -> Vec<(Option<String>, Option<rsure::AttMap>)> {
+    store
+        .load_iter(version)
+        .unwrap()
+        .map(|n| {
+            let n: SureNode = n.unwrap();
+            (n.get_name().map(|s| s.to_string()), n.atts().cloned())
+        })
+        .collect()
+}
+
+fn numbers(store: &dyn Store) -> Vec<usize> {
+    store
+        .get_versions()
+        .unwrap()
+        .iter()
+        .map(|v| v.version.numeric().unwrap())
+        .collect()
+}
+
+#[test]
+fn delete_version() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    let wrong = tmp.path().join("wrong");
+    fs::create_dir(&tree).unwrap();
+    fs::create_dir(&wrong).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    fs::write(wrong.join("b"), "b\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let first = nodes(&*store, Version::Latest);
+
+    // A snapshot of the wrong directory, which is then removed again.
+    tags.insert("name".into(), "wrong".into());
+    rsure::update(&wrong, &*store, false, &tags, &[]).unwrap();
+    fs::write(tree.join("c"), "c\n").unwrap();
+    tags.insert("name".into(), "third".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    let third = nodes(&*store, Version::Latest);
+    assert_eq!(numbers(&*store), [3, 2, 1]);
+
+    store.delete_version(Version::Tagged("2".into())).unwrap();
+    assert_eq!(numbers(&*store), [3, 1]);
+    assert_eq!(nodes(&*store, Version::Latest), third);
+    assert_eq!(nodes(&*store, Version::Prior), first);
+    assert_eq!(nodes(&*store, Version::Tagged("1".into())), first);
+
+    assert!(matches!(
+        store.delete_version(Version::Tagged("2".into())),
+        Err(Error::UnknownVersion(_))
+    ));
+    assert!(matches!(
+        store.load_iter(Version::Tagged("2".into())),
+        Err(Error::UnknownVersion(_))
+    ));
+    assert!(matches!(
+        store.load_iter(Version::Tagged("9".into())),
+        Err(Error::UnknownVersion(_))
+    ));
+    store.delete_version(Version::Latest).unwrap();
+    assert_eq!(numbers(&*store), [1]);
+    assert_eq!(nodes(&*store, Version::Latest), first);
+    assert!(store.delete_version(Version::Latest).is_err());
+
+    // A new version isn't given the number of a deleted one.
+    tags.insert("name".into(), "fourth".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert_eq!(numbers(&*store), [4, 1]);
+    assert!(matches!(
+        store.load_iter(Version::Tagged("3".into())),
+        Err(Error::UnknownVersion(_))
+    ));
+}
@@ -0,0 +1,70 @@
+// Deterministic scan mode.
+//
+// With RSURE_DETERMINISTIC set, two scans of identical trees should give
+// byte-identical surefiles, even though the inodes and ctimes differ.  As
+// the clock is also frozen, the whole store files should match.
+
+use rsure::{node, parse_store, StoreTags, Version};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+use tempdir::TempDir;
+
+fn make_tree(root: &Path) {
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::create_dir_all(root.join("sub")).unwrap();
+    for (name, text) in &[("a", "alpha\n"), ("b", "beta\n"), ("sub/c", "gamma\n")] {
+        let mut fd = File::create(root.join(name)).unwrap();
+        fd.write_all(text.as_bytes()).unwrap();
+        fd.set_modified(mtime).unwrap();
+    }
+    // Writing the files moved the directories' mtimes, so pin them last.
+    for dir in &[root.join("sub"), root.to_path_buf()] {
+        File::open(dir).unwrap().set_modified(mtime).unwrap();
+    }
+}
+
+fn scan(tmp: &Path, name: &str) -> Vec<u8> {
+    let tree = tmp.join(name);
+    make_tree(&tree);
+    let store = parse_store(tmp.join(format!("{}.dat.gz", name)).to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "fixed".into());
+    rsure::update(&tree, &*store, false, &tags, &[rsure::HashAlgorithm::Sha1]).unwrap();
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    buf
+}
+
+#[test]
+fn identical_scans() {
+    std::env::set_var("RSURE_DETERMINISTIC", "2020-01-01T00:00:00Z");
+    let tmp = TempDir::new("rsure").unwrap();
+
+    let one = scan(tmp.path(), "one");
+    let two = scan(tmp.path(), "two");
+    assert_eq!(one, two);
+    assert_eq!(
+        fs::read(tmp.path().join("one.dat.gz")).unwrap(),
+        fs::read(tmp.path().join("two.dat.gz")).unwrap()
+    );
+
+    let store = parse_store(tmp.path().join("one.dat.gz").to_str().unwrap()).unwrap();
+    let versions = store.get_versions().unwrap();
+    assert_eq!(versions[0].time.to_rfc3339(), "2020-01-01T00:00:00+00:00");
+
+    let text = String::from_utf8(one).unwrap();
+    assert!(text.contains(" ino 0 "));
+    assert!(text.contains(" ctime 0 "));
+    assert!(text.contains(" sha1 "));
+    // Any mtime recorded is a pinned one, or the scans would differ when
+    // they straddle a second.
+    assert_eq!(
+        text.matches(" mtime ").count(),
+        text.matches(" mtime 1600000000 ").count()
+    );
+}
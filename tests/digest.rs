@@ -0,0 +1,89 @@
+// The digest of the whole tree recorded with each version.
+
+use rsure::{parse_store, tree_digest, Store, StoreTags, Version, DIGEST_TAG};
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+use tempdir::TempDir;
+
+fn digest_of(store: &dyn Store, version: Version) -> String {
+    let info = store.get_version(&version).unwrap().unwrap();
+    let digest = info.tags[DIGEST_TAG].clone();
+    assert_eq!(
+        tree_digest(store.load_iter(version).unwrap()).unwrap(),
+        digest
+    );
+    digest
+}
+
+#[test]
+fn tree_digests() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    fs::write(tree.join("sub").join("b"), "b\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let first = digest_of(&*store, Version::Latest);
+    assert_eq!(first.len(), 64);
+
+    // Nothing changed, so the tree is the same.
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert_eq!(digest_of(&*store, Version::Latest), first);
+
+    // A change of only metadata changes it.
+    let mut perms = fs::metadata(tree.join("a")).unwrap().permissions();
+    std::os::unix::fs::PermissionsExt::set_mode(&mut perms, 0o600);
+    fs::set_permissions(tree.join("a"), perms).unwrap();
+    tags.insert("name".into(), "third".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    let third = digest_of(&*store, Version::Latest);
+    assert_ne!(third, first);
+    assert_eq!(digest_of(&*store, Version::Named("first".into())), first);
+
+    // A copy of the tree, as on another machine, or restored from a backup,
+    // has other inodes and change times, but the same digest.
+    let copy = tmp.path().join("copy");
+    copy_tree(&tree, &copy);
+    let other = parse_store(tmp.path().join("other.dat.gz").to_str().unwrap()).unwrap();
+    rsure::update(&copy, &*other, false, &tags, &[]).unwrap();
+    let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+    assert_ne!(inode(&copy.join("a")), inode(&tree.join("a")));
+    assert_eq!(digest_of(&*other, Version::Latest), third);
+}
+
+/// Copy the files and directories under `from`, with their modes and
+/// modification times.
+fn copy_tree(from: &Path, to: &Path) {
+    fs::create_dir(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let dest = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_tree(&entry.path(), &dest);
+        } else {
+            fs::copy(entry.path(), &dest).unwrap();
+        }
+    }
+    let meta = fs::metadata(from).unwrap();
+    fs::set_permissions(to, meta.permissions()).unwrap();
+    fs::File::open(to)
+        .unwrap()
+        .set_modified(meta.modified().unwrap())
+        .unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if !entry.file_type().unwrap().is_dir() {
+            let mtime = entry.metadata().unwrap().modified().unwrap();
+            fs::File::options()
+                .write(true)
+                .open(to.join(entry.file_name()))
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Stores encrypted with age.
+
+#![cfg(feature = "encryption")]
+
+use rsure::{parse_store, AgeCipher, Store, StoreTags, Version};
+use std::{fs, sync::Arc};
+use tempdir::TempDir;
+
+const IDENTITY: &str = "\
+# A key for these tests only.
+AGE-SECRET-KEY-1V7RFX64SYPX0S60FX3F5XX5WM6GY0UDXXJDMD989S3PY3L64DAYQ83TDGP
+";
+
+const OTHER: &str = "AGE-SECRET-KEY-1QTLNUKYZ49DM0STWWZH434WZZYKTA23G6PC9U89D5JTCTHPLCG3SAQN3HS";
+
+fn open(dir: &str, identity: &str) -> Box<dyn Store> {
+    let mut store = parse_store(dir).unwrap();
+    store
+        .set_cipher(Arc::new(AgeCipher::parse(identity).unwrap()))
+        .unwrap();
+    store
+}
+
+fn file_names(store: &dyn Store, version: Version) -> Vec<String> {
+    store
+        .load_iter(version)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .filter(|n| n.is_file())
+        .map(|n| n.get_name().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn encrypted_store() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    let sure = tmp.path().join("sure");
+    fs::create_dir(&tree).unwrap();
+    fs::create_dir(&sure).unwrap();
+    let sure = sure.to_str().unwrap();
+    fs::write(tree.join("payroll.xlsx"), "data\n").unwrap();
+
+    let store = open(sure, IDENTITY);
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    fs::write(tree.join("passwords.kdbx"), "data\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+
+    assert_eq!(
+        file_names(&*store, Version::Latest),
+        ["passwords.kdbx", "payroll.xlsx"]
+    );
+    assert_eq!(file_names(&*store, Version::Prior), ["payroll.xlsx"]);
+
+    // Only the store and its backup are left, and neither gives away the names.
+    let mut names: Vec<_> = fs::read_dir(sure)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["2sure.bak.gz", "2sure.dat.gz"]);
+    for name in &names {
+        let data = fs::read(tmp.path().join("sure").join(name)).unwrap();
+        assert!(data.starts_with(b"age-encryption.org/v1\n"));
+    }
+
+    // Another identity, or none, can't read it.
+    assert!(open(sure, OTHER).get_versions().is_err());
+    assert!(parse_store(sure).unwrap().get_versions().is_err());
+}
+
+#[test]
+fn identities() {
+    assert!(AgeCipher::parse("").is_err());
+    assert!(AgeCipher::parse("# nothing here\n").is_err());
+    assert!(AgeCipher::parse("AGE-SECRET-KEY-1XYZ").is_err());
+
+    // Either of two identities reads the store.
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    let sure = tmp.path().to_str().unwrap();
+    let both = format!("{}{}\n", IDENTITY, OTHER);
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*open(sure, &both), false, &tags, &[]).unwrap();
+    assert_eq!(open(sure, OTHER).get_versions().unwrap().len(), 1);
+    assert_eq!(open(sure, IDENTITY).get_versions().unwrap().len(), 1);
+
+    // Blocked stores are read by seeking, so can't be encrypted.
+    let mut blocked = open(sure, IDENTITY);
+    blocked.set_blocked();
+    assert!(rsure::update(&tree, &*blocked, true, &tags, &[]).is_err());
+}
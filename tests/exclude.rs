@@ -0,0 +1,170 @@
+// Exclude patterns, and the .rsureignore file.
+
+use rsure::{
+    fs::scan_fs_with, parse_store, ChangeAction, Exclude, ScanOptions, StoreTags, SureNode,
+    Tombstones, UpdateHooks, Version,
+};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn patterns() {
+    let mut ex = Exclude::new();
+    for glob in &[
+        "*.tmp",
+        "cache/",
+        "var/spool",
+        "/top",
+        "**/logs/*.log",
+        "x[0-9]?",
+    ] {
+        ex.add(glob).unwrap();
+    }
+    let check = |path: &str, is_dir: bool| ex.is_excluded(Path::new(path), is_dir);
+
+    assert!(check("a.tmp", false));
+    assert!(check("deep/dir/a.tmp", false));
+    assert!(!check("a.tmpx", false));
+    assert!(check("home/cache", true));
+    assert!(!check("home/cache", false));
+    assert!(check("var/spool", true));
+    assert!(!check("other/var/spool", true));
+    assert!(check("top", false));
+    assert!(!check("sub/top", false));
+    assert!(check("logs/a.log", false));
+    assert!(check("srv/app/logs/b.log", false));
+    assert!(!check("srv/app/logs/old/b.log", false));
+    assert!(check("x1a", false));
+    assert!(!check("xa1", false));
+
+    assert!(Exclude::new().add("[abc").is_err());
+    assert!(Exclude::new().add("/").is_err());
+}
+
+#[test]
+fn scan_excludes() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let root = tmp.path();
+    for dir in &["keep", "cache", "keep/cache", "spool"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in &["a", "b.tmp", "keep/c", "keep/cache/d", "cache/e", "spool/f"] {
+        fs::write(root.join(file), "data\n").unwrap();
+    }
+    fs::write(
+        root.join(".rsureignore"),
+        "# Scratch files.\n*.tmp\n\ncache/\n",
+    )
+    .unwrap();
+
+    let mut options = ScanOptions::default();
+    options.exclude.add("/spool").unwrap();
+
+    let mut names = vec![];
+    let mut path = vec![];
+    for node in scan_fs_with(root, &options).unwrap() {
+        match node.unwrap() {
+            SureNode::Enter { name, .. } => path.push(name),
+            SureNode::Leave => {
+                path.pop();
+            }
+            SureNode::File { name, .. } => {
+                let mut full = path[1..].to_vec();
+                full.push(name);
+                names.push(full.join("/"));
+            }
+            SureNode::Sep => (),
+        }
+    }
+    // Subdirectories come before the files of a directory.
+    assert_eq!(names, ["keep/c", ".rsureignore", "a"]);
+}
+
+#[test]
+fn builtin_profiles() {
+    let names: Vec<_> = Exclude::profile_names().collect();
+    assert!(names.contains(&"linux-host"));
+
+    let mut ex = Exclude::new();
+    ex.add_profile("linux-host").unwrap();
+    for dir in &["proc", "sys", "run", "tmp", "var/tmp"] {
+        assert!(ex.is_excluded(Path::new(dir), true), "{}", dir);
+    }
+    for file in &[
+        "home/u/Cargo.lock",
+        "home/u/.x.swp",
+        "etc/fstab~",
+        "home/u/#draft#",
+    ] {
+        assert!(ex.is_excluded(Path::new(file), false), "{}", file);
+    }
+    assert!(!ex.is_excluded(Path::new("etc/passwd"), false));
+    assert!(!ex.is_excluded(Path::new("home/u/tmp"), true));
+
+    assert!(Exclude::new().add_profile("no-such").is_err());
+}
+
+#[test]
+fn excluded_not_deleted() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("build/obj")).unwrap();
+    fs::write(tree.join("build/obj/a.o"), "a\n").unwrap();
+    fs::write(tree.join("keep"), "keep\n").unwrap();
+    fs::write(tree.join("gone"), "gone\n").unwrap();
+    fs::write(tree.join("notes.tmp"), "notes\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let first = store.get_version(&Version::Latest).unwrap().unwrap();
+    assert!(Tombstones::from_tags(&first.tags).unwrap().is_empty());
+
+    // The exclude patterns change, and a file really is deleted.
+    fs::remove_file(tree.join("gone")).unwrap();
+    let mut exclude = Exclude::new();
+    exclude.add("build/").unwrap();
+    exclude.add("*.tmp").unwrap();
+    let hooks = UpdateHooks {
+        scan: ScanOptions {
+            exclude,
+            ..ScanOptions::default()
+        },
+        ..UpdateHooks::default()
+    };
+    tags.insert("name".into(), "second".into());
+    rsure::update_with(&tree, &*store, true, &tags, &[], hooks).unwrap();
+
+    let second = store.get_version(&Version::Latest).unwrap().unwrap();
+    let excluded = Tombstones::from_tags(&second.tags).unwrap();
+    let stones: Vec<_> = excluded.iter().collect();
+    assert_eq!(stones, [("build", "build/"), ("notes.tmp", "*.tmp")]);
+
+    let mut changes = vec![];
+    rsure::compare_trees_with(
+        store.load_iter(Version::Prior).unwrap(),
+        store.load_iter(Version::Latest).unwrap(),
+        Path::new("."),
+        &[],
+        &excluded,
+        |c| changes.push(c),
+    )
+    .unwrap();
+    let actions: Vec<_> = changes
+        .iter()
+        .map(|c| (c.path.to_str().unwrap(), c.action, c.rule.as_deref()))
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("./build", ChangeAction::Excluded, Some("build/")),
+            ("./gone", ChangeAction::Removed, None),
+            ("./notes.tmp", ChangeAction::Excluded, Some("*.tmp")),
+        ]
+    );
+    assert_eq!(
+        changes[0].to_string(),
+        "x dir                    \"./build\" (build/)"
+    );
+}
@@ -0,0 +1,183 @@
+// Exporting versions for other tools.
+
+use data_encoding::HEXLOWER;
+use openssl::sha::sha256;
+use rsure::{
+    export::{export, export_changed, ChangedOptions, ExportFormat},
+    parse_store, Error, HashAlgorithm, StoreTags, Version,
+};
+use std::{fs, os::unix::fs::symlink, process::Command};
+use tempdir::TempDir;
+
+#[test]
+fn mtree() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub dir")).unwrap();
+    fs::write(tree.join("sub dir").join("a#b"), "hi\n").unwrap();
+    fs::write(tree.join("top"), "x\n").unwrap();
+    symlink("sub dir/a#b", tree.join("lnk")).unwrap();
+
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let algorithms = [HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+    rsure::update(&tree, &*store, false, &tags, &algorithms).unwrap();
+
+    let mut out = vec![];
+    export(&*store, &Version::Latest, ExportFormat::Mtree, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.split(' ').filter(|w| !w.is_empty()).collect::<Vec<_>>())
+        .collect();
+    assert!(text.starts_with("#mtree\n"));
+    assert!(text.contains("#\tversion: 1 (first)\n"));
+
+    // Entered, and left, by name.
+    let names: Vec<_> = lines.iter().map(|l| l[0]).collect();
+    assert_eq!(names, [".", "sub\\040dir", "a\\043b", "..", "lnk", "top"]);
+    assert!(lines[0].contains(&"type=dir"));
+    assert!(lines[2].contains(&"type=file"));
+    assert!(lines[2].contains(&"size=3"));
+    assert!(lines[2].contains(
+        &"sha256digest=98ea6e4f216f2fb4b69fff9b3a44842c38686ca685f3f55dc48c5d3fb1107be4"
+    ));
+    assert!(!lines[2].iter().any(|w| w.contains("blake3")));
+    assert!(lines[4].contains(&"type=link"));
+    assert!(lines[4].contains(&"link=sub\\040dir/a\\043b"));
+    let mode = lines[5].iter().find(|w| w.starts_with("mode=")).unwrap();
+    assert_eq!(mode.len(), "mode=0644".len());
+
+    assert!(export(
+        &*store,
+        &Version::Tagged("7".into()),
+        ExportFormat::Mtree,
+        vec![]
+    )
+    .is_err());
+    assert!("tar".parse::<ExportFormat>().is_err());
+}
+
+#[test]
+fn sha256sum() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub dir")).unwrap();
+    fs::write(tree.join("sub dir").join("back\\slash"), "hi\n").unwrap();
+    fs::write(tree.join("new\nline"), "x\n").unwrap();
+    fs::write(tree.join("top"), "top\n").unwrap();
+    symlink("top", tree.join("lnk")).unwrap();
+
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha256]).unwrap();
+
+    let manifest = tmp.path().join("SHA256SUMS");
+    let out = fs::File::create(&manifest).unwrap();
+    export(&*store, &Version::Latest, ExportFormat::Sha256sum, out).unwrap();
+    let text = fs::read_to_string(&manifest).unwrap();
+    assert_eq!(text.lines().count(), 3);
+    assert!(text.contains("\\98ea6e4f216f2fb4b69fff9b3a44842c38686ca685f3f55dc48c5d3fb1107be4  sub dir/back\\\\slash\n"));
+    assert!(text.contains("  new\\nline\n"));
+    assert!(!text.contains("lnk"));
+
+    // Which coreutils agrees with, when it is there to ask.
+    if let Ok(status) = Command::new("sha256sum")
+        .arg("--quiet")
+        .arg("-c")
+        .arg(&manifest)
+        .current_dir(&tree)
+        .status()
+    {
+        assert!(status.success());
+    }
+
+    // Sha1 wasn't asked for.
+    match export(&*store, &Version::Latest, ExportFormat::Sha1sum, vec![]) {
+        Err(rsure::Error::NotHashed(name, alg)) => {
+            assert_eq!(name, "first");
+            assert_eq!(alg, "sha1");
+        }
+        other => panic!("Unexpected: {:?}", other.err()),
+    }
+    assert_eq!(
+        "sha1sum".parse::<ExportFormat>().unwrap(),
+        ExportFormat::Sha1sum
+    );
+}
+
+#[test]
+fn changed() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("sub").join("same"), "same\n").unwrap();
+    fs::write(tree.join("gone"), "gone\n").unwrap();
+
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let algorithms = [HashAlgorithm::Sha256];
+    rsure::update(&tree, &*store, false, &tags, &algorithms).unwrap();
+
+    fs::write(tree.join("sub").join("a"), "changed\n").unwrap();
+    fs::remove_file(tree.join("gone")).unwrap();
+    fs::create_dir_all(tree.join("new").join("deeper")).unwrap();
+    fs::write(tree.join("new").join("deeper").join("b"), "b\n").unwrap();
+    fs::write(tree.join("new").join("c\nd"), "c\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &algorithms).unwrap();
+
+    let first: Version = "name:first".parse().unwrap();
+    let list = |options: &ChangedOptions| {
+        let mut out = vec![];
+        export_changed(&*store, &first, &Version::Latest, options, &mut out)
+            .map(|count| (count, String::from_utf8(out).unwrap()))
+    };
+
+    let options = ChangedOptions {
+        null: true,
+        ..ChangedOptions::default()
+    };
+    let (count, text) = list(&options).unwrap();
+    assert_eq!(count, 5);
+    assert_eq!(
+        text.split_terminator('\0').collect::<Vec<_>>(),
+        ["new", "new/deeper", "new/deeper/b", "new/c\nd", "sub/a"]
+    );
+
+    // A newline in a name can't be listed one per line.
+    match list(&ChangedOptions::default()) {
+        Err(Error::NewlineInPath(path)) => assert_eq!(path, "new/c\nd"),
+        other => panic!("Unexpected: {:?}", other),
+    }
+    fs::remove_file(tree.join("new").join("c\nd")).unwrap();
+    rsure::update(&tree, &*store, true, &tags, &algorithms).unwrap();
+    let (_, text) = list(&ChangedOptions::default()).unwrap();
+    assert_eq!(text, "new\nnew/deeper\nnew/deeper/b\nsub/a\n");
+
+    // With hashes, only the files, as sha256sum writes them.
+    let options = ChangedOptions {
+        hashes: Some(HashAlgorithm::Sha256),
+        ..ChangedOptions::default()
+    };
+    let (count, text) = list(&options).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(
+        text,
+        format!(
+            "{}  new/deeper/b\n{}  sub/a\n",
+            HEXLOWER.encode(&sha256(b"b\n")),
+            HEXLOWER.encode(&sha256(b"changed\n"))
+        )
+    );
+    let options = ChangedOptions {
+        hashes: Some(HashAlgorithm::Sha1),
+        ..ChangedOptions::default()
+    };
+    assert!(list(&options).is_err());
+}
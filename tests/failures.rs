@@ -0,0 +1,87 @@
+// Recording the paths an update couldn't read.
+
+use rsure::{
+    check, inject_failure, parse_store, CheckOptions, Failures, StoreTags, Version,
+    FAILURES_OMITTED_TAG, FAILURES_TAG, MAX_FAILURES,
+};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn failure_tags() {
+    let failures = Failures::new();
+    let mut tags = StoreTags::new();
+    failures.add_to_tags(&mut tags);
+    assert!(!tags.contains_key(FAILURES_TAG));
+
+    failures.add(Path::new("/tree/b"), "Permission denied".into());
+    failures
+        .clone()
+        .add(Path::new("/tree/a"), "No such file".into());
+    failures.add_to_tags(&mut tags);
+    let read = Failures::from_tags(&tags).unwrap();
+    assert_eq!(
+        read.list(),
+        [
+            ("/tree/a".to_string(), "No such file".to_string()),
+            ("/tree/b".to_string(), "Permission denied".to_string()),
+        ]
+    );
+    assert!(!tags.contains_key(FAILURES_OMITTED_TAG));
+    assert!(Failures::from_tags(&StoreTags::new()).unwrap().is_empty());
+}
+
+#[test]
+fn failures_capped() {
+    let failures = Failures::new();
+    for i in 0..MAX_FAILURES + 5 {
+        failures.add(Path::new(&format!("/tree/{:05}", i)), "Gone".into());
+    }
+    // A path already recorded is only updated.
+    failures.add(Path::new("/tree/00000"), "Still gone".into());
+    assert_eq!(failures.len(), MAX_FAILURES + 5);
+    assert_eq!(failures.omitted(), 5);
+
+    let mut tags = StoreTags::new();
+    failures.add_to_tags(&mut tags);
+    assert_eq!(tags[FAILURES_OMITTED_TAG], "5");
+    let read = Failures::from_tags(&tags).unwrap();
+    assert_eq!(read.len(), MAX_FAILURES + 5);
+    let list = read.list();
+    assert_eq!(list.len(), MAX_FAILURES);
+    assert_eq!(list[0].1, "Still gone");
+}
+
+#[test]
+fn unreadable_paths() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("closed")).unwrap();
+    fs::write(tree.join("closed").join("x"), "x\n").unwrap();
+    fs::write(tree.join("open"), "open\n").unwrap();
+    fs::write(tree.join("secret"), "secret\n").unwrap();
+    inject_failure(&tree.join("secret"));
+    inject_failure(&tree.join("closed"));
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    let version = store.get_version(&Version::Latest).unwrap().unwrap();
+    let failures: Vec<_> = Failures::from_tags(&version.tags)
+        .unwrap()
+        .list()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let expected = [tree.join("closed"), tree.join("secret")];
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(failures, expected);
+
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert_eq!(report.failures.len(), 2);
+}
@@ -0,0 +1,95 @@
+// Recording file flags, such as immutable and append only.
+
+#![cfg(target_os = "linux")]
+
+use rsure::{check, parse_store, CheckOptions, ScanOptions, StoreTags, UpdateHooks, Version};
+use std::{fs::File, os::unix::io::AsRawFd, path::Path};
+use tempdir::TempDir;
+
+const NODUMP: libc::c_int = 0x40;
+
+/// Set or clear the nodump flag, which the owner of a file can, returning
+/// false if the filesystem has no flags.
+fn set_nodump(path: &Path, on: bool) -> bool {
+    let file = File::open(path).unwrap();
+    let fd = file.as_raw_fd();
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return false;
+    }
+    if on {
+        flags |= NODUMP;
+    } else {
+        flags &= !NODUMP;
+    }
+    unsafe { libc::ioctl(fd, libc::FS_IOC_SETFLAGS, &flags) == 0 }
+}
+
+fn with_flags() -> UpdateHooks {
+    UpdateHooks {
+        scan: ScanOptions {
+            flags: true,
+            ..ScanOptions::default()
+        },
+        ..UpdateHooks::default()
+    }
+}
+
+#[test]
+fn flags() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir_all(tree.join("dir")).unwrap();
+    std::fs::write(tree.join("dir").join("file"), "file\n").unwrap();
+    if !set_nodump(&tree.join("dir").join("file"), true) {
+        return;
+    }
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update_with(&tree, &*store, false, &tags, &[], with_flags()).unwrap();
+    let flags: Vec<_> = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|node| node.unwrap())
+        .filter(|node| node.is_enter() || node.is_file())
+        .map(|node| {
+            let name = node.name().to_string();
+            (name, node.atts().unwrap().get("flags").cloned())
+        })
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            ("__root__".to_string(), None),
+            ("dir".to_string(), None),
+            ("file".to_string(), Some("nodump".to_string())),
+        ]
+    );
+
+    // Flags set and cleared are changes.
+    assert!(set_nodump(&tree.join("dir"), true));
+    assert!(set_nodump(&tree.join("dir").join("file"), false));
+    let options = CheckOptions {
+        hooks: with_flags(),
+        ..CheckOptions::default()
+    };
+    let report = check(&tree, &*store, Version::Latest, options).unwrap();
+    let changes: Vec<_> = report
+        .changes
+        .iter()
+        .map(|c| (c.path.clone(), c.attrs_changed.clone()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (tree.join("dir"), vec!["flags".to_string()]),
+            (tree.join("dir").join("file"), vec!["flags".to_string()]),
+        ]
+    );
+
+    // A scan that didn't look for flags doesn't find them gone.
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    assert!(report.changes.is_empty());
+}
@@ -0,0 +1,80 @@
+// Gathering run summaries from many hosts.
+
+use chrono::{Duration, TimeZone, Utc};
+use rsure::{
+    fleet::{parse_age, Fleet, Problem, RunSummary, Thresholds},
+    ChangeSummary,
+};
+use tempdir::TempDir;
+
+fn run(host: &str, command: &str, hours: i64, changed: usize) -> RunSummary {
+    let mut run = RunSummary::new("2sure.dat.gz", command);
+    run.host = host.to_string();
+    run.time = Utc.ymd(2024, 3, 1).and_hms(0, 0, 0) + Duration::hours(hours);
+    run.changes = Some(ChangeSummary {
+        modified: changed,
+        ..ChangeSummary::default()
+    });
+    run
+}
+
+#[test]
+fn report() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let fleet = Fleet::open(tmp.path().join("fleet.jsonl"));
+
+    let mut input = vec![];
+    run("good", "update", 0, 5).write(&mut input).unwrap();
+    run("good", "check", 40, 0).write(&mut input).unwrap();
+    run("changed", "check", 10, 0).write(&mut input).unwrap();
+    run("changed", "check", 40, 2).write(&mut input).unwrap();
+    run("drifted", "update", 40, 50).write(&mut input).unwrap();
+    run("old", "check", 1, 0).write(&mut input).unwrap();
+    let mut failed = run("failed", "update", 40, 0);
+    failed.changes = None;
+    failed.error = Some("Permission denied".to_string());
+    failed.write(&mut input).unwrap();
+    assert_eq!(fleet.ingest(&input[..]).unwrap(), 7);
+    assert_eq!(fleet.ingest(&input[..]).unwrap(), 0);
+    assert_eq!(fleet.summaries().unwrap().len(), 7);
+
+    let thresholds = Thresholds {
+        max_changes: Some(10),
+        stale: Some(parse_age("1d").unwrap()),
+    };
+    let now = Utc.ymd(2024, 3, 2).and_hms(18, 0, 0);
+    let report = fleet.report(&thresholds, now).unwrap();
+    let problems: Vec<_> = report
+        .iter()
+        .map(|s| {
+            let kinds: Vec<_> = s
+                .problems
+                .iter()
+                .map(|p| match p {
+                    Problem::Failed(_) => "failed",
+                    Problem::Drifted(_) => "drifted",
+                    Problem::Stale(_) => "stale",
+                })
+                .collect();
+            (s.host.as_str(), kinds)
+        })
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            ("changed", vec!["failed"]),
+            ("drifted", vec!["drifted"]),
+            ("failed", vec!["failed"]),
+            ("good", vec![]),
+            ("old", vec!["stale"]),
+        ]
+    );
+    assert_eq!(
+        report[2].problems[0].to_string(),
+        "update failed: Permission denied"
+    );
+
+    assert!(parse_age("2x").is_err());
+    assert!(parse_age("d").is_err());
+    assert_eq!(parse_age("36h").unwrap(), Duration::hours(36));
+}
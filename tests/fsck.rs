@@ -0,0 +1,37 @@
+// Checking the structure of a store's weave.
+
+use rsure::{parse_store, StoreTags};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn store_fsck() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    // Uncompressed, so that it can be damaged.
+    let path = tmp.path().join("2sure.dat");
+    let store = parse_store(path.to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert_eq!(store.fsck().unwrap(), []);
+
+    let text = fs::read_to_string(&path).unwrap();
+    // The last end of delta 2 is that of the insert of "b".
+    let end = text.rfind("\x01E 2\n").unwrap();
+    let damaged = format!("{}{}", &text[..end], &text[end + 5..]);
+    fs::write(&path, damaged).unwrap();
+    let problems: Vec<_> = store
+        .fsck()
+        .unwrap()
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    assert_eq!(problems, ["insert of delta 2 never ended"]);
+}
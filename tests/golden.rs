@@ -0,0 +1,154 @@
+// Golden-file compatibility tests.
+//
+// The files under `tests/data` are in the formats written by earlier
+// releases of rsure: a plain "asure-2.0" surefile, and a weave store from
+// the 0.8 era, when the weave was still named `2sure.weave.gz`.  Users keep
+// these files for years, so the current code must keep reading them, and
+// produce the same results.
+
+use flate2::read::GzDecoder;
+use rsure::{node, parse_store, Change, ChangeAction, ChangeSummary, SureNode, Version};
+use std::{fs::File, io::Read, path::Path};
+
+const PLAIN: &str = "tests/data/plain-v2/2sure.dat.gz";
+const WEAVE: &str = "tests/data/weave-0.8/2sure.weave.gz";
+
+/// Serialize a node stream back to the surefile text format.
+fn to_bytes<I: Iterator<Item = rsure::Result<SureNode>>>(nodes: I) -> Vec<u8> {
+    let mut buf = vec![];
+    node::save_to(&mut buf, nodes).unwrap();
+    buf
+}
+
+fn gunzip<P: AsRef<Path>>(name: P) -> Vec<u8> {
+    let mut buf = vec![];
+    GzDecoder::new(File::open(name).unwrap())
+        .read_to_end(&mut buf)
+        .unwrap();
+    buf
+}
+
+#[test]
+fn plain_v2_roundtrip() {
+    let nodes = node::load(PLAIN).unwrap();
+    assert_eq!(to_bytes(nodes), gunzip(PLAIN));
+}
+
+#[test]
+fn weave_versions() {
+    let store = parse_store(WEAVE).unwrap();
+    let versions = store.get_versions().unwrap();
+    let listing: Vec<_> = versions
+        .iter()
+        .map(|v| (v.version.numeric(), v.name.as_str(), v.time.to_rfc3339()))
+        .collect();
+    assert_eq!(
+        listing,
+        vec![
+            (
+                Some(2),
+                "2019-03-31T02:40:00+00:00",
+                "2019-03-31T02:40:00+00:00".to_string()
+            ),
+            (
+                Some(1),
+                "2019-03-01T09:30:00+00:00",
+                "2019-03-01T09:30:00+00:00".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn weave_matches_plain() {
+    // The first delta of the weave holds the same snapshot as the plain
+    // surefile.
+    let store = parse_store(WEAVE).unwrap();
+    let first = store.load_iter(Version::Tagged("1".into())).unwrap();
+    assert_eq!(to_bytes(first), gunzip(PLAIN));
+
+    let prior = store.load_iter(Version::Prior).unwrap();
+    assert_eq!(to_bytes(prior), gunzip(PLAIN));
+}
+
+#[test]
+fn weave_latest() {
+    let store = parse_store(WEAVE).unwrap();
+    let names: Vec<_> = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .filter(|n| n.is_file())
+        .map(|n| (n.name().to_string(), n.kind().to_string()))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("added".to_string(), "file".to_string()),
+            ("nested".to_string(), "file".to_string()),
+            ("hello.txt".to_string(), "file".to_string()),
+            ("spaced=20name".to_string(), "file".to_string()),
+        ]
+    );
+}
+
+/// Compare two trees, collecting the changes, which must agree with the
+/// summary returned.
+fn compare<IA, IB>(old: IA, new: IB) -> Vec<Change>
+where
+    IA: Iterator<Item = rsure::Result<SureNode>>,
+    IB: Iterator<Item = rsure::Result<SureNode>>,
+{
+    let mut changes = vec![];
+    let summary = rsure::compare_trees(old, new, Path::new("/home/user/work"), &[], |c| {
+        changes.push(c)
+    })
+    .unwrap();
+    // The changes themselves don't give what was unchanged, or their sizes.
+    let mut counted = ChangeSummary {
+        unchanged: summary.unchanged,
+        bytes: summary.bytes,
+        ..ChangeSummary::default()
+    };
+    changes.iter().for_each(|c| counted.add(c));
+    assert_eq!(summary, counted);
+    assert_eq!(summary.count(), changes.len());
+    changes
+}
+
+#[test]
+fn weave_compare() {
+    let store = parse_store(WEAVE).unwrap();
+    let old = store.load_iter(Version::Prior).unwrap();
+    let new = store.load_iter(Version::Latest).unwrap();
+    let changes = compare(old, new);
+    let summary: Vec<_> = changes
+        .iter()
+        .map(|c| (c.action, c.kind.as_str(), c.path.to_str().unwrap()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (ChangeAction::Added, "file", "/home/user/work/sub/added"),
+            (ChangeAction::Modified, "file", "/home/user/work/hello.txt"),
+            (ChangeAction::Removed, "lnk", "/home/user/work/link"),
+        ]
+    );
+    assert_eq!(changes[1].attrs_changed, ["mtime", "sha1", "size"]);
+
+    // The textual report is unchanged from earlier releases.
+    let text: Vec<_> = changes.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        text,
+        [
+            "+ file                   \"/home/user/work/sub/added\"",
+            "  [mtime,sha1,size     ] \"/home/user/work/hello.txt\"",
+            "- lnk                    \"/home/user/work/link\"",
+        ]
+    );
+
+    // Comparing against the plain surefile should behave identically.
+    let old = node::load(PLAIN).unwrap();
+    let new = store.load_iter(Version::Latest).unwrap();
+    assert_eq!(compare(old, new), changes);
+}
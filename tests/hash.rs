@@ -0,0 +1,233 @@
+// Configurable hash algorithms.
+//
+// A snapshot made with a given algorithm should store hashes under that
+// algorithm's attribute, and record the algorithm in the version's tags.
+
+use rsure::{
+    node, parse_store, HashAlgorithm, HashReuse, ProgressEvent, StoreTags, UpdateHooks, Version,
+};
+use std::{
+    fs::File,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+use tempdir::TempDir;
+
+#[test]
+fn sha256_snapshot() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    File::create(tree.join("file"))
+        .unwrap()
+        .write_all(b"hello\n")
+        .unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.weave.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha256]).unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[HashAlgorithm::Sha256]).unwrap();
+
+    let latest = store.get_version(&Version::Latest).unwrap().unwrap();
+    assert_eq!(latest.name, "second");
+    assert_eq!(
+        HashAlgorithm::from_tags(&latest.tags).unwrap(),
+        [HashAlgorithm::Sha256]
+    );
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    assert!(
+        text.contains(" sha256 5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+    );
+    assert!(!text.contains(" sha1 "));
+}
+
+#[test]
+fn blake3_large_file() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+
+    // Large enough to be hashed across several threads, and not a
+    // multiple of the block size.
+    let data: Vec<u8> = (0..9 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+    File::create(tree.join("big"))
+        .unwrap()
+        .write_all(&data)
+        .unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "big".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Blake3]).unwrap();
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    let expect = format!("[blake3 {} ", blake3::hash(&data).to_hex());
+    assert!(text.contains(&expect));
+}
+
+#[test]
+fn mapped() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    let data: Vec<u8> = (0..5 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+    std::fs::write(tree.join("big"), &data).unwrap();
+    std::fs::write(tree.join("small"), "small\n").unwrap();
+    std::fs::write(tree.join("empty"), "").unwrap();
+
+    // Mapping files, read or not, gives the same hashes as reading them.
+    let algorithms = [HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "hashed".into());
+    let hashed = |name: &str, mmap, pipelined| {
+        let store = parse_store(tmp.path().join(name).to_str().unwrap()).unwrap();
+        let hooks = UpdateHooks {
+            mmap,
+            pipelined,
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, false, &tags, &algorithms, hooks).unwrap();
+        let mut buf = vec![];
+        node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    let read = hashed("read.dat.gz", None, false);
+    assert!(read.contains(&format!("[blake3 {} ", blake3::hash(&data).to_hex())));
+    assert_eq!(hashed("all.dat.gz", Some(0), false), read);
+    assert_eq!(hashed("big.dat.gz", Some(1024 * 1024), true), read);
+}
+
+#[test]
+fn uring() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    for i in 0..100 {
+        std::fs::write(tree.join(format!("small{:03}", i)), format!("{}\n", i)).unwrap();
+    }
+    std::fs::write(tree.join("empty"), "").unwrap();
+    let data: Vec<u8> = (0..300 * 1024 + 5).map(|i| (i % 251) as u8).collect();
+    std::fs::write(tree.join("large"), &data).unwrap();
+
+    // Reading in batches, where it can, gives the same hashes as reading
+    // each file in turn.
+    let algorithms = [HashAlgorithm::Sha1, HashAlgorithm::Blake3];
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "hashed".into());
+    let hashed = |name: &str, uring, pipelined| {
+        let store = parse_store(tmp.path().join(name).to_str().unwrap()).unwrap();
+        let hooks = UpdateHooks {
+            uring,
+            pipelined,
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, false, &tags, &algorithms, hooks).unwrap();
+        let mut buf = vec![];
+        node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    let read = hashed("read.dat.gz", false, false);
+    assert!(read.contains(&format!("[blake3 {} ", blake3::hash(&data).to_hex())));
+    assert_eq!(hashed("uring.dat.gz", true, false), read);
+    assert_eq!(hashed("piped.dat.gz", true, true), read);
+}
+
+#[test]
+fn multiple_digests() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    File::create(tree.join("file"))
+        .unwrap()
+        .write_all(b"hello\n")
+        .unwrap();
+
+    // An old snapshot with just sha1, then an update adding sha256.
+    let store = parse_store(tmp.path().join("2sure.weave.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "old".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha1]).unwrap();
+    tags.insert("name".into(), "both".into());
+    let both = [HashAlgorithm::Sha1, HashAlgorithm::Sha256];
+    rsure::update(&tree, &*store, true, &tags, &both).unwrap();
+
+    let latest = store.get_version(&Version::Latest).unwrap().unwrap();
+    assert_eq!(latest.tags["hash"], "sha1,sha256");
+    assert_eq!(HashAlgorithm::from_tags(&latest.tags).unwrap(), both);
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    assert!(text.contains(" sha1 f572d396fae9206628714fb2ce00f72e94f2258f "));
+    assert!(
+        text.contains(" sha256 5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03 ")
+    );
+}
+
+#[test]
+fn non_utf8_root() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let tmp = TempDir::new("rsure").unwrap();
+    // Not valid UTF-8, and with the escape character in it.
+    let tree = tmp.path().join(OsStr::from_bytes(b"tree=\xff"));
+    std::fs::create_dir(&tree).unwrap();
+    File::create(tree.join("file"))
+        .unwrap()
+        .write_all(b"hello\n")
+        .unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha1]).unwrap();
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    assert!(text.contains(" sha1 f572d396fae9206628714fb2ce00f72e94f2258f"));
+}
+
+#[test]
+fn reuse() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    std::fs::write(tree.join("a"), "a\n").unwrap();
+    std::fs::write(tree.join("b"), "bb\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    // The number of files each update hashes, with nothing changed.
+    let hashed = |reuse| {
+        let total = Arc::new(Mutex::new(0));
+        let record = total.clone();
+        let hooks = UpdateHooks {
+            hash_reuse: Some(reuse),
+            progress: Some(Arc::new(move |event: &ProgressEvent| {
+                if let ProgressEvent::Hash { total_files, .. } = *event {
+                    *record.lock().unwrap() = total_files;
+                }
+            })),
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, true, &tags, &[], hooks).unwrap();
+        let total = *total.lock().unwrap();
+        total
+    };
+    assert_eq!(hashed(HashReuse::Metadata), 0);
+    assert_eq!(hashed(HashReuse::Never), 2);
+
+    assert_eq!("never".parse::<HashReuse>().unwrap(), HashReuse::Never);
+    assert_eq!(HashReuse::Metadata.to_string(), "metadata");
+    assert!("sometimes".parse::<HashReuse>().is_err());
+}
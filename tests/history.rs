@@ -0,0 +1,61 @@
+// The history of a single file.
+
+use rsure::{
+    history::{path_history, verify_file},
+    parse_store, HashAlgorithm, StoreTags,
+};
+use std::{fs, os::unix::fs::PermissionsExt};
+use tempdir::TempDir;
+
+#[test]
+fn verify() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("bin")).unwrap();
+    let tool = tree.join("bin/tool");
+    fs::write(&tool, "good\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    let mut snapshot = |name: &str, algorithms: &[HashAlgorithm]| {
+        let is_update = !tags.is_empty();
+        tags.insert("name".into(), name.into());
+        rsure::update(&tree, &*store, is_update, &tags, algorithms).unwrap();
+    };
+    snapshot("first", &[]);
+    fs::remove_file(&tool).unwrap();
+    snapshot("missing", &[]);
+    fs::write(&tool, "good\n").unwrap();
+    snapshot("good", &[HashAlgorithm::Sha256]);
+    fs::write(&tool, "bad\n").unwrap();
+    snapshot("bad", &[]);
+
+    let history = path_history(&*store, "bin/tool").unwrap();
+    let present: Vec<_> = history.iter().map(|(_, a)| a.is_some()).collect();
+    assert_eq!(present, [true, true, false, true]);
+
+    // Put back the good content, with different permissions.
+    fs::write(&tool, "good\n").unwrap();
+    let check = || {
+        verify_file(&*store, &tree, &tool)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.version.name.clone(), m.content, m.attrs_changed.clone()))
+            .collect::<Vec<_>>()
+    };
+    let found = check();
+    let names: Vec<_> = found.iter().map(|f| f.0.as_str()).collect();
+    assert_eq!(names, ["bad", "good", "first"]);
+    assert!(!found[0].1);
+    assert!(found[1].1);
+    assert!(found[2].1);
+    assert!(!found[1].2.iter().any(|a| a == "perm"));
+
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o700)).unwrap();
+    assert!(check()[1].2.iter().any(|a| a == "perm"));
+
+    assert!(verify_file(&*store, &tree, &tmp.path().join("2sure.dat.gz")).is_err());
+    assert!(verify_file(&*store, &tree, &tree.join("bin/other"))
+        .unwrap()
+        .is_empty());
+}
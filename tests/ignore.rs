@@ -0,0 +1,70 @@
+// Attributes ignored when comparing, kept with the store.
+
+use rsure::{
+    ignore::{IgnoreRule, IgnoreRules},
+    parse_store, Error, StoreTags, Tombstones, Version,
+};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tempdir::TempDir;
+
+#[test]
+fn rules() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("var").join("log")).unwrap();
+    fs::write(tree.join("var").join("log").join("messages"), "log\n").unwrap();
+    fs::write(tree.join("var").join("state"), "state\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    let later = SystemTime::now() + Duration::from_secs(3600);
+    for name in &["var/log/messages", "var/state"] {
+        let file = fs::File::options()
+            .write(true)
+            .open(tree.join(name))
+            .unwrap();
+        file.set_modified(later).unwrap();
+    }
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+
+    assert!(IgnoreRules::load(&*store).unwrap().is_empty());
+    let mut rules = IgnoreRules::new();
+    assert!(rules.add(IgnoreRule::new("mtime", Some(Path::new("./var/log"))).unwrap()));
+    assert!(rules.add(IgnoreRule::new("atime", None).unwrap()));
+    assert!(!rules.add(IgnoreRule::new("mtime", Some(Path::new("var/log"))).unwrap()));
+    rules.save(&*store).unwrap();
+
+    let rules = IgnoreRules::load(&*store).unwrap();
+    let shown: Vec<_> = rules.rules().iter().map(|r| r.to_string()).collect();
+    assert_eq!(shown, ["mtime var/log", "atime"]);
+
+    let mut changes = vec![];
+    rsure::compare_trees_with_rules(
+        store.load_iter(Version::Prior).unwrap(),
+        store.load_iter(Version::Latest).unwrap(),
+        Path::new(""),
+        &rules,
+        &Tombstones::new(),
+        |c| changes.push(c.to_string()),
+    )
+    .unwrap();
+    assert_eq!(changes, ["  [mtime               ] \"var/state\""]);
+
+    let mut rules = rules;
+    assert!(rules.remove(&IgnoreRule::new("mtime", Some(Path::new("var/log"))).unwrap()));
+    assert!(!rules.remove(&IgnoreRule::new("mtime", None).unwrap()));
+
+    for (att, under) in &[("two words", None), ("mtime", Some("/var/log"))] {
+        match IgnoreRule::new(att, under.map(Path::new)) {
+            Err(Error::InvalidIgnore(_)) => (),
+            other => panic!("Unexpected: {:?}", other),
+        }
+    }
+}
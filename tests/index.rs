@@ -0,0 +1,76 @@
+// Looking up content hashes across every version, with and without the
+// index.
+
+use rsure::{
+    index::{build_index, seen_hash, INDEX_EXT},
+    parse_store, Error, HashAlgorithm, Store, StoreTags, Version,
+};
+use std::fs;
+use tempdir::TempDir;
+
+fn digest(store: &dyn Store, name: &str, algorithm: &str) -> String {
+    store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .find(|n| n.is_file() && n.name() == name)
+        .and_then(|n| n.atts().and_then(|a| a.get(algorithm).cloned()))
+        .unwrap()
+}
+
+#[test]
+fn seen() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("bin")).unwrap();
+    fs::write(tree.join("bin/evil"), "evil\n").unwrap();
+    fs::write(tree.join("good"), "good\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let evil = digest(&*store, "evil", "sha1");
+
+    fs::remove_file(tree.join("bin/evil")).unwrap();
+    tags.insert("name".into(), "second".into());
+    let algorithms = [HashAlgorithm::Sha256];
+    rsure::update(&tree, &*store, true, &tags, &algorithms).unwrap();
+    let good = digest(&*store, "good", "sha256");
+
+    // Without an index, every version is read.
+    let found = seen_hash(&*store, &evil).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].path, "bin/evil");
+    assert_eq!(found[0].algorithm, "sha1");
+    assert_eq!(found[0].version.name, "first");
+
+    assert_eq!(build_index(&*store).unwrap(), 3);
+    assert!(store.sidecar(INDEX_EXT).unwrap().is_file());
+    assert_eq!(seen_hash(&*store, &evil).unwrap().len(), 1);
+    let found = seen_hash(&*store, &good.to_uppercase()).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].version.name, "second");
+    let never = "0123456789abcdef0123456789abcdef01234567";
+    assert!(seen_hash(&*store, never).unwrap().is_empty());
+    assert!(matches!(
+        seen_hash(&*store, "xyz"),
+        Err(Error::InvalidDigest(_))
+    ));
+
+    // An update adds the hashes it writes to the index, which stays current,
+    // so what is only in the new version is found through it.
+    fs::write(tree.join("new"), "new\n").unwrap();
+    tags.insert("name".into(), "third".into());
+    rsure::update(&tree, &*store, true, &tags, &algorithms).unwrap();
+    let index = fs::read(store.sidecar(INDEX_EXT).unwrap()).unwrap();
+    let header = index.split(|&b| b == b'\n').next().unwrap();
+    let versions = String::from_utf8_lossy(header)
+        .split(' ')
+        .nth(4)
+        .unwrap()
+        .to_string();
+    assert_eq!(versions, "3");
+    let new = digest(&*store, "new", "sha256");
+    assert_eq!(seen_hash(&*store, &new).unwrap().len(), 1);
+}
@@ -0,0 +1,62 @@
+// Hardlink counts and groups.
+
+use rsure::{fs::scan_fs, ChangeAction, SureNode};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+fn scan(root: &Path) -> Vec<SureNode> {
+    scan_fs(root).unwrap().map(|n| n.unwrap()).collect()
+}
+
+fn file_atts<'a>(nodes: &'a [SureNode], name: &str) -> &'a rsure::AttMap {
+    nodes
+        .iter()
+        .find(|n| n.is_file() && n.name() == name)
+        .and_then(|n| n.atts())
+        .unwrap()
+}
+
+#[test]
+fn link_groups() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let root = tmp.path();
+    fs::create_dir(root.join("sub")).unwrap();
+    fs::write(root.join("a"), "data\n").unwrap();
+    fs::hard_link(root.join("a"), root.join("b")).unwrap();
+    fs::hard_link(root.join("a"), root.join("sub/c")).unwrap();
+    fs::write(root.join("d"), "other\n").unwrap();
+
+    let before = scan(root);
+    assert_eq!(file_atts(&before, "a")["nlink"], "3");
+    assert!(!file_atts(&before, "a").contains_key("link"));
+    assert_eq!(file_atts(&before, "b")["link"], "a");
+    assert_eq!(file_atts(&before, "c")["link"], "a");
+    assert_eq!(file_atts(&before, "d")["nlink"], "1");
+    assert!(!file_atts(&before, "d").contains_key("link"));
+
+    // Break one link, and make another.
+    fs::remove_file(root.join("b")).unwrap();
+    fs::write(root.join("b"), "data\n").unwrap();
+    fs::remove_file(root.join("d")).unwrap();
+    fs::hard_link(root.join("a"), root.join("d")).unwrap();
+    let after = scan(root);
+
+    let mut changes = vec![];
+    rsure::compare_trees(
+        before.into_iter().map(Ok),
+        after.into_iter().map(Ok),
+        Path::new("."),
+        &[],
+        |c| changes.push(c),
+    )
+    .unwrap();
+    let files: Vec<_> = changes.iter().filter(|c| c.kind == "file").collect();
+    let paths: Vec<_> = files.iter().map(|c| c.path.to_str().unwrap()).collect();
+    assert_eq!(paths, ["./b", "./d"]);
+    assert!(files.iter().all(|c| c.action == ChangeAction::Modified));
+    // "a" and "sub/c" are still in a group of three.
+    assert_eq!(files[0].attrs_changed, ["link", "nlink"]);
+    for att in &["link", "nlink", "size"] {
+        assert!(files[1].attrs_changed.iter().any(|a| a == att), "{}", att);
+    }
+}
@@ -0,0 +1,51 @@
+// Updates of a store locking it against each other.
+
+use rsure::{parse_store, Error, StoreTags};
+use std::{fs, time::Duration};
+use tempdir::TempDir;
+
+#[test]
+fn store_lock() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    let path = tmp.path().join("2sure.dat.gz");
+    let mut store = parse_store(path.to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    // A second writer fails while the first is still writing.
+    tags.insert("name".into(), "second".into());
+    let writer = store.make_new(&tags).unwrap();
+    match rsure::update(&tree, &*store, true, &tags, &[]) {
+        Err(Error::Weave(weave::Error::Locked(lock))) => {
+            assert_eq!(lock, tmp.path().join("2sure.lock"))
+        }
+        Err(e) => panic!("Unexpected error: {:?}", e),
+        Ok(()) => panic!("Second update not locked out"),
+    }
+    drop(writer);
+
+    store.set_lock_wait(Duration::from_secs(5)).unwrap();
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert_eq!(store.get_versions().unwrap().len(), 2);
+
+    // A lock taken to read the latest version is given to the writer, rather than taken again.
+    let other = parse_store(path.to_str().unwrap()).unwrap();
+    let lock = store.lock().unwrap();
+    assert!(matches!(
+        rsure::update(&tree, &*other, true, &tags, &[]),
+        Err(Error::Weave(weave::Error::Locked(_)))
+    ));
+    tags.insert("name".into(), "third".into());
+    let writer = store.make_new_locked(&tags, lock).unwrap();
+    assert!(matches!(
+        other.make_new(&tags),
+        Err(Error::Weave(weave::Error::Locked(_)))
+    ));
+    drop(writer);
+    other.make_new(&tags).unwrap();
+}
@@ -0,0 +1,100 @@
+// Checking trees against checksum manifests.
+
+use rsure::{
+    export::{export, ExportFormat},
+    manifest::Manifest,
+    parse_store, ChangeAction, HashAlgorithm, StoreTags, Version,
+};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn check() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("sub").join("gone"), "gone\n").unwrap();
+    fs::write(tree.join("same"), "same\n").unwrap();
+    fs::write(tree.join("back\\slash"), "b\n").unwrap();
+
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let algorithms = [HashAlgorithm::Sha256];
+    rsure::update(&tree, &*store, false, &tags, &algorithms).unwrap();
+    let mut sums = vec![];
+    export(
+        &*store,
+        &Version::Latest,
+        ExportFormat::Sha256sum,
+        &mut sums,
+    )
+    .unwrap();
+    let manifest = Manifest::read(&sums[..], None).unwrap();
+    assert_eq!(manifest.algorithm(), HashAlgorithm::Sha256);
+    assert_eq!(manifest.len(), 4);
+
+    let changes = |version| {
+        let mut changes = vec![];
+        manifest
+            .check(store.load_iter(version).unwrap(), Path::new("top"), |c| {
+                changes.push((
+                    c.path.to_str().unwrap().to_string(),
+                    c.action,
+                    c.attrs_changed,
+                ))
+            })
+            .unwrap();
+        changes
+    };
+    assert!(changes(Version::Latest).is_empty());
+
+    fs::write(tree.join("sub").join("a"), "changed\n").unwrap();
+    fs::remove_file(tree.join("sub").join("gone")).unwrap();
+    fs::write(tree.join("new"), "new\n").unwrap();
+    rsure::update(&tree, &*store, true, &tags, &algorithms).unwrap();
+    assert_eq!(
+        changes(Version::Latest),
+        [
+            ("top/new".to_string(), ChangeAction::Added, vec![]),
+            (
+                "top/sub/a".to_string(),
+                ChangeAction::Modified,
+                vec!["sha256".to_string()]
+            ),
+            ("top/sub/gone".to_string(), ChangeAction::Removed, vec![]),
+        ]
+    );
+}
+
+#[test]
+fn forms() {
+    let sha1 = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+    let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let text = format!(
+        "{0}  ./a\n{0} *b c\n\\{0}  d\\\\e\\nf\nSHA1 (g) = h) = {0}\r\n\n",
+        sha1
+    );
+    let manifest = Manifest::read(text.as_bytes(), None).unwrap();
+    assert_eq!(manifest.algorithm(), HashAlgorithm::Sha1);
+    assert_eq!(manifest.len(), 4);
+
+    let tagged = format!("SHA256 (x) = {}\n", sha256.to_uppercase());
+    let manifest = Manifest::read(tagged.as_bytes(), None).unwrap();
+    assert_eq!(manifest.algorithm(), HashAlgorithm::Sha256);
+    let b3 = format!("{}  x\n", sha256);
+    let manifest = Manifest::read(b3.as_bytes(), Some(HashAlgorithm::Blake3)).unwrap();
+    assert_eq!(manifest.algorithm(), HashAlgorithm::Blake3);
+
+    for bad in &[
+        format!("{} x\n", sha1),
+        format!("{}  ../x\n", sha1),
+        format!("{}  x\n", &sha1[1..]),
+        format!("\\{}  x\\y\n", sha1),
+        format!("MD5 (x) = {}\n", sha1),
+        format!("{}  x\nSHA1 (y) = {}\n", sha256, sha1),
+    ] {
+        assert!(Manifest::read(bad.as_bytes(), None).is_err(), "{:?}", bad);
+    }
+}
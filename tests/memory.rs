@@ -0,0 +1,60 @@
+// Scanning under a memory limit.
+//
+// A scan with a tight limit uses smaller buffers and fewer hashing
+// threads, but must still produce the same hashes as an unlimited one.
+
+use rsure::{node, parse_store, HashAlgorithm, StoreTags, UpdateHooks, Version};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn limited_scan() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for i in 0..50 {
+        fs::write(tree.join(format!("file{}", i)), format!("file {}\n", i)).unwrap();
+    }
+    // Larger than the shrunken buffers.
+    let data: Vec<u8> = (0..5 * 1024 * 1024 + 3).map(|i| (i % 251) as u8).collect();
+    fs::write(tree.join("big"), &data).unwrap();
+
+    let algorithms = [HashAlgorithm::Sha1, HashAlgorithm::Blake3];
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "scan".into());
+
+    let full = parse_store(tmp.path().join("full.dat.gz").to_str().unwrap()).unwrap();
+    rsure::update(&tree, &*full, false, &tags, &algorithms).unwrap();
+
+    let small = parse_store(tmp.path().join("small.dat.gz").to_str().unwrap()).unwrap();
+    let hooks = UpdateHooks {
+        memory_limit: Some("1M".parse().unwrap()),
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*small, false, &tags, &algorithms, hooks).unwrap();
+
+    let hashes = |store: &dyn rsure::Store| {
+        let mut result = vec![];
+        for n in store.load_iter(Version::Latest).unwrap() {
+            let n = n.unwrap();
+            if let node::SureNode::File { name, atts } = n {
+                result.push((name, atts["sha1"].clone(), atts["blake3"].clone()));
+            }
+        }
+        result
+    };
+    let expect = hashes(&*full);
+    assert_eq!(expect.len(), 51);
+    assert_eq!(hashes(&*small), expect);
+
+    // As must one hashing a file at a time, or more than there are CPUs.
+    for (name, jobs) in &[("one.dat.gz", 1), ("many.dat.gz", 64)] {
+        let store = parse_store(tmp.path().join(name).to_str().unwrap()).unwrap();
+        let hooks = UpdateHooks {
+            jobs: Some(*jobs),
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, false, &tags, &algorithms, hooks).unwrap();
+        assert_eq!(hashes(&*store), expect);
+    }
+}
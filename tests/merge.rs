@@ -0,0 +1,59 @@
+// Pulling the merged nodes from a HashMerger.
+
+use openssl::sha::sha1;
+use rsure::{
+    fs::scan_fs, parse_store, Estimate, HashUpdater, NodeWriter, Result, Source, SureNode,
+};
+use std::fs;
+use tempdir::TempDir;
+
+struct Nodes(Vec<SureNode>);
+
+impl Source for Nodes {
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<SureNode>> + Send>> {
+        Ok(Box::new(self.0.clone().into_iter().map(Ok)))
+    }
+}
+
+#[test]
+fn pull_merge() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for i in 0..20 {
+        fs::write(tree.join(format!("file{:02}", i)), format!("{}\n", i)).unwrap();
+    }
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let nodes = Nodes(scan_fs(&tree).unwrap().map(|n| n.unwrap()).collect());
+    let estimate = Estimate {
+        files: 20,
+        bytes: 0,
+    };
+
+    // The nodes come out with their hashes, and can be filtered before they are written.
+    let merger = HashUpdater::new(nodes, &*store)
+        .compute_parallel(&tree, &estimate)
+        .unwrap();
+    let mut out = vec![];
+    let mut writer = NodeWriter::new(&mut out).unwrap();
+    let mut seen = 0;
+    for node in merger.iter().unwrap() {
+        let node = node.unwrap();
+        if let SureNode::File { name, atts } = &node {
+            let data = fs::read(tree.join(name)).unwrap();
+            assert_eq!(atts["sha1"], data_encoding::HEXLOWER.encode(&sha1(&data)));
+            seen += 1;
+            if name.ends_with('5') {
+                continue;
+            }
+        }
+        writer.write_node(&node).unwrap();
+    }
+    drop(writer);
+    assert_eq!(seen, 20);
+
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.contains("ffile04 ["));
+    assert!(!text.contains("ffile05 ["));
+    assert!(!text.contains("ffile15 ["));
+}
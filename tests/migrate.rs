@@ -0,0 +1,108 @@
+// Migrating plain surefiles to a weave store.
+
+use rsure::{import::migrate, node, parse_store, Error, SureNode, Version};
+use std::{
+    fs,
+    time::{Duration, SystemTime},
+};
+use tempdir::TempDir;
+
+const PLAIN: &str = "tests/data/plain-v2/2sure.dat.gz";
+
+/// The nodes, as the text of a surefile.
+fn all(nodes: impl Iterator<Item = rsure::Result<SureNode>>) -> Vec<u8> {
+    let mut buf = vec![];
+    node::save_to(&mut buf, nodes).unwrap();
+    buf
+}
+
+#[test]
+fn migrate_plain() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let old = tmp.path().join("old");
+    fs::create_dir(&old).unwrap();
+    let src = old.join("2sure.dat.gz");
+    fs::copy(PLAIN, &src).unwrap();
+
+    let dest = tmp.path().join("new");
+    fs::create_dir(&dest).unwrap();
+    let mut store = parse_store(dest.to_str().unwrap()).unwrap();
+    assert_eq!(migrate(&src, &mut *store).unwrap(), 1);
+    let versions = store.get_versions().unwrap();
+    assert_eq!(versions.len(), 1);
+    let mtime = fs::metadata(&src).unwrap().modified().unwrap();
+    assert_eq!(
+        versions[0].time,
+        chrono::DateTime::<chrono::Utc>::from(mtime)
+    );
+    assert_eq!(
+        all(store.load_iter(Version::Latest).unwrap()),
+        all(node::load(&src).unwrap())
+    );
+
+    // Once there are versions, migrating again is refused.
+    assert!(matches!(
+        migrate(&src, &mut *store),
+        Err(Error::StoreNotEmpty)
+    ));
+
+    // With a backup, that is the first version.
+    let backup = old.join("2sure.bak.gz");
+    fs::copy(PLAIN, &backup).unwrap();
+    let dest2 = tmp.path().join("new2");
+    fs::create_dir(&dest2).unwrap();
+    let mut store = parse_store(dest2.to_str().unwrap()).unwrap();
+    assert_eq!(migrate(&src, &mut *store).unwrap(), 2);
+    assert_eq!(store.get_versions().unwrap().len(), 2);
+    assert_eq!(
+        all(store.load_iter(Version::Prior).unwrap()),
+        all(node::load(&backup).unwrap())
+    );
+
+    // A store that is there, but can't be read, isn't taken to be empty.
+    let dest3 = tmp.path().join("new3");
+    fs::create_dir(&dest3).unwrap();
+    fs::write(dest3.join("2sure.dat.gz"), "not a weave\n").unwrap();
+    let mut store = parse_store(dest3.to_str().unwrap()).unwrap();
+    assert!(matches!(
+        migrate(&src, &mut *store),
+        Err(e) if !matches!(e, Error::StoreNotEmpty)
+    ));
+    assert_eq!(
+        fs::read(dest3.join("2sure.dat.gz")).unwrap(),
+        b"not a weave\n"
+    );
+}
+
+#[test]
+fn import_dir() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let src = tmp.path().join("history");
+    fs::create_dir(&src).unwrap();
+    // Named so that their names sort differently than their times.
+    let base = SystemTime::now() - Duration::from_secs(86400);
+    for (name, days) in &[("b-monday", 0), ("a-tuesday", 1), ("c-wednesday", 2)] {
+        let path = src.join(format!("{}.dat.gz", name));
+        fs::copy(PLAIN, &path).unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(base + Duration::from_secs(3600 * days))
+            .unwrap();
+    }
+    fs::write(src.join("notes.txt"), "not a surefile\n").unwrap();
+
+    let dest = tmp.path().join("store");
+    fs::create_dir(&dest).unwrap();
+    let mut store = parse_store(dest.to_str().unwrap()).unwrap();
+    assert_eq!(rsure::import::import_dir(&src, &mut *store).unwrap(), 3);
+    let names: Vec<_> = store
+        .get_versions()
+        .unwrap()
+        .into_iter()
+        .map(|v| v.name)
+        .collect();
+    assert_eq!(names, ["c-wednesday", "a-tuesday", "b-monday"]);
+    assert_eq!(
+        all(store.load_iter(Version::Named("a-tuesday".into())).unwrap()),
+        all(node::load(PLAIN).unwrap())
+    );
+}
@@ -0,0 +1,160 @@
+// Importing mtree specifications.
+
+use rsure::{
+    export::{export, ExportFormat},
+    import::{import_file, ImportFormat},
+    parse_store, HashAlgorithm, Store, StoreTags, SureNode, Tombstones, Version,
+};
+use std::{fs, os::unix::fs::symlink};
+use tempdir::TempDir;
+
+fn nodes(store: &dyn Store, version: Version) -> Vec<SureNode> {
+    store
+        .load_iter(version)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .collect()
+}
+
+#[test]
+fn round_trip() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub dir").join("deeper")).unwrap();
+    fs::write(tree.join("sub dir").join("a#b"), "hi\n").unwrap();
+    fs::write(tree.join("sub dir").join("deeper").join("c"), "c\n").unwrap();
+    fs::write(tree.join("top"), "x\n").unwrap();
+    symlink("sub dir/a#b", tree.join("lnk")).unwrap();
+
+    let store = parse_store(tmp.path().join("one").to_str().unwrap()).unwrap();
+    fs::create_dir(tmp.path().join("one")).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "scan".into());
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha256]).unwrap();
+    let spec = tmp.path().join("home.mtree");
+    let out = fs::File::create(&spec).unwrap();
+    export(&*store, &Version::Latest, ExportFormat::Mtree, out).unwrap();
+
+    let other = parse_store(tmp.path().join("two").to_str().unwrap()).unwrap();
+    fs::create_dir(tmp.path().join("two")).unwrap();
+    import_file(&*other, &spec, ImportFormat::Mtree, &StoreTags::new()).unwrap();
+    let version = other.get_version(&Version::Latest).unwrap().unwrap();
+    assert_eq!(version.name, "home");
+    assert_eq!(version.tags["hash"], "sha256");
+
+    // The same tree, less what mtree has no place for.
+    let scanned = nodes(&*store, Version::Latest);
+    let imported = nodes(&*other, Version::Latest);
+    assert_eq!(imported.len(), scanned.len());
+    for (s, i) in scanned.iter().zip(&imported) {
+        assert_eq!(s.get_name(), i.get_name());
+        if let (Some(s), Some(i)) = (s.atts(), i.atts()) {
+            let mut s = s.clone();
+            for att in &[
+                "ctime", "ino", "fstype", "blocks", "btime", "rollup", "recorded", "sparse",
+            ] {
+                s.remove(*att);
+            }
+            assert_eq!(&s, i);
+        }
+    }
+
+    let mut changes = 0;
+    rsure::compare_trees_with(
+        imported.into_iter().map(Ok),
+        scanned.into_iter().map(Ok),
+        &tree,
+        &[],
+        &Tombstones::default(),
+        |_| changes += 1,
+    )
+    .unwrap();
+    assert_eq!(changes, 0);
+}
+
+#[test]
+fn full_paths() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let spec = tmp.path().join("spec");
+    fs::write(
+        &spec,
+        "#mtree
+/set type=file uid=0 gid=0 mode=644
+. type=dir mode=755
+./etc type=dir
+./etc/passwd size=1024 time=1700000000.5 \\
+    sha1digest=DA39A3EE5E6B4B0D3255BFEF95601890AFD80709
+./var/log/messages uid=4 optional
+/unset uid
+./etc/rc\\040local type=link mode=777 link=/etc/rc\\134d
+./dev/sda type=block device=native,8,0
+",
+    )
+    .unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    import_file(&*store, &spec, ImportFormat::Mtree, &StoreTags::new()).unwrap();
+
+    let names: Vec<_> = nodes(&*store, Version::Latest)
+        .iter()
+        .map(|n| match n {
+            SureNode::Enter { name, .. } => format!("d{}", name),
+            SureNode::File { name, .. } => format!("f{}", name),
+            SureNode::Sep => "-".to_string(),
+            SureNode::Leave => "u".to_string(),
+        })
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "d__root__",
+            "ddev",
+            "-",
+            "fsda",
+            "u",
+            "detc",
+            "-",
+            "fpasswd",
+            "frc=20local",
+            "u",
+            "dvar",
+            "dlog",
+            "-",
+            "fmessages",
+            "u",
+            "-",
+            "u",
+            "-",
+            "u"
+        ]
+    );
+
+    let files: Vec<_> = nodes(&*store, Version::Latest)
+        .into_iter()
+        .filter_map(|n| match n {
+            SureNode::File { name, atts } => Some((name, atts)),
+            _ => None,
+        })
+        .collect();
+    let passwd = &files[1].1;
+    assert_eq!(passwd["perm"], "420");
+    assert_eq!(passwd["size"], "1024");
+    assert_eq!(passwd["mtime"], "1700000000");
+    assert_eq!(passwd["sha1"], "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    let rc = &files[2].1;
+    assert_eq!(rc["kind"], "lnk");
+    assert_eq!(rc["targ"], "/etc/rc\\d");
+    assert!(!rc.contains_key("uid"));
+    assert_eq!(files[3].1["uid"], "4");
+    assert_eq!(files[0].1["devmaj"], "8");
+
+    for bad in &[
+        "x type=widget\n",
+        "x mode=999\n",
+        "x sha1digest=abc\n",
+        "/bogus\n",
+        "x size=1 \\\n",
+    ] {
+        fs::write(&spec, bad).unwrap();
+        assert!(import_file(&*store, &spec, ImportFormat::Mtree, &StoreTags::new()).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+// Watching the nodes of a new version as they are written.
+
+use rsure::{
+    parse_store, Error, NodeObserver, NodeTee, NodeWriter, Result, StoreTags, SureNode, UpdateHooks,
+};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+use tempdir::TempDir;
+
+#[derive(Default)]
+struct Seen {
+    files: Vec<String>,
+    hashes: usize,
+    finished: bool,
+}
+
+struct Gather(Arc<Mutex<Seen>>);
+
+impl NodeObserver for Gather {
+    fn observe(&mut self, node: &SureNode) -> Result<()> {
+        if let SureNode::File { name, atts } = node {
+            let mut seen = self.0.lock().unwrap();
+            seen.files.push(name.clone());
+            if atts.contains_key("sha1") {
+                seen.hashes += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.0.lock().unwrap().finished = true;
+        Ok(())
+    }
+}
+
+#[test]
+fn observed_update() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+
+    for is_update in &[false, true] {
+        let seen = Arc::new(Mutex::new(Seen::default()));
+        let hooks = UpdateHooks {
+            observers: vec![Box::new(Gather(seen.clone()))],
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, *is_update, &tags, &[], hooks).unwrap();
+
+        // The observer saw the same nodes as were stored, hashes and all.
+        let seen = seen.lock().unwrap();
+        assert!(seen.finished);
+        assert_eq!(seen.files, ["a", "b"]);
+        assert_eq!(seen.hashes, 2);
+    }
+    assert_eq!(store.get_versions().unwrap().len(), 2);
+}
+
+#[test]
+fn tee() {
+    let nodes = vec![
+        SureNode::Enter {
+            name: "__root__".into(),
+            atts: Default::default(),
+        },
+        SureNode::Sep,
+        SureNode::File {
+            name: "x".into(),
+            atts: Default::default(),
+        },
+        SureNode::Leave,
+    ];
+
+    let mut count = 0;
+    let mut counter = |_: &SureNode| {
+        count += 1;
+        Ok(())
+    };
+    let mut tee = NodeTee::new(NodeWriter::new(vec![]).unwrap()).with_observer(&mut counter);
+    for node in &nodes {
+        tee.write_node(node).unwrap();
+    }
+    let out = tee.into_inner().unwrap();
+    assert_eq!(count, 4);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "asure-2.0\n-----\nd__root__ []\n-\nfx []\nu\n"
+    );
+
+    // An observer can refuse a node, which then isn't written.
+    let mut refuse = |node: &SureNode| match node {
+        SureNode::File { .. } => Err(Error::Cancelled),
+        _ => Ok(()),
+    };
+    let mut tee = NodeTee::new(NodeWriter::new(vec![]).unwrap()).with_observer(&mut refuse);
+    assert!(tee.write_node(&nodes[0]).is_ok());
+    assert!(tee.write_node(&nodes[2]).is_err());
+    let out = tee.into_inner().unwrap();
+    assert!(!String::from_utf8(out).unwrap().contains("fx"));
+}
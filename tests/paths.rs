@@ -0,0 +1,78 @@
+// Updating just the paths in a list.
+
+use rsure::{parse_store, Error, PathList, StoreTags, UpdateHooks, Version};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn listed_update() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("sub").join("same"), "same\n").unwrap();
+    fs::write(tree.join("gone"), "gone\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    fs::write(tree.join("sub").join("a"), "changed\n").unwrap();
+    fs::write(tree.join("sub").join("same"), "diff\n").unwrap();
+    fs::remove_file(tree.join("gone")).unwrap();
+    fs::create_dir_all(tree.join("new").join("deeper")).unwrap();
+    fs::write(tree.join("new").join("deeper").join("c"), "c\n").unwrap();
+    fs::write(tree.join("new").join("unlisted"), "u\n").unwrap();
+
+    // "same" changed, but isn't listed, so is carried forward as it was.
+    let list = "./sub/a\ngone\nnew/deeper/c\nmissing\n";
+    let paths = PathList::read(list.as_bytes()).unwrap();
+    assert_eq!(paths.len(), 4);
+    tags.insert("name".into(), "second".into());
+    let hooks = UpdateHooks {
+        paths: Some(paths),
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*store, true, &tags, &[], hooks).unwrap();
+
+    let mut changes = vec![];
+    rsure::compare_trees(
+        store.load_iter(Version::Prior).unwrap(),
+        store.load_iter(Version::Latest).unwrap(),
+        Path::new(""),
+        &[],
+        |c| changes.push(c.to_string()),
+    )
+    .unwrap();
+    assert_eq!(
+        changes,
+        [
+            "+ dir                    \"new\"",
+            "  [sha1,size           ] \"sub/a\"",
+            "- file                   \"gone\"",
+        ]
+    );
+
+    // A full scan agrees, but for what wasn't listed.
+    let full = parse_store(tmp.path().join("full.dat.gz").to_str().unwrap()).unwrap();
+    rsure::update(&tree, &*full, false, &tags, &[]).unwrap();
+    let mut changes = vec![];
+    rsure::compare_trees(
+        store.load_iter(Version::Latest).unwrap(),
+        full.load_iter(Version::Latest).unwrap(),
+        Path::new(""),
+        &["mtime"],
+        |c| changes.push(c.path.to_str().unwrap().to_string()),
+    )
+    .unwrap();
+    assert_eq!(changes, ["new/unlisted", "sub/same"]);
+
+    for bad in &["../a", "/etc/passwd"] {
+        match PathList::read(bad.as_bytes()) {
+            Err(Error::InvalidPathList(path)) => assert_eq!(&path, bad),
+            other => panic!("Unexpected: {:?}", other),
+        }
+    }
+    let nul = PathList::read(&b"a\nb\0c\0"[..]).unwrap();
+    assert_eq!(nul.len(), 2);
+}
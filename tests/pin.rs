@@ -0,0 +1,69 @@
+// Pinning a tree to a version of its store.
+
+use rsure::{
+    node::fs::scan_fs,
+    parse_store,
+    pin::{self, Pin, PIN_FILE},
+    Error, StoreTags, SureNode, Version,
+};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn pin() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    assert!(Pin::load(&tree).unwrap().is_none());
+
+    let first = store.get_version(&Version::Latest).unwrap().unwrap();
+    Pin::of(&first).save(&tree, &*store).unwrap();
+    assert!(!tree.join(format!("{}.tmp", PIN_FILE)).exists());
+    assert!(!tmp.path().join("2sure.pin.tmp").exists());
+    let pin = Pin::load(&tree).unwrap().unwrap();
+    assert_eq!(pin.name, "first");
+    assert_eq!(pin.time, first.time);
+    assert_eq!(
+        pin.resolve(&*store).unwrap().to_string(),
+        first.version.to_string()
+    );
+
+    // The pin isn't part of the tree.
+    let names: Vec<_> = scan_fs(&tree)
+        .unwrap()
+        .filter_map(|n| match n.unwrap() {
+            SureNode::File { name, .. } => Some(name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, ["a"]);
+
+    // A pin of a version that was since replaced by another of the same number.
+    let other = tmp.path().join("other.dat.gz");
+    let store = parse_store(other.to_str().unwrap()).unwrap();
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    match pin.resolve(&*store) {
+        Err(Error::StalePin(version, name)) => {
+            assert_eq!(version, first.version.to_string());
+            assert_eq!(name, "first");
+        }
+        other => panic!("Unexpected: {:?}", other),
+    }
+
+    fs::write(tree.join(PIN_FILE), "version 1\nname x\n").unwrap();
+    assert!(Pin::load(&tree).is_err());
+    fs::write(tree.join(PIN_FILE), "version 1\nbogus 2\n").unwrap();
+    assert!(Pin::load(&tree).is_err());
+
+    for (text, number) in &[("v42", "42"), ("42", "42"), ("vintage", "vintage")] {
+        match pin::parse_version(text).unwrap() {
+            Version::Tagged(n) => assert_eq!(&n, number),
+            other => panic!("Unexpected: {:?}", other),
+        }
+    }
+}
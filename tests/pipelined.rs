@@ -0,0 +1,70 @@
+// Pipelined updates.
+//
+// Hashing files while the scan is still going must give the same snapshot
+// as hashing them afterwards, both for a fresh scan and an update.
+
+use rsure::{parse_store, HashAlgorithm, Store, StoreTags, UpdateHooks, Version};
+use std::fs;
+use tempdir::TempDir;
+
+type Node = (String, Option<String>, Option<rsure::AttMap>);
+
+/// The latest version, as the kind, name and attributes of each node.
+fn latest(store: &dyn Store) -> Vec<Node> {
+    store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|n| {
+            let n = n.unwrap();
+            let name = n.get_name().map(|s| s.to_string());
+            (n.kind().to_string(), name, n.atts().cloned())
+        })
+        .collect()
+}
+
+#[test]
+fn same_as_normal() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    for dir in &["a", "a/b", "c"] {
+        fs::create_dir_all(tree.join(dir)).unwrap();
+        for i in 0..20 {
+            fs::write(
+                tree.join(dir).join(format!("f{}", i)),
+                format!("{} {}\n", dir, i),
+            )
+            .unwrap();
+        }
+    }
+
+    let algorithms = [HashAlgorithm::Sha1, HashAlgorithm::Sha256];
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let normal = parse_store(tmp.path().join("normal.dat.gz").to_str().unwrap()).unwrap();
+    let piped = parse_store(tmp.path().join("piped.dat.gz").to_str().unwrap()).unwrap();
+    let pipelined = || UpdateHooks {
+        pipelined: true,
+        ..UpdateHooks::default()
+    };
+
+    rsure::update(&tree, &*normal, false, &tags, &algorithms).unwrap();
+    rsure::update_with(&tree, &*piped, false, &tags, &algorithms, pipelined()).unwrap();
+    let first = latest(&*normal);
+    let files: Vec<_> = first.iter().filter(|n| n.0 == "file").collect();
+    assert_eq!(files.len(), 60);
+    for (_, _, atts) in files {
+        let atts = atts.as_ref().unwrap();
+        assert!(atts.contains_key("sha1") && atts.contains_key("sha256"));
+    }
+    assert_eq!(latest(&*piped), first);
+
+    // An update, with some files changed and added, carries over the other
+    // hashes.
+    fs::write(tree.join("a/f3"), "changed\n").unwrap();
+    fs::write(tree.join("a/b/new"), "new\n").unwrap();
+    fs::remove_file(tree.join("c/f7")).unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*normal, true, &tags, &algorithms).unwrap();
+    rsure::update_with(&tree, &*piped, true, &tags, &algorithms, pipelined()).unwrap();
+    assert_eq!(latest(&*piped), latest(&*normal));
+}
@@ -0,0 +1,167 @@
+// Routing the progress of an update to a sink of our own.
+
+use rsure::{
+    parse_store, JsonProgress, NullProgress, ProgressEvent, ProgressSink, StoreTags, UpdateHooks,
+    Version,
+};
+use serde_json::Value;
+use std::{
+    fs,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tempdir::TempDir;
+
+#[test]
+fn recorded() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("b"), "bb\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+
+    for pipelined in &[false, true] {
+        let events = Arc::new(Mutex::new(vec![]));
+        let record = events.clone();
+        let hooks = UpdateHooks {
+            pipelined: *pipelined,
+            progress: Some(Arc::new(move |event: &ProgressEvent| {
+                record.lock().unwrap().push(event.clone())
+            })),
+            ..UpdateHooks::default()
+        };
+        rsure::update_with(&tree, &*store, false, &tags, &[], hooks).unwrap();
+
+        let events = events.lock().unwrap();
+        let finished: Vec<_> = events
+            .iter()
+            .filter_map(|e| match *e {
+                ProgressEvent::Scan {
+                    dirs,
+                    files,
+                    bytes,
+                    done: true,
+                    ..
+                } => Some(format!("scan {} {} {}", dirs, files, bytes)),
+                ProgressEvent::Hash {
+                    files,
+                    total_files,
+                    bytes,
+                    total_bytes,
+                    done: true,
+                    ..
+                } => Some(format!(
+                    "hash {}/{} {}/{}",
+                    files, total_files, bytes, total_bytes
+                )),
+                ProgressEvent::Stage {
+                    label, done: true, ..
+                } => Some(label.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(finished, ["scan 1 2 5", "hash 2/2 5/5", "write"]);
+    }
+}
+
+#[test]
+fn silent() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "quiet".into());
+
+    let hooks = UpdateHooks {
+        progress: Some(Arc::new(NullProgress)),
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*store, false, &tags, &[], hooks).unwrap();
+    let latest = store.get_version(&Version::Latest).unwrap().unwrap();
+    assert_eq!(latest.name, "quiet");
+}
+
+// A writer whose output can still be read once the sink has it.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn json() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    fs::write(tree.join("b"), "bb\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "json".into());
+
+    let out = Shared::default();
+    let hooks = UpdateHooks {
+        progress: Some(Arc::new(
+            JsonProgress::new(out.clone()).with_interval(Duration::from_secs(0)),
+        )),
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*store, false, &tags, &[], hooks).unwrap();
+
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let events: Vec<Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let hashed: Vec<_> = events
+        .iter()
+        .filter(|e| e["event"] == "hash" && e["done"] == false)
+        .map(|e| {
+            let path = e["path"].as_str().unwrap();
+            assert!(path.starts_with(tree.to_str().unwrap()));
+            (
+                e["files"].as_u64().unwrap(),
+                e["total_files"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(hashed, [(1, 2), (2, 2)]);
+
+    let last = &events[events.len() - 1];
+    assert_eq!(last["event"], "stage");
+    assert_eq!(last["label"], "write");
+    assert_eq!(last["done"], true);
+    assert!(last["elapsed"].as_f64().unwrap() >= 0.0);
+}
+
+#[test]
+fn json_lossy_path() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::PathBuf};
+
+    let out = Shared::default();
+    let sink = JsonProgress::new(out.clone());
+    sink.report(&ProgressEvent::Scan {
+        dirs: 1,
+        files: 0,
+        bytes: 0,
+        path: Some(PathBuf::from(OsStr::from_bytes(b"/tree/bad\xff"))),
+        done: true,
+    });
+
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let event: Value = serde_json::from_str(text.trim_end()).unwrap();
+    assert_eq!(event["path"], "/tree/bad\u{fffd}");
+}
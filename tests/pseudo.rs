@@ -0,0 +1,21 @@
+// Pseudo filesystems are refused as the root of a scan.
+
+#![cfg(target_os = "linux")]
+
+use rsure::{fs::scan_fs, Error};
+use std::path::Path;
+
+#[test]
+fn refuse_pseudo_roots() {
+    // Only where they are mounted, which might not be so in a container.
+    for (root, probe) in &[("/proc", "/proc/self"), ("/sys", "/sys/kernel")] {
+        if !Path::new(probe).exists() {
+            continue;
+        }
+        match scan_fs(root) {
+            Err(Error::PseudoFilesystem(path, _)) => assert_eq!(path, Path::new(root)),
+            Err(e) => panic!("scan of {} gave {}", root, e),
+            Ok(_) => panic!("scan of {} allowed", root),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// Finding renames when comparing trees.
+//
+// A file moved elsewhere, with the same content, should be reported once,
+// as renamed, rather than as removed and added.
+
+use rsure::{
+    compare_trees_with_renames, ignore::IgnoreRules, parse_store, ChangeAction, ChangeSummary,
+    StoreTags, Tombstones, Version,
+};
+use std::{fs, path::PathBuf};
+use tempdir::TempDir;
+
+#[test]
+fn renames() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("a")).unwrap();
+    fs::create_dir_all(tree.join("b")).unwrap();
+    fs::write(tree.join("a/moved"), "moved\n").unwrap();
+    fs::write(tree.join("a/gone"), "gone\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.weave.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[Default::default()]).unwrap();
+
+    fs::rename(tree.join("a/moved"), tree.join("b/moved")).unwrap();
+    fs::remove_file(tree.join("a/gone")).unwrap();
+    fs::write(tree.join("b/new"), "new\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[Default::default()]).unwrap();
+
+    let mut changes = vec![];
+    let summary = compare_trees_with_renames(
+        store.load_iter(Version::Prior).unwrap(),
+        store.load_iter(Version::Latest).unwrap(),
+        "",
+        &IgnoreRules::everywhere(&["mtime"]),
+        &Tombstones::new(),
+        |change| changes.push(change),
+    )
+    .unwrap();
+    let found: Vec<_> = changes
+        .iter()
+        .map(|c| (c.path.to_str().unwrap(), c.action, c.from.clone()))
+        .collect();
+    let from = |path: &str| Some(PathBuf::from(path));
+    assert_eq!(
+        found,
+        [
+            ("a/gone", ChangeAction::Removed, None),
+            ("b/moved", ChangeAction::Renamed, from("a/moved")),
+            ("b/new", ChangeAction::Added, None),
+        ]
+    );
+    assert_eq!(
+        summary,
+        ChangeSummary {
+            added: 1,
+            removed: 1,
+            modified: 0,
+            excluded: 0,
+            renamed: 1,
+            // The top directory, "a" and "b".
+            unchanged: 3,
+            // The moved files' contents haven't changed.
+            bytes: 9,
+        }
+    );
+    assert_eq!(summary.to_string(), "+1 -1 ~0 >1");
+    assert_eq!(
+        changes[1].to_string(),
+        format!("> {:22} {:?} (from {:?})", "file", "b/moved", "a/moved")
+    );
+}
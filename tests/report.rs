@@ -0,0 +1,98 @@
+// Writing comparison reports.
+
+use rsure::{
+    report::{sink, Format},
+    Change, ChangeAction,
+};
+
+fn change(path: &str, kind: &str, action: ChangeAction, attrs: &[&str]) -> Change {
+    let mut change = Change::new(path, kind, action);
+    change.attrs_changed = attrs.iter().map(|a| a.to_string()).collect();
+    change
+}
+
+fn write(format: Format, changes: Vec<Change>) -> String {
+    let mut out = vec![];
+    {
+        let mut sink = sink(format, "check <2sure.dat.gz>", Box::new(&mut out));
+        for c in changes {
+            sink.change(c);
+        }
+        sink.finish().unwrap();
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn changes() -> Vec<Change> {
+    vec![
+        change("./a & b", "file", ChangeAction::Added, &[]),
+        change("./sub/c", "file", ChangeAction::Modified, &["sha1", "size"]),
+        change("./sub/d", "lnk", ChangeAction::Removed, &[]),
+    ]
+}
+
+#[test]
+fn text() {
+    let text = write(Format::Text, changes());
+    assert_eq!(text.lines().count(), 3);
+    assert!(text.contains("\"./sub/d\""));
+}
+
+#[test]
+fn junit() {
+    let xml = write(Format::Junit, changes());
+    assert!(xml.starts_with("<?xml"));
+    assert!(
+        xml.contains(r#"<testsuites name="check &lt;2sure.dat.gz&gt;" tests="3" failures="3">"#)
+    );
+    assert!(xml.contains(r#"<testsuite name="." tests="1" failures="1">"#));
+    assert!(xml.contains(r#"<testsuite name="./sub" tests="2" failures="2">"#));
+    assert!(xml.contains(r#"<testcase classname="." name="./a &amp; b">"#));
+    assert!(xml.contains(r#"<failure type="modified" message="sha1,size">"#));
+    assert!(xml.contains(r#"<failure type="removed" message="removed lnk">"#));
+    assert!(xml.trim_end().ends_with("</testsuites>"));
+
+    let clean = write(Format::Junit, vec![]);
+    assert!(clean.contains(r#"tests="1" failures="0""#));
+    assert!(clean.contains(r#"name="unchanged"/>"#));
+
+    assert_eq!("junit".parse::<Format>().unwrap(), Format::Junit);
+    assert!("yaml".parse::<Format>().is_err());
+}
+
+#[test]
+fn markdown() {
+    let md = write(Format::Markdown, changes());
+    assert!(md.starts_with("### check &lt;2sure.dat.gz&gt;\n"));
+    assert!(md.contains("| added | 1 |\n| removed | 1 |\n| modified | 1 |\n"));
+    assert!(md.contains("| **total** | **3** |"));
+    assert!(md.contains("<summary><code>./sub</code> (2 changes)</summary>"));
+    assert!(md.contains("| <code>./sub/c</code> | file | modified | sha1, size |"));
+    assert!(md.contains("| <code>./a &amp; b</code> | file | added |  |"));
+    assert_eq!(md.matches("<details>").count(), 2);
+    assert_eq!(md.matches("</details>").count(), 2);
+
+    let clean = write(Format::Markdown, vec![]);
+    assert!(clean.contains("No changes."));
+}
+
+#[test]
+fn html() {
+    let html = write(Format::Html, changes());
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>check &lt;2sure.dat.gz&gt;</title>"));
+    assert!(html.contains("<tr><td>modified</td><td>1</td></tr>"));
+    assert!(html.contains(r#"<span id="shown">3</span>"#));
+    assert!(html.contains(
+        r#"<tr class="added"><td>./a &amp; b</td><td>file</td><td>added</td><td></td></tr>"#
+    ));
+    assert!(html.contains(
+        r#"<tr class="modified"><td>./sub/c</td><td>file</td><td>modified</td><td>sha1, size</td></tr>"#
+    ));
+    assert!(html.contains("<script>"));
+    assert!(html.trim_end().ends_with("</html>"));
+
+    let clean = write(Format::Html, vec![]);
+    assert!(clean.contains("<p>No changes.</p>"));
+    assert!(!clean.contains(r#"id="changes""#));
+}
@@ -0,0 +1,55 @@
+// Rollup hashes of the directories of a version.
+
+use rsure::{check, parse_store, rollup, CheckOptions, Store, StoreTags, Version};
+use std::{fs, os::unix::fs::symlink, path::Path};
+use tempdir::TempDir;
+
+fn rollup_of(store: &dyn Store, version: Version, path: &str) -> Option<String> {
+    rollup(store.load_iter(version).unwrap(), Path::new(path)).unwrap()
+}
+
+fn make_tree(tree: &Path) {
+    fs::create_dir_all(tree.join("etc").join("ssh")).unwrap();
+    fs::create_dir_all(tree.join("var")).unwrap();
+    fs::write(tree.join("etc").join("hosts"), "127.0.0.1 localhost\n").unwrap();
+    fs::write(
+        tree.join("etc").join("ssh").join("sshd_config"),
+        "Port 22\n",
+    )
+    .unwrap();
+    symlink("hosts", tree.join("etc").join("alias")).unwrap();
+    fs::write(tree.join("var").join("log"), "started\n").unwrap();
+}
+
+#[test]
+fn rollups() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    make_tree(&tree);
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    let etc = rollup_of(&*store, Version::Latest, "etc").unwrap();
+    let top = rollup_of(&*store, Version::Latest, "").unwrap();
+    assert_eq!(etc.len(), 64);
+    assert_eq!(rollup_of(&*store, Version::Latest, "usr"), None);
+
+    // An update rolls up the directories above a change again.
+    fs::write(tree.join("var").join("log"), "started\nstopped\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+    assert_eq!(rollup_of(&*store, Version::Latest, "etc").unwrap(), etc);
+    assert_ne!(rollup_of(&*store, Version::Latest, "").unwrap(), top);
+    assert_eq!(
+        rollup_of(&*store, Version::Named("first".into()), "").unwrap(),
+        top
+    );
+
+    // The rollups aren't reported as changes of their own.
+    fs::write(tree.join("etc").join("hosts"), "::1 localhost\n").unwrap();
+    let report = check(&tree, &*store, Version::Latest, CheckOptions::default()).unwrap();
+    let changed: Vec<_> = report.changes.iter().map(|c| c.path.clone()).collect();
+    assert_eq!(changed, [tree.join("etc").join("hosts")]);
+}
@@ -0,0 +1,113 @@
+// Several directories in one tree.
+//
+// Each directory should be scanned, hashed and updated as a directory at
+// the top of the tree, with what it leaves out, and its hardlinks, named by
+// their paths from the top.
+
+use rsure::{
+    node, parse_store, roots::Roots, Error, HashAlgorithm, PathList, StoreTags, Tombstones,
+    UpdateHooks, Version,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tempdir::TempDir;
+
+#[test]
+fn roots() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let top = tmp.path();
+    fs::create_dir_all(top.join("a/etc/ssh")).unwrap();
+    fs::create_dir_all(top.join("b/usr")).unwrap();
+    fs::create_dir_all(top.join("boot")).unwrap();
+    fs::write(top.join("a/etc/passwd"), "root\n").unwrap();
+    fs::write(top.join("a/etc/ssh/config"), "ssh\n").unwrap();
+    fs::write(top.join("b/usr/bin"), "bin\n").unwrap();
+    fs::hard_link(top.join("b/usr/bin"), top.join("b/usr/bin2")).unwrap();
+    fs::write(top.join("b/usr/skip"), "skip\n").unwrap();
+    fs::write(top.join("b/usr/.rsureignore"), "skip\n").unwrap();
+    fs::write(top.join("boot/vmlinuz"), "kernel\n").unwrap();
+
+    let dirs = [top.join("boot"), top.join("a/etc"), top.join("b/usr")];
+    let roots = Arc::new(Roots::new(&dirs).unwrap());
+    let names: Vec<_> = roots
+        .iter()
+        .map(|(name, _)| name.to_str().unwrap())
+        .collect();
+    assert_eq!(names, ["boot", "etc", "usr"]);
+    assert_eq!(
+        roots.resolve(Path::new("etc/ssh/config")),
+        top.join("a/etc/ssh/config")
+    );
+
+    let store = parse_store(top.join("2sure.weave.gz").to_str().unwrap()).unwrap();
+    let algorithms = [HashAlgorithm::Sha256];
+    let mut tags = StoreTags::new();
+    let hooks = || UpdateHooks {
+        roots: Some(roots.clone()),
+        ..UpdateHooks::default()
+    };
+    let text = |version| {
+        let mut buf = vec![];
+        node::save_to(&mut buf, store.load_iter(version).unwrap()).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+
+    tags.insert("name".into(), "first".into());
+    // The directory given is left alone.
+    let nowhere = top.join("nowhere");
+    rsure::update_with(&nowhere, &*store, false, &tags, &algorithms, hooks()).unwrap();
+    let first = text(Version::Latest);
+    let lines: Vec<_> = first.lines().collect();
+    assert!(lines[2].starts_with("d__root__ "), "{}", first);
+    assert!(lines[3].starts_with("dboot "), "{}", first);
+    assert!(first.contains("link usr/bin"), "{}", first);
+    assert_eq!(first.matches("sha256 ").count(), 6, "{}", first);
+
+    let latest = store.get_version(&Version::Latest).unwrap().unwrap();
+    let excluded = Tombstones::from_tags(&latest.tags).unwrap();
+    assert_eq!(excluded.rule("usr/skip"), Some("skip"));
+
+    // An update rehashes the changed file, where it is.
+    fs::write(top.join("a/etc/passwd"), "root\nuser\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update_with(&nowhere, &*store, true, &tags, &algorithms, hooks()).unwrap();
+    let mut changes = vec![];
+    rsure::compare_trees(
+        store.load_iter(Version::Prior).unwrap(),
+        store.load_iter(Version::Latest).unwrap(),
+        Path::new(""),
+        &[],
+        |change| changes.push(change.path),
+    )
+    .unwrap();
+    assert!(
+        changes.contains(&PathBuf::from("etc/passwd")),
+        "{:?}",
+        changes
+    );
+    assert!(
+        changes.iter().all(|path| path.starts_with("etc")),
+        "{:?}",
+        changes
+    );
+
+    // Listing paths needs a single directory.
+    let mut listed = hooks();
+    listed.paths = Some(PathList::new());
+    assert!(matches!(
+        rsure::update_with(&nowhere, &*store, true, &tags, &algorithms, listed),
+        Err(Error::SeveralRoots(_))
+    ));
+}
+
+#[test]
+fn root_names() {
+    assert!(matches!(
+        Roots::new(&["a/etc", "b/etc"]),
+        Err(Error::DuplicateRoot(name)) if name == "etc"
+    ));
+    assert!(matches!(Roots::new(&["/"]), Err(Error::RootName(_))));
+}
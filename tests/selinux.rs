@@ -0,0 +1,110 @@
+// Recording SELinux security contexts.
+
+#![cfg(target_os = "linux")]
+
+use rsure::{
+    check, parse_store, CheckOptions, PathList, ScanOptions, StoreTags, UpdateHooks, Version,
+};
+use std::{ffi::CString, fs, os::unix::ffi::OsStrExt, path::Path};
+use tempdir::TempDir;
+
+/// Label the file, returning false if that isn't allowed here.
+fn label(path: &Path, context: &str) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let key = CString::new("security.selinux").unwrap();
+    let value = CString::new(context).unwrap();
+    let value = value.as_bytes_with_nul();
+    unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            key.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        ) == 0
+    }
+}
+
+fn selinux() -> UpdateHooks {
+    UpdateHooks {
+        scan: ScanOptions {
+            selinux: true,
+            ..ScanOptions::default()
+        },
+        ..UpdateHooks::default()
+    }
+}
+
+#[test]
+fn contexts() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("bin")).unwrap();
+    fs::write(tree.join("bin").join("ls"), "ls\n").unwrap();
+    fs::write(tree.join("bin").join("cat"), "cat\n").unwrap();
+    // Labels are set by the policy where SELinux is enforced, and may be
+    // refused elsewhere.
+    if !label(&tree.join("bin").join("ls"), "system_u:object_r:bin_t:s0")
+        || !label(&tree.join("bin"), "system_u:object_r:bin_t:s0")
+    {
+        return;
+    }
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update_with(&tree, &*store, false, &tags, &[], selinux()).unwrap();
+
+    let contexts: Vec<_> = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|node| node.unwrap())
+        .filter(|node| node.is_enter() || node.is_file())
+        .map(|node| {
+            let name = node.name().to_string();
+            (name, node.atts().unwrap().get("secontext").cloned())
+        })
+        .collect();
+    let bin_t = Some("system_u:object_r:bin_t:s0".to_string());
+    assert_eq!(
+        contexts,
+        [
+            ("__root__".to_string(), None),
+            ("bin".to_string(), bin_t.clone()),
+            ("cat".to_string(), None),
+            ("ls".to_string(), bin_t),
+        ]
+    );
+
+    // A relabelled file is a change, and so is one picked up by a listed
+    // update.
+    assert!(label(
+        &tree.join("bin").join("ls"),
+        "system_u:object_r:tmp_t:s0"
+    ));
+    let options = CheckOptions {
+        hooks: selinux(),
+        ..CheckOptions::default()
+    };
+    let report = check(&tree, &*store, Version::Latest, options).unwrap();
+    assert_eq!(report.changes.len(), 1);
+    assert_eq!(report.changes[0].path, tree.join("bin").join("ls"));
+    assert_eq!(report.changes[0].attrs_changed, ["secontext"]);
+
+    tags.insert("name".into(), "second".into());
+    let hooks = UpdateHooks {
+        paths: Some(PathList::read(&b"bin/ls\n"[..]).unwrap()),
+        ..selinux()
+    };
+    rsure::update_with(&tree, &*store, true, &tags, &[], hooks).unwrap();
+    let ls = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|node| node.unwrap())
+        .find(|node| node.is_file() && node.name() == "ls")
+        .unwrap();
+    assert_eq!(
+        ls.atts().unwrap()["secontext"],
+        "system_u:object_r:tmp_t:s0"
+    );
+}
@@ -0,0 +1,36 @@
+// Pausing the updates of the process with signals.
+
+#![cfg(unix)]
+
+use rsure::service::{handle_pause, pause_token};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Wait for the token to be paused, or not, as the signal is passed on from another thread.
+fn wait_paused(paused: bool) {
+    let start = Instant::now();
+    while pause_token().is_paused() != paused {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "paused {}",
+            paused
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn pause_signals() {
+    let token = pause_token();
+    assert!(!token.is_paused());
+    handle_pause().unwrap();
+    handle_pause().unwrap();
+
+    unsafe { libc::raise(libc::SIGUSR1) };
+    wait_paused(true);
+    unsafe { libc::raise(libc::SIGUSR2) };
+    wait_paused(false);
+    assert!(token.check().is_ok());
+}
@@ -0,0 +1,69 @@
+// Showing a version as an indented tree.
+
+use rsure::{parse_store, show_nodes, Error, ShowOptions, StoreTags, Version};
+use std::{fs, path::PathBuf};
+use tempdir::TempDir;
+
+#[test]
+fn tree() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub").join("deeper")).unwrap();
+    fs::write(tree.join("sub").join("a"), "a\n").unwrap();
+    fs::write(tree.join("sub").join("deeper").join("c"), "ccc\n").unwrap();
+    fs::write(tree.join("b"), "bb\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    let show = |options: ShowOptions| {
+        let mut out = vec![];
+        show_nodes(
+            store.load_iter(Version::Latest).unwrap(),
+            &options,
+            &mut out,
+        )?;
+        Ok::<_, Error>(String::from_utf8(out).unwrap())
+    };
+
+    let whole = show(ShowOptions {
+        atts: vec!["kind".into(), "size".into()],
+        ..ShowOptions::default()
+    })
+    .unwrap();
+    assert_eq!(
+        whole,
+        ".  kind=dir\n  \
+         sub/  kind=dir\n    \
+         deeper/  kind=dir\n      \
+         c  kind=file  size=4\n    \
+         a  kind=file  size=2\n  \
+         b  kind=file  size=3\n"
+    );
+
+    let sub = show(ShowOptions {
+        path: Some(PathBuf::from("./sub")),
+        depth: Some(1),
+        atts: vec!["size".into()],
+    })
+    .unwrap();
+    assert_eq!(sub, "./sub/\n  deeper/\n  a  size=2\n");
+
+    // The default attributes, in a readable form.
+    let file = show(ShowOptions {
+        path: Some(PathBuf::from("b")),
+        ..ShowOptions::default()
+    })
+    .unwrap();
+    assert!(file.starts_with("b  perm=0"), "{}", file);
+    assert!(file.contains("  size=3  mtime=20"), "{}", file);
+
+    match show(ShowOptions {
+        path: Some(PathBuf::from("sub/missing")),
+        ..ShowOptions::default()
+    }) {
+        Err(Error::PathNotInVersion(path)) => assert_eq!(path, PathBuf::from("sub/missing")),
+        other => panic!("Unexpected: {:?}", other),
+    }
+}
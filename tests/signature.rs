@@ -0,0 +1,105 @@
+// Signed stores.
+
+use openssl::pkey::PKey;
+use rsure::{parse_store, Error, SignedStore, SigningKeys, Store, StoreTags, Version};
+use std::{fs, path::Path, sync::Arc};
+use tempdir::TempDir;
+
+fn keys() -> (Vec<u8>, Vec<u8>) {
+    let key = PKey::generate_ed25519().unwrap();
+    (
+        key.private_key_to_pem_pkcs8().unwrap(),
+        key.public_key_to_pem().unwrap(),
+    )
+}
+
+fn open(dir: &Path, keys: SigningKeys) -> SignedStore {
+    SignedStore::new(parse_store(dir.to_str().unwrap()).unwrap(), Arc::new(keys))
+}
+
+fn read_all(store: &dyn Store, version: Version) -> rsure::Result<usize> {
+    let mut count = 0;
+    for node in store.load_iter(version)? {
+        node?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[test]
+fn signed_store() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    let sure = tmp.path().join("sure");
+    fs::create_dir(&tree).unwrap();
+    fs::create_dir(&sure).unwrap();
+    fs::write(tree.join("a"), "first\n").unwrap();
+    let (private, public) = keys();
+
+    // An unsigned version, from before the store was signed.
+    let plain = parse_store(sure.to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*plain, false, &tags, &[]).unwrap();
+
+    let store = open(&sure, SigningKeys::from_private_pem(&private).unwrap());
+    assert!(matches!(
+        read_all(&store, Version::Latest),
+        Err(Error::Unsigned(_))
+    ));
+    store.sign_version(&Version::Latest).unwrap();
+    store.verify_version(&Version::Latest).unwrap();
+
+    fs::write(tree.join("b"), "second\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &store, true, &tags, &[]).unwrap();
+    assert!(store
+        .get_artifact(&Version::Latest, rsure::SIGNATURE_ARTIFACT)
+        .unwrap()
+        .is_some());
+
+    // The public key alone checks, but can't write.
+    let checker = open(&sure, SigningKeys::from_public_pem(&public).unwrap());
+    assert!(read_all(&checker, Version::Latest).unwrap() > 0);
+    assert!(read_all(&checker, Version::Prior).unwrap() > 0);
+    assert!(checker.make_new(&tags).is_err());
+
+    // Another key doesn't match.
+    let (_, other) = keys();
+    let other = open(&sure, SigningKeys::from_public_pem(&other).unwrap());
+    assert!(matches!(
+        read_all(&other, Version::Latest),
+        Err(Error::BadSignature(_))
+    ));
+
+    // Neither does a signature moved to another version.
+    let aux = sure.join("2sure.aux");
+    fs::copy(
+        aux.join("1").join(rsure::SIGNATURE_ARTIFACT),
+        aux.join("2").join(rsure::SIGNATURE_ARTIFACT),
+    )
+    .unwrap();
+    assert!(matches!(
+        checker.verify_version(&Version::Latest),
+        Err(Error::BadSignature(_))
+    ));
+    checker.verify_version(&Version::Prior).unwrap();
+}
+
+#[test]
+fn key_files() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let (private, public) = keys();
+    let priv_path = tmp.path().join("key.pem");
+    let pub_path = tmp.path().join("key.pub");
+    fs::write(&priv_path, &private).unwrap();
+    fs::write(&pub_path, &public).unwrap();
+    assert!(SigningKeys::from_file(&priv_path).unwrap().can_sign());
+    assert!(!SigningKeys::from_file(&pub_path).unwrap().can_sign());
+
+    // Only ed25519 keys.
+    let rsa = openssl::rsa::Rsa::generate(2048).unwrap();
+    let rsa = PKey::from_rsa(rsa).unwrap();
+    assert!(SigningKeys::from_private_pem(&rsa.private_key_to_pem_pkcs8().unwrap()).is_err());
+    assert!(SigningKeys::from_file(tmp.path().join("missing")).is_err());
+}
@@ -0,0 +1,50 @@
+// Updates with enough files that the hashes are read back from sqlite a
+// page at a time, or, with `cargo test --no-default-features`, are merged
+// from several sorted runs.
+
+use openssl::sha::sha1;
+use rsure::{node, parse_store, StoreTags, UpdateHooks, Version};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn sorted_runs() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for d in 0..5 {
+        let dir = tree.join(format!("dir{}", d));
+        fs::create_dir(&dir).unwrap();
+        for i in 0..500 {
+            fs::write(dir.join(format!("file{}", i)), format!("{} {}\n", d, i)).unwrap();
+        }
+    }
+
+    // A limit small enough to need several runs, without sqlite.
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "scan".into());
+    let hooks = || UpdateHooks {
+        memory_limit: Some("1M".parse().unwrap()),
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*store, false, &tags, &[], hooks()).unwrap();
+    fs::write(tree.join("dir3").join("file7"), "changed\n").unwrap();
+    rsure::update_with(&tree, &*store, true, &tags, &[], hooks()).unwrap();
+
+    let mut dir = None;
+    let mut count = 0;
+    for n in store.load_iter(Version::Latest).unwrap() {
+        match n.unwrap() {
+            node::SureNode::Enter { name, .. } => dir = Some(name),
+            node::SureNode::File { name, atts } => {
+                let path = tree.join(dir.as_ref().unwrap()).join(&name);
+                let expect = data_encoding::HEXLOWER.encode(&sha1(&fs::read(path).unwrap()));
+                assert_eq!(atts["sha1"], expect);
+                count += 1;
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(count, 2500);
+}
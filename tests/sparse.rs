@@ -0,0 +1,64 @@
+// Recording the blocks allocated to files, and marking those with fewer
+// than their size as sparse.
+
+#![cfg(unix)]
+
+use rsure::{check, ignore::IgnoreRules, parse_store, CheckOptions, StoreTags, Version};
+use std::{
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+};
+use tempdir::TempDir;
+
+const SIZE: u64 = 1 << 20;
+
+#[test]
+fn sparse() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    let path = tree.join("sparse");
+    let mut file = fs::File::create(&path).unwrap();
+    file.set_len(SIZE - 1).unwrap();
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(b"\0").unwrap();
+    drop(file);
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+
+    let node = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|node| node.unwrap())
+        .find(|node| node.is_file())
+        .unwrap();
+    let atts = node.atts().unwrap();
+    assert_eq!(node.size(), SIZE);
+    let blocks: u64 = atts["blocks"].parse().unwrap();
+    // Not every filesystem has holes.
+    if blocks * 512 >= SIZE {
+        return;
+    }
+    assert_eq!(node.data_size(), blocks * 512);
+    assert_eq!(atts["sparse"], "1");
+
+    // Filling in the holes, with the zeros they read as, leaves the
+    // contents as they were, but it is no longer sparse.
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.write_all(&vec![0; SIZE as usize]).unwrap();
+    file.sync_all().unwrap();
+    drop(file);
+
+    let options = CheckOptions {
+        rules: Some(IgnoreRules::everywhere(&["mtime", "ctime"])),
+        ..CheckOptions::default()
+    };
+    let report = check(&tree, &*store, Version::Latest, options).unwrap();
+    assert_eq!(report.changes.len(), 1);
+    assert_eq!(report.changes[0].path, path);
+    assert_eq!(report.changes[0].attrs_changed, ["sparse"]);
+    assert_eq!(report.summary.bytes, 0);
+}
@@ -0,0 +1,139 @@
+// Stores on another host, reached over ssh.  The ssh command is replaced by a script that runs
+// the remote command locally, noting the arguments it was given.
+
+use rsure::{parse_store, Bucket, ObjectStore, SshBucket, Store, StoreTags};
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+use tempdir::TempDir;
+
+/// Write the fake ssh command, which logs its arguments to `args` beside it.
+fn fake_ssh(dir: &Path) -> String {
+    let script = dir.join("fake-ssh");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> {}\nwhile [ $# -gt 1 ]; do shift; done\nexec sh -c \"$1\"\n",
+            dir.join("args").display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    script.to_str().unwrap().to_string()
+}
+
+#[test]
+fn bucket() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let bucket = SshBucket::new("me@host")
+        .unwrap()
+        .with_command(&fake_ssh(tmp.path()));
+    let key = tmp.path().join("it's here").to_str().unwrap().to_string();
+
+    let mut data = vec![];
+    assert!(!bucket.get_to(&key, &mut data).unwrap());
+    assert_eq!(bucket.get(&key).unwrap(), None);
+    bucket.put_from(&key, &mut &b"contents\n"[..]).unwrap();
+    assert_eq!(fs::read(&key).unwrap(), b"contents\n");
+    assert!(!Path::new(&format!("{}.tmp", key)).exists());
+    assert!(bucket.get_to(&key, &mut data).unwrap());
+    assert_eq!(data, b"contents\n");
+    bucket.put(&key, b"whole\n").unwrap();
+    assert_eq!(bucket.get(&key).unwrap().unwrap(), b"whole\n");
+
+    // A directory can't be read, and a missing one can't be written to.
+    let dir = tmp.path().to_str().unwrap();
+    assert!(bucket.get_to(dir, &mut vec![]).is_err());
+    let nowhere = tmp.path().join("missing/file");
+    assert!(bucket.put(nowhere.to_str().unwrap(), b"").is_err());
+
+    assert!(SshBucket::new("-oProxyCommand=x").is_err());
+    let missing = SshBucket::new("host")
+        .unwrap()
+        .with_command("/nonexistent/ssh");
+    assert!(missing.get(&key).is_err());
+}
+
+#[test]
+fn parse() {
+    let parsed = |text: &str| {
+        let (bucket, path) = SshBucket::parse(text).unwrap();
+        (bucket.host().to_string(), bucket.port(), path)
+    };
+    assert_eq!(
+        parsed("me@vault:2222/srv/web1/"),
+        (
+            "me@vault".into(),
+            Some(2222),
+            "/srv/web1/2sure.dat.gz".into()
+        )
+    );
+    assert_eq!(
+        parsed("vault/~/2sure.weave"),
+        ("vault".into(), None, "2sure.weave".into())
+    );
+
+    // IPv6 addresses are in brackets, which ssh doesn't want.
+    assert_eq!(
+        parsed("[::1]/srv/"),
+        ("::1".into(), None, "/srv/2sure.dat.gz".into())
+    );
+    assert_eq!(
+        parsed("me@[fe80::1]:2222/srv/"),
+        ("me@fe80::1".into(), Some(2222), "/srv/2sure.dat.gz".into())
+    );
+
+    for bad in [
+        "vault",
+        "vault:port/x",
+        "::1/srv/",
+        "[::1/srv/",
+        "[::1]x/srv/",
+        "[::1]:port/srv/",
+        "-oProxyCommand=x/srv/",
+    ] {
+        assert!(SshBucket::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn store() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    let remote = tmp.path().join("remote");
+    fs::create_dir(&tree).unwrap();
+    fs::create_dir(&remote).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    let ssh = fake_ssh(tmp.path());
+    let open = |url: &str| {
+        let (bucket, path) = SshBucket::parse(url).unwrap();
+        ObjectStore::with_key(Box::new(bucket.with_command(&ssh)), &path).unwrap()
+    };
+    let store = open(&format!("backup@vault:2222{}/", remote.display()));
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &store, false, &tags, &[]).unwrap();
+    assert!(remote.join("2sure.dat.gz").is_file());
+
+    let args = fs::read_to_string(tmp.path().join("args")).unwrap();
+    assert!(args.lines().all(|l| l.starts_with("-p 2222 backup@vault ")));
+
+    // The file can be named, giving its compression.
+    let plain = open(&format!("vault{}/2sure.weave", remote.display()));
+    rsure::update(&tree, &plain, false, &tags, &[]).unwrap();
+    let text = fs::read_to_string(remote.join("2sure.weave")).unwrap();
+    assert!(text.starts_with("\x01t"));
+
+    let again = ObjectStore::with_key(
+        Box::new(
+            SshBucket::new("vault")
+                .unwrap()
+                .with_command(&fake_ssh(tmp.path())),
+        ),
+        remote.join("2sure.dat.gz").to_str().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(again.get_versions().unwrap().len(), 1);
+
+    assert!(parse_store("ssh://vault").is_err());
+    assert!(parse_store("ssh://vault:port/x").is_err());
+}
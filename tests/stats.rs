@@ -0,0 +1,57 @@
+// The performance counters.
+//
+// The counters are process wide, so this is the only test in this binary
+// that runs updates.
+
+use rsure::{parse_store, stats, HashAlgorithm, StoreTags};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn update_counters() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("one"), "one\n").unwrap();
+    fs::write(tree.join("sub/two"), "two two\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+
+    let before = stats::global().snapshot();
+    rsure::update(&tree, &*store, false, &tags, &[HashAlgorithm::Sha1]).unwrap();
+    let scan = stats::global().snapshot().since(&before);
+
+    // Enter, separator and leave for each directory, and the two files.
+    assert_eq!(scan.nodes_scanned, 8);
+    assert!(scan.attrs > 0);
+    assert_eq!(scan.files_hashed, 2);
+    assert_eq!(scan.bytes_hashed, 12);
+    let names: Vec<_> = scan.stages.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["scan", "hash", "write"]);
+    assert!(scan.stages[0].bytes_written.unwrap() > 0);
+    assert_eq!(scan.stages[1].bytes_written, None);
+
+    let before = stats::global().snapshot();
+    rsure::update(&tree, &*store, true, &tags, &[HashAlgorithm::Sha1]).unwrap();
+    let update = stats::global().snapshot().since(&before);
+    assert_eq!(update.nodes_scanned, 8);
+    assert_eq!(update.files_hashed, 0);
+    // The stages are kept in the order they first ran in the process.
+    let names: Vec<_> = update.stages.iter().map(|s| s.name).collect();
+    assert_eq!(names, ["scan", "hash", "write", "combine"]);
+    assert!(update.stages.iter().all(|s| s.runs == 1));
+    // The written snapshot is the same size both times.
+    assert_eq!(update.stages[2].bytes_written, scan.stages[2].bytes_written);
+
+    // Runs of a stage are added together, rather than each kept.
+    rsure::update(&tree, &*store, true, &tags, &[HashAlgorithm::Sha1]).unwrap();
+    let both = stats::global().snapshot().since(&before);
+    assert_eq!(both.stages.len(), 4);
+    assert!(both.stages.iter().all(|s| s.runs == 2));
+    assert_eq!(
+        both.stages[2].bytes_written,
+        scan.stages[2].bytes_written.map(|b| b * 2)
+    );
+}
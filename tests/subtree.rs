@@ -0,0 +1,116 @@
+// Comparing a single directory between versions.
+//
+// Only the named directory should be compared, reading no more of either
+// version than it takes to get past it.
+
+use rsure::{
+    compare_dir, ignore::IgnoreRule, ignore::IgnoreRules, parse_store, ChangeAction, Error,
+    StoreTags, Version,
+};
+use std::{cell::Cell, fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn diff_dir() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    for dir in &["a", "b/sub", "c", "gone"] {
+        fs::create_dir_all(tree.join(dir)).unwrap();
+    }
+    for i in 0..100 {
+        fs::write(tree.join("c").join(format!("file{}", i)), "c\n").unwrap();
+    }
+    fs::write(tree.join("a/file"), "a\n").unwrap();
+    fs::write(tree.join("b/file"), "b\n").unwrap();
+    fs::write(tree.join("b/sub/file"), "sub\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.weave.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[Default::default()]).unwrap();
+
+    fs::write(tree.join("a/file"), "changed\n").unwrap();
+    fs::write(tree.join("b/file"), "changed\n").unwrap();
+    fs::write(tree.join("b/new"), "new\n").unwrap();
+    fs::write(tree.join("b/sub/file"), "changed\n").unwrap();
+    fs::write(tree.join("c/file0"), "changed\n").unwrap();
+    fs::remove_dir(tree.join("gone")).unwrap();
+    fs::create_dir(tree.join("new")).unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[Default::default()]).unwrap();
+
+    let diff = |path: &str, rules: &IgnoreRules| {
+        let mut changes = vec![];
+        store
+            .diff_dir(
+                Version::Prior,
+                Version::Latest,
+                Path::new(path),
+                rules,
+                &mut |change| changes.push((change.path, change.action)),
+            )
+            .map(|_| changes)
+    };
+    // The directories' mtimes may or may not have changed within the second.
+    let no_mtime = IgnoreRules::everywhere(&["mtime"]);
+
+    // Just the directory, and what is in it, with paths from the top.
+    let changes = diff("b", &no_mtime).unwrap();
+    let paths: Vec<_> = changes.iter().map(|(p, _)| p.to_str().unwrap()).collect();
+    assert_eq!(paths, ["b/sub/file", "b/file", "b/new"]);
+    assert_eq!(changes[2].1, ChangeAction::Added);
+
+    let changes = diff("./b/sub", &no_mtime).unwrap();
+    let paths: Vec<_> = changes.iter().map(|(p, _)| p.to_str().unwrap()).collect();
+    assert_eq!(paths, ["b/sub/file"]);
+
+    // The rules kept with the store are relative to the top of the tree.
+    let mut rules = IgnoreRules::new();
+    rules.add(IgnoreRule::new("mtime", Some(Path::new("b/sub"))).unwrap());
+    rules.add(IgnoreRule::new("sha1", Some(Path::new("b/sub"))).unwrap());
+    rules.add(IgnoreRule::new("size", Some(Path::new("b/sub"))).unwrap());
+    rules.add(IgnoreRule::new("sha1", Some(Path::new("a"))).unwrap());
+    let changes = diff("b/sub", &rules).unwrap();
+    let paths: Vec<_> = changes.iter().map(|(p, _)| p.to_str().unwrap()).collect();
+    assert!(paths.is_empty(), "{:?}", paths);
+
+    // A directory in just one version is added or removed, as a whole.
+    assert_eq!(
+        diff("gone", &no_mtime).unwrap(),
+        [(Path::new("gone").to_path_buf(), ChangeAction::Removed)]
+    );
+    assert_eq!(
+        diff("new", &no_mtime).unwrap(),
+        [(Path::new("new").to_path_buf(), ChangeAction::Added)]
+    );
+    assert!(matches!(
+        diff("missing", &no_mtime),
+        Err(Error::DirNotInVersions(_))
+    ));
+    assert!(matches!(
+        diff("b/file", &no_mtime),
+        Err(Error::DirNotInVersions(_))
+    ));
+
+    // Nothing after the directory is read, nor is anything in the
+    // directories before it compared.
+    let read = Cell::new(0);
+    let counted = |version| {
+        store
+            .load_iter(version)
+            .unwrap()
+            .inspect(|_| read.set(read.get() + 1))
+    };
+    let mut changes = vec![];
+    compare_dir(
+        counted(Version::Prior),
+        counted(Version::Latest),
+        "",
+        Path::new("a"),
+        &no_mtime,
+        |change| changes.push(change.path),
+    )
+    .unwrap();
+    assert_eq!(changes, [Path::new("a/file")]);
+    assert!(read.get() < 20, "read {} nodes", read.get());
+}
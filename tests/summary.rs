@@ -0,0 +1,66 @@
+// The summary of changes recorded with each version.
+
+use rsure::{humanize, parse_store, ChangeSummary, StoreTags, Version, CHANGES_TAG};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn summary_tags() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    fs::write(tree.join("c"), "c\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    let first = store.get_version(&Version::Latest).unwrap().unwrap();
+    assert!(!first.tags.contains_key(CHANGES_TAG));
+
+    let mtime = fs::metadata(&tree).unwrap().modified().unwrap();
+    fs::remove_file(tree.join("a")).unwrap();
+    fs::write(tree.join("b"), "bigger b\n").unwrap();
+    fs::write(tree.join("d"), "d\n").unwrap();
+    fs::create_dir(tree.join("e")).unwrap();
+    fs::write(tree.join("e/f"), "f\n").unwrap();
+    // So that only "b" is modified.
+    fs::File::open(&tree).unwrap().set_modified(mtime).unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+
+    let second = store.get_version(&Version::Latest).unwrap().unwrap();
+    let summary = ChangeSummary::from_tags(&second.tags).unwrap().unwrap();
+    // The new directory counts once.
+    assert_eq!(
+        summary,
+        ChangeSummary {
+            added: 2,
+            removed: 1,
+            modified: 1,
+            excluded: 0,
+            renamed: 0,
+            // The top directory and "c".
+            unchanged: 2,
+            // "a", the new "b", "d" and "e/f".
+            bytes: 15,
+        }
+    );
+    assert_eq!(summary.to_string(), "+2 -1 ~1");
+    assert_eq!(
+        summary.describe(),
+        "2 added, 1 removed, 1 modified, 2 unchanged, 15B affected"
+    );
+    assert_eq!(ChangeSummary::from_tags(&first.tags).unwrap(), None);
+}
+
+#[test]
+fn humanize_sizes() {
+    // Bytes are whole, without a fraction.
+    assert_eq!(humanize(2), "     2B  ");
+    assert_eq!(humanize(999), "   999B  ");
+    assert_eq!(humanize(4096), " 4.000KiB");
+    assert_eq!(humanize(150 * 1024 * 1024), " 150.0MiB");
+}
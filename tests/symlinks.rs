@@ -0,0 +1,198 @@
+// Following symlinks, and hashing what they point to, while scanning.
+
+use rsure::{
+    check,
+    fs::{scan_fs, scan_fs_with},
+    parse_store, CheckOptions, ScanOptions, Store, StoreTags, SureNode, UpdateHooks, Version,
+};
+use std::{fs, os::unix::fs::symlink};
+use tempdir::TempDir;
+
+/// The path and kind of each node.
+fn kinds(nodes: impl Iterator<Item = rsure::Result<SureNode>>) -> Vec<(String, String)> {
+    let mut dirs: Vec<String> = vec![];
+    let mut result = vec![];
+    for node in nodes {
+        let node = node.unwrap();
+        match &node {
+            SureNode::Enter { name, .. } => dirs.push(name.clone()),
+            SureNode::Leave => {
+                dirs.pop();
+            }
+            SureNode::Sep => continue,
+            SureNode::File { .. } => (),
+        }
+        if let Some(atts) = node.atts() {
+            let mut path = dirs[1..].join("/");
+            if node.is_file() {
+                if !path.is_empty() {
+                    path.push('/');
+                }
+                path.push_str(node.name());
+            }
+            result.push((path, atts["kind"].clone()));
+        }
+    }
+    result
+}
+
+fn kind<'a>(nodes: &'a [(String, String)], path: &str) -> Option<&'a str> {
+    nodes
+        .iter()
+        .find(|(p, _)| p == path)
+        .map(|(_, k)| k.as_str())
+}
+
+#[test]
+fn follow_symlinks() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let root = tmp.path();
+    fs::create_dir_all(root.join("real/sub")).unwrap();
+    fs::write(root.join("real/sub/data"), "data\n").unwrap();
+    fs::create_dir(root.join("farm")).unwrap();
+    symlink("../real/sub/data", root.join("farm/data")).unwrap();
+    symlink("../real", root.join("farm/real")).unwrap();
+    symlink("..", root.join("farm/up")).unwrap();
+    symlink("nowhere", root.join("farm/broken")).unwrap();
+
+    let plain = kinds(scan_fs(root).unwrap());
+    assert_eq!(kind(&plain, "farm/data"), Some("lnk"));
+    assert_eq!(kind(&plain, "farm/real"), Some("lnk"));
+    assert_eq!(kind(&plain, "farm/real/sub/data"), None);
+
+    let options = ScanOptions {
+        follow_symlinks: true,
+        ..ScanOptions::default()
+    };
+    let followed = kinds(scan_fs_with(root, &options).unwrap());
+    assert_eq!(kind(&followed, "farm/data"), Some("file"));
+    assert_eq!(kind(&followed, "farm/real"), Some("dir"));
+    assert_eq!(kind(&followed, "farm/real/sub/data"), Some("file"));
+    // A link back up the tree would loop, so stays a link, as does one
+    // that doesn't go anywhere.
+    assert_eq!(kind(&followed, "farm/up"), Some("lnk"));
+    assert_eq!(kind(&followed, "farm/broken"), Some("lnk"));
+    assert_eq!(kind(&followed, "real/sub/data"), Some("file"));
+
+    // A symlinked root is followed too.
+    symlink(root.join("real"), root.join("top")).unwrap();
+    assert!(scan_fs(root.join("top")).is_err());
+    let top = kinds(scan_fs_with(root.join("top"), &options).unwrap());
+    assert_eq!(kind(&top, "sub/data"), Some("file"));
+}
+
+#[test]
+fn hash_link_targets() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("one"), "one\n").unwrap();
+    fs::write(tree.join("two"), "two\n").unwrap();
+    // As in /etc/alternatives, the link text stays the same, but an
+    // intermediate link is changed.
+    symlink("one", tree.join("choice")).unwrap();
+    symlink("choice", tree.join("alt")).unwrap();
+    symlink(".", tree.join("dir")).unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let hooks = || UpdateHooks {
+        scan: ScanOptions {
+            hash_link_targets: true,
+            ..ScanOptions::default()
+        },
+        ..UpdateHooks::default()
+    };
+    let atts = |store: &dyn Store, name: &str| {
+        store
+            .load_iter(Version::Latest)
+            .unwrap()
+            .map(|n| n.unwrap())
+            .find(|n| n.is_file() && n.name() == name)
+            .and_then(|n| n.atts().cloned())
+            .unwrap()
+    };
+
+    rsure::update_with(&tree, &*store, false, &tags, &[], hooks()).unwrap();
+    let one = atts(&*store, "one");
+    let alt = atts(&*store, "alt");
+    assert_eq!(alt["kind"], "lnk");
+    assert_eq!(alt["targ"], "choice");
+    assert!(alt["tpath"].ends_with("/one"));
+    assert_eq!(alt["tsize"], "4");
+    assert_eq!(alt["sha1"], one["sha1"]);
+    // Directories are resolved, but have nothing to hash.
+    let dir = atts(&*store, "dir");
+    assert!(dir.contains_key("tpath"));
+    assert!(!dir.contains_key("sha1"));
+
+    fs::remove_file(tree.join("choice")).unwrap();
+    symlink("two", tree.join("choice")).unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update_with(&tree, &*store, true, &tags, &[], hooks()).unwrap();
+    let alt2 = atts(&*store, "alt");
+    assert_eq!(alt2["targ"], "choice");
+    assert_ne!(alt2["tpath"], alt["tpath"]);
+    assert_eq!(alt2["sha1"], atts(&*store, "two")["sha1"]);
+}
+
+#[test]
+fn broken_links() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("data"), "data\n").unwrap();
+    let long = format!("{}/data", ["."; 200].join("/"));
+    symlink(&long, tree.join("long")).unwrap();
+    symlink("data", tree.join("short")).unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    let hooks = || UpdateHooks {
+        scan: ScanOptions {
+            hash_link_targets: true,
+            ..ScanOptions::default()
+        },
+        ..UpdateHooks::default()
+    };
+    rsure::update_with(&tree, &*store, false, &tags, &[], hooks()).unwrap();
+    let digests: Vec<_> = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .filter(|n| n.is_file())
+        .map(|n| n.atts().unwrap().get("tdigest").cloned())
+        .collect();
+    assert_eq!(digests[0], None);
+    assert_eq!(digests[1].as_ref().unwrap().len(), 64);
+    assert_eq!(
+        digests[2].as_deref(),
+        // sha256 of "data".
+        Some("3a6eb0790f39ac87c94f3856b2dd2c5d110e6811602261a9a923d3bb23adc8b7")
+    );
+
+    // Both links are broken by the file going, which is a change to where
+    // they point, not just a change of format.
+    fs::remove_file(tree.join("data")).unwrap();
+    let options = CheckOptions {
+        hooks: hooks(),
+        ..CheckOptions::default()
+    };
+    let report = check(&tree, &*store, Version::Latest, options).unwrap();
+    let changes: Vec<_> = report
+        .changes
+        .iter()
+        .map(|c| (c.path.clone(), c.attrs_changed.clone()))
+        .collect();
+    let broken = vec!["sha1".to_string(), "tpath".into(), "tsize".into()];
+    assert_eq!(
+        changes,
+        [
+            (tree.join("data"), vec![]),
+            (tree.join("long"), broken.clone()),
+            (tree.join("short"), broken),
+        ]
+    );
+}
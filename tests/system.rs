@@ -0,0 +1,95 @@
+// The mount table, for whole system scans, and the filesystem each
+// directory is on.
+
+#![cfg(unix)]
+
+use rsure::{
+    compare_trees,
+    fs::scan_fs,
+    system::{parse_mounts, Mount},
+};
+use std::{fs, path::Path};
+use tempdir::TempDir;
+
+#[test]
+fn mount_table() {
+    let text = "\
+/dev/sda2 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev,mode=755 0 0
+/dev/sda1 /boot/efi vfat rw,relatime 0 0
+/dev/mapper/data /srv/My\\040Files xfs rw,relatime 0 0
+";
+    let mounts = parse_mounts(text);
+    assert_eq!(mounts.len(), 5);
+    assert_eq!(
+        mounts[4],
+        Mount {
+            source: "/dev/mapper/data".into(),
+            mountpoint: "/srv/My Files".into(),
+            fstype: "xfs".into(),
+        }
+    );
+
+    let real: Vec<_> = mounts.iter().filter(|m| m.is_real()).collect();
+    let points: Vec<_> = real.iter().map(|m| m.mountpoint.as_path()).collect();
+    assert_eq!(
+        points,
+        [
+            Path::new("/"),
+            Path::new("/boot/efi"),
+            Path::new("/srv/My Files")
+        ]
+    );
+    let labels: Vec<_> = real.iter().map(|m| m.label()).collect();
+    assert_eq!(
+        labels,
+        ["root", "boot-efi-5a35215e", "srv-My Files-ff2d4b07"]
+    );
+
+    // Mounts whose paths differ only in their slashes and dashes have their
+    // own labels.
+    let mounts = parse_mounts("a /var/lib ext4 rw 0 0\nb /var-lib ext4 rw 0 0\n");
+    assert_ne!(mounts[0].label(), mounts[1].label());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn mount_attributes() {
+    let tmp = TempDir::new("rsure").unwrap();
+    fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+    fs::write(tmp.path().join("a/file"), "data\n").unwrap();
+
+    let before: Vec<_> = scan_fs(tmp.path()).unwrap().map(|n| n.unwrap()).collect();
+    let dirs: Vec<_> = before.iter().filter(|n| n.is_enter()).collect();
+    assert_eq!(dirs.len(), 3);
+    let fstype = &dirs[0].atts().unwrap()["fstype"];
+    for dir in &dirs[1..] {
+        let atts = dir.atts().unwrap();
+        assert_eq!(&atts["fstype"], fstype);
+        assert!(!atts.contains_key("mount"));
+    }
+
+    // As if "a/b" had something else mounted on it.
+    let mut after = before.clone();
+    let b = after
+        .iter_mut()
+        .find(|n| n.is_enter() && n.name() == "b")
+        .unwrap();
+    let atts = b.atts_mut().unwrap();
+    atts.insert("fstype".into(), "nfs".into());
+    atts.insert("mount".into(), "1".into());
+
+    let mut changes = vec![];
+    compare_trees(
+        before.into_iter().map(Ok),
+        after.into_iter().map(Ok),
+        Path::new("."),
+        &[],
+        |c| changes.push(c),
+    )
+    .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path, Path::new("./a/b"));
+    assert_eq!(changes[0].attrs_changed, ["fstype", "mount"]);
+}
@@ -0,0 +1,62 @@
+// Temp files are checked against the checksum taken as they were written.
+
+use rsure::{load_from, parse_store};
+use std::{
+    fs::{self, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
+use tempdir::TempDir;
+
+const SURE: &[u8] = b"asure-2.0\n-----\nd__root__ []\n-\nu\n";
+
+#[test]
+fn corrupt_temp() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+
+    let mut temp = store.make_temp().unwrap();
+    temp.write_all(SURE).unwrap();
+    let loader = temp.into_loader().unwrap();
+
+    let mut data = vec![];
+    loader.new_loader().unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, SURE);
+    assert_eq!(load_from(loader.new_loader().unwrap()).unwrap().count(), 3);
+
+    // Flip a byte in the file, as if the disk had lost it.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(loader.path_ref())
+        .unwrap();
+    file.seek(SeekFrom::Start(20)).unwrap();
+    file.write_all(b"x").unwrap();
+    drop(file);
+
+    let err = loader
+        .new_loader()
+        .unwrap()
+        .read_to_end(&mut vec![])
+        .unwrap_err();
+    assert!(err.to_string().contains("checksum"), "{}", err);
+    let nodes: Vec<_> = load_from(loader.new_loader().unwrap()).unwrap().collect();
+    assert!(nodes.last().unwrap().is_err());
+
+    // Data cut short is caught too.
+    fs::write(loader.path_ref(), &SURE[..SURE.len() - 2]).unwrap();
+    assert!(loader
+        .new_loader()
+        .unwrap()
+        .read_to_end(&mut vec![])
+        .is_err());
+}
+
+#[test]
+fn trailing_data() {
+    let mut data = SURE.to_vec();
+    data.extend_from_slice(b"u\n");
+    // Data after the end of the tree is ignored, as older surefiles may
+    // have it.
+    let nodes: Vec<_> = load_from(&data[..]).unwrap().collect();
+    assert_eq!(nodes.len(), 3);
+    assert!(nodes.iter().all(|n| n.is_ok()));
+}
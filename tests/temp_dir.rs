@@ -0,0 +1,35 @@
+// Writing the temp files of updates away from the store.
+
+use rsure::{parse_store, StoreTags, Version};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn store_temp_dir() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    let temps = tmp.path().join("temps");
+    fs::create_dir(&temps).unwrap();
+    let dir = tmp.path().join("store");
+    fs::create_dir(&dir).unwrap();
+
+    let mut store = parse_store(dir.join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    store.set_temp_dir(&temps).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+
+    assert_eq!(fs::read_dir(&temps).unwrap().count(), 0);
+    let mut names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["2sure.bak.gz", "2sure.dat.gz", "2sure.lock"]);
+    assert_eq!(store.load_iter(Version::Latest).unwrap().count(), 5);
+}
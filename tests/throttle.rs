@@ -0,0 +1,72 @@
+// Throttling the hashing of an update.
+//
+// A throttled update must read no faster than its rate, shared between
+// the hashing threads, and still produce the same hashes.
+
+use rsure::{
+    node, parse_store, HashAlgorithm, ReadRate, StoreTags, Throttle, UpdateHooks, Version,
+};
+use std::{
+    fs,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+
+#[test]
+fn parse_rates() {
+    let rate = |text: &str| text.parse::<ReadRate>().unwrap().bytes_per_second();
+    assert_eq!(rate("50"), 50 << 20);
+    assert_eq!(rate("512K"), 512 << 10);
+    assert_eq!(rate("512K/s"), 512 << 10);
+    assert_eq!(rate("1G/s"), 1 << 30);
+    assert_eq!(ReadRate::new(20 << 20).to_string(), "20M/s");
+    assert!("".parse::<ReadRate>().is_err());
+    assert!("0".parse::<ReadRate>().is_err());
+    assert!("fast".parse::<ReadRate>().is_err());
+    assert!("10M/h".parse::<ReadRate>().is_err());
+}
+
+#[test]
+fn throttled_update() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for i in 0..20 {
+        fs::write(tree.join(format!("file{}", i)), vec![i as u8; 100 * 1024]).unwrap();
+    }
+
+    let algorithms = [HashAlgorithm::Sha256];
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "scan".into());
+    let scanned = |name: &str, hooks: UpdateHooks| {
+        let store = parse_store(tmp.path().join(name).to_str().unwrap()).unwrap();
+        rsure::update_with(&tree, &*store, false, &tags, &algorithms, hooks).unwrap();
+        let mut buf = vec![];
+        node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+
+    let full = scanned("full.dat.gz", UpdateHooks::default());
+
+    // 2M of files at 4M/s takes at least half a second, however many
+    // threads share the throttle.
+    let start = Instant::now();
+    let throttled = scanned(
+        "throttled.dat.gz",
+        UpdateHooks {
+            throttle: Some(Arc::new(Throttle::new(ReadRate::new(4 << 20)))),
+            background: true,
+            ..UpdateHooks::default()
+        },
+    );
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    assert_eq!(throttled.lines().count(), full.lines().count());
+    let hashes = |text: &str| -> Vec<String> {
+        text.lines()
+            .filter_map(|line| line.split("sha256 ").nth(1))
+            .map(|rest| rest.to_string())
+            .collect()
+    };
+    assert_eq!(hashes(&throttled), hashes(&full));
+}
@@ -0,0 +1,40 @@
+// Committing versions with explicit timestamps.
+//
+// Replayed snapshots should carry the time they were originally captured,
+// not the time they were added to the store.
+
+use rsure::{clock, parse_store, FixedClock, HashAlgorithm, StoreTags};
+use std::{fs::File, io::Write};
+use tempdir::TempDir;
+
+#[test]
+fn explicit_timestamps() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    std::fs::create_dir(&tree).unwrap();
+    File::create(tree.join("file"))
+        .unwrap()
+        .write_all(b"hello\n")
+        .unwrap();
+
+    let path = tmp.path().join("2sure.weave.gz");
+    let times = ["2019-03-01T09:30:00+00:00", "2019-03-31T02:40:00+00:00"];
+    for (i, time) in times.iter().enumerate() {
+        let mut store = parse_store(path.to_str().unwrap()).unwrap();
+        store.set_clock(Box::new(FixedClock(clock::parse_time(time).unwrap())));
+        let mut tags = StoreTags::new();
+        tags.insert("name".into(), format!("snap{}", i));
+        rsure::update(&tree, &*store, i > 0, &tags, &[HashAlgorithm::Sha1]).unwrap();
+    }
+
+    let store = parse_store(path.to_str().unwrap()).unwrap();
+    let found: Vec<_> = store
+        .get_versions()
+        .unwrap()
+        .iter()
+        .map(|v| v.time.to_rfc3339())
+        .collect();
+    assert_eq!(found, [times[1], times[0]]);
+
+    assert!(clock::parse_time("yesterday").is_err());
+}
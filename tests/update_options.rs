@@ -0,0 +1,75 @@
+// Building the settings of an update.
+
+use rsure::{
+    parse_store, Exclude, HashAlgorithm, ProgressEvent, UpdateHooks, UpdateOptions, Version,
+    EXCLUDED_TAG,
+};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+use tempdir::TempDir;
+
+#[test]
+fn update_options() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("cache")).unwrap();
+    fs::write(tree.join("cache").join("junk"), "junk\n").unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+
+    let mut exclude = Exclude::new();
+    exclude.add("cache").unwrap();
+    let events = Arc::new(Mutex::new(0));
+    let record = events.clone();
+    UpdateOptions::new()
+        .with_tag("name", "first")
+        .with_algorithms(&[HashAlgorithm::Sha256])
+        .with_jobs(1)
+        .with_exclude(exclude)
+        .with_progress(Arc::new(move |_: &ProgressEvent| {
+            *record.lock().unwrap() += 1
+        }))
+        .run(&tree, &*store)
+        .unwrap();
+    assert!(*events.lock().unwrap() > 0);
+
+    let versions = store.get_versions().unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].name, "first");
+    assert_eq!(versions[0].tags["hash"], "sha256");
+    let names: Vec<_> = store
+        .load_iter(Version::Latest)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .filter(|n| n.is_enter() || n.is_file())
+        .map(|n| n.name().to_string())
+        .collect();
+    assert_eq!(names, ["__root__", "a"]);
+
+    fs::write(tree.join("b"), "b\n").unwrap();
+    UpdateOptions::new()
+        .with_update(true)
+        .with_tag("name", "second")
+        .run(&tree, &*store)
+        .unwrap();
+    assert_eq!(store.get_versions().unwrap()[0].name, "second");
+
+    // Hooks are a starting point, which the methods then add to.
+    let mut exclude = Exclude::new();
+    exclude.add("b").unwrap();
+    let hooks = UpdateHooks {
+        pipelined: true,
+        ..UpdateHooks::default()
+    };
+    UpdateOptions::from_hooks(hooks)
+        .with_tag("name", "third")
+        .with_exclude(exclude)
+        .run(&tree, &*store)
+        .unwrap();
+    assert_eq!(store.get_versions().unwrap()[0].name, "third");
+    assert!(store.get_versions().unwrap()[0]
+        .tags
+        .contains_key(EXCLUDED_TAG));
+}
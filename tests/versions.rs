@@ -0,0 +1,100 @@
+// Selecting versions of a store.
+
+use chrono::{Local, TimeZone, Utc};
+use rsure::{parse_store, version_name, Error, Store, StoreTags, SureNode, Version};
+use std::fs;
+use tempdir::TempDir;
+
+fn names(store: &dyn Store, version: Version) -> Vec<String> {
+    store
+        .load_iter(version)
+        .unwrap()
+        .map(|n| n.unwrap())
+        .filter(SureNode::is_file)
+        .map(|n| n.name().to_string())
+        .collect()
+}
+
+#[test]
+fn parse() {
+    let parse = |text: &str| text.parse::<Version>().unwrap().to_string();
+    assert_eq!(parse("latest"), "latest");
+    assert_eq!(parse("prior"), "prior");
+    assert_eq!(parse("12"), "12");
+    assert_eq!(parse("name:monthly"), "name:monthly");
+    assert!(matches!(
+        "name:x:y".parse(),
+        Ok(Version::Named(ref name)) if name == "x:y"
+    ));
+    assert!("name:".parse::<Version>().is_err());
+}
+
+#[test]
+fn named() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    for (name, file) in &[("monthly", "b"), ("daily", "c"), ("monthly", "d")] {
+        fs::write(tree.join(file), "data\n").unwrap();
+        tags.insert("name".into(), name.to_string());
+        rsure::update(&tree, &*store, file != &"b", &tags, &[]).unwrap();
+    }
+
+    assert_eq!(
+        names(&*store, Version::Named("daily".into())),
+        ["a", "b", "c"]
+    );
+    // The latest of several with the same name.
+    assert_eq!(
+        names(&*store, Version::Named("monthly".into())),
+        ["a", "b", "c", "d"]
+    );
+    let daily = store
+        .get_version(&Version::Named("daily".into()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(daily.version.numeric(), Some(2));
+
+    let missing = Version::Named("weekly".into());
+    assert!(store.get_version(&missing).unwrap().is_none());
+    assert!(matches!(
+        store.load_iter(missing.clone()),
+        Err(Error::UnknownVersion(_))
+    ));
+    assert!(store.delete_version(missing).is_err());
+
+    store
+        .delete_version(Version::Named("daily".into()))
+        .unwrap();
+    assert!(store
+        .get_version(&Version::Named("daily".into()))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn default_names() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    let store = parse_store(tmp.path().join("2sure.dat.gz").to_str().unwrap()).unwrap();
+    let time = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+    let base = time.with_timezone(&Local).to_rfc3339();
+    assert_eq!(version_name(&*store, time).unwrap(), base);
+
+    // Versions made within the same second are told apart.
+    let mut tags = StoreTags::new();
+    for expect in [base.clone(), format!("{}-2", base), format!("{}-3", base)] {
+        let name = version_name(&*store, time).unwrap();
+        assert_eq!(name, expect);
+        tags.insert("name".into(), name);
+        rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    }
+    assert_eq!(store.get_versions().unwrap().len(), 3);
+}
@@ -0,0 +1,91 @@
+// Watching a tree for changes.
+//
+// The watch should commit just what is touched, including in directories
+// made while watching, without a full scan.
+
+#![cfg(target_os = "linux")]
+
+use rsure::{
+    node, parse_store, watch::Watch, CancellationToken, HashAlgorithm, Store, StoreTags,
+    UpdateHooks, Version,
+};
+use std::{
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+
+/// Wait for the store to have the given number of versions.
+fn wait_for_versions(store: &Path, count: usize) {
+    let start = Instant::now();
+    loop {
+        let store = parse_store(store.to_str().unwrap()).unwrap();
+        if store.get_versions().map_or(0, |v| v.len()) >= count {
+            return;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "no version {}",
+            count
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn watch() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir_all(tree.join("dir")).unwrap();
+    fs::write(tree.join("file"), "old\n").unwrap();
+    fs::write(tree.join("dir/kept"), "kept\n").unwrap();
+    let path = tmp.path().join("2sure.weave.gz");
+
+    let cancel = CancellationToken::new();
+    let changer = {
+        let (tree, path, cancel) = (tree.clone(), path.clone(), cancel.clone());
+        thread::spawn(move || {
+            // The first version is the full update once the watches are in.
+            wait_for_versions(&path, 1);
+            fs::write(tree.join("file"), "new\n").unwrap();
+            fs::create_dir_all(tree.join("made/deep")).unwrap();
+            fs::write(tree.join("made/deep/file"), "deep\n").unwrap();
+            wait_for_versions(&path, 2);
+            cancel.cancel();
+        })
+    };
+
+    let store = parse_store(path.to_str().unwrap()).unwrap();
+    Watch::new(&tree, &*store, &[HashAlgorithm::Sha256])
+        .with_tags(&StoreTags::new())
+        .with_interval(Duration::from_millis(500))
+        .with_cancel(cancel)
+        .run(|| Ok(UpdateHooks::default()))
+        .unwrap();
+    changer.join().unwrap();
+
+    // Just the touched paths, but the same as scanning the whole tree.
+    assert_eq!(store.get_versions().unwrap().len(), 2);
+    let text = |store: &dyn Store| {
+        let mut buf = vec![];
+        node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    let watched = text(&*store);
+    let full = parse_store(tmp.path().join("full.dat.gz").to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "full".into());
+    rsure::update(&tree, &*full, false, &tags, &[HashAlgorithm::Sha256]).unwrap();
+    let full = text(&*full);
+    let hashes = |text: &str| -> Vec<String> {
+        text.lines()
+            .filter_map(|line| line.split("sha256 ").nth(1))
+            .map(|rest| rest.to_string())
+            .collect()
+    };
+    assert_eq!(hashes(&watched).len(), 3);
+    assert_eq!(hashes(&watched), hashes(&full));
+    assert!(watched.contains("deep"), "{}", watched);
+}
@@ -0,0 +1,32 @@
+// The sizes of a store and its versions.
+
+use rsure::{parse_store, StoreTags};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn weave_stats() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("a"), "a\n").unwrap();
+
+    let path = tmp.path().join("2sure.dat.gz");
+    let store = parse_store(path.to_str().unwrap()).unwrap();
+    let mut tags = StoreTags::new();
+    tags.insert("name".into(), "first".into());
+    rsure::update(&tree, &*store, false, &tags, &[]).unwrap();
+    fs::write(tree.join("b"), "b\n").unwrap();
+    tags.insert("name".into(), "second".into());
+    rsure::update(&tree, &*store, true, &tags, &[]).unwrap();
+
+    let stats = store.weave_stats().unwrap();
+    assert_eq!(stats.stored, fs::metadata(&path).unwrap().len());
+    assert!(stats.plain > stats.stored);
+    for v in store.get_versions().unwrap() {
+        // Each node is a line, following the two lines starting a surefile.
+        let nodes = store.load_iter(v.version.clone()).unwrap().count();
+        let lines = stats.delta_lines[&v.version.numeric().unwrap()];
+        assert_eq!(lines, nodes + 2, "{}", v.name);
+    }
+}
@@ -0,0 +1,37 @@
+// Zstd compressed stores.
+
+use rsure::{node, parse_store, HashAlgorithm, StoreTags, Version};
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn zstd_store() {
+    let tmp = TempDir::new("rsure").unwrap();
+    let tree = tmp.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("file"), "hello\n").unwrap();
+
+    let path = tmp.path().join("2sure.dat.zstd");
+    for name in &["first", "second"] {
+        let store = parse_store(path.to_str().unwrap()).unwrap();
+        let mut tags = StoreTags::new();
+        tags.insert("name".into(), name.to_string());
+        let is_update = *name != "first";
+        rsure::update(&tree, &*store, is_update, &tags, &[HashAlgorithm::Sha1]).unwrap();
+        fs::write(tree.join(name), "more\n").unwrap();
+    }
+
+    // The zstd frame magic number.
+    assert_eq!(&fs::read(&path).unwrap()[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+
+    // Naming the directory finds the existing zstd store.
+    let store = parse_store(tmp.path().to_str().unwrap()).unwrap();
+    let versions = store.get_versions().unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].name, "second");
+
+    let mut buf = vec![];
+    node::save_to(&mut buf, store.load_iter(Version::Latest).unwrap()).unwrap();
+    let text = String::from_utf8(buf).unwrap();
+    assert!(text.contains("\nffirst "));
+}
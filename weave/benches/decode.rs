@@ -0,0 +1,52 @@
+// Benchmark reading a blocked weave, decompressing its blocks on other threads.
+//
+// This runs on stable Rust, without the `test` crate, so the timing is done by hand.  Run with
+// `cargo bench`.  Zero threads reads the blocks in turn, on the parsing thread.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+use weave::{Compression, Entry, NewWeave, PullParser, SimpleNaming};
+
+const ITERATIONS: u32 = 10;
+
+/// Lines in the weave, about 16MiB of text.
+const LINES: usize = 400_000;
+
+fn main() {
+    let tmp = TempDir::new("weave-bench").unwrap();
+    for &block_size in &[16 * 1024, 256 * 1024] {
+        let nc = SimpleNaming::new(tmp.path(), "bench", "weave", Compression::Gzip)
+            .with_block_size(block_size);
+        let mut tags = BTreeMap::new();
+        tags.insert("name", "bench");
+        let mut nw = NewWeave::new(&nc, tags.into_iter()).unwrap();
+        for i in 0..LINES {
+            writeln!(nw, "file {:08} sha1=0123456789abcdef0123456789abcdef", i).unwrap();
+        }
+        nw.close().unwrap();
+
+        for &threads in &[0, 1, 2, 4] {
+            let nc = nc.clone().with_decode_threads(threads);
+            let mut total = Duration::default();
+            for _ in 0..ITERATIONS {
+                let start = Instant::now();
+                let count = PullParser::new(&nc, 1)
+                    .unwrap()
+                    .filter(|e| matches!(e, Ok(Entry::Plain { keep: true, .. })))
+                    .count();
+                total += start.elapsed();
+                assert_eq!(count, LINES);
+            }
+            println!(
+                "{:>4}K blocks, {} threads {:>12?}/iter",
+                block_size / 1024,
+                threads,
+                total / ITERATIONS
+            );
+        }
+    }
+}
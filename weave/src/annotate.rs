@@ -0,0 +1,46 @@
+//! Annotate the lines of a delta with the delta that added each of them.
+//!
+//! This is the SCCS `get -m`, or the `blame` of other systems.  The delta given for a line is the
+//! one whose insert decides that the line is kept, which is the last delta, up to the one being
+//! read, to have added it.
+
+use crate::{Entry, NamingConvention, PullParser, Result};
+use std::io::{BufReader, Read};
+
+/// A line of a delta, and the delta that added it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnnotatedLine {
+    pub delta: usize,
+    pub text: String,
+}
+
+/// An iterator over the lines of a delta, each with the delta that added it.
+pub struct Annotator {
+    parser: PullParser<BufReader<Box<dyn Read>>>,
+}
+
+impl Annotator {
+    /// Annotate the given delta of the main file of the naming convention.
+    pub fn new(naming: &dyn NamingConvention, delta: usize) -> Result<Annotator> {
+        Ok(Annotator {
+            parser: PullParser::new(naming, delta)?,
+        })
+    }
+}
+
+impl Iterator for Annotator {
+    type Item = Result<AnnotatedLine>;
+
+    fn next(&mut self) -> Option<Result<AnnotatedLine>> {
+        loop {
+            match self.parser.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(Entry::Plain { text, keep: true }) => {
+                    let delta = self.parser.introduced().expect("kept line has an insert");
+                    return Some(Ok(AnnotatedLine { delta, text }));
+                }
+                Ok(_) => (),
+            }
+        }
+    }
+}
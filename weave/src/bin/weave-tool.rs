@@ -0,0 +1,269 @@
+//! Keep the versions of a text file in a weave.
+//!
+//! This is a small front end to the weave crate, for files other than
+//! rsure's, such as configuration files.  The weave is named by its main
+//! file, such as `hosts.weave.gz`, whose suffix gives the compression.
+
+use chrono::Local;
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process,
+};
+use weave::{
+    extract, fsck, get_last_delta, read_header, Annotator, Compression, DeltaWriter,
+    NamingConvention, NewWeave, SimpleNaming, SystemClock,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const USAGE: &str = "\
+usage: weave-tool <command> WEAVE [args]
+
+commands:
+    init WEAVE FILE [-n NAME] [-t KEY=VALUE]...
+                            Make a new weave holding FILE
+    add WEAVE FILE [-n NAME] [-t KEY=VALUE]...
+                            Add FILE to the weave as a new delta
+    extract WEAVE [-d DELTA]
+                            Write a delta, by default the latest
+    list WEAVE              List the deltas of the weave
+    annotate WEAVE [-d DELTA]
+                            Write a delta, with the delta adding each line
+    fsck WEAVE              Check the structure of the weave
+
+WEAVE is the weave file, such as hosts.weave.gz, with a .gz or .zstd
+suffix to compress it.  FILE is a UTF-8 text file, or \"-\" to read the
+standard input.  DELTA is a delta number, or else a name.  A delta is
+named with the time it was added, if not given a name.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("weave-tool: {}", e);
+        let mut source = e.source();
+        while let Some(e) = source {
+            eprintln!("    caused by: {}", e);
+            source = e.source();
+        }
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (command, path, rest) = match args {
+        [command, path, rest @ ..] => (command.as_str(), path.as_str(), rest),
+        [help] if help == "-h" || help == "--help" => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        _ => usage(),
+    };
+    let naming = naming(path)?;
+    let opts = Options::parse(rest)?;
+
+    match command {
+        "init" => {
+            if naming.main_file().exists() {
+                return Err(format!("{} already exists", naming.main_file().display()).into());
+            }
+            let text = read_text(opts.file()?)?;
+            let tags = opts.tags();
+            let mut weave = NewWeave::new(&naming, tags.iter().map(tag))?;
+            weave.write_all(text.as_bytes())?;
+            weave.close()?;
+        }
+        "add" => {
+            let text = read_text(opts.file()?)?;
+            let tags = opts.tags();
+            let mut weave = DeltaWriter::on_latest(&naming, tags.iter().map(tag), &SystemClock)?;
+            weave.write_all(text.as_bytes())?;
+            weave.close()?;
+        }
+        "extract" => {
+            let delta = opts.delta(&naming)?;
+            extract(&naming, delta, io::stdout().lock())?;
+        }
+        "list" => {
+            opts.no_args()?;
+            let header = read_header(&naming)?;
+            println!("delta | Time added          | name");
+            println!("------+---------------------+------------------");
+            for d in &header.deltas {
+                let time = d.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+                println!("{:>5} | {} | {}", d.number, time, d.name);
+            }
+        }
+        "annotate" => {
+            let delta = opts.delta(&naming)?;
+            let stdout = io::stdout();
+            let mut out = io::BufWriter::new(stdout.lock());
+            for line in Annotator::new(&naming, delta)? {
+                let line = line?;
+                writeln!(out, "{:>5}: {}", line.delta, line.text)?;
+            }
+            out.flush()?;
+        }
+        "fsck" => {
+            opts.no_args()?;
+            let problems = fsck(&naming)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("{} problems found", problems.len()).into());
+            }
+        }
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// The options given after the weave.
+#[derive(Default)]
+struct Options {
+    file: Option<String>,
+    name: Option<String>,
+    tags: BTreeMap<String, String>,
+    delta: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options> {
+        let mut opts = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "-n" | "--name" => opts.name = Some(value()?.clone()),
+                "-d" | "--delta" => opts.delta = Some(value()?.clone()),
+                "-t" | "--tag" => {
+                    let text = value()?;
+                    let (key, value) = text
+                        .split_once('=')
+                        .ok_or_else(|| format!("Tag {:?} should be KEY=VALUE", text))?;
+                    opts.tags.insert(key.to_string(), value.to_string());
+                }
+                _ if opts.file.is_none() && (arg == "-" || !arg.starts_with('-')) => {
+                    opts.file = Some(arg.clone())
+                }
+                _ => return Err(format!("Unexpected argument {:?}", arg).into()),
+            }
+        }
+        Ok(opts)
+    }
+
+    fn file(&self) -> Result<&str> {
+        match &self.file {
+            Some(file) if self.delta.is_none() => Ok(file),
+            Some(_) => Err("-d is only for extract and annotate".into()),
+            None => Err("No FILE given".into()),
+        }
+    }
+
+    /// The tags of a new delta, including its name.
+    fn tags(&self) -> BTreeMap<String, String> {
+        let mut tags = self.tags.clone();
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => Local::now().to_rfc3339(),
+        };
+        tags.insert("name".to_string(), name);
+        tags
+    }
+
+    /// The delta number given, or else the latest.
+    fn delta(&self, naming: &dyn NamingConvention) -> Result<usize> {
+        if self.file.is_some() || self.name.is_some() || !self.tags.is_empty() {
+            return Err("Only -d is taken here".into());
+        }
+        match &self.delta {
+            None => Ok(get_last_delta(naming)?),
+            Some(text) => {
+                let header = read_header(naming)?;
+                let number = match text.parse() {
+                    Ok(number) => Some(number),
+                    Err(_) => header.find_delta(text),
+                };
+                number
+                    .filter(|n| header.deltas.iter().any(|d| d.number == *n))
+                    .ok_or_else(|| format!("No delta {:?} in the weave", text).into())
+            }
+        }
+    }
+
+    fn no_args(&self) -> Result<()> {
+        if self.file.is_some()
+            || self.name.is_some()
+            || self.delta.is_some()
+            || !self.tags.is_empty()
+        {
+            return Err("list and fsck take no options".into());
+        }
+        Ok(())
+    }
+}
+
+fn tag<'a>((key, value): (&'a String, &'a String)) -> (&'a str, &'a str) {
+    (key, value)
+}
+
+/// The naming convention of a weave, from the name of its main file.
+fn naming(path: &str) -> Result<SimpleNaming> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid weave name {:?}", path))?;
+    let (name, compression) = if let Some(name) = name.strip_suffix(".gz") {
+        (name, Compression::Gzip)
+    } else if let Some(name) = name.strip_suffix(".zstd") {
+        (name, Compression::Zstd)
+    } else {
+        (name, Compression::Plain)
+    };
+    match name.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() && !ext.is_empty() => {
+            Ok(SimpleNaming::new(dir, base, ext, compression))
+        }
+        _ => Err(format!(
+            "Weave {:?} should have an extension, such as {}.weave.gz",
+            path, name
+        )
+        .into()),
+    }
+}
+
+/// Read a text file to add to the weave.  The weave holds lines of text,
+/// so the last line is given a newline, if it doesn't have one, and no line
+/// may start with a control-A, which marks the weave's own lines.
+fn read_text(file: &str) -> Result<String> {
+    let data = if file == "-" {
+        let mut data = vec![];
+        io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        fs::read(file)?
+    };
+    let mut text = String::from_utf8(data).map_err(|_| format!("{} is not UTF-8 text", file))?;
+    if text.lines().any(|line| line.starts_with('\x01')) {
+        return Err(format!("{} has a line starting with control-A", file).into());
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok(text)
+}
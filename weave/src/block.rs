@@ -0,0 +1,415 @@
+//! Blocked weave files.
+//!
+//! A plain weave file is a single compressed stream, so reading any delta, or even just the
+//! header, has to decompress the entire history.  A blocked weave is instead written as a series
+//! of independently compressed frames: one holding just the header line, followed by the body of
+//! the weave split into blocks of roughly [`DEFAULT_BLOCK_SIZE`] bytes of text.  Concatenated, the
+//! frames are still a valid gzip or zstd stream of the whole weave.
+//!
+//! The header of a blocked weave has version 2, and lists the compressed size of each block, along
+//! with the deltas that have at least one line in that block.  A reader after a single delta only
+//! decompresses the blocks that contribute to it, which, for the latest delta of a long history,
+//! skips the blocks holding nothing but lines deleted long ago.  So that a block can be read on
+//! its own, each block begins with a `\x01S` control line giving the inserts and deletes that are
+//! open at that point.
+//!
+//! Readers decide how to read a file from its header, so plain weaves written by earlier versions
+//! are still read as a single stream.
+
+use crate::{
+    decode::ThreadReader, decrypt_from, header::Header, Compression, EncryptWrite, Error,
+    NamingConvention, Result,
+};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{remove_file, File},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+/// The default amount of uncompressed text in each block of a blocked weave.
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// The header version written for blocked weaves.
+pub(crate) const BLOCKED_VERSION: usize = 2;
+
+/// A range of delta numbers, from the first up to, but not including, the second.  `None` for the
+/// end includes all later deltas.
+pub type DeltaRange = (usize, Option<usize>);
+
+/// Information about one block of a blocked weave.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockInfo {
+    /// The size of the compressed block, in bytes.
+    pub size: u64,
+    /// The deltas that keep at least one line of this block.
+    pub live: Vec<DeltaRange>,
+}
+
+impl BlockInfo {
+    /// Does this block hold any lines of the given delta?
+    pub fn has_delta(&self, delta: usize) -> bool {
+        self.live
+            .iter()
+            .any(|&(start, end)| delta >= start && !matches!(end, Some(end) if delta >= end))
+    }
+}
+
+/// A writer for a temp file that can report errors when it is finished.
+pub(crate) trait WeaveWrite: Write {
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl WeaveWrite for BufWriter<Box<dyn EncryptWrite>> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.into_inner().map_err(|e| e.into_error())?.finish()
+    }
+}
+
+impl WeaveWrite for GzEncoder<Box<dyn EncryptWrite>> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        GzEncoder::finish(*self)?.finish()
+    }
+}
+
+impl WeaveWrite for zstd::Encoder<'static, Box<dyn EncryptWrite>> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        zstd::Encoder::finish(*self)?.finish()
+    }
+}
+
+/// Compress a single frame.
+fn compress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::Plain => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut enc = GzEncoder::new(vec![], flate2::Compression::default());
+            enc.write_all(data)?;
+            enc.finish()
+        }
+        Compression::Zstd => zstd::encode_all(data, 3),
+    }
+}
+
+/// A decoder for a single frame.
+pub(crate) fn decoder<R>(rd: R, compression: Compression) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    Ok(match compression {
+        Compression::Plain => Box::new(rd),
+        Compression::Gzip => Box::new(GzDecoder::new(rd)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(rd)?.single_frame()),
+    })
+}
+
+/// Open the main file of the naming convention, as a single stream of the whole weave, whether it
+/// is blocked or not.
+pub(crate) fn open_all(naming: &dyn NamingConvention) -> Result<Box<dyn Read>> {
+    let threads = naming.decode_threads();
+    if threads > 0 {
+        // The blocks of a blocked weave can be decoded in parallel.
+        let header = read_header(naming)?;
+        if let Some(blocks) = &header.blocks {
+            let mut line = vec![];
+            header.write(&mut line)?;
+            let rest = open_blocks(naming, blocks, |_| true)?;
+            return Ok(Box::new(Cursor::new(line).chain(rest)));
+        }
+    }
+
+    let fd = decrypt_from(naming, &naming.main_file())?;
+    let rd: Box<dyn Read + Send> = match naming.compression() {
+        Compression::Plain => Box::new(fd),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(fd)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(fd)?),
+    };
+    if threads > 0 {
+        Ok(Box::new(ThreadReader::stream(rd, threads)))
+    } else {
+        Ok(rd)
+    }
+}
+
+/// Open the blocks of a blocked weave for which `wanted` is true, to be read one after the
+/// other.
+pub(crate) fn open_blocks<F>(
+    naming: &dyn NamingConvention,
+    blocks: &[BlockInfo],
+    wanted: F,
+) -> Result<Box<dyn Read>>
+where
+    F: Fn(&BlockInfo) -> bool,
+{
+    if naming.cipher().is_some() {
+        return Err(Error::EncryptedBlocks);
+    }
+    let file = File::open(naming.main_file())?;
+    // The blocks are at the end of the file, following the header.
+    let body: u64 = blocks.iter().map(|b| b.size).sum();
+    let mut offset = file
+        .metadata()?
+        .len()
+        .checked_sub(body)
+        .ok_or(Error::UnexpectedEof)?;
+    let mut selected = VecDeque::new();
+    for block in blocks {
+        if wanted(block) {
+            selected.push_back((offset, block.size));
+        }
+        offset += block.size;
+    }
+
+    let compression = naming.compression();
+    Ok(match naming.decode_threads() {
+        0 => Box::new(BlockReader {
+            file,
+            compression,
+            blocks: selected,
+            current: None,
+        }),
+        threads => Box::new(ThreadReader::blocks(file, compression, selected, threads)),
+    })
+}
+
+/// Read just the header of the main file.  In a blocked weave, this is the whole first frame.
+pub(crate) fn read_header(naming: &dyn NamingConvention) -> Result<Header> {
+    let fd = decrypt_from(naming, &naming.main_file())?;
+    let mut rd = BufReader::new(decoder(fd, naming.compression())?);
+    let mut line = String::new();
+    if rd.read_line(&mut line)? == 0 {
+        return Err(Error::EmptyWeave);
+    }
+    Header::decode(line.trim_end_matches('\n'))
+}
+
+/// Reads the wanted blocks of a blocked weave, one after the other.
+struct BlockReader {
+    file: File,
+    compression: Compression,
+    // The offset and size of each block still to be read.
+    blocks: VecDeque<(u64, u64)>,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(current) = &mut self.current {
+                let n = current.read(buf)?;
+                if n > 0 {
+                    return Ok(n);
+                }
+                self.current = None;
+            }
+            match self.blocks.pop_front() {
+                None => return Ok(0),
+                Some((offset, size)) => {
+                    let mut fd = self.file.try_clone()?;
+                    fd.seek(SeekFrom::Start(offset))?;
+                    self.current = Some(decoder(fd.take(size), self.compression)?);
+                }
+            }
+        }
+    }
+}
+
+/// Writes a weave, header line first, as a blocked weave.  The blocks are compressed into a
+/// separate temp file, and only copied after the header, which indexes them, once everything has
+/// been written.
+pub(crate) struct BlockWriter {
+    dest: File,
+    body: File,
+    body_name: PathBuf,
+    compression: Compression,
+    block_size: usize,
+
+    // The header, once its line has been written.
+    header: Option<Header>,
+    // Any partial line written.
+    pending: Vec<u8>,
+    // The text of the current block.
+    block: Vec<u8>,
+    // The deltas that keep lines of the current block.
+    live: Vec<DeltaRange>,
+    blocks: Vec<BlockInfo>,
+
+    // The open inserts and deletes, newest delta first, as in the parser, and the deltas that
+    // keep a line with this state.
+    state: Vec<(usize, u8)>,
+    state_live: Vec<DeltaRange>,
+}
+
+impl BlockWriter {
+    pub(crate) fn new(
+        dest: File,
+        body: (PathBuf, File),
+        compression: Compression,
+        block_size: usize,
+    ) -> BlockWriter {
+        BlockWriter {
+            dest,
+            body: body.1,
+            body_name: body.0,
+            compression,
+            block_size,
+            header: None,
+            pending: vec![],
+            block: vec![],
+            live: vec![],
+            blocks: vec![],
+            state: vec![],
+            state_live: vec![],
+        }
+    }
+
+    fn line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.header.is_none() {
+            let text = String::from_utf8_lossy(line);
+            let header = Header::decode(text.trim_end_matches('\n')).map_err(invalid)?;
+            self.header = Some(header);
+            return Ok(());
+        }
+
+        if self.block.is_empty() {
+            self.block.extend_from_slice(b"\x01S");
+            for (delta, kind) in &self.state {
+                write!(&mut self.block, " {}{}", *kind as char, delta)?;
+            }
+            self.block.push(b'\n');
+        }
+
+        match line {
+            [b'\x01', kind @ (b'I' | b'D' | b'E'), b' ', number @ ..] => {
+                let number = String::from_utf8_lossy(number);
+                let delta: usize = number.trim_end().parse().map_err(invalid)?;
+                match self.state.binary_search_by(|ent| delta.cmp(&ent.0)) {
+                    Ok(pos) if *kind == b'E' => {
+                        self.state.remove(pos);
+                    }
+                    Err(pos) if *kind != b'E' => self.state.insert(pos, (delta, *kind)),
+                    _ => return Err(invalid("unbalanced weave control line")),
+                }
+                self.state_live = live_ranges(&self.state);
+            }
+            [b'\x01', ..] => (),
+            _ => add_ranges(&mut self.live, &self.state_live),
+        }
+
+        self.block.extend_from_slice(line);
+        if self.block.len() >= self.block_size {
+            self.end_block()?;
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let data = compress(&self.block, self.compression)?;
+        self.body.write_all(&data)?;
+        self.blocks.push(BlockInfo {
+            size: data.len() as u64,
+            live: std::mem::take(&mut self.live),
+        });
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl Write for BlockWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(pos) = self.pending.iter().position(|&ch| ch == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            self.line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WeaveWrite for BlockWriter {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line)?;
+        }
+        self.end_block()?;
+
+        let mut header = self.header.take().ok_or(Error::EmptyWeave)?;
+        header.version = BLOCKED_VERSION;
+        header.blocks = Some(std::mem::take(&mut self.blocks));
+        let mut line = vec![];
+        header.write(&mut line)?;
+        self.dest.write_all(&compress(&line, self.compression)?)?;
+
+        // The temp file was opened only for writing.
+        io::copy(&mut File::open(&self.body_name)?, &mut self.dest)?;
+        self.dest.flush()?;
+        remove_file(&self.body_name)?;
+        Ok(())
+    }
+}
+
+fn invalid<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// The deltas that keep a line, given the open inserts and deletes, newest first.  This follows
+/// the same rules as the parser: the newest insert, or the newest delete that isn't newer than the
+/// delta, decides.  The answer can only change at the deltas named in the state.
+pub(crate) fn live_ranges(state: &[(usize, u8)]) -> Vec<DeltaRange> {
+    let keeps = |delta: usize| {
+        for &(number, kind) in state {
+            match kind {
+                b'I' => return delta >= number,
+                _ if delta >= number => return false,
+                _ => (),
+            }
+        }
+        false
+    };
+
+    let mut points: Vec<usize> = state.iter().map(|&(number, _)| number).collect();
+    points.reverse();
+    let mut result = vec![];
+    for (i, &start) in points.iter().enumerate() {
+        if keeps(start) {
+            add_ranges(&mut result, &[(start, points.get(i + 1).copied())]);
+        }
+    }
+    result
+}
+
+/// Add ranges to a set, merging those that overlap or touch.
+fn add_ranges(set: &mut Vec<DeltaRange>, ranges: &[DeltaRange]) {
+    if ranges.iter().all(|r| set.contains(r)) {
+        return;
+    }
+    set.extend_from_slice(ranges);
+    set.sort_unstable();
+    let mut merged: Vec<DeltaRange> = Vec::with_capacity(set.len());
+    for &(start, end) in set.iter() {
+        match merged.last_mut() {
+            Some((_, last_end)) if !matches!(*last_end, Some(e) if start > e) => {
+                match (end, *last_end) {
+                    (None, _) => *last_end = None,
+                    (Some(e), Some(last)) if e > last => *last_end = end,
+                    _ => (),
+                }
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    *set = merged;
+}
@@ -0,0 +1,61 @@
+//! Encrypted weave files.
+//!
+//! A weave holds every version of its file, so the files of the weave may need to be kept from
+//! anyone who can read the disk.  A naming convention can give a [`Cipher`], which every file the
+//! weave writes passes through: the main file, and so the backup file it is renamed to, and the
+//! temp files used while writing it.
+//!
+//! A blocked weave is read by seeking to its blocks, which an encrypted stream doesn't allow, so
+//! an encrypted weave is always written as a single stream.
+
+use crate::{NamingConvention, Result};
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// Encrypts the files written by the weave, and decrypts them when they are read.
+pub trait Cipher: fmt::Debug + Send + Sync {
+    /// Start writing an encrypted file to `dest`.
+    fn encrypt(&self, dest: File) -> Result<Box<dyn EncryptWrite>>;
+
+    /// Start reading the encrypted file `src`.
+    fn decrypt(&self, src: File) -> Result<Box<dyn Read + Send>>;
+}
+
+/// A writer to a file, which may need to finish the file once everything is written.
+pub trait EncryptWrite: Write + Send {
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl EncryptWrite for File {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+/// Start writing a file of the weave, encrypted if the naming convention has a cipher.
+pub fn encrypt_to<N>(naming: &N, dest: File) -> Result<Box<dyn EncryptWrite>>
+where
+    N: NamingConvention + ?Sized,
+{
+    match naming.cipher() {
+        Some(cipher) => cipher.encrypt(dest),
+        None => Ok(Box::new(dest)),
+    }
+}
+
+/// Open a file of the weave for reading, decrypting it if the naming convention has a cipher.
+pub fn decrypt_from<N>(naming: &N, path: &Path) -> Result<Box<dyn Read + Send>>
+where
+    N: NamingConvention + ?Sized,
+{
+    let src = File::open(path)?;
+    match naming.cipher() {
+        Some(cipher) => cipher.decrypt(src),
+        None => Ok(Box::new(src)),
+    }
+}
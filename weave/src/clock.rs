@@ -0,0 +1,31 @@
+//! Sources of time.
+//!
+//! Each delta records the time it was added.  This time comes from a [`Clock`], so that tests can
+//! freeze time, and importers can add deltas with their original, historical timestamps.
+
+use chrono::{DateTime, Utc};
+
+/// Something that can tell us the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The clock that reads the system time.  This is the default for new deltas.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that is stopped at a given time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
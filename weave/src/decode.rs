@@ -0,0 +1,146 @@
+//! Decompressing on other threads.
+//!
+//! Decompression, especially of gzip, can be the slowest part of reading a large weave.  A single
+//! compressed stream can at least be decompressed on its own thread, overlapping with the parsing,
+//! and the blocks of a blocked weave can be decompressed in parallel, by a fixed pool of threads.
+//! Either way, the results are passed back, in order, over a bounded channel, so memory stays
+//! limited when the reader falls behind.
+
+use crate::Compression;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// The size of the chunks a single stream is passed back in.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A piece of the decompressed data, possibly still being decompressed.
+enum Pending {
+    Ready(io::Result<Vec<u8>>),
+    Decoding(Receiver<io::Result<Vec<u8>>>),
+}
+
+/// A compressed block, and where to send it once decompressed.
+type Job = (Vec<u8>, SyncSender<io::Result<Vec<u8>>>);
+
+/// Reads data decompressed by other threads.  Dropping the reader stops the threads once they
+/// notice.
+pub(crate) struct ThreadReader {
+    rx: Receiver<Pending>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl ThreadReader {
+    /// Decompress a single stream on its own thread.
+    pub(crate) fn stream(mut rd: Box<dyn Read + Send>, threads: usize) -> ThreadReader {
+        let (tx, rx) = sync_channel(threads);
+        thread::spawn(move || loop {
+            let mut buf = Vec::with_capacity(CHUNK_SIZE);
+            match rd.by_ref().take(CHUNK_SIZE as u64).read_to_end(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send(Pending::Ready(Ok(buf))).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.send(Pending::Ready(Err(e)));
+                    break;
+                }
+            }
+        });
+        ThreadReader::new(rx)
+    }
+
+    /// Decompress the given blocks of a file, each an independent frame at the given offset and
+    /// size, on a pool of `threads` threads.  The blocks are read in order on another thread,
+    /// which hands them to whichever of the pool is free.
+    pub(crate) fn blocks(
+        mut file: File,
+        compression: Compression,
+        mut blocks: VecDeque<(u64, u64)>,
+        threads: usize,
+    ) -> ThreadReader {
+        let threads = threads.max(1);
+        let (tx, rx) = sync_channel(threads);
+        let (job_tx, job_rx) = sync_channel::<Job>(threads);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..threads {
+            let job_rx = job_rx.clone();
+            thread::spawn(move || loop {
+                // The lock is only held while waiting for a job.  The pool stops once the reading
+                // thread is done, and drops its sender.
+                let job = job_rx.lock().unwrap().recv();
+                let (data, done) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let _ = done.send(decode(data, compression));
+            });
+        }
+        thread::spawn(move || {
+            while let Some((offset, size)) = blocks.pop_front() {
+                let mut data = vec![0; size as usize];
+                if let Err(e) = file
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| file.read_exact(&mut data))
+                {
+                    let _ = tx.send(Pending::Ready(Err(e)));
+                    break;
+                }
+                let (done_tx, done_rx) = sync_channel(1);
+                if tx.send(Pending::Decoding(done_rx)).is_err()
+                    || job_tx.send((data, done_tx)).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        ThreadReader::new(rx)
+    }
+
+    fn new(rx: Receiver<Pending>) -> ThreadReader {
+        ThreadReader {
+            rx,
+            buf: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ThreadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            let data = match self.rx.recv() {
+                // The sender is gone once everything has been sent.
+                Err(_) => return Ok(0),
+                Ok(Pending::Ready(data)) => data,
+                Ok(Pending::Decoding(done)) => done
+                    .recv()
+                    .map_err(|_| io::Error::other("decode thread panicked"))?,
+            };
+            self.buf = data?;
+            self.pos = 0;
+        }
+
+        let count = buf.len().min(self.buf.len() - self.pos);
+        buf[..count].copy_from_slice(&self.buf[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Decompress a single block.
+fn decode(data: Vec<u8>, compression: Compression) -> io::Result<Vec<u8>> {
+    let mut result = vec![];
+    crate::block::decoder(Cursor::new(data), compression)?.read_to_end(&mut result)?;
+    Ok(result)
+}
@@ -0,0 +1,127 @@
+//! Remove a delta from a weave file.
+//!
+//! Removing a delta rewrites the weave with every line given the range of the remaining deltas that
+//! keep it.  A line inserted by the removed delta that is still present in the next delta becomes
+//! an insert of that next delta, and a line deleted by it is instead deleted by the next delta that
+//! doesn't have it.  Lines that were only ever present in the removed delta are dropped.  The
+//! other deltas keep their numbers, so they read back exactly as before.
+
+use crate::{
+    block::live_ranges,
+    naming::{replace_main, temp_writer},
+    DeltaRange, Entry, Error, NamingConvention, PullParser, Result, DEFAULT_BLOCK_SIZE,
+};
+use std::io::{BufRead, BufReader, Write};
+
+/// Rewrite the weave of the naming convention without the given delta.  The previous weave is
+/// kept as the backup file.  The last remaining delta can't be removed.
+pub fn delete_delta(naming: &dyn NamingConvention, delta: usize) -> Result<()> {
+    let _lock = crate::lock(naming)?;
+    let mut header = crate::read_header(naming)?;
+    let pos = header
+        .deltas
+        .iter()
+        .position(|d| d.number == delta)
+        .ok_or(Error::NoSuchDelta(delta))?;
+    if header.deltas.len() == 1 {
+        return Err(Error::LastDelta);
+    }
+    // Kept, so that the number isn't given to a later delta.
+    header.last_number = header.highest_number();
+    header.deltas.remove(pos);
+
+    let mut remaining: Vec<usize> = header.deltas.iter().map(|d| d.number).collect();
+    remaining.sort_unstable();
+
+    // A blocked weave stays blocked.
+    let block_size = naming
+        .block_size()
+        .or_else(|| header.blocks.as_ref().map(|_| DEFAULT_BLOCK_SIZE));
+    let temp = temp_writer(naming, block_size)?;
+    let mut dest = temp.writer;
+    header.write(&mut dest)?;
+
+    let lines = BufReader::new(crate::block::open_all(naming)?).lines();
+    let mut writer = GroupWriter {
+        dest: &mut dest,
+        current: None,
+    };
+    // The open inserts and deletes, newest delta first, as in the parser.
+    let mut state: Vec<(usize, u8)> = vec![];
+    let mut live = vec![];
+    for entry in PullParser::new_raw(lines, delta)? {
+        let (number, kind) = match entry? {
+            Entry::Insert { delta } => (delta, b'I'),
+            Entry::Delete { delta } => (delta, b'D'),
+            Entry::End { delta } => (delta, b'E'),
+            Entry::Plain { text, .. } => {
+                let range = match live.as_slice() {
+                    [] => None,
+                    [range] => refold(*range, &remaining),
+                    _ => return Err(Error::UnsupportedWeave),
+                };
+                if let Some(range) = range {
+                    writer.line(range, &text)?;
+                }
+                continue;
+            }
+            Entry::Control => continue,
+        };
+        match state.binary_search_by(|ent| number.cmp(&ent.0)) {
+            Ok(pos) if kind == b'E' => {
+                state.remove(pos);
+            }
+            Err(pos) if kind != b'E' => state.insert(pos, (number, kind)),
+            _ => return Err(Error::UnsupportedWeave),
+        }
+        live = live_ranges(&state);
+    }
+    writer.close()?;
+    dest.finish()?;
+
+    replace_main(naming, &temp.name)?;
+    Ok(())
+}
+
+/// Narrow a range of deltas to those remaining, returning None if none of them are left in it.
+fn refold((start, end): DeltaRange, remaining: &[usize]) -> Option<DeltaRange> {
+    let first = |from: usize| remaining.iter().copied().find(|&n| n >= from);
+    let start = first(start)?;
+    let end = end.and_then(first);
+    match end {
+        Some(end) if end <= start => None,
+        _ => Some((start, end)),
+    }
+}
+
+/// Writes lines, surrounding each run of lines with the same range of deltas by an insert, and
+/// possibly a delete.
+struct GroupWriter<'a, W: Write + ?Sized> {
+    dest: &'a mut W,
+    current: Option<DeltaRange>,
+}
+
+impl<'a, W: Write + ?Sized> GroupWriter<'a, W> {
+    fn line(&mut self, range: DeltaRange, text: &str) -> Result<()> {
+        if self.current != Some(range) {
+            self.close()?;
+            writeln!(self.dest, "\x01I {}", range.0)?;
+            if let Some(end) = range.1 {
+                writeln!(self.dest, "\x01D {}", end)?;
+            }
+            self.current = Some(range);
+        }
+        writeln!(self.dest, "{}", text)?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some((start, end)) = self.current.take() {
+            if let Some(end) = end {
+                writeln!(self.dest, "\x01E {}", end)?;
+            }
+            writeln!(self.dest, "\x01E {}", start)?;
+        }
+        Ok(())
+    }
+}
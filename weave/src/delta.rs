@@ -1,19 +1,42 @@
 //! Add a delta to a weave file.
 
-use regex::Regex;
 use std::{
     collections::BTreeMap,
-    fs::{remove_file, rename},
+    fs::remove_file,
     io::{self, BufRead, BufReader, BufWriter, Write},
-    mem::replace,
-    path::PathBuf,
-    process::{Command, Stdio},
+    rc::Rc,
+    sync::Arc,
 };
 
-use crate::{header::Header, Entry, Error, NamingConvention, Parser, PullParser, Result, Sink, WriterInfo};
+use crate::{
+    decrypt_from,
+    diff::diff,
+    encrypt_to,
+    header::Header,
+    lock::lock,
+    naming::{replace_main, temp_writer},
+    stream::Stream,
+    Clock, Entry, Error, NamingConvention, Parser, PullParser, Result, Sink, SystemClock,
+    WeaveLock, WriterInfo, DEFAULT_BLOCK_SIZE,
+};
+
+/// Asked now and then while a new weave is written, returning true once the write should stop,
+/// with [`Error::Cancelled`].
+pub type Cancel = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// How many lines are written to a new weave between asking whether to stop.
+const CANCEL_LINES: usize = 4096;
 
 /// A DeltaWriter is used to write a new delta.  Data should be written to the writer, and then the
 /// `close` method called to update the weave file with the new delta.
+///
+/// The delta is written in streaming mode, compared with the base as it is written, within the
+/// naming convention's [`NamingConvention::delta_window`], so the memory used doesn't grow with
+/// the size of the file.  A convention with no window has the whole delta written to a temp file,
+/// and compared with the whole base, held in memory, when the writer is closed.
+///
+/// The weave is locked against other writers from when the writer is constructed until it is
+/// closed or dropped.  See [`crate::lock`].
 pub struct DeltaWriter<'n> {
     naming: &'n dyn NamingConvention,
 
@@ -26,21 +49,91 @@ pub struct DeltaWriter<'n> {
     // The new delta.
     new_delta: usize,
 
-    // The name of the file with the base written to it.
-    base_name: PathBuf,
-
-    // The regex for parsing diff output.
-    diff_re: Regex,
+    // The lines of the base delta.
+    base_lines: Vec<String>,
 
     // The header to be written for the new delta.
     header: Header,
+
+    // The block size, if the new weave is to be blocked.
+    block_size: Option<usize>,
+
+    // The new weave being written, in streaming mode.
+    stream: Option<Stream>,
+
+    // Asked whether to stop writing.
+    cancel: Option<Cancel>,
+
+    // Held until the new weave is in place.
+    _lock: WeaveLock,
 }
 
 impl<'n> DeltaWriter<'n> {
     /// Construct a writer for a new delta.  The naming convention and the tags set where the names
     /// will be written, and what tags will be associated with the convention.  The `base` is the
     /// existing delta that the change should be based on.
-    pub fn new<'a, 'b, I>(nc: &dyn NamingConvention, tags: I, base: usize) -> Result<DeltaWriter>
+    pub fn new<'a, 'b, I>(
+        nc: &dyn NamingConvention,
+        tags: I,
+        base: usize,
+    ) -> Result<DeltaWriter<'_>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        DeltaWriter::with_clock(nc, tags, base, &SystemClock)
+    }
+
+    /// Construct a writer for a new delta, with the delta's timestamp taken from the given clock.
+    pub fn with_clock<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        base: usize,
+        clock: &dyn Clock,
+    ) -> Result<DeltaWriter<'n>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        let lock = lock(nc)?;
+        DeltaWriter::locked(nc, tags, base, clock, lock)
+    }
+
+    /// Construct a writer for a new delta based on the newest delta in the weave.  The newest
+    /// delta is found once the weave is locked, so a writer that waited for another is based on
+    /// the other's delta, rather than replacing it.
+    pub fn on_latest<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        clock: &dyn Clock,
+    ) -> Result<DeltaWriter<'n>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        let lock = lock(nc)?;
+        DeltaWriter::on_latest_locked(nc, tags, clock, lock)
+    }
+
+    /// Construct a writer for a new delta based on the newest delta in the weave, which the caller
+    /// has already locked, such as to read the newest delta before deciding what to write.
+    pub fn on_latest_locked<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        clock: &dyn Clock,
+        lock: WeaveLock,
+    ) -> Result<DeltaWriter<'n>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        let base = crate::get_last_delta(nc)?;
+        DeltaWriter::locked(nc, tags, base, clock, lock)
+    }
+
+    fn locked<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        base: usize,
+        clock: &dyn Clock,
+        lock: WeaveLock,
+    ) -> Result<DeltaWriter<'n>>
     where
         I: Iterator<Item = (&'a str, &'b str)>,
     {
@@ -54,29 +147,50 @@ impl<'n> DeltaWriter<'n> {
             return Err(Error::NameMissing);
         }
 
-        // Extract the base delta to a file.
+        if let Some(window) = nc.delta_window() {
+            let mut header = PullParser::new(nc, base)?.into_header();
+            let new_delta = header.add_with_clock(ntags, clock)?;
+            let block_size = nc
+                .block_size()
+                .or_else(|| header.blocks.as_ref().map(|_| DEFAULT_BLOCK_SIZE));
+            let stream = Stream::new(nc, &header, base, new_delta, block_size, window)?;
+
+            return Ok(DeltaWriter {
+                naming: nc,
+                temp: None,
+                base,
+                new_delta,
+                base_lines: vec![],
+                header,
+                block_size,
+                stream: Some(stream),
+                cancel: None,
+                _lock: lock,
+            });
+        }
 
-        let (base_name, mut base_file) = nc.temp_file()?;
+        // Extract the lines of the base delta.
+        let mut base_lines = vec![];
         let mut header = {
             let mut parser = PullParser::new(nc, base)?;
             for node in &mut parser {
-                match node? {
-                    Entry::Plain { text, keep } => {
-                        if keep {
-                            writeln!(base_file, "{}", text)?;
-                        }
-                    }
-                    _ => (),
+                if let Entry::Plain { text, keep: true } = node? {
+                    base_lines.push(text);
                 }
             }
             parser.into_header()
         };
-        let new_delta = header.add(ntags)?;
+        let new_delta = header.add_with_clock(ntags, clock)?;
+
+        // A blocked weave stays blocked.
+        let block_size = nc
+            .block_size()
+            .or_else(|| header.blocks.as_ref().map(|_| DEFAULT_BLOCK_SIZE));
 
         let (new_name, new_file) = nc.temp_file()?;
         let new_info = WriterInfo {
             name: new_name,
-            writer: Box::new(BufWriter::new(new_file)),
+            writer: Box::new(BufWriter::new(encrypt_to(nc, new_file)?)),
         };
 
         Ok(DeltaWriter {
@@ -84,38 +198,55 @@ impl<'n> DeltaWriter<'n> {
             temp: Some(new_info),
             base,
             new_delta,
-            base_name,
-            diff_re: Regex::new(r"^(\d+)(,(\d+))?([acd]).*$").unwrap(),
+            base_lines,
             header,
+            block_size,
+            stream: None,
+            cancel: None,
+            _lock: lock,
         })
     }
 
+    /// Stop writing, with [`Error::Cancelled`], once `cancel` returns true.  It is asked when
+    /// the writer is closed, and every few thousand lines as the new weave is written.
+    pub fn set_cancel(&mut self, cancel: Cancel) {
+        if let Some(stream) = self.stream.as_mut() {
+            stream.set_cancel(cancel.clone());
+        }
+        self.cancel = Some(cancel);
+    }
+
     pub fn close(mut self) -> Result<()> {
+        if self.cancel.as_ref().is_some_and(|cancel| cancel()) {
+            return Err(Error::Cancelled);
+        }
+        if let Some(stream) = self.stream.take() {
+            let name = stream.finish()?;
+            replace_main(self.naming, &name)?;
+            return Ok(());
+        }
+
         // Close the temporary file, getting its name.
-        let temp = replace(&mut self.temp, None);
+        let temp = self.temp.take();
         let temp_name = match temp {
-            Some(mut wi) => {
-                wi.writer.flush()?;
-                drop(wi.writer);
+            Some(wi) => {
+                wi.writer.finish()?;
                 wi.name
             }
             None => return Err(Error::AlreadyClosed),
         };
 
-        let tweave_info = self.naming.new_temp()?;
+        let tweave_info = temp_writer(self.naming, self.block_size)?;
 
-        // Invoke diff on the files.
-        let mut child = Command::new("diff")
-            .arg(self.base_name.as_os_str())
-            .arg(temp_name.as_os_str())
-            .stdout(Stdio::piped())
-            .spawn()?;
+        // Compute the differences between the base and the new data.
+        let new_lines = BufReader::new(decrypt_from(self.naming, &temp_name)?)
+            .lines()
+            .collect::<io::Result<Vec<_>>>()?;
+        let hunks = diff(&self.base_lines, &new_lines);
 
         {
-            let lines = BufReader::new(child.stdout.as_mut().unwrap()).lines();
-            let weave_write = WeaveWriter {
-                dest: tweave_info.writer,
-            };
+            let mut weave_write = WeaveWriter::new(tweave_info.writer);
+            weave_write.cancel = self.cancel.clone();
             let mut parser = Parser::new(self.naming, weave_write, self.base)?;
 
             let weave_write = parser.get_sink();
@@ -123,100 +254,91 @@ impl<'n> DeltaWriter<'n> {
             self.header.write(&mut weave_write.borrow_mut().dest)?;
 
             let mut is_done = false;
-            let mut is_adding = false;
-
-            for line in lines {
-                let line = line?;
-                if let Some(cap) = self.diff_re.captures(&line) {
-                    // If adding, this completes the add.
-                    if is_adding {
-                        weave_write.borrow_mut().end(self.new_delta)?;
-                        is_adding = false;
-                    }
-
-                    let left = cap.get(1).unwrap().as_str().parse::<usize>().unwrap();
-                    let right = match cap.get(3) {
-                        None => left,
-                        Some(r) => r.as_str().parse().unwrap(),
-                    };
-                    let cmd = cap.get(4).unwrap().as_str().chars().next().unwrap();
-
-                    if cmd == 'd' || cmd == 'c' {
-                        // These include deletions.
-                        match parser.parse_to(left)? {
-                            0 => return Err(Error::UnexpectedEof),
-                            n if n == left => (),
-                            _ => panic!("Unexpected parse result"),
-                        }
-                        weave_write.borrow_mut().delete(self.new_delta)?;
-                        match parser.parse_to(right + 1) {
-                            Ok(0) => is_done = true,
-                            Ok(n) if n == right + 1 => (),
-                            Ok(_) => panic!("Unexpected parse result"),
-                            Err(e) => return Err(e),
-                        }
-                        weave_write.borrow_mut().end(self.new_delta)?;
-                    } else {
-                        match parser.parse_to(right + 1) {
-                            Ok(0) => is_done = true,
-                            Ok(n) if n == right + 1 => (),
-                            Ok(_) => panic!("Unexpected parse result"),
-                            Err(e) => return Err(e),
-                        }
-                    }
-
-                    if cmd == 'c' || cmd == 'a' {
-                        weave_write.borrow_mut().insert(self.new_delta)?;
-                        is_adding = true;
-                    }
-
-                    continue;
-                }
 
-                match line.chars().next() {
-                    None => panic!("Unexpected blank line in diff"),
-                    Some('<') => continue,
-                    Some('-') => continue,
-                    Some('>') => {
-                        // Add lines should just be written as-is.
-                        weave_write.borrow_mut().plain(&line[2..], true)?;
-                    }
-                    Some(_) => panic!("Unexpected diff line: {:?}", line),
+            for hunk in &hunks {
+                let lines = &new_lines[hunk.new..hunk.new + hunk.new_len];
+                if apply_change(&mut parser, self.new_delta, hunk.old, hunk.old_len, lines)? {
+                    is_done = true;
                 }
             }
 
-            if is_adding {
-                weave_write.borrow_mut().end(self.new_delta)?;
-            }
-
             if !is_done {
-                match parser.parse_to(0) {
-                    Ok(0) => (),
-                    Ok(_) => panic!("Unexpected non-eof"),
-                    Err(e) => return Err(e),
+                match parser.parse_to(0)? {
+                    0 => (),
+                    n => return Err(Error::OutOfStep(0, n)),
                 }
             }
-        }
 
-        match child.wait()?.code() {
-            None => return Err(Error::DiffKilled),
-            Some(0) => (), // No diffs
-            Some(1) => (), // Normal with diffs
-            Some(n) => return Err(Error::DiffError(n)),
+            drop(parser);
+            match Rc::try_unwrap(weave_write) {
+                Ok(weave_write) => weave_write.into_inner().dest.finish()?,
+                Err(_) => unreachable!("weave writer still shared"),
+            }
         }
 
         // Now that is all done, clean up the temp files, and cycle the backup.
-        let _ = rename(self.naming.main_file(), self.naming.backup_file());
-        rename(tweave_info.name, self.naming.main_file())?;
-        remove_file(&self.base_name)?;
+        replace_main(self.naming, &tweave_info.name)?;
         remove_file(&temp_name)?;
 
         Ok(())
     }
 }
 
+/// Write a change to the new weave: the base lines from `old`, counting from zero, for `old_len`
+/// lines, are deleted, and `lines` are inserted in their place.  Changes must be given in order.
+/// Returns true once the parser has reached the end of the weave.
+pub(crate) fn apply_change<W: Write, B: BufRead>(
+    parser: &mut Parser<WeaveWriter<W>, B>,
+    delta: usize,
+    old: usize,
+    old_len: usize,
+    lines: &[String],
+) -> Result<bool> {
+    let weave_write = parser.get_sink();
+    let mut is_done = false;
+
+    // Hunk positions are zero based, and the parser's line numbers
+    // start at one.
+    if old_len > 0 {
+        let left = old + 1;
+        let right = old + old_len;
+        match parser.parse_to(left)? {
+            0 => return Err(Error::UnexpectedEof),
+            n if n == left => (),
+            n => return Err(Error::OutOfStep(left, n)),
+        }
+        weave_write.borrow_mut().delete(delta)?;
+        match parser.parse_to(right + 1)? {
+            0 => is_done = true,
+            n if n == right + 1 => (),
+            n => return Err(Error::OutOfStep(right + 1, n)),
+        }
+        weave_write.borrow_mut().end(delta)?;
+    } else {
+        // Pure insertion, after line `old`.
+        match parser.parse_to(old + 1)? {
+            0 => is_done = true,
+            n if n == old + 1 => (),
+            n => return Err(Error::OutOfStep(old + 1, n)),
+        }
+    }
+
+    if !lines.is_empty() {
+        let mut w = weave_write.borrow_mut();
+        w.insert(delta)?;
+        for line in lines {
+            w.plain(line, true)?;
+        }
+        w.end(delta)?;
+    }
+    Ok(is_done)
+}
+
 impl<'n> Write for DeltaWriter<'n> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(stream) = self.stream.as_mut() {
+            return stream.write(buf);
+        }
         self.temp
             .as_mut()
             .expect("Attempt to write to DeltaWriter that is closed")
@@ -225,6 +347,9 @@ impl<'n> Write for DeltaWriter<'n> {
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
         self.temp
             .as_mut()
             .expect("Attempt to flush DeltaWriter that is closed")
@@ -233,6 +358,7 @@ impl<'n> Write for DeltaWriter<'n> {
     }
 }
 
+#[allow(dead_code)]
 struct RevWriter<W: Write> {
     dest: BufWriter<W>,
 }
@@ -249,8 +375,21 @@ impl<W: Write> Sink for RevWriter<W> {
 }
 
 /// The weave writer writes out the contents of a weave to a file.
-struct WeaveWriter<W: Write> {
-    dest: W,
+pub(crate) struct WeaveWriter<W: Write> {
+    pub(crate) dest: W,
+    // Asked every `CANCEL_LINES` lines whether to stop.
+    pub(crate) cancel: Option<Cancel>,
+    lines: usize,
+}
+
+impl<W: Write> WeaveWriter<W> {
+    pub(crate) fn new(dest: W) -> WeaveWriter<W> {
+        WeaveWriter {
+            dest,
+            cancel: None,
+            lines: 0,
+        }
+    }
 }
 
 impl<W: Write> Sink for WeaveWriter<W> {
@@ -268,6 +407,12 @@ impl<W: Write> Sink for WeaveWriter<W> {
     }
     fn plain(&mut self, text: &str, _keep: bool) -> Result<()> {
         writeln!(&mut self.dest, "{}", text)?;
+        self.lines += 1;
+        if self.lines.is_multiple_of(CANCEL_LINES)
+            && self.cancel.as_ref().is_some_and(|cancel| cancel())
+        {
+            return Err(Error::Cancelled);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,193 @@
+//! Line differences.
+//!
+//! Computes the changes between two sequences of lines using Myers' O(ND)
+//! algorithm, in its linear space form, which finds the "middle snake" of
+//! an optimal edit path, and recurses on either side of it.  Common
+//! prefixes and suffixes are trimmed first, which for the usual case of a
+//! small change to a large file, leaves very little work for the main
+//! algorithm.
+
+/// A single change: `old_len` lines of the old sequence, starting at
+/// `old`, are replaced by `new_len` lines of the new sequence, starting at
+/// `new`.  Positions are zero based.  Either length may be zero, for a pure
+/// insertion or deletion.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hunk {
+    pub old: usize,
+    pub old_len: usize,
+    pub new: usize,
+    pub new_len: usize,
+}
+
+/// Compute the changes needed to transform `old` into `new`, in order.
+pub fn diff<T: Eq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let mut deleted = vec![false; old.len()];
+    let mut inserted = vec![false; new.len()];
+    compare(old, new, 0, 0, &mut deleted, &mut inserted);
+
+    // Walk the two sequences together, collecting runs of changed lines
+    // into hunks.
+    let mut hunks = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && !deleted[i] && !inserted[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (old_start, new_start) = (i, j);
+        while i < old.len() && deleted[i] {
+            i += 1;
+        }
+        while j < new.len() && inserted[j] {
+            j += 1;
+        }
+        hunks.push(Hunk {
+            old: old_start,
+            old_len: i - old_start,
+            new: new_start,
+            new_len: j - new_start,
+        });
+    }
+    hunks
+}
+
+/// Mark the lines of `a` that are deleted, and the lines of `b` that are
+/// inserted.  `aoff` and `boff` are the positions of the slices within
+/// the full sequences.
+fn compare<T: Eq>(
+    a: &[T],
+    b: &[T],
+    aoff: usize,
+    boff: usize,
+    deleted: &mut [bool],
+    inserted: &mut [bool],
+) {
+    // Trim the common prefix and suffix.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let (aoff, boff) = (aoff + prefix, boff + prefix);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    if a.is_empty() || b.is_empty() {
+        mark(deleted, aoff, a.len());
+        mark(inserted, boff, b.len());
+        return;
+    }
+
+    match middle_snake(a, b) {
+        // Only split if it makes progress, otherwise treat the whole
+        // region as changed.
+        Some((x, y)) if (x, y) != (0, 0) && (x, y) != (a.len(), b.len()) => {
+            compare(&a[..x], &b[..y], aoff, boff, deleted, inserted);
+            compare(&a[x..], &b[y..], aoff + x, boff + y, deleted, inserted);
+        }
+        _ => {
+            mark(deleted, aoff, a.len());
+            mark(inserted, boff, b.len());
+        }
+    }
+}
+
+fn mark(flags: &mut [bool], start: usize, len: usize) {
+    for f in &mut flags[start..start + len] {
+        *f = true;
+    }
+}
+
+/// Find a point on an optimal edit path from the start of both sequences
+/// to their ends, by searching forward from the start and backward from
+/// the end until the two searches overlap.
+fn middle_snake<T: Eq>(a: &[T], b: &[T]) -> Option<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d + 1;
+    let size = (2 * max_d + 3) as usize;
+
+    // The furthest x reached on each diagonal k, forward from the start in
+    // `vf`, and backward from the end in `vb`, or -1 if not reached.
+    let mut vf = vec![-1isize; size];
+    let mut vb = vec![-1isize; size];
+    vf[(offset + 1) as usize] = 0;
+    vb[(offset + 1) as usize] = 0;
+
+    let delta = n - m;
+    // With an odd delta, the forward search will be the one to overlap.
+    let front = delta % 2 != 0;
+
+    // Diagonals that have run off the edge of the grid don't need to be
+    // searched again.
+    let (mut k1start, mut k1end, mut k2start, mut k2end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1start;
+        while k1 <= d - k1end {
+            let k1off = (offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && vf[k1off - 1] < vf[k1off + 1]) {
+                vf[k1off + 1]
+            } else {
+                vf[k1off - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            vf[k1off] = x1;
+            if x1 > n {
+                k1end += 2;
+            } else if y1 > m {
+                k1start += 2;
+            } else if front {
+                let k2off = offset + delta - k1;
+                if k2off >= 0 && (k2off as usize) < size && vb[k2off as usize] != -1 {
+                    let x2 = n - vb[k2off as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2start;
+        while k2 <= d - k2end {
+            let k2off = (offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && vb[k2off - 1] < vb[k2off + 1]) {
+                vb[k2off + 1]
+            } else {
+                vb[k2off - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            vb[k2off] = x2;
+            if x2 > n {
+                k2end += 2;
+            } else if y2 > m {
+                k2start += 2;
+            } else if !front {
+                let k1off = offset + delta - k2;
+                if k1off >= 0 && (k1off as usize) < size && vf[k1off as usize] != -1 {
+                    let x1 = vf[k1off as usize];
+                    let y1 = offset + x1 - k1off;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+
+    None
+}
@@ -1,6 +1,6 @@
 // Errors in the weave code.
 
-use std::{io, result};
+use std::{io, path::PathBuf, result};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,12 +17,22 @@ pub enum Error {
     AlreadyClosed,
     #[error("unexpected end of weave file")]
     UnexpectedEof,
+    #[error("weave file parsed to line {1} when line {0} was expected")]
+    OutOfStep(usize, usize),
     #[error("weave file appears empty")]
     EmptyWeave,
-    #[error("diff error status {0}")]
-    DiffError(i32),
-    #[error("diff killed by signal")]
-    DiffKilled,
+    #[error("no delta {0} in weave file")]
+    NoSuchDelta(usize),
+    #[error("can't remove the only delta of a weave file")]
+    LastDelta,
+    #[error("weave file has unbalanced or overlapping deltas")]
+    UnsupportedWeave,
+    #[error("blocked weave files can't be encrypted")]
+    EncryptedBlocks,
+    #[error("weave file is locked by another writer ({0:?})")]
+    Locked(PathBuf),
+    #[error("writing the weave file was cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = result::Result<T, Error>;
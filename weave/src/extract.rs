@@ -0,0 +1,23 @@
+//! Extract a single delta as plain text.
+
+use crate::{Entry, Error, NamingConvention, PullParser, Result};
+use std::io::{BufWriter, Write};
+
+/// Write the lines of the given delta of the naming convention's weave to `dest`, each followed by
+/// a newline.  The delta is given by its number, which can be found from its name with
+/// [`crate::Header::find_delta`].
+pub fn extract<W: Write>(naming: &dyn NamingConvention, delta: usize, dest: W) -> Result<()> {
+    let parser = PullParser::new(naming, delta)?;
+    if !parser.get_header().deltas.iter().any(|d| d.number == delta) {
+        return Err(Error::NoSuchDelta(delta));
+    }
+
+    let mut dest = BufWriter::new(dest);
+    for entry in parser {
+        if let Entry::Plain { text, keep: true } = entry? {
+            writeln!(dest, "{}", text)?;
+        }
+    }
+    dest.flush()?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! Check the structure of a weave file.
+//!
+//! The parser trusts the weave it reads, and can panic, or silently give the wrong lines, when
+//! the file has been damaged.  [`fsck`] reads the whole weave, without interpreting it for any
+//! delta, and reports what is wrong with it: deltas in the header that are out of order or
+//! repeated, inserts and deletes that aren't balanced by an end, or that name deltas the header
+//! doesn't have, and, for a blocked weave, blocks that don't agree with the header.
+
+use crate::{block::BLOCKED_VERSION, header::Header, Error, NamingConvention, Result};
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{BufRead, BufReader},
+};
+
+/// Something wrong with a weave file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Problem {
+    /// The line of the decompressed weave the problem was found on, the header being line 1, or 0
+    /// for a problem with the file as a whole.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+/// Read through the weave of the naming convention, returning each problem found with its
+/// structure, in the order found.  A weave that can't be read at all, such as one whose header
+/// isn't valid, is an error instead.
+pub fn fsck(naming: &dyn NamingConvention) -> Result<Vec<Problem>> {
+    let mut lines = BufReader::new(crate::block::open_all(naming)?).lines();
+    let header = match lines.next() {
+        Some(line) => Header::decode(&line?)?,
+        None => return Err(Error::EmptyWeave),
+    };
+    let mut check = Check {
+        problems: vec![],
+        line: 1,
+        known: header.deltas.iter().map(|d| d.number).collect(),
+        state: vec![],
+        blocked: header.blocks.is_some(),
+        blocks: 0,
+    };
+    check.header(&header);
+
+    for line in lines {
+        let line = line?;
+        check.line += 1;
+        check.body(&line);
+    }
+
+    check.line = 0;
+    for (delta, kind) in std::mem::take(&mut check.state) {
+        check.problem(format!("{} of delta {} never ended", name(kind), delta));
+    }
+    match &header.blocks {
+        Some(blocks) if blocks.len() != check.blocks => check.problem(format!(
+            "header lists {} blocks, but the weave has {}",
+            blocks.len(),
+            check.blocks
+        )),
+        _ => (),
+    }
+    Ok(check.problems)
+}
+
+struct Check {
+    problems: Vec<Problem>,
+    // The line being checked.
+    line: usize,
+    // The delta numbers in the header.
+    known: HashSet<usize>,
+    // The open inserts and deletes, newest delta first, as in the parser.
+    state: Vec<(usize, u8)>,
+    // Whether the header lists blocks, and the number of blocks started.
+    blocked: bool,
+    blocks: usize,
+}
+
+impl Check {
+    fn problem(&mut self, message: String) {
+        self.problems.push(Problem {
+            line: self.line,
+            message,
+        });
+    }
+
+    fn header(&mut self, header: &Header) {
+        if header.version == 0 {
+            self.problem("no weave header".to_string());
+        } else if header.version > BLOCKED_VERSION {
+            self.problem(format!("unknown weave version {}", header.version));
+        }
+        if header.deltas.is_empty() {
+            self.problem("header has no deltas".to_string());
+        }
+        if (header.version == BLOCKED_VERSION) != header.blocks.is_some() {
+            self.problem(format!(
+                "version {} header {} blocks",
+                header.version,
+                if header.blocks.is_some() {
+                    "lists"
+                } else {
+                    "doesn't list"
+                }
+            ));
+        }
+
+        let mut last = 0;
+        let mut names = HashSet::new();
+        for delta in &header.deltas {
+            if delta.number <= last {
+                self.problem(format!(
+                    "delta {} follows delta {} in the header",
+                    delta.number, last
+                ));
+            }
+            last = last.max(delta.number);
+            if !names.insert(delta.name.as_str()) {
+                self.problem(format!("delta name {:?} is repeated", delta.name));
+            }
+        }
+    }
+
+    fn body(&mut self, line: &str) {
+        let rest = match line.strip_prefix('\x01') {
+            Some(rest) => rest,
+            None => {
+                if !self.state.iter().any(|&(_, kind)| kind == b'I') {
+                    self.problem("text outside of any insert".to_string());
+                }
+                return;
+            }
+        };
+
+        let (kind, args) = match rest.as_bytes().first() {
+            Some(&kind) if kind.is_ascii() => (kind, &rest[1..]),
+            Some(_) => {
+                self.problem(format!("unknown control line {:?}", line));
+                return;
+            }
+            None => {
+                self.problem("empty control line".to_string());
+                return;
+            }
+        };
+        match kind {
+            b'I' | b'D' | b'E' => {
+                let delta = match args.strip_prefix(' ').and_then(|n| n.parse().ok()) {
+                    Some(delta) => delta,
+                    None => {
+                        self.problem(format!("invalid control line {:?}", line));
+                        return;
+                    }
+                };
+                self.control(kind, delta);
+            }
+            b'S' => self.start_block(args),
+            _ => self.problem(format!("unknown control line {:?}", line)),
+        }
+    }
+
+    /// An insert, delete or end of the given delta.
+    fn control(&mut self, kind: u8, delta: usize) {
+        if !self.known.contains(&delta) {
+            self.problem(format!(
+                "{} of delta {}, which isn't in the header",
+                name(kind),
+                delta
+            ));
+        }
+        match self.state.binary_search_by(|ent| delta.cmp(&ent.0)) {
+            Ok(pos) if kind == b'E' => {
+                self.state.remove(pos);
+            }
+            Err(pos) if kind != b'E' => self.state.insert(pos, (delta, kind)),
+            Ok(pos) => {
+                let open = self.state[pos].1;
+                self.problem(format!(
+                    "{} of delta {} within its {}",
+                    name(kind),
+                    delta,
+                    name(open)
+                ));
+            }
+            Err(_) => self.problem(format!("end of delta {}, which isn't open", delta)),
+        }
+    }
+
+    /// The `\x01S` line starting a block, which should give the open inserts and deletes.
+    fn start_block(&mut self, args: &str) {
+        if !self.blocked {
+            self.problem("block start in a weave without blocks".to_string());
+            return;
+        }
+        self.blocks += 1;
+        let given: Option<Vec<(usize, u8)>> = args
+            .split_whitespace()
+            .map(|item| {
+                let kind = *item.as_bytes().first()?;
+                let delta = item.get(1..)?.parse().ok()?;
+                Some((delta, kind))
+            })
+            .collect();
+        match given {
+            Some(given) if given == self.state => (),
+            Some(_) => self
+                .problem("block starts with inserts and deletes other than those open".to_string()),
+            None => self.problem(format!("invalid block start {:?}", args)),
+        }
+    }
+}
+
+/// The name of a kind of control line.
+fn name(kind: u8) -> &'static str {
+    match kind {
+        b'I' => "insert",
+        b'D' => "delete",
+        _ => "end",
+    }
+}
@@ -6,7 +6,7 @@ use chrono::{DateTime, Utc};
 use serde_derive::{Deserialize, Serialize};
 use std::{collections::BTreeMap, io::Write};
 
-use crate::{Error, Result};
+use crate::{BlockInfo, Clock, Error, Result, SystemClock};
 
 /// The header placed at the beginning of the each weave file.  The deltas correspond with the
 /// deltas checked in.  Note that the value passed to [`crate::PullParser::new`] should be the `number`
@@ -15,6 +15,17 @@ use crate::{Error, Result};
 pub struct Header {
     pub version: usize,
     pub deltas: Vec<DeltaInfo>,
+    /// For a blocked weave, the blocks following the header.  See [`crate::BlockInfo`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks: Option<Vec<BlockInfo>>,
+    /// The highest delta number given so far, so that the number of a deleted delta isn't given
+    /// to a new one.  Older weaves don't have it, and have never had a delta deleted.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub last_number: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
 }
 
 /// Information about a single delta.
@@ -37,6 +48,8 @@ impl Default for Header {
         Header {
             version: THIS_VERSION,
             deltas: vec![],
+            blocks: None,
+            last_number: 0,
         }
     }
 }
@@ -51,30 +64,64 @@ impl Header {
             Ok(Header {
                 version: 0,
                 deltas: vec![],
+                blocks: None,
+                last_number: 0,
             })
         }
     }
 
-    /// Add a delta to this header.  Returns the delta number to be used.
-    pub fn add(&mut self, mut tags: BTreeMap<String, String>) -> Result<usize> {
+    /// Add a delta to this header, stamped with the current time.  Returns the delta number to be
+    /// used.
+    pub fn add(&mut self, tags: BTreeMap<String, String>) -> Result<usize> {
+        self.add_with_clock(tags, &SystemClock)
+    }
+
+    /// Add a delta to this header, taking its timestamp from the given clock.  Returns the delta
+    /// number to be used.
+    pub fn add_with_clock(
+        &mut self,
+        mut tags: BTreeMap<String, String>,
+        clock: &dyn Clock,
+    ) -> Result<usize> {
         let name = if let Some(name) = tags.remove("name") {
             name
         } else {
             return Err(Error::NameMissing);
         };
 
-        let next_delta = self.deltas.iter().map(|x| x.number).max().unwrap_or(0) + 1;
+        let next_delta = self.highest_number() + 1;
+        self.last_number = next_delta;
 
         self.deltas.push(DeltaInfo {
             name,
             number: next_delta,
             tags,
-            time: Utc::now(),
+            time: clock.now(),
         });
 
         Ok(next_delta)
     }
 
+    /// The highest delta number given so far, including to deltas since deleted.
+    pub fn highest_number(&self) -> usize {
+        self.deltas
+            .iter()
+            .map(|x| x.number)
+            .max()
+            .unwrap_or(0)
+            .max(self.last_number)
+    }
+
+    /// The number of the delta with the given name, if there is one.  If several have the name,
+    /// this is the latest of them.
+    pub fn find_delta(&self, name: &str) -> Option<usize> {
+        self.deltas
+            .iter()
+            .filter(|d| d.name == name)
+            .map(|d| d.number)
+            .max()
+    }
+
     /// Write the header to the writer, as the first line.
     pub fn write<W: Write>(&self, mut wr: &mut W) -> Result<()> {
         write!(&mut wr, "\x01t")?;
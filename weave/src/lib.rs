@@ -19,47 +19,77 @@
 //! the initial file.
 //!
 //! Adding a delta to a weave file is done with the [`DeltaWriter`].  This is also written to, as a
-//! regular file, and then [`DeltaWriter::close`] method will extract a base revision and use the
-//! `diff` command to write a new version of the weave.  The `close` method will make several
-//! temporary files in the process.
+//! regular file, and then [`DeltaWriter::close`] method will compare it with a base revision, and
+//! write a new version of the weave.  The differences are computed in-crate, so no external `diff`
+//! program is needed.  The `close` method will make several temporary files in the process.  A
+//! delta can also be removed again, with [`delete_delta`].  The [`Annotator`] gives the lines of a
+//! delta along with the delta that added each of them.  A single delta can be written out as
+//! plain text with [`extract`], and the sizes of the weave and its deltas found with [`stats`].
+//! A weave that may have been damaged can be checked with [`fsck`].  Writers [`lock`] the weave,
+//! so that two of them can't add deltas at the same time.
 //!
 //! The weave data is stored using a [`NamingConvention`], a trait that manages a related
 //! collection of files, and temp files.  [`SimpleNaming`] is a basic representation of this that
 //! has a base name, a backup file, and some temporary files.  The data in the file can be
-//! compressed.
+//! compressed, either as a single stream, or, for large files, as a series of independently
+//! compressed blocks, so that recent deltas can be read without decompressing the whole history
+//! (see [`SimpleNaming::with_block_size`]).
 
 #![warn(bare_trait_objects)]
 
+mod annotate;
+mod block;
+mod cipher;
+mod clock;
+mod decode;
+mod delete;
 mod delta;
+mod diff;
 mod errors;
+mod extract;
+mod fsck;
 mod header;
+mod lock;
 mod naming;
 mod newweave;
 mod parse;
+mod stats;
+mod stream;
 
 pub use crate::{
-    delta::DeltaWriter,
+    annotate::{AnnotatedLine, Annotator},
+    block::{BlockInfo, DeltaRange, DEFAULT_BLOCK_SIZE},
+    cipher::{decrypt_from, encrypt_to, Cipher, EncryptWrite},
+    clock::{Clock, FixedClock, SystemClock},
+    delete::delete_delta,
+    delta::{Cancel, DeltaWriter},
     errors::{Error, Result},
+    extract::extract,
+    fsck::{fsck, Problem},
     header::{DeltaInfo, Header},
+    lock::{lock, WeaveLock},
+    naming::Compression,
     naming::NamingConvention,
     naming::SimpleNaming,
-    naming::Compression,
     newweave::NewWeave,
     parse::{Entry, Parser, PullParser, Sink},
+    stats::{stats, WeaveStats},
 };
 
-use std::{io::Write, path::PathBuf};
+use crate::block::WeaveWrite;
+use std::path::PathBuf;
 
 /// Something we can write into, that remembers its name.  The writer is boxed because the writer
 /// may be compressed.
 pub struct WriterInfo {
     name: PathBuf,
-    writer: Box<dyn Write>,
+    writer: Box<dyn WeaveWrite>,
 }
 
-/// Read the header from a weave file.
+/// Read the header from a weave file.  For a blocked weave, this only needs to read the start of
+/// the file.
 pub fn read_header(naming: &dyn NamingConvention) -> Result<Header> {
-    Ok(PullParser::new(naming, 1)?.into_header())
+    block::read_header(naming)
 }
 
 /// Retrieve the last delta in the weave file.  Will panic if the weave file is malformed and
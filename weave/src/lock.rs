@@ -0,0 +1,56 @@
+//! Lock a weave against other writers.
+//!
+//! Writing a delta reads the weave, writes a new one beside it, and then renames the new one into
+//! place.  Two writers doing this at once would each base their delta on the same weave, and the
+//! second rename would lose the first writer's delta.  Each writer holds an advisory lock on the
+//! naming convention's [`lock_file`](NamingConvention::lock_file) from when it reads the weave
+//! until its new weave is in place.  A second writer either fails with [`Error::Locked`], or waits
+//! for the first, as long as the convention's [`lock_wait`](NamingConvention::lock_wait) allows.
+//!
+//! The lock is taken with the operating system's file locking, so it is released when the writer
+//! is dropped, or its process exits, even if it is killed.  The lock file itself is left in
+//! place.  Readers don't take the lock, as they never see a weave that is only partly written.
+//!
+//! A writer that has to read the weave before it knows what to write, such as the latest delta to
+//! base the new one on, takes the lock first, and gives it to
+//! [`DeltaWriter::on_latest_locked`](crate::DeltaWriter::on_latest_locked) or
+//! [`NewWeave::with_lock`](crate::NewWeave::with_lock), so that nothing is added in between.
+
+use crate::{Error, NamingConvention, Result};
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to sleep between attempts to take a lock that is held.
+const POLL: Duration = Duration::from_millis(100);
+
+/// An exclusive lock on a weave, held until this is dropped.
+#[derive(Debug)]
+pub struct WeaveLock {
+    _file: File,
+}
+
+/// Lock the weave of the naming convention against other writers.
+pub fn lock(naming: &dyn NamingConvention) -> Result<WeaveLock> {
+    let path = naming.lock_file();
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    let deadline = naming.lock_wait().map(|wait| Instant::now() + wait);
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(WeaveLock { _file: file }),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+            Err(TryLockError::WouldBlock) => (),
+        }
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => thread::sleep(POLL),
+            _ => return Err(Error::Locked(path)),
+        }
+    }
+}
@@ -2,12 +2,18 @@
 //! files and other aspects.  The SCCS conventions are not followed, because they are not safe
 //! (this crate will never write to a file that already exists).
 
-use crate::{Result, WriterInfo};
+use crate::{
+    block::{BlockWriter, WeaveWrite},
+    cipher::encrypt_to,
+    Cipher, Error, Result, WriterInfo,
+};
 use flate2::write::GzEncoder;
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufWriter, ErrorKind, Write},
+    fs::{copy, remove_file, rename, File, OpenOptions},
+    io::{BufWriter, ErrorKind},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
 /// A naming convention provides utilities needed to find the involved files, and construct
@@ -18,10 +24,13 @@ use std::{
 /// directly.  The main file is always compressed if the convention enables compression.
 ///
 /// The backup file is only used by name.  It is neither written to, nor read.  It will be
-/// compressed, as it always comes from renaming the main file.
+/// compressed, as it always comes from renaming the main file.  If the convention keeps more
+/// than one backup, the older ones are renamed to the names of the older generations.
 ///
 /// The temporary files are used by name, and written to.  They may or may not be compressed,
-/// depending on how they will be used.
+/// depending on how they will be used.  They needn't be in the directory of the main file, but
+/// the new main file is renamed into place from there, so it is quickest if they are on the same
+/// filesystem.
 pub trait NamingConvention {
     /// Create a temporary file for writing.  Upon success, returns the full path of the file, and
     /// the opened File for writing to the file.  The path should refer to a new file that did not
@@ -34,23 +43,134 @@ pub trait NamingConvention {
     /// Return the pathname of the backup file.
     fn backup_file(&self) -> PathBuf;
 
+    /// Return the number of backups to keep, the newest being the backup file, and the older ones
+    /// given by [`older_backup_file`](NamingConvention::older_backup_file).
+    fn backups(&self) -> usize {
+        1
+    }
+
+    /// Return the pathname of an older backup, generation 1 being the backup before the backup
+    /// file, 2 the one before that, and so on.
+    fn older_backup_file(&self, generation: usize) -> PathBuf {
+        let mut name = self.backup_file().into_os_string();
+        name.push(format!(".{}", generation));
+        name.into()
+    }
+
     /// Return if compression is requested on main file.
     fn compression(&self) -> Compression;
 
+    /// Return the block size, if the main file should be written as a blocked weave.  Weaves that
+    /// are already blocked stay blocked when deltas are added, regardless.
+    fn block_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Return the number of threads to decompress the main file with when reading it, or zero to
+    /// decompress it on the reading thread.  A plain weave is decompressed on a single other
+    /// thread, and the blocks of a blocked weave on up to this many threads at once.
+    fn decode_threads(&self) -> usize {
+        0
+    }
+
+    /// Return the window, in lines, if new deltas should be compared with their base as they are
+    /// written, rather than once the whole delta has been written.  See [`crate::DeltaWriter`].
+    fn delta_window(&self) -> Option<usize> {
+        None
+    }
+
+    /// Return the cipher to encrypt the main file and temp files with, if they should be
+    /// encrypted.  See [`crate::Cipher`].
+    fn cipher(&self) -> Option<&dyn Cipher> {
+        None
+    }
+
+    /// Return the pathname of the file that writers lock, so that only one of them writes the
+    /// weave at a time.  See [`crate::lock`].
+    fn lock_file(&self) -> PathBuf {
+        let mut name = self.main_file().into_os_string();
+        name.push(".lock");
+        name.into()
+    }
+
+    /// Return how long a writer should wait for another to finish with the weave, or `None` to
+    /// fail as soon as it finds the weave locked.
+    fn lock_wait(&self) -> Option<Duration> {
+        None
+    }
+
     /// Open a possibly compressed temp file, returning a WriterInfo for it.  The stream will be
     /// buffered, and possibly compressed.
     fn new_temp(&self) -> Result<WriterInfo> {
-        let (name, file) = self.temp_file()?;
-        let writer = match self.compression() {
-            Compression::Plain =>
-                Box::new(BufWriter::new(file)) as Box<dyn Write>,
-            Compression::Gzip =>
-                Box::new(GzEncoder::new(file, flate2::Compression::default())) as Box<dyn Write>,
-            Compression::Zstd =>
-                Box::new(zstd::Encoder::new(file, 3)?.auto_finish()) as Box<dyn Write>,
-        };
-        Ok(WriterInfo { name, writer })
+        temp_writer(self, self.block_size())
+    }
+}
+
+/// Replace the main file with the named new one, keeping the main file as the backup, and
+/// shifting the existing backups back a generation, dropping the oldest.  A new file in another
+/// directory is first moved beside the main file, copying it if it is on another filesystem, so
+/// that the main file is still replaced by a rename.
+pub(crate) fn replace_main<N>(naming: &N, name: &Path) -> Result<()>
+where
+    N: NamingConvention + ?Sized,
+{
+    let main = naming.main_file();
+    let mut staged = main.clone().into_os_string();
+    staged.push(".new");
+    let staged = PathBuf::from(staged);
+    let name = if name.parent() == main.parent() {
+        name
+    } else {
+        match rename(name, &staged) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                copy(name, &staged)?;
+                File::open(&staged)?.sync_all()?;
+                remove_file(name)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        &staged
+    };
+
+    let backup = |generation| match generation {
+        0 => naming.backup_file(),
+        generation => naming.older_backup_file(generation),
+    };
+    for generation in (1..naming.backups()).rev() {
+        let _ = rename(backup(generation - 1), backup(generation));
     }
+    let _ = rename(&main, naming.backup_file());
+    rename(name, &main)?;
+    Ok(())
+}
+
+/// Open a temp file for a new main file, written as a blocked weave if a block size is given.
+pub(crate) fn temp_writer<N>(naming: &N, block_size: Option<usize>) -> Result<WriterInfo>
+where
+    N: NamingConvention + ?Sized,
+{
+    let (name, file) = naming.temp_file()?;
+    let writer = match (block_size, naming.compression()) {
+        (Some(_), _) if naming.cipher().is_some() => return Err(Error::EncryptedBlocks),
+        (Some(size), compression) => Box::new(BlockWriter::new(
+            file,
+            naming.temp_file()?,
+            compression,
+            size,
+        )) as Box<dyn WeaveWrite>,
+        (None, Compression::Plain) => {
+            Box::new(BufWriter::new(encrypt_to(naming, file)?)) as Box<dyn WeaveWrite>
+        }
+        (None, Compression::Gzip) => Box::new(GzEncoder::new(
+            encrypt_to(naming, file)?,
+            flate2::Compression::default(),
+        )) as Box<dyn WeaveWrite>,
+        (None, Compression::Zstd) => {
+            Box::new(zstd::Encoder::new(encrypt_to(naming, file)?, 3)?) as Box<dyn WeaveWrite>
+        }
+    };
+    Ok(WriterInfo { name, writer })
 }
 
 /// Supported compression types.
@@ -63,8 +183,10 @@ pub enum Compression {
 
 /// The SimpleNaming is a NamingConvention that has a basename, with the main file having a
 /// specified extension, the backup file having a ".bak" extension, and the temp files using a
-/// numbered extension starting with ".0".  If the names are intended to be compressed, a ".gz"
-/// suffix can also be added.
+/// numbered extension starting with ".0", in the temp directory, if one is given.  If the names
+/// are intended to be compressed, a ".gz" suffix can also be added.  Writers lock the file with
+/// the ".lock" extension.  Older backups, if more are kept, are ".bak.1", ".bak.2" and so on,
+/// before the suffix.
 #[derive(Debug, Clone)]
 pub struct SimpleNaming {
     // The directory for the files to be written.
@@ -75,18 +197,93 @@ pub struct SimpleNaming {
     ext: String,
     // Compression to be used.
     compression: Compression,
+    // The block size, when writing blocked weaves.
+    block_size: Option<usize>,
+    // The threads used to decompress the main file.
+    decode_threads: usize,
+    // The window for streaming deltas.
+    delta_window: Option<usize>,
+    // Encrypts the files.
+    cipher: Option<Arc<dyn Cipher>>,
+    // How long to wait for the lock.
+    lock_wait: Option<Duration>,
+    // The number of backups to keep.
+    backups: usize,
+    // Where to write temp files, if not beside the main file.
+    temp_dir: Option<PathBuf>,
 }
 
 impl SimpleNaming {
-    pub fn new<P: AsRef<Path>>(path: P, base: &str, ext: &str, compression: Compression) -> SimpleNaming {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        base: &str,
+        ext: &str,
+        compression: Compression,
+    ) -> SimpleNaming {
         SimpleNaming {
             path: path.as_ref().to_path_buf(),
             base: base.to_string(),
             ext: ext.to_string(),
             compression,
+            block_size: None,
+            decode_threads: 0,
+            delta_window: None,
+            cipher: None,
+            lock_wait: None,
+            backups: 1,
+            temp_dir: None,
         }
     }
 
+    /// Write the main file as a blocked weave, with about `size` bytes of text in each
+    /// independently compressed block.
+    pub fn with_block_size(mut self, size: usize) -> SimpleNaming {
+        self.block_size = Some(size);
+        self
+    }
+
+    /// Decompress the main file on other threads when reading it.  See
+    /// [`NamingConvention::decode_threads`].
+    pub fn with_decode_threads(mut self, threads: usize) -> SimpleNaming {
+        self.decode_threads = threads;
+        self
+    }
+
+    /// Write new deltas in streaming mode, comparing them with their base within a window of this
+    /// many lines.  See [`NamingConvention::delta_window`].
+    pub fn with_delta_window(mut self, lines: usize) -> SimpleNaming {
+        self.delta_window = Some(lines.max(1));
+        self
+    }
+
+    /// Encrypt the main file and temp files with this cipher.  See [`NamingConvention::cipher`].
+    pub fn with_cipher(mut self, cipher: Arc<dyn Cipher>) -> SimpleNaming {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Wait up to this long for another writer to finish with the weave, rather than failing at
+    /// once.  See [`NamingConvention::lock_wait`].
+    pub fn with_lock_wait(mut self, wait: Duration) -> SimpleNaming {
+        self.lock_wait = Some(wait);
+        self
+    }
+
+    /// Keep this many backups of the main file, rather than one, so that a bad write doesn't
+    /// replace the only good copy.  At least one is always kept.
+    pub fn with_backups(mut self, count: usize) -> SimpleNaming {
+        self.backups = count.max(1);
+        self
+    }
+
+    /// Write temp files in this directory, such as one on a faster disk, rather than beside the
+    /// main file.  The new main file is copied into place if the directory is on another
+    /// filesystem.
+    pub fn with_temp_dir<P: AsRef<Path>>(mut self, dir: P) -> SimpleNaming {
+        self.temp_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
     pub fn make_name(&self, ext: &str, compression: Compression) -> PathBuf {
         let name = format!(
             "{}.{}{}",
@@ -111,10 +308,26 @@ impl NamingConvention for SimpleNaming {
         self.make_name("bak", self.compression)
     }
 
+    fn backups(&self) -> usize {
+        self.backups
+    }
+
+    fn older_backup_file(&self, generation: usize) -> PathBuf {
+        self.make_name(&format!("bak.{}", generation), self.compression)
+    }
+
+    fn lock_file(&self) -> PathBuf {
+        self.make_name("lock", Compression::Plain)
+    }
+
     fn temp_file(&self) -> Result<(PathBuf, File)> {
         let mut n = 0;
         loop {
             let name = self.make_name(&n.to_string(), Compression::Plain);
+            let name = match (&self.temp_dir, name.file_name()) {
+                (Some(dir), Some(file)) => dir.join(file),
+                _ => name,
+            };
 
             match OpenOptions::new().write(true).create_new(true).open(&name) {
                 Ok(fd) => return Ok((name, fd)),
@@ -129,4 +342,24 @@ impl NamingConvention for SimpleNaming {
     fn compression(&self) -> Compression {
         self.compression
     }
+
+    fn block_size(&self) -> Option<usize> {
+        self.block_size
+    }
+
+    fn decode_threads(&self) -> usize {
+        self.decode_threads
+    }
+
+    fn delta_window(&self) -> Option<usize> {
+        self.delta_window
+    }
+
+    fn cipher(&self) -> Option<&dyn Cipher> {
+        self.cipher.as_deref()
+    }
+
+    fn lock_wait(&self) -> Option<Duration> {
+        self.lock_wait
+    }
 }
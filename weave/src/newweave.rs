@@ -2,25 +2,54 @@
 
 use std::{
     collections::BTreeMap,
-    fs::rename,
     io::{self, Write},
-    mem::replace,
 };
 
-use crate::{header::Header, Error, NamingConvention, Result, WriterInfo};
 #[allow(unused)]
 use crate::Compression;
+use crate::{
+    header::Header, lock::lock, naming::replace_main, Clock, Error, NamingConvention, Result,
+    SystemClock, WeaveLock, WriterInfo,
+};
 
 /// A builder for a new weave file.  The data should be written as a writer.  Closing the weaver
 /// will finish up the write and move the new file into place.  If the weaver is just dropped, the
-/// file will not be moved into place.
+/// file will not be moved into place.  The weave is locked against other writers until then.
 pub struct NewWeave<'n> {
     naming: &'n dyn NamingConvention,
     temp: Option<WriterInfo>,
+    _lock: WeaveLock,
 }
 
 impl<'n> NewWeave<'n> {
-    pub fn new<'a, 'b, I>(nc: &dyn NamingConvention, tags: I) -> Result<NewWeave>
+    pub fn new<'a, 'b, I>(nc: &dyn NamingConvention, tags: I) -> Result<NewWeave<'_>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        NewWeave::with_clock(nc, tags, &SystemClock)
+    }
+
+    /// Construct a new weave, with the delta's timestamp taken from the given clock.
+    pub fn with_clock<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        clock: &dyn Clock,
+    ) -> Result<NewWeave<'n>>
+    where
+        I: Iterator<Item = (&'a str, &'b str)>,
+    {
+        let lock = lock(nc)?;
+        NewWeave::with_lock(nc, tags, clock, lock)
+    }
+
+    /// Construct a new weave, whose naming convention the caller has already locked, such as to
+    /// check that there is no weave yet.
+    pub fn with_lock<'a, 'b, I>(
+        nc: &'n dyn NamingConvention,
+        tags: I,
+        clock: &dyn Clock,
+        lock: WeaveLock,
+    ) -> Result<NewWeave<'n>>
     where
         I: Iterator<Item = (&'a str, &'b str)>,
     {
@@ -31,27 +60,28 @@ impl<'n> NewWeave<'n> {
             ntags.insert(k.to_owned(), v.to_owned());
         }
         let mut header: Header = Default::default();
-        let delta = header.add(ntags)?;
+        let delta = header.add_with_clock(ntags, clock)?;
         header.write(&mut writeinfo.writer)?;
         writeln!(&mut writeinfo.writer, "\x01I {}", delta)?;
 
         Ok(NewWeave {
             naming: nc,
             temp: Some(writeinfo),
+            _lock: lock,
         })
     }
 
     pub fn close(mut self) -> Result<()> {
-        let temp = replace(&mut self.temp, None);
+        let temp = self.temp.take();
         let name = match temp {
             Some(mut wi) => {
                 writeln!(&mut wi.writer, "\x01E 1")?;
+                wi.writer.finish()?;
                 wi.name
             }
             None => return Err(Error::AlreadyClosed),
         };
-        let _ = rename(self.naming.main_file(), self.naming.backup_file());
-        rename(name, self.naming.main_file())?;
+        replace_main(self.naming, &name)?;
         Ok(())
     }
 }
@@ -1,13 +1,11 @@
 //! Weave parsing
 
-use crate::{header::Header, Error, NamingConvention, Compression, Result};
-use flate2::read::GzDecoder;
+use crate::{block, header::Header, Error, NamingConvention, Result};
 use log::info;
 use std::{
     cell::RefCell,
-    fs::File,
     io::{BufRead, BufReader, Lines, Read},
-    mem,
+    ops::ControlFlow,
     rc::Rc,
 };
 
@@ -15,7 +13,8 @@ use std::{
 /// those in the weave file, and `plain` are the lines of data.  With each plain is a flag
 /// indicating if that line should be included in the output (all lines are called, so that updates
 /// can use this same code).  All methods return a result, with the Err value stopping the parse.
-/// Note that the default implementations just return success, and ignore the result.
+/// Note that the default implementations just return success, and ignore the result.  A sink
+/// that has what it needs can stop the parse early, without an error, with `flow`.
 pub trait Sink {
     /// Begin an insert sequence for the given delta.
     fn insert(&mut self, _delta: usize) -> Result<()> {
@@ -37,6 +36,13 @@ pub trait Sink {
     fn plain(&mut self, _text: &str, _keep: bool) -> Result<()> {
         Ok(())
     }
+
+    /// Asked after each of the above, to decide if the parse should go on.  Returning
+    /// `ControlFlow::Break(())`, such as once the one line being looked for has been seen, stops
+    /// the parse there, without reading the rest of the weave.
+    fn flow(&self) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
 }
 
 /// The PullParser returns the entries as nodes.  These are equivalent to
@@ -74,29 +80,20 @@ pub struct Parser<S: Sink, B> {
 
     /// Tracking the line number.
     lineno: usize,
+
+    /// Set once the sink has asked to stop.
+    stopped: bool,
 }
 
 impl<S: Sink> Parser<S, BufReader<Box<dyn Read>>> {
-    /// Construct a parser, based on the main file of the naming convention.
+    /// Construct a parser, based on the main file of the naming convention.  The whole weave is
+    /// read, even when it is blocked.
     pub fn new(
         naming: &dyn NamingConvention,
         sink: S,
         delta: usize,
     ) -> Result<Parser<S, BufReader<Box<dyn Read>>>> {
-        let rd = match naming.compression() {
-            Compression::Plain => {
-                Box::new(File::open(naming.main_file())?) as Box<dyn Read>
-            }
-            Compression::Gzip => {
-                let fd = File::open(naming.main_file())?;
-                Box::new(GzDecoder::new(fd)) as Box<dyn Read>
-            }
-            Compression::Zstd => {
-                let fd = File::open(naming.main_file())?;
-                Box::new(zstd::Decoder::new(fd)?) as Box<dyn Read>
-            }
-        };
-        let lines = BufReader::new(rd).lines();
+        let lines = BufReader::new(block::open_all(naming)?).lines();
         Parser::new_raw(lines, Rc::new(RefCell::new(sink)), delta)
     }
 }
@@ -105,31 +102,40 @@ impl<S: Sink, B: BufRead> Parser<S, B> {
     /// Construct a new Parser, reading from the given Reader, giving records to the given Sink,
     /// and aiming for the specified `delta`.  This is not the intended constructor, normal users
     /// should use `new`.  (This is public, for testing).
-    pub fn new_raw(
-        source: Lines<B>,
-        sink: Rc<RefCell<S>>,
-        delta: usize,
-    ) -> Result<Parser<S, B>> {
+    pub fn new_raw(source: Lines<B>, sink: Rc<RefCell<S>>, delta: usize) -> Result<Parser<S, B>> {
         let pull = PullParser::new_raw(source, delta)?;
         Ok(Parser {
             pull,
             sink,
             pending: None,
             lineno: 0,
+            stopped: false,
         })
     }
 
     /// Run the parser until we either reach the given line number, or the end of the weave.  Lines
     /// are numbered from 1, so calling with a lineno of zero will run the parser until the end of
     /// the input.  Returns Ok(0) for the end of input, Ok(n) for stopping at line n (which should
-    /// always be the same as the passed in lineno, or Err if there is an error.
+    /// always be the same as the passed in lineno, or Err if there is an error.  Running to the
+    /// line the parser last stopped at returns right away, so that changes can be made on either
+    /// side of the same point.  If the sink stops the parse, this returns Ok(0), as at the end of
+    /// input, and `stopped` tells the two apart.
     pub fn parse_to(&mut self, lineno: usize) -> Result<usize> {
+        if self.pending.is_some() && self.lineno == lineno {
+            return Ok(lineno);
+        }
+
         // Handle any pending input line.  Pending lines only happen while keeping.
-        if let Some(text) = mem::replace(&mut self.pending, None) {
+        if let Some(text) = self.pending.take() {
             self.sink.borrow_mut().plain(&text, true)?;
         }
 
         loop {
+            if self.stopped || self.sink.borrow().flow().is_break() {
+                self.stopped = true;
+                return Ok(0);
+            }
+
             match self.pull.next() {
                 Some(Ok(Entry::Plain { text, keep })) => {
                     if keep {
@@ -164,6 +170,10 @@ impl<S: Sink, B: BufRead> Parser<S, B> {
         }
     }
 
+    /// Did the sink stop the parse before the end of the weave?
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
 
     /// Get the header read from this weave file.
     pub fn get_header(&self) -> &Header {
@@ -210,6 +220,9 @@ pub struct PullIterator<B> {
 /// delta with [`PullParser::new`], the parser can be used as an iterator, to return [`Entry`] values.  In
 /// particular, the entries for [`Entry::Plain`] where `keep` is true will be the lines of the
 /// weave that comprise the expected delta.
+///
+/// When reading a blocked weave, blocks with no lines of the delta are skipped entirely, so not
+/// every line of the weave is returned.
 pub struct PullParser<B> {
     /// The lines of the input.
     source: Lines<B>,
@@ -234,21 +247,17 @@ impl PullParser<BufReader<Box<dyn Read>>> {
         naming: &dyn NamingConvention,
         delta: usize,
     ) -> Result<PullParser<BufReader<Box<dyn Read>>>> {
-        let rd = match naming.compression() {
-            Compression::Plain => {
-                Box::new(File::open(naming.main_file())?) as Box<dyn Read>
-            }
-            Compression::Gzip => {
-                let fd = File::open(naming.main_file())?;
-                Box::new(GzDecoder::new(fd)) as Box<dyn Read>
-            }
-            Compression::Zstd => {
-                let fd = File::open(naming.main_file())?;
-                Box::new(zstd::Decoder::new(fd)?) as Box<dyn Read>
+        let header = block::read_header(naming)?;
+        let blocks = match &header.blocks {
+            Some(blocks) => blocks,
+            None => {
+                let lines = BufReader::new(block::open_all(naming)?).lines();
+                return PullParser::new_raw(lines, delta);
             }
         };
-        let lines = BufReader::new(rd).lines();
-        PullParser::new_raw(lines, delta)
+        let rd = block::open_blocks(naming, blocks, |b| b.has_delta(delta))?;
+        let source = BufReader::new(rd).lines();
+        Ok(PullParser::with_header(source, header, delta))
     }
 }
 
@@ -260,19 +269,46 @@ impl<B: BufRead> PullParser<B> {
         if let Some(line) = source.next() {
             let line = line?;
             let header = Header::decode(&line)?;
-
-            Ok(PullParser {
-                source,
-                delta,
-                delta_state: vec![],
-                keeping: false,
-                header,
-            })
+            Ok(PullParser::with_header(source, header, delta))
         } else {
             Err(Error::EmptyWeave)
         }
     }
 
+    /// Construct a parser for the lines following a header that has already been read.
+    fn with_header(source: Lines<B>, header: Header, delta: usize) -> PullParser<B> {
+        PullParser {
+            source,
+            delta,
+            delta_state: vec![],
+            keeping: false,
+            header,
+        }
+    }
+
+    /// The mode for an insert or delete of the given delta, relative to the delta being
+    /// retrieved.
+    fn mode(&self, kind: u8, delta: usize) -> StateMode {
+        match kind {
+            b'I' if self.delta >= delta => StateMode::Keep,
+            b'I' => StateMode::Skip,
+            _ if self.delta >= delta => StateMode::Skip,
+            _ => StateMode::Next,
+        }
+    }
+
+    /// Replace the state with that given by the `\x01S` line at the start of a block.
+    fn set_state(&mut self, line: &str) -> Result<()> {
+        self.delta_state.clear();
+        for item in line[2..].split_whitespace() {
+            let delta: usize = item.get(1..).unwrap_or("").parse()?;
+            let mode = self.mode(item.as_bytes()[0], delta);
+            self.push(delta, mode);
+        }
+        self.update_keep();
+        Ok(())
+    }
+
     /// Remove the given numbered state.
     fn pop(&mut self, delta: usize) {
         // The binary search is reversed, so the largest are first.
@@ -321,6 +357,17 @@ impl<B: BufRead> PullParser<B> {
         self.keeping = false;
     }
 
+    /// The delta that inserted the lines currently being kept, or None if lines aren't being
+    /// kept.  This is the insert that decides they are kept: the newest one not newer than the
+    /// delta being retrieved.
+    pub fn introduced(&self) -> Option<usize> {
+        self.delta_state
+            .iter()
+            .find(|st| st.mode != StateMode::Next)
+            .filter(|st| st.mode == StateMode::Keep)
+            .map(|st| st.delta)
+    }
+
     /// Get the header read from this weave file.
     pub fn get_header(&self) -> &Header {
         &self.header
@@ -362,6 +409,10 @@ impl<B: BufRead> Iterator for PullParser<B> {
 
         let linebytes = line.as_bytes();
 
+        if linebytes.get(1) == Some(&b'S') {
+            return Some(self.set_state(&line).map(|()| Entry::Control));
+        }
+
         if linebytes.len() < 4 {
             return Some(Ok(Entry::Control));
         }
@@ -380,21 +431,13 @@ impl<B: BufRead> Iterator for PullParser<B> {
                 Some(Ok(Entry::End { delta: this_delta }))
             }
             b'I' => {
-                if self.delta >= this_delta {
-                    self.push(this_delta, StateMode::Keep);
-                } else {
-                    self.push(this_delta, StateMode::Skip);
-                }
+                self.push(this_delta, self.mode(b'I', this_delta));
                 self.update_keep();
 
                 Some(Ok(Entry::Insert { delta: this_delta }))
             }
             b'D' => {
-                if self.delta >= this_delta {
-                    self.push(this_delta, StateMode::Skip);
-                } else {
-                    self.push(this_delta, StateMode::Next);
-                }
+                self.push(this_delta, self.mode(b'D', this_delta));
                 self.update_keep();
 
                 Some(Ok(Entry::Delete { delta: this_delta }))
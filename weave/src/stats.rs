@@ -0,0 +1,102 @@
+//! Figures about a weave file, to see how it grows.
+
+use crate::{block::live_ranges, DeltaRange, Entry, Error, NamingConvention, PullParser, Result};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    rc::Rc,
+};
+
+/// The sizes of a weave file, and of each of its deltas.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WeaveStats {
+    /// The size of the main file, as stored, compressed and encrypted if it is.
+    pub stored: u64,
+    /// The size of the weave once decompressed, header included.
+    pub plain: u64,
+    /// The lines of text in the weave, those of every delta, each counted once.
+    pub lines: usize,
+    /// The number of lines of each delta, by its number.
+    pub delta_lines: BTreeMap<usize, usize>,
+}
+
+/// Read through the weave of the naming convention, counting the lines of each of its deltas.
+/// This decompresses the whole weave once.
+pub fn stats(naming: &dyn NamingConvention) -> Result<WeaveStats> {
+    let header = crate::read_header(naming)?;
+    let numbers: Vec<usize> = header.deltas.iter().map(|d| d.number).collect();
+    let mut result = WeaveStats {
+        stored: fs::metadata(naming.main_file())?.len(),
+        delta_lines: numbers.iter().map(|&n| (n, 0)).collect(),
+        ..WeaveStats::default()
+    };
+
+    let plain = Rc::new(Cell::new(0));
+    let reader = Counted {
+        inner: crate::block::open_all(naming)?,
+        count: plain.clone(),
+    };
+    let lines = BufReader::new(reader).lines();
+    let first = numbers.first().copied().ok_or(Error::EmptyWeave)?;
+
+    // The open inserts and deletes, newest delta first, as in the parser, and the lines seen
+    // since they last changed.
+    let mut state: Vec<(usize, u8)> = vec![];
+    let mut live: Vec<DeltaRange> = vec![];
+    let mut run = 0;
+    for entry in PullParser::new_raw(lines, first)? {
+        let (number, kind) = match entry? {
+            Entry::Insert { delta } => (delta, b'I'),
+            Entry::Delete { delta } => (delta, b'D'),
+            Entry::End { delta } => (delta, b'E'),
+            Entry::Plain { .. } => {
+                run += 1;
+                continue;
+            }
+            Entry::Control => continue,
+        };
+        add_run(&mut result, &live, run);
+        run = 0;
+        match state.binary_search_by(|ent| number.cmp(&ent.0)) {
+            Ok(pos) if kind == b'E' => {
+                state.remove(pos);
+            }
+            Err(pos) if kind != b'E' => state.insert(pos, (number, kind)),
+            _ => return Err(Error::UnsupportedWeave),
+        }
+        live = live_ranges(&state);
+    }
+    add_run(&mut result, &live, run);
+
+    result.plain = plain.get();
+    Ok(result)
+}
+
+/// Count a run of lines, kept by the deltas in `live`.
+fn add_run(stats: &mut WeaveStats, live: &[DeltaRange], run: usize) {
+    stats.lines += run;
+    for (&number, lines) in stats.delta_lines.iter_mut() {
+        if live
+            .iter()
+            .any(|&(start, end)| number >= start && !matches!(end, Some(end) if number >= end))
+        {
+            *lines += run;
+        }
+    }
+}
+
+/// A reader counting the bytes read through it.
+struct Counted<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
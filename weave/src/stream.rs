@@ -0,0 +1,245 @@
+//! Streaming deltas.
+//!
+//! The buffered [`crate::DeltaWriter`] holds the lines of the base delta in memory, and writes the
+//! whole new delta to a temp file, before computing the differences.  For a very large file, that
+//! is a lot of memory and temp space.  In streaming mode, the new lines are compared as they are
+//! written, against the base delta read alongside, and the new weave is written as the changes
+//! are found.  Only a window of lines from each side is held, and the new weave is the only temp
+//! file.
+//!
+//! Changes that don't fit in the window can't be matched up, and are stored as the lines
+//! deleted, and the lines inserted, so the weave may grow more than with the buffered writer.
+//! Appended lines, and lines changed in place, the usual changes to a surefile, are found just
+//! the same.
+
+use crate::{
+    block::WeaveWrite,
+    delta::{apply_change, Cancel, WeaveWriter},
+    diff::{diff, Hunk},
+    header::Header,
+    naming::temp_writer,
+    Entry, Error, NamingConvention, Parser, PullParser, Result,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read},
+    mem,
+    path::PathBuf,
+    rc::Rc,
+};
+
+type Reader = BufReader<Box<dyn Read>>;
+
+pub(crate) struct Stream {
+    // Writes the new weave.
+    parser: Parser<WeaveWriter<Box<dyn WeaveWrite>>, Reader>,
+    // The name of the new weave.
+    name: PathBuf,
+    // Reads the lines of the base delta.
+    base: PullParser<Reader>,
+    // The window of base lines not yet settled, the number of the first of them, counting from
+    // zero, and whether they are the last.
+    old: VecDeque<String>,
+    old_pos: usize,
+    old_eof: bool,
+    // The window of new lines not yet settled.
+    new: VecDeque<String>,
+    // The start of a line not yet ended.
+    partial: Vec<u8>,
+    window: usize,
+    delta: usize,
+    // The parser has reached the end of the weave.
+    done: bool,
+}
+
+impl Stream {
+    /// Start writing a new weave, adding `delta` after `base`.  The header already has the new
+    /// delta.
+    pub(crate) fn new(
+        naming: &dyn NamingConvention,
+        header: &Header,
+        base: usize,
+        delta: usize,
+        block_size: Option<usize>,
+        window: usize,
+    ) -> Result<Stream> {
+        let info = temp_writer(naming, block_size)?;
+        let mut sink = WeaveWriter::new(info.writer);
+        header.write(&mut sink.dest)?;
+
+        Ok(Stream {
+            parser: Parser::new(naming, sink, base)?,
+            name: info.name,
+            base: PullParser::new(naming, base)?,
+            old: VecDeque::new(),
+            old_pos: 0,
+            old_eof: false,
+            new: VecDeque::new(),
+            partial: vec![],
+            window,
+            delta,
+            done: false,
+        })
+    }
+
+    /// Stop writing once `cancel` returns true.
+    pub(crate) fn set_cancel(&mut self, cancel: Cancel) {
+        self.parser.get_sink().borrow_mut().cancel = Some(cancel);
+    }
+
+    /// Take the lines of the new delta.  As with `BufRead::lines`, a line may end with "\r\n".
+    pub(crate) fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let line = self.take_line()?;
+            self.push_line(line).map_err(into_io)?;
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    /// Settle the rest of the changes, and finish the new weave, returning its name.
+    pub(crate) fn finish(mut self) -> Result<PathBuf> {
+        if !self.partial.is_empty() {
+            let line = self.take_line()?;
+            self.new.push_back(line);
+        }
+        while !(self.old_eof && self.old.is_empty() && self.new.is_empty()) {
+            self.settle(true)?;
+        }
+        if !self.done {
+            match self.parser.parse_to(0)? {
+                0 => (),
+                n => return Err(Error::OutOfStep(0, n)),
+            }
+        }
+
+        let sink = self.parser.get_sink();
+        drop(self.parser);
+        match Rc::try_unwrap(sink) {
+            Ok(sink) => sink.into_inner().dest.finish()?,
+            Err(_) => unreachable!("weave writer still shared"),
+        }
+        Ok(self.name)
+    }
+
+    fn take_line(&mut self) -> io::Result<String> {
+        let mut line = mem::take(&mut self.partial);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )
+        })
+    }
+
+    fn push_line(&mut self, line: String) -> Result<()> {
+        // Lines matching the base as it is read need nothing written for them.
+        if self.new.is_empty() && self.old.is_empty() && !self.old_eof {
+            match self.next_base()? {
+                Some(text) if text == line => {
+                    self.old_pos += 1;
+                    return Ok(());
+                }
+                Some(text) => self.old.push_back(text),
+                None => self.old_eof = true,
+            }
+        }
+        self.new.push_back(line);
+        if self.new.len() >= self.window {
+            self.settle(false)?;
+        }
+        Ok(())
+    }
+
+    /// The next line of the base delta.
+    fn next_base(&mut self) -> Result<Option<String>> {
+        for entry in &mut self.base {
+            if let Entry::Plain { text, keep: true } = entry? {
+                return Ok(Some(text));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Compare the windows, and write out the changes that later lines can't alter.  With
+    /// `new_done`, there are no more new lines.
+    fn settle(&mut self, new_done: bool) -> Result<()> {
+        while self.old.len() < self.window && !self.old_eof {
+            match self.next_base()? {
+                Some(text) => self.old.push_back(text),
+                None => self.old_eof = true,
+            }
+        }
+
+        let old = self.old.make_contiguous();
+        let new = self.new.make_contiguous();
+        let mut hunks = diff(old, new);
+
+        // A change reaching the end of both windows may go on past them, unless both sides are
+        // complete, so it is left for later, along with the rest of the windows.
+        let open = hunks
+            .last()
+            .is_some_and(|h| h.old + h.old_len == old.len() && h.new + h.new_len == new.len())
+            && !(self.old_eof && new_done);
+        let (old_end, new_end) = match hunks.pop() {
+            Some(h) if open && (h.old > 0 || h.new > 0) => (h.old, h.new),
+            Some(_) if open => {
+                // The windows have nothing in common.  Settle part of them as a replacement, so
+                // the windows can move on.
+                let old_end = if self.old_eof {
+                    0
+                } else {
+                    old.len().div_ceil(2)
+                };
+                let new_end = match (new_done, old.is_empty()) {
+                    (true, _) => 0,
+                    (false, true) => new.len(),
+                    (false, false) => new.len().div_ceil(2),
+                };
+                hunks.push(Hunk {
+                    old: 0,
+                    old_len: old_end,
+                    new: 0,
+                    new_len: new_end,
+                });
+                (old_end, new_end)
+            }
+            Some(h) => {
+                hunks.push(h);
+                (old.len(), new.len())
+            }
+            None => (old.len(), new.len()),
+        };
+
+        for h in &hunks {
+            let lines = &new[h.new..h.new + h.new_len];
+            if apply_change(
+                &mut self.parser,
+                self.delta,
+                self.old_pos + h.old,
+                h.old_len,
+                lines,
+            )? {
+                self.done = true;
+            }
+        }
+
+        self.old.drain(..old_end);
+        self.new.drain(..new_end);
+        self.old_pos += old_end;
+        Ok(())
+    }
+}
+
+fn into_io(err: Error) -> io::Error {
+    match err {
+        Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
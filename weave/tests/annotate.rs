@@ -0,0 +1,63 @@
+// Annotating the lines of a delta with the delta that added them.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write};
+
+use tempdir::TempDir;
+use weave::{AnnotatedLine, Annotator, Compression, DeltaWriter, NewWeave, SimpleNaming};
+
+#[test]
+fn annotate() {
+    let versions: &[&[&str]] = &[
+        &["a", "b", "c"],
+        &["a", "x", "c"],
+        &["a", "x", "c", "d"],
+        &["b", "x", "c", "d"],
+    ];
+    let expect: &[&[usize]] = &[&[1, 1, 1], &[1, 2, 1], &[1, 2, 1, 3], &[4, 2, 1, 3]];
+
+    for &blocked in &[false, true] {
+        let tmp = TempDir::new("weave").unwrap();
+        let mut nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+        if blocked {
+            nc = nc.with_block_size(4);
+        }
+
+        for (i, lines) in versions.iter().enumerate() {
+            let name = format!("{}", i + 1);
+            let mut tags = BTreeMap::new();
+            tags.insert("name", name.as_str());
+            if i == 0 {
+                let mut nw = NewWeave::new(&nc, tags.into_iter()).unwrap();
+                for line in lines.iter() {
+                    writeln!(nw, "{}", line).unwrap();
+                }
+                nw.close().unwrap();
+            } else {
+                let mut dw = DeltaWriter::new(&nc, tags.into_iter(), i).unwrap();
+                for line in lines.iter() {
+                    writeln!(dw, "{}", line).unwrap();
+                }
+                dw.close().unwrap();
+            }
+        }
+
+        for (i, (lines, deltas)) in versions.iter().zip(expect).enumerate() {
+            let got: Vec<_> = Annotator::new(&nc, i + 1)
+                .unwrap()
+                .map(|l| l.unwrap())
+                .collect();
+            let want: Vec<_> = lines
+                .iter()
+                .zip(deltas.iter())
+                .map(|(text, &delta)| AnnotatedLine {
+                    delta,
+                    text: text.to_string(),
+                })
+                .collect();
+            assert_eq!(got, want, "delta {}", i + 1);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Keeping several generations of backups.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write};
+
+use tempdir::TempDir;
+use weave::{
+    delete_delta, read_header, Compression, DeltaWriter, NamingConvention, NewWeave, SimpleNaming,
+    SystemClock,
+};
+
+fn add(nc: &SimpleNaming, name: &str) {
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name);
+    if nc.main_file().exists() {
+        let mut dw = DeltaWriter::on_latest(nc, tags.into_iter(), &SystemClock).unwrap();
+        writeln!(dw, "{}", name).unwrap();
+        dw.close().unwrap();
+    } else {
+        let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+        writeln!(nw, "{}", name).unwrap();
+        nw.close().unwrap();
+    }
+}
+
+/// The names of the deltas in a backup, read as the main file of a weave of its own.
+fn names(nc: &SimpleNaming, ext: &str) -> Vec<String> {
+    let backup = SimpleNaming::new(
+        nc.main_file().parent().unwrap(),
+        "sample",
+        ext,
+        Compression::Gzip,
+    );
+    let header = read_header(&backup).unwrap();
+    header.deltas.into_iter().map(|d| d.name).collect()
+}
+
+#[test]
+fn generations() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip).with_backups(3);
+    assert_eq!(nc.backups(), 3);
+    assert_eq!(nc.older_backup_file(2), tmp.path().join("sample.bak.2.gz"));
+
+    for name in &["a", "b", "c", "d", "e"] {
+        add(&nc, name);
+    }
+    assert_eq!(names(&nc, "weave"), ["a", "b", "c", "d", "e"]);
+    assert_eq!(names(&nc, "bak"), ["a", "b", "c", "d"]);
+    assert_eq!(names(&nc, "bak.1"), ["a", "b", "c"]);
+    assert_eq!(names(&nc, "bak.2"), ["a", "b"]);
+    assert!(!nc.older_backup_file(3).exists());
+
+    // Removing a delta is a write like any other.
+    delete_delta(&nc, 3).unwrap();
+    assert_eq!(names(&nc, "weave"), ["a", "b", "d", "e"]);
+    assert_eq!(names(&nc, "bak"), ["a", "b", "c", "d", "e"]);
+    assert_eq!(names(&nc, "bak.1"), ["a", "b", "c", "d"]);
+    assert_eq!(names(&nc, "bak.2"), ["a", "b", "c"]);
+}
+
+#[test]
+fn single_backup() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip).with_backups(0);
+    assert_eq!(nc.backups(), 1);
+    for name in &["a", "b", "c"] {
+        add(&nc, name);
+    }
+    assert_eq!(names(&nc, "bak"), ["a", "b"]);
+    assert!(!nc.older_backup_file(1).exists());
+}
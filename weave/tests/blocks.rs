@@ -0,0 +1,123 @@
+// Blocked weave files.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write};
+
+use tempdir::TempDir;
+use weave::{
+    read_header, Compression, DeltaWriter, Entry, NamingConvention, NewWeave, PullParser,
+    SimpleNaming,
+};
+
+/// The lines of each version: a long history of lines that are later removed, followed by lines
+/// that stay.
+fn version(i: usize) -> Vec<String> {
+    let mut lines: Vec<String> = (0..40).map(|n| format!("old {} {}", i, n)).collect();
+    lines.extend((0..40).map(|n| format!("kept {}", n)));
+    lines
+}
+
+fn write_version(nc: &dyn NamingConvention, i: usize) {
+    let name = format!("{}", i);
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name.as_str());
+    let lines = version(i);
+    if i == 1 {
+        let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+        for line in &lines {
+            writeln!(nw, "{}", line).unwrap();
+        }
+        nw.close().unwrap();
+    } else {
+        let mut dw = DeltaWriter::new(nc, tags.into_iter(), i - 1).unwrap();
+        for line in &lines {
+            writeln!(dw, "{}", line).unwrap();
+        }
+        dw.close().unwrap();
+    }
+}
+
+fn read_version(nc: &dyn NamingConvention, i: usize) -> Vec<String> {
+    PullParser::new(nc, i)
+        .unwrap()
+        .filter_map(|e| match e.unwrap() {
+            Entry::Plain { text, keep: true } => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn blocked() {
+    for &compression in &[Compression::Plain, Compression::Gzip, Compression::Zstd] {
+        let tmp = TempDir::new("weave").unwrap();
+        let nc = SimpleNaming::new(tmp.path(), "sample", "weave", compression).with_block_size(256);
+
+        for i in 1..=8 {
+            write_version(&nc, i);
+        }
+        for i in 1..=8 {
+            assert_eq!(
+                read_version(&nc, i),
+                version(i),
+                "{:?} delta {}",
+                compression,
+                i
+            );
+        }
+
+        let header = read_header(&nc).unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.deltas.len(), 8);
+        let blocks = header.blocks.unwrap();
+        assert!(blocks.len() > 8);
+        // The lines deleted from earlier versions don't need to be read for the latest.
+        assert!(blocks.iter().any(|b| !b.has_delta(8)));
+        assert!(blocks.iter().any(|b| b.has_delta(8)));
+    }
+}
+
+#[test]
+fn stays_blocked() {
+    let tmp = TempDir::new("weave").unwrap();
+    let blocked =
+        SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip).with_block_size(256);
+    let plain = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+
+    // Deltas added with a naming that doesn't ask for blocks keep the existing format.
+    write_version(&plain, 1);
+    write_version(&blocked, 2);
+    write_version(&plain, 3);
+    assert!(read_header(&plain).unwrap().blocks.is_some());
+    for i in 1..=3 {
+        assert_eq!(read_version(&plain, i), version(i));
+    }
+}
+
+#[test]
+fn threaded_decode() {
+    for &block_size in &[None, Some(256)] {
+        let tmp = TempDir::new("weave").unwrap();
+        let mut nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+        if let Some(size) = block_size {
+            nc = nc.with_block_size(size);
+        }
+        let nc = nc.with_decode_threads(3);
+
+        // Adding deltas reads the whole weave back.
+        for i in 1..=6 {
+            write_version(&nc, i);
+        }
+        for i in 1..=6 {
+            assert_eq!(
+                read_version(&nc, i),
+                version(i),
+                "{:?} delta {}",
+                block_size,
+                i
+            );
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Weaves written through a cipher.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use tempdir::TempDir;
+use weave::{
+    Cipher, Compression, DeltaWriter, EncryptWrite, Entry, Error, NamingConvention, NewWeave,
+    PullParser, Result, SimpleNaming,
+};
+
+/// A stand-in for real encryption, which flips every bit, and ends the file with a marker, so a
+/// file that wasn't finished can't be read.
+#[derive(Debug)]
+struct Flip;
+
+const END: &[u8] = b"end";
+
+struct FlipWriter(File);
+
+impl Write for FlipWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let flipped: Vec<u8> = buf.iter().map(|b| !b).collect();
+        self.0.write_all(&flipped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl EncryptWrite for FlipWriter {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.write_all(END)?;
+        Ok(())
+    }
+}
+
+impl Cipher for Flip {
+    fn encrypt(&self, dest: File) -> Result<Box<dyn EncryptWrite>> {
+        Ok(Box::new(FlipWriter(dest)))
+    }
+
+    fn decrypt(&self, mut src: File) -> Result<Box<dyn Read + Send>> {
+        let mut data = vec![];
+        src.read_to_end(&mut data)?;
+        if !data.ends_with(END) {
+            return Err(Error::UnexpectedEof);
+        }
+        data.truncate(data.len() - END.len());
+        let plain: Vec<u8> = data.iter().map(|b| !b).collect();
+        Ok(Box::new(io::Cursor::new(plain)))
+    }
+}
+
+fn lines(nc: &dyn NamingConvention, delta: usize) -> Vec<String> {
+    PullParser::new(nc, delta)
+        .unwrap()
+        .filter_map(|e| match e.unwrap() {
+            Entry::Plain { text, keep: true } => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+fn add(nc: &dyn NamingConvention, name: &str, text: &str) {
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name);
+    match weave::get_last_delta(nc) {
+        Ok(base) => {
+            let mut dw = DeltaWriter::new(nc, tags.into_iter(), base).unwrap();
+            dw.write_all(text.as_bytes()).unwrap();
+            dw.close().unwrap();
+        }
+        Err(_) => {
+            let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+            nw.write_all(text.as_bytes()).unwrap();
+            nw.close().unwrap();
+        }
+    }
+}
+
+#[test]
+fn encrypted() {
+    for &compression in &[Compression::Plain, Compression::Gzip, Compression::Zstd] {
+        for &window in &[None, Some(4)] {
+            let tmp = TempDir::new("weave").unwrap();
+            let mut nc = SimpleNaming::new(tmp.path(), "sample", "weave", compression)
+                .with_cipher(Arc::new(Flip));
+            if let Some(window) = window {
+                nc = nc.with_delta_window(window);
+            }
+            add(&nc, "first", "secret one\nsecret two\n");
+            add(&nc, "second", "secret one\nsecret three\n");
+
+            assert_eq!(lines(&nc, 1), ["secret one", "secret two"]);
+            assert_eq!(lines(&nc, 2), ["secret one", "secret three"]);
+            assert_eq!(weave::read_header(&nc).unwrap().deltas.len(), 2);
+
+            // The main file and backup are both encrypted, and no temp files are left, only the
+            // lock file.
+            for name in &[nc.main_file(), nc.backup_file()] {
+                let data = fs::read(name).unwrap();
+                assert!(data.ends_with(END));
+                assert!(!String::from_utf8_lossy(&data).contains("secret"));
+            }
+            assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+            assert!(nc.lock_file().exists());
+
+            // Without the cipher, the weave can't be read.
+            let plain = SimpleNaming::new(tmp.path(), "sample", "weave", compression);
+            assert!(PullParser::new(&plain, 2).is_err());
+        }
+    }
+}
+
+#[test]
+fn blocked() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip)
+        .with_cipher(Arc::new(Flip))
+        .with_block_size(256);
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "first");
+    assert!(matches!(
+        NewWeave::new(&nc, tags.into_iter()),
+        Err(Error::EncryptedBlocks)
+    ));
+}
@@ -0,0 +1,129 @@
+// Removing deltas from a weave file.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write};
+
+use tempdir::TempDir;
+use weave::{
+    delete_delta, read_header, Compression, DeltaWriter, Entry, Error, NamingConvention, NewWeave,
+    PullParser, SimpleNaming,
+};
+
+/// The lines of each version.  Some lines come and go, some are only in one version, and some are
+/// in every version.
+fn version(i: usize) -> Vec<String> {
+    let mut lines = vec!["first".to_string()];
+    lines.extend((0..i).map(|n| format!("grow {}", n)));
+    lines.push(format!("only {}", i));
+    if i == 2 || i == 4 {
+        lines.push("between".to_string());
+    }
+    lines.extend((i..6).map(|n| format!("shrink {}", n)));
+    lines.push("last".to_string());
+    lines
+}
+
+fn write_version(nc: &dyn NamingConvention, i: usize, base: Option<usize>) {
+    let name = format!("{}", i);
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name.as_str());
+    let lines = version(i);
+    match base {
+        None => {
+            let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+            for line in &lines {
+                writeln!(nw, "{}", line).unwrap();
+            }
+            nw.close().unwrap();
+        }
+        Some(base) => {
+            let mut dw = DeltaWriter::new(nc, tags.into_iter(), base).unwrap();
+            for line in &lines {
+                writeln!(dw, "{}", line).unwrap();
+            }
+            dw.close().unwrap();
+        }
+    }
+}
+
+fn read_version(nc: &dyn NamingConvention, i: usize) -> Vec<String> {
+    PullParser::new(nc, i)
+        .unwrap()
+        .filter_map(|e| match e.unwrap() {
+            Entry::Plain { text, keep: true } => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+fn numbers(nc: &dyn NamingConvention) -> Vec<usize> {
+    read_header(nc)
+        .unwrap()
+        .deltas
+        .iter()
+        .map(|d| d.number)
+        .collect()
+}
+
+#[test]
+fn delete() {
+    for &blocked in &[false, true] {
+        let tmp = TempDir::new("weave").unwrap();
+        let mut nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+        if blocked {
+            nc = nc.with_block_size(64);
+        }
+
+        write_version(&nc, 1, None);
+        for i in 2..=5 {
+            write_version(&nc, i, Some(i - 1));
+        }
+
+        // From the middle, the start and the end.
+        for &gone in &[3, 1, 5] {
+            delete_delta(&nc, gone).unwrap();
+            let left = numbers(&nc);
+            assert!(!left.contains(&gone));
+            for &i in &left {
+                assert_eq!(
+                    read_version(&nc, i),
+                    version(i),
+                    "delta {} after {}",
+                    i,
+                    gone
+                );
+            }
+            assert_eq!(read_header(&nc).unwrap().blocks.is_some(), blocked);
+        }
+        assert_eq!(numbers(&nc), [2, 4]);
+
+        // Lines only in the removed deltas are gone.
+        let all: Vec<_> = PullParser::new(&nc, 4)
+            .unwrap()
+            .filter_map(|e| match e.unwrap() {
+                Entry::Plain { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert!(!all.iter().any(|l| l == "only 3" || l == "only 5"));
+
+        // New deltas carry on from there, never given the number of a deleted one.
+        write_version(&nc, 6, Some(4));
+        assert_eq!(numbers(&nc), [2, 4, 6]);
+        assert_eq!(read_version(&nc, 2), version(2));
+        assert_eq!(read_version(&nc, 4), version(4));
+        assert_eq!(read_version(&nc, 6), version(6));
+
+        assert!(matches!(delete_delta(&nc, 3), Err(Error::NoSuchDelta(3))));
+        delete_delta(&nc, 2).unwrap();
+        delete_delta(&nc, 6).unwrap();
+        write_version(&nc, 7, Some(4));
+        assert_eq!(numbers(&nc), [4, 7]);
+        assert_eq!(read_version(&nc, 7), version(7));
+        delete_delta(&nc, 7).unwrap();
+        assert!(matches!(delete_delta(&nc, 4), Err(Error::LastDelta)));
+        assert_eq!(read_version(&nc, 4), version(4));
+    }
+}
@@ -0,0 +1,107 @@
+// Test deltas at the edges: empty versions, and unchanged versions.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tempdir::TempDir;
+use weave::{
+    read_header, Compression, DeltaWriter, Entry, Error, NewWeave, PullParser, SimpleNaming,
+};
+
+#[test]
+fn edge_deltas() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain);
+
+    let versions: &[&[&str]] = &[
+        &[],
+        &["a", "b", "c"],
+        &["a", "b", "c"],
+        &[],
+        &["x"],
+        &["a", "x", "b", "x", "c", "x"],
+        &["x", "x", "x"],
+    ];
+
+    for (i, lines) in versions.iter().enumerate() {
+        let name = format!("{}", i + 1);
+        let mut tags = BTreeMap::new();
+        tags.insert("name", name.as_str());
+        if i == 0 {
+            let mut nw = NewWeave::new(&nc, tags.into_iter()).unwrap();
+            for line in lines.iter() {
+                writeln!(nw, "{}", line).unwrap();
+            }
+            nw.close().unwrap();
+        } else {
+            let mut dw = DeltaWriter::new(&nc, tags.into_iter(), i).unwrap();
+            for line in lines.iter() {
+                writeln!(dw, "{}", line).unwrap();
+            }
+            dw.close().unwrap();
+        }
+    }
+
+    for (i, lines) in versions.iter().enumerate() {
+        let got: Vec<_> = PullParser::new(&nc, i + 1)
+            .unwrap()
+            .filter_map(|e| match e.unwrap() {
+                Entry::Plain { text, keep: true } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(&got, lines, "delta {}", i + 1);
+    }
+}
+
+#[test]
+fn cancelled() {
+    let tmp = TempDir::new("weave").unwrap();
+    let plain = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+    let streaming = plain.clone().with_delta_window(100);
+    let lines: Vec<String> = (0..10_000).map(|i| format!("line {}", i)).collect();
+
+    let mut nw = NewWeave::new(&plain, vec![("name", "1")].into_iter()).unwrap();
+    for line in &lines {
+        writeln!(nw, "{}", line).unwrap();
+    }
+    nw.close().unwrap();
+
+    // Cancelled before closing, and part way through writing the new weave, which only happens
+    // for the changes left once the writer is closed.
+    for nc in &[&plain, &streaming] {
+        for &stop_at in &[0, 1] {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let seen = calls.clone();
+            let mut dw = DeltaWriter::new(*nc, vec![("name", "2")].into_iter(), 1).unwrap();
+            dw.set_cancel(Arc::new(move || {
+                seen.fetch_add(1, Ordering::SeqCst) >= stop_at
+            }));
+            writeln!(dw, "first").unwrap();
+            if stop_at > 0 {
+                // Unchanged lines are only copied once the writer is closed.
+                for line in &lines {
+                    writeln!(dw, "{}", line).unwrap();
+                }
+            }
+            assert!(matches!(dw.close(), Err(Error::Cancelled)));
+            assert!(calls.load(Ordering::SeqCst) > stop_at);
+        }
+    }
+
+    // The weave is as it was, and can still be added to.
+    assert_eq!(read_header(&plain).unwrap().deltas.len(), 1);
+    let mut dw = DeltaWriter::new(&plain, vec![("name", "2")].into_iter(), 1).unwrap();
+    writeln!(dw, "first").unwrap();
+    dw.close().unwrap();
+    assert_eq!(read_header(&plain).unwrap().deltas.len(), 2);
+}
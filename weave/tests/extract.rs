@@ -0,0 +1,106 @@
+// Extracting a delta as plain text.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write, ops::ControlFlow};
+
+use tempdir::TempDir;
+use weave::{
+    extract, read_header, Compression, DeltaWriter, Error, NewWeave, Parser, Result, SimpleNaming,
+    Sink,
+};
+
+#[test]
+fn extract_delta() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Zstd);
+
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "base");
+    let mut nw = NewWeave::new(&nc, tags.into_iter()).unwrap();
+    writeln!(nw, "one\ntwo").unwrap();
+    nw.close().unwrap();
+
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "edited");
+    let mut dw = DeltaWriter::new(&nc, tags.into_iter(), 1).unwrap();
+    writeln!(dw, "one\n2\nthree").unwrap();
+    dw.close().unwrap();
+
+    let header = read_header(&nc).unwrap();
+    assert_eq!(header.find_delta("edited"), Some(2));
+    assert_eq!(header.find_delta("missing"), None);
+
+    let mut out = vec![];
+    extract(&nc, header.find_delta("base").unwrap(), &mut out).unwrap();
+    assert_eq!(out, b"one\ntwo\n");
+    let mut out = vec![];
+    extract(&nc, 2, &mut out).unwrap();
+    assert_eq!(out, b"one\n2\nthree\n");
+
+    assert!(matches!(
+        extract(&nc, 3, vec![]),
+        Err(Error::NoSuchDelta(3))
+    ));
+}
+
+/// A sink looking for the first line starting with a prefix, and counting the lines it is given.
+struct Find {
+    prefix: &'static str,
+    found: Option<String>,
+    seen: usize,
+}
+
+impl Sink for Find {
+    fn plain(&mut self, text: &str, keep: bool) -> Result<()> {
+        self.seen += 1;
+        if keep && self.found.is_none() && text.starts_with(self.prefix) {
+            self.found = Some(text.to_string());
+        }
+        Ok(())
+    }
+
+    fn flow(&self) -> ControlFlow<()> {
+        if self.found.is_some() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+#[test]
+fn stop_early() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain);
+
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "base");
+    let mut nw = NewWeave::new(&nc, tags.into_iter()).unwrap();
+    for i in 0..1000 {
+        writeln!(nw, "line {}", i).unwrap();
+    }
+    nw.close().unwrap();
+
+    let find = |prefix| Find {
+        prefix,
+        found: None,
+        seen: 0,
+    };
+
+    let mut parser = Parser::new(&nc, find("line 10"), 1).unwrap();
+    assert_eq!(parser.parse_to(0).unwrap(), 0);
+    assert!(parser.stopped());
+    // Stopping is kept, and the rest of the weave is never read.
+    assert_eq!(parser.parse_to(0).unwrap(), 0);
+    let sink = parser.get_sink();
+    assert_eq!(sink.borrow().found.as_deref(), Some("line 10"));
+    assert_eq!(sink.borrow().seen, 11);
+
+    // Without a match, the whole weave is read.
+    let mut parser = Parser::new(&nc, find("missing"), 1).unwrap();
+    assert_eq!(parser.parse_to(0).unwrap(), 0);
+    assert!(!parser.stopped());
+    assert_eq!(parser.get_sink().borrow().seen, 1000);
+}
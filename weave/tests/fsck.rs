@@ -0,0 +1,88 @@
+// Checking the structure of weave files.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, fs, io::Write};
+
+use tempdir::TempDir;
+use weave::{fsck, Compression, DeltaWriter, NamingConvention, NewWeave, SimpleNaming};
+
+fn write_versions(nc: &dyn NamingConvention) {
+    let texts = ["one\ntwo\n", "one\n2\nthree\n", "2\nthree\n"];
+    for (i, text) in texts.iter().enumerate() {
+        let name = format!("v{}", i + 1);
+        let mut tags = BTreeMap::new();
+        tags.insert("name", name.as_str());
+        if i == 0 {
+            let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+            nw.write_all(text.as_bytes()).unwrap();
+            nw.close().unwrap();
+        } else {
+            let mut dw = DeltaWriter::new(nc, tags.into_iter(), i).unwrap();
+            dw.write_all(text.as_bytes()).unwrap();
+            dw.close().unwrap();
+        }
+    }
+}
+
+/// The problems found after changing the text of a plain weave.
+fn damaged<F>(edit: F) -> Vec<String>
+where
+    F: FnOnce(String) -> String,
+{
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain);
+    write_versions(&nc);
+    let text = fs::read_to_string(nc.main_file()).unwrap();
+    fs::write(nc.main_file(), edit(text)).unwrap();
+    fsck(&nc).unwrap().iter().map(|p| p.to_string()).collect()
+}
+
+#[test]
+fn sound() {
+    let tmp = TempDir::new("weave").unwrap();
+    let plain = SimpleNaming::new(tmp.path(), "plain", "weave", Compression::Gzip);
+    write_versions(&plain);
+    assert_eq!(fsck(&plain).unwrap(), []);
+
+    let blocked =
+        SimpleNaming::new(tmp.path(), "blocked", "weave", Compression::Zstd).with_block_size(4);
+    write_versions(&blocked);
+    assert_eq!(fsck(&blocked).unwrap(), []);
+    assert_eq!(fsck(&blocked.with_decode_threads(2)).unwrap(), []);
+}
+
+#[test]
+fn unbalanced() {
+    let problems = damaged(|text| text.replacen("\x01E 1\n", "", 1));
+    assert_eq!(problems, ["insert of delta 1 never ended"]);
+
+    let problems = damaged(|text| text.replacen("\x01I 1\n", "", 1));
+    assert!(problems.contains(&"line 3: text outside of any insert".to_string()));
+    assert!(problems
+        .iter()
+        .any(|p| p.ends_with("end of delta 1, which isn't open")));
+}
+
+#[test]
+fn unknown_deltas() {
+    let problems = damaged(|text| text.replace(" 3\n", " 7\n"));
+    assert!(!problems.is_empty());
+    assert!(problems
+        .iter()
+        .all(|p| p.ends_with("of delta 7, which isn't in the header")));
+
+    let problems = damaged(|text| text.replacen("\x01I 1\n", "\x01X 1\n", 1));
+    assert!(problems.contains(&"line 2: unknown control line \"\\u{1}X 1\"".to_string()));
+}
+
+#[test]
+fn header() {
+    let problems = damaged(|text| {
+        text.replacen("\"number\":2", "\"number\":1", 1)
+            .replacen("\"v3\"", "\"v1\"", 1)
+    });
+    assert!(problems.contains(&"line 1: delta 1 follows delta 1 in the header".to_string()));
+    assert!(problems.contains(&"line 1: delta name \"v1\" is repeated".to_string()));
+}
@@ -0,0 +1,84 @@
+// Writers locking a weave against each other.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, io::Write, thread, time::Duration};
+
+use tempdir::TempDir;
+use weave::{
+    delete_delta, extract, get_last_delta, lock, Compression, DeltaWriter, Error, NewWeave,
+    SimpleNaming, SystemClock,
+};
+
+fn tags(name: &str) -> BTreeMap<&str, &str> {
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name);
+    tags
+}
+
+fn text(nc: &SimpleNaming, delta: usize) -> String {
+    let mut buf = vec![];
+    extract(nc, delta, &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn second_writer_fails() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip);
+    let mut nw = NewWeave::new(&nc, tags("1").into_iter()).unwrap();
+    nw.write_all(b"one\n").unwrap();
+    nw.close().unwrap();
+
+    let mut dw = DeltaWriter::new(&nc, tags("2").into_iter(), 1).unwrap();
+    match DeltaWriter::new(&nc, tags("other").into_iter(), 1) {
+        Err(Error::Locked(path)) => assert_eq!(path, tmp.path().join("sample.lock")),
+        Err(e) => panic!("Unexpected error: {:?}", e),
+        Ok(_) => panic!("Second writer not locked out"),
+    }
+    assert!(matches!(
+        NewWeave::new(&nc, tags("other").into_iter()),
+        Err(Error::Locked(_))
+    ));
+    assert!(matches!(delete_delta(&nc, 1), Err(Error::Locked(_))));
+
+    dw.write_all(b"one\ntwo\n").unwrap();
+    dw.close().unwrap();
+    assert_eq!(get_last_delta(&nc).unwrap(), 2);
+
+    // Once the first writer is done, the lock is free again.
+    let dw = DeltaWriter::new(&nc, tags("3").into_iter(), 2).unwrap();
+    drop(dw);
+    drop(lock(&nc).unwrap());
+}
+
+#[test]
+fn waiting_writer() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain)
+        .with_lock_wait(Duration::from_secs(30));
+    let mut nw = NewWeave::new(&nc, tags("1").into_iter()).unwrap();
+    nw.write_all(b"one\n").unwrap();
+    nw.close().unwrap();
+
+    // The second writer waits for the first, and is based on its delta.
+    let mut dw = DeltaWriter::new(&nc, tags("2").into_iter(), 1).unwrap();
+    let waiter = {
+        let nc = nc.clone();
+        thread::spawn(move || {
+            let mut dw = DeltaWriter::on_latest(&nc, tags("3").into_iter(), &SystemClock).unwrap();
+            dw.write_all(b"one\ntwo\nthree\n").unwrap();
+            dw.close().unwrap();
+        })
+    };
+    thread::sleep(Duration::from_millis(300));
+    dw.write_all(b"one\ntwo\n").unwrap();
+    dw.close().unwrap();
+    waiter.join().unwrap();
+
+    assert_eq!(get_last_delta(&nc).unwrap(), 3);
+    assert_eq!(text(&nc, 1), "one\n");
+    assert_eq!(text(&nc, 2), "one\ntwo\n");
+    assert_eq!(text(&nc, 3), "one\ntwo\nthree\n");
+}
@@ -6,7 +6,7 @@ extern crate weave;
 use std::path::Path;
 
 use tempdir::TempDir;
-use weave::{NamingConvention, SimpleNaming, Compression};
+use weave::{Compression, NamingConvention, SimpleNaming};
 
 #[test]
 fn test_names() {
@@ -31,7 +31,7 @@ const VERIFY_ALL_DELTAS: bool = true;
 
 #[test]
 fn sccs() {
-    let _ = env_logger::init();
+    env_logger::init();
 
     // Normally, detect the SCCS command being present, and use it for additional tests.  It can be
     // ignored by setting NO_SCCS=1 in the environment.
@@ -109,7 +109,7 @@ impl Gen {
             nums: (1..FILE_SIZE + 1).collect(),
             rand: SeedableRng::from_seed(seed),
             deltas: vec![],
-            use_sccs: use_sccs,
+            use_sccs,
         })
     }
 
@@ -135,7 +135,7 @@ impl Gen {
 
         self.emit_to(&self.sccs_plain);
         Command::new("sccs")
-            .args(&["admin", "-itfile", "-n", "s.tfile"])
+            .args(["admin", "-itfile", "-n", "s.tfile"])
             .current_dir(&self.tdir)
             .status()
             .expect("Unable to run sccs admin")
@@ -150,7 +150,7 @@ impl Gen {
         }
 
         Command::new("sccs")
-            .args(&["get", "-e", "s.tfile"])
+            .args(["get", "-e", "s.tfile"])
             .current_dir(&self.tdir)
             .stderr(Stdio::null())
             .stdout(Stdio::null())
@@ -159,7 +159,7 @@ impl Gen {
             .expect_success("sccs get failed");
         self.emit_to(&self.sccs_plain);
         Command::new("sccs")
-            .args(&["delta", "-yMessage", "s.tfile"])
+            .args(["delta", "-yMessage", "s.tfile"])
             .current_dir(&self.tdir)
             .stderr(Stdio::null())
             .stdout(Stdio::null())
@@ -191,7 +191,7 @@ impl Gen {
         }
 
         let out = Command::new("sccs")
-            .args(&["get", &format!("-r1.{}", num + 1), "-p", "s.tfile"])
+            .args(["get", &format!("-r1.{}", num + 1), "-p", "s.tfile"])
             .current_dir(&self.tdir)
             .output()
             .expect("Unable to run sccs get");
@@ -233,13 +233,10 @@ impl Gen {
         let lines = BufReader::new(fd).lines();
         let mut nums: Vec<usize> = vec![];
         for node in PullParser::new_raw(lines, num + 1).unwrap() {
-            match node.unwrap() {
-                Entry::Plain { text, keep } => {
-                    if keep {
-                        nums.push(text.parse::<usize>().unwrap());
-                    }
+            if let Entry::Plain { text, keep } = node.unwrap() {
+                if keep {
+                    nums.push(text.parse::<usize>().unwrap());
                 }
-                _ => (),
             }
         }
         assert_eq!(data, nums);
@@ -250,13 +247,10 @@ impl Gen {
         let lines = BufReader::new(fd).lines();
         let mut nums: Vec<usize> = vec![];
         for node in PullParser::new_raw(lines, num + 1).unwrap() {
-            match node.unwrap() {
-                Entry::Plain { text, keep } => {
-                    if keep {
-                        nums.push(text.parse::<usize>().unwrap());
-                    }
+            if let Entry::Plain { text, keep } = node.unwrap() {
+                if keep {
+                    nums.push(text.parse::<usize>().unwrap());
                 }
-                _ => (),
             }
         }
 
@@ -305,6 +299,7 @@ impl Gen {
 }
 
 /// A Weave Sink that just collects the numbers in the given delta.
+#[allow(dead_code)]
 struct DeltaSink {
     nums: Vec<usize>,
 }
@@ -0,0 +1,69 @@
+// The sizes of a weave and its deltas.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, fs, io::Write};
+
+use tempdir::TempDir;
+use weave::{
+    delete_delta, stats, Compression, DeltaWriter, NamingConvention, NewWeave, SimpleNaming,
+};
+
+/// Write three versions: three lines, then one replaced and one added, then one removed.
+fn write_versions(nc: &dyn NamingConvention) {
+    let texts = [
+        "one\ntwo\nthree\n",
+        "one\n2\nthree\nfour\n",
+        "one\n2\nfour\n",
+    ];
+    for (i, text) in texts.iter().enumerate() {
+        let name = format!("{}", i + 1);
+        let mut tags = BTreeMap::new();
+        tags.insert("name", name.as_str());
+        if i == 0 {
+            let mut nw = NewWeave::new(nc, tags.into_iter()).unwrap();
+            nw.write_all(text.as_bytes()).unwrap();
+            nw.close().unwrap();
+        } else {
+            let mut dw = DeltaWriter::new(nc, tags.into_iter(), i).unwrap();
+            dw.write_all(text.as_bytes()).unwrap();
+            dw.close().unwrap();
+        }
+    }
+}
+
+#[test]
+fn weave_stats() {
+    let tmp = TempDir::new("weave").unwrap();
+    for (base, nc) in [
+        (
+            "plain",
+            SimpleNaming::new(tmp.path(), "plain", "weave", Compression::Gzip),
+        ),
+        (
+            "blocked",
+            SimpleNaming::new(tmp.path(), "blocked", "weave", Compression::Zstd).with_block_size(8),
+        ),
+    ] {
+        write_versions(&nc);
+        let found = stats(&nc).unwrap();
+        let lines: Vec<_> = found.delta_lines.into_iter().collect();
+        assert_eq!(lines, [(1, 3), (2, 4), (3, 3)], "{}", base);
+        // "one", "two", "2", "three" and "four".
+        assert_eq!(found.lines, 5, "{}", base);
+        assert_eq!(
+            found.stored,
+            fs::metadata(nc.main_file()).unwrap().len(),
+            "{}",
+            base
+        );
+        assert!(found.plain > found.stored, "{}", base);
+
+        delete_delta(&nc, 2).unwrap();
+        let found = stats(&nc).unwrap();
+        let lines: Vec<_> = found.delta_lines.into_iter().collect();
+        assert_eq!(lines, [(1, 3), (3, 3)], "{}", base);
+        assert_eq!(found.lines, 5, "{}", base);
+    }
+}
@@ -0,0 +1,181 @@
+// Deltas written in streaming mode.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, fs, io::Write};
+
+use chrono::{TimeZone, Utc};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tempdir::TempDir;
+use weave::{
+    Compression, DeltaWriter, Entry, FixedClock, NamingConvention, NewWeave, PullParser,
+    SimpleNaming,
+};
+
+/// Write each version to a new weave, the first with `NewWeave`, and the rest as deltas, written
+/// in uneven pieces.
+fn write_versions(nc: &dyn NamingConvention, versions: &[Vec<String>]) {
+    let clock = FixedClock(Utc.timestamp_opt(1_500_000_000, 0).unwrap());
+    for (i, lines) in versions.iter().enumerate() {
+        let name = format!("{}", i + 1);
+        let mut tags = BTreeMap::new();
+        tags.insert("name", name.as_str());
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if i == 0 {
+            let mut nw = NewWeave::with_clock(nc, tags.into_iter(), &clock).unwrap();
+            nw.write_all(text.as_bytes()).unwrap();
+            nw.close().unwrap();
+        } else {
+            let mut dw = DeltaWriter::with_clock(nc, tags.into_iter(), i, &clock).unwrap();
+            for piece in text.as_bytes().chunks(7) {
+                dw.write_all(piece).unwrap();
+            }
+            dw.close().unwrap();
+        }
+    }
+}
+
+fn check_versions(nc: &dyn NamingConvention, versions: &[Vec<String>]) {
+    for (i, lines) in versions.iter().enumerate() {
+        let got: Vec<_> = PullParser::new(nc, i + 1)
+            .unwrap()
+            .filter_map(|e| match e.unwrap() {
+                Entry::Plain { text, keep: true } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(&got, lines, "delta {}", i + 1);
+    }
+}
+
+/// A history of random edits: lines inserted, deleted, changed, and appended, some of them
+/// longer than the smaller windows.
+fn random_versions(rng: &mut StdRng) -> Vec<Vec<String>> {
+    let mut next = 0;
+    let mut line = || {
+        next += 1;
+        format!("line {}", next)
+    };
+    let mut lines: Vec<String> = (0..50).map(|_| line()).collect();
+    let mut versions = vec![lines.clone()];
+    for _ in 0..12 {
+        for _ in 0..rng.gen_range(1..5) {
+            let pos = rng.gen_range(0..=lines.len());
+            let len = rng.gen_range(1..12);
+            match rng.gen_range(0..4) {
+                0 => {
+                    for _ in 0..len {
+                        lines.insert(pos, line());
+                    }
+                }
+                1 => {
+                    let end = (pos + len).min(lines.len());
+                    lines.drain(pos..end);
+                }
+                2 => {
+                    for l in lines.iter_mut().skip(pos).take(len) {
+                        *l = line();
+                    }
+                }
+                _ => {
+                    for _ in 0..len {
+                        lines.push(line());
+                    }
+                }
+            }
+        }
+        versions.push(lines.clone());
+    }
+    versions
+}
+
+#[test]
+fn random() {
+    let mut rng = StdRng::seed_from_u64(3527);
+    for &window in &[1, 2, 5, 64] {
+        for &blocked in &[false, true] {
+            let versions = random_versions(&mut rng);
+            let tmp = TempDir::new("weave").unwrap();
+            let mut nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Gzip)
+                .with_delta_window(window);
+            if blocked {
+                nc = nc.with_block_size(256);
+            }
+            write_versions(&nc, &versions);
+            check_versions(&nc, &versions);
+        }
+    }
+}
+
+#[test]
+fn edges() {
+    let versions: Vec<Vec<String>> = [
+        &[][..],
+        &["a", "b", "c"],
+        &["a", "b", "c"],
+        &[],
+        &["x"],
+        &["a", "x", "b", "x", "c", "x"],
+        &["x", "x", "x"],
+        &["", "", "y"],
+    ]
+    .iter()
+    .map(|v| v.iter().map(|l| l.to_string()).collect())
+    .collect();
+    for &window in &[1, 3] {
+        let tmp = TempDir::new("weave").unwrap();
+        let nc = SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain)
+            .with_delta_window(window);
+        write_versions(&nc, &versions);
+        check_versions(&nc, &versions);
+    }
+}
+
+#[test]
+fn unterminated() {
+    let tmp = TempDir::new("weave").unwrap();
+    let nc =
+        SimpleNaming::new(tmp.path(), "sample", "weave", Compression::Plain).with_delta_window(4);
+    write_versions(&nc, &[vec!["a".to_string()]]);
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "2");
+    let mut dw = DeltaWriter::new(&nc, tags.into_iter(), 1).unwrap();
+    dw.write_all(b"a\r\nb").unwrap();
+    dw.close().unwrap();
+    check_versions(&nc, &[vec!["a".into()], vec!["a".into(), "b".into()]]);
+
+    let mut tags = BTreeMap::new();
+    tags.insert("name", "3");
+    let mut dw = DeltaWriter::new(&nc, tags.into_iter(), 2).unwrap();
+    assert!(dw.write_all(b"\xff\n").is_err());
+}
+
+/// Small changes to a file give the same weave as the buffered writer, once the window holds
+/// them.
+#[test]
+fn same_as_buffered() {
+    let mut versions = vec![];
+    let mut lines: Vec<String> = (0..200).map(|n| format!("line {}", n)).collect();
+    for i in 0..6 {
+        lines[i * 30 + 7] = format!("changed {}", i);
+        lines.push(format!("appended {}", i));
+        lines.remove(i * 25 + 3);
+        versions.push(lines.clone());
+    }
+
+    let tmp = TempDir::new("weave").unwrap();
+    let buffered = SimpleNaming::new(tmp.path(), "buffered", "weave", Compression::Plain);
+    let streamed = SimpleNaming::new(tmp.path(), "streamed", "weave", Compression::Plain)
+        .with_delta_window(16);
+    write_versions(&buffered, &versions);
+    write_versions(&streamed, &versions);
+    assert_eq!(
+        fs::read_to_string(buffered.main_file()).unwrap(),
+        fs::read_to_string(streamed.main_file()).unwrap()
+    );
+}
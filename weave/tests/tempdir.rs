@@ -0,0 +1,77 @@
+// Writing temp files in a directory of their own.
+
+extern crate tempdir;
+extern crate weave;
+
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use tempdir::TempDir;
+use weave::{
+    delete_delta, extract, Compression, DeltaWriter, NamingConvention, NewWeave, SimpleNaming,
+    SystemClock,
+};
+
+fn tags(name: &str) -> BTreeMap<&str, &str> {
+    let mut tags = BTreeMap::new();
+    tags.insert("name", name);
+    tags
+}
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn temp_dir() {
+    // A tmpfs is usually another filesystem, so the new weave is copied into place.
+    let mut parents = vec![std::env::temp_dir()];
+    if Path::new("/dev/shm").is_dir() {
+        parents.push("/dev/shm".into());
+    }
+    for parent in &parents {
+        for &window in &[None, Some(2)] {
+            for &block_size in &[None, Some(4)] {
+                check(parent, window, block_size);
+            }
+        }
+    }
+}
+
+fn check(parent: &Path, window: Option<usize>, block_size: Option<usize>) {
+    let store = TempDir::new("weave").unwrap();
+    let temps = TempDir::new_in(parent, "weave-temp").unwrap();
+    let mut nc = SimpleNaming::new(store.path(), "sample", "weave", Compression::Gzip)
+        .with_temp_dir(temps.path());
+    if let Some(window) = window {
+        nc = nc.with_delta_window(window);
+    }
+    if let Some(size) = block_size {
+        nc = nc.with_block_size(size);
+    }
+
+    let mut nw = NewWeave::new(&nc, tags("1").into_iter()).unwrap();
+    nw.write_all(b"one\ntwo\n").unwrap();
+    assert_eq!(names(store.path()), ["sample.lock"]);
+    assert!(!names(temps.path()).is_empty());
+    nw.close().unwrap();
+
+    let mut dw = DeltaWriter::on_latest(&nc, tags("2").into_iter(), &SystemClock).unwrap();
+    dw.write_all(b"one\n2\nthree\n").unwrap();
+    dw.close().unwrap();
+    delete_delta(&nc, 1).unwrap();
+
+    let mut text = vec![];
+    extract(&nc, 2, &mut text).unwrap();
+    assert_eq!(text, b"one\n2\nthree\n");
+    assert!(names(temps.path()).is_empty());
+    assert_eq!(
+        names(store.path()),
+        ["sample.bak.gz", "sample.lock", "sample.weave.gz"]
+    );
+    assert_eq!(nc.temp_file().unwrap().0.parent(), Some(temps.path()));
+}
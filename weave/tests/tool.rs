@@ -0,0 +1,107 @@
+// Test the weave-tool command.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+use tempdir::TempDir;
+
+fn tool(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_weave-tool"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(dir: &Path, args: &[&str]) -> String {
+    let out = tool(dir, args);
+    assert!(
+        out.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn versions() {
+    let tmp = TempDir::new("weave").unwrap();
+    let dir = tmp.path();
+    fs::write(dir.join("hosts"), "alpha\nbeta\n").unwrap();
+    stdout(dir, &["init", "hosts.weave.gz", "hosts", "-n", "first"]);
+    assert!(dir.join("hosts.weave.gz").is_file());
+
+    // The last line is given a newline.
+    fs::write(dir.join("hosts"), "alpha\ngamma\nbeta").unwrap();
+    stdout(
+        dir,
+        &[
+            "add",
+            "hosts.weave.gz",
+            "hosts",
+            "-n",
+            "second",
+            "-t",
+            "by=me",
+        ],
+    );
+
+    let list = stdout(dir, &["list", "hosts.weave.gz"]);
+    let names: Vec<_> = list
+        .lines()
+        .skip(2)
+        .map(|l| l.rsplit(" | ").next().unwrap())
+        .collect();
+    assert_eq!(names, ["first", "second"]);
+
+    assert_eq!(
+        stdout(dir, &["extract", "hosts.weave.gz"]),
+        "alpha\ngamma\nbeta\n"
+    );
+    assert_eq!(
+        stdout(dir, &["extract", "hosts.weave.gz", "-d", "first"]),
+        "alpha\nbeta\n"
+    );
+    assert_eq!(
+        stdout(dir, &["annotate", "hosts.weave.gz", "-d", "2"]),
+        "    1: alpha\n    2: gamma\n    1: beta\n"
+    );
+}
+
+#[test]
+fn errors() {
+    let tmp = TempDir::new("weave").unwrap();
+    let dir = tmp.path();
+    fs::write(dir.join("text"), "one\n").unwrap();
+    fs::write(dir.join("binary"), b"\xff\xfe\n").unwrap();
+    fs::write(dir.join("control"), "one\n\x01I 1\n").unwrap();
+
+    assert_eq!(tool(dir, &[]).status.code(), Some(2));
+    assert!(!tool(dir, &["add", "text.weave", "text"]).status.success());
+    assert!(!tool(dir, &["init", "text", "text"]).status.success());
+    assert!(!tool(dir, &["init", "text.weave", "binary"])
+        .status
+        .success());
+    assert!(!tool(dir, &["init", "text.weave", "control"])
+        .status
+        .success());
+    assert!(!dir.join("text.weave").exists());
+
+    stdout(dir, &["init", "text.weave", "text"]);
+    assert!(!tool(dir, &["init", "text.weave", "text"]).status.success());
+    assert!(!tool(dir, &["extract", "text.weave", "-d", "7"])
+        .status
+        .success());
+    let err = tool(dir, &["extract", "text.weave", "-d", "weekly"]);
+    assert!(String::from_utf8_lossy(&err.stderr).contains("No delta \"weekly\""));
+
+    stdout(dir, &["fsck", "text.weave"]);
+    let weave = fs::read_to_string(dir.join("text.weave")).unwrap();
+    fs::write(dir.join("text.weave"), weave.replace("\x01E 1\n", "")).unwrap();
+    let err = tool(dir, &["fsck", "text.weave"]);
+    assert!(!err.status.success());
+    assert!(String::from_utf8_lossy(&err.stdout).contains("insert of delta 1 never ended"));
+}